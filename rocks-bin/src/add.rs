@@ -0,0 +1,102 @@
+use std::path::PathBuf;
+
+use clap::Args;
+use eyre::{OptionExt as _, Result};
+use itertools::Itertools;
+use rocks_lib::{
+    build::BuildBehaviour,
+    config::{Config, LuaVersion},
+    lockfile::PinnedState,
+    operations,
+    package::{PackageReq, PackageVersionReq},
+    progress::MultiProgress,
+    project::{DependencyType, GitDependency, Project},
+    remote_package_db::RemotePackageDB,
+    rockspec::GitSource,
+    tree::Tree,
+};
+
+#[derive(Args)]
+pub struct Add {
+    /// Packages to install and add to the project's `dependencies`.
+    /// If no version constraint is given, the installed version is recorded as the minimum.
+    #[arg(required_unless_present = "git", conflicts_with = "git")]
+    package_req: Vec<PackageReq>,
+
+    /// The URL of a git repository containing a rockspec, e.g. `https://github.com/user/repo`.
+    /// The rock is installed and added to the project's `git_dependencies`.
+    #[arg(long, value_name = "url")]
+    git: Option<String>,
+
+    /// The tag, branch or commit to check out (used with `--git`).
+    #[arg(long, requires = "git")]
+    tag: Option<String>,
+
+    /// The path of the rockspec to install, relative to the repository's root
+    /// (used with `--git` if the repository contains more than one rockspec).
+    #[arg(long, requires = "git")]
+    rockspec: Option<PathBuf>,
+}
+
+/// Add dependencies to the current project.
+pub async fn add(data: Add, config: Config) -> Result<()> {
+    let mut project = Project::current()?
+        .ok_or_eyre("'rocks add' must be run in a project root, with a 'project.rockspec'")?;
+    let package_db = RemotePackageDB::from_config(&config).await?;
+
+    if let Some(url) = data.git {
+        let source = GitSource {
+            checkout_ref: data.tag,
+            ..GitSource::from_repository_url(&url)?
+        };
+        let package = operations::install_from_git(
+            source.clone(),
+            data.rockspec.clone(),
+            PinnedState::Unpinned,
+            BuildBehaviour::NoForce,
+            &package_db,
+            &config,
+            MultiProgress::new_arc_from_config(&config),
+        )
+        .await?;
+        project.add_git(GitDependency {
+            name: package.name().clone(),
+            source,
+            rockspec: data.rockspec,
+        })?;
+        return Ok(());
+    }
+
+    operations::install(
+        data.package_req
+            .iter()
+            .map(|req| (BuildBehaviour::NoForce, req.clone()))
+            .collect(),
+        PinnedState::Unpinned,
+        &package_db,
+        &config,
+        MultiProgress::new_arc_from_config(&config),
+    )
+    .await?;
+
+    let tree = Tree::from_config(&config, LuaVersion::from(&config)?)?;
+    let dependencies = data
+        .package_req
+        .into_iter()
+        .map(|req| {
+            if *req.version_req() != PackageVersionReq::default() {
+                return req;
+            }
+            match tree.has_rock(&req) {
+                Some(package) => {
+                    let version_req = package.version().into_minimum_version_req();
+                    req.with_version_req(version_req)
+                }
+                None => req,
+            }
+        })
+        .collect_vec();
+    project.add(DependencyType::Regular, dependencies)?;
+
+    Ok(())
+}
@@ -6,11 +6,12 @@ use std::{path::PathBuf, sync::Arc};
 use clap::Args;
 use eyre::Result;
 use rocks_lib::{
-    build::BuildBehaviour,
+    build::{BuildBehaviour, BuildProfile},
     config::Config,
     lockfile::{LockConstraint::Unconstrained, PinnedState},
+    operations::pack_binary_rock,
     package::{PackageName, PackageReq},
-    progress::MultiProgress,
+    progress::{ArtifactKind, Event, MessageFormat, MultiProgress, Progress},
     remote_package_db::RemotePackageDB,
     rockspec::Rockspec,
     tree::Tree,
@@ -25,9 +26,35 @@ pub struct Build {
 
     #[arg(long)]
     force: bool,
+
+    /// Compile the rock, but do not install it into the tree.
+    /// The build artifacts are left in the build directory.
+    #[arg(long)]
+    no_install: bool,
+
+    /// A comma-separated list of features to enable.
+    /// A feature installs the optional dependency with the same name.
+    #[arg(long, value_delimiter = ',')]
+    features: Vec<String>,
+
+    /// Build C modules with debug symbols and without optimizations,
+    /// instead of optimized and with stripped symbols.
+    #[arg(long)]
+    debug: bool,
+
+    /// Pack the built rock into a binary rock in the current directory, instead of installing it.
+    /// Debug builds are packed into a `<arch>-debug.rock`,
+    /// so that they don't overwrite release builds.
+    #[arg(long, conflicts_with = "no_install")]
+    pack_binary_rock: bool,
 }
 
 pub async fn build(data: Build, config: Config) -> Result<()> {
+    let config = if data.debug {
+        config.with_build_profile(BuildProfile::Debug)
+    } else {
+        config
+    };
     let pin = PinnedState::from(data.pin);
 
     let rockspec_path = data.rockspec_path.map_or_else(|| {
@@ -66,9 +93,45 @@ pub async fn build(data: Build, config: Config) -> Result<()> {
     let rockspec = std::fs::read_to_string(rockspec_path)?;
     let rockspec = Rockspec::new(&rockspec)?;
 
+    if data.pack_binary_rock {
+        let progress = MultiProgress::from_config(&config);
+        let bar = Progress::Progress(progress.new_bar());
+        let build_dir = rocks_lib::build::build_no_install(rockspec.clone(), &config, &bar).await?;
+        bar.map(|b| b.finish_and_clear());
+        let rock_path =
+            pack_binary_rock(&rockspec, &build_dir, &std::env::current_dir()?, &config)?;
+        match config.message_format() {
+            MessageFormat::Human => println!("📦 Packed {}", rock_path.display()),
+            MessageFormat::Json => Event::Artifact {
+                kind: ArtifactKind::Rock,
+                path: rock_path,
+            }
+            .emit(config.message_format()),
+        }
+        return Ok(());
+    }
+
+    if data.no_install {
+        let progress = MultiProgress::from_config(&config);
+        let bar = Progress::Progress(progress.new_bar());
+        let build_dir = rocks_lib::build::build_no_install(rockspec, &config, &bar).await?;
+        bar.map(|b| b.finish_and_clear());
+        match config.message_format() {
+            MessageFormat::Human => {
+                println!("Build artifacts left in {}", build_dir.display())
+            }
+            MessageFormat::Json => Event::Artifact {
+                kind: ArtifactKind::Directory,
+                path: build_dir,
+            }
+            .emit(config.message_format()),
+        }
+        return Ok(());
+    }
+
     let lua_version = rockspec.lua_version_from_config(&config)?;
 
-    let tree = Tree::new(config.tree().clone(), lua_version)?;
+    let tree = Tree::from_config(&config, lua_version)?;
     let package_db = RemotePackageDB::from_config(&config).await?;
 
     let build_behaviour = match tree.has_rock_and(
@@ -95,14 +158,20 @@ pub async fn build(data: Build, config: Config) -> Result<()> {
     };
 
     // Ensure all dependencies are installed first
+    let features = data
+        .features
+        .into_iter()
+        .map(PackageName::new)
+        .collect_vec();
     let dependencies = rockspec
         .dependencies
-        .current_platform()
+        .for_platform(config.platform())
         .iter()
+        .chain(rockspec.enabled_optional_dependencies(&features, config.platform()))
         .filter(|package| !package.name().eq(&PackageName::new("lua".into())))
         .collect_vec();
 
-    let progress_arc = MultiProgress::new_arc();
+    let progress_arc = MultiProgress::new_arc_from_config(&config);
     let progress = Arc::clone(&progress_arc);
 
     let dependencies_to_install = dependencies
@@ -122,6 +191,7 @@ pub async fn build(data: Build, config: Config) -> Result<()> {
 
     rocks_lib::build::build(
         rockspec,
+        None,
         pin,
         Unconstrained,
         build_behaviour,
@@ -19,7 +19,7 @@ pub async fn check(config: Config) -> Result<()> {
         Pinned,
         &db,
         &config,
-        MultiProgress::new_arc(),
+        MultiProgress::new_arc_from_config(&config),
     )
     .await?;
 
@@ -29,7 +29,7 @@ pub async fn check(config: Config) -> Result<()> {
             project.root().to_string_lossy().into(),
             "--exclude-files".into(),
             project
-                .tree(LuaVersion::from(&config)?)?
+                .tree(&config, LuaVersion::from(&config)?)?
                 .root()
                 .to_string_lossy()
                 .to_string(),
@@ -0,0 +1,64 @@
+use clap::Args;
+use eyre::{eyre, Result};
+use itertools::Itertools as _;
+use rocks_lib::{
+    config::{Config, LuaVersion},
+    operations::{self, RockIntegrity},
+    package::PackageReq,
+    progress,
+    tree::Tree,
+};
+
+#[derive(Args)]
+pub struct CheckIntegrity {
+    /// Only check the installed rocks that match this package requirement.
+    package_req: Option<PackageReq>,
+}
+
+/// Check the files of the installed rocks against their `rock_manifest`.
+pub fn check_integrity(data: CheckIntegrity, config: Config) -> Result<()> {
+    let tree = Tree::from_config(&config, LuaVersion::from(&config)?)?;
+    let lockfile = tree.lockfile()?;
+    let packages = lockfile
+        .rocks()
+        .values()
+        .filter(|package| {
+            data.package_req
+                .as_ref()
+                .is_none_or(|req| req.matches(&package.to_package()))
+        })
+        .sorted_by(|a, b| a.name().cmp(b.name()).then(a.version().cmp(b.version())))
+        .collect_vec();
+
+    if packages.is_empty() {
+        return Err(eyre!("No matching rocks installed"));
+    }
+
+    let mut corrupted = 0;
+    for package in packages {
+        match operations::check_integrity(package, &tree)? {
+            RockIntegrity::Intact => println!("✅ {}@{}", package.name(), package.version()),
+            RockIntegrity::Violated(violations) => {
+                corrupted += 1;
+                println!("❌ {}@{}", package.name(), package.version());
+                for violation in violations {
+                    println!("  {}", violation);
+                }
+            }
+            RockIntegrity::NoRockManifest => progress::warn(
+                config.message_format(),
+                format!(
+                "{}@{} has no rock_manifest and cannot be checked. Reinstall it to generate one.",
+                package.name(),
+                package.version()
+            ),
+            ),
+        }
+    }
+
+    if corrupted > 0 {
+        Err(eyre!("{} rocks failed the integrity check", corrupted))
+    } else {
+        Ok(())
+    }
+}
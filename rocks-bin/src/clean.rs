@@ -0,0 +1,48 @@
+use clap::Args;
+use eyre::Result;
+use rocks_lib::{
+    config::{Config, LuaVersion},
+    operations,
+    progress::{MultiProgress, ProgressBar},
+    tree::Tree,
+};
+
+#[derive(Args)]
+pub struct Clean {
+    /// Also remove the manifest caches, the installed rocks and the lockfile.
+    #[arg(long)]
+    all: bool,
+
+    /// Print what would be removed, without removing anything.
+    #[arg(long)]
+    dry_run: bool,
+}
+
+/// Remove transient build state:
+/// build directories left by `rocks build --no-install`, interrupted downloads,
+/// cached object files and rock directories that are not referenced by the lockfile.
+pub fn clean(data: Clean, config: Config) -> Result<()> {
+    let tree = Tree::from_config(&config, LuaVersion::from(&config)?)?;
+    let targets = operations::clean_targets(&tree, &config, data.all)?;
+
+    if targets.is_empty() {
+        println!("Nothing to clean");
+        return Ok(());
+    }
+
+    if data.dry_run {
+        println!("Would remove:");
+        for target in &targets {
+            println!("  {} ({})", target.path.display(), target.kind);
+        }
+        return Ok(());
+    }
+
+    let _spinner = MultiProgress::from_config(&config).add(ProgressBar::from(format!(
+        "🧹 Removing {} build artifacts and caches",
+        targets.len()
+    )));
+    operations::clean(&targets)?;
+
+    Ok(())
+}
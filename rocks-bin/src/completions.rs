@@ -0,0 +1,18 @@
+use std::io;
+
+use clap::{Args, Command};
+use clap_complete::Shell;
+use eyre::Result;
+
+#[derive(Args)]
+pub struct Completions {
+    /// The shell to generate completions for.
+    shell: Shell,
+}
+
+/// Print shell completions for `cmd` to stdout.
+pub fn completions(data: Completions, cmd: &mut Command) -> Result<()> {
+    let name = cmd.get_name().to_string();
+    clap_complete::generate(data.shell, cmd, name, &mut io::stdout());
+    Ok(())
+}
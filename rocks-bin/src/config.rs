@@ -0,0 +1,98 @@
+use clap::{Args, Subcommand};
+use eyre::{eyre, Result};
+use rocks_lib::{
+    config::Config,
+    operations::{self, CheckStatus, DoctorCheck},
+    progress::MultiProgress,
+    remote_package_db::RemotePackageDB,
+    tree::Tree,
+};
+
+use crate::utils::confirm::confirm;
+
+#[derive(Subcommand)]
+pub enum ConfigCmd {
+    /// Check whether the environment is set up to install and build rocks,
+    /// and whether the trees are consistent with their lockfiles.
+    /// Exits with an error if any check fails.
+    Doctor(Doctor),
+}
+
+#[derive(Args)]
+pub struct Doctor {
+    /// Repair the inconsistencies between the trees and their lockfiles:
+    /// reinstall rocks that are in the lockfile but missing from the tree,
+    /// remove rock directories that the lockfile doesn't record,
+    /// and recompute the lockfile's entrypoints.
+    /// Each fix is confirmed first, unless `--yes` is set.
+    #[arg(long)]
+    fix: bool,
+}
+
+pub async fn doctor(data: Doctor, config: Config) -> Result<()> {
+    let mut checks = operations::doctor(&config).await;
+    let trees = Tree::all_from_config(&config)
+        .into_iter()
+        .map(|tree| (config.clone(), tree))
+        .chain(
+            Tree::all_from_config(&operations::test_tree_config(&config))
+                .into_iter()
+                .map(|tree| (operations::test_tree_config(&config), tree)),
+        );
+    let mut package_db = None;
+    for (config, tree) in trees {
+        let problems = operations::check_tree(&tree)?;
+        if !data.fix || problems.is_empty() {
+            checks.extend(problems.iter().map(DoctorCheck::from));
+            continue;
+        }
+        let mut fixes = Vec::new();
+        for problem in problems {
+            if confirm(&format!("{}. Fix it ({})?", problem, problem.fix()))? {
+                fixes.push(problem);
+            } else {
+                checks.push(DoctorCheck::from(&problem));
+            }
+        }
+        if fixes.is_empty() {
+            continue;
+        }
+        // The manifests are only fetched if there is something to fix.
+        if package_db.is_none() {
+            package_db = Some(RemotePackageDB::from_config(&config).await?);
+        }
+        let config = config.with_lua_version(tree.version().clone());
+        operations::fix_tree(
+            &fixes,
+            &tree,
+            package_db.as_ref().expect("fetched above"),
+            &config,
+            MultiProgress::new_arc_from_config(&config),
+        )
+        .await?;
+        for fix in &fixes {
+            println!("🔧 {}: {}", tree.root().display(), fix.fix());
+        }
+    }
+
+    for check in &checks {
+        let icon = match check.status {
+            CheckStatus::Pass => "✅",
+            CheckStatus::Warn => "⚠️",
+            CheckStatus::Fail => "❌",
+        };
+        println!("{} {}", icon, check.message);
+        if let Some(hint) = &check.hint {
+            println!("   {}", hint);
+        }
+    }
+
+    let failures = checks
+        .iter()
+        .filter(|check| check.status == CheckStatus::Fail)
+        .count();
+    if failures > 0 {
+        return Err(eyre!("{} checks failed", failures));
+    }
+    Ok(())
+}
@@ -0,0 +1,51 @@
+use clap::Args;
+use eyre::{OptionExt, Result};
+use rocks_lib::{
+    config::Config,
+    operations::{ensure_ldoc, generate_docs, has_ldoc, DocServer},
+    progress::MultiProgress,
+    project::Project,
+    remote_package_db::RemotePackageDB,
+    tree::Tree,
+};
+
+#[derive(Args)]
+pub struct Doc {
+    /// Serve the generated documentation over a local HTTP server.
+    /// Pages are regenerated when the project's sources change.
+    #[arg(long)]
+    serve: bool,
+    /// The port to serve the documentation on.
+    #[arg(long, default_value_t = 8080)]
+    port: u16,
+}
+
+pub async fn doc(doc: Doc, config: Config) -> Result<()> {
+    let project = Project::current()?
+        .ok_or_eyre("'rocks doc' must be run in a project root, with a 'project.rockspec'")?;
+    if !has_ldoc(&project) {
+        eyre::bail!("no documentation generator configured! Please add a `config.ld` or add `ldoc` to your rockspec's dependencies.");
+    }
+    let lua_version = project.rockspec().lua_version_from_config(&config)?;
+    let doc_config = config.with_lua_version(lua_version.clone());
+    let tree = Tree::from_config(&doc_config, lua_version)?;
+    let package_db = RemotePackageDB::from_config(&doc_config).await?;
+    ensure_ldoc(
+        &tree,
+        &package_db,
+        &doc_config,
+        MultiProgress::new_arc_from_config(&doc_config),
+    )
+    .await?;
+
+    let doc_dir = generate_docs(&project, tree.clone())?;
+    if doc.serve {
+        let server = DocServer::bind(project, tree, doc.port).await?;
+        println!("Serving documentation at {}", server.url()?);
+        server.serve().await?;
+    } else {
+        println!("Documentation generated in {}", doc_dir.display());
+    }
+
+    Ok(())
+}
@@ -1,25 +1,66 @@
+use std::{io::Cursor, path::PathBuf};
+
 use clap::Args;
 use eyre::Result;
 use rocks_lib::{
     config::Config,
     package::PackageReq,
-    progress::{MultiProgress, Progress},
+    progress::{ArtifactKind, Event, MultiProgress, Progress},
     remote_package_db::RemotePackageDB,
 };
 
 #[derive(Args)]
 pub struct Download {
     package_req: PackageReq,
+
+    /// Extract the rock into this directory instead of saving it
+    /// (defaults to the current directory).
+    #[arg(long, value_name = "dir", num_args = 0..=1, default_missing_value = ".")]
+    unpack: Option<PathBuf>,
 }
 
 pub async fn download(dl_data: Download, config: Config) -> Result<()> {
     let package_db = RemotePackageDB::from_config(&config).await?;
-    let progress = MultiProgress::new();
+    let progress = MultiProgress::from_config(&config);
     let bar = Progress::Progress(progress.new_bar());
 
-    let rock =
-        rocks_lib::operations::download_to_file(&dl_data.package_req, None, &package_db, &bar)
-            .await?;
+    if let Some(destination) = dl_data.unpack {
+        let rock = rocks_lib::operations::search_and_download_src_rock(
+            &dl_data.package_req,
+            &package_db,
+            &config,
+            &bar,
+        )
+        .await?;
+        let unpack_path =
+            rocks_lib::operations::unpack_src_rock(Cursor::new(rock.bytes), destination, &bar)
+                .await?;
+
+        bar.map(|b| {
+            b.finish_with_message(format!(
+                "Succesfully downloaded {}@{} and unpacked it into {}",
+                rock.name,
+                rock.version,
+                unpack_path.display()
+            ))
+        });
+        Event::Artifact {
+            kind: ArtifactKind::Directory,
+            path: unpack_path,
+        }
+        .emit(config.message_format());
+
+        return Ok(());
+    }
+
+    let rock = rocks_lib::operations::download_to_file(
+        &dl_data.package_req,
+        None,
+        &package_db,
+        &config,
+        &bar,
+    )
+    .await?;
 
     bar.map(|b| {
         b.finish_with_message(format!(
@@ -27,6 +68,11 @@ pub async fn download(dl_data: Download, config: Config) -> Result<()> {
             rock.name, rock.version
         ))
     });
+    Event::Artifact {
+        kind: ArtifactKind::Rock,
+        path: rock.path,
+    }
+    .emit(config.message_format());
 
     Ok(())
 }
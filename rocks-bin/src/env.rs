@@ -0,0 +1,36 @@
+use clap::{Args, Subcommand};
+use eyre::Result;
+use rocks_lib::{config::Config, tree::environment};
+
+#[derive(Subcommand)]
+pub enum Env {
+    /// List the named environments, marking the one in use.
+    List,
+    /// Select the environment to use when `--tree-name` is not specified.
+    Use(UseEnv),
+}
+
+#[derive(Args)]
+pub struct UseEnv {
+    /// The name of the environment. Use `default` for the base tree.
+    name: String,
+}
+
+pub fn list_environments(config: Config) -> Result<()> {
+    for name in environment::list_environments(&config)? {
+        if &name == config.tree_name() {
+            println!("* {}", name);
+        } else {
+            println!("  {}", name);
+        }
+    }
+
+    Ok(())
+}
+
+pub fn use_environment(data: UseEnv, config: Config) -> Result<()> {
+    let tree = environment::use_environment(&config, &data.name)?;
+    println!("Using environment '{}' ({})", data.name, tree.display());
+
+    Ok(())
+}
@@ -11,17 +11,18 @@ use crate::unpack::UnpackRemote;
 
 pub async fn fetch_remote(data: UnpackRemote, config: Config) -> Result<()> {
     let package_req = data.package_req;
-    let progress = MultiProgress::new();
+    let progress = MultiProgress::from_config(&config);
     let bar = Progress::Progress(progress.new_bar());
     let package_db = RemotePackageDB::from_config(&config).await?;
     let rockspec =
-        rocks_lib::operations::download_rockspec(&package_req, &package_db, &bar).await?;
+        rocks_lib::operations::download_rockspec(&package_req, &package_db, &config, &bar).await?;
 
     let destination = data
         .path
         .unwrap_or_else(|| PathBuf::from(format!("{}-{}", &rockspec.package, &rockspec.version)));
-    let rock_source = rockspec.source.current_platform();
-    rocks_lib::operations::fetch_src(destination.clone().as_path(), rock_source, &bar).await?;
+    let rock_source = rockspec.source.for_platform(config.platform());
+    rocks_lib::operations::fetch_src(destination.clone().as_path(), rock_source, &config, &bar)
+        .await?;
 
     let build_dir = rock_source
         .unpack_dir
@@ -0,0 +1,27 @@
+use clap::Args;
+use eyre::Result;
+use rocks_lib::{
+    config::{Config, LuaVersion},
+    lockfile::GraphFormat,
+    project::Project,
+    tree::Tree,
+};
+
+#[derive(Args)]
+pub struct Graph {
+    /// The format to print the graph in.
+    #[arg(long, value_enum, default_value_t)]
+    format: GraphFormat,
+}
+
+/// Print the dependency graph of the current project's rocks,
+/// or of the installed rocks if not in a project.
+pub fn graph(data: Graph, config: Config) -> Result<()> {
+    let lua_version = LuaVersion::from(&config)?;
+    let tree = match Project::current()? {
+        Some(project) => project.tree(&config, lua_version)?,
+        None => Tree::from_config(&config, lua_version)?,
+    };
+    println!("{}", tree.lockfile()?.to_graph(data.format));
+    Ok(())
+}
@@ -1,65 +1,557 @@
-use clap::Args;
-use eyre::Result;
+use std::{process::Command, sync::Arc};
+
+use clap::{Args, ValueEnum};
+use eyre::{eyre, Result};
+use itertools::Itertools;
 use rocks_lib::{
     config::{Config, LuaVersion},
-    operations::download_rockspec,
-    package::PackageReq,
+    operations::{changelog_since, download_rockspec, find_changelog},
+    package::{PackageReq, PackageVersion, PackageVersionReq},
     progress::{MultiProgress, Progress},
-    remote_package_db::RemotePackageDB,
-    tree::Tree,
+    remote_package_db::{did_you_mean, RemotePackageDB},
+    rockspec::{PerPlatform, RockDescription, Rockspec},
+    tree::{RockManifest, Tree},
 };
+use serde_json::json;
 
 #[derive(Args)]
 pub struct Info {
-    package: PackageReq,
+    /// The rocks to show metadata for.
+    /// Their rockspecs are downloaded concurrently, and each rock is printed in its own section.
+    #[arg(required = true)]
+    packages: Vec<PackageReq>,
+
+    /// Print the rock's rockspec instead of a summary.
+    #[arg(long)]
+    rockspec: bool,
+
+    /// The format to print the rockspec in.
+    #[arg(long, value_enum, default_value_t = RockspecOutputFormat::Lua, requires = "rockspec")]
+    format: RockspecOutputFormat,
+
+    /// Only print the rock's dependencies, build dependencies and test dependencies
+    /// for the current platform.
+    #[arg(long, conflicts_with = "rockspec")]
+    deps_only: bool,
+
+    /// Print the summary, the dependencies with `--deps-only`,
+    /// the installed files with `--installed-files`, or the versions with `--versions`, as JSON.
+    /// If several rocks are given, an array with an object per rock is printed.
+    #[arg(long, conflicts_with_all = ["rockspec", "open", "print", "changelog", "since"])]
+    json: bool,
+
+    /// Open one of the rock's links in the browser.
+    #[arg(long, value_enum, conflicts_with_all = ["rockspec", "deps_only", "print"])]
+    open: Option<RockLink>,
+
+    /// Print one of the rock's links, e.g. for use in scripts.
+    #[arg(long, value_enum, conflicts_with_all = ["rockspec", "deps_only"])]
+    print: Option<RockLink>,
+
+    /// Print the rock's changelog, from the installed rock or its source.
+    #[arg(long, conflicts_with_all = ["rockspec", "deps_only", "open", "print"])]
+    changelog: bool,
+
+    /// Only print the changelog entries for versions newer than this one.
+    /// Implies `--changelog`.
+    #[arg(long, conflicts_with_all = ["rockspec", "deps_only", "open", "print"])]
+    since: Option<PackageVersion>,
+
+    /// List the files the installed rock installed into the tree, from its `rock_manifest`,
+    /// e.g. to find out which rocks install the same executable.
+    /// Lua modules and libraries installed into a tree with the `fhs` layout are not listed.
+    #[arg(
+        long,
+        conflicts_with_all = ["rockspec", "deps_only", "open", "print", "changelog", "since"]
+    )]
+    installed_files: bool,
+
+    /// List all versions of the rock that are available on the configured servers, newest first,
+    /// marking the ones that are installed or yanked.
+    #[arg(
+        long,
+        conflicts_with_all = ["rockspec", "deps_only", "open", "print", "changelog", "since", "installed_files"]
+    )]
+    versions: bool,
+
+    /// Mark the versions that satisfy this constraint, e.g. `">= 1.0, < 2.0"`
+    /// (used with `--versions`).
+    #[arg(long, requires = "versions")]
+    constraint: Option<PackageVersionReq>,
+}
+
+#[derive(ValueEnum, Clone, Copy, PartialEq, Eq, Debug)]
+#[clap(rename_all = "lowercase")]
+enum RockspecOutputFormat {
+    /// The raw rockspec, as it is stored on the server.
+    Lua,
+    /// The parsed rockspec, serialized as JSON.
+    Json,
+}
+
+#[derive(ValueEnum, Clone, Copy, PartialEq, Eq, Debug)]
+#[clap(rename_all = "lowercase")]
+enum RockLink {
+    /// The rock's homepage (`description.homepage`).
+    Homepage,
+    /// The rock's issue tracker (`description.issues_url`).
+    Issues,
+}
+
+impl RockLink {
+    fn url(self, description: &RockDescription) -> Option<&String> {
+        match self {
+            RockLink::Homepage => description.homepage.as_ref(),
+            RockLink::Issues => description.issues_url.as_ref(),
+        }
+    }
+
+    fn field(self) -> &'static str {
+        match self {
+            RockLink::Homepage => "description.homepage",
+            RockLink::Issues => "description.issues_url",
+        }
+    }
 }
 
 pub async fn info(data: Info, config: Config) -> Result<()> {
-    // TODO(vhyrro): Add `Tree::from(&Config)`
-    let tree = Tree::new(config.tree().clone(), LuaVersion::from(&config)?)?;
-    let package_db = RemotePackageDB::from_config(&config).await?;
+    if data.packages.len() > 1 && (data.open.is_some() || data.changelog || data.since.is_some()) {
+        return Err(eyre!(
+            "`--open`, `--changelog` and `--since` can only be used with a single rock."
+        ));
+    }
+
+    let tree = Tree::from_config(&config, LuaVersion::from(&config)?)?;
+    if data.installed_files {
+        return print_installed_files(&data.packages, &tree, data.json);
+    }
+    let mut package_db = RemotePackageDB::from_config(&config).await?;
+    package_db
+        .add_namespaces(
+            data.packages
+                .iter()
+                .filter_map(|package| package.namespace()),
+            &config,
+        )
+        .await?;
+    if data.versions {
+        return print_versions(
+            &data.packages,
+            data.constraint.as_ref(),
+            &package_db,
+            &tree,
+            data.json,
+        );
+    }
+
+    let progress = MultiProgress::from_config(&config);
+    let package_db = Arc::new(package_db);
+    let downloads = data
+        .packages
+        .iter()
+        .map(|package| {
+            let package = package.clone();
+            let package_db = Arc::clone(&package_db);
+            let config = config.clone();
+            let bar = Progress::Progress(progress.new_bar());
+            tokio::spawn(async move {
+                let rockspec = download_rockspec(&package, &package_db, &config, &bar).await;
+                bar.map(|b| b.finish_and_clear());
+                rockspec
+            })
+        })
+        .collect_vec();
+
+    // A rock that can't be found is reported, without aborting the others.
+    let mut rockspecs = Vec::new();
+    let mut failures = Vec::new();
+    for (package, download) in data.packages.iter().zip(downloads) {
+        match download.await? {
+            Ok(rockspec) => rockspecs.push((package, rockspec)),
+            Err(err) => failures.push((package, err)),
+        }
+    }
+    if data.packages.len() == 1 {
+        if let Some((_, err)) = failures.pop() {
+            return Err(err.into());
+        }
+    }
+    for (package, err) in &failures {
+        eprintln!("Failed to get info for {}: {}", package, err);
+    }
+
+    if data.changelog || data.since.is_some() {
+        let (_, rockspec) = &rockspecs[0];
+        let bar = Progress::Progress(progress.new_bar());
+        let changelog = find_changelog(rockspec, &tree, &config, &bar).await?;
+        bar.map(|b| b.finish_and_clear());
+        match changelog {
+            Some(changelog) => match data.since {
+                Some(since) => println!("{}", changelog_since(&changelog, &since)),
+                None => println!("{}", changelog),
+            },
+            None => println!(
+                "No changelog found for {}@{}",
+                rockspec.package, rockspec.version
+            ),
+        }
+        return Ok(());
+    }
 
-    let progress = MultiProgress::new();
-    let bar = Progress::Progress(progress.new_bar());
+    if data.rockspec {
+        match data.format {
+            RockspecOutputFormat::Lua => {
+                println!(
+                    "{}",
+                    rockspecs
+                        .iter()
+                        .map(|(_, rockspec)| &rockspec.raw_content)
+                        .join("\n")
+                )
+            }
+            RockspecOutputFormat::Json => {
+                let json = rockspecs
+                    .iter()
+                    .map(|(_, rockspec)| serde_json::to_value(rockspec))
+                    .try_collect::<_, Vec<_>, _>()?;
+                println!(
+                    "{}",
+                    serde_json::to_string_pretty(&json_value(json, data.packages.len() == 1))?
+                )
+            }
+        }
+    } else if data.json {
+        let json = rockspecs
+            .iter()
+            .map(|(package, rockspec)| {
+                if data.deps_only {
+                    dependencies_json(rockspec, &config)
+                } else {
+                    summary_json(package, rockspec, &tree)
+                }
+            })
+            .collect_vec();
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&json_value(json, data.packages.len() == 1))?
+        );
+    } else if let Some(link) = data.open.or(data.print) {
+        for (_, rockspec) in &rockspecs {
+            match link.url(&rockspec.description) {
+                Some(url) if data.open.is_some() => open_in_browser(url)?,
+                Some(url) => println!("{}", url),
+                None => {
+                    let err = eyre!(
+                        "{}@{} does not have a {} in its rockspec.",
+                        rockspec.package,
+                        rockspec.version,
+                        link.field()
+                    );
+                    if data.packages.len() == 1 {
+                        return Err(err);
+                    }
+                    eprintln!("{}", err);
+                }
+            }
+        }
+    } else {
+        for (i, (package, rockspec)) in rockspecs.iter().enumerate() {
+            if i > 0 {
+                println!();
+            }
+            if data.deps_only {
+                if rockspecs.len() > 1 {
+                    println!("{}@{}:", rockspec.package, rockspec.version);
+                }
+                print_dependencies(rockspec, &config);
+            } else {
+                print_summary(package, rockspec, &tree);
+            }
+        }
+    }
 
-    let rockspec = download_rockspec(&data.package, &package_db, &bar).await?;
+    if failures.is_empty() {
+        Ok(())
+    } else {
+        Err(eyre!(
+            "failed to get info for {} of {} rocks",
+            failures.len(),
+            data.packages.len()
+        ))
+    }
+}
 
-    bar.map(|b| b.finish_and_clear());
+/// The JSON value of a single requested rock, or an array if several rocks were requested.
+fn json_value(mut values: Vec<serde_json::Value>, single: bool) -> serde_json::Value {
+    if single && values.len() == 1 {
+        values.pop().unwrap()
+    } else {
+        serde_json::Value::Array(values)
+    }
+}
 
-    if tree.has_rock(&data.package).is_some() {
+fn print_summary(package: &PackageReq, rockspec: &Rockspec, tree: &Tree) {
+    if tree.has_rock(package).is_some() {
         println!("Currently installed in {}", tree.root().display());
     }
 
+    if let Some(namespace) = package.namespace() {
+        println!("Package namespace: {}", namespace);
+    }
     println!("Package name: {}", rockspec.package);
     println!("Package version: {}", rockspec.version);
     println!();
 
+    let description = &rockspec.description;
     println!(
         "Summary: {}",
-        rockspec.description.summary.unwrap_or("None".into())
+        description.summary.as_deref().unwrap_or("None")
     );
     println!(
         "Description: {}",
-        rockspec
-            .description
-            .detailed
-            .unwrap_or("None".into())
-            .trim()
+        description.detailed.as_deref().unwrap_or("None").trim()
     );
     println!(
         "License: {}",
-        rockspec
-            .description
+        description
             .license
-            .unwrap_or("Unknown (all rights reserved by the author)".into())
+            .as_deref()
+            .unwrap_or("Unknown (all rights reserved by the author)")
     );
     println!(
         "Maintainer: {}",
-        rockspec
-            .description
-            .maintainer
-            .unwrap_or("Unspecified".into())
+        description.maintainer.as_deref().unwrap_or("Unspecified")
+    );
+    println!(
+        "Labels: {}",
+        if description.labels.is_empty() {
+            "None".into()
+        } else {
+            description.labels.join(", ")
+        }
     );
+}
+
+fn summary_json(package: &PackageReq, rockspec: &Rockspec, tree: &Tree) -> serde_json::Value {
+    let description = &rockspec.description;
+    json!({
+        "name": rockspec.package.to_string(),
+        "version": rockspec.version.to_string(),
+        "namespace": package.namespace().map(|namespace| namespace.to_string()),
+        "installed": tree.has_rock(package).is_some(),
+        "summary": description.summary,
+        "description": description.detailed.as_deref().map(str::trim),
+        "license": description.license,
+        "maintainer": description.maintainer,
+        "labels": description.labels,
+        "homepage": description.homepage,
+    })
+}
 
+fn print_installed_files(packages: &[PackageReq], tree: &Tree, json: bool) -> Result<()> {
+    let mut values = Vec::new();
+    for (i, req) in packages.iter().enumerate() {
+        let package = tree
+            .has_rock(req)
+            .ok_or_else(|| eyre!("{} is not installed in {}", req, tree.root().display()))?;
+        let rock_layout = tree.rock_layout(&package);
+        let manifest = RockManifest::load(&rock_layout)?.ok_or_else(|| {
+            eyre!(
+                "{}@{} has no rock_manifest. Reinstall it to generate one.",
+                package.name(),
+                package.version()
+            )
+        })?;
+        if json {
+            let files = manifest
+                .installed_paths(&rock_layout)
+                .map(|(key, path)| {
+                    json!({
+                        "manifest_path": key,
+                        "path": path.display().to_string(),
+                    })
+                })
+                .collect_vec();
+            values.push(json!({
+                "name": package.name().to_string(),
+                "version": package.version().to_string(),
+                "files": files,
+            }));
+            continue;
+        }
+        if i > 0 {
+            println!();
+        }
+        println!("{}@{}:", package.name(), package.version());
+        for (_, path) in manifest.installed_paths(&rock_layout) {
+            println!("  {}", path.display());
+        }
+    }
+    if json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&json_value(values, packages.len() == 1))?
+        );
+    }
     Ok(())
 }
+
+fn print_versions(
+    packages: &[PackageReq],
+    constraint: Option<&PackageVersionReq>,
+    package_db: &RemotePackageDB,
+    tree: &Tree,
+    json: bool,
+) -> Result<()> {
+    let lockfile = tree.lockfile_snapshot()?;
+    let mut values = Vec::new();
+    for (i, req) in packages.iter().enumerate() {
+        let versions = package_db.versions(req.name(), req.namespace());
+        if versions.is_empty() {
+            let suggestions = package_db
+                .similar_names(req.name())
+                .into_iter()
+                .cloned()
+                .collect_vec();
+            return Err(eyre!(
+                "no rock named '{}' found{}",
+                req.name(),
+                did_you_mean(&suggestions)
+            ));
+        }
+        let installed = lockfile
+            .rocks()
+            .values()
+            .filter(|package| package.name() == req.name())
+            .map(|package| package.version())
+            .collect_vec();
+        let versions = versions.into_iter().map(|(version, yanked)| {
+            (
+                version,
+                installed.contains(&version),
+                yanked,
+                constraint.map(|constraint| constraint.matches(version)),
+            )
+        });
+        if json {
+            let versions = versions
+                .map(|(version, installed, yanked, satisfies)| {
+                    json!({
+                        "version": version.to_string(),
+                        "installed": installed,
+                        "yanked": yanked,
+                        "satisfies": satisfies,
+                    })
+                })
+                .collect_vec();
+            values.push(json!({
+                "name": req.name().to_string(),
+                "namespace": req.namespace().map(|namespace| namespace.to_string()),
+                "versions": versions,
+            }));
+            continue;
+        }
+        if i > 0 {
+            println!();
+        }
+        println!("{}:", req.name());
+        for (version, installed, yanked, satisfies) in versions {
+            let marks = [
+                (satisfies == Some(true)).then_some("satisfies constraint"),
+                installed.then_some("installed"),
+                yanked.then_some("yanked"),
+            ]
+            .into_iter()
+            .flatten()
+            .join(", ");
+            if marks.is_empty() {
+                println!("  {}", version);
+            } else {
+                println!("  {} ({})", version, marks);
+            }
+        }
+    }
+    if json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&json_value(values, packages.len() == 1))?
+        );
+    }
+    Ok(())
+}
+
+fn open_in_browser(url: &str) -> Result<()> {
+    let mut command = if cfg!(target_os = "macos") {
+        Command::new("open")
+    } else if cfg!(target_os = "windows") {
+        let mut command = Command::new("cmd");
+        command.args(["/C", "start", ""]);
+        command
+    } else {
+        Command::new("xdg-open")
+    };
+    let status = command
+        .arg(url)
+        .status()
+        .map_err(|err| eyre!("Failed to open {}: {}", url, err))?;
+    if !status.success() {
+        return Err(eyre!("Failed to open {} ({})", url, status));
+    }
+    Ok(())
+}
+
+fn dependency_fields(
+    rockspec: &Rockspec,
+) -> [(&'static str, &'static str, &PerPlatform<Vec<PackageReq>>); 4] {
+    [
+        ("dependencies", "Dependencies", &rockspec.dependencies),
+        (
+            "build_dependencies",
+            "Build dependencies",
+            &rockspec.build_dependencies,
+        ),
+        (
+            "optional_dependencies",
+            "Optional dependencies",
+            &rockspec.optional_dependencies,
+        ),
+        (
+            "test_dependencies",
+            "Test dependencies",
+            &rockspec.test_dependencies,
+        ),
+    ]
+}
+
+fn dependencies_json(rockspec: &Rockspec, config: &Config) -> serde_json::Value {
+    let mut json = serde_json::Map::new();
+    json.insert("name".into(), rockspec.package.to_string().into());
+    json.insert("version".into(), rockspec.version.to_string().into());
+    for (key, _, dependencies) in dependency_fields(rockspec) {
+        let dependencies = dependencies
+            .for_platform(config.platform())
+            .iter()
+            .map(|dep| {
+                json!({
+                    "name": dep.name().to_string(),
+                    "version_req": dep.version_req().to_string(),
+                })
+            })
+            .collect::<Vec<_>>();
+        json.insert(key.to_string(), serde_json::Value::Array(dependencies));
+    }
+    serde_json::Value::Object(json)
+}
+
+fn print_dependencies(rockspec: &Rockspec, config: &Config) {
+    for (_, title, dependencies) in dependency_fields(rockspec) {
+        println!("{}:", title);
+        let dependencies = dependencies.for_platform(config.platform());
+        if dependencies.is_empty() {
+            println!("  None");
+        }
+        for dep in dependencies {
+            println!("  {}", dep);
+        }
+    }
+}
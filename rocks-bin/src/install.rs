@@ -1,20 +1,66 @@
-use eyre::Result;
+use std::{path::PathBuf, str::FromStr};
+
+use crate::lock;
+use eyre::{eyre, OptionExt as _, Result};
 use inquire::Confirm;
 use itertools::Itertools;
 use rocks_lib::{
     build::BuildBehaviour,
     config::{Config, LuaVersion},
     lockfile::PinnedState,
-    package::PackageReq,
+    operations,
+    package::{PackageName, PackageReq, PackageVersionReq},
     progress::MultiProgress,
+    project::{DependencyType, Project},
     remote_package_db::RemotePackageDB,
-    tree::Tree,
+    rockspec::{GitSource, SourceUrlError},
+    tree::{Tree, TreeLayout},
 };
 
+/// A package to install from a rocks server, a git repository containing a rockspec,
+/// or a packed source rock.
+#[derive(Clone, Debug)]
+pub enum InstallTarget {
+    Package(PackageReq),
+    Git(GitSource),
+    SrcRock(PathBuf),
+}
+
+impl FromStr for InstallTarget {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.to_lowercase().ends_with(".rock") {
+            return Ok(Self::SrcRock(PathBuf::from(s)));
+        }
+        match s.parse::<GitSource>() {
+            Ok(source) => Ok(Self::Git(source)),
+            Err(SourceUrlError::Unsupported(_)) => {
+                Ok(Self::Package(s.parse().map_err(|err| format!("{}", err))?))
+            }
+            Err(err) => Err(format!("{}", err)),
+        }
+    }
+}
+
 #[derive(clap::Args)]
 pub struct Install {
-    /// Package or list of packages to install.
-    package_req: Vec<PackageReq>,
+    /// Package or list of packages to install,
+    /// or the URL of a git repository containing a rockspec, e.g. `git+https://github.com/user/repo`
+    /// or `git@github.com:user/repo.git`. SSH URLs are cloned with the system's git,
+    /// so that your SSH agent and keys are used.
+    /// A packed source rock, e.g. `./foo-1.0-1.src.rock`, is unpacked, built and installed.
+    /// If none are given, the dependencies of the current project are installed.
+    package_req: Vec<InstallTarget>,
+
+    /// The tag, branch or commit to check out (used with a git URL).
+    #[arg(long)]
+    tag: Option<String>,
+
+    /// The path of the rockspec to install, relative to the repository's root
+    /// (used with a git URL that contains more than one rockspec).
+    #[arg(long)]
+    rockspec: Option<PathBuf>,
 
     /// Pin the package so that it doesn't get updated.
     #[arg(long)]
@@ -23,18 +69,257 @@ pub struct Install {
     /// Reinstall without prompt if a package is already installed.
     #[arg(long)]
     force: bool,
+
+    /// A comma-separated list of features to enable.
+    /// A feature installs the optional dependency with the same name.
+    /// Features stay enabled when the packages are reinstalled or updated.
+    #[arg(long, value_delimiter = ',')]
+    features: Vec<String>,
+
+    /// Print the packages that would be installed, without installing anything.
+    #[arg(long, conflicts_with = "save_type")]
+    dry_run: bool,
+
+    /// Add the packages to the current project's `dependencies`.
+    #[arg(long, group = "save_type")]
+    save: bool,
+
+    /// Add the packages to the current project's `test_dependencies`.
+    #[arg(long, group = "save_type")]
+    save_dev: bool,
+
+    /// Add the packages to the current project's `build_dependencies`.
+    #[arg(long, group = "save_type")]
+    save_build: bool,
+
+    /// Add the packages to the current project's `optional_dependencies`.
+    #[arg(long, group = "save_type")]
+    save_optional: bool,
+
+    /// When installing the current project's dependencies, i.e. when no packages are given,
+    /// skip its `test_dependencies` and `build_dependencies`,
+    /// so that only the runtime dependencies are installed, e.g. for production.
+    #[arg(long)]
+    no_dev_dependencies: bool,
+
+    /// Only install the current project's dependencies.
+    /// This is what `rocks install` does if no packages are given,
+    /// but it fails outside of a project root instead.
+    #[arg(long, conflicts_with_all = ["package_req", "dry_run"])]
+    dependencies_only: bool,
+
+    /// Install the current project's dependencies exactly as they are recorded
+    /// in the lockfiles of its trees, e.g. to bootstrap a project in CI:
+    ///
+    /// - Fails without installing anything if a lockfile doesn't satisfy the
+    ///   `project.rockspec`'s dependencies, or if it records rocks the project doesn't depend on.
+    /// - Installs the missing rocks at their locked versions and sources,
+    ///   without resolving newer ones, so the lockfiles don't change.
+    /// - Fails if a rock's rockspec or source doesn't match the lockfile's hashes.
+    #[arg(
+        long,
+        conflicts_with_all = ["package_req", "dry_run", "save_type", "features"]
+    )]
+    locked: bool,
+
+    /// Install into a system prefix like `/usr/local`, with the standard
+    /// `share/lua/<lua-version>` and `lib/lua/<lua-version>` layout, instead of the tree.
+    /// Takes precedence over `--tree`.
+    /// The lockfile is kept in `<prefix>/lib/rocks/<lua-version>`.
+    #[arg(long, value_name = "prefix", conflicts_with = "save_type")]
+    prefix: Option<PathBuf>,
+
+    /// The layout of the tree to install into.
+    /// `luarocks` uses luarocks' directory layout and keeps a luarocks manifest,
+    /// so that luarocks and rocks can share the tree.
+    /// Defaults to the layout of the existing tree.
+    #[arg(long, value_name = "format", conflicts_with = "prefix")]
+    tree_format: Option<TreeLayout>,
+
+    /// Fail if the dependency graph is deeper than this, reporting the offending chain,
+    /// e.g. to diagnose runaway transitive dependencies.
+    /// The packages to install are at depth 0. Unlimited by default.
+    #[arg(long, value_name = "depth")]
+    max_depth: Option<usize>,
+
+    /// Install rocks even if they overwrite files that other installed rocks installed,
+    /// e.g. executables with the same name, warning about each of them.
+    /// Installing such a rock fails by default.
+    #[arg(long)]
+    allow_conflicts: bool,
+
+    /// Don't print the rocks that were added, removed or updated in the lockfile.
+    #[arg(long)]
+    quiet: bool,
 }
 
 pub async fn install(data: Install, config: Config) -> Result<()> {
+    let config = match &data.prefix {
+        Some(prefix) => config.with_prefix(prefix.clone()),
+        None => config,
+    };
+    let config = match &data.tree_format {
+        Some(tree_layout) => config.with_tree_layout(tree_layout.clone()),
+        None => config,
+    };
+    let config = config
+        .with_max_dependency_depth(data.max_depth)
+        .with_allow_conflicts(data.allow_conflicts);
+    let before = if data.quiet || data.dry_run {
+        None
+    } else {
+        lock::lockfile_snapshot(&config)?
+    };
+
+    install_impl(data, config.clone()).await?;
+
+    if let Some(before) = before {
+        lock::print_lockfile_changes(&before, &config)?;
+    }
+    Ok(())
+}
+
+async fn install_impl(data: Install, config: Config) -> Result<()> {
     let pin = PinnedState::from(data.pin);
+    let save = if data.save {
+        Some(DependencyType::Regular)
+    } else if data.save_dev {
+        Some(DependencyType::Test)
+    } else if data.save_build {
+        Some(DependencyType::Build)
+    } else if data.save_optional {
+        Some(DependencyType::Optional)
+    } else {
+        None
+    };
+    let features = data
+        .features
+        .into_iter()
+        .map(PackageName::new)
+        .collect_vec();
+
+    if (data.dependencies_only || data.package_req.is_empty()) && !data.dry_run {
+        let project = Project::current()?.ok_or_eyre(
+            "no packages given. Run 'rocks install' in a project root to install the project's dependencies",
+        )?;
+        let no_dev_dependencies = data.no_dev_dependencies || config.no_dev_dependencies();
+        let config = config.with_no_dev_dependencies(no_dev_dependencies);
+        let package_db = RemotePackageDB::from_config(&config).await?;
+        let progress = MultiProgress::new_arc_from_config(&config);
+        if data.locked {
+            operations::install_locked(&project, &package_db, &config, progress).await?;
+            return Ok(());
+        }
+        operations::install_project_dependencies(
+            project.rockspec(),
+            features,
+            &package_db,
+            &config,
+            progress.clone(),
+        )
+        .await?;
+        operations::install_git_dependencies(
+            project.git_dependencies(),
+            &package_db,
+            &config,
+            progress,
+        )
+        .await?;
+        return Ok(());
+    }
+
+    let project = match save {
+        Some(_) => Some(Project::current()?.ok_or_eyre(
+            "'rocks install --save' must be run in a project root, with a 'project.rockspec'",
+        )?),
+        None => None,
+    };
+    let mut package_reqs = Vec::new();
+    let mut git_sources = Vec::new();
+    let mut src_rocks = Vec::new();
+    for target in data.package_req {
+        match target {
+            InstallTarget::Package(req) => package_reqs.push(req),
+            InstallTarget::Git(source) => git_sources.push(source),
+            InstallTarget::SrcRock(path) => src_rocks.push(path),
+        }
+    }
+
+    if !src_rocks.is_empty() {
+        if !git_sources.is_empty() || data.dry_run || save.is_some() || !features.is_empty() {
+            return Err(eyre!(
+                "source rocks can't be installed along with git URLs, or with --dry-run, --save or --features"
+            ));
+        }
+        let package_db = RemotePackageDB::from_config(&config).await?;
+        let progress = MultiProgress::new_arc_from_config(&config);
+        for path in src_rocks {
+            operations::install_from_src_rock(
+                &path,
+                pin,
+                BuildBehaviour::from(data.force),
+                &package_db,
+                &config,
+                progress.clone(),
+            )
+            .await?;
+        }
+        if package_reqs.is_empty() {
+            return Ok(());
+        }
+    }
+
+    match git_sources.as_slice() {
+        [] if data.tag.is_some() || data.rockspec.is_some() => {
+            return Err(eyre!(
+                "--tag and --rockspec can only be used with a git URL"
+            ));
+        }
+        [] => {}
+        [_] if !features.is_empty() => {
+            return Err(eyre!("--features cannot be used with a git URL"));
+        }
+        [source] if package_reqs.is_empty() && !data.dry_run && save.is_none() => {
+            let source = GitSource {
+                checkout_ref: data.tag,
+                ..source.clone()
+            };
+            let package_db = RemotePackageDB::from_config(&config).await?;
+            operations::install_from_git(
+                source,
+                data.rockspec,
+                pin,
+                BuildBehaviour::from(data.force),
+                &package_db,
+                &config,
+                MultiProgress::new_arc_from_config(&config),
+            )
+            .await?;
+            return Ok(());
+        }
+        _ => {
+            return Err(eyre!(
+                "a git URL must be installed on its own, without --dry-run or --save"
+            ));
+        }
+    }
 
     let lua_version = LuaVersion::from(&config)?;
-    let tree = Tree::new(config.tree().clone(), lua_version)?;
+    // A dry run must not create the tree.
+    let tree = if data.dry_run {
+        Tree::from_config_unchecked(&config, lua_version)
+    } else {
+        Tree::from_config(&config, lua_version)?
+    };
 
-    let packages = data
-        .package_req
-        .into_iter()
+    let packages = package_reqs
+        .iter()
+        .cloned()
         .filter_map(|req| {
+            if data.dry_run {
+                // Already installed packages are skipped unless forced.
+                return Some((BuildBehaviour::from(data.force), req));
+            }
             let build_behaviour: Option<BuildBehaviour> =
                 match tree.has_rock_and(&req, |rock| pin == rock.pinned()) {
                     Some(_) if !data.force => {
@@ -54,17 +339,88 @@ pub async fn install(data: Install, config: Config) -> Result<()> {
         })
         .collect_vec();
 
-    let package_db = RemotePackageDB::from_config(&config).await?;
+    let mut package_db = RemotePackageDB::from_config(&config).await?;
+    package_db
+        .add_namespaces(
+            packages.iter().filter_map(|(_, req)| req.namespace()),
+            &config,
+        )
+        .await?;
+
+    if data.dry_run {
+        let plan = operations::install_plan(
+            packages,
+            pin,
+            features,
+            &package_db,
+            &config,
+            MultiProgress::new_arc_from_config(&config),
+        )
+        .await?;
+        if plan.is_empty() {
+            println!("Nothing to install");
+            return Ok(());
+        }
+        let removals = plan
+            .iter()
+            .filter_map(|planned| planned.replaces.as_ref())
+            .collect_vec();
+        if !removals.is_empty() {
+            println!("Would remove:");
+            for package in removals {
+                println!("  {}@{}", package.name(), package.version());
+            }
+        }
+        println!("Would install:");
+        for planned in plan {
+            println!(
+                "  {}{}{}",
+                planned.package,
+                if planned.is_dependency {
+                    " (dependency)"
+                } else {
+                    ""
+                },
+                if planned.build_behaviour == BuildBehaviour::Force {
+                    " (reinstall)"
+                } else {
+                    ""
+                },
+            );
+        }
+        return Ok(());
+    }
 
     // TODO(vhyrro): If the tree doesn't exist then error out.
-    rocks_lib::operations::install(
+    operations::install_with_features(
         packages,
         pin,
+        features,
         &package_db,
         &config,
-        MultiProgress::new_arc(),
+        MultiProgress::new_arc_from_config(&config),
     )
     .await?;
 
+    if let (Some(dependency_type), Some(mut project)) = (save, project) {
+        // Record the installed version as the minimum if no constraint was given.
+        let dependencies = package_reqs
+            .into_iter()
+            .map(|req| {
+                if *req.version_req() != PackageVersionReq::default() {
+                    return req;
+                }
+                match tree.has_rock(&req) {
+                    Some(package) => {
+                        let version_req = package.version().into_minimum_version_req();
+                        req.with_version_req(version_req)
+                    }
+                    None => req,
+                }
+            })
+            .collect_vec();
+        project.add(dependency_type, dependencies)?;
+    }
+
     Ok(())
 }
@@ -1,24 +1,67 @@
+use clap::Args;
 use eyre::Result;
 use rocks_lib::{
-    config::{Config, LuaVersion},
+    config::{Config, LuaVersion, LuaVersionUnset},
     lua_installation::LuaInstallation,
-    progress::{MultiProgress, ProgressBar},
+    package::PackageVersion,
+    progress::{MultiProgress, Progress, ProgressBar},
 };
 
-pub async fn install_lua(config: Config) -> Result<()> {
-    let version_stringified = &LuaVersion::from(&config)?;
+#[derive(Args)]
+pub struct InstallLua {
+    /// A specific release to build from its official source tarball,
+    /// e.g. `5.4.6`, or `2.1.0-beta3` for LuaJIT.
+    /// Replaces an existing installation of the same Lua version.
+    /// The Lua version is inferred from the release if `--lua-version` is not set.
+    release: Option<String>,
 
-    let progress = MultiProgress::new();
+    /// The expected sha256 checksum of the source tarball,
+    /// for releases whose checksum is not known.
+    #[arg(long, requires = "release")]
+    sha256: Option<String>,
+}
+
+pub async fn install_lua(data: InstallLua, config: Config) -> Result<()> {
+    let version_stringified = &match (config.lua_version(), &data.release) {
+        (None, Some(release)) => PackageVersion::parse(release)
+            .ok()
+            .and_then(|version| LuaVersion::from_version(version).ok())
+            .ok_or(LuaVersionUnset)?,
+        _ => LuaVersion::from(&config)?,
+    };
+
+    let progress = MultiProgress::from_config(&config);
     let bar = progress.add(ProgressBar::from(format!(
         "🌔 Installing Lua ({})",
         version_stringified
     )));
 
-    // TODO: Detect when path already exists by checking `Lua::path()` and prompt the user
-    // whether they'd like to forcefully reinstall.
-    LuaInstallation::new(version_stringified, &config);
+    match &data.release {
+        Some(release) => {
+            let bar = Progress::Progress(bar);
+            LuaInstallation::install_release(
+                version_stringified,
+                release,
+                data.sha256.as_deref(),
+                &config,
+                &bar,
+            )
+            .await?;
+            bar.map(|bar| {
+                bar.finish_with_message(format!(
+                    "🌔 Installed Lua ({}) {}",
+                    version_stringified, release
+                ))
+            });
+        }
+        None => {
+            // TODO: Detect when path already exists by checking `Lua::path()` and prompt the user
+            // whether they'd like to forcefully reinstall.
+            LuaInstallation::install(version_stringified, &config);
 
-    bar.finish_with_message(format!("🌔 Installed Lua ({})", version_stringified));
+            bar.finish_with_message(format!("🌔 Installed Lua ({})", version_stringified));
+        }
+    }
 
     Ok(())
 }
@@ -1,36 +1,65 @@
 use crate::project::NewProject;
 use std::path::PathBuf;
 
+use add::Add;
 use build::Build;
+use check_integrity::CheckIntegrity;
 use clap::{Parser, Subcommand};
+use clean::Clean;
+use completions::Completions;
+use config::ConfigCmd;
 use debug::Debug;
+use doc::Doc;
 use download::Download;
+use env::Env;
+use graph::Graph;
 use info::Info;
 use install::Install;
+use install_lua::InstallLua;
+use lint::Lint;
 use list::ListCmd;
+use lock::Lock;
 use outdated::Outdated;
+use pack::Pack;
 use path::Path;
 use pin::ChangePin;
+use regex::Regex;
 use remove::Remove;
 use rocks_lib::config::LuaVersion;
+use rocks_lib::progress::MessageFormat;
+use rocks_lib::rockspec::PlatformIdentifier;
 use run::Run;
 use run_lua::RunLua;
 use search::Search;
+use self_update::SelfUpdate;
 use test::Test;
+use trusted_keys::TrustedKeys;
 use update::Update;
 use upload::Upload;
+use which::Which;
 
+pub mod add;
 pub mod build;
 pub mod check;
+pub mod check_integrity;
+pub mod clean;
+pub mod completions;
+pub mod config;
 pub mod debug;
+pub mod doc;
 pub mod download;
+pub mod env;
 pub mod fetch;
 pub mod format;
+pub mod graph;
 pub mod info;
 pub mod install;
 pub mod install_lua;
+pub mod lint;
 pub mod list;
+pub mod lock;
 pub mod outdated;
+pub mod pack;
 pub mod path;
 pub mod pin;
 pub mod project;
@@ -39,11 +68,23 @@ pub mod remove;
 pub mod run;
 pub mod run_lua;
 pub mod search;
+pub mod self_update;
 pub mod test;
+pub mod trusted_keys;
 pub mod unpack;
 pub mod update;
 pub mod upload;
 pub mod utils;
+pub mod which;
+
+/// Parse a `--url-rewrite` rule of the form `<regex>=<replacement>`.
+pub fn parse_url_rewrite(input: &str) -> Result<(Regex, String), String> {
+    let (pattern, replacement) = input
+        .split_once('=')
+        .ok_or_else(|| format!("expected <regex>=<replacement>, got {input}"))?;
+    let pattern = Regex::new(pattern).map_err(|err| err.to_string())?;
+    Ok((pattern, replacement.to_string()))
+}
 
 /// A fast and efficient Lua package manager.
 #[derive(Parser)]
@@ -56,6 +97,9 @@ pub struct Cli {
 
     /// Fetch rocks/rockspecs from this server (takes priority
     /// over config file).
+    /// A `file://` URL serves the `.rockspec` and `.rock` files in a local directory,
+    /// e.g. for offline installs.
+    /// Can also be set with the `ROCKS_SERVER` environment variable.
     #[arg(long, value_name = "server")]
     pub server: Option<String>,
 
@@ -81,13 +125,30 @@ pub struct Cli {
     pub lua_version: Option<LuaVersion>,
 
     /// Which tree to operate on.
+    /// Can also be set with the `ROCKS_TREE` environment variable.
     #[arg(long, value_name = "tree")]
     pub tree: Option<PathBuf>,
 
+    /// Which named environment (an isolated tree within the tree) to operate on.
+    #[arg(long, value_name = "name")]
+    pub tree_name: Option<String>,
+
+    /// Install the project's rocks into this directory instead of the project's `.rocks`
+    /// directory, e.g. to cache them in CI.
+    /// Can also be set with the `ROCKS_TARGET_DIR` environment variable.
+    #[arg(long, value_name = "path")]
+    pub target_dir: Option<PathBuf>,
+
     /// Specifies the cache directory for e.g. luarocks manifests.
     #[arg(long, value_name = "path")]
     pub cache_path: Option<PathBuf>,
 
+    /// Load the config from this file instead of the default config file.
+    /// Can also be set with the `ROCKS_CONFIG` environment variable.
+    /// Command line flags take precedence over the config file.
+    #[arg(long, value_name = "path")]
+    pub config: Option<PathBuf>,
+
     /// Do not use project tree even if running from a project folder.
     #[arg(long)]
     pub no_project: bool,
@@ -96,54 +157,122 @@ pub struct Cli {
     #[arg(long)]
     pub verbose: bool,
 
+    /// Answer "yes" to confirmation prompts, e.g. before removing rocks.
+    /// Without it, destructive operations are aborted if stdin is not a terminal.
+    #[arg(long, short = 'y')]
+    pub yes: bool,
+
+    /// Fingerprint of a key that is trusted to sign rockspecs and sources.
+    /// Can be specified multiple times.
+    /// If not set, all keys added with `rocks trusted-keys add` are trusted.
+    #[arg(long, value_name = "fingerprint")]
+    pub trusted_key: Option<Vec<String>>,
+
+    /// Fail if a rockspec or source does not have a valid signature.
+    #[arg(long)]
+    pub require_signatures: bool,
+
+    /// Do not verify TLS certificates when fetching manifests, rockspecs, sources and signatures,
+    /// e.g. for a mirror with a self-signed certificate.
+    /// DANGEROUS: this allows anyone on the network path to tamper with the downloaded rocks.
+    #[arg(long)]
+    pub insecure: bool,
+
+    /// Allow resolving requirements to versions that the server marks as yanked,
+    /// or that the `yanked` denylist of the config file lists.
+    /// Yanked versions are otherwise only installed if they are requested exactly, e.g. `foo@1.2.0`.
+    #[arg(long)]
+    pub allow_yanked: bool,
+
+    /// Rewrite source and rockspec URLs that match a regex before fetching them,
+    /// e.g. `^https://github.com/=https://mirror.corp/github/`.
+    /// The replacement may refer to capture groups as `$1`.
+    /// Can be specified multiple times. The first matching rule is applied.
+    #[arg(long, value_name = "regex=replacement", value_parser = parse_url_rewrite)]
+    pub url_rewrite: Option<Vec<(Regex, String)>>,
+
     /// Timeout on network operations, in seconds.
     /// 0 means no timeout (wait forever). Default is 30.
+    /// Can also be set with the `ROCKS_TIMEOUT` environment variable.
     #[arg(long, value_name = "seconds")]
     pub timeout: Option<usize>,
 
+    /// How long to wait for another rocks process to release the tree's lock, in seconds.
+    /// Default is 60.
+    #[arg(long, value_name = "seconds")]
+    pub lock_timeout: Option<u64>,
+
+    /// Select the per-platform entries of rockspecs for this platform
+    /// (e.g. `windows`), instead of the platform rocks is running on.
+    /// This only affects dependency resolution, not the target of the compiler.
+    #[arg(long, value_name = "platform")]
+    pub platform: Option<PlatformIdentifier>,
+
+    /// How to report progress, warnings and errors.
+    /// `json` prints newline-delimited JSON events to stdout, for editors and other tools.
+    #[arg(long, value_enum, default_value_t, value_name = "format")]
+    pub message_format: MessageFormat,
+
     #[command(subcommand)]
     pub command: Commands,
 }
 
 #[derive(Subcommand)]
 pub enum Commands {
-    /// [UNIMPLEMENTED] Add a dependency to the current project.
-    Add,
+    /// Add a dependency to the current project.
+    /// With `--git`, the dependency is a rock in a git repository.
+    Add(Add),
     /// Build/compile a rock.
     Build(Build),
     /// Runs `luacheck` in the current project.
     Check,
-    /// [UNIMPLEMENTED] Query information about Rocks's configuration.
-    Config,
+    /// Check the files of the installed rocks against their `rock_manifest`.
+    CheckIntegrity(CheckIntegrity),
+    /// Remove build artifacts, interrupted downloads and orphaned rocks.
+    /// With `--all`, also remove the manifest caches, the installed rocks and the lockfile.
+    Clean(Clean),
+    /// Print shell completions to stdout, e.g. `rocks completions bash > /etc/bash_completion.d/rocks`.
+    Completions(Completions),
+    /// Query information about Rocks's configuration.
+    #[command(subcommand, arg_required_else_help = true)]
+    Config(ConfigCmd),
     /// Various debugging utilities.
     #[command(subcommand, arg_required_else_help = true)]
     Debug(Debug),
-    /// [UNIMPLEMENTED] Show documentation for an installed rock.
-    Doc,
+    /// Generate documentation for the current project with LDoc.
+    Doc(Doc),
     /// Download a specific rock file from a rocks server.
     #[command(arg_required_else_help = true)]
     Download(Download),
+    /// Manage named environments, which are isolated trees with their own lockfiles.
+    #[command(subcommand, arg_required_else_help = true)]
+    Env(Env),
     /// Formats the codebase with stylua.
     Fmt,
+    /// Print the dependency graph of the installed rocks in Graphviz DOT or Mermaid format.
+    Graph(Graph),
     /// Show metadata for any rock.
     Info(Info),
     /// Install a rock for use on the system.
     #[command(arg_required_else_help = true)]
     Install(Install),
     /// Manually install and manage Lua headers for various Lua versions.
-    InstallLua,
-    /// [UNIMPLEMENTED] Check syntax of a rockspec.
-    Lint,
+    InstallLua(InstallLua),
+    /// Check a rockspec for fields that cannot be parsed.
+    Lint(Lint),
     /// List currently installed rocks.
     List(ListCmd),
+    /// Inspect lockfiles.
+    #[command(subcommand, arg_required_else_help = true)]
+    Lock(Lock),
     /// Run lua, with the `LUA_PATH` and `LUA_CPATH` set to the specified rocks tree.
     Lua(RunLua),
     /// Create a new Lua project.
     New(NewProject),
     /// List outdated rocks.
     Outdated(Outdated),
-    /// [UNIMPLEMENTED] Create a rock, packing sources or binaries.
-    Pack,
+    /// Pack the current project's sources into a `.src.rock`, optionally signing it.
+    Pack(Pack),
     /// Return the currently configured package path.
     Path(Path),
     /// Pin an existing rock, preventing any updates to the package.
@@ -152,24 +281,32 @@ pub enum Commands {
     Purge,
     /// Uninstall a rock.
     Remove(Remove),
-    /// Run a command that has been installed with rocks.
+    /// Run a command that has been installed with rocks,
+    /// or a script defined in the project's `scripts`.
     /// If the command is not found:
     /// When run from within a rocks project, this command will build the project.
     /// Otherwise, it will try to install a package named after the command.
     Run(Run),
+    /// Update rocks to the latest release.
+    /// Only use this if rocks was not installed with a package manager.
+    SelfUpdate(SelfUpdate),
     /// Query the Luarocks servers.
     #[command(arg_required_else_help = true)]
     Search(Search),
     /// Run the test suite in the current directory.
     Test(Test),
+    /// Manage the public keys that are trusted to sign rockspecs and sources.
+    #[command(subcommand, arg_required_else_help = true)]
+    TrustedKeys(TrustedKeys),
     /// [UNIMPLEMENTED] Uninstall a rock from the system.
     Uninstall,
     /// Unpins an existing rock, allowing updates to alter the package.
     Unpin(ChangePin),
     /// Updates all rocks in a project.
     Update(Update),
-    /// Upload a rockspec to the public rocks repository.
+    /// Upload a rockspec, and the rock packed by `rocks pack`, if any, to the public rocks repository.
+    /// Pre-built files can be uploaded with `--file`.
     Upload(Upload),
-    /// [UNIMPLEMENTED] Tell which file corresponds to a given module name.
-    Which,
+    /// Tell which installed files a module name resolves to.
+    Which(Which),
 }
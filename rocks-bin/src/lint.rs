@@ -0,0 +1,50 @@
+use std::path::PathBuf;
+
+use clap::Args;
+use eyre::{eyre, OptionExt, Result};
+use rocks_lib::{config::Config, progress, project::Project, rockspec::Rockspec};
+
+#[derive(Args)]
+pub struct Lint {
+    /// The rockspec to check.
+    /// Defaults to the `project.rockspec` of the current project.
+    rockspec: Option<PathBuf>,
+}
+
+/// Parse a rockspec leniently and report the fields that could not be parsed.
+/// Fails if there are any, or if a critical field (`package`, `version` or `source`) is invalid.
+/// Fields that the declared `rockspec_format` doesn't support are reported as warnings.
+pub fn lint(data: Lint, config: Config) -> Result<()> {
+    let path = match data.rockspec {
+        Some(path) => path,
+        None => Project::find_rockspec(std::env::current_dir()?)?
+            .ok_or_eyre("Not in a project! Provide the rockspec to check.")?,
+    };
+    let rockspec_content = std::fs::read_to_string(&path)?;
+    let (rockspec, warnings) = Rockspec::new_lenient(&rockspec_content)
+        .map_err(|err| eyre!("{}: {}", path.display(), err))?;
+
+    for mismatch in rockspec.format_mismatches() {
+        progress::warn(
+            config.message_format(),
+            format!("{}: {}", path.display(), mismatch),
+        );
+    }
+
+    if warnings.is_empty() {
+        println!("✅ {}", path.display());
+        return Ok(());
+    }
+
+    for warning in &warnings {
+        progress::warn(
+            config.message_format(),
+            format!("{}: {}", path.display(), warning),
+        );
+    }
+    Err(eyre!(
+        "{} invalid fields in {}",
+        warnings.len(),
+        path.display()
+    ))
+}
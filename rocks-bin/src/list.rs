@@ -1,9 +1,14 @@
+use std::collections::HashMap;
+
 use clap::Args;
 use eyre::Result;
 use itertools::Itertools as _;
 use rocks_lib::{
     config::{Config, LuaVersion},
-    lockfile::PinnedState,
+    lockfile::{LocalPackage, PinnedState},
+    package::PackageVersion,
+    progress::{self, MultiProgress, ProgressBar},
+    remote_package_db::RemotePackageDB,
     tree::Tree,
 };
 use text_trees::{FormatCharacters, StringTreeNode, TreeFormatting};
@@ -12,28 +17,87 @@ use text_trees::{FormatCharacters, StringTreeNode, TreeFormatting};
 pub struct ListCmd {
     #[arg(long)]
     porcelain: bool,
+
+    /// Annotate each installed rock with the latest available version, if it is outdated.
+    #[arg(long)]
+    outdated: bool,
 }
 
-pub fn list_installed(list_data: ListCmd, config: Config) -> Result<()> {
-    let tree = Tree::new(config.tree().clone(), LuaVersion::from(&config)?)?;
+pub async fn list_installed(list_data: ListCmd, config: Config) -> Result<()> {
+    let tree = Tree::from_config(&config, LuaVersion::from(&config)?)?;
     let available_rocks = tree.list()?;
 
+    let package_db = if list_data.outdated {
+        let progress = MultiProgress::from_config(&config);
+        let bar = progress.add(ProgressBar::from(
+            "🔎 Checking for outdated rocks...".to_string(),
+        ));
+        let package_db = RemotePackageDB::from_config(&config).await;
+        bar.finish_and_clear();
+        match package_db {
+            Ok(package_db) => Some(package_db),
+            Err(err) => {
+                progress::warn(
+                    config.message_format(),
+                    format!("Could not check for outdated rocks: {}", err),
+                );
+                None
+            }
+        }
+    } else {
+        None
+    };
+    let latest_version = |package: &LocalPackage| -> Option<PackageVersion> {
+        package_db
+            .as_ref()
+            .and_then(|package_db| package.to_package().has_update(package_db).ok())
+            .flatten()
+    };
+
     if list_data.porcelain {
-        println!("{}", serde_json::to_string(&available_rocks)?);
+        if package_db.is_some() {
+            let annotated_rocks = available_rocks
+                .iter()
+                .map(|(name, packages)| {
+                    let packages = packages
+                        .iter()
+                        .map(|package| {
+                            let mut value = serde_json::to_value(package)?;
+                            if let Some(object) = value.as_object_mut() {
+                                object.insert(
+                                    "latest_version".into(),
+                                    serde_json::to_value(
+                                        latest_version(package).map(|version| version.to_string()),
+                                    )?,
+                                );
+                            }
+                            Ok(value)
+                        })
+                        .collect::<Result<Vec<_>, serde_json::Error>>()?;
+                    Ok((name, packages))
+                })
+                .collect::<Result<HashMap<_, _>, serde_json::Error>>()?;
+            println!("{}", serde_json::to_string(&annotated_rocks)?);
+        } else {
+            println!("{}", serde_json::to_string(&available_rocks)?);
+        }
     } else {
         let formatting = TreeFormatting::dir_tree(FormatCharacters::box_chars());
-        for (name, packages) in available_rocks.into_iter().sorted() {
+        for (name, packages) in available_rocks.iter().sorted() {
             let mut tree = StringTreeNode::new(name.to_string());
 
             for package in packages {
                 tree.push(format!(
-                    "{}{}",
+                    "{}{}{}",
                     package.version(),
                     if package.pinned() == PinnedState::Pinned {
                         " (pinned)"
                     } else {
                         ""
-                    }
+                    },
+                    latest_version(package)
+                        .map(|version| format!(" => {} available", version))
+                        .unwrap_or_default(),
                 ));
             }
 
@@ -0,0 +1,74 @@
+use std::path::PathBuf;
+
+use clap::{Args, Subcommand};
+use eyre::Result;
+use rocks_lib::{
+    config::{Config, LuaVersion},
+    lockfile::Lockfile,
+    progress::MessageFormat,
+    tree::Tree,
+};
+
+#[derive(Subcommand)]
+pub enum Lock {
+    /// Show the rocks that were added, removed or changed between two lockfiles.
+    Diff(DiffLockfiles),
+}
+
+#[derive(Args)]
+pub struct DiffLockfiles {
+    /// The old lockfile.
+    old: PathBuf,
+    /// The new lockfile.
+    new: PathBuf,
+}
+
+pub fn diff(data: DiffLockfiles) -> Result<()> {
+    let old = Lockfile::load(&data.old)?;
+    let new = Lockfile::load(&data.new)?;
+    let diff = old.diff(&new);
+
+    if diff.is_empty() {
+        println!("No changes");
+        return Ok(());
+    }
+
+    for line in diff.summary() {
+        println!("{}", line);
+    }
+
+    Ok(())
+}
+
+/// A snapshot of the lockfile of the tree that `config` operates on,
+/// so that the changes a command makes can be reported with [`print_lockfile_changes`].
+/// Returns `None` if the Lua version is not set, as there is no tree to compare.
+pub fn lockfile_snapshot(config: &Config) -> Result<Option<Lockfile>> {
+    let Ok(lua_version) = LuaVersion::from(config) else {
+        return Ok(None);
+    };
+    let tree = Tree::from_config_unchecked(config, lua_version);
+    Ok(Some(tree.lockfile_snapshot()?))
+}
+
+/// Print the rocks that were added, removed or updated in the lockfile
+/// since `before` was taken with [`lockfile_snapshot`]:
+/// a line per rock with [`MessageFormat::Human`], or an event per rock with [`MessageFormat::Json`].
+pub fn print_lockfile_changes(before: &Lockfile, config: &Config) -> Result<()> {
+    let Some(after) = lockfile_snapshot(config)? else {
+        return Ok(());
+    };
+    let diff = before.diff(&after);
+    match config.message_format() {
+        MessageFormat::Human => {
+            for line in diff.summary() {
+                println!("{}", line);
+            }
+        }
+        MessageFormat::Json => diff
+            .events()
+            .iter()
+            .for_each(|event| event.emit(config.message_format())),
+    }
+    Ok(())
+}
@@ -1,17 +1,30 @@
 use std::{path::PathBuf, time::Duration};
 
-use clap::{Parser, Subcommand};
+use clap::{CommandFactory, Parser, Subcommand};
+use regex::Regex;
 use rocks::{
+    add::{self, Add},
     build::{self, Build},
     check,
+    check_integrity::{self, CheckIntegrity},
+    clean::{self, Clean},
+    completions::{self, Completions},
+    config::{self, ConfigCmd},
     debug::Debug,
+    doc::{self, Doc},
     download::{self, Download},
+    env::{self, Env},
     fetch, format,
+    graph::{self, Graph},
     info::{self, Info},
     install::{self, Install},
-    install_lua,
+    install_lua::{self, InstallLua},
+    lint::{self, Lint},
     list::{self, ListCmd},
+    lock::{self, Lock},
     outdated::{self, Outdated},
+    pack::{self, Pack},
+    parse_url_rewrite,
     path::{self, Path},
     pin::{self, ChangePin},
     project::{self, NewProject},
@@ -20,14 +33,20 @@ use rocks::{
     run::{self, Run},
     run_lua::{self, RunLua},
     search::{self, Search},
+    self_update::{self, SelfUpdate},
     test::{self, Test},
+    trusted_keys::{self, TrustedKeys},
     unpack,
     update::{self, Update},
     upload::{self, Upload},
+    utils::confirm,
+    which::{self, Which},
 };
 use rocks_lib::{
     config::{ConfigBuilder, LuaVersion},
     lockfile::PinnedState::{Pinned, Unpinned},
+    progress::{Event, MessageFormat},
+    rockspec::PlatformIdentifier,
 };
 
 /// A fast and efficient Lua package manager.
@@ -41,6 +60,9 @@ pub struct Cli {
 
     /// Fetch rocks/rockspecs from this server (takes priority
     /// over config file).
+    /// A `file://` URL serves the `.rockspec` and `.rock` files in a local directory,
+    /// e.g. for offline installs.
+    /// Can also be set with the `ROCKS_SERVER` environment variable.
     #[arg(long, value_name = "server")]
     pub server: Option<String>,
 
@@ -66,13 +88,30 @@ pub struct Cli {
     pub lua_version: Option<LuaVersion>,
 
     /// Which tree to operate on.
+    /// Can also be set with the `ROCKS_TREE` environment variable.
     #[arg(long, value_name = "tree")]
     pub tree: Option<PathBuf>,
 
+    /// Which named environment (an isolated tree within the tree) to operate on.
+    #[arg(long, value_name = "name")]
+    pub tree_name: Option<String>,
+
+    /// Install the project's rocks into this directory instead of the project's `.rocks`
+    /// directory, e.g. to cache them in CI.
+    /// Can also be set with the `ROCKS_TARGET_DIR` environment variable.
+    #[arg(long, value_name = "path")]
+    pub target_dir: Option<PathBuf>,
+
     /// Specifies the cache directory for e.g. luarocks manifests.
     #[arg(long, value_name = "path")]
     pub cache_path: Option<PathBuf>,
 
+    /// Load the config from this file instead of the default config file.
+    /// Can also be set with the `ROCKS_CONFIG` environment variable.
+    /// Command line flags take precedence over the config file.
+    #[arg(long, value_name = "path")]
+    pub config: Option<PathBuf>,
+
     /// Do not use project tree even if running from a project folder.
     #[arg(long)]
     pub no_project: bool,
@@ -81,54 +120,122 @@ pub struct Cli {
     #[arg(long)]
     pub verbose: bool,
 
+    /// Answer "yes" to confirmation prompts, e.g. before removing rocks.
+    /// Without it, destructive operations are aborted if stdin is not a terminal.
+    #[arg(long, short = 'y')]
+    pub yes: bool,
+
+    /// Fingerprint (or 16 digit long key ID) of a key that is trusted to sign rockspecs and sources.
+    /// Can be specified multiple times.
+    /// If not set, all keys added with `rocks trusted-keys add` are trusted.
+    #[arg(long, value_name = "fingerprint")]
+    pub trusted_key: Option<Vec<String>>,
+
+    /// Fail if a rockspec or source does not have a valid signature.
+    #[arg(long)]
+    pub require_signatures: bool,
+
+    /// Do not verify TLS certificates when fetching manifests, rockspecs, sources and signatures,
+    /// e.g. for a mirror with a self-signed certificate.
+    /// DANGEROUS: this allows anyone on the network path to tamper with the downloaded rocks.
+    #[arg(long)]
+    pub insecure: bool,
+
+    /// Allow resolving requirements to versions that the server marks as yanked,
+    /// or that the `yanked` denylist of the config file lists.
+    /// Yanked versions are otherwise only installed if they are requested exactly, e.g. `foo@1.2.0`.
+    #[arg(long)]
+    pub allow_yanked: bool,
+
+    /// Rewrite source and rockspec URLs that match a regex before fetching them,
+    /// e.g. `^https://github.com/=https://mirror.corp/github/`.
+    /// The replacement may refer to capture groups as `$1`.
+    /// Can be specified multiple times. The first matching rule is applied.
+    #[arg(long, value_name = "regex=replacement", value_parser = parse_url_rewrite)]
+    pub url_rewrite: Option<Vec<(Regex, String)>>,
+
     /// Timeout on network operations, in seconds.
     /// 0 means no timeout (wait forever). Default is 30.
+    /// Can also be set with the `ROCKS_TIMEOUT` environment variable.
     #[arg(long, value_name = "seconds")]
     pub timeout: Option<usize>,
 
+    /// How long to wait for another rocks process to release the tree's lock, in seconds.
+    /// Default is 60.
+    #[arg(long, value_name = "seconds")]
+    pub lock_timeout: Option<u64>,
+
+    /// Select the per-platform entries of rockspecs for this platform
+    /// (e.g. `windows`), instead of the platform rocks is running on.
+    /// This only affects dependency resolution, not the target of the compiler.
+    #[arg(long, value_name = "platform")]
+    pub platform: Option<PlatformIdentifier>,
+
+    /// How to report progress, warnings and errors.
+    /// `json` prints newline-delimited JSON events to stdout, for editors and other tools.
+    #[arg(long, value_enum, default_value_t, value_name = "format")]
+    pub message_format: MessageFormat,
+
     #[command(subcommand)]
     pub command: Commands,
 }
 
 #[derive(Subcommand)]
 pub enum Commands {
-    /// [UNIMPLEMENTED] Add a dependency to the current project.
-    Add,
+    /// Add a dependency to the current project.
+    /// With `--git`, the dependency is a rock in a git repository.
+    Add(Add),
     /// Build/compile a rock.
     Build(Build),
     /// Runs `luacheck` in the current project.
     Check,
-    /// [UNIMPLEMENTED] Query information about Rocks's configuration.
-    Config,
+    /// Check the files of the installed rocks against their `rock_manifest`.
+    CheckIntegrity(CheckIntegrity),
+    /// Remove build artifacts, interrupted downloads and orphaned rocks.
+    /// With `--all`, also remove the manifest caches, the installed rocks and the lockfile.
+    Clean(Clean),
+    /// Print shell completions to stdout, e.g. `rocks completions bash > /etc/bash_completion.d/rocks`.
+    Completions(Completions),
+    /// Query information about Rocks's configuration.
+    #[command(subcommand, arg_required_else_help = true)]
+    Config(ConfigCmd),
     /// Various debugging utilities.
     #[command(subcommand, arg_required_else_help = true)]
     Debug(Debug),
-    /// [UNIMPLEMENTED] Show documentation for an installed rock.
-    Doc,
+    /// Generate documentation for the current project with LDoc.
+    Doc(Doc),
     /// Download a specific rock file from a rocks server.
     #[command(arg_required_else_help = true)]
     Download(Download),
+    /// Manage named environments, which are isolated trees with their own lockfiles.
+    #[command(subcommand, arg_required_else_help = true)]
+    Env(Env),
     /// Formats the codebase with stylua.
     Fmt,
+    /// Print the dependency graph of the installed rocks in Graphviz DOT or Mermaid format.
+    Graph(Graph),
     /// Show metadata for any rock.
     Info(Info),
     /// Install a rock for use on the system.
     #[command(arg_required_else_help = true)]
     Install(Install),
     /// Manually install and manage Lua headers for various Lua versions.
-    InstallLua,
-    /// [UNIMPLEMENTED] Check syntax of a rockspec.
-    Lint,
+    InstallLua(InstallLua),
+    /// Check a rockspec for fields that cannot be parsed.
+    Lint(Lint),
     /// List currently installed rocks.
     List(ListCmd),
+    /// Inspect lockfiles.
+    #[command(subcommand, arg_required_else_help = true)]
+    Lock(Lock),
     /// Run lua, with the `LUA_PATH` and `LUA_CPATH` set to the specified rocks tree.
     Lua(RunLua),
     /// Create a new Lua project.
     New(NewProject),
     /// List outdated rocks.
     Outdated(Outdated),
-    /// [UNIMPLEMENTED] Create a rock, packing sources or binaries.
-    Pack,
+    /// Pack the current project's sources into a `.src.rock`, optionally signing it.
+    Pack(Pack),
     /// Return the currently configured package path.
     Path(Path),
     /// Pin an existing rock, preventing any updates to the package.
@@ -137,34 +244,44 @@ pub enum Commands {
     Purge,
     /// Uninstall a rock.
     Remove(Remove),
-    /// Run a command that has been installed with rocks.
+    /// Run a command that has been installed with rocks,
+    /// or a script defined in the project's `scripts`.
     /// If the command is not found:
     /// When run from within a rocks project, this command will build the project.
     /// Otherwise, it will try to install a package named after the command.
     Run(Run),
+    /// Update rocks to the latest release.
+    /// Only use this if rocks was not installed with a package manager.
+    SelfUpdate(SelfUpdate),
     /// Query the Luarocks servers.
     #[command(arg_required_else_help = true)]
     Search(Search),
     /// Run the test suite in the current directory.
     Test(Test),
+    /// Manage the public keys that are trusted to sign rockspecs and sources.
+    #[command(subcommand, arg_required_else_help = true)]
+    TrustedKeys(TrustedKeys),
     /// [UNIMPLEMENTED] Uninstall a rock from the system.
     Uninstall,
     /// Unpins an existing rock, allowing updates to alter the package.
     Unpin(ChangePin),
     /// Updates all rocks in a project.
     Update(Update),
-    /// Upload a rockspec to the public rocks repository.
+    /// Upload a rockspec, and the rock packed by `rocks pack`, if any, to the public rocks repository.
+    /// Pre-built files can be uploaded with `--file`.
     Upload(Upload),
-    /// [UNIMPLEMENTED] Tell which file corresponds to a given module name.
-    Which,
+    /// Tell which installed files a module name resolves to.
+    Which(Which),
 }
 
 #[tokio::main(flavor = "multi_thread")]
 async fn main() {
     let cli = Cli::parse();
 
-    let config = ConfigBuilder::new()
-        .dev(Some(cli.dev))
+    confirm::set_assume_yes(cli.yes);
+
+    let cli_config = ConfigBuilder::new()
+        .dev(cli.dev.then_some(true))
         .lua_dir(cli.lua_dir)
         .lua_version(cli.lua_version)
         .namespace(cli.namespace)
@@ -172,55 +289,111 @@ async fn main() {
         .only_sources(cli.only_sources)
         .server(cli.server)
         .tree(cli.tree)
+        .tree_name(cli.tree_name)
+        .target_dir(cli.target_dir)
         .timeout(
             cli.timeout
                 .map(|duration| Duration::from_secs(duration as u64)),
         )
-        .no_project(Some(cli.no_project))
-        .verbose(Some(cli.verbose))
+        .lock_timeout(cli.lock_timeout.map(Duration::from_secs))
+        .platform(cli.platform)
+        .message_format(Some(cli.message_format))
+        .no_project(cli.no_project.then_some(true))
+        .verbose(cli.verbose.then_some(true))
+        .trusted_keys(cli.trusted_key)
+        .require_signatures(cli.require_signatures.then_some(true))
+        .danger_accept_invalid_certs(cli.insecure.then_some(true))
+        .allow_yanked(cli.allow_yanked.then_some(true))
+        .url_rewrites(cli.url_rewrite);
+    let config = ConfigBuilder::from_env()
+        .unwrap()
+        .merge(ConfigBuilder::from_config_file(cli.config).unwrap())
+        .merge(cli_config)
         .build()
         .unwrap();
 
-    match cli.command {
-        Commands::Search(search_data) => search::search(search_data, config).await.unwrap(),
-        Commands::Download(download_data) => {
-            download::download(download_data, config).await.unwrap()
+    let update_notice = match cli.message_format {
+        MessageFormat::Human => Some(tokio::spawn(self_update::print_update_notice(
+            config.clone(),
+        ))),
+        MessageFormat::Json => None,
+    };
+
+    let result = match cli.command {
+        Commands::Search(search_data) => search::search(search_data, config).await,
+        Commands::SelfUpdate(self_update_data) => {
+            self_update::self_update(self_update_data, config).await
         }
+        Commands::Download(download_data) => download::download(download_data, config).await,
+        Commands::Env(env) => match env {
+            Env::List => env::list_environments(config),
+            Env::Use(use_data) => env::use_environment(use_data, config),
+        },
+        Commands::Lock(lock) => match lock {
+            Lock::Diff(diff_data) => lock::diff(diff_data),
+        },
         Commands::Debug(debug) => match debug {
-            Debug::FetchRemote(unpack_data) => {
-                fetch::fetch_remote(unpack_data, config).await.unwrap()
-            }
-            Debug::Unpack(unpack_data) => unpack::unpack(unpack_data).await.unwrap(),
-            Debug::UnpackRemote(unpack_data) => {
-                unpack::unpack_remote(unpack_data, config).await.unwrap()
-            }
-            Debug::Project => project::debug_project().unwrap(),
+            Debug::FetchRemote(unpack_data) => fetch::fetch_remote(unpack_data, config).await,
+            Debug::Unpack(unpack_data) => unpack::unpack(unpack_data, config).await,
+            Debug::UnpackRemote(unpack_data) => unpack::unpack_remote(unpack_data, config).await,
+            Debug::Project => project::debug_project(),
         },
-        Commands::New(project_data) => project::write_project_rockspec(project_data).await.unwrap(),
-        Commands::Build(build_data) => build::build(build_data, config).await.unwrap(),
-        Commands::List(list_data) => list::list_installed(list_data, config).unwrap(),
-        Commands::Lua(run_lua) => run_lua::run_lua(run_lua, config).await.unwrap(),
-        Commands::Install(install_data) => install::install(install_data, config).await.unwrap(),
-        Commands::Outdated(outdated) => outdated::outdated(outdated, config).await.unwrap(),
-        Commands::InstallLua => install_lua::install_lua(config).await.unwrap(),
-        Commands::Fmt => format::format().unwrap(),
-        Commands::Purge => purge::purge(config).await.unwrap(),
-        Commands::Remove(remove_args) => remove::remove(remove_args, config).await.unwrap(),
-        Commands::Run(run_args) => run::run(run_args, config).await.unwrap(),
-        Commands::Test(test) => test::test(test, config).await.unwrap(),
-        Commands::Update(_update_args) => update::update(config).await.unwrap(),
-        Commands::Info(info_data) => info::info(info_data, config).await.unwrap(),
-        Commands::Path(path_data) => path::path(path_data, config).await.unwrap(),
-        Commands::Pin(pin_data) => pin::set_pinned_state(pin_data, config, Pinned).unwrap(),
-        Commands::Unpin(pin_data) => pin::set_pinned_state(pin_data, config, Unpinned).unwrap(),
-        Commands::Upload(upload_data) => upload::upload(upload_data, config).await.unwrap(),
-        Commands::Check => check::check(config).await.unwrap(),
-        Commands::Add => unimplemented!(),
-        Commands::Config => unimplemented!(),
-        Commands::Doc => unimplemented!(),
-        Commands::Lint => unimplemented!(),
-        Commands::Pack => unimplemented!(),
+        Commands::New(project_data) => project::write_project_rockspec(project_data).await,
+        Commands::Build(build_data) => build::build(build_data, config).await,
+        Commands::List(list_data) => list::list_installed(list_data, config).await,
+        Commands::Lua(run_lua) => run_lua::run_lua(run_lua, config).await,
+        Commands::Install(install_data) => install::install(install_data, config).await,
+        Commands::Outdated(outdated) => outdated::outdated(outdated, config).await,
+        Commands::InstallLua(install_lua) => install_lua::install_lua(install_lua, config).await,
+        Commands::Fmt => format::format(),
+        Commands::Purge => purge::purge(config).await,
+        Commands::Remove(remove_args) => remove::remove(remove_args, config).await,
+        Commands::Run(run_args) => run::run(run_args, config).await,
+        Commands::Test(test) => test::test(test, config).await,
+        Commands::TrustedKeys(trusted_keys) => match trusted_keys {
+            TrustedKeys::Add(add_data) => trusted_keys::add_keys(add_data, config),
+            TrustedKeys::List => trusted_keys::list_keys(config),
+        },
+        Commands::Update(update_data) => update::update(update_data, config).await,
+        Commands::Graph(graph_data) => graph::graph(graph_data, config),
+        Commands::Info(info_data) => info::info(info_data, config).await,
+        Commands::Path(path_data) => path::path(path_data, config).await,
+        Commands::Pin(pin_data) => pin::set_pinned_state(pin_data, config, Pinned),
+        Commands::Unpin(pin_data) => pin::set_pinned_state(pin_data, config, Unpinned),
+        Commands::Upload(upload_data) => upload::upload(upload_data, config).await,
+        Commands::Check => check::check(config).await,
+        Commands::CheckIntegrity(check_integrity_data) => {
+            check_integrity::check_integrity(check_integrity_data, config)
+        }
+        Commands::Clean(clean_data) => clean::clean(clean_data, config),
+        Commands::Completions(completions_data) => {
+            completions::completions(completions_data, &mut Cli::command())
+        }
+        Commands::Doc(doc_data) => doc::doc(doc_data, config).await,
+        Commands::Add(add_data) => add::add(add_data, config).await,
+        Commands::Config(config_cmd) => match config_cmd {
+            ConfigCmd::Doctor(doctor_data) => config::doctor(doctor_data, config).await,
+        },
+        Commands::Lint(lint_data) => lint::lint(lint_data, config),
+        Commands::Pack(pack_data) => pack::pack(pack_data),
         Commands::Uninstall => unimplemented!(),
-        Commands::Which => unimplemented!(),
+        Commands::Which(which_data) => which::which(which_data, config),
+    };
+
+    if let Some(update_notice) = update_notice {
+        let _ = update_notice.await;
+    }
+
+    if let Err(err) = result {
+        match cli.message_format {
+            MessageFormat::Human => panic!("{:?}", err),
+            MessageFormat::Json => {
+                Event::Error {
+                    message: format!("{:#}", err),
+                }
+                .emit(cli.message_format);
+                std::process::exit(1);
+            }
+        }
     }
 }
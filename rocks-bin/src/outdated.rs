@@ -18,12 +18,12 @@ pub struct Outdated {
 }
 
 pub async fn outdated(outdated_data: Outdated, config: Config) -> Result<()> {
-    let progress = MultiProgress::new();
+    let progress = MultiProgress::from_config(&config);
     let bar = progress.add(ProgressBar::from(
         "🔎 Checking for outdated rocks...".to_string(),
     ));
 
-    let tree = Tree::new(config.tree().clone(), LuaVersion::from(&config)?)?;
+    let tree = Tree::from_config(&config, LuaVersion::from(&config)?)?;
 
     let package_db = RemotePackageDB::from_config(&config).await?;
 
@@ -0,0 +1,57 @@
+use std::path::PathBuf;
+
+use clap::Args;
+use eyre::{OptionExt, Result};
+use rocks_lib::{
+    operations::{pack_project, sign_rock, PackFilter},
+    project::Project,
+    upload::SignatureProtocol,
+};
+
+#[derive(Args)]
+pub struct Pack {
+    /// The directory to write the packed rock to.
+    /// Defaults to the project root, where `rocks upload` picks it up.
+    #[arg(long)]
+    dest: Option<PathBuf>,
+    /// Write a detached signature of the packed rock to `<rock>.asc`.
+    #[arg(long)]
+    sign: bool,
+    /// The fingerprint or key ID of the secret key to sign with.
+    /// Defaults to GPG's default key.
+    #[arg(long, requires = "sign")]
+    key: Option<String>,
+    #[arg(long, default_value_t, requires = "sign")]
+    sign_protocol: SignatureProtocol,
+    /// Only pack the files that match this glob, relative to the project root,
+    /// in addition to the rockspec and the sources of the modules it declares.
+    /// Can be specified multiple times.
+    #[arg(long, value_name = "glob")]
+    include: Vec<String>,
+    /// Do not pack the files and directories that match this glob, relative to the project root,
+    /// e.g. `spec/fixtures`. Can be specified multiple times.
+    /// The sources of the modules that the rockspec declares can't be excluded.
+    #[arg(long, value_name = "glob")]
+    exclude: Vec<String>,
+}
+
+/// Pack the current project's sources into a `.src.rock`.
+pub fn pack(data: Pack) -> Result<()> {
+    let project = Project::current()?
+        .ok_or_eyre("'rocks pack' must be run in a project root, with a 'project.rockspec'")?;
+    let dest = data.dest.unwrap_or_else(|| project.root().to_path_buf());
+
+    let filter = PackFilter {
+        include: data.include,
+        exclude: data.exclude,
+    };
+    let rock_path = pack_project(&project, &dest, &filter)?;
+    println!("📦 Packed {}", rock_path.display());
+
+    if data.sign {
+        let signature_path = sign_rock(&rock_path, data.key.as_deref(), data.sign_protocol)?;
+        println!("🔏 Signed {}", signature_path.display());
+    }
+
+    Ok(())
+}
@@ -1,10 +1,12 @@
-use std::{env, str::FromStr as _};
+use std::{env, path::PathBuf, str::FromStr as _};
 
 use clap::Subcommand;
 use eyre::Result;
 use rocks_lib::{
     config::{Config, LuaVersion},
+    luarc::{self, LUARC_FILE_NAME},
     path::{BinPath, PackagePath, Paths},
+    project::Project,
     tree::Tree,
 };
 use strum::{EnumString, VariantNames};
@@ -21,6 +23,13 @@ pub struct Path {
     #[clap(default_value_t = false)]
     #[arg(long)]
     prepend: bool,
+
+    /// Generate the paths of each Lua version that rocks are installed for,
+    /// instead of only those of the current Lua version.
+    /// Each version's block is preceded by a comment naming the Lua version.
+    /// Only supported when generating export statements.
+    #[arg(long)]
+    all_versions: bool,
 }
 
 #[derive(Subcommand, PartialEq, Eq, Debug, Clone)]
@@ -38,6 +47,10 @@ enum PathCmd {
     /// Generate a `PATH` expression for `bin` executables in the rocks tree.
     /// (not formatted as a shell command)
     Bin,
+    /// Add the Lua source directories of the rocks tree to the `workspace.library`
+    /// of a `.luarc.json`, so that lua-language-server can resolve `require`s.
+    /// Other settings in an existing `.luarc.json` are preserved.
+    Lsp(LspArgs),
 }
 
 impl Default for PathCmd {
@@ -59,55 +72,92 @@ struct FullArgs {
     shell: Shell,
 }
 
-#[derive(EnumString, VariantNames, Display, ValueEnum, PartialEq, Eq, Debug, Clone)]
+#[derive(Args, PartialEq, Eq, Debug, Clone, Default)]
+struct LspArgs {
+    /// The `.luarc.json` to write to.
+    /// Defaults to the one in the project root, or in the current directory
+    /// if not in a project.
+    #[arg(long)]
+    output: Option<PathBuf>,
+}
+
+#[derive(EnumString, VariantNames, Display, ValueEnum, PartialEq, Eq, Debug, Clone, Default)]
 #[strum(serialize_all = "lowercase")]
 enum Shell {
+    #[default]
     Posix,
     Fish,
     Nu,
 }
 
-impl Default for Shell {
-    fn default() -> Self {
-        Self::Posix
-    }
-}
-
 pub async fn path(path_data: Path, config: Config) -> Result<()> {
-    let tree = Tree::new(config.tree().clone(), LuaVersion::from(&config)?)?;
-    let paths = Paths::from_tree(tree)?;
     let cmd = path_data.cmd.unwrap_or_default();
     let prepend = path_data.prepend;
-    match cmd {
-        PathCmd::Full(args) => {
-            let mut result = String::new();
-            let shell = args.shell;
-            let package_path = mk_package_path(&paths, prepend)?;
-            if !package_path.is_empty() {
-                result.push_str(format_export(&shell, "LUA_PATH", &package_path).as_str());
-                result.push('\n')
-            }
-            let package_cpath = mk_package_cpath(&paths, prepend)?;
-            if !package_cpath.is_empty() {
-                result.push_str(format_export(&shell, "LUA_CPATH", &package_cpath).as_str());
-                result.push('\n')
-            }
-            if !args.no_bin {
-                let path = mk_bin_path(&paths, prepend)?;
-                if !path.is_empty() {
-                    result.push_str(format_export(&shell, "PATH", &path).as_str());
-                    result.push('\n')
-                }
-            }
-            println!("{}", &result);
+    if path_data.all_versions {
+        let PathCmd::Full(args) = cmd else {
+            eyre::bail!("`--all-versions` is only supported when generating export statements");
+        };
+        let trees = Tree::all_from_config(&config);
+        if trees.is_empty() {
+            eyre::bail!("no rocks are installed for any Lua version");
         }
+        let blocks = trees
+            .into_iter()
+            .map(|tree| {
+                let version = tree.version().clone();
+                let exports = format_exports(&Paths::from_tree(tree)?, &args, prepend)?;
+                Ok(format!("# Lua {}\n{}", version, exports))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        println!("{}", blocks.join("\n"));
+        return Ok(());
+    }
+    let tree = Tree::from_config(&config, LuaVersion::from(&config)?)?;
+    let paths = Paths::from_tree(tree.clone())?;
+    match cmd {
+        PathCmd::Full(args) => println!("{}", &format_exports(&paths, &args, prepend)?),
         PathCmd::Lua => println!("{}", &mk_package_path(&paths, prepend)?),
         PathCmd::C => println!("{}", &mk_package_cpath(&paths, prepend)?),
         PathCmd::Bin => println!("{}", &mk_bin_path(&paths, prepend)?),
+        PathCmd::Lsp(args) => {
+            let output = match args.output {
+                Some(output) => output,
+                None => match Project::current()? {
+                    Some(project) => project.root().join(LUARC_FILE_NAME),
+                    None => env::current_dir()?.join(LUARC_FILE_NAME),
+                },
+            };
+            luarc::update_luarc(&output, &tree)?;
+            println!("Updated {}", output.display());
+        }
     }
     Ok(())
 }
 
+/// The export statements for `paths`, one per line.
+fn format_exports(paths: &Paths, args: &FullArgs, prepend: bool) -> Result<String> {
+    let mut result = String::new();
+    let shell = &args.shell;
+    let package_path = mk_package_path(paths, prepend)?;
+    if !package_path.is_empty() {
+        result.push_str(format_export(shell, "LUA_PATH", &package_path).as_str());
+        result.push('\n')
+    }
+    let package_cpath = mk_package_cpath(paths, prepend)?;
+    if !package_cpath.is_empty() {
+        result.push_str(format_export(shell, "LUA_CPATH", &package_cpath).as_str());
+        result.push('\n')
+    }
+    if !args.no_bin {
+        let path = mk_bin_path(paths, prepend)?;
+        if !path.is_empty() {
+            result.push_str(format_export(shell, "PATH", &path).as_str());
+            result.push('\n')
+        }
+    }
+    Ok(result)
+}
+
 fn mk_package_path(paths: &Paths, prepend: bool) -> Result<PackagePath> {
     let mut result = if prepend {
         PackagePath::from_str(env::var("LUA_PATH").unwrap_or_default().as_str()).unwrap_or_default()
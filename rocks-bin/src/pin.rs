@@ -15,7 +15,7 @@ pub struct ChangePin {
 }
 
 pub fn set_pinned_state(data: ChangePin, config: Config, pin: PinnedState) -> Result<()> {
-    let tree = Tree::new(config.tree().clone(), LuaVersion::from(&config)?)?;
+    let tree = Tree::from_config(&config, LuaVersion::from(&config)?)?;
 
     if let Some(mut rock) = tree.has_rock_and(&data.package.clone().into_package_req(), |package| {
         pin != package.pinned()
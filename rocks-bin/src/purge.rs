@@ -1,23 +1,21 @@
 use eyre::Result;
-use inquire::Confirm;
 use rocks_lib::{
     config::{Config, LuaVersion},
     progress::{MultiProgress, ProgressBar},
     tree::Tree,
 };
 
+use crate::utils::confirm::confirm;
+
 pub async fn purge(config: Config) -> Result<()> {
-    let tree = Tree::new(config.tree().clone(), LuaVersion::from(&config)?)?;
+    let tree = Tree::from_config(&config, LuaVersion::from(&config)?)?;
 
     let len = tree.list()?.len();
 
-    if Confirm::new(&format!("Are you sure you want to purge all {len} rocks?"))
-        .with_default(false)
-        .prompt()?
-    {
+    if confirm(&format!("Are you sure you want to purge all {len} rocks?"))? {
         let root_dir = tree.root();
 
-        let _spinner = MultiProgress::new().add(ProgressBar::from(format!(
+        let _spinner = MultiProgress::from_config(&config).add(ProgressBar::from(format!(
             "🗑️ Purging {}",
             root_dir.display()
         )));
@@ -1,22 +1,58 @@
 use clap::Args;
-use eyre::Result;
+use eyre::{OptionExt as _, Result};
 use rocks_lib::{
     config::{Config, LuaVersion},
+    operations,
     package::{PackageName, PackageSpec, PackageVersion},
     progress::{MultiProgress, Progress},
+    project::{DependencyType, Project},
     remote_package_db::RemotePackageDB,
     tree::Tree,
 };
 
+use crate::utils::confirm::confirm;
+
 #[derive(Args)]
 pub struct Remove {
     /// The name of the rock to remove.
     name: PackageName,
     /// The name of the version to remove.
     version: Option<PackageVersion>,
+    /// Remove the rock from the current project's dependencies of this kind,
+    /// then uninstall it unless another installed rock depends on it.
+    /// Test dependencies are uninstalled from the test tree.
+    #[arg(long, value_enum, conflicts_with = "version")]
+    from: Option<DependencyType>,
 }
 
 pub async fn remove(remove_args: Remove, config: Config) -> Result<()> {
+    if let Some(dependency_type) = remove_args.from {
+        let mut project = Project::current()?.ok_or_eyre(
+            "'rocks remove --from' must be run in a project root, with a 'project.rockspec'",
+        )?;
+        if !confirm(&format!(
+            "Remove {} from {} and uninstall it?",
+            remove_args.name,
+            dependency_type.rockspec_field()
+        ))? {
+            return Ok(());
+        }
+        operations::remove_project_dependency(
+            &mut project,
+            dependency_type,
+            &remove_args.name,
+            &config,
+            &Progress::Progress(MultiProgress::from_config(&config).new_bar()),
+        )
+        .await?;
+        println!(
+            "Removed {} from {}",
+            remove_args.name,
+            dependency_type.rockspec_field()
+        );
+        return Ok(());
+    }
+
     let package_db = RemotePackageDB::from_config(&config).await?;
 
     let target_version = remove_args
@@ -24,17 +60,22 @@ pub async fn remove(remove_args: Remove, config: Config) -> Result<()> {
         .or(package_db.latest_version(&remove_args.name).cloned())
         .unwrap();
 
-    let tree = Tree::new(config.tree().clone(), LuaVersion::from(&config)?)?;
+    let tree = Tree::from_config(&config, LuaVersion::from(&config)?)?;
 
     match tree.has_rock(
         &PackageSpec::new(remove_args.name.clone(), target_version.clone()).into_package_req(),
     ) {
-        Some(package) => Ok(rocks_lib::operations::remove(
-            package,
-            &config,
-            &Progress::Progress(MultiProgress::new().new_bar()),
-        )
-        .await?),
+        Some(package) => {
+            if !confirm(&format!("Remove {}@{}?", package.name(), package.version()))? {
+                return Ok(());
+            }
+            Ok(rocks_lib::operations::remove(
+                package,
+                &config,
+                &Progress::Progress(MultiProgress::from_config(&config).new_bar()),
+            )
+            .await?)
+        }
         None => {
             eprintln!("Could not find {}@{}", remove_args.name, target_version);
             Ok(())
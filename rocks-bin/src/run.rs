@@ -16,6 +16,8 @@ use crate::build::Build;
 #[derive(Args)]
 pub struct Run {
     /// The command to run.
+    /// If run from within a project that defines a script of this name in its `scripts`,
+    /// the script is run instead.
     command: String,
     /// Arguments to pass to the program.
     args: Option<Vec<String>>,
@@ -23,11 +25,19 @@ pub struct Run {
 
 pub async fn run(run: Run, config: Config) -> Result<()> {
     let project = Project::current()?;
+    if let Some(prj) = project
+        .as_ref()
+        .filter(|prj| prj.script(&run.command).is_some())
+    {
+        let args = run.args.unwrap_or_default();
+        operations::run_script(prj, &run.command, args, config).await?;
+        return Ok(());
+    }
     let lua_version = match &project {
         Some(prj) => prj.rockspec().lua_version_from_config(&config)?,
         None => LuaVersion::from(&config)?,
     };
-    let tree = Tree::new(config.tree().clone(), lua_version.clone())?;
+    let tree = Tree::from_config(&config, lua_version.clone())?;
     let paths = Paths::from_tree(tree)?;
     unsafe {
         // safe as long as this is single-threaded
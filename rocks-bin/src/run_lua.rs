@@ -1,4 +1,4 @@
-use std::process::Command;
+use std::process::{Command, Stdio};
 
 use clap::Args;
 use eyre::{eyre, Result};
@@ -7,6 +7,7 @@ use rocks_lib::{
     config::{Config, LuaVersion},
     lua_installation::get_installed_lua_version,
     path::Paths,
+    progress,
     project::Project,
     tree::Tree,
 };
@@ -15,9 +16,16 @@ use rocks_lib::{
 #[clap(disable_help_flag = true)]
 pub struct RunLua {
     /// Arguments to pass to Lua. See `lua -h`.
+    /// Use `-` as the script to read it from stdin.
     args: Option<Vec<String>>,
 
-    /// Path to the Lua interpreter to use
+    /// Execute a chunk of Lua code before running the script (if any).
+    /// Can be specified multiple times.
+    #[arg(short = 'e', long = "eval", value_name = "CHUNK")]
+    eval: Vec<String>,
+
+    /// Path to the Lua interpreter to use.
+    /// Defaults to `luajit` for LuaJIT and to `lua` otherwise.
     #[arg(long)]
     lua: Option<String>,
 
@@ -27,8 +35,12 @@ pub struct RunLua {
 }
 
 pub async fn run_lua(run_lua: RunLua, config: Config) -> Result<()> {
-    let lua_cmd = run_lua.lua.unwrap_or("lua".into());
     if run_lua.help {
+        let lua_cmd = run_lua.lua.unwrap_or_else(|| {
+            LuaVersion::from(&config)
+                .map_or("lua", |lua_version| lua_version.interpreter())
+                .into()
+        });
         return print_lua_help(&lua_cmd);
     }
     let project = Project::current()?;
@@ -36,9 +48,12 @@ pub async fn run_lua(run_lua: RunLua, config: Config) -> Result<()> {
         Some(prj) => prj.rockspec().lua_version_from_config(&config)?,
         None => LuaVersion::from(&config)?,
     };
+    let lua_cmd = run_lua
+        .lua
+        .unwrap_or_else(|| lua_version.interpreter().into());
     match get_installed_lua_version(&lua_cmd).and_then(|ver| Ok(LuaVersion::from_version(ver)?)) {
         Ok(installed_version) => {
-            if installed_version != lua_version {
+            if !installed_version.is_compatible_with(&lua_version) {
                 return Err(eyre!(
                     "{} -v (= {}) does not match expected Lua version {}",
                     &lua_cmd,
@@ -48,16 +63,20 @@ pub async fn run_lua(run_lua: RunLua, config: Config) -> Result<()> {
             }
         }
         Err(_) => {
-            eprintln!(
-                "⚠️ WARNING: Could not parse Lua version from '{} -v' output. Assuming Lua {} compatibility.",
+            progress::warn(
+                config.message_format(),
+                format!(
+                "Could not parse Lua version from '{} -v' output. Assuming Lua {} compatibility.",
                 &lua_cmd, lua_version
+            ),
             );
         }
     }
-    let tree = Tree::new(config.tree().clone(), lua_version.clone())?;
+    let tree = Tree::from_config(&config, lua_version.clone())?;
     let paths = Paths::from_tree(tree)?;
     let status = match Command::new(&lua_cmd)
-        .args(run_lua.args.unwrap_or_default())
+        .args(lua_args(run_lua.eval, run_lua.args.unwrap_or_default()))
+        .stdin(Stdio::inherit())
         .env("PATH", paths.path_prepended().joined())
         .env("LUA_PATH", paths.package_path().joined())
         .env("LUA_CPATH", paths.package_cpath().joined())
@@ -80,6 +99,14 @@ pub async fn run_lua(run_lua: RunLua, config: Config) -> Result<()> {
     }
 }
 
+/// `lua` runs `-e` chunks in order, before the script and its arguments.
+fn lua_args(eval: Vec<String>, args: Vec<String>) -> Vec<String> {
+    eval.into_iter()
+        .flat_map(|chunk| ["-e".into(), chunk])
+        .chain(args)
+        .collect()
+}
+
 fn print_lua_help(lua_cmd: &str) -> Result<()> {
     let output = match Command::new(lua_cmd)
         // HACK: This fails with exit 1, because lua doesn't actually have a help flag (╯°□°)╯︵ ┻━┻
@@ -97,14 +124,15 @@ fn print_lua_help(lua_cmd: &str) -> Result<()> {
         .join("\n");
     print!(
         "
-Usage: rocks lua -- [LUA_OPTIONS] [SCRIPT [ARGS]]...
+Usage: rocks lua [-e CHUNK]... -- [LUA_OPTIONS] [SCRIPT [ARGS]]...
 
 Arguments:
   [LUA_OPTIONS]...
 {}
 
 Options:
-  -h, --help  Print help
+  -e, --eval <CHUNK>  Execute a chunk of Lua code before running the script (if any)
+  -h, --help          Print help
 ",
         lua_help,
     );
@@ -126,4 +154,19 @@ mod test {
         let config = ConfigBuilder::new().build().unwrap();
         run_lua(args, config).await.unwrap()
     }
+
+    #[test]
+    fn eval_chunks_precede_script() {
+        assert_eq!(
+            lua_args(
+                vec!["print(require('foo'))".into(), "x = 1".into()],
+                vec!["-".into(), "arg".into()]
+            ),
+            vec!["-e", "print(require('foo'))", "-e", "x = 1", "-", "arg"]
+        );
+        assert_eq!(
+            lua_args(Vec::new(), vec!["script.lua".into()]),
+            vec!["script.lua"]
+        );
+    }
 }
@@ -7,26 +7,37 @@ use text_trees::{FormatCharacters, StringTreeNode, TreeFormatting};
 
 use rocks_lib::{
     config::Config,
+    operations::filter_by_labels,
     package::{PackageName, PackageReq, PackageVersion},
-    progress::{MultiProgress, ProgressBar},
-    remote_package_db::RemotePackageDB,
+    progress::{MultiProgress, Progress, ProgressBar},
+    remote_package_db::{did_you_mean, RemotePackageDB},
 };
 
 #[derive(Args)]
 pub struct Search {
     lua_package_req: PackageReq,
     // TODO(vhyrro): Add options.
-    /// Return a machine readable format.
-    #[arg(long)]
+    /// Only show rocks with this label (`description.labels`), e.g. `testing`.
+    /// Can be specified multiple times, to show rocks that have all of the labels.
+    #[arg(long, value_name = "label")]
+    label: Vec<String>,
+    /// Print one `name<TAB>version<TAB>summary` row per matching version,
+    /// without headers or colours.
+    /// The columns are stable, so that the output can be used in scripts.
+    /// The summary is currently always empty, as the manifest doesn't include it.
+    #[arg(long, conflicts_with = "json")]
     porcelain: bool,
+    /// Return the results as a JSON object, mapping rock names to their versions.
+    #[arg(long)]
+    json: bool,
 }
 
 pub async fn search(data: Search, config: Config) -> Result<()> {
-    let progress = MultiProgress::new();
-    let bar = progress.add(ProgressBar::from(format!(
+    let progress = MultiProgress::from_config(&config);
+    let bar = Progress::Progress(progress.add(ProgressBar::from(format!(
         "🔎 Searching for `{}`...",
         data.lua_package_req
-    )));
+    ))));
 
     let formatting = TreeFormatting::dir_tree(FormatCharacters::box_chars());
 
@@ -35,13 +46,36 @@ pub async fn search(data: Search, config: Config) -> Result<()> {
     let lua_package_req = data.lua_package_req;
 
     let result = package_db.search(&lua_package_req);
+    let result = filter_by_labels(result, &data.label, &package_db, &config, &bar).await?;
 
-    bar.finish_and_clear();
+    bar.map(|b| b.finish_and_clear());
 
     if data.porcelain {
+        for (name, versions) in result.into_iter().sorted() {
+            for version in versions {
+                println!("{}\t{}\t", name, version);
+            }
+        }
+    } else if data.json {
         let rock_to_version_map: HashMap<&PackageName, Vec<&PackageVersion>> =
             HashMap::from_iter(result);
         println!("{}", serde_json::to_string(&rock_to_version_map)?);
+    } else if result.is_empty() {
+        let suggestions = package_db
+            .similar_names(lua_package_req.name())
+            .into_iter()
+            .cloned()
+            .collect_vec();
+        println!(
+            "No rocks found matching `{}`{}{}",
+            lua_package_req,
+            if data.label.is_empty() {
+                String::new()
+            } else {
+                format!(" with labels {}", data.label.iter().join(", "))
+            },
+            did_you_mean(&suggestions)
+        );
     } else {
         for (key, versions) in result.into_iter().sorted() {
             let mut tree = StringTreeNode::new(key.to_string().to_owned());
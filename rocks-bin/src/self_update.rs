@@ -0,0 +1,70 @@
+use std::io::IsTerminal as _;
+
+use clap::Args;
+use eyre::Result;
+use rocks_lib::{
+    config::Config,
+    progress::{MultiProgress, Progress},
+    self_update::{self, RELEASES_URL},
+};
+use semver::Version;
+
+#[derive(Args)]
+pub struct SelfUpdate {
+    /// Only check whether a new release is available, without installing it.
+    #[arg(long)]
+    check: bool,
+}
+
+fn current_version() -> Version {
+    env!("CARGO_PKG_VERSION")
+        .parse()
+        .expect("the crate version is a valid semver version")
+}
+
+/// Replace the running `rocks` executable with the latest release.
+/// This is meant for installs from the release page, not for installs via a package manager.
+pub async fn self_update(data: SelfUpdate, config: Config) -> Result<()> {
+    let release = self_update::latest_release(RELEASES_URL, &config).await?;
+    let current_version = current_version();
+    if release.version <= current_version {
+        println!("rocks {} is up to date", current_version);
+        return Ok(());
+    }
+    if data.check {
+        println!(
+            "rocks {} is available (installed: {})",
+            release.version, current_version
+        );
+        return Ok(());
+    }
+
+    let executable = std::env::current_exe()?;
+    let progress = MultiProgress::from_config(&config);
+    let bar = Progress::Progress(progress.new_bar());
+    self_update::install_release(&release, &executable, &config, &bar).await?;
+    bar.map(|b| {
+        b.finish_with_message(format!(
+            "Updated rocks {} -> {}",
+            current_version, release.version
+        ))
+    });
+
+    Ok(())
+}
+
+/// Print a notice if a new release is available and update checks are enabled.
+/// Nothing is printed in non-interactive environments, like CI.
+pub async fn print_update_notice(config: Config) {
+    if !std::io::stderr().is_terminal() || std::env::var_os("CI").is_some() {
+        return;
+    }
+    let current_version = current_version();
+    if let Some(latest) = self_update::update_notice(&current_version, RELEASES_URL, &config).await
+    {
+        eprintln!(
+            "📢 rocks {} is available (installed: {}). Run `rocks self-update` to install it.",
+            latest, current_version
+        );
+    }
+}
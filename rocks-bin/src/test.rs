@@ -1,12 +1,17 @@
+use std::num::NonZeroUsize;
+
 use clap::Args;
-use eyre::{OptionExt, Result};
+use eyre::{eyre, OptionExt, Result};
 use rocks_lib::{
     config::Config,
-    operations::{ensure_busted, ensure_dependencies, run_tests, TestEnv},
+    operations::{
+        ensure_busted, ensure_dependencies, ensure_luacov, run_tests, run_tests_in_parallel,
+        run_tests_with_coverage, CoverageConfig, CoverageFormat, RunTestsError, TestEnv,
+    },
     progress::MultiProgress,
     project::Project,
     remote_package_db::RemotePackageDB,
-    tree::Tree,
+    rockspec::TestSpec,
 };
 
 #[derive(Args)]
@@ -16,6 +21,18 @@ pub struct Test {
     /// Don't isolate the user environment (keep `HOME` and `XDG` environment variables).
     #[arg(long)]
     impure: bool,
+    /// Collect code coverage with luacov and generate a report.
+    #[arg(long)]
+    coverage: bool,
+    /// The format of the coverage report.
+    #[arg(long, value_enum, default_value_t, requires = "coverage")]
+    coverage_format: CoverageFormat,
+    /// Fail if the total coverage (in percent) is below this threshold.
+    #[arg(long, requires = "coverage")]
+    min_coverage: Option<f64>,
+    /// Run the test files (`spec/**/*_spec.lua`) in up to this many busted processes in parallel.
+    #[arg(long, short, conflicts_with = "coverage")]
+    jobs: Option<NonZeroUsize>,
 }
 
 pub async fn test(test: Test, config: Config) -> Result<()> {
@@ -27,21 +44,68 @@ pub async fn test(test: Test, config: Config) -> Result<()> {
         Err(_) => rockspec.test_lua_version().ok_or_eyre("lua version not set! Please provide a version through `--lua-version <ver>` or add it to your rockspec's dependencies"),
     }?;
     let package_db = RemotePackageDB::from_config(&config).await?;
+    let test_command = matches!(
+        rockspec.test.for_platform(config.platform()),
+        TestSpec::Command(_)
+    );
     let test_config = config.with_lua_version(lua_version);
-    let tree = Tree::new(
-        test_config.tree().clone(),
-        test_config.lua_version().unwrap().clone(),
-    )?;
-    let progress = MultiProgress::new_arc();
-    // TODO(#204): Only ensure busted if running with busted (e.g. a .busted directory exists)
-    ensure_busted(&tree, &package_db, &test_config, progress.clone()).await?;
-    ensure_dependencies(rockspec, &tree, &package_db, &test_config, progress).await?;
+    let progress = MultiProgress::new_arc_from_config(&test_config);
+    if !test_command {
+        // TODO(#204): Only ensure busted if running with busted (e.g. a .busted directory exists)
+        ensure_busted(&package_db, &test_config, progress.clone()).await?;
+    }
+    if test.coverage {
+        ensure_luacov(
+            &package_db,
+            test.coverage_format,
+            &test_config,
+            progress.clone(),
+        )
+        .await?;
+    }
+    ensure_dependencies(rockspec, &package_db, &test_config, progress).await?;
     let test_args = test.test_args.unwrap_or_default();
     let test_env = if test.impure {
         TestEnv::Impure
     } else {
         TestEnv::Pure
     };
-    run_tests(project, test_args, test_env, test_config).await?;
+    if test.coverage {
+        let coverage_config = CoverageConfig {
+            format: test.coverage_format,
+            min_coverage: test.min_coverage,
+        };
+        let report =
+            run_tests_with_coverage(project, test_args, test_env, coverage_config, test_config)
+                .await?;
+        println!(
+            "Coverage: {:.2}% (report: {})",
+            report.coverage,
+            report.report.display()
+        );
+    } else if let Some(jobs) = test.jobs.filter(|jobs| jobs.get() > 1) {
+        let summary =
+            run_tests_in_parallel(project, test_args, test_env, jobs, test_config).await?;
+        println!(
+            "{} test files passed, {} failed",
+            summary.passed.len(),
+            summary.failed.len()
+        );
+        for test_file in &summary.failed {
+            println!("  ❌ {}", test_file.display());
+        }
+        if !summary.success() {
+            return Err(eyre!("tests failed!"));
+        }
+    } else {
+        match run_tests(project, test_args, test_env, test_config).await {
+            // Exit with the test command's exit code, so that it can be checked by CI, etc.
+            Err(RunTestsError::TestCommandFailure { command, status }) => {
+                eprintln!("test command `{command}` failed ({status})");
+                std::process::exit(status.code().unwrap_or(1));
+            }
+            result => result?,
+        }
+    }
     Ok(())
 }
@@ -0,0 +1,42 @@
+use std::path::PathBuf;
+
+use clap::{Args, Subcommand};
+use eyre::Result;
+use itertools::Itertools;
+use rocks_lib::{config::Config, signature::Keyring};
+
+#[derive(Subcommand)]
+pub enum TrustedKeys {
+    /// Import public keys that are trusted to sign rockspecs and sources.
+    Add(AddKeys),
+    /// List the trusted public keys.
+    List,
+}
+
+#[derive(Args)]
+pub struct AddKeys {
+    /// Path to an (armored or binary) GPG public key file.
+    path: PathBuf,
+}
+
+pub fn add_keys(data: AddKeys, config: Config) -> Result<()> {
+    let keys = std::fs::read(&data.path)?;
+    let fingerprints = Keyring::new(&config)?.import(&keys)?;
+
+    if fingerprints.is_empty() {
+        println!("No keys imported from {}", data.path.display());
+    }
+    for fingerprint in fingerprints {
+        println!("Imported {}", fingerprint);
+    }
+
+    Ok(())
+}
+
+pub fn list_keys(config: Config) -> Result<()> {
+    for key in Keyring::new(&config)?.list()? {
+        println!("{} {}", key.fingerprint, key.user_ids.iter().join(", "));
+    }
+
+    Ok(())
+}
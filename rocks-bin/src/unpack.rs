@@ -24,12 +24,12 @@ pub struct UnpackRemote {
     pub path: Option<PathBuf>,
 }
 
-pub async fn unpack(data: Unpack) -> Result<()> {
+pub async fn unpack(data: Unpack, config: Config) -> Result<()> {
     let destination = data.destination.unwrap_or_else(|| {
         PathBuf::from(data.path.to_string_lossy().trim_end_matches(".src.rock"))
     });
     let src_file = File::open(data.path)?;
-    let progress = MultiProgress::new();
+    let progress = MultiProgress::from_config(&config);
     let bar = Progress::Progress(progress.new_bar());
 
     let unpack_path = rocks_lib::operations::unpack_src_rock(src_file, destination, &bar).await?;
@@ -50,10 +50,15 @@ and type `rocks make` to build.",
 pub async fn unpack_remote(data: UnpackRemote, config: Config) -> Result<()> {
     let package_req = data.package_req;
     let package_db = RemotePackageDB::from_config(&config).await?;
-    let progress = MultiProgress::new();
+    let progress = MultiProgress::from_config(&config);
     let bar = Progress::Progress(progress.new_bar());
-    let rock = rocks_lib::operations::search_and_download_src_rock(&package_req, &package_db, &bar)
-        .await?;
+    let rock = rocks_lib::operations::search_and_download_src_rock(
+        &package_req,
+        &package_db,
+        &config,
+        &bar,
+    )
+    .await?;
     let cursor = Cursor::new(rock.bytes);
 
     let destination = data
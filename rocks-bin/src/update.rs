@@ -1,34 +1,118 @@
+use std::io::IsTerminal as _;
+
+use crate::lock;
 use clap::Args;
-use eyre::Result;
+use eyre::{eyre, Result};
+use inquire::MultiSelect;
+use itertools::Itertools as _;
 use rocks_lib::config::LuaVersion;
-use rocks_lib::lockfile::PinnedState;
+use rocks_lib::lockfile::{LocalPackage, PinnedState};
+use rocks_lib::package::PackageVersion;
 use rocks_lib::progress::{MultiProgress, ProgressBar};
 use rocks_lib::remote_package_db::RemotePackageDB;
 use rocks_lib::{config::Config, operations, package::PackageReq, tree::Tree};
 
 #[derive(Args)]
-pub struct Update {}
+pub struct Update {
+    /// Print the updates that would be applied, without installing anything.
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Select the rocks to update from a list of the available updates.
+    /// Pinned rocks are not listed.
+    #[arg(long, short, conflicts_with = "dry_run")]
+    interactive: bool,
+
+    /// Don't print the rocks that were updated in the lockfile.
+    #[arg(long)]
+    quiet: bool,
+}
+
+pub async fn update(data: Update, config: Config) -> Result<()> {
+    let before = if data.quiet || data.dry_run {
+        None
+    } else {
+        lock::lockfile_snapshot(&config)?
+    };
 
-pub async fn update(config: Config) -> Result<()> {
-    let progress = MultiProgress::new_arc();
-    progress.map(|p| p.add(ProgressBar::from("🔎 Looking for updates...".to_string())));
+    update_impl(data, &config).await?;
 
-    let tree = Tree::new(config.tree().clone(), LuaVersion::from(&config)?)?;
+    if let Some(before) = before {
+        lock::print_lockfile_changes(&before, &config)?;
+    }
+    Ok(())
+}
+
+async fn update_impl(data: Update, config: &Config) -> Result<()> {
+    if data.interactive && !std::io::stdin().is_terminal() {
+        return Err(eyre!(
+            "`rocks update --interactive` requires a terminal.
+Use `rocks update --dry-run` to list the available updates, or `rocks update` to apply all of them."
+        ));
+    }
 
+    let progress = MultiProgress::new_arc_from_config(config);
+    let bar = progress.map(|p| p.add(ProgressBar::from("🔎 Looking for updates...".to_string())));
+
+    let lua_version = LuaVersion::from(config)?;
+    let package_db = RemotePackageDB::from_config(config).await?;
+
+    if data.dry_run || data.interactive {
+        // The updates are resolved, and selected, without creating or locking the tree.
+        let snapshot =
+            Tree::from_config_unchecked(config, lua_version.clone()).lockfile_snapshot()?;
+        let updates = available_updates(snapshot.rocks().values(), &package_db)?;
+        bar.map(|b| b.finish_and_clear());
+        if updates.is_empty() {
+            println!("Nothing to update");
+            return Ok(());
+        }
+
+        if data.dry_run {
+            println!("Would update:");
+            for update in &updates {
+                println!("  {}", update);
+            }
+            println!("Would remove:");
+            for AvailableUpdate { package, .. } in &updates {
+                println!("  {}@{}", package.name(), package.version());
+            }
+            return Ok(());
+        }
+
+        let selected = MultiSelect::new("Select the rocks to update:", updates).prompt()?;
+
+        // Only lock the tree once the rocks are selected,
+        // skipping the ones that another process removed in the meantime.
+        let tree = Tree::from_config(config, lua_version)?;
+        let lockfile = tree.lockfile()?;
+        for AvailableUpdate { package, .. } in selected {
+            if lockfile.get(&package.id()).is_none() {
+                continue;
+            }
+            operations::update(
+                package.clone(),
+                constraint_of(package)?,
+                &package_db,
+                config,
+                progress.clone(),
+            )
+            .await?;
+        }
+        return Ok(());
+    }
+
+    let tree = Tree::from_config(config, lua_version)?;
     let lockfile = tree.lockfile()?;
     let rocks = lockfile.rocks();
-    let package_db = RemotePackageDB::from_config(&config).await?;
 
     for package in rocks.values() {
         if package.pinned() == PinnedState::Unpinned {
             operations::update(
                 package.clone(),
-                PackageReq::new(
-                    package.name().to_string(),
-                    package.constraint().to_string_opt(),
-                )?,
+                constraint_of(package)?,
                 &package_db,
-                &config,
+                config,
                 progress.clone(),
             )
             .await?;
@@ -37,3 +121,49 @@ pub async fn update(config: Config) -> Result<()> {
 
     Ok(())
 }
+
+struct AvailableUpdate<'a> {
+    package: &'a LocalPackage,
+    latest_version: PackageVersion,
+}
+
+impl std::fmt::Display for AvailableUpdate<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {} -> {}",
+            self.package.name(),
+            self.package.version(),
+            self.latest_version
+        )
+    }
+}
+
+/// The updates of the unpinned `packages`, sorted by name.
+fn available_updates<'a>(
+    packages: impl IntoIterator<Item = &'a LocalPackage>,
+    package_db: &RemotePackageDB,
+) -> Result<Vec<AvailableUpdate<'a>>> {
+    let mut updates = Vec::new();
+    for package in packages {
+        if let Some(latest_version) =
+            operations::update_plan(package, &constraint_of(package)?, package_db)?
+        {
+            updates.push(AvailableUpdate {
+                package,
+                latest_version,
+            });
+        }
+    }
+    Ok(updates
+        .into_iter()
+        .sorted_by(|a, b| a.package.name().cmp(b.package.name()))
+        .collect())
+}
+
+fn constraint_of(package: &LocalPackage) -> Result<PackageReq> {
+    Ok(PackageReq::new(
+        package.name().to_string(),
+        package.constraint().to_string_opt(),
+    )?)
+}
@@ -1,18 +1,59 @@
+use std::path::PathBuf;
+
 use clap::Args;
-use eyre::Result;
+use eyre::{eyre, Result};
 use rocks_lib::{
     config::Config,
     project::Project,
-    upload::{upload_from_project, ApiKey, SignatureProtocol},
+    upload::{upload_files, upload_from_project, ApiKey, SignatureProtocol},
 };
 
 #[derive(Args)]
 pub struct Upload {
     #[arg(long, default_value_t)]
     sign_protocol: SignatureProtocol,
+
+    /// Upload a pre-built `.rockspec` file, and optionally a `.rock` file,
+    /// instead of the project's rockspec and packed rock.
+    /// Can be specified once for the rockspec and once for the rock.
+    /// The rock's detached signature (`<rock>.asc`) is uploaded too, if it exists.
+    #[arg(long = "file", value_name = "PATH")]
+    files: Vec<PathBuf>,
 }
 
 pub async fn upload(data: Upload, config: Config) -> Result<()> {
+    if !data.files.is_empty() {
+        let (rockspecs, rocks): (Vec<_>, Vec<_>) = data
+            .files
+            .iter()
+            .partition(|path| path.extension().is_some_and(|ext| ext == "rockspec"));
+        if let Some(path) = rocks
+            .iter()
+            .find(|path| path.extension().is_none_or(|ext| ext != "rock"))
+        {
+            return Err(eyre!(
+                "{} is neither a .rockspec nor a .rock file",
+                path.display()
+            ));
+        }
+        let rockspec = match rockspecs.as_slice() {
+            [rockspec] => rockspec,
+            _ => return Err(eyre!("exactly one .rockspec file must be uploaded")),
+        };
+        if rocks.len() > 1 {
+            return Err(eyre!("at most one .rock file can be uploaded"));
+        }
+        upload_files(
+            rockspec,
+            rocks.first().map(|path| path.as_path()),
+            &ApiKey::new()?,
+            data.sign_protocol,
+            &config,
+        )
+        .await?;
+        return Ok(());
+    }
+
     let project = Project::current()?.unwrap();
 
     upload_from_project(&project, &ApiKey::new()?, data.sign_protocol, &config).await?;
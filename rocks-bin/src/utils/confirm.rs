@@ -0,0 +1,51 @@
+use std::{
+    io::IsTerminal as _,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use eyre::Result;
+use inquire::Confirm;
+
+static ASSUME_YES: AtomicBool = AtomicBool::new(false);
+
+/// Answer all confirmation prompts with "yes" (`--yes`).
+pub fn set_assume_yes(assume_yes: bool) {
+    ASSUME_YES.store(assume_yes, Ordering::Relaxed);
+}
+
+/// Ask the user to confirm a destructive operation.
+/// Returns `true` without prompting if `--yes` is set.
+/// If stdin is not a terminal, nobody can answer the prompt,
+/// so the operation is not confirmed.
+pub fn confirm(prompt: &str) -> Result<bool> {
+    confirm_impl(
+        prompt,
+        ASSUME_YES.load(Ordering::Relaxed),
+        std::io::stdin().is_terminal(),
+    )
+}
+
+fn confirm_impl(prompt: &str, assume_yes: bool, interactive: bool) -> Result<bool> {
+    if assume_yes {
+        return Ok(true);
+    }
+    if !interactive {
+        eprintln!(
+            "{} Not confirmed, as stdin is not a terminal. Pass `--yes` to skip this prompt.",
+            prompt
+        );
+        return Ok(false);
+    }
+    Ok(Confirm::new(prompt).with_default(false).prompt()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn assume_yes_skips_prompt() {
+        assert!(confirm_impl("Remove foo@1.0.0-1?", true, false).unwrap());
+        assert!(!confirm_impl("Remove foo@1.0.0-1?", false, false).unwrap());
+    }
+}
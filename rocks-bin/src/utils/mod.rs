@@ -1 +1,2 @@
+pub mod confirm;
 pub mod github_metadata;
@@ -0,0 +1,66 @@
+use clap::Args;
+use eyre::{eyre, Result};
+use rocks_lib::{
+    config::{Config, LuaVersion},
+    path::{ModuleKind, Paths},
+    project::Project,
+    tree::Tree,
+};
+
+#[derive(Args)]
+pub struct Which {
+    /// The module name, as passed to `require`, e.g. `foo.bar`.
+    module: String,
+
+    /// Only search for pure Lua modules, on the `package.path`.
+    #[arg(long, conflicts_with = "cpath")]
+    lua: bool,
+
+    /// Only search for C modules, on the `package.cpath`.
+    #[arg(long)]
+    cpath: bool,
+
+    /// Show the files that `require` tries, in order, and the one that it loads.
+    #[arg(long)]
+    resolve_require: bool,
+}
+
+/// Tell which installed files a module name resolves to.
+pub fn which(data: Which, config: Config) -> Result<()> {
+    let lua_version = match Project::current()? {
+        Some(project) => project.rockspec().lua_version_from_config(&config)?,
+        None => LuaVersion::from(&config)?,
+    };
+    let tree = Tree::from_config(&config, lua_version)?;
+    let paths = Paths::from_tree(tree)?;
+    let kind = if data.lua {
+        Some(ModuleKind::Lua)
+    } else if data.cpath {
+        Some(ModuleKind::C)
+    } else {
+        None
+    };
+
+    if data.resolve_require {
+        let resolution = paths.resolve_require(&data.module, kind);
+        for file in &resolution.searched {
+            println!("no file '{}'", file.path.display());
+        }
+        return match resolution.loaded {
+            Some(file) => {
+                println!("{} ({} module)", file.path.display(), file.kind);
+                Ok(())
+            }
+            None => Err(eyre!("module '{}' not found", data.module)),
+        };
+    }
+
+    let files = paths.find_module(&data.module, kind);
+    if files.is_empty() {
+        return Err(eyre!("module '{}' not found", data.module));
+    }
+    for file in files {
+        println!("{}", file.path.display());
+    }
+    Ok(())
+}
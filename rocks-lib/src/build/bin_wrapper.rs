@@ -0,0 +1,110 @@
+//! Launchers for the command-line scripts that rocks install with `build.install.bin`.
+//!
+//! The scripts themselves are installed into the rock's own `bin` directory.
+//! The tree's shared `bin` directory gets a launcher with the same name,
+//! which puts the tree's rocks on the `LUA_PATH` and `LUA_CPATH`
+//! and runs the script with the Lua interpreter for the tree's Lua version,
+//! so that the script works regardless of the system's Lua setup.
+
+use std::{io, path::Path};
+
+use crate::{
+    config::LuaVersion,
+    lockfile::LocalPackage,
+    tree::{Tree, TreeLayout},
+};
+
+use super::utils::lua_lib_extension;
+
+/// Write a launcher for the script at `staged_script` to `launcher`.
+/// `script` is the path the script will have once the rock has been moved into `tree`.
+pub(crate) fn write_launcher(
+    launcher: &Path,
+    staged_script: &Path,
+    script: &Path,
+    package: &LocalPackage,
+    tree: &Tree,
+    lua_version: &LuaVersion,
+) -> io::Result<()> {
+    // On other platforms, the script is installed as is.
+    if cfg!(not(unix)) {
+        return std::fs::copy(staged_script, launcher).map(|_| ());
+    }
+
+    let (src, lib) = match tree.layout() {
+        // Each rock has its own directory, so the search paths are collected when the
+        // launcher is run, to include rocks that are installed after this one.
+        TreeLayout::Rocks => (
+            tree.root().join("*").join("src"),
+            tree.root().join("*").join("lib"),
+        ),
+        TreeLayout::Fhs | TreeLayout::Luarocks => {
+            let layout = tree.rock_layout(package);
+            (layout.src, layout.lib)
+        }
+    };
+    let content = format!(
+        r#"#!/bin/sh
+# Launcher for {package}@{version}, generated by rocks.
+rocks_path=
+for dir in {src}; do rocks_path="$rocks_path$dir/?.lua;$dir/?/init.lua;"; done
+rocks_cpath=
+for dir in {lib}; do rocks_cpath="$rocks_cpath$dir/?.{lib_extension};"; done
+LUA_PATH="$rocks_path${{LUA_PATH:-;}}" LUA_CPATH="$rocks_cpath${{LUA_CPATH:-;}}" exec {lua} {script} "$@"
+"#,
+        package = package.name(),
+        version = package.version(),
+        src = quote_glob(&src),
+        lib = quote_glob(&lib),
+        lib_extension = lua_lib_extension(),
+        lua = quote(&interpreter(lua_version)),
+        script = quote(&script.to_string_lossy()),
+    );
+    std::fs::write(launcher, content)?;
+    set_executable(launcher)
+}
+
+/// The Lua interpreter for `lua_version`, preferring version-specific executables, e.g. `lua5.1`.
+fn interpreter(lua_version: &LuaVersion) -> String {
+    let version = lua_version.version_compatibility_str();
+    let candidates = if lua_version.is_luajit() {
+        vec!["luajit".to_string()]
+    } else {
+        vec![
+            format!("lua{}", version),
+            format!("lua{}", version.replace('.', "")),
+            "lua".to_string(),
+        ]
+    };
+    candidates
+        .iter()
+        .find_map(|candidate| which::which(candidate).ok())
+        .map(|path| path.to_string_lossy().to_string())
+        .unwrap_or_else(|| lua_version.interpreter().to_string())
+}
+
+/// Quote `value` for a POSIX shell.
+fn quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+/// Quote a path for a POSIX shell, leaving `*` components unquoted so that they are expanded.
+fn quote_glob(path: &Path) -> String {
+    path.to_string_lossy()
+        .split('*')
+        .map(quote)
+        .collect::<Vec<_>>()
+        .join("*")
+}
+
+#[cfg(unix)]
+fn set_executable(path: &Path) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt as _;
+
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o755))
+}
+
+#[cfg(not(unix))]
+fn set_executable(_path: &Path) -> io::Result<()> {
+    Ok(())
+}
@@ -1,6 +1,5 @@
-use itertools::Itertools;
 use std::{
-    collections::{HashMap, HashSet},
+    collections::HashMap,
     path::{Path, PathBuf},
     str::FromStr,
 };
@@ -10,6 +9,8 @@ use crate::{
     build::utils,
     config::Config,
     lua_installation::LuaInstallation,
+    package::PackageSpec,
+    path::Paths,
     progress::{
         Progress::{self},
         ProgressBar,
@@ -18,7 +19,7 @@ use crate::{
     tree::RockLayout,
 };
 
-use super::BuildError;
+use super::{object_cache::ObjectCache, BuildBehaviour, BuildError};
 
 impl Build for BuiltinBuildSpec {
     type Err = BuildError;
@@ -27,16 +28,16 @@ impl Build for BuiltinBuildSpec {
         self,
         output_paths: &RockLayout,
         _no_install: bool,
+        behaviour: BuildBehaviour,
+        package: &PackageSpec,
         lua: &LuaInstallation,
-        _config: &Config,
+        _build_env: &Paths,
+        config: &Config,
         build_dir: &Path,
         progress: &Progress<ProgressBar>,
     ) -> Result<(), Self::Err> {
-        // Detect all Lua modules
-        let modules = autodetect_modules(build_dir, source_paths(build_dir, &self.modules))
-            .into_iter()
-            .chain(self.modules)
-            .collect::<HashMap<_, _>>();
+        let modules = resolve_modules(build_dir, self.modules);
+        let object_cache = ObjectCache::new(config, package, lua.version(), behaviour);
 
         progress.map(|p| p.set_position(modules.len() as u64));
 
@@ -54,9 +55,12 @@ impl Build for BuiltinBuildSpec {
                         let absolute_source_paths = vec![build_dir.join(source)];
                         utils::compile_c_files(
                             &absolute_source_paths,
+                            build_dir,
                             destination_path,
                             &output_paths.lib,
                             lua,
+                            config.build_profile(),
+                            Some(&object_cache),
                         )?
                     } else {
                         progress.map(|p| {
@@ -76,13 +80,18 @@ impl Build for BuiltinBuildSpec {
                 }
                 ModuleSpec::SourcePaths(files) => {
                     progress.map(|p| p.set_message("Compiling C files..."));
-                    let absolute_source_paths =
-                        files.iter().map(|file| build_dir.join(file)).collect();
+                    let absolute_source_paths = files
+                        .iter()
+                        .map(|file| build_dir.join(file))
+                        .collect::<Vec<_>>();
                     utils::compile_c_files(
                         &absolute_source_paths,
+                        build_dir,
                         destination_path,
                         &output_paths.lib,
                         lua,
+                        config.build_profile(),
+                        Some(&object_cache),
                     )?
                 }
                 ModuleSpec::ModulePaths(data) => {
@@ -93,6 +102,8 @@ impl Build for BuiltinBuildSpec {
                         destination_path,
                         &output_paths.lib,
                         lua,
+                        config.build_profile(),
+                        Some(&object_cache),
                     )?
                 }
             }
@@ -109,22 +120,22 @@ impl Build for BuiltinBuildSpec {
     }
 }
 
-fn source_paths(build_dir: &Path, modules: &HashMap<LuaModule, ModuleSpec>) -> HashSet<PathBuf> {
-    modules
-        .iter()
-        .flat_map(|(_, spec)| match spec {
-            ModuleSpec::SourcePath(path_buf) => vec![path_buf],
-            ModuleSpec::SourcePaths(vec) => vec.iter().collect_vec(),
-            ModuleSpec::ModulePaths(module_paths) => module_paths.sources.iter().collect_vec(),
-        })
-        .map(|path| build_dir.join(path))
-        .collect()
-}
-
-fn autodetect_modules(
+/// Explicitly listed modules are authoritative.
+/// If there are none (which rockspec format 3.0 allows), the Lua modules in the
+/// `src`, `lua` and `lib` directories are detected and mapped to module names,
+/// e.g. `src/foo/bar.lua` to `foo.bar`.
+fn resolve_modules(
     build_dir: &Path,
-    exclude: HashSet<PathBuf>,
+    modules: HashMap<LuaModule, ModuleSpec>,
 ) -> HashMap<LuaModule, ModuleSpec> {
+    if modules.is_empty() {
+        autodetect_modules(build_dir)
+    } else {
+        modules
+    }
+}
+
+fn autodetect_modules(build_dir: &Path) -> HashMap<LuaModule, ModuleSpec> {
     WalkDir::new(build_dir.join("src"))
         .into_iter()
         .chain(WalkDir::new(build_dir.join("lua")))
@@ -135,7 +146,7 @@ fn autodetect_modules(
                     .extension()
                     .map(|ext| ext == "lua")
                     .unwrap_or(false);
-                if is_lua_file && !exclude.contains(&file.clone().into_path()) {
+                if is_lua_file {
                     Some(file)
                 } else {
                     None
@@ -179,3 +190,44 @@ fn autodetect_bin_scripts(build_dir: &Path) -> Vec<PathBuf> {
         })
         .collect()
 }
+
+#[cfg(test)]
+mod tests {
+    use assert_fs::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn autodetect_modules_if_none_are_listed() {
+        let build_dir = assert_fs::TempDir::new().unwrap();
+        build_dir.child("src/foo/bar.lua").touch().unwrap();
+        build_dir.child("src/foo/init.lua").touch().unwrap();
+        build_dir.child("lua/baz.lua").touch().unwrap();
+        build_dir.child("src/foo/README.md").touch().unwrap();
+
+        let modules = resolve_modules(&build_dir, HashMap::new());
+        assert_eq!(
+            modules,
+            HashMap::from([
+                (
+                    LuaModule::from_str("foo.bar").unwrap(),
+                    ModuleSpec::SourcePath("src/foo/bar.lua".into())
+                ),
+                (
+                    LuaModule::from_str("foo.init").unwrap(),
+                    ModuleSpec::SourcePath("src/foo/init.lua".into())
+                ),
+                (
+                    LuaModule::from_str("baz").unwrap(),
+                    ModuleSpec::SourcePath("lua/baz.lua".into())
+                ),
+            ])
+        );
+
+        let explicit = HashMap::from([(
+            LuaModule::from_str("foo").unwrap(),
+            ModuleSpec::SourcePath("src/foo/bar.lua".into()),
+        )]);
+        assert_eq!(resolve_modules(&build_dir, explicit.clone()), explicit);
+    }
+}
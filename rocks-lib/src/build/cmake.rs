@@ -6,9 +6,11 @@ use std::{
 use thiserror::Error;
 
 use crate::{
-    build::utils,
+    build::{utils, BuildBehaviour},
     config::Config,
     lua_installation::LuaInstallation,
+    package::PackageSpec,
+    path::Paths,
     progress::{Progress, ProgressBar},
     rockspec::{Build, CMakeBuildSpec},
     tree::RockLayout,
@@ -33,6 +35,8 @@ pub enum CMakeError {
     WriteCmakeListsError(io::Error),
     #[error("failed to run `cmake` step: `{0}` command not found!")]
     CommandNotFound(String),
+    #[error("the '{0}' CMake generator requires `ninja`, but it could not be found. Install ninja or remove 'cmake_generator' from the rockspec's build table")]
+    NinjaNotFound(String),
 }
 
 impl Build for CMakeBuildSpec {
@@ -42,7 +46,10 @@ impl Build for CMakeBuildSpec {
         self,
         output_paths: &RockLayout,
         no_install: bool,
+        _behaviour: BuildBehaviour,
+        _package: &PackageSpec,
         lua: &LuaInstallation,
+        build_env: &Paths,
         config: &Config,
         build_dir: &Path,
         _progress: &Progress<ProgressBar>,
@@ -51,11 +58,24 @@ impl Build for CMakeBuildSpec {
         if let Some(content) = self.cmake_lists_content {
             let cmakelists = build_dir.join("CMakeLists.txt");
             std::fs::write(&cmakelists, content).map_err(CMakeError::WriteCmakeListsError)?;
-            args.push(format!("-G\"{}\"", cmakelists.display()));
+        }
+        if let Some(generator) = self.generator {
+            // CMake finds ninja on the PATH, unless the rockspec points it elsewhere.
+            if is_ninja(&generator)
+                && !self.variables.contains_key("CMAKE_MAKE_PROGRAM")
+                && which::which("ninja").is_err()
+            {
+                return Err(CMakeError::NinjaNotFound(generator));
+            }
+            args.push(format!("-G{}", generator));
         } else if cfg!(all(target_os = "windows", target_arch = "x86_64")) {
             // With msvc and x64, CMake does not select it by default so we need to be explicit.
             args.push("-DCMAKE_GENERATOR_PLATFORM=x64".into());
         }
+        let build_type = config.build_profile().cmake_build_type();
+        if !self.variables.contains_key("CMAKE_BUILD_TYPE") {
+            args.push(format!("-DCMAKE_BUILD_TYPE={}", build_type));
+        }
         self.variables
             .into_iter()
             .map(|(key, value)| {
@@ -69,6 +89,7 @@ impl Build for CMakeBuildSpec {
         spawn_cmake_cmd(
             Command::new(config.cmake_cmd())
                 .current_dir(build_dir)
+                .envs(build_env.env_vars())
                 .arg("-H.")
                 .arg(format!("-B{}", CMAKE_BUILD_FILE))
                 .args(args),
@@ -79,10 +100,11 @@ impl Build for CMakeBuildSpec {
             spawn_cmake_cmd(
                 Command::new(config.cmake_cmd())
                     .current_dir(build_dir)
+                    .envs(build_env.env_vars())
                     .arg("--build")
                     .arg(CMAKE_BUILD_FILE)
                     .arg("--config")
-                    .arg("Release"),
+                    .arg(build_type),
                 config,
             )?
         }
@@ -91,12 +113,13 @@ impl Build for CMakeBuildSpec {
             spawn_cmake_cmd(
                 Command::new(config.cmake_cmd())
                     .current_dir(build_dir)
+                    .envs(build_env.env_vars())
                     .arg("--build")
                     .arg(CMAKE_BUILD_FILE)
                     .arg("--target")
                     .arg("install")
                     .arg("--config")
-                    .arg("Release"),
+                    .arg(build_type),
                 config,
             )?;
         }
@@ -105,6 +128,14 @@ impl Build for CMakeBuildSpec {
     }
 }
 
+/// Whether `generator` is one of CMake's Ninja generators, e.g. `Ninja` or `Ninja Multi-Config`.
+fn is_ninja(generator: &str) -> bool {
+    generator
+        .split_whitespace()
+        .next()
+        .is_some_and(|name| name.eq_ignore_ascii_case("ninja"))
+}
+
 fn substitute_variables(input: &str) -> String {
     variables::substitute(
         |var_name| match var_name {
@@ -9,12 +9,14 @@ use thiserror::Error;
 use crate::{
     config::Config,
     lua_installation::LuaInstallation,
+    package::PackageSpec,
+    path::Paths,
     progress::{Progress, ProgressBar},
     rockspec::{Build, CommandBuildSpec},
     tree::RockLayout,
 };
 
-use super::utils;
+use super::{utils, BuildBehaviour};
 
 #[derive(Error, Debug)]
 pub enum CommandError {
@@ -40,16 +42,33 @@ impl Build for CommandBuildSpec {
         self,
         output_paths: &RockLayout,
         no_install: bool,
+        _behaviour: BuildBehaviour,
+        _package: &PackageSpec,
         lua: &LuaInstallation,
+        build_env: &Paths,
         config: &Config,
         build_dir: &Path,
         progress: &Progress<ProgressBar>,
     ) -> Result<(), Self::Err> {
         progress.map(|bar| bar.set_message("Running build_command..."));
-        run_command(&self.build_command, output_paths, lua, config, build_dir)?;
+        run_command(
+            &self.build_command,
+            output_paths,
+            lua,
+            build_env,
+            config,
+            build_dir,
+        )?;
         if !no_install {
             progress.map(|bar| bar.set_message("Running install_command..."));
-            run_command(&self.install_command, output_paths, lua, config, build_dir)?;
+            run_command(
+                &self.install_command,
+                output_paths,
+                lua,
+                build_env,
+                config,
+                build_dir,
+            )?;
         }
         Ok(())
     }
@@ -59,6 +78,7 @@ fn run_command(
     command: &str,
     output_paths: &RockLayout,
     lua: &LuaInstallation,
+    build_env: &Paths,
     config: &Config,
     build_dir: &Path,
 ) -> Result<(), CommandError> {
@@ -71,6 +91,7 @@ fn run_command(
     match Command::new(program)
         .args(args)
         .current_dir(build_dir)
+        .envs(build_env.env_vars())
         .spawn()
     {
         Err(err) => {
@@ -1,5 +1,6 @@
 use itertools::Itertools;
 use std::{
+    collections::HashMap,
     io,
     path::Path,
     process::{Command, ExitStatus},
@@ -7,9 +8,11 @@ use std::{
 use thiserror::Error;
 
 use crate::{
-    build::utils,
+    build::{utils, BuildBehaviour},
     config::Config,
     lua_installation::LuaInstallation,
+    package::PackageSpec,
+    path::Paths,
     progress::{Progress, ProgressBar},
     rockspec::{Build, MakeBuildSpec},
     tree::RockLayout,
@@ -30,6 +33,13 @@ pub enum MakeError {
     CommandNotFound(String),
 }
 
+/// The variables that are passed to the build pass, like luarocks does,
+/// so that Makefiles can use them without the rockspec having to declare them.
+const DEFAULT_BUILD_VARIABLES: &[&str] = &["CFLAGS", "LIBFLAG", "LUA_LIBDIR", "LUA_INCDIR", "LUA"];
+
+/// The variables that are passed to the install pass, like luarocks does.
+const DEFAULT_INSTALL_VARIABLES: &[&str] = &["PREFIX", "BINDIR", "LIBDIR", "LUADIR", "CONFDIR"];
+
 impl Build for MakeBuildSpec {
     type Err = MakeError;
 
@@ -37,25 +47,25 @@ impl Build for MakeBuildSpec {
         self,
         output_paths: &RockLayout,
         no_install: bool,
+        _behaviour: BuildBehaviour,
+        _package: &PackageSpec,
         lua: &LuaInstallation,
+        build_env: &Paths,
         config: &Config,
         build_dir: &Path,
         _progress: &Progress<ProgressBar>,
     ) -> Result<(), Self::Err> {
         // Build step
         if self.build_pass {
-            let build_args = self
-                .variables
-                .iter()
-                .chain(&self.build_variables)
-                .map(|(key, value)| {
-                    let substituted_value =
-                        utils::substitute_variables(value, output_paths, lua, config);
-                    format!("{key}={substituted_value}")
-                })
-                .collect_vec();
+            let build_args = make_args(
+                DEFAULT_BUILD_VARIABLES,
+                &self.variables,
+                &self.build_variables,
+                |value| utils::substitute_variables(value, output_paths, lua, config),
+            );
             match Command::new(config.make_cmd())
                 .current_dir(build_dir)
+                .envs(build_env.env_vars())
                 .arg(&self.build_target)
                 .args(["-f", self.makefile.to_str().unwrap()])
                 .args(build_args)
@@ -79,18 +89,15 @@ impl Build for MakeBuildSpec {
 
         // Install step
         if self.install_pass && !no_install {
-            let install_args = self
-                .variables
-                .iter()
-                .chain(&self.install_variables)
-                .map(|(key, value)| {
-                    let substituted_value =
-                        utils::substitute_variables(value, output_paths, lua, config);
-                    format!("{key}={substituted_value}")
-                })
-                .collect_vec();
+            let install_args = make_args(
+                DEFAULT_INSTALL_VARIABLES,
+                &self.variables,
+                &self.install_variables,
+                |value| utils::substitute_variables(value, output_paths, lua, config),
+            );
             match Command::new(config.make_cmd())
                 .current_dir(build_dir)
+                .envs(build_env.env_vars())
                 .arg(&self.install_target)
                 .args(["-f", self.makefile.to_str().unwrap()])
                 .args(install_args)
@@ -112,3 +119,70 @@ impl Build for MakeBuildSpec {
         Ok(())
     }
 }
+
+/// The `KEY=value` arguments for a `make` pass, with `$(VAR)` references substituted.
+/// The `defaults` are passed too, unless the rockspec sets them or they have no value.
+fn make_args(
+    defaults: &[&str],
+    variables: &HashMap<String, String>,
+    pass_variables: &HashMap<String, String>,
+    substitute: impl Fn(&str) -> String,
+) -> Vec<String> {
+    defaults
+        .iter()
+        .filter(|key| !variables.contains_key(**key) && !pass_variables.contains_key(**key))
+        .filter_map(|key| {
+            let reference = format!("$({key})");
+            let value = substitute(&reference);
+            (value != reference).then(|| format!("{key}={value}"))
+        })
+        .chain(
+            variables
+                .iter()
+                .chain(pass_variables)
+                .map(|(key, value)| format!("{key}={}", substitute(value))),
+        )
+        .collect_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_make_variables() {
+        let substitute = |value: &str| {
+            value
+                .replace("$(LUA_INCDIR)", "/usr/include/lua5.1")
+                .replace("$(PREFIX)", "/tree/foo/1.0.0-1")
+        };
+        let variables = HashMap::from([("CFLAGS".to_string(), "-O2".to_string())]);
+        let build_variables =
+            HashMap::from([("INCLUDES".to_string(), "-I$(LUA_INCDIR)".to_string())]);
+        let args = make_args(
+            DEFAULT_BUILD_VARIABLES,
+            &variables,
+            &build_variables,
+            substitute,
+        );
+        assert!(args.contains(&"LUA_INCDIR=/usr/include/lua5.1".to_string()));
+        assert!(args.contains(&"INCLUDES=-I/usr/include/lua5.1".to_string()));
+        // The rockspec's variables take precedence over the defaults.
+        assert!(args.contains(&"CFLAGS=-O2".to_string()));
+        assert_eq!(
+            args.iter().filter(|arg| arg.starts_with("CFLAGS=")).count(),
+            1
+        );
+
+        let args = make_args(
+            DEFAULT_INSTALL_VARIABLES,
+            &HashMap::new(),
+            &HashMap::new(),
+            substitute,
+        );
+        assert!(args.contains(&"PREFIX=/tree/foo/1.0.0-1".to_string()));
+        assert!(!args.iter().any(|arg| arg.starts_with("LUA_INCDIR=")));
+        // Defaults without a value are not passed.
+        assert!(!args.iter().any(|arg| arg.starts_with("BINDIR=")));
+    }
+}
@@ -1,15 +1,21 @@
-use std::{io, path::Path, process::ExitStatus};
+use std::{
+    io,
+    path::{Path, PathBuf},
+    process::ExitStatus,
+};
 
 use crate::{
-    config::Config,
-    hash::HasIntegrity,
+    config::{Config, LuaVersion},
+    hash::{self, HasIntegrity},
     lockfile::{LocalPackage, LocalPackageHashes, LockConstraint, PinnedState},
     lua_installation::LuaInstallation,
-    operations::{self, FetchSrcRockError},
-    package::PackageSpec,
-    progress::{Progress, ProgressBar},
-    rockspec::{Build as _, BuildBackendSpec, LuaVersionError, Rockspec},
-    tree::{RockLayout, Tree},
+    operations::{self, FetchSrcError, FetchSrcRockError},
+    package::{PackageNamespace, PackageSpec},
+    path::Paths,
+    progress::{self, Progress, ProgressBar},
+    rockspec::{Build as _, BuildBackendSpec, LuaModule, LuaVersionError, Rockspec},
+    signature::SignatureError,
+    tree::{RockLayout, RockManifest, RockManifestError, Tree, TreeLayout},
 };
 pub(crate) mod utils;
 use cmake::CMakeError;
@@ -17,6 +23,7 @@ use command::CommandError;
 use external_dependency::{ExternalDependencyError, ExternalDependencyInfo};
 
 use indicatif::style::TemplateError;
+use itertools::Itertools as _;
 use luarocks::LuarocksBuildError;
 use make::MakeError;
 use rust_mlua::RustError;
@@ -24,16 +31,23 @@ use ssri::Integrity;
 use thiserror::Error;
 use utils::recursive_copy_dir;
 
+mod bin_wrapper;
 mod builtin;
 mod cmake;
 mod command;
 mod luarocks;
 mod make;
+mod object_cache;
 mod rust_mlua;
 
 pub mod external_dependency;
 pub mod variables;
 
+pub use utils::GlobError;
+
+/// The subdirectory of the build directory that [`build_no_install`] stages the rock's files in.
+pub(crate) const STAGING_DIR_NAME: &str = ".rocks-build";
+
 #[derive(Error, Debug)]
 pub enum BuildError {
     #[error("IO operation failed: {0}")]
@@ -69,6 +83,31 @@ pub enum BuildError {
     },
     #[error(transparent)]
     LuarocksBuildError(#[from] LuarocksBuildError),
+    #[error("source signature verification failed: {0}")]
+    SignatureError(#[from] SignatureError),
+    #[error(transparent)]
+    GlobError(#[from] GlobError),
+    #[error(transparent)]
+    RockManifestError(#[from] RockManifestError),
+    #[error("{package} would overwrite files that other rocks installed:\n{}\nUse `--allow-conflicts` to install it anyway.", .conflicts.iter().join("\n"))]
+    FileConflicts {
+        package: String,
+        conflicts: Vec<FileConflict>,
+    },
+}
+
+/// A file that a rock would install, but that another installed rock owns.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileConflict {
+    pub path: PathBuf,
+    /// The rock that installed the file, e.g. `foo@1.0.0-1`.
+    pub owner: String,
+}
+
+impl std::fmt::Display for FileConflict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} (installed by {})", self.path.display(), self.owner)
+    }
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
@@ -87,40 +126,168 @@ impl From<bool> for BuildBehaviour {
     }
 }
 
+/// Whether C modules are built for debugging or optimized for production.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+#[cfg_attr(feature = "clap", clap(rename_all = "lowercase"))]
+pub enum BuildProfile {
+    /// Unoptimized, with debug symbols.
+    Debug,
+    /// Optimized, without assertions and with stripped symbols.
+    #[default]
+    Release,
+}
+
+impl BuildProfile {
+    /// The flags that are appended to `CFLAGS`.
+    pub fn cflags(&self) -> &'static str {
+        match self {
+            Self::Debug if cfg!(target_os = "windows") => "/Zi /Od",
+            Self::Debug => "-g -O0",
+            Self::Release if cfg!(target_os = "windows") => "/O2 /DNDEBUG",
+            Self::Release => "-O2 -DNDEBUG",
+        }
+    }
+
+    /// The flags that are appended to `LIBFLAG` when linking a C module.
+    pub fn ldflags(&self) -> &'static str {
+        match self {
+            Self::Debug if cfg!(target_os = "windows") => "/DEBUG",
+            Self::Debug => "",
+            Self::Release if cfg!(target_os = "windows") => "",
+            Self::Release if cfg!(target_os = "macos") => "-Wl,-S",
+            Self::Release => "-s",
+        }
+    }
+
+    /// The value of `CMAKE_BUILD_TYPE` and of the `--config` of CMake builds.
+    pub(crate) fn cmake_build_type(&self) -> &'static str {
+        match self {
+            Self::Debug => "Debug",
+            Self::Release => "Release",
+        }
+    }
+
+    pub(crate) fn opt_level(&self) -> u32 {
+        match self {
+            Self::Debug => 0,
+            Self::Release => 3,
+        }
+    }
+
+    /// Appended to the architecture in the file names of packed binary rocks,
+    /// so that debug builds don't overwrite release builds.
+    pub(crate) fn rock_name_suffix(&self) -> &'static str {
+        match self {
+            Self::Debug => "-debug",
+            Self::Release => "",
+        }
+    }
+}
+
+/// The paths of the rocks in the build tree, which `build_dependencies` are installed into
+/// instead of the tree the rock is installed into, so that build commands can use them.
+fn build_env(config: &Config, lua_version: &LuaVersion) -> io::Result<Paths> {
+    Paths::from_tree(Tree::new(
+        config.luarocks_tree().clone(),
+        lua_version.clone(),
+    )?)
+}
+
+#[allow(clippy::too_many_arguments)]
 async fn run_build(
     rockspec: &Rockspec,
     output_paths: &RockLayout,
+    no_install: bool,
+    behaviour: BuildBehaviour,
     lua: &LuaInstallation,
+    build_env: &Paths,
     config: &Config,
     build_dir: &Path,
     progress: &Progress<ProgressBar>,
 ) -> Result<(), BuildError> {
     progress.map(|p| p.set_message("🛠️ Building..."));
-
-    match rockspec.build.current_platform().build_backend.to_owned() {
+    let package = PackageSpec::new(rockspec.package.clone(), rockspec.version.clone());
+
+    match rockspec
+        .build
+        .for_platform(config.platform())
+        .build_backend
+        .to_owned()
+    {
         Some(BuildBackendSpec::Builtin(build_spec)) => {
             build_spec
-                .run(output_paths, false, lua, config, build_dir, progress)
+                .run(
+                    output_paths,
+                    no_install,
+                    behaviour,
+                    &package,
+                    lua,
+                    build_env,
+                    config,
+                    build_dir,
+                    progress,
+                )
                 .await?
         }
         Some(BuildBackendSpec::Make(make_spec)) => {
             make_spec
-                .run(output_paths, false, lua, config, build_dir, progress)
+                .run(
+                    output_paths,
+                    no_install,
+                    behaviour,
+                    &package,
+                    lua,
+                    build_env,
+                    config,
+                    build_dir,
+                    progress,
+                )
                 .await?
         }
         Some(BuildBackendSpec::CMake(cmake_spec)) => {
             cmake_spec
-                .run(output_paths, false, lua, config, build_dir, progress)
+                .run(
+                    output_paths,
+                    no_install,
+                    behaviour,
+                    &package,
+                    lua,
+                    build_env,
+                    config,
+                    build_dir,
+                    progress,
+                )
                 .await?
         }
         Some(BuildBackendSpec::Command(command_spec)) => {
             command_spec
-                .run(output_paths, false, lua, config, build_dir, progress)
+                .run(
+                    output_paths,
+                    no_install,
+                    behaviour,
+                    &package,
+                    lua,
+                    build_env,
+                    config,
+                    build_dir,
+                    progress,
+                )
                 .await?
         }
         Some(BuildBackendSpec::RustMlua(rust_mlua_spec)) => {
             rust_mlua_spec
-                .run(output_paths, false, lua, config, build_dir, progress)
+                .run(
+                    output_paths,
+                    no_install,
+                    behaviour,
+                    &package,
+                    lua,
+                    build_env,
+                    config,
+                    build_dir,
+                    progress,
+                )
                 .await?
         }
         Some(BuildBackendSpec::LuaRock(_)) => {
@@ -132,14 +299,17 @@ async fn run_build(
     Ok(())
 }
 
+/// Install the rock's Lua modules, C libraries and scripts into `output_paths`.
+/// The scripts are installed into the rock's own `bin` directory and their paths are returned,
+/// so that launchers can be written for them.
 async fn install(
     rockspec: &Rockspec,
-    tree: &Tree,
     output_paths: &RockLayout,
     lua: &LuaInstallation,
+    config: &Config,
     build_dir: &Path,
     progress: &Progress<ProgressBar>,
-) -> Result<(), BuildError> {
+) -> Result<Vec<PathBuf>, BuildError> {
     progress.map(|p| {
         p.set_message(format!(
             "💻 Installing {} {}",
@@ -147,7 +317,7 @@ async fn install(
         ))
     });
 
-    let install_spec = &rockspec.build.current_platform().install;
+    let install_spec = &rockspec.build.for_platform(config.platform()).install;
     let lua_len = install_spec.lua.len();
     let lib_len = install_spec.lib.len();
     let bin_len = install_spec.bin.len();
@@ -158,34 +328,157 @@ async fn install(
         progress.map(|p| p.set_message("Copying Lua modules..."));
     }
     for (target, source) in &install_spec.lua {
-        let absolute_source = build_dir.join(source);
-        utils::copy_lua_to_module_path(&absolute_source, target, &output_paths.src)?;
+        if utils::is_glob(source) {
+            // Each match is installed as a submodule of `target`,
+            // preserving its path relative to the pattern's base directory.
+            let base = utils::glob_base(source);
+            for relative_path in utils::expand_glob(build_dir, source)? {
+                let module = LuaModule::from_pathbuf(
+                    relative_path
+                        .strip_prefix(&base)
+                        .unwrap_or(&relative_path)
+                        .to_path_buf(),
+                );
+                utils::copy_lua_to_module_path(
+                    &build_dir.join(&relative_path),
+                    &target.join(&module),
+                    &output_paths.src,
+                )?;
+            }
+        } else {
+            let absolute_source = build_dir.join(source);
+            utils::copy_lua_to_module_path(&absolute_source, target, &output_paths.src)?;
+        }
         progress.map(|p| p.set_position(p.position() + 1));
     }
     if lib_len > 0 {
         progress.map(|p| p.set_message("Compiling C libraries..."));
     }
     for (target, source) in &install_spec.lib {
+        // All sources matching a glob are compiled into a single library.
+        let sources = if utils::is_glob(source) {
+            utils::expand_glob(build_dir, source)?
+                .into_iter()
+                .map(|relative_path| build_dir.join(relative_path))
+                .collect_vec()
+        } else {
+            vec![build_dir.join(source)]
+        };
         utils::compile_c_files(
-            &vec![build_dir.join(source)],
+            &sources,
+            build_dir,
             target,
             &output_paths.lib,
             lua,
+            config.build_profile(),
+            None,
         )?;
         progress.map(|p| p.set_position(p.position() + 1));
     }
-    if lib_len > 0 {
+    if bin_len > 0 {
         progress.map(|p| p.set_message("Copying binaries..."));
+        std::fs::create_dir_all(output_paths.rock_path.join("bin"))?;
     }
+    let mut bins = Vec::new();
     for (target, source) in &install_spec.bin {
-        std::fs::copy(build_dir.join(source), tree.bin().join(target))?;
+        if utils::is_glob(source) {
+            // The target name only applies to a single script,
+            // so each match is installed under its own file name.
+            for relative_path in utils::expand_glob(build_dir, source)? {
+                if let Some(file_name) = relative_path.file_name() {
+                    let bin = output_paths.rock_path.join("bin").join(file_name);
+                    std::fs::copy(build_dir.join(&relative_path), &bin)?;
+                    bins.push(bin);
+                }
+            }
+        } else {
+            let bin = output_paths.rock_path.join("bin").join(target);
+            std::fs::copy(build_dir.join(source), &bin)?;
+            bins.push(bin);
+        }
         progress.map(|p| p.set_position(p.position() + 1));
     }
+    Ok(bins)
+}
+
+/// Install the rock's configuration files into `staged_conf`.
+/// Configuration files may have been edited by the user, so an existing file in `installed_conf`
+/// (the `conf` directory of a previous installation) is never overwritten.
+/// If it differs from the new file, the new one is installed alongside it as `<name>.new`.
+fn install_conf(
+    rockspec: &Rockspec,
+    build_dir: &Path,
+    staged_conf: &Path,
+    installed_conf: &Path,
+    config: &Config,
+) -> io::Result<()> {
+    for (target, source) in &rockspec.build.for_platform(config.platform()).install.conf {
+        let content = std::fs::read(build_dir.join(source))?;
+        let destination = match std::fs::read(installed_conf.join(target)) {
+            Ok(installed) if installed != content => staged_conf.join(format!("{}.new", target)),
+            _ => staged_conf.join(target),
+        };
+        if let Some(parent) = destination.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(destination, content)?;
+    }
     Ok(())
 }
 
+/// Fetch a rock's source into `dest_dir` and verify it against the rockspec's integrity, if any.
+async fn fetch_and_verify_src(
+    rockspec: &Rockspec,
+    dest_dir: &Path,
+    config: &Config,
+    progress: &Progress<ProgressBar>,
+) -> Result<LocalPackageHashes, BuildError> {
+    // Install the source in order to build.
+    let rock_source = rockspec.source.for_platform(config.platform());
+    match operations::fetch_src(dest_dir, rock_source, config, progress).await {
+        Ok(()) => {}
+        // Never fall back to a different source if the signature is invalid.
+        Err(FetchSrcError::Signature(err)) => return Err(BuildError::SignatureError(err)),
+        Err(err) => {
+            let package = PackageSpec::new(rockspec.package.clone(), rockspec.version.clone());
+            progress.map(|p| {
+                p.println(format!(
+                    "⚠️ WARNING: Failed to fetch source for {}: {}",
+                    &package, err
+                ))
+            });
+            progress.map(|p| {
+                p.println(format!(
+                    "⚠️ Falling back to .src.rock archive from {}",
+                    &config.server()
+                ))
+            });
+            operations::fetch_src_rock(&package, dest_dir, config, progress).await?;
+        }
+    }
+
+    let hashes = LocalPackageHashes {
+        rockspec: rockspec.hash()?,
+        source: dest_dir.hash()?,
+    };
+
+    if let Some(expected) = &rock_source.integrity {
+        // The source may be declared with other algorithms than the sha256 in the lockfile.
+        let actual = dest_dir.hash_with(&hash::algorithms(expected))?;
+        if hash::integrity_matches(expected, &actual).is_none() {
+            return Err(BuildError::SourceIntegrityMismatch {
+                expected: expected.clone(),
+                actual,
+            });
+        }
+    }
+
+    Ok(hashes)
+}
+
 pub async fn build(
     rockspec: Rockspec,
+    namespace: Option<PackageNamespace>,
     pinned: PinnedState,
     constraint: LockConstraint,
     behaviour: BuildBehaviour,
@@ -199,48 +492,20 @@ pub async fn build(
         ))
     });
 
-    for (name, dep) in rockspec.external_dependencies.current_platform() {
+    for (name, dep) in rockspec
+        .external_dependencies
+        .for_platform(config.platform())
+    {
         let _ = ExternalDependencyInfo::detect(name, dep, config.external_deps())?;
     }
 
     let lua_version = rockspec.lua_version_from_config(config)?;
 
-    let tree = Tree::new(config.tree().clone(), lua_version.clone())?;
+    let tree = Tree::from_config(config, lua_version.clone())?;
 
     let temp_dir = tempdir::TempDir::new(&rockspec.package.to_string())?;
 
-    // Install the source in order to build.
-    let rock_source = rockspec.source.current_platform();
-    if let Err(err) = operations::fetch_src(temp_dir.path(), rock_source, progress).await {
-        let package = PackageSpec::new(rockspec.package.clone(), rockspec.version.clone());
-        progress.map(|p| {
-            p.println(format!(
-                "⚠️ WARNING: Failed to fetch source for {}: {}",
-                &package, err
-            ))
-        });
-        progress.map(|p| {
-            p.println(format!(
-                "⚠️ Falling back to .src.rock archive from {}",
-                &config.server()
-            ))
-        });
-        operations::fetch_src_rock(&package, temp_dir.path(), config, progress).await?;
-    }
-
-    let hashes = LocalPackageHashes {
-        rockspec: rockspec.hash()?,
-        source: temp_dir.hash()?,
-    };
-
-    if let Some(expected) = &rockspec.source.current_platform().integrity {
-        if expected.matches(&hashes.source).is_none() {
-            return Err(BuildError::SourceIntegrityMismatch {
-                expected: expected.clone(),
-                actual: hashes.source,
-            });
-        }
-    }
+    let hashes = fetch_and_verify_src(&rockspec, temp_dir.path(), config, progress).await?;
 
     let mut package = LocalPackage::from(
         &PackageSpec::new(rockspec.package.clone(), rockspec.version.clone()),
@@ -248,32 +513,232 @@ pub async fn build(
         hashes,
     );
     package.spec.pinned = pinned;
+    package.spec.namespace = namespace;
 
     match tree.lockfile()?.get(&package.id()) {
         Some(package) if behaviour == BuildBehaviour::NoForce => Ok(package.clone()),
         _ => {
-            let output_paths = tree.rock(&package)?;
+            // The rock is built and installed into a staging tree,
+            // so that a failed build doesn't leave a partially installed rock behind.
+            let (_staging_dir, staging_tree) = tree.staging_tree()?;
+            let output_paths = staging_tree.rock(&package)?;
 
             let lua = LuaInstallation::new(&lua_version, config);
+            let build_env = build_env(config, &lua_version)?;
 
-            let build_dir = match &rock_source.unpack_dir {
+            let build_dir = match &rockspec.source.for_platform(config.platform()).unpack_dir {
                 Some(unpack_dir) => temp_dir.path().join(unpack_dir),
                 None => temp_dir.path().into(),
             };
 
-            run_build(&rockspec, &output_paths, &lua, config, &build_dir, progress).await?;
-
-            install(&rockspec, &tree, &output_paths, &lua, &build_dir, progress).await?;
+            run_build(
+                &rockspec,
+                &output_paths,
+                false,
+                behaviour,
+                &lua,
+                &build_env,
+                config,
+                &build_dir,
+                progress,
+            )
+            .await?;
+
+            let scripts =
+                install(&rockspec, &output_paths, &lua, config, &build_dir, progress).await?;
+
+            install_conf(
+                &rockspec,
+                &build_dir,
+                &output_paths.conf,
+                &tree.rock_layout(&package).conf,
+                config,
+            )?;
+
+            for directory in &rockspec
+                .build
+                .for_platform(config.platform())
+                .copy_directories
+            {
+                if utils::is_glob(directory) {
+                    utils::copy_glob(&build_dir, directory, &output_paths.etc)?;
+                } else {
+                    recursive_copy_dir(&build_dir.join(directory), &output_paths.etc)?;
+                }
+            }
 
-            for directory in &rockspec.build.current_platform().copy_directories {
-                recursive_copy_dir(&build_dir.join(directory), &output_paths.etc)?;
+            // The launchers refer to the scripts' paths after the rock has been moved into place.
+            let rock_path = tree.root_for(&package);
+            let bins = scripts
+                .iter()
+                .filter_map(|script| {
+                    let file_name = script.file_name()?;
+                    let launcher = staging_tree.bin().join(file_name);
+                    Some(
+                        bin_wrapper::write_launcher(
+                            &launcher,
+                            script,
+                            &rock_path.join("bin").join(file_name),
+                            &package,
+                            &tree,
+                            &lua_version,
+                        )
+                        .map(|()| launcher),
+                    )
+                })
+                .try_collect::<_, Vec<_>, _>()?;
+
+            let rock_manifest = RockManifest::generate(&output_paths, tree.layout(), &bins)?;
+            let conflicts = find_file_conflicts(&rock_manifest, &package, &tree)?;
+            if !conflicts.is_empty() {
+                let package = format!("{}@{}", package.name(), package.version());
+                if !config.allow_conflicts() {
+                    return Err(BuildError::FileConflicts { package, conflicts });
+                }
+                progress::warn(
+                    config.message_format(),
+                    format!(
+                        "{} overwrites files that other rocks installed:\n{}",
+                        package,
+                        conflicts.iter().join("\n")
+                    ),
+                );
+            }
+            rock_manifest.write(&output_paths)?;
+            // luarocks expects the rockspec alongside the `rock_manifest`.
+            if *tree.layout() == TreeLayout::Luarocks {
+                std::fs::write(
+                    output_paths.rock_path.join(format!(
+                        "{}-{}.rockspec",
+                        rockspec.package, rockspec.version
+                    )),
+                    &rockspec.raw_content,
+                )?;
             }
 
+            tree.install_staged(&staging_tree)?;
+
             Ok(package)
         }
     }
 }
 
+/// The files in `rock_manifest` that `package` would install into `tree`,
+/// but that another rock installed, according to the `rock_manifest`s of the installed rocks.
+/// Other versions of `package` are not considered, as they are replaced by it.
+/// With the [`TreeLayout::Rocks`] layout, only executables can conflict,
+/// as each rock's Lua modules and libraries are installed into its own directory.
+/// With the [`TreeLayout::Fhs`] layout, Lua modules and libraries are not part of
+/// the `rock_manifest`s, so conflicts between them are not detected.
+fn find_file_conflicts(
+    rock_manifest: &RockManifest,
+    package: &LocalPackage,
+    tree: &Tree,
+) -> Result<Vec<FileConflict>, BuildError> {
+    let rock_layout = tree.rock_layout(package);
+    let paths = rock_manifest
+        .installed_paths(&rock_layout)
+        .map(|(_, path)| path)
+        .collect_vec();
+    let mut conflicts = Vec::new();
+    for other in tree.lockfile_snapshot()?.rocks().values() {
+        if other.name() == package.name() {
+            continue;
+        }
+        let other_layout = tree.rock_layout(other);
+        let other_manifest = match RockManifest::load(&other_layout)? {
+            Some(other_manifest) => other_manifest,
+            None => continue,
+        };
+        conflicts.extend(
+            other_manifest
+                .installed_paths(&other_layout)
+                .filter(|(_, path)| paths.contains(path))
+                .map(|(_, path)| FileConflict {
+                    path,
+                    owner: format!("{}@{}", other.name(), other.version()),
+                }),
+        );
+    }
+    Ok(conflicts)
+}
+
+/// Build a rock without installing it into the tree.
+/// This runs the full build backend, so that compilation errors surface,
+/// but skips the install phase and leaves the lockfile untouched.
+/// Returns the build directory, which is kept around for inspection until `rocks clean` is run.
+/// Backends that write their output directly (e.g. `builtin`) write it to
+/// a staging layout in the build directory's [`STAGING_DIR_NAME`] subdirectory.
+pub async fn build_no_install(
+    rockspec: Rockspec,
+    config: &Config,
+    progress: &Progress<ProgressBar>,
+) -> Result<PathBuf, BuildError> {
+    progress.map(|p| {
+        p.set_message(format!(
+            "Building {}@{}...",
+            rockspec.package, rockspec.version
+        ))
+    });
+
+    for (name, dep) in rockspec
+        .external_dependencies
+        .for_platform(config.platform())
+    {
+        let _ = ExternalDependencyInfo::detect(name, dep, config.external_deps())?;
+    }
+
+    let lua_version = rockspec.lua_version_from_config(config)?;
+
+    // Kept in the cache directory, so that `rocks clean` can remove it.
+    let build_cache_dir = operations::build_cache_dir(config);
+    std::fs::create_dir_all(&build_cache_dir)?;
+    let temp_dir =
+        tempdir::TempDir::new_in(build_cache_dir, &rockspec.package.to_string())?.into_path();
+
+    fetch_and_verify_src(&rockspec, &temp_dir, config, progress).await?;
+
+    let build_dir = match &rockspec.source.for_platform(config.platform()).unpack_dir {
+        Some(unpack_dir) => temp_dir.join(unpack_dir),
+        None => temp_dir,
+    };
+
+    let rock_path = build_dir.join(STAGING_DIR_NAME);
+    let etc = rock_path.join("etc");
+    let output_paths = RockLayout {
+        lib: rock_path.join("lib"),
+        src: rock_path.join("src"),
+        bin: rock_path.join("bin"),
+        conf: etc.join("conf"),
+        doc: etc.join("doc"),
+        etc,
+        rock_path,
+    };
+    std::fs::create_dir_all(&output_paths.lib)?;
+    std::fs::create_dir_all(&output_paths.src)?;
+    std::fs::create_dir_all(&output_paths.bin)?;
+    std::fs::create_dir_all(&output_paths.conf)?;
+    std::fs::create_dir_all(&output_paths.doc)?;
+
+    let lua = LuaInstallation::new(&lua_version, config);
+    let build_env = build_env(config, &lua_version)?;
+
+    run_build(
+        &rockspec,
+        &output_paths,
+        true,
+        BuildBehaviour::NoForce,
+        &lua,
+        &build_env,
+        config,
+        &build_dir,
+        progress,
+    )
+    .await?;
+
+    Ok(build_dir)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -282,7 +747,7 @@ mod tests {
 
     use assert_fs::{
         assert::PathAssert,
-        prelude::{PathChild as _, PathCopy},
+        prelude::{FileWriteStr as _, PathChild as _, PathCopy},
     };
 
     use crate::{
@@ -319,7 +784,10 @@ mod tests {
         run_build(
             &rockspec,
             &rock_layout,
+            false,
+            BuildBehaviour::NoForce,
             &lua,
+            &Paths::default(),
             &config,
             &build_dir,
             &progress.map(|p| p.new_bar()),
@@ -344,4 +812,151 @@ mod tests {
         bin_file.assert(predicate::str::contains("#!/usr/bin/env bash"));
         bin_file.assert(predicate::str::contains("echo \"Hello\""));
     }
+
+    #[tokio::test]
+    async fn install_glob_patterns() {
+        let build_dir = assert_fs::TempDir::new().unwrap();
+        for file in [
+            "src/foo.lua",
+            "src/nested/bar.lua",
+            "src/nested/deeper/baz.lua",
+            "src/README.md",
+            "docs/guide/intro.md",
+            "docs/notes.txt",
+        ] {
+            build_dir.child(file).write_str("return true").unwrap();
+        }
+        let rockspec = Rockspec::new(
+            r#"
+package = "foo"
+version = "1.0.0-1"
+source = {
+    url = "https://github.com/nvim-neorocks/luarocks-stub",
+}
+build = {
+    type = "builtin",
+    install = {
+        lua = {
+            mymod = "src/**/*.lua",
+        },
+    },
+}
+"#,
+        )
+        .unwrap();
+        let dest_dir = assert_fs::TempDir::new().unwrap();
+        let rock_layout = RockLayout {
+            rock_path: dest_dir.to_path_buf(),
+            etc: dest_dir.join("etc"),
+            lib: dest_dir.join("lib"),
+            src: dest_dir.join("src"),
+            bin: dest_dir.join("bin"),
+            conf: dest_dir.join("conf"),
+            doc: dest_dir.join("doc"),
+        };
+        let config = ConfigBuilder::new().build().unwrap();
+        let lua = LuaInstallation::new(&LuaVersion::Lua51, &config);
+        let progress = Progress::Progress(MultiProgress::new());
+        install(
+            &rockspec,
+            &rock_layout,
+            &lua,
+            &config,
+            &build_dir,
+            &progress.map(|p| p.new_bar()),
+        )
+        .await
+        .unwrap();
+        let src_dir = dest_dir.child("src").child("mymod");
+        src_dir.child("foo.lua").assert(predicate::path::is_file());
+        src_dir
+            .child("nested/bar.lua")
+            .assert(predicate::path::is_file());
+        src_dir
+            .child("nested/deeper/baz.lua")
+            .assert(predicate::path::is_file());
+        src_dir
+            .child("README.md")
+            .assert(predicate::path::missing());
+
+        utils::copy_glob(&build_dir, Path::new("docs/**/*.md"), &rock_layout.etc).unwrap();
+        let etc_dir = dest_dir.child("etc");
+        etc_dir
+            .child("docs/guide/intro.md")
+            .assert(predicate::path::is_file());
+        etc_dir
+            .child("docs/notes.txt")
+            .assert(predicate::path::missing());
+
+        assert!(matches!(
+            utils::expand_glob(&build_dir, Path::new("../**/*.lua")),
+            Err(GlobError::EscapesSourceDir(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn failed_build_leaves_tree_unchanged() {
+        let source_dir = assert_fs::TempDir::new().unwrap();
+        source_dir
+            .child("src/foo.lua")
+            .write_str("return true")
+            .unwrap();
+        source_dir.child("bin/foo").write_str("echo foo").unwrap();
+        let rockspec = Rockspec::new(&format!(
+            r#"
+package = "foo"
+version = "1.0.0-1"
+source = {{
+    url = "file://{}",
+}}
+build = {{
+    type = "builtin",
+    modules = {{
+        foo = "src/foo.lua",
+        bar = "src/missing.lua",
+    }},
+    install = {{
+        bin = {{
+            foo = "bin/foo",
+        }},
+    }},
+}}
+"#,
+            source_dir.display()
+        ))
+        .unwrap();
+        let tree_dir = assert_fs::TempDir::new().unwrap();
+        let config = ConfigBuilder::new()
+            .tree(Some(tree_dir.to_path_buf()))
+            .lua_version(Some(LuaVersion::Lua51))
+            .build()
+            .unwrap();
+        let tree = Tree::from_config(&config, LuaVersion::Lua51).unwrap();
+        let list_files = || {
+            walkdir::WalkDir::new(tree_dir.path())
+                .sort_by_file_name()
+                .into_iter()
+                .map(|entry| entry.unwrap().into_path())
+                .collect::<Vec<_>>()
+        };
+        // Creates the lockfile and the tree lock, which the build needs anyway.
+        drop(tree.lockfile().unwrap());
+        let files_before = list_files();
+
+        let result = build(
+            rockspec,
+            None,
+            PinnedState::Unpinned,
+            LockConstraint::Unconstrained,
+            BuildBehaviour::NoForce,
+            &config,
+            &Progress::NoProgress,
+        )
+        .await;
+
+        assert!(result.is_err());
+        // Neither the rock's files nor the staging tree are left behind.
+        assert_eq!(list_files(), files_before);
+        assert!(tree.lockfile().unwrap().rocks().is_empty());
+    }
 }
@@ -0,0 +1,257 @@
+//! Incremental compilation of C modules.
+//!
+//! The object files of each module are kept in the cache directory, per Lua version and package,
+//! along with a small manifest of the content hashes of the sources they were compiled from.
+//! Rebuilding a module only recompiles the sources that have changed since the last build.
+//! Everything is recompiled if the compiler, the compile flags or any header in the
+//! source directory change, or if the build is forced.
+
+use std::{
+    collections::BTreeMap,
+    io,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use walkdir::WalkDir;
+
+use crate::{
+    config::{Config, LuaVersion},
+    operations,
+    package::PackageSpec,
+    rockspec::LuaModule,
+};
+
+use super::{utils::lua_obj_extension, BuildBehaviour, BuildError};
+
+const MANIFEST_FILE_NAME: &str = "objects.json";
+
+/// The object files of previously compiled C modules.
+pub(crate) struct ObjectCache {
+    root: PathBuf,
+    behaviour: BuildBehaviour,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug, PartialEq, Eq)]
+struct ObjectManifest {
+    /// The hash of everything other than the sources that affects the object files.
+    fingerprint: String,
+    /// The content hashes of the sources that were compiled, by relative path.
+    sources: BTreeMap<String, String>,
+}
+
+impl ObjectCache {
+    /// The object files of `package`'s modules, compiled against Lua `lua_version`.
+    /// With [`BuildBehaviour::Force`], all sources are recompiled,
+    /// but the cache is still updated for subsequent builds.
+    pub(crate) fn new(
+        config: &Config,
+        package: &PackageSpec,
+        lua_version: &LuaVersion,
+        behaviour: BuildBehaviour,
+    ) -> Self {
+        Self {
+            root: operations::object_cache_dir(config)
+                .join(lua_version.to_string())
+                .join(format!("{}@{}", package.name(), package.version())),
+            behaviour,
+        }
+    }
+
+    /// Compile the `sources` of `module` that have changed since the last build
+    /// and return the object files of all `sources`, in the same order.
+    /// `build` must be configured with an output directory, but without any files.
+    /// `flags` are the compile flags that are not part of `build`'s compiler,
+    /// with paths relative to `source_dir`.
+    pub(crate) fn compile(
+        &self,
+        build: &mut cc::Build,
+        module: &LuaModule,
+        source_dir: &Path,
+        sources: &[PathBuf],
+        flags: &[String],
+    ) -> Result<Vec<PathBuf>, BuildError> {
+        let cache_dir = self.root.join(module.as_str());
+        std::fs::create_dir_all(&cache_dir)?;
+        let manifest_path = cache_dir.join(MANIFEST_FILE_NAME);
+
+        let fingerprint = fingerprint(build, source_dir, flags)?;
+        let previous = match self.behaviour {
+            BuildBehaviour::NoForce => load_manifest(&manifest_path)
+                .filter(|manifest| manifest.fingerprint == fingerprint)
+                .unwrap_or_default(),
+            BuildBehaviour::Force => ObjectManifest::default(),
+        };
+
+        let mut manifest = ObjectManifest {
+            fingerprint,
+            sources: BTreeMap::new(),
+        };
+        let mut objects = Vec::with_capacity(sources.len());
+        let mut stale = Vec::new();
+        for source in sources {
+            let relative_path = source
+                .strip_prefix(source_dir)
+                .unwrap_or(source)
+                .to_string_lossy()
+                .to_string();
+            let hash = hash_file(source)?;
+            let object = cache_dir.join(object_name(&relative_path));
+            if previous.sources.get(&relative_path) != Some(&hash) || !object.is_file() {
+                stale.push((source, object.clone()));
+            }
+            manifest.sources.insert(relative_path, hash);
+            objects.push(object);
+        }
+
+        if !stale.is_empty() {
+            // See https://github.com/rust-lang/cc-rs/issues/594#issuecomment-2110551057
+            let intermediates = build
+                .files(stale.iter().map(|(source, _)| source))
+                .try_compile_intermediates()?;
+            for (intermediate, (_, object)) in intermediates.iter().zip(&stale) {
+                std::fs::copy(intermediate, object)?;
+            }
+        }
+
+        for relative_path in previous.sources.keys() {
+            if !manifest.sources.contains_key(relative_path) {
+                let _ = std::fs::remove_file(cache_dir.join(object_name(relative_path)));
+            }
+        }
+        let content = serde_json::to_string(&manifest).map_err(io::Error::from)?;
+        std::fs::write(&manifest_path, content)?;
+
+        Ok(objects)
+    }
+}
+
+fn load_manifest(path: &Path) -> Option<ObjectManifest> {
+    let content = std::fs::read_to_string(path).ok()?;
+    serde_json::from_str(&content).ok()
+}
+
+/// Headers are not tracked per source, so a change to any of them invalidates all objects.
+fn fingerprint(build: &cc::Build, source_dir: &Path, flags: &[String]) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    hasher.update(build.get_compiler().path().to_string_lossy().as_bytes());
+    for flag in flags {
+        hasher.update(b"\0");
+        hasher.update(flag.as_bytes());
+    }
+    let headers = WalkDir::new(source_dir)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter(|entry| {
+            entry.file_type().is_file() && entry.path().extension().is_some_and(|ext| ext == "h")
+        });
+    for header in headers {
+        hasher.update(b"\0");
+        let relative_path = header
+            .path()
+            .strip_prefix(source_dir)
+            .unwrap_or(header.path());
+        hasher.update(relative_path.to_string_lossy().as_bytes());
+        hasher.update(std::fs::read(header.path())?);
+    }
+    Ok(hex::encode(hasher.finalize()))
+}
+
+fn hash_file(path: &Path) -> io::Result<String> {
+    Ok(hex::encode(Sha256::digest(std::fs::read(path)?)))
+}
+
+/// Sources with the same file name may live in different directories,
+/// so object files are prefixed with a hash of the source's relative path.
+fn object_name(relative_path: &str) -> String {
+    let stem = Path::new(relative_path)
+        .file_stem()
+        .unwrap_or_default()
+        .to_string_lossy();
+    let path_hash = hex::encode(Sha256::digest(relative_path.as_bytes()));
+    format!("{}-{}.{}", &path_hash[..16], stem, lua_obj_extension())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs::File,
+        str::FromStr,
+        time::{Duration, SystemTime},
+    };
+
+    use assert_fs::prelude::*;
+    use target_lexicon::Triple;
+
+    use crate::config::ConfigBuilder;
+
+    use super::*;
+
+    fn modified(path: &Path) -> SystemTime {
+        std::fs::metadata(path).unwrap().modified().unwrap()
+    }
+
+    #[test]
+    fn only_recompile_changed_sources() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let source_dir = temp.child("src");
+        source_dir
+            .child("foo.c")
+            .write_str("int foo(void) { return 1; }")
+            .unwrap();
+        source_dir
+            .child("bar.c")
+            .write_str("int bar(void) { return 1; }")
+            .unwrap();
+        let sources = vec![source_dir.join("foo.c"), source_dir.join("bar.c")];
+        let config = ConfigBuilder::new()
+            .cache_dir(Some(temp.join("cache")))
+            .build()
+            .unwrap();
+        let module = LuaModule::from_str("foo").unwrap();
+        let package = PackageSpec::parse("foo".into(), "1.0.0-1".into()).unwrap();
+        let build_with = |behaviour| {
+            let intermediate_dir = assert_fs::TempDir::new().unwrap();
+            let host = Triple::host().to_string();
+            let mut build = cc::Build::new();
+            build
+                .cargo_metadata(false)
+                .host(&host)
+                .target(&host)
+                .opt_level(0)
+                .out_dir(&intermediate_dir);
+            ObjectCache::new(&config, &package, &LuaVersion::Lua51, behaviour)
+                .compile(&mut build, &module, &source_dir, &sources, &[])
+                .unwrap()
+        };
+        let unchanged = SystemTime::UNIX_EPOCH + Duration::from_secs(1);
+        let mark_unchanged = |objects: &[PathBuf]| {
+            for object in objects {
+                File::options()
+                    .write(true)
+                    .open(object)
+                    .unwrap()
+                    .set_modified(unchanged)
+                    .unwrap();
+            }
+        };
+
+        let objects = build_with(BuildBehaviour::NoForce);
+        assert!(objects.iter().all(|object| object.is_file()));
+        mark_unchanged(&objects);
+
+        source_dir
+            .child("bar.c")
+            .write_str("int bar(void) { return 2; }")
+            .unwrap();
+        assert_eq!(build_with(BuildBehaviour::NoForce), objects);
+        assert_eq!(modified(&objects[0]), unchanged);
+        assert_ne!(modified(&objects[1]), unchanged);
+
+        mark_unchanged(&objects);
+        build_with(BuildBehaviour::Force);
+        assert!(objects.iter().all(|object| modified(object) != unchanged));
+    }
+}
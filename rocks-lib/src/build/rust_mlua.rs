@@ -1,9 +1,11 @@
-use super::utils::lua_lib_extension;
+use super::{utils::lua_lib_extension, BuildBehaviour};
 use crate::config::LuaVersionUnset;
 use crate::progress::{Progress, ProgressBar};
 use crate::{
     config::{Config, LuaVersion},
     lua_installation::LuaInstallation,
+    package::PackageSpec,
+    path::Paths,
     rockspec::{Build, RustMluaBuildSpec},
     tree::RockLayout,
 };
@@ -35,7 +37,10 @@ impl Build for RustMluaBuildSpec {
         self,
         output_paths: &RockLayout,
         _no_install: bool,
+        _behaviour: BuildBehaviour,
+        _package: &PackageSpec,
         _lua: &LuaInstallation,
+        build_env: &Paths,
         config: &Config,
         build_dir: &Path,
         progress: &Progress<ProgressBar>,
@@ -63,6 +68,7 @@ impl Build for RustMluaBuildSpec {
         build_args.push(&features);
         match Command::new("cargo")
             .current_dir(build_dir)
+            .envs(build_env.env_vars())
             .args(build_args)
             .output()
         {
@@ -1,20 +1,22 @@
 use crate::{
-    build::BuildError,
+    build::{BuildError, BuildProfile},
     config::Config,
     lua_installation::LuaInstallation,
     rockspec::{LuaModule, ModulePaths},
     tree::RockLayout,
 };
+use globset::GlobBuilder;
 use itertools::Itertools;
 use shlex::try_quote;
 use std::{
     io,
-    path::{Path, PathBuf},
+    path::{Component, Path, PathBuf},
     process::Output,
 };
 use target_lexicon::Triple;
+use thiserror::Error;
 
-use super::variables::HasVariables;
+use super::{object_cache::ObjectCache, variables::HasVariables};
 
 /// Copies a lua source file to a specific destination. The destination is described by a
 /// `module.path` syntax (equivalent to the syntax provided to Lua's `require()` function).
@@ -50,6 +52,85 @@ pub(crate) fn recursive_copy_dir(src: &PathBuf, dest: &Path) -> Result<(), io::E
     }
     Ok(())
 }
+#[derive(Error, Debug)]
+pub enum GlobError {
+    #[error("invalid glob pattern '{pattern}': {err}")]
+    InvalidPattern {
+        pattern: String,
+        err: globset::Error,
+    },
+    #[error("glob pattern '{0}' must be relative to the source directory")]
+    EscapesSourceDir(String),
+    #[error("error expanding glob pattern '{pattern}': {err}")]
+    Walk {
+        pattern: String,
+        err: walkdir::Error,
+    },
+}
+
+/// Whether a path in a rockspec is a glob pattern, e.g. `lua/**/*.lua`.
+pub(crate) fn is_glob(path: &Path) -> bool {
+    path.to_string_lossy().contains(['*', '?', '[', '{'])
+}
+
+/// The leading components of a glob pattern that don't contain any wildcards,
+/// e.g. `lua` for `lua/**/*.lua`.
+pub(crate) fn glob_base(pattern: &Path) -> PathBuf {
+    pattern
+        .components()
+        .take_while(|component| !is_glob(Path::new(component)))
+        .collect()
+}
+
+/// Expand a glob pattern to the files it matches in `source_dir`.
+/// Returns the paths of the matched files, relative to `source_dir`, in a stable order.
+pub(crate) fn expand_glob(source_dir: &Path, pattern: &Path) -> Result<Vec<PathBuf>, GlobError> {
+    let pattern_str = pattern.to_string_lossy().to_string();
+    if pattern
+        .components()
+        .any(|component| !matches!(component, Component::Normal(_) | Component::CurDir))
+    {
+        return Err(GlobError::EscapesSourceDir(pattern_str));
+    }
+    let glob = GlobBuilder::new(pattern_str.trim_start_matches("./"))
+        .literal_separator(true)
+        .build()
+        .map_err(|err| GlobError::InvalidPattern {
+            pattern: pattern_str.clone(),
+            err,
+        })?
+        .compile_matcher();
+    let base = source_dir.join(glob_base(pattern));
+    if !base.exists() {
+        return Ok(Vec::new());
+    }
+    walkdir::WalkDir::new(&base)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_map_ok(|entry| {
+            let relative_path = entry.path().strip_prefix(source_dir).ok()?.to_path_buf();
+            (entry.file_type().is_file() && glob.is_match(&relative_path)).then_some(relative_path)
+        })
+        .map(|result| {
+            result.map_err(|err| GlobError::Walk {
+                pattern: pattern_str.clone(),
+                err,
+            })
+        })
+        .try_collect()
+}
+
+/// Copies the files matching a glob pattern in `source_dir` to `dest`,
+/// preserving their paths relative to `source_dir`.
+pub(crate) fn copy_glob(source_dir: &Path, pattern: &Path, dest: &Path) -> Result<(), BuildError> {
+    for relative_path in expand_glob(source_dir, pattern)? {
+        let target = dest.join(&relative_path);
+        std::fs::create_dir_all(target.parent().unwrap())?;
+        std::fs::copy(source_dir.join(relative_path), target)?;
+    }
+    Ok(())
+}
+
 fn validate_output(output: Output) -> Result<(), BuildError> {
     if !output.status.success() {
         return Err(BuildError::CommandFailure {
@@ -62,13 +143,17 @@ fn validate_output(output: Output) -> Result<(), BuildError> {
 }
 
 /// Compiles a set of C files into a single dynamic library and places them under `{target_dir}/{target_file}`.
+/// If an [`ObjectCache`] is given, only the files that have changed since the last build are recompiled.
 /// # Panics
 /// Panics if no parent or no filename can be determined for the target path.
 pub(crate) fn compile_c_files(
-    files: &Vec<PathBuf>,
+    files: &[PathBuf],
+    source_dir: &Path,
     target_module: &LuaModule,
     target_dir: &Path,
     lua: &LuaInstallation,
+    profile: BuildProfile,
+    object_cache: Option<&ObjectCache>,
 ) -> Result<(), BuildError> {
     let target = target_dir.join(target_module.to_lib_path());
 
@@ -89,24 +174,37 @@ pub(crate) fn compile_c_files(
 
     let host = Triple::host();
 
-    // See https://github.com/rust-lang/cc-rs/issues/594#issuecomment-2110551057
-
     let mut build = cc::Build::new();
     let intermediate_dir = tempdir::TempDir::new(target_module.as_str())?;
     let build = build
         .cargo_metadata(false)
-        .debug(false)
-        .files(files)
+        .debug(profile == BuildProfile::Debug)
         .host(std::env::consts::OS)
-        .opt_level(3)
-        .out_dir(intermediate_dir)
+        .opt_level(profile.opt_level())
+        .out_dir(intermediate_dir.path())
         .target(&host.to_string());
+    if profile == BuildProfile::Release {
+        build.define("NDEBUG", None);
+    }
 
-    for arg in lua.compile_args() {
-        build.flag(&arg);
+    let compile_args = lua.compile_args();
+    for arg in &compile_args {
+        build.flag(arg);
     }
 
-    let objects = build.compile_intermediates();
+    // Objects compiled with another profile must not be reused.
+    let cache_flags = compile_args
+        .into_iter()
+        .chain(std::iter::once(profile.cflags().to_string()))
+        .collect_vec();
+    let objects = compile_objects(
+        build,
+        files,
+        source_dir,
+        target_module,
+        &cache_flags,
+        object_cache,
+    )?;
     let output = build
         .get_compiler()
         .to_command()
@@ -114,12 +212,29 @@ pub(crate) fn compile_c_files(
         .arg(parent.join(file))
         .arg(format!("-L{}", lua.lib_dir.to_string_lossy())) // TODO: In luarocks, this is behind a link_lua_explicitly config option Library directory
         .args(lua.link_args())
+        .args(profile.ldflags().split_whitespace())
         .args(&objects)
         .output()?;
     validate_output(output)?;
     Ok(())
 }
 
+/// Compiles `files` to object files, reusing the cached objects of unchanged files if possible.
+fn compile_objects(
+    build: &mut cc::Build,
+    files: &[PathBuf],
+    source_dir: &Path,
+    target_module: &LuaModule,
+    flags: &[String],
+    object_cache: Option<&ObjectCache>,
+) -> Result<Vec<PathBuf>, BuildError> {
+    match object_cache {
+        Some(object_cache) => object_cache.compile(build, target_module, source_dir, files, flags),
+        // See https://github.com/rust-lang/cc-rs/issues/594#issuecomment-2110551057
+        None => Ok(build.files(files).try_compile_intermediates()?),
+    }
+}
+
 // TODO: (#261): special cases for mingw/cygwin?
 
 /// the extension for Lua libraries.
@@ -159,6 +274,7 @@ pub(crate) fn default_libflag() -> &'static str {
 }
 
 /// Compiles a set of C files (with extra metadata) to a given destination.
+/// If an [`ObjectCache`] is given, only the files that have changed since the last build are recompiled.
 /// # Panics
 /// Panics if no filename for the target path can be determined.
 pub(crate) fn compile_c_modules(
@@ -167,6 +283,8 @@ pub(crate) fn compile_c_modules(
     target_module: &LuaModule,
     target_dir: &Path,
     lua: &LuaInstallation,
+    profile: BuildProfile,
+    object_cache: Option<&ObjectCache>,
 ) -> Result<(), BuildError> {
     let target = target_dir.join(target_module.to_lib_path());
 
@@ -195,17 +313,17 @@ pub(crate) fn compile_c_modules(
     let intermediate_dir = tempdir::TempDir::new(target_module.as_str())?;
     let build = build
         .cargo_metadata(false)
-        .debug(false)
-        .files(source_files)
+        .debug(profile == BuildProfile::Debug)
         .host(std::env::consts::OS)
         .includes(&include_dirs)
-        .opt_level(3)
-        .out_dir(intermediate_dir)
+        .opt_level(profile.opt_level())
+        .out_dir(intermediate_dir.path())
         .shared_flag(true)
         .target(&host.to_string());
 
-    for arg in lua.compile_args() {
-        build.flag(&arg);
+    let compile_args = lua.compile_args();
+    for arg in &compile_args {
+        build.flag(arg);
     }
 
     // `cc::Build` has no `defines()` function, so we manually feed in the
@@ -213,6 +331,25 @@ pub(crate) fn compile_c_modules(
     for (name, value) in &data.defines {
         build.define(name, value.as_deref());
     }
+    if profile == BuildProfile::Release {
+        build.define("NDEBUG", None);
+    }
+
+    // The include directories are absolute, so they are listed relative to the
+    // source directory, which may differ from one build to the next.
+    let cache_flags = compile_args
+        .into_iter()
+        .chain(std::iter::once(profile.cflags().to_string()))
+        .chain(
+            data.incdirs
+                .iter()
+                .map(|dir| format!("-I{}", dir.display())),
+        )
+        .chain(data.defines.iter().map(|(name, value)| match value {
+            Some(value) => format!("-D{}={}", name, value),
+            None => format!("-D{}", name),
+        }))
+        .collect_vec();
 
     let file = target.file_name().unwrap_or_else(|| {
         panic!(
@@ -220,18 +357,14 @@ pub(crate) fn compile_c_modules(
             target.to_str().unwrap_or("")
         )
     });
-    // See https://github.com/rust-lang/cc-rs/issues/594#issuecomment-2110551057
-    let objects = build.compile_intermediates();
-
-    let libdir_args = data
-        .libdirs
-        .iter()
-        .map(|libdir| format!("-L{}", source_dir.join(libdir).to_str().unwrap()));
-
-    let library_args = data
-        .libraries
-        .iter()
-        .map(|library| format!("-l{}", library.to_str().unwrap()));
+    let objects = compile_objects(
+        build,
+        &source_files,
+        source_dir,
+        target_module,
+        &cache_flags,
+        object_cache,
+    )?;
 
     let output = build
         .get_compiler()
@@ -240,15 +373,32 @@ pub(crate) fn compile_c_modules(
         .arg(parent.join(file))
         .arg(format!("-L{}", lua.lib_dir.to_string_lossy())) // TODO: In luarocks, this is behind a link_lua_explicitly config option Library directory
         .args(lua.link_args())
+        .args(profile.ldflags().split_whitespace())
         .args(&objects)
-        .args(libdir_args)
-        .args(library_args)
+        .args(library_link_args(data, source_dir))
         .output()?;
     validate_output(output)?;
 
     Ok(())
 }
 
+/// The linker arguments for the `libdirs` and `libraries` of a module.
+/// The libraries are linked in the order in which the rockspec declares them,
+/// as linkers resolve a library's symbols from the libraries that follow it.
+fn library_link_args(data: &ModulePaths, source_dir: &Path) -> Vec<String> {
+    let libdir_args = data
+        .libdirs
+        .iter()
+        .map(|libdir| format!("-L{}", source_dir.join(libdir).to_str().unwrap()));
+
+    let library_args = data
+        .libraries
+        .iter()
+        .map(|library| format!("-l{}", library.to_str().unwrap()));
+
+    libdir_args.chain(library_args).collect()
+}
+
 pub(crate) fn substitute_variables(
     input: &str,
     output_paths: &RockLayout,
@@ -270,3 +420,48 @@ pub(crate) fn escape_path(path: &Path) -> String {
             .unwrap_or(format!("'{}'", path_str))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use mlua::{Lua, LuaSerdeExt as _};
+
+    use crate::rockspec::{BuiltinBuildSpec, ModuleSpec};
+
+    use super::*;
+
+    #[test]
+    fn link_libraries_in_declared_order() {
+        let lua = Lua::new();
+        lua.load(
+            "
+            build = {
+                modules = {
+                    foo = {
+                        sources = { 'src/foo.c' },
+                        libraries = { 'ssl', 'crypto', 'z' },
+                        libdirs = { 'lib' },
+                    },
+                },
+            }
+            ",
+        )
+        .exec()
+        .unwrap();
+        let build_spec: BuiltinBuildSpec =
+            lua.from_value(lua.globals().get("build").unwrap()).unwrap();
+        let module_paths = match build_spec.modules.values().next().unwrap() {
+            ModuleSpec::ModulePaths(module_paths) => module_paths,
+            module_spec => panic!("expected module paths, got {module_spec:?}"),
+        };
+        let source_dir = Path::new("/src");
+        assert_eq!(
+            library_link_args(module_paths, source_dir),
+            vec![
+                format!("-L{}", source_dir.join("lib").display()),
+                "-lssl".into(),
+                "-lcrypto".into(),
+                "-lz".into(),
+            ]
+        );
+    }
+}
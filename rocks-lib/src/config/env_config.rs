@@ -0,0 +1,139 @@
+//! Settings from `ROCKS_*` environment variables, which provide defaults
+//! for the config file and command line flags (see [`super::file`] for the order of precedence).
+//!
+//! | Variable                    | Setting                                          |
+//! |-----------------------------|--------------------------------------------------|
+//! | `ROCKS_SERVER`              | [`ConfigBuilder::server`]                        |
+//! | `ROCKS_TREE`                | [`ConfigBuilder::tree`]                          |
+//! | `ROCKS_TARGET_DIR`          | [`ConfigBuilder::target_dir`]                    |
+//! | `ROCKS_CACHE_DIR`           | [`ConfigBuilder::cache_dir`]                     |
+//! | `ROCKS_LUA_VERSION`         | [`ConfigBuilder::lua_version`], see below        |
+//! | `ROCKS_TIMEOUT`             | [`ConfigBuilder::timeout`], in seconds           |
+//! | `ROCKS_CHECK_FOR_UPDATES`   | [`ConfigBuilder::check_for_updates`], `1`/`true` |
+//! | `ROCKS_NO_DEV_DEPENDENCIES` | [`ConfigBuilder::no_dev_dependencies`], `1`/`true` |
+//!
+//! Unlike the other settings, `ROCKS_LUA_VERSION` does not take precedence over the Lua version
+//! of the current project (see [`ConfigBuilder::lua_version`]).
+//!
+//! The config file is selected with `ROCKS_CONFIG` (see [`ConfigBuilder::from_config_file`]).
+//! Proxies are configured with the standard `HTTP_PROXY`, `HTTPS_PROXY`, `ALL_PROXY`
+//! and `NO_PROXY` environment variables, which are read by the HTTP client.
+
+use std::{env, path::PathBuf, time::Duration};
+
+use super::{ConfigBuilder, ConfigError};
+
+impl ConfigBuilder {
+    /// Read the settings from the `ROCKS_*` environment variables.
+    /// The config file and command line flags can then be applied with [`ConfigBuilder::merge`].
+    pub fn from_env() -> Result<Self, ConfigError> {
+        Ok(Self {
+            server: var("ROCKS_SERVER"),
+            tree: var("ROCKS_TREE").map(PathBuf::from),
+            target_dir: var("ROCKS_TARGET_DIR").map(PathBuf::from),
+            cache_dir: var("ROCKS_CACHE_DIR").map(PathBuf::from),
+            env_lua_version: var("ROCKS_LUA_VERSION")
+                .map(|lua_version| lua_version.parse())
+                .transpose()
+                .map_err(ConfigError::LuaVersionEnv)?,
+            timeout: var("ROCKS_TIMEOUT")
+                .map(|timeout| {
+                    timeout.parse().map(Duration::from_secs).map_err(|_| {
+                        ConfigError::EnvVar("ROCKS_TIMEOUT", "expected a number of seconds".into())
+                    })
+                })
+                .transpose()?,
+            check_for_updates: flag("ROCKS_CHECK_FOR_UPDATES"),
+            no_dev_dependencies: flag("ROCKS_NO_DEV_DEPENDENCIES"),
+            ..Self::default()
+        })
+    }
+}
+
+/// The value of an environment variable, if it is set and not empty.
+fn var(name: &str) -> Option<String> {
+    env::var(name).ok().filter(|value| !value.is_empty())
+}
+
+fn flag(name: &str) -> Option<bool> {
+    var(name).map(|value| value == "1" || value == "true")
+}
+
+#[cfg(test)]
+mod tests {
+    use serial_test::serial;
+
+    use crate::config::LuaVersion;
+
+    use super::*;
+
+    const VARS: [(&str, &str); 8] = [
+        ("ROCKS_SERVER", "https://env.example.com/"),
+        ("ROCKS_TREE", "/env/tree"),
+        ("ROCKS_TARGET_DIR", "/env/target"),
+        ("ROCKS_CACHE_DIR", "/env/cache"),
+        ("ROCKS_LUA_VERSION", "5.3"),
+        ("ROCKS_TIMEOUT", "5"),
+        ("ROCKS_CHECK_FOR_UPDATES", "true"),
+        ("ROCKS_NO_DEV_DEPENDENCIES", "0"),
+    ];
+
+    #[test]
+    #[serial]
+    fn env_vars_map_to_settings() {
+        for (name, value) in VARS {
+            env::set_var(name, value);
+        }
+        let builder = ConfigBuilder::from_env();
+        for (name, _) in VARS {
+            env::remove_var(name);
+        }
+        let builder = builder.unwrap();
+        assert_eq!(builder.server.as_deref(), Some("https://env.example.com/"));
+        assert_eq!(builder.tree, Some("/env/tree".into()));
+        assert_eq!(builder.target_dir, Some("/env/target".into()));
+        assert_eq!(builder.cache_dir, Some("/env/cache".into()));
+        assert_eq!(builder.env_lua_version, Some(LuaVersion::Lua53));
+        assert_eq!(builder.lua_version, None);
+        assert_eq!(builder.timeout, Some(Duration::from_secs(5)));
+        assert_eq!(builder.check_for_updates, Some(true));
+        assert_eq!(builder.no_dev_dependencies, Some(false));
+
+        // Command line flags and the config file take precedence.
+        let builder =
+            builder.merge(ConfigBuilder::new().server(Some("https://cli.example.com/".into())));
+        assert_eq!(builder.server.as_deref(), Some("https://cli.example.com/"));
+        assert_eq!(builder.timeout, Some(Duration::from_secs(5)));
+    }
+
+    #[test]
+    #[serial]
+    fn unset_env_vars() {
+        for (name, _) in VARS {
+            env::remove_var(name);
+        }
+        env::set_var("ROCKS_SERVER", "");
+        let builder = ConfigBuilder::from_env();
+        env::remove_var("ROCKS_SERVER");
+        let builder = builder.unwrap();
+        assert_eq!(builder.server, None);
+        assert_eq!(builder.check_for_updates, None);
+    }
+
+    #[test]
+    #[serial]
+    fn invalid_env_vars() {
+        env::set_var("ROCKS_TIMEOUT", "soon");
+        let result = ConfigBuilder::from_env();
+        env::remove_var("ROCKS_TIMEOUT");
+        assert!(matches!(
+            result,
+            Err(ConfigError::EnvVar("ROCKS_TIMEOUT", _))
+        ));
+
+        env::set_var("ROCKS_LUA_VERSION", "5.9");
+        let result = ConfigBuilder::from_env();
+        env::remove_var("ROCKS_LUA_VERSION");
+        assert!(matches!(result, Err(ConfigError::LuaVersionEnv(_))));
+    }
+}
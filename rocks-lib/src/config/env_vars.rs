@@ -0,0 +1,133 @@
+//! Environment variable substitution in source and server URLs.
+//!
+//! URLs may reference environment variables as `${VAR}`, e.g. to pass an access token to
+//! a private server without storing it in a rockspec or config file.
+//! Templates are kept as written everywhere except in the request itself,
+//! so that secrets never end up in lockfiles, error messages or progress output.
+
+use std::{env, str::FromStr};
+
+use reqwest::Url;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum EnvVarError {
+    #[error("environment variable '{0}' referenced in '{1}' is not set")]
+    Unset(String, String),
+    #[error("expanding environment variables in '{0}' produced an invalid URL: {1}")]
+    InvalidUrl(String, #[source] <Url as FromStr>::Err),
+}
+
+/// Replace each `${VAR}` in `template` with the value of the environment variable `VAR`.
+/// Because [`Url`] percent-encodes braces in the userinfo and path,
+/// `$%7BVAR%7D` is treated as a placeholder, too.
+pub fn expand_env_vars(template: &str) -> Result<String, EnvVarError> {
+    let mut result = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('$') {
+        result.push_str(&rest[..start]);
+        let candidate = &rest[start + 1..];
+        match placeholder(candidate) {
+            Some((name, len)) => {
+                let value = env::var(name)
+                    .map_err(|_| EnvVarError::Unset(name.to_string(), template.to_string()))?;
+                result.push_str(&value);
+                rest = &candidate[len..];
+            }
+            None => {
+                result.push('$');
+                rest = candidate;
+            }
+        }
+    }
+    result.push_str(rest);
+    Ok(result)
+}
+
+/// Expand the environment variables referenced by `url`.
+/// The result must only be used to make requests and must not be displayed.
+pub fn expand_url(url: &Url) -> Result<Url, EnvVarError> {
+    if !has_env_vars(url.as_str()) {
+        return Ok(url.clone());
+    }
+    expand_env_vars(url.as_str())?
+        .parse()
+        .map_err(|err| EnvVarError::InvalidUrl(url.to_string(), err))
+}
+
+/// Replace the expanded URL in a request error with `template`, so that the error can be displayed.
+pub fn redact_error(err: reqwest::Error, template: &str) -> reqwest::Error {
+    if !has_env_vars(template) {
+        return err;
+    }
+    match template.parse() {
+        Ok(url) => err.with_url(url),
+        Err(_) => err.without_url(),
+    }
+}
+
+/// Whether `template` references any environment variables.
+pub fn has_env_vars(template: &str) -> bool {
+    template
+        .match_indices('$')
+        .any(|(start, _)| placeholder(&template[start + 1..]).is_some())
+}
+
+/// Parses a placeholder following a `$`, returning the variable name
+/// and the length of the placeholder (excluding the `$`).
+fn placeholder(str: &str) -> Option<(&str, usize)> {
+    let (open, close) = if str.starts_with('{') {
+        ("{", "}")
+    } else if str.get(..3).is_some_and(|s| s.eq_ignore_ascii_case("%7B")) {
+        ("%7B", "%7D")
+    } else {
+        return None;
+    };
+    let body = &str[open.len()..];
+    let name_len = body
+        .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+        .unwrap_or(body.len());
+    let name = &body[..name_len];
+    let is_valid_name = name
+        .chars()
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_');
+    let end = &body[name_len..];
+    let is_closed = end
+        .get(..close.len())
+        .is_some_and(|s| s.eq_ignore_ascii_case(close));
+    if is_valid_name && is_closed {
+        Some((name, open.len() + name_len + close.len()))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serial_test::serial;
+
+    use super::*;
+
+    #[test]
+    #[serial]
+    fn expand_placeholders() {
+        env::set_var("ROCKS_TEST_TOKEN", "s3cr3t");
+        assert_eq!(
+            expand_env_vars("https://${ROCKS_TEST_TOKEN}@example.com/${ROCKS_TEST_TOKEN}").unwrap(),
+            "https://s3cr3t@example.com/s3cr3t"
+        );
+        assert_eq!(
+            expand_env_vars("https://example.com/$%7BROCKS_TEST_TOKEN%7d/foo").unwrap(),
+            "https://example.com/s3cr3t/foo"
+        );
+        assert_eq!(
+            expand_env_vars("https://example.com/$foo/${}/${1A}/${UNCLOSED").unwrap(),
+            "https://example.com/$foo/${}/${1A}/${UNCLOSED"
+        );
+        env::remove_var("ROCKS_TEST_TOKEN");
+        let err = expand_env_vars("https://example.com/${ROCKS_TEST_TOKEN}").unwrap_err();
+        assert!(!err.to_string().contains("s3cr3t"));
+        assert!(matches!(err, EnvVarError::Unset(name, _) if name == "ROCKS_TEST_TOKEN"));
+    }
+}
@@ -0,0 +1,251 @@
+//! The config file, `config.toml` in the `rocks` config directory
+//! (e.g. `$XDG_CONFIG_HOME/rocks/config.toml` or `~/.config/rocks/config.toml` on Linux).
+//! Another config file can be selected with `--config` or the `ROCKS_CONFIG` environment variable,
+//! e.g. to isolate test environments or CI. The default config file is not read in that case.
+//!
+//! Settings are applied in this order of precedence, from highest to lowest:
+//!
+//! 1. Command line flags.
+//! 2. The selected config file, or the default config file.
+//! 3. Environment variables that provide defaults, e.g. `ROCKS_NO_DEV_DEPENDENCIES`
+//!    (see [`super::env_config`]).
+//! 4. Built-in defaults.
+
+use std::{path::PathBuf, time::Duration};
+
+use directories::ProjectDirs;
+use serde::Deserialize;
+
+use crate::package::PackageReq;
+
+use super::{Config, ConfigBuilder, ConfigError};
+
+pub const CONFIG_FILE_NAME: &str = "config.toml";
+
+/// The settings that can be set in a config file.
+/// All of them are optional and correspond to the [`ConfigBuilder`] fields of the same name.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ConfigFile {
+    dev: Option<bool>,
+    server: Option<String>,
+    extra_servers: Option<Vec<String>>,
+    only_sources: Option<String>,
+    namespace: Option<String>,
+    lua_dir: Option<PathBuf>,
+    tree: Option<PathBuf>,
+    luarocks_tree: Option<PathBuf>,
+    verbose: Option<bool>,
+    /// In seconds.
+    timeout: Option<u64>,
+    /// In seconds.
+    lock_timeout: Option<u64>,
+    make: Option<String>,
+    cmake: Option<String>,
+    trusted_keys: Option<Vec<String>>,
+    require_signatures: Option<bool>,
+    check_for_updates: Option<bool>,
+    no_dev_dependencies: Option<bool>,
+    yanked: Option<Vec<PackageReq>>,
+    allow_yanked: Option<bool>,
+    cache_dir: Option<PathBuf>,
+    data_dir: Option<PathBuf>,
+}
+
+impl From<ConfigFile> for ConfigBuilder {
+    fn from(file: ConfigFile) -> Self {
+        Self {
+            enable_development_rockspecs: file.dev,
+            server: file.server,
+            extra_servers: file.extra_servers,
+            only_sources: file.only_sources,
+            namespace: file.namespace,
+            lua_dir: file.lua_dir,
+            tree: file.tree,
+            luarocks_tree: file.luarocks_tree,
+            verbose: file.verbose,
+            timeout: file.timeout.map(Duration::from_secs),
+            lock_timeout: file.lock_timeout.map(Duration::from_secs),
+            make: file.make,
+            cmake: file.cmake,
+            trusted_keys: file.trusted_keys,
+            require_signatures: file.require_signatures,
+            check_for_updates: file.check_for_updates,
+            no_dev_dependencies: file.no_dev_dependencies,
+            yanked: file.yanked,
+            allow_yanked: file.allow_yanked,
+            cache_dir: file.cache_dir,
+            data_dir: file.data_dir,
+            ..Self::default()
+        }
+    }
+}
+
+impl Config {
+    /// The default config file:
+    ///
+    /// - Linux: `$XDG_CONFIG_HOME/rocks/config.toml`, or `~/.config/rocks/config.toml`.
+    /// - macOS: `~/Library/Application Support/org.neorocks.rocks/config.toml`.
+    /// - Windows: `%APPDATA%\neorocks\rocks\config\config.toml`.
+    pub fn get_default_config_path() -> Result<PathBuf, ConfigError> {
+        Ok(super::xdg_dir("XDG_CONFIG_HOME", ProjectDirs::config_dir)?.join(CONFIG_FILE_NAME))
+    }
+}
+
+impl ConfigBuilder {
+    /// Load the config file at `path`, or, if it is `None`, at `ROCKS_CONFIG`.
+    /// If neither is set, the default config file is loaded if it exists.
+    /// Command line flags can then be applied with [`ConfigBuilder::merge`].
+    pub fn from_config_file(path: Option<PathBuf>) -> Result<Self, ConfigError> {
+        Self::from_config_file_or(path, Config::get_default_config_path()?)
+    }
+
+    fn from_config_file_or(
+        path: Option<PathBuf>,
+        default_path: PathBuf,
+    ) -> Result<Self, ConfigError> {
+        let path = path.or_else(|| std::env::var_os("ROCKS_CONFIG").map(PathBuf::from));
+        let path = match path {
+            Some(path) => path,
+            None if default_path.is_file() => default_path,
+            None => return Ok(Self::default()),
+        };
+        let content = std::fs::read_to_string(&path)?;
+        let file: ConfigFile =
+            toml::from_str(&content).map_err(|err| ConfigError::ConfigFile(path, err))?;
+        Ok(file.into())
+    }
+
+    /// Apply the settings of `overrides` that are set on top of this builder's.
+    pub fn merge(self, overrides: ConfigBuilder) -> Self {
+        Self {
+            enable_development_rockspecs: overrides
+                .enable_development_rockspecs
+                .or(self.enable_development_rockspecs),
+            server: overrides.server.or(self.server),
+            extra_servers: overrides.extra_servers.or(self.extra_servers),
+            only_sources: overrides.only_sources.or(self.only_sources),
+            namespace: overrides.namespace.or(self.namespace),
+            lua_dir: overrides.lua_dir.or(self.lua_dir),
+            lua_version: overrides.lua_version.or(self.lua_version),
+            tree: overrides.tree.or(self.tree),
+            tree_layout: overrides.tree_layout.or(self.tree_layout),
+            prefix: overrides.prefix.or(self.prefix),
+            tree_name: overrides.tree_name.or(self.tree_name),
+            target_dir: overrides.target_dir.or(self.target_dir),
+            luarocks_tree: overrides.luarocks_tree.or(self.luarocks_tree),
+            no_project: overrides.no_project.or(self.no_project),
+            verbose: overrides.verbose.or(self.verbose),
+            timeout: overrides.timeout.or(self.timeout),
+            lock_timeout: overrides.lock_timeout.or(self.lock_timeout),
+            make: overrides.make.or(self.make),
+            cmake: overrides.cmake.or(self.cmake),
+            variables: overrides.variables.or(self.variables),
+            external_deps: overrides.external_deps.or(self.external_deps),
+            trusted_keys: overrides.trusted_keys.or(self.trusted_keys),
+            require_signatures: overrides.require_signatures.or(self.require_signatures),
+            check_for_updates: overrides.check_for_updates.or(self.check_for_updates),
+            url_rewrites: overrides.url_rewrites.or(self.url_rewrites),
+            no_dev_dependencies: overrides.no_dev_dependencies.or(self.no_dev_dependencies),
+            build_profile: overrides.build_profile.or(self.build_profile),
+            platform: overrides.platform.or(self.platform),
+            message_format: overrides.message_format.or(self.message_format),
+            danger_accept_invalid_certs: overrides
+                .danger_accept_invalid_certs
+                .or(self.danger_accept_invalid_certs),
+            yanked: overrides.yanked.or(self.yanked),
+            allow_yanked: overrides.allow_yanked.or(self.allow_yanked),
+            cache_dir: overrides.cache_dir.or(self.cache_dir),
+            data_dir: overrides.data_dir.or(self.data_dir),
+            env_lua_version: overrides.env_lua_version.or(self.env_lua_version),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_fs::prelude::{FileWriteStr as _, PathChild as _};
+    use serial_test::serial;
+
+    use super::*;
+
+    #[test]
+    #[serial]
+    fn config_file_precedence() {
+        let dir = assert_fs::TempDir::new().unwrap();
+        let default_file = dir.child("default.toml");
+        default_file
+            .write_str(
+                r#"
+                server = "https://default.example.com/"
+                make = "gmake"
+                "#,
+            )
+            .unwrap();
+        let custom_file = dir.child("custom.toml");
+        custom_file
+            .write_str(
+                r#"
+                server = "https://custom.example.com/"
+                timeout = 5
+                namespace = "custom"
+                yanked = ["foo == 1.2.0"]
+                "#,
+            )
+            .unwrap();
+        let cli = ConfigBuilder::new().namespace(Some("cli".into()));
+
+        let config = ConfigBuilder::from_config_file_or(None, default_file.to_path_buf())
+            .unwrap()
+            .build()
+            .unwrap();
+        assert_eq!(config.server(), "https://default.example.com/");
+        assert_eq!(config.make_cmd(), "gmake");
+
+        let config = ConfigBuilder::from_config_file_or(
+            Some(custom_file.to_path_buf()),
+            default_file.to_path_buf(),
+        )
+        .unwrap()
+        .merge(cli)
+        .build()
+        .unwrap();
+        assert_eq!(config.server(), "https://custom.example.com/");
+        assert_eq!(config.timeout(), &Duration::from_secs(5));
+        assert_eq!(config.namespace(), "cli");
+        assert_eq!(config.yanked(), &vec!["foo == 1.2.0".parse().unwrap()]);
+        // The default config file is not read if another one is selected.
+        assert_eq!(config.make_cmd(), "make");
+
+        std::env::set_var("ROCKS_CONFIG", custom_file.path());
+        let config = ConfigBuilder::from_config_file_or(None, default_file.to_path_buf());
+        std::env::remove_var("ROCKS_CONFIG");
+        assert_eq!(
+            config.unwrap().build().unwrap().server(),
+            "https://custom.example.com/"
+        );
+
+        // Neither a selected nor a default config file.
+        let config = ConfigBuilder::from_config_file_or(None, dir.join("missing.toml"))
+            .unwrap()
+            .build()
+            .unwrap();
+        assert_eq!(config.server(), "https://luarocks.org/");
+        assert!(
+            ConfigBuilder::from_config_file_or(Some(dir.join("missing.toml")), PathBuf::new())
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn reject_unknown_settings() {
+        let dir = assert_fs::TempDir::new().unwrap();
+        let file = dir.child("config.toml");
+        file.write_str("sever = \"https://typo.example.com/\"")
+            .unwrap();
+        assert!(matches!(
+            ConfigBuilder::from_config_file_or(Some(file.to_path_buf()), PathBuf::new()),
+            Err(ConfigError::ConfigFile(..))
+        ));
+    }
+}
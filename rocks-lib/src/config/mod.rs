@@ -1,5 +1,7 @@
 use directories::ProjectDirs;
 use external_deps::ExternalDependencySearchConfig;
+use regex::Regex;
+use reqwest::Client;
 use std::{
     collections::HashMap, env, fmt::Display, io, path::PathBuf, str::FromStr, time::Duration,
 };
@@ -9,12 +11,23 @@ use crate::{
     build::{
         utils,
         variables::{self, HasVariables},
+        BuildProfile,
     },
-    package::{PackageVersion, PackageVersionReq},
+    package::{PackageReq, PackageVersion, PackageVersionReq},
+    progress::{self, MessageFormat},
     project::{Project, ProjectError},
+    rockspec::{get_platform, PlatformIdentifier},
+    signature,
+    tree::{
+        environment::{self, EnvironmentError},
+        TreeLayout, DEFAULT_LOCK_TIMEOUT,
+    },
 };
 
+pub mod env_config;
+pub mod env_vars;
 pub mod external_deps;
+pub mod file;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum LuaVersion {
@@ -35,6 +48,18 @@ pub enum LuaVersionError {
 }
 
 impl LuaVersion {
+    /// All supported Lua versions.
+    pub fn all() -> [LuaVersion; 6] {
+        [
+            LuaVersion::Lua51,
+            LuaVersion::Lua52,
+            LuaVersion::Lua53,
+            LuaVersion::Lua54,
+            LuaVersion::LuaJIT,
+            LuaVersion::LuaJIT52,
+        ]
+    }
+
     pub fn as_version(&self) -> PackageVersion {
         match self {
             LuaVersion::Lua51 => "5.1.0".parse().unwrap(),
@@ -59,6 +84,29 @@ impl LuaVersion {
             .unwrap()
     }
 
+    /// Whether this is LuaJIT, which is compatible with Lua 5.1
+    /// (or Lua 5.2, if built with `LUAJIT_ENABLE_LUA52COMPAT`).
+    pub fn is_luajit(&self) -> bool {
+        matches!(self, LuaVersion::LuaJIT | LuaVersion::LuaJIT52)
+    }
+
+    /// The name of the interpreter executable for this Lua version.
+    pub fn interpreter(&self) -> &'static str {
+        if self.is_luajit() {
+            "luajit"
+        } else {
+            "lua"
+        }
+    }
+
+    /// Whether an interpreter that reports `self` (see [`LuaVersion::from_version`])
+    /// can be used for `other`.
+    /// `luajit -v` does not tell whether LuaJIT was built with Lua 5.2 compatibility,
+    /// so any LuaJIT is considered compatible with any other.
+    pub fn is_compatible_with(&self, other: &LuaVersion) -> bool {
+        self == other || (self.is_luajit() && other.is_luajit())
+    }
+
     /// Get the LuaVersion from a version that has been parsed from the `lua -v` output
     pub fn from_version(version: PackageVersion) -> Result<LuaVersion, LuaVersionError> {
         // NOTE: Special case. luajit -v outputs 2.x.y as a version
@@ -97,7 +145,7 @@ impl DefaultFromConfig for Option<LuaVersion> {
 }
 
 #[derive(Error, Debug)]
-#[error("lua version not set! Please provide a version through `--lua-version <ver>`")]
+#[error("lua version not set! Please provide a version through `--lua-version <ver>`, a `.lua-version` file or the `ROCKS_LUA_VERSION` environment variable")]
 pub struct LuaVersionUnset;
 
 impl LuaVersion {
@@ -152,14 +200,33 @@ pub struct Config {
     lua_dir: PathBuf,
     lua_version: Option<LuaVersion>,
     tree: PathBuf,
+    tree_layout: Option<TreeLayout>,
+    prefix: Option<PathBuf>,
+    base_tree: PathBuf,
+    tree_name: String,
+    target_dir: Option<PathBuf>,
     luarocks_tree: PathBuf,
     no_project: bool,
     verbose: bool,
     timeout: Duration,
+    lock_timeout: Duration,
     make: String,
     cmake: String,
     variables: HashMap<String, String>,
     external_deps: ExternalDependencySearchConfig,
+    trusted_keys: Vec<String>,
+    require_signatures: bool,
+    check_for_updates: bool,
+    url_rewrites: Vec<(Regex, String)>,
+    no_dev_dependencies: bool,
+    build_profile: BuildProfile,
+    platform: PlatformIdentifier,
+    message_format: MessageFormat,
+    danger_accept_invalid_certs: bool,
+    yanked: Vec<PackageReq>,
+    allow_yanked: bool,
+    max_dependency_depth: Option<usize>,
+    allow_conflicts: bool,
 
     cache_dir: PathBuf,
     data_dir: PathBuf,
@@ -170,14 +237,22 @@ impl Config {
         directories::ProjectDirs::from("org", "neorocks", "rocks").ok_or(NoValidHomeDirectory)
     }
 
+    /// The default cache directory:
+    ///
+    /// - Linux: `$XDG_CACHE_HOME/rocks`, or `~/.cache/rocks`.
+    /// - macOS: `~/Library/Caches/org.neorocks.rocks`.
+    /// - Windows: `%LOCALAPPDATA%\neorocks\rocks\cache`.
     pub fn get_default_cache_path() -> Result<PathBuf, NoValidHomeDirectory> {
-        let project_dirs = Config::get_project_dirs()?;
-        Ok(project_dirs.cache_dir().to_path_buf())
+        xdg_dir("XDG_CACHE_HOME", ProjectDirs::cache_dir)
     }
 
+    /// The default data directory, which contains the default tree and the keyring:
+    ///
+    /// - Linux: `$XDG_DATA_HOME/rocks`, or `~/.local/share/rocks`.
+    /// - macOS: `~/Library/Application Support/org.neorocks.rocks`.
+    /// - Windows: `%LOCALAPPDATA%\neorocks\rocks\data`.
     pub fn get_default_data_path() -> Result<PathBuf, NoValidHomeDirectory> {
-        let project_dirs = Config::get_project_dirs()?;
-        Ok(project_dirs.data_local_dir().to_path_buf())
+        xdg_dir("XDG_DATA_HOME", ProjectDirs::data_local_dir)
     }
 
     pub fn with_lua_version(self, lua_version: LuaVersion) -> Self {
@@ -190,6 +265,48 @@ impl Config {
     pub fn with_tree(self, tree: PathBuf) -> Self {
         Self { tree, ..self }
     }
+
+    pub fn with_tree_layout(self, tree_layout: TreeLayout) -> Self {
+        Self {
+            tree_layout: Some(tree_layout),
+            ..self
+        }
+    }
+
+    pub fn with_prefix(self, prefix: PathBuf) -> Self {
+        Self {
+            prefix: Some(prefix),
+            ..self
+        }
+    }
+
+    pub fn with_no_dev_dependencies(self, no_dev_dependencies: bool) -> Self {
+        Self {
+            no_dev_dependencies,
+            ..self
+        }
+    }
+
+    pub fn with_build_profile(self, build_profile: BuildProfile) -> Self {
+        Self {
+            build_profile,
+            ..self
+        }
+    }
+
+    pub fn with_max_dependency_depth(self, max_dependency_depth: Option<usize>) -> Self {
+        Self {
+            max_dependency_depth,
+            ..self
+        }
+    }
+
+    pub fn with_allow_conflicts(self, allow_conflicts: bool) -> Self {
+        Self {
+            allow_conflicts,
+            ..self
+        }
+    }
 }
 
 impl Config {
@@ -226,11 +343,41 @@ impl Config {
         &self.tree
     }
 
+    /// The layout of [`Config::tree`].
+    /// If unset, the layout is detected from the existing tree.
+    pub fn tree_layout(&self) -> Option<&TreeLayout> {
+        self.tree_layout.as_ref()
+    }
+
+    /// A system prefix like `/usr/local` to install rocks into,
+    /// using the [`TreeLayout::Fhs`] layout.
+    /// If set, this takes precedence over [`Config::tree`].
+    pub fn prefix(&self) -> Option<&PathBuf> {
+        self.prefix.as_ref()
+    }
+
+    /// The tree that contains all named environments.
+    /// This is the tree of the `default` environment.
+    pub fn base_tree(&self) -> &PathBuf {
+        &self.base_tree
+    }
+
+    /// The name of the environment whose tree is [`Config::tree`].
+    pub fn tree_name(&self) -> &String {
+        &self.tree_name
+    }
+
     /// The tree in which to install luarocks for use as a compatibility layer
     pub fn luarocks_tree(&self) -> &PathBuf {
         &self.luarocks_tree
     }
 
+    /// The directory to install the project's rocks into,
+    /// instead of the `.rocks` directory in the project root.
+    pub fn target_dir(&self) -> Option<&PathBuf> {
+        self.target_dir.as_ref()
+    }
+
     pub fn no_project(&self) -> bool {
         self.no_project
     }
@@ -243,6 +390,11 @@ impl Config {
         &self.timeout
     }
 
+    /// How long to wait for another `rocks` process to release a tree's lock.
+    pub fn lock_timeout(&self) -> &Duration {
+        &self.lock_timeout
+    }
+
     pub fn make_cmd(&self) -> &String {
         &self.make
     }
@@ -259,6 +411,109 @@ impl Config {
         &self.external_deps
     }
 
+    /// Fingerprints (or long key IDs) of the keys that are trusted to sign rockspecs and sources,
+    /// as upper case hex digits.
+    /// If empty, all keys in the `rocks` keyring are trusted.
+    pub fn trusted_keys(&self) -> &Vec<String> {
+        &self.trusted_keys
+    }
+
+    /// Whether to fail if a rockspec or source has no signature.
+    pub fn require_signatures(&self) -> bool {
+        self.require_signatures
+    }
+
+    /// Whether to check for new releases of `rocks` once a day.
+    /// This is opt-in, as it queries the GitHub release API.
+    pub fn check_for_updates(&self) -> bool {
+        self.check_for_updates
+    }
+
+    /// Rules for rewriting source and rockspec URLs, e.g. to fetch them from a mirror.
+    /// Each rule is a pattern and its replacement, which may refer to capture groups as `$1`.
+    pub fn url_rewrites(&self) -> &Vec<(Regex, String)> {
+        &self.url_rewrites
+    }
+
+    /// Whether to skip the test and build dependencies when installing a project's dependencies,
+    /// so that only its runtime dependencies are installed, e.g. for production.
+    pub fn no_dev_dependencies(&self) -> bool {
+        self.no_dev_dependencies
+    }
+
+    /// Whether C modules are built for debugging or optimized for production.
+    /// The profile's flags are appended to the `CFLAGS` and `LIBFLAG` [`Config::variables`].
+    pub fn build_profile(&self) -> BuildProfile {
+        self.build_profile
+    }
+
+    /// The platform whose per-platform entries of rockspecs are selected,
+    /// e.g. their dependencies, build and source overrides.
+    /// This is the detected platform, unless it is overridden (e.g. with `--platform windows`),
+    /// which only affects resolution, not the target of the compiler.
+    pub fn platform(&self) -> &PlatformIdentifier {
+        &self.platform
+    }
+
+    /// How progress, warnings and errors are reported.
+    pub fn message_format(&self) -> MessageFormat {
+        self.message_format
+    }
+
+    /// The maximum depth of the dependency graph when resolving dependencies,
+    /// where the requested packages are at depth 0 and their dependencies at depth 1.
+    /// Resolution fails if a dependency is deeper than this. Unlimited if `None`.
+    pub fn max_dependency_depth(&self) -> Option<usize> {
+        self.max_dependency_depth
+    }
+
+    /// Whether a rock may overwrite files that another installed rock installed,
+    /// e.g. an executable with the same name. Installing it fails otherwise.
+    pub fn allow_conflicts(&self) -> bool {
+        self.allow_conflicts
+    }
+
+    /// Versions that are treated as yanked in addition to the ones that the manifests mark as yanked,
+    /// e.g. `foo == 1.2.0` or `foo >= 2.0.0` for a local denylist.
+    pub fn yanked(&self) -> &Vec<PackageReq> {
+        &self.yanked
+    }
+
+    /// Whether the latest match of a requirement may be a yanked version.
+    /// Otherwise, yanked versions are only installed if they are requested exactly, e.g. `foo == 1.2.0`,
+    /// as the rocks recorded in a lockfile are.
+    pub fn allow_yanked(&self) -> bool {
+        self.allow_yanked
+    }
+
+    /// Whether TLS certificates are not verified when fetching manifests, rockspecs,
+    /// sources and signatures, e.g. for mirrors with self-signed certificates.
+    /// This is never the default, and a warning is printed whenever it is used.
+    pub fn danger_accept_invalid_certs(&self) -> bool {
+        self.danger_accept_invalid_certs
+    }
+
+    /// The HTTP client for fetching manifests, rockspecs, sources and signatures.
+    /// Prints a warning if [`Config::danger_accept_invalid_certs`] is set.
+    pub fn http_client(&self) -> Client {
+        if self.danger_accept_invalid_certs {
+            warn_invalid_certs(self.message_format);
+        }
+        Client::builder()
+            .danger_accept_invalid_certs(self.danger_accept_invalid_certs)
+            .build()
+            .expect("failed to initialize the HTTP client")
+    }
+
+    /// Apply the first of the [`Config::url_rewrites`] whose pattern matches `url`.
+    /// Returns `None` if no rule matches.
+    pub fn rewrite_url(&self, url: &str) -> Option<String> {
+        self.url_rewrites
+            .iter()
+            .find(|(pattern, _)| pattern.is_match(url))
+            .map(|(pattern, replacement)| pattern.replace(url, replacement).into_owned())
+    }
+
     pub fn cache_dir(&self) -> &PathBuf {
         &self.cache_dir
     }
@@ -268,18 +523,95 @@ impl Config {
     }
 }
 
+/// The `rocks` directory in the XDG base directory `xdg_var`, if it is set to an absolute path,
+/// so that it can be used without a home directory.
+/// Otherwise, or on macOS and Windows, the platform's directory from `directories`.
+fn xdg_dir(
+    xdg_var: &str,
+    project_dir: impl FnOnce(&ProjectDirs) -> &std::path::Path,
+) -> Result<PathBuf, NoValidHomeDirectory> {
+    if cfg!(all(unix, not(target_os = "macos"))) {
+        // Relative paths are invalid according to the XDG base directory specification.
+        if let Some(dir) = env::var_os(xdg_var)
+            .map(PathBuf::from)
+            .filter(|dir| dir.is_absolute())
+        {
+            return Ok(dir.join("rocks"));
+        }
+    }
+    let project_dirs = Config::get_project_dirs()?;
+    Ok(project_dir(&project_dirs).to_path_buf())
+}
+
+#[cfg(test)]
+thread_local! {
+    static INVALID_CERTS_WARNINGS: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+}
+
+fn warn_invalid_certs(format: MessageFormat) {
+    #[cfg(test)]
+    INVALID_CERTS_WARNINGS.with(|count| count.set(count.get() + 1));
+    progress::warn(
+        format,
+        "TLS certificate verification is disabled (--insecure)! \
+        Anyone on the network path can tamper with the rocks you download.",
+    );
+}
+
 impl HasVariables for Config {
     fn substitute_variables(&self, input: &str) -> String {
-        variables::substitute(|var_name| self.variables().get(var_name).cloned(), input)
+        variables::substitute(
+            |var_name| {
+                let value = self.variables().get(var_name).cloned();
+                let profile_flags = match var_name {
+                    "CFLAGS" => self.build_profile.cflags(),
+                    "LIBFLAG" => self.build_profile.ldflags(),
+                    _ => return value,
+                };
+                match value {
+                    Some(value) if !profile_flags.is_empty() => {
+                        Some(format!("{} {}", value, profile_flags))
+                    }
+                    Some(value) => Some(value),
+                    None => Some(profile_flags.to_string()),
+                }
+            },
+            input,
+        )
+    }
+}
+
+#[cfg(feature = "lua")]
+impl mlua::UserData for Config {
+    fn add_methods<M: mlua::UserDataMethods<Self>>(methods: &mut M) {
+        use crate::tree::Tree;
+        use mlua::ExternalResult as _;
+
+        methods.add_method("tree", |_, this, ()| {
+            let version = LuaVersion::from(this).into_lua_err()?;
+            Tree::from_config(this, version).into_lua_err()
+        });
     }
 }
 
 #[derive(Error, Debug)]
-#[error(transparent)]
 pub enum ConfigError {
+    #[error(transparent)]
     Io(#[from] io::Error),
+    #[error(transparent)]
     NoValidHomeDirectory(#[from] NoValidHomeDirectory),
+    #[error(transparent)]
     Project(#[from] ProjectError),
+    #[error("invalid ROCKS_LUA_VERSION: {0}")]
+    LuaVersionEnv(String),
+    #[error("invalid {0}: {1}")]
+    EnvVar(&'static str, String),
+    #[error(transparent)]
+    Environment(#[from] EnvironmentError),
+    #[error("failed to parse config file {path}: {err}", path = .0.display(), err = .1)]
+    ConfigFile(PathBuf, toml::de::Error),
+    #[error("invalid trusted key {0:?}: expected a full fingerprint or a 16 digit long key ID")]
+    InvalidTrustedKey(String),
 }
 
 #[derive(Default)]
@@ -292,17 +624,36 @@ pub struct ConfigBuilder {
     lua_dir: Option<PathBuf>,
     lua_version: Option<LuaVersion>,
     tree: Option<PathBuf>,
+    tree_layout: Option<TreeLayout>,
+    prefix: Option<PathBuf>,
+    tree_name: Option<String>,
+    target_dir: Option<PathBuf>,
     luarocks_tree: Option<PathBuf>,
     no_project: Option<bool>,
     verbose: Option<bool>,
     timeout: Option<Duration>,
+    lock_timeout: Option<Duration>,
     make: Option<String>,
     cmake: Option<String>,
     variables: Option<HashMap<String, String>>,
     external_deps: Option<ExternalDependencySearchConfig>,
+    trusted_keys: Option<Vec<String>>,
+    require_signatures: Option<bool>,
+    check_for_updates: Option<bool>,
+    url_rewrites: Option<Vec<(Regex, String)>>,
+    no_dev_dependencies: Option<bool>,
+    build_profile: Option<BuildProfile>,
+    platform: Option<PlatformIdentifier>,
+    message_format: Option<MessageFormat>,
+    danger_accept_invalid_certs: Option<bool>,
+    yanked: Option<Vec<PackageReq>>,
+    allow_yanked: Option<bool>,
 
     cache_dir: Option<PathBuf>,
     data_dir: Option<PathBuf>,
+
+    /// `ROCKS_LUA_VERSION`, which has a lower precedence than the project's Lua version.
+    env_lua_version: Option<LuaVersion>,
 }
 
 impl ConfigBuilder {
@@ -343,6 +694,13 @@ impl ConfigBuilder {
         Self { lua_dir, ..self }
     }
 
+    /// Set the Lua version. If unset, the version is resolved when building the config,
+    /// in order of precedence, from:
+    ///
+    /// 1. The current project's `project.rockspec`
+    /// 2. A `.lua-version` file in the current project's root
+    /// 3. The `ROCKS_LUA_VERSION` environment variable, if read with [`ConfigBuilder::from_env`]
+    /// 4. The version of the `lua` binary on the `PATH`
     pub fn lua_version(self, lua_version: Option<LuaVersion>) -> Self {
         Self {
             lua_version,
@@ -354,6 +712,27 @@ impl ConfigBuilder {
         Self { tree, ..self }
     }
 
+    /// See [`Config::tree_layout`].
+    pub fn tree_layout(self, tree_layout: Option<TreeLayout>) -> Self {
+        Self {
+            tree_layout,
+            ..self
+        }
+    }
+
+    /// Install rocks into a system prefix like `/usr/local`, instead of the tree.
+    /// See [`Config::prefix`].
+    pub fn prefix(self, prefix: Option<PathBuf>) -> Self {
+        Self { prefix, ..self }
+    }
+
+    /// Select a named environment, which is an isolated tree within the base tree.
+    /// If unset, the environment selected with `rocks env use` is used,
+    /// falling back to the `default` environment.
+    pub fn tree_name(self, tree_name: Option<String>) -> Self {
+        Self { tree_name, ..self }
+    }
+
     pub fn luarocks_tree(self, luarocks_tree: Option<PathBuf>) -> Self {
         Self {
             luarocks_tree,
@@ -361,6 +740,11 @@ impl ConfigBuilder {
         }
     }
 
+    /// See [`Config::target_dir`].
+    pub fn target_dir(self, target_dir: Option<PathBuf>) -> Self {
+        Self { target_dir, ..self }
+    }
+
     pub fn no_project(self, no_project: Option<bool>) -> Self {
         Self { no_project, ..self }
     }
@@ -373,6 +757,13 @@ impl ConfigBuilder {
         Self { timeout, ..self }
     }
 
+    pub fn lock_timeout(self, lock_timeout: Option<Duration>) -> Self {
+        Self {
+            lock_timeout,
+            ..self
+        }
+    }
+
     pub fn make_cmd(self, make: Option<String>) -> Self {
         Self { make, ..self }
     }
@@ -392,6 +783,78 @@ impl ConfigBuilder {
         }
     }
 
+    pub fn trusted_keys(self, trusted_keys: Option<Vec<String>>) -> Self {
+        Self {
+            trusted_keys,
+            ..self
+        }
+    }
+
+    pub fn require_signatures(self, require_signatures: Option<bool>) -> Self {
+        Self {
+            require_signatures,
+            ..self
+        }
+    }
+
+    pub fn check_for_updates(self, check_for_updates: Option<bool>) -> Self {
+        Self {
+            check_for_updates,
+            ..self
+        }
+    }
+
+    pub fn url_rewrites(self, url_rewrites: Option<Vec<(Regex, String)>>) -> Self {
+        Self {
+            url_rewrites,
+            ..self
+        }
+    }
+
+    pub fn no_dev_dependencies(self, no_dev_dependencies: Option<bool>) -> Self {
+        Self {
+            no_dev_dependencies,
+            ..self
+        }
+    }
+
+    pub fn build_profile(self, build_profile: Option<BuildProfile>) -> Self {
+        Self {
+            build_profile,
+            ..self
+        }
+    }
+
+    pub fn platform(self, platform: Option<PlatformIdentifier>) -> Self {
+        Self { platform, ..self }
+    }
+
+    pub fn message_format(self, message_format: Option<MessageFormat>) -> Self {
+        Self {
+            message_format,
+            ..self
+        }
+    }
+
+    /// Disable TLS certificate verification. Use with care.
+    pub fn danger_accept_invalid_certs(self, danger_accept_invalid_certs: Option<bool>) -> Self {
+        Self {
+            danger_accept_invalid_certs,
+            ..self
+        }
+    }
+
+    pub fn yanked(self, yanked: Option<Vec<PackageReq>>) -> Self {
+        Self { yanked, ..self }
+    }
+
+    pub fn allow_yanked(self, allow_yanked: Option<bool>) -> Self {
+        Self {
+            allow_yanked,
+            ..self
+        }
+    }
+
     pub fn cache_dir(self, cache_dir: Option<PathBuf>) -> Self {
         Self { cache_dir, ..self }
     }
@@ -404,14 +867,38 @@ impl ConfigBuilder {
         let data_dir = self.data_dir.unwrap_or(Config::get_default_data_path()?);
         let cache_dir = self.cache_dir.unwrap_or(Config::get_default_cache_path()?);
         let current_project = Project::current()?;
-        let lua_version = self
-            .lua_version
-            .or(current_project
-                .as_ref()
-                .and_then(|project| project.rockspec().lua_version()))
-            .or(crate::lua_installation::get_installed_lua_version("lua")
-                .ok()
-                .and_then(|version| LuaVersion::from_version(version).ok()));
+        let lua_version = resolve_lua_version(
+            self.lua_version,
+            current_project.as_ref(),
+            self.env_lua_version,
+        )?;
+        let target_dir = self.target_dir;
+        let base_tree = self
+            .tree
+            .or_else(|| {
+                if self.no_project.unwrap_or(false) {
+                    None
+                } else {
+                    current_project.as_ref().map(|project| {
+                        target_dir
+                            .clone()
+                            .unwrap_or_else(|| project.default_tree_root_dir())
+                    })
+                }
+            })
+            .unwrap_or_else(|| data_dir.join("tree"));
+        let tree_name = match self.tree_name {
+            Some(tree_name) => tree_name,
+            None => environment::selected_environment(&base_tree)?
+                .unwrap_or_else(|| environment::DEFAULT_ENVIRONMENT.to_string()),
+        };
+        let tree = environment::environment_tree(&base_tree, &tree_name)?;
+        let trusted_keys = self
+            .trusted_keys
+            .unwrap_or_default()
+            .into_iter()
+            .map(|key| signature::normalize_key_id(&key).ok_or(ConfigError::InvalidTrustedKey(key)))
+            .collect::<Result<_, _>>()?;
         let default_variables = vec![
             ("LUA", "lua"),
             ("LIB_EXTENSION", utils::lua_lib_extension()),
@@ -437,28 +924,265 @@ impl ConfigBuilder {
             namespace: self.namespace.unwrap_or_default(),
             lua_dir: self.lua_dir.unwrap_or_else(|| data_dir.join("lua")),
             lua_version,
-            tree: self
-                .tree
-                .or_else(|| {
-                    if self.no_project.unwrap_or(false) {
-                        None
-                    } else {
-                        current_project
-                            .as_ref()
-                            .map(|project| project.root().join(".rocks"))
-                    }
-                })
-                .unwrap_or_else(|| data_dir.join("tree")),
+            tree,
+            tree_layout: self.tree_layout,
+            prefix: self.prefix,
+            base_tree,
+            tree_name,
+            target_dir,
             luarocks_tree: self.luarocks_tree.unwrap_or(data_dir.join(".luarocks")),
             no_project: self.no_project.unwrap_or(false),
             verbose: self.verbose.unwrap_or(false),
             timeout: self.timeout.unwrap_or_else(|| Duration::from_secs(30)),
+            lock_timeout: self.lock_timeout.unwrap_or(DEFAULT_LOCK_TIMEOUT),
             make: self.make.unwrap_or("make".into()),
             cmake: self.cmake.unwrap_or("cmake".into()),
             variables: self.variables.unwrap_or(default_variables),
             external_deps: self.external_deps.unwrap_or_default(),
+            trusted_keys,
+            require_signatures: self.require_signatures.unwrap_or(false),
+            check_for_updates: self.check_for_updates.unwrap_or(false),
+            url_rewrites: self.url_rewrites.unwrap_or_default(),
+            no_dev_dependencies: self.no_dev_dependencies.unwrap_or(false),
+            build_profile: self.build_profile.unwrap_or_default(),
+            platform: self.platform.unwrap_or_else(get_platform),
+            message_format: self.message_format.unwrap_or_default(),
+            danger_accept_invalid_certs: self.danger_accept_invalid_certs.unwrap_or(false),
+            yanked: self.yanked.unwrap_or_default(),
+            allow_yanked: self.allow_yanked.unwrap_or(false),
+            max_dependency_depth: None,
+            allow_conflicts: false,
             cache_dir,
             data_dir,
         })
     }
 }
+
+fn resolve_lua_version(
+    lua_version: Option<LuaVersion>,
+    project: Option<&Project>,
+    env_lua_version: Option<LuaVersion>,
+) -> Result<Option<LuaVersion>, ConfigError> {
+    if lua_version.is_some() {
+        return Ok(lua_version);
+    }
+    if let Some(project) = project {
+        if let Some(lua_version) = project.rockspec().lua_version() {
+            return Ok(Some(lua_version));
+        }
+        if let Some(lua_version) = project.lua_version_file()? {
+            return Ok(Some(lua_version));
+        }
+    }
+    if env_lua_version.is_some() {
+        return Ok(env_lua_version);
+    }
+    Ok(["lua", "luajit"].into_iter().find_map(|lua_cmd| {
+        crate::lua_installation::get_installed_lua_version(lua_cmd)
+            .ok()
+            .and_then(|version| LuaVersion::from_version(version).ok())
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_fs::prelude::{FileWriteStr, PathChild};
+    use serial_test::serial;
+
+    use super::*;
+
+    fn project_with(
+        rockspec_dependencies: &str,
+        lua_version_file: Option<&str>,
+    ) -> (assert_fs::TempDir, Project) {
+        let project_root = assert_fs::TempDir::new().unwrap();
+        project_root
+            .child("project.rockspec")
+            .write_str(&format!(
+                r#"
+                rockspec_format = "3.0"
+                package = "foo"
+                version = "1.0.0-1"
+                source = {{ url = "https://github.com/nvim-neorocks/luarocks-stub" }}
+                dependencies = {{ {} }}
+                "#,
+                rockspec_dependencies
+            ))
+            .unwrap();
+        if let Some(content) = lua_version_file {
+            project_root
+                .child(".lua-version")
+                .write_str(content)
+                .unwrap();
+        }
+        let project = Project::from(project_root.path()).unwrap().unwrap();
+        (project_root, project)
+    }
+
+    #[test]
+    #[serial]
+    fn lua_version_flag_overrides_project_rockspec() {
+        let (_project_root, project) = project_with(r#""lua == 5.3""#, Some("5.2"));
+        let lua_version =
+            resolve_lua_version(Some(LuaVersion::Lua54), Some(&project), None).unwrap();
+        assert_eq!(lua_version, Some(LuaVersion::Lua54));
+    }
+
+    #[test]
+    #[serial]
+    fn project_rockspec_overrides_lua_version_file() {
+        let (_project_root, project) = project_with(r#""lua == 5.3""#, Some("5.2"));
+        let lua_version = resolve_lua_version(None, Some(&project), None).unwrap();
+        assert_eq!(lua_version, Some(LuaVersion::Lua53));
+    }
+
+    #[test]
+    #[serial]
+    fn lua_version_file_overrides_env() {
+        let (_project_root, project) = project_with("", Some("luajit\n"));
+        let lua_version = resolve_lua_version(None, Some(&project), Some(LuaVersion::Lua51));
+        assert_eq!(lua_version.unwrap(), Some(LuaVersion::LuaJIT));
+    }
+
+    #[test]
+    #[serial]
+    fn env_overrides_installed_lua() {
+        let (_project_root, project) = project_with("", None);
+        let lua_version = resolve_lua_version(None, Some(&project), Some(LuaVersion::Lua52));
+        assert_eq!(lua_version.unwrap(), Some(LuaVersion::Lua52));
+    }
+
+    #[test]
+    #[serial]
+    fn invalid_lua_version_file() {
+        let (_project_root, project) = project_with("", Some("5.9"));
+        assert!(matches!(
+            resolve_lua_version(None, Some(&project), None),
+            Err(ConfigError::Project(ProjectError::LuaVersionFile(_)))
+        ));
+    }
+
+    #[test]
+    fn rewrite_url() {
+        let config = ConfigBuilder::new()
+            .url_rewrites(Some(vec![
+                (
+                    Regex::new("^https://github.com/(.*)").unwrap(),
+                    "https://mirror.corp/github/$1".into(),
+                ),
+                (
+                    Regex::new("^https://luarocks.org/").unwrap(),
+                    "https://mirror.corp/luarocks/".into(),
+                ),
+                (
+                    Regex::new("^https://github.com/").unwrap(),
+                    "https://unused.corp/".into(),
+                ),
+            ]))
+            .build()
+            .unwrap();
+        assert_eq!(
+            config.rewrite_url("https://github.com/nvim-neorg/neorg/archive/v8.0.0.zip"),
+            Some("https://mirror.corp/github/nvim-neorg/neorg/archive/v8.0.0.zip".into())
+        );
+        assert_eq!(
+            config.rewrite_url("https://luarocks.org/neorg-8.0.0-1.rockspec"),
+            Some("https://mirror.corp/luarocks/neorg-8.0.0-1.rockspec".into())
+        );
+        assert_eq!(config.rewrite_url("https://gitlab.com/foo/bar.git"), None);
+    }
+
+    #[test]
+    fn build_profile_flags() {
+        let config = ConfigBuilder::new()
+            .variables(Some(HashMap::from([
+                ("CFLAGS".into(), "-fPIC".into()),
+                ("LIBFLAG".into(), "-shared".into()),
+                ("LUA".into(), "lua".into()),
+            ])))
+            .build()
+            .unwrap();
+        assert_eq!(config.build_profile(), BuildProfile::Release);
+        // Only `CFLAGS` and `LIBFLAG` depend on the profile.
+        assert_eq!(config.substitute_variables("$(LUA)"), "lua");
+        let release_cflags = config.substitute_variables("$(CFLAGS)");
+        let debug_cflags = config
+            .clone()
+            .with_build_profile(BuildProfile::Debug)
+            .substitute_variables("$(CFLAGS)");
+        assert_ne!(release_cflags, debug_cflags);
+        assert!(release_cflags.starts_with("-fPIC "));
+        assert!(debug_cflags.starts_with("-fPIC "));
+        if cfg!(not(target_os = "windows")) {
+            assert!(release_cflags.contains("-DNDEBUG"));
+            assert!(!release_cflags.contains("-g"));
+            assert!(debug_cflags.contains("-g"));
+            assert_eq!(
+                config.substitute_variables("$(LIBFLAG)"),
+                format!("-shared {}", BuildProfile::Release.ldflags())
+            );
+        }
+    }
+
+    #[test]
+    #[serial]
+    #[cfg(all(unix, not(target_os = "macos")))]
+    fn xdg_base_directories() {
+        let dir = assert_fs::TempDir::new().unwrap();
+        env::set_var("XDG_CACHE_HOME", dir.join("cache"));
+        env::set_var("XDG_DATA_HOME", dir.join("data"));
+        env::set_var("XDG_CONFIG_HOME", dir.join("config"));
+        let config = ConfigBuilder::new().build();
+        let config_path = Config::get_default_config_path();
+        // Relative paths are ignored.
+        env::set_var("XDG_CACHE_HOME", "relative/cache");
+        let fallback_cache_path = Config::get_default_cache_path();
+        env::remove_var("XDG_CACHE_HOME");
+        env::remove_var("XDG_DATA_HOME");
+        env::remove_var("XDG_CONFIG_HOME");
+
+        let config = config.unwrap();
+        assert_eq!(config.cache_dir(), &dir.join("cache").join("rocks"));
+        assert_eq!(config.data_dir(), &dir.join("data").join("rocks"));
+        assert_eq!(
+            config_path.unwrap(),
+            dir.join("config").join("rocks").join("config.toml")
+        );
+        assert!(fallback_cache_path.is_ok_and(|path| path.is_absolute()));
+    }
+
+    #[test]
+    fn warn_on_invalid_certs() {
+        let warnings = || INVALID_CERTS_WARNINGS.with(|count| count.get());
+        let config = ConfigBuilder::new().build().unwrap();
+        assert!(!config.danger_accept_invalid_certs());
+        config.http_client();
+        assert_eq!(warnings(), 0);
+
+        let config = ConfigBuilder::new()
+            .danger_accept_invalid_certs(Some(true))
+            .build()
+            .unwrap();
+        config.http_client();
+        config.http_client();
+        assert_eq!(warnings(), 2);
+    }
+
+    #[test]
+    fn reject_short_trusted_keys() {
+        let config = ConfigBuilder::new()
+            .trusted_keys(Some(vec!["0x1234567890abcdef".into()]))
+            .build()
+            .unwrap();
+        assert_eq!(config.trusted_keys(), &vec!["1234567890ABCDEF".to_string()]);
+
+        for key in ["", "90ABCDEF"] {
+            assert!(matches!(
+                ConfigBuilder::new()
+                    .trusted_keys(Some(vec![key.into()]))
+                    .build(),
+                Err(ConfigError::InvalidTrustedKey(_))
+            ));
+        }
+    }
+}
@@ -1,3 +1,4 @@
+use itertools::Itertools;
 use ssri::{Algorithm, Integrity, IntegrityOpts};
 use std::fs::File;
 use std::io::{self, Read};
@@ -6,14 +7,25 @@ use tempdir::TempDir;
 use walkdir::WalkDir;
 
 pub trait HasIntegrity {
-    fn hash(&self) -> io::Result<Integrity>;
+    /// The sha256 integrity, as recorded in lockfiles.
+    fn hash(&self) -> io::Result<Integrity> {
+        self.hash_with(&[Algorithm::Sha256])
+    }
+
+    /// The integrity, with a hash for each of the given `algorithms`.
+    fn hash_with(&self, algorithms: &[Algorithm]) -> io::Result<Integrity>;
 }
 
 impl HasIntegrity for PathBuf {
-    fn hash(&self) -> io::Result<Integrity> {
-        let mut integrity_opts = IntegrityOpts::new().algorithm(Algorithm::Sha256);
+    fn hash_with(&self, algorithms: &[Algorithm]) -> io::Result<Integrity> {
+        let mut integrity_opts = integrity_opts(algorithms);
         if self.is_dir() {
-            for entry in WalkDir::new(self) {
+            // The `.git` directory of a git checkout differs between clones,
+            // so it's not part of the source.
+            for entry in WalkDir::new(self)
+                .into_iter()
+                .filter_entry(|entry| entry.file_name() != ".git")
+            {
                 let entry = entry?;
                 if entry.file_type().is_file() {
                     hash_file(entry.path(), &mut integrity_opts)?;
@@ -27,18 +39,58 @@ impl HasIntegrity for PathBuf {
 }
 
 impl HasIntegrity for Path {
-    fn hash(&self) -> io::Result<Integrity> {
+    fn hash_with(&self, algorithms: &[Algorithm]) -> io::Result<Integrity> {
         let path_buf: PathBuf = self.into();
-        path_buf.hash()
+        path_buf.hash_with(algorithms)
     }
 }
 
 impl HasIntegrity for TempDir {
-    fn hash(&self) -> io::Result<Integrity> {
-        self.path().hash()
+    fn hash_with(&self, algorithms: &[Algorithm]) -> io::Result<Integrity> {
+        self.path().hash_with(algorithms)
     }
 }
 
+pub(crate) fn integrity_opts(algorithms: &[Algorithm]) -> IntegrityOpts {
+    algorithms
+        .iter()
+        .fold(IntegrityOpts::new(), |opts, algorithm| {
+            opts.algorithm(*algorithm)
+        })
+}
+
+/// The algorithms of the hashes in `integrity`, without duplicates.
+pub fn algorithms(integrity: &Integrity) -> Vec<Algorithm> {
+    integrity
+        .hashes
+        .iter()
+        .map(|hash| hash.algorithm)
+        .sorted()
+        .dedup()
+        .collect()
+}
+
+/// Compare `actual` with `expected` using the strongest algorithm that both have hashes for,
+/// returning that algorithm if they match.
+///
+/// Unlike [`Integrity::matches`], which only considers the first algorithm of `actual`,
+/// this works for integrities with multiple algorithms, e.g. `sha256-... sha512-...`.
+pub fn integrity_matches(expected: &Integrity, actual: &Integrity) -> Option<Algorithm> {
+    // `Algorithm`s are ordered from strongest to weakest.
+    let algorithm = algorithms(expected).into_iter().find(|algorithm| {
+        actual
+            .hashes
+            .iter()
+            .any(|hash| hash.algorithm == *algorithm)
+    })?;
+    expected
+        .hashes
+        .iter()
+        .filter(|hash| hash.algorithm == algorithm)
+        .any(|hash| actual.hashes.contains(hash))
+        .then_some(algorithm)
+}
+
 fn hash_file(path: &Path, integrity_opts: &mut IntegrityOpts) -> io::Result<()> {
     let mut file = File::open(path)?;
     let mut buffer = Vec::new();
@@ -46,3 +98,67 @@ fn hash_file(path: &Path, integrity_opts: &mut IntegrityOpts) -> io::Result<()>
     integrity_opts.input(&buffer);
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use assert_fs::prelude::{FileWriteStr as _, PathChild as _};
+
+    use super::*;
+
+    #[test]
+    fn sha512_integrity_matches() {
+        let dir = assert_fs::TempDir::new().unwrap();
+        let file = dir.child("foo.lua");
+        file.write_str("return {}").unwrap();
+        let expected = IntegrityOpts::new()
+            .algorithm(Algorithm::Sha512)
+            .chain("return {}")
+            .result();
+        let actual = file.path().hash_with(&algorithms(&expected)).unwrap();
+        assert_eq!(
+            integrity_matches(&expected, &actual),
+            Some(Algorithm::Sha512)
+        );
+        // A sha256 hash can't be compared with a sha512 hash.
+        assert_eq!(
+            integrity_matches(&expected, &file.path().hash().unwrap()),
+            None
+        );
+        let tampered = IntegrityOpts::new()
+            .algorithm(Algorithm::Sha512)
+            .chain("return nil")
+            .result();
+        assert_eq!(integrity_matches(&expected, &tampered), None);
+    }
+
+    #[test]
+    fn multi_hash_integrity_matches_strongest_algorithm() {
+        let content = "return {}";
+        let sha256 = Integrity::from(content);
+        let sha512 = IntegrityOpts::new()
+            .algorithm(Algorithm::Sha512)
+            .chain(content)
+            .result();
+        let expected = sha256.concat(sha512.clone());
+        let actual = integrity_opts(&algorithms(&expected))
+            .chain(content)
+            .result();
+        assert_eq!(
+            integrity_matches(&expected, &actual),
+            Some(Algorithm::Sha512)
+        );
+        assert_eq!(
+            integrity_matches(&expected, &Integrity::from(content)),
+            Some(Algorithm::Sha256)
+        );
+        // The strongest common algorithm decides, even if a weaker one matches.
+        let wrong_sha512 = IntegrityOpts::new()
+            .algorithm(Algorithm::Sha512)
+            .chain("return nil")
+            .result();
+        assert_eq!(
+            integrity_matches(&Integrity::from(content).concat(wrong_sha512), &actual),
+            None
+        );
+    }
+}
@@ -3,6 +3,7 @@ pub mod config;
 pub mod hash;
 pub mod lockfile;
 pub mod lua_installation;
+pub mod luarc;
 pub mod luarocks_installation;
 pub mod manifest;
 pub mod operations;
@@ -12,6 +13,8 @@ pub mod progress;
 pub mod project;
 pub mod remote_package_db;
 pub mod rockspec;
+pub mod self_update;
+pub mod signature;
 pub mod tree;
 pub mod upload;
 
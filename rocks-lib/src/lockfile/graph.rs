@@ -0,0 +1,162 @@
+use std::collections::HashMap;
+
+use itertools::Itertools;
+
+use super::{LocalPackage, LocalPackageId, Lockfile, PinnedState};
+
+/// A text format for rendering the dependency graph of a lockfile.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+#[cfg_attr(feature = "clap", clap(rename_all = "lowercase"))]
+pub enum GraphFormat {
+    /// A Graphviz `digraph`.
+    #[default]
+    Dot,
+    /// A Mermaid `flowchart`.
+    Mermaid,
+}
+
+impl Lockfile {
+    /// Render the rocks in this lockfile and their dependencies as a graph.
+    /// Entrypoints are drawn in bold and pinned rocks are highlighted.
+    pub fn to_graph(&self, format: GraphFormat) -> String {
+        let rocks = self
+            .rocks
+            .values()
+            .sorted_by(|a, b| a.name().cmp(b.name()).then(a.version().cmp(b.version())))
+            .collect_vec();
+        // Lockfile IDs are long hashes, so the nodes get short, stable names instead.
+        let node_ids: HashMap<LocalPackageId, String> = rocks
+            .iter()
+            .enumerate()
+            .map(|(index, rock)| (rock.id(), format!("n{index}")))
+            .collect();
+        let entrypoints = self
+            .entrypoints()
+            .into_iter()
+            .map(|rock| rock.id())
+            .collect_vec();
+
+        let mut lines = Vec::new();
+        match format {
+            GraphFormat::Dot => lines.push("digraph {".to_string()),
+            GraphFormat::Mermaid => lines.push("flowchart TD".to_string()),
+        }
+
+        for rock in &rocks {
+            let node_id = &node_ids[&rock.id()];
+            let label = label(rock);
+            let is_entrypoint = entrypoints.contains(&rock.id());
+            let is_pinned = rock.pinned() == PinnedState::Pinned;
+            lines.push(match format {
+                GraphFormat::Dot => {
+                    let mut attributes = vec![format!("label=\"{label}\"")];
+                    if is_entrypoint {
+                        attributes.push("style=bold".into());
+                    }
+                    if is_pinned {
+                        attributes.push("color=blue".into());
+                    }
+                    format!("    {node_id} [{}];", attributes.join(", "))
+                }
+                GraphFormat::Mermaid => {
+                    let label = if is_entrypoint {
+                        format!("<b>{label}</b>")
+                    } else {
+                        label
+                    };
+                    let class = if is_pinned { ":::pinned" } else { "" };
+                    format!("    {node_id}[\"{label}\"]{class}")
+                }
+            });
+        }
+
+        for rock in &rocks {
+            for dependency in rock
+                .dependencies()
+                .into_iter()
+                .filter_map(|id| node_ids.get(id))
+                .sorted()
+            {
+                let node_id = &node_ids[&rock.id()];
+                lines.push(match format {
+                    GraphFormat::Dot => format!("    {node_id} -> {dependency};"),
+                    GraphFormat::Mermaid => format!("    {node_id} --> {dependency}"),
+                });
+            }
+        }
+
+        match format {
+            GraphFormat::Dot => lines.push("}".to_string()),
+            GraphFormat::Mermaid => {
+                if rocks
+                    .iter()
+                    .any(|rock| rock.pinned() == PinnedState::Pinned)
+                {
+                    lines.push("    classDef pinned stroke:blue".to_string());
+                }
+            }
+        }
+
+        lines.join("\n")
+    }
+}
+
+fn label(rock: &LocalPackage) -> String {
+    format!("{}@{}", rock.name(), rock.version())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lockfile::test_package;
+
+    use super::*;
+
+    fn lockfile() -> Lockfile {
+        let package = |name: &str, version: &str, pinned: PinnedState| {
+            let mut package = test_package(name, version);
+            package.spec.pinned = pinned;
+            package
+        };
+        let neorg = package("neorg", "8.0.0-1", PinnedState::Unpinned);
+        let lua_utils = package("lua-utils.nvim", "1.0.2-1", PinnedState::Pinned);
+        let pathlib = package("pathlib.nvim", "2.2.3-1", PinnedState::Unpinned);
+
+        let mut lockfile = Lockfile::default();
+        lockfile.add(&neorg);
+        lockfile.add_dependency(&neorg, &lua_utils);
+        lockfile.add_dependency(&neorg, &pathlib);
+        lockfile.add_dependency(&pathlib, &lua_utils);
+        lockfile
+    }
+
+    #[test]
+    fn dot_graph() {
+        assert_eq!(
+            lockfile().to_graph(GraphFormat::Dot),
+            r#"digraph {
+    n0 [label="lua-utils.nvim@1.0.2-1", color=blue];
+    n1 [label="neorg@8.0.0-1", style=bold];
+    n2 [label="pathlib.nvim@2.2.3-1"];
+    n1 -> n0;
+    n1 -> n2;
+    n2 -> n0;
+}"#
+        );
+    }
+
+    #[test]
+    fn mermaid_graph() {
+        assert_eq!(
+            lockfile().to_graph(GraphFormat::Mermaid),
+            r#"flowchart TD
+    n0["lua-utils.nvim@1.0.2-1"]:::pinned
+    n1["<b>neorg@8.0.0-1</b>"]
+    n2["pathlib.nvim@2.2.3-1"]
+    n1 --> n0
+    n1 --> n2
+    n2 --> n0
+    classDef pinned stroke:blue"#
+        );
+    }
+}
@@ -1,6 +1,15 @@
+mod graph;
+
+pub use graph::GraphFormat;
+
 use std::fmt::Display;
 use std::io::{self, Write};
-use std::{collections::HashMap, fs::File, io::ErrorKind, path::PathBuf};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fs::File,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
 
 use itertools::Itertools;
 use serde::{de, Deserialize, Serialize};
@@ -9,8 +18,11 @@ use ssri::Integrity;
 use thiserror::Error;
 
 use crate::package::{
-    PackageName, PackageReq, PackageSpec, PackageVersion, PackageVersionReq, PackageVersionReqError,
+    PackageName, PackageNamespace, PackageReq, PackageSpec, PackageVersion, PackageVersionReq,
+    PackageVersionReqError,
 };
+use crate::progress::{Event, LockfileChangeKind};
+use crate::tree::TreeLock;
 
 #[cfg(feature = "lua")]
 use mlua::{ExternalResult as _, FromLua};
@@ -63,12 +75,17 @@ impl<'de> Deserialize<'de> for PinnedState {
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub(crate) struct LocalPackageSpec {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub namespace: Option<PackageNamespace>,
     pub name: PackageName,
     pub version: PackageVersion,
     pub pinned: PinnedState,
     pub dependencies: Vec<LocalPackageId>,
     // TODO: Deserialize this directly into a `LuaPackageReq`
     pub constraint: Option<String>,
+    /// The features that were enabled, i.e. the names of the installed optional dependencies.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub features: Vec<PackageName>,
 }
 
 #[derive(Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize, Clone)]
@@ -76,6 +93,7 @@ pub struct LocalPackageId(String);
 
 impl LocalPackageId {
     pub fn new(
+        namespace: Option<&PackageNamespace>,
         name: &PackageName,
         version: &PackageVersion,
         pinned: PinnedState,
@@ -84,7 +102,10 @@ impl LocalPackageId {
         let mut hasher = Sha256::new();
 
         hasher.update(format!(
-            "{}{}{}{}",
+            "{}{}{}{}{}",
+            namespace
+                .map(|namespace| format!("{}/", namespace))
+                .unwrap_or_default(),
             name,
             version,
             pinned.as_bool(),
@@ -113,6 +134,7 @@ impl LocalPackageSpec {
         pinned: &PinnedState,
     ) -> Self {
         Self {
+            namespace: None,
             name: name.clone(),
             version: version.clone(),
             pinned: *pinned,
@@ -121,11 +143,21 @@ impl LocalPackageSpec {
                 LockConstraint::Unconstrained => None,
                 LockConstraint::Constrained(version_req) => Some(version_req.to_string()),
             },
+            features: Vec::new(),
         }
     }
 
+    pub fn with_namespace(self, namespace: Option<PackageNamespace>) -> Self {
+        Self { namespace, ..self }
+    }
+
+    pub fn with_features(self, features: Vec<PackageName>) -> Self {
+        Self { features, ..self }
+    }
+
     pub fn id(&self) -> LocalPackageId {
         LocalPackageId::new(
+            self.namespace(),
             self.name(),
             self.version(),
             self.pinned,
@@ -145,6 +177,10 @@ impl LocalPackageSpec {
         &self.name
     }
 
+    pub fn namespace(&self) -> Option<&PackageNamespace> {
+        self.namespace.as_ref()
+    }
+
     pub fn version(&self) -> &PackageVersion {
         &self.version
     }
@@ -157,31 +193,60 @@ impl LocalPackageSpec {
         self.dependencies.iter().collect()
     }
 
+    pub fn features(&self) -> &[PackageName] {
+        &self.features
+    }
+
     pub fn to_package(&self) -> PackageSpec {
         PackageSpec::new(self.name.clone(), self.version.clone())
     }
 
     pub fn into_package_req(self) -> PackageReq {
-        PackageSpec::new(self.name, self.version).into_package_req()
+        PackageSpec::new(self.name, self.version)
+            .into_package_req()
+            .with_namespace(self.namespace)
     }
 }
 
+/// Where a package that was not installed from a rocks server was fetched from.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum RemotePackageSourceUrl {
+    Git {
+        url: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        checkout_ref: Option<String>,
+        /// The commit that was checked out, so that the installation can be reproduced.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        commit: Option<String>,
+    },
+    /// A packed source rock (`.src.rock`) on the local file system.
+    File { path: PathBuf },
+}
+
 // TODO(vhyrro): Move to `package/local.rs`
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub struct LocalPackage {
     pub(crate) spec: LocalPackageSpec,
     hashes: LocalPackageHashes,
+    source: Option<RemotePackageSourceUrl>,
 }
 
 #[cfg_attr(feature = "lua", derive(FromLua,))]
 #[derive(Debug, Serialize, Deserialize, Clone)]
 struct LocalPackageIntermediate {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    namespace: Option<PackageNamespace>,
     name: PackageName,
     version: PackageVersion,
     pinned: PinnedState,
     dependencies: Vec<LocalPackageId>,
     constraint: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    features: Vec<PackageName>,
     hashes: LocalPackageHashes,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    source: Option<RemotePackageSourceUrl>,
 }
 
 impl TryFrom<LocalPackageIntermediate> for LocalPackage {
@@ -196,8 +261,11 @@ impl TryFrom<LocalPackageIntermediate> for LocalPackage {
                 constraint,
                 value.dependencies,
                 &value.pinned,
-            ),
+            )
+            .with_namespace(value.namespace)
+            .with_features(value.features),
             hashes: value.hashes,
+            source: value.source,
         })
     }
 }
@@ -205,12 +273,15 @@ impl TryFrom<LocalPackageIntermediate> for LocalPackage {
 impl From<&LocalPackage> for LocalPackageIntermediate {
     fn from(value: &LocalPackage) -> Self {
         Self {
+            namespace: value.spec.namespace.clone(),
             name: value.spec.name.clone(),
             version: value.spec.version.clone(),
             pinned: value.spec.pinned,
             dependencies: value.spec.dependencies.clone(),
             constraint: value.spec.constraint.clone(),
+            features: value.spec.features.clone(),
             hashes: value.hashes.clone(),
+            source: value.source.clone(),
         }
     }
 }
@@ -237,6 +308,13 @@ impl Serialize for LocalPackage {
 #[cfg(feature = "lua")]
 impl FromLua for LocalPackage {
     fn from_lua(value: mlua::Value, lua: &mlua::Lua) -> mlua::Result<Self> {
+        // Packages that were passed to Lua, e.g. by `Tree:installed_rocks()`.
+        if let Some(package) = value
+            .as_userdata()
+            .and_then(|userdata| userdata.borrow::<Self>().ok())
+        {
+            return Ok(package.clone());
+        }
         LocalPackage::try_from(LocalPackageIntermediate::from_lua(value, lua)?)
             .map_err(|err| mlua::Error::DeserializeError(format!("{}", err)))
     }
@@ -257,6 +335,20 @@ impl LocalPackage {
                 &PinnedState::Unpinned,
             ),
             hashes,
+            source: None,
+        }
+    }
+
+    /// Record where the package was fetched from, if not from a rocks server.
+    pub fn with_source(self, source: Option<RemotePackageSourceUrl>) -> Self {
+        Self { source, ..self }
+    }
+
+    /// Record the features that were enabled when installing the package.
+    pub fn with_features(self, features: Vec<PackageName>) -> Self {
+        Self {
+            spec: self.spec.with_features(features),
+            ..self
         }
     }
 
@@ -268,6 +360,10 @@ impl LocalPackage {
         self.spec.name()
     }
 
+    pub fn namespace(&self) -> Option<&PackageNamespace> {
+        self.spec.namespace()
+    }
+
     pub fn version(&self) -> &PackageVersion {
         self.spec.version()
     }
@@ -280,6 +376,10 @@ impl LocalPackage {
         self.spec.dependencies()
     }
 
+    pub fn features(&self) -> &[PackageName] {
+        self.spec.features()
+    }
+
     pub fn constraint(&self) -> LockConstraint {
         self.spec.constraint()
     }
@@ -288,6 +388,10 @@ impl LocalPackage {
         &self.hashes
     }
 
+    pub fn source(&self) -> Option<&RemotePackageSourceUrl> {
+        self.source.as_ref()
+    }
+
     pub fn to_package(&self) -> PackageSpec {
         self.spec.to_package()
     }
@@ -301,6 +405,9 @@ impl LocalPackage {
 impl mlua::UserData for LocalPackage {
     fn add_fields<F: mlua::UserDataFields<Self>>(fields: &mut F) {
         fields.add_field_method_get("name", |_, this| Ok(this.name().to_string()));
+        fields.add_field_method_get("namespace", |_, this| {
+            Ok(this.namespace().map(|namespace| namespace.to_string()))
+        });
         fields.add_field_method_get("version", |_, this| Ok(this.version().to_string()));
         fields.add_field_method_get("pinned", |_, this| Ok(this.pinned().as_bool()));
         fields.add_field_method_get("dependencies", |_, this| {
@@ -312,6 +419,13 @@ impl mlua::UserData for LocalPackage {
                 .collect_vec())
         });
         fields.add_field_method_get("constraint", |_, this| Ok(this.spec.constraint.clone()));
+        fields.add_field_method_get("features", |_, this| {
+            Ok(this
+                .features()
+                .iter()
+                .map(|feature| feature.to_string())
+                .collect_vec())
+        });
         fields.add_field_method_get("id", |_, this| Ok(this.id().0));
     }
 
@@ -343,6 +457,22 @@ impl PartialOrd for LocalPackageHashes {
     }
 }
 
+/// An unconstrained package with placeholder hashes, for tests that don't verify them.
+#[cfg(test)]
+pub(crate) fn test_package(name: &str, version: &str) -> LocalPackage {
+    let hash: Integrity = "sha256-uU0nuZNNPgilLlLX2n2r+sSE7+N6U4DukIj3rOLvzek="
+        .parse()
+        .unwrap();
+    LocalPackage::from(
+        &PackageSpec::parse(name.into(), version.into()).unwrap(),
+        LockConstraint::Unconstrained,
+        LocalPackageHashes {
+            rockspec: hash.clone(),
+            source: hash,
+        },
+    )
+}
+
 #[cfg(feature = "lua")]
 impl mlua::UserData for LocalPackageHashes {
     fn add_fields<F: mlua::UserDataFields<Self>>(fields: &mut F) {
@@ -386,17 +516,51 @@ impl TryFrom<&Option<String>> for LockConstraint {
     }
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+/// A chain of dependencies that leads back to the package it started from,
+/// e.g. `a → b → a`.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[error("circular dependency: {}", .0.iter().join(" → "))]
+pub struct DependencyCycle(pub Vec<PackageName>);
+
+#[cfg(test)]
+thread_local! {
+    /// The number of times a lockfile has been written on the current thread.
+    static WRITE_COUNT: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+}
+
+#[derive(Debug, Serialize, Deserialize)]
 pub struct Lockfile {
     #[serde(skip)]
     filepath: PathBuf,
+    /// Held until the lockfile is dropped, so that other processes can't modify it in the meantime.
+    #[serde(skip)]
+    tree_lock: Option<TreeLock>,
+    /// Whether the lockfile has been changed since it was loaded or last flushed.
+    /// Only then is it flushed on drop, so that reading it doesn't rewrite it.
+    #[serde(skip)]
+    modified: bool,
     // TODO: Serialize this directly into a `Version`
     version: String,
     // NOTE: We cannot directly serialize to a `Sha256` object as they don't implement serde traits.
-    rocks: HashMap<LocalPackageId, LocalPackage>,
+    // A `BTreeMap`, so that the rocks are serialized in a stable order.
+    rocks: BTreeMap<LocalPackageId, LocalPackage>,
     entrypoints: Vec<LocalPackageId>,
 }
 
+impl Default for Lockfile {
+    /// An empty lockfile that is not backed by a file.
+    fn default() -> Self {
+        Self {
+            filepath: PathBuf::default(),
+            tree_lock: None,
+            modified: false,
+            version: "1.0.0".into(),
+            rocks: BTreeMap::default(),
+            entrypoints: Vec::default(),
+        }
+    }
+}
+
 impl Lockfile {
     pub fn new(filepath: PathBuf) -> io::Result<Self> {
         // Ensure that the lockfile exists
@@ -424,11 +588,40 @@ impl Lockfile {
         Ok(new)
     }
 
+    /// Create or load the lockfile at `filepath` while holding `tree_lock`.
+    pub(crate) fn new_locked(filepath: PathBuf, tree_lock: TreeLock) -> io::Result<Self> {
+        let mut lockfile = Self::new(filepath)?;
+        lockfile.tree_lock = Some(tree_lock);
+        Ok(lockfile)
+    }
+
+    /// Load an existing lockfile without creating or modifying it.
+    /// Changes to the returned lockfile are not persisted.
+    pub fn load(filepath: &Path) -> io::Result<Self> {
+        Ok(serde_json::from_str(&std::fs::read_to_string(filepath)?)?)
+    }
+
+    /// A copy of the lockfile's content that is not backed by a file,
+    /// e.g. to resolve dependencies against, while the lockfile itself is being modified.
+    /// Unlike the lockfile, the copy doesn't hold the tree lock and is never flushed.
+    pub(crate) fn snapshot(&self) -> Self {
+        Self {
+            filepath: PathBuf::default(),
+            tree_lock: None,
+            modified: false,
+            version: self.version.clone(),
+            rocks: self.rocks.clone(),
+            entrypoints: self.entrypoints.clone(),
+        }
+    }
+
     pub fn add(&mut self, rock: &LocalPackage) {
+        self.modified = true;
         self.rocks.insert(rock.id(), rock.clone());
     }
 
     pub fn add_dependency(&mut self, target: &LocalPackage, dependency: &LocalPackage) {
+        self.modified = true;
         let target_id = target.id();
         let dependency_id = dependency.id();
 
@@ -444,6 +637,7 @@ impl Lockfile {
     }
 
     pub fn remove(&mut self, target: &LocalPackage) {
+        self.modified = true;
         self.rocks.remove(&target.id());
     }
 
@@ -451,7 +645,7 @@ impl Lockfile {
         &self.version
     }
 
-    pub fn rocks(&self) -> &HashMap<LocalPackageId, LocalPackage> {
+    pub fn rocks(&self) -> &BTreeMap<LocalPackageId, LocalPackage> {
         &self.rocks
     }
 
@@ -460,32 +654,149 @@ impl Lockfile {
     }
 
     pub fn get_mut(&mut self, id: &LocalPackageId) -> Option<&mut LocalPackage> {
+        self.modified = true;
         self.rocks.get_mut(id)
     }
 
-    // TODO: `fn entrypoints() -> Vec<LockedRock>`
+    /// The direct and transitive dependencies of the package with the given `id`,
+    /// each listed once, with dependencies before the packages that depend on them.
+    /// Dependencies that are missing from the lockfile are skipped.
+    pub fn all_dependencies(
+        &self,
+        id: &LocalPackageId,
+    ) -> Result<Vec<&LocalPackage>, DependencyCycle> {
+        fn visit<'a>(
+            lockfile: &'a Lockfile,
+            package: &'a LocalPackage,
+            path: &mut Vec<&'a LocalPackage>,
+            visited: &mut HashSet<LocalPackageId>,
+            dependencies: &mut Vec<&'a LocalPackage>,
+        ) -> Result<(), DependencyCycle> {
+            path.push(package);
+            for dependency_id in package.dependencies() {
+                if let Some(start) = path
+                    .iter()
+                    .position(|ancestor| ancestor.id() == *dependency_id)
+                {
+                    return Err(DependencyCycle(
+                        path[start..]
+                            .iter()
+                            .chain(std::iter::once(&path[start]))
+                            .map(|package| package.name().clone())
+                            .collect(),
+                    ));
+                }
+                if !visited.insert(dependency_id.clone()) {
+                    continue;
+                }
+                if let Some(dependency) = lockfile.get(dependency_id) {
+                    visit(lockfile, dependency, path, visited, dependencies)?;
+                    dependencies.push(dependency);
+                }
+            }
+            path.pop();
+            Ok(())
+        }
+
+        let mut dependencies = Vec::new();
+        if let Some(package) = self.get(id) {
+            visit(
+                self,
+                package,
+                &mut Vec::new(),
+                &mut HashSet::new(),
+                &mut dependencies,
+            )?;
+        }
+        Ok(dependencies)
+    }
 
-    pub fn flush(&mut self) -> io::Result<()> {
+    /// The rocks that depend directly on `package`.
+    pub fn dependents(&self, package: &LocalPackage) -> Vec<&LocalPackage> {
+        let id = package.id();
+        self.rocks
+            .values()
+            .filter(|rock| rock.dependencies().contains(&&id))
+            .collect()
+    }
+
+    /// The rocks that no other rock depends on.
+    pub fn entrypoints(&self) -> Vec<&LocalPackage> {
         let dependencies = self
             .rocks
             .iter()
             .flat_map(|(_, rock)| rock.dependencies())
             .collect_vec();
 
-        self.entrypoints = self
-            .rocks
-            .keys()
-            .filter(|id| !dependencies.iter().contains(&id))
+        self.rocks
+            .iter()
+            .filter(|(id, _)| !dependencies.iter().contains(id))
+            .map(|(_, rock)| rock)
+            .collect()
+    }
+
+    /// Whether the entrypoints recorded in the lockfile differ from [`Lockfile::entrypoints`],
+    /// e.g. because the lockfile was edited by hand.
+    /// They are recomputed whenever the lockfile is flushed.
+    pub(crate) fn has_stale_entrypoints(&self) -> bool {
+        let entrypoints = self.entrypoints().into_iter().map(|rock| rock.id());
+        !self
+            .entrypoints
+            .iter()
             .cloned()
+            .sorted()
+            .eq(entrypoints.sorted())
+    }
+
+    /// Write the lockfile to disk.
+    /// The output only depends on the lockfile's logical content, not on the order
+    /// in which rocks and their dependencies were added (e.g. by concurrent installs),
+    /// so that lockfiles of equivalent trees are byte-identical.
+    ///
+    /// The whole lockfile is serialized and written, so changes should be batched,
+    /// e.g. with [`Lockfile::map_then_flush`], rather than flushed one by one.
+    pub fn flush(&mut self) -> io::Result<()> {
+        for rock in self.rocks.values_mut() {
+            rock.spec.dependencies.sort();
+            rock.spec.dependencies.dedup();
+            rock.spec.features.sort();
+            rock.spec.features.dedup();
+        }
+        self.entrypoints = self
+            .entrypoints()
+            .into_iter()
+            .map(|rock| rock.id())
             .collect();
 
         let content = serde_json::to_string_pretty(self)?;
 
         std::fs::write(&self.filepath, content)?;
+        self.modified = false;
+
+        #[cfg(test)]
+        WRITE_COUNT.with(|count| count.set(count.get() + 1));
 
         Ok(())
     }
 
+    /// Apply `cb` to the lockfile, then flush it once.
+    /// Unlike flushing on drop, this surfaces errors from writing the lockfile,
+    /// and makes it explicit when the changes are persisted.
+    /// If `cb` fails, the changes it has made are discarded, rather than flushed.
+    pub fn map_then_flush<T, E, F>(mut self, cb: F) -> Result<T, E>
+    where
+        F: FnOnce(&mut Lockfile) -> Result<T, E>,
+        E: From<io::Error>,
+    {
+        let result = cb(&mut self).and_then(|result| {
+            self.flush()?;
+            Ok(result)
+        });
+        // Either the changes have been flushed, or they must not be flushed on drop.
+        self.modified = false;
+        result
+    }
+
     pub(crate) fn list(&self) -> HashMap<PackageName, Vec<LocalPackage>> {
         self.rocks()
             .values()
@@ -494,14 +805,77 @@ impl Lockfile {
             .into_group_map()
     }
 
+    /// Compare this lockfile to a newer one, grouping the changes by package name.
+    /// A package is considered changed if its versions or sources differ,
+    /// e.g. if the git ref of a dev version has moved.
+    pub fn diff(&self, other: &Lockfile) -> LockDiff {
+        let sorted = |packages: Vec<LocalPackage>| {
+            packages
+                .into_iter()
+                .sorted_by(|a, b| {
+                    a.version()
+                        .cmp(b.version())
+                        .then_with(|| a.hashes().cmp(b.hashes()))
+                })
+                .collect_vec()
+        };
+        let old = self.list();
+        let mut new = other.list();
+        let mut diff = LockDiff::default();
+        for (name, old_packages) in old {
+            let old_packages = sorted(old_packages);
+            match new.remove(&name) {
+                None => {
+                    diff.removed.insert(name, old_packages);
+                }
+                Some(new_packages) => {
+                    let new_packages = sorted(new_packages);
+                    let key = |package: &LocalPackage| {
+                        (package.version().clone(), package.hashes().source.clone())
+                    };
+                    if !old_packages
+                        .iter()
+                        .map(key)
+                        .eq(new_packages.iter().map(key))
+                    {
+                        diff.changed.insert(
+                            name,
+                            LockChange {
+                                old: old_packages,
+                                new: new_packages,
+                            },
+                        );
+                    }
+                }
+            }
+        }
+        diff.added = new
+            .into_iter()
+            .map(|(name, packages)| (name, sorted(packages)))
+            .collect();
+        diff
+    }
+
+    /// The features that were enabled for the installed versions of `name`,
+    /// so that they stay enabled when it is reinstalled or updated.
+    pub(crate) fn features_of(&self, name: &PackageName) -> Vec<PackageName> {
+        self.rocks
+            .values()
+            .filter(|rock| rock.name() == name)
+            .flat_map(|rock| rock.features().iter().cloned())
+            .unique()
+            .collect_vec()
+    }
+
     pub(crate) fn has_rock(&self, req: &PackageReq) -> Option<LocalPackage> {
         self.list()
             .get(req.name())
             .map(|packages| {
-                packages
-                    .iter()
-                    .rev()
-                    .find(|package| req.version_req().matches(package.version()))
+                packages.iter().rev().find(|package| {
+                    req.namespace()
+                        .is_none_or(|namespace| package.namespace() == Some(namespace))
+                        && req.version_req().matches(package.version())
+                })
             })?
             .cloned()
     }
@@ -509,10 +883,90 @@ impl Lockfile {
 
 impl Drop for Lockfile {
     fn drop(&mut self) {
-        let _ = self.flush();
+        // Lockfiles obtained with `Lockfile::load` or `Lockfile::default` are not backed by a file.
+        if self.modified && !self.filepath.as_os_str().is_empty() {
+            let _ = self.flush();
+        }
     }
 }
 
+/// The changes between two lockfiles, keyed by package name.
+#[derive(Debug, Default)]
+pub struct LockDiff {
+    pub added: BTreeMap<PackageName, Vec<LocalPackage>>,
+    pub removed: BTreeMap<PackageName, Vec<LocalPackage>>,
+    pub changed: BTreeMap<PackageName, LockChange>,
+}
+
+impl LockDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+
+    /// A line per changed rock, e.g. `+ foo 1.0.0-1`, `- bar 1.0.0-1`
+    /// or `~ baz 1.0.0-1 -> 1.1.0-1`.
+    pub fn summary(&self) -> Vec<String> {
+        let added = self
+            .added
+            .iter()
+            .map(|(name, packages)| format!("+ {} {}", name, versions(packages).join(", ")));
+        let removed = self
+            .removed
+            .iter()
+            .map(|(name, packages)| format!("- {} {}", name, versions(packages).join(", ")));
+        let changed = self.changed.iter().map(|(name, change)| {
+            let (old, new) = (
+                versions(&change.old).join(", "),
+                versions(&change.new).join(", "),
+            );
+            if old == new {
+                format!("~ {} {} (source changed)", name, new)
+            } else {
+                format!("~ {} {} -> {}", name, old, new)
+            }
+        });
+        added.chain(removed).chain(changed).collect_vec()
+    }
+
+    /// An [`Event::LockfileChange`] per changed rock, in the same order as [`LockDiff::summary`].
+    pub fn events(&self) -> Vec<Event> {
+        let event = |change, name: &PackageName, old: &[LocalPackage], new: &[LocalPackage]| {
+            Event::LockfileChange {
+                change,
+                name: name.to_string(),
+                old_versions: versions(old),
+                new_versions: versions(new),
+            }
+        };
+        let added = self
+            .added
+            .iter()
+            .map(|(name, packages)| event(LockfileChangeKind::Added, name, &[], packages));
+        let removed = self
+            .removed
+            .iter()
+            .map(|(name, packages)| event(LockfileChangeKind::Removed, name, packages, &[]));
+        let changed = self.changed.iter().map(|(name, change)| {
+            event(LockfileChangeKind::Updated, name, &change.old, &change.new)
+        });
+        added.chain(removed).chain(changed).collect_vec()
+    }
+}
+
+fn versions(packages: &[LocalPackage]) -> Vec<String> {
+    packages
+        .iter()
+        .map(|package| package.version().to_string())
+        .collect_vec()
+}
+
+/// The locked packages of a name before and after a change.
+#[derive(Debug)]
+pub struct LockChange {
+    pub old: Vec<LocalPackage>,
+    pub new: Vec<LocalPackage>,
+}
+
 #[cfg(feature = "lua")]
 impl mlua::UserData for Lockfile {
     fn add_methods<M: mlua::UserDataMethods<Self>>(methods: &mut M) {
@@ -617,6 +1071,62 @@ mod tests {
         assert_json_snapshot!(lockfile, { ".**" => sorted_redaction() });
     }
 
+    #[test]
+    fn all_dependencies_of_cyclic_lockfile() {
+        let package = |name: &str| test_package(name, "1.0.0");
+        let (a, b, c, d) = (package("a"), package("b"), package("c"), package("d"));
+
+        let mut lockfile = Lockfile::default();
+        for package in [&a, &b, &c, &d] {
+            lockfile.add(package);
+        }
+        // a depends on b and c, which both depend on d.
+        lockfile.add_dependency(&a, &b);
+        lockfile.add_dependency(&a, &c);
+        lockfile.add_dependency(&b, &d);
+        lockfile.add_dependency(&c, &d);
+        assert_eq!(
+            lockfile
+                .all_dependencies(&a.id())
+                .unwrap()
+                .into_iter()
+                .map(|package| package.name().to_string())
+                .collect_vec(),
+            vec!["d", "b", "c"]
+        );
+
+        lockfile.add_dependency(&d, &b);
+        assert_eq!(
+            lockfile.all_dependencies(&a.id()).unwrap_err().to_string(),
+            "circular dependency: b → d → b"
+        );
+    }
+
+    #[test]
+    fn features_round_trip() {
+        let package = |name: &str| test_package(name, "1.0.0");
+        let plain = package("plain");
+        let with_features = package("foo").with_features(vec!["bar".into()]);
+        // Features don't change the package's identity.
+        assert_eq!(with_features.id(), package("foo").id());
+        assert!(!serde_json::to_string(&plain).unwrap().contains("features"));
+
+        let mut lockfile = Lockfile::default();
+        lockfile.add(&plain);
+        lockfile.add(&with_features);
+        let lockfile: Lockfile =
+            serde_json::from_str(&serde_json::to_string(&lockfile).unwrap()).unwrap();
+        assert_eq!(
+            lockfile.get(&with_features.id()).unwrap().features(),
+            [PackageName::from("bar")]
+        );
+        assert_eq!(
+            lockfile.features_of(&"foo".into()),
+            vec![PackageName::from("bar")]
+        );
+        assert!(lockfile.features_of(&"plain".into()).is_empty());
+    }
+
     #[test]
     fn parse_nonexistent_lockfile() {
         let tree_path =
@@ -631,4 +1141,226 @@ mod tests {
 
         tree.lockfile().unwrap(); // Try to create the lockfile but don't actually do anything with it.
     }
+
+    #[test]
+    fn map_then_flush() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let package = |name: &str| test_package(name, "1.0.0-1");
+        let neorg = package("neorg");
+        let nio = package("nvim-nio");
+        let busted = package("busted");
+
+        let filepath = temp.join("lock.json");
+        let rock_count = Lockfile::new(filepath.clone())
+            .unwrap()
+            .map_then_flush(|lockfile| {
+                lockfile.add(&neorg);
+                lockfile.add_dependency(&neorg, &nio);
+                lockfile.add(&busted);
+                Ok::<_, io::Error>(lockfile.rocks().len())
+            })
+            .unwrap();
+        assert_eq!(rock_count, 3);
+
+        let lockfile = Lockfile::load(&filepath).unwrap();
+        assert_eq!(lockfile.rocks().len(), 3);
+        assert_eq!(
+            lockfile.get(&neorg.id()).unwrap().dependencies(),
+            vec![&nio.id()]
+        );
+        assert_eq!(
+            lockfile.entrypoints.iter().sorted().collect_vec(),
+            vec![&busted.id(), &neorg.id()]
+                .into_iter()
+                .sorted()
+                .collect_vec()
+        );
+    }
+
+    #[test]
+    fn map_then_flush_discards_changes_on_error() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let filepath = temp.join("lock.json");
+        drop(Lockfile::new(filepath.clone()).unwrap());
+        let content = std::fs::read_to_string(&filepath).unwrap();
+
+        let package = test_package("neorg", "1.0.0-1");
+        let result = Lockfile::new(filepath.clone())
+            .unwrap()
+            .map_then_flush(|lockfile| {
+                lockfile.add(&package);
+                Err::<(), _>(io::Error::other("failed halfway"))
+            });
+        assert!(result.is_err());
+        assert_eq!(std::fs::read_to_string(&filepath).unwrap(), content);
+    }
+
+    #[test]
+    fn batch_writes() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let packages = (0..500)
+            .map(|i| test_package(&format!("rock-{i}"), "1.0.0-1"))
+            .collect_vec();
+        let filepath = temp.join("lock.json");
+        let write_count = || WRITE_COUNT.with(|count| count.get());
+        let initial_count = write_count();
+
+        Lockfile::new(filepath.clone())
+            .unwrap()
+            .map_then_flush(|lockfile| {
+                for (package, dependency) in packages.iter().tuple_windows() {
+                    lockfile.add(package);
+                    lockfile.add_dependency(package, dependency);
+                }
+                Ok::<_, io::Error>(())
+            })
+            .unwrap();
+        assert_eq!(write_count() - initial_count, 1);
+
+        // Reading the lockfile doesn't rewrite it.
+        let lockfile = Lockfile::new(filepath.clone()).unwrap();
+        assert_eq!(lockfile.rocks().len(), 500);
+        drop(lockfile);
+        assert_eq!(write_count() - initial_count, 1);
+
+        // Flushing explicitly isn't repeated on drop.
+        let mut lockfile = Lockfile::new(filepath.clone()).unwrap();
+        lockfile.remove(&packages[0]);
+        lockfile.flush().unwrap();
+        drop(lockfile);
+        assert_eq!(write_count() - initial_count, 2);
+
+        // Changes that weren't flushed are flushed on drop.
+        let mut lockfile = Lockfile::new(filepath.clone()).unwrap();
+        lockfile.remove(&packages[1]);
+        drop(lockfile);
+        assert_eq!(write_count() - initial_count, 3);
+        assert_eq!(Lockfile::load(&filepath).unwrap().rocks().len(), 498);
+    }
+
+    #[test]
+    fn flush_is_deterministic() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let package = |name: &str| test_package(name, "1.0.0-1");
+        let neorg = package("neorg").with_features(vec!["b".into(), "a".into()]);
+        let nio = package("nvim-nio");
+        let pathlib = package("pathlib.nvim");
+        let busted = package("busted");
+        let say = package("say");
+
+        // The same set of rocks, installed in a different order.
+        let first_path = temp.join("first.json");
+        let mut first = Lockfile::new(first_path.clone()).unwrap();
+        first.add(&neorg);
+        first.add_dependency(&neorg, &nio);
+        first.add_dependency(&neorg, &pathlib);
+        first.add(&busted);
+        first.add_dependency(&busted, &say);
+        first.flush().unwrap();
+
+        let second_path = temp.join("second.json");
+        let mut second = Lockfile::new(second_path.clone()).unwrap();
+        second.add(&busted);
+        second.add(&neorg.clone().with_features(vec!["a".into(), "b".into()]));
+        second.add_dependency(&busted, &say);
+        second.add_dependency(&neorg, &pathlib);
+        second.add_dependency(&neorg, &nio);
+        second.flush().unwrap();
+
+        assert_eq!(
+            std::fs::read(&first_path).unwrap(),
+            std::fs::read(&second_path).unwrap()
+        );
+
+        // Flushing a loaded lockfile again must not change it.
+        let mut reloaded = Lockfile::new(first_path.clone()).unwrap();
+        reloaded.filepath = second_path.clone();
+        reloaded.flush().unwrap();
+        assert_eq!(
+            std::fs::read(&first_path).unwrap(),
+            std::fs::read(&second_path).unwrap()
+        );
+    }
+
+    #[test]
+    fn diff_lockfiles() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let package = |name: &str, version: &str, hash: &str| LocalPackage {
+            hashes: LocalPackageHashes {
+                rockspec: hash.parse().unwrap(),
+                source: hash.parse().unwrap(),
+            },
+            ..test_package(name, version)
+        };
+        let hash_a = "sha256-uU0nuZNNPgilLlLX2n2r+sSE7+N6U4DukIj3rOLvzek=";
+        let hash_b = "sha256-47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU=";
+
+        let mut old = Lockfile::new(temp.join("old.json")).unwrap();
+        old.add(&package("neorg", "8.0.0-1", hash_a));
+        old.add(&package("neorg", "8.8.1-1", hash_a));
+        old.add(&package("lua-cjson", "2.1.0-1", hash_a));
+        old.add(&package("nvim-nio", "scm-1", hash_a));
+        old.add(&package("pathlib.nvim", "2.2.0-1", hash_a));
+
+        let mut new = Lockfile::new(temp.join("new.json")).unwrap();
+        new.add(&package("neorg", "8.8.1-1", hash_a));
+        new.add(&package("neorg", "9.0.0-1", hash_a));
+        new.add(&package("say", "1.4.1-3", hash_a));
+        // Same version, different source (e.g. a moved git ref)
+        new.add(&package("nvim-nio", "scm-1", hash_b));
+        new.add(&package("pathlib.nvim", "2.2.0-1", hash_a));
+
+        assert!(old.diff(&old).is_empty());
+        let diff = old.diff(&new);
+        assert_eq!(
+            diff.added.keys().collect_vec(),
+            vec![&PackageName::new("say".into())]
+        );
+        assert_eq!(
+            diff.removed.keys().collect_vec(),
+            vec![&PackageName::new("lua-cjson".into())]
+        );
+        assert_eq!(
+            diff.changed.keys().collect_vec(),
+            vec![
+                &PackageName::new("neorg".into()),
+                &PackageName::new("nvim-nio".into())
+            ]
+        );
+        let neorg_change = &diff.changed[&PackageName::new("neorg".into())];
+        assert_eq!(
+            neorg_change
+                .old
+                .iter()
+                .map(|package| package.version().to_string())
+                .collect_vec(),
+            vec!["8.0.0-1", "8.8.1-1"]
+        );
+        assert_eq!(
+            neorg_change
+                .new
+                .iter()
+                .map(|package| package.version().to_string())
+                .collect_vec(),
+            vec!["8.8.1-1", "9.0.0-1"]
+        );
+        assert_eq!(
+            diff.summary(),
+            vec![
+                "+ say 1.4.1-3",
+                "- lua-cjson 2.1.0-1",
+                "~ neorg 8.0.0-1, 8.8.1-1 -> 8.8.1-1, 9.0.0-1",
+                "~ nvim-nio scm-1 (source changed)",
+            ]
+        );
+        assert_eq!(
+            diff.events()[1],
+            Event::LockfileChange {
+                change: LockfileChangeKind::Removed,
+                name: "lua-cjson".into(),
+                old_versions: vec!["2.1.0-1".into()],
+                new_versions: Vec::new(),
+            }
+        );
+    }
 }
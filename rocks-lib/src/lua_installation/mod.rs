@@ -1,7 +1,10 @@
 use itertools::Itertools as _;
 use pkg_config::{Config as PkgConfig, Library};
 use std::io;
-use std::{path::PathBuf, process::Command};
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+};
 use target_lexicon::Triple;
 use thiserror::Error;
 
@@ -12,97 +15,132 @@ use crate::{
     package::PackageVersion,
 };
 
+mod source;
+
+pub use source::BuildLuaReleaseError;
+
 pub struct LuaInstallation {
     pub include_dir: PathBuf,
     pub lib_dir: PathBuf,
     version: LuaVersion,
+    /// The release the installation was built from, e.g. `5.4.6`, if known.
+    release: Option<PackageVersion>,
+    /// The name of the Lua library to link against, e.g. `lua5.1` for `liblua5.1.so`.
+    lib_name: String,
     /// pkg-config library information if available
     lib_info: Option<Library>,
 }
 
 impl LuaInstallation {
+    /// Find an installation of Lua `version`, building it from source
+    /// and installing it into the `lua_dir` if none is found.
+    /// See [`LuaInstallation::find`] for where installations are searched for.
     pub fn new(version: &LuaVersion, config: &Config) -> Self {
-        let pkg_name = match version {
-            LuaVersion::Lua51 => "lua5.1",
-            LuaVersion::Lua52 => "lua5.2",
-            LuaVersion::Lua53 => "lua5.3",
-            LuaVersion::Lua54 => "lua5.4",
-            LuaVersion::LuaJIT | LuaVersion::LuaJIT52 => "luajit",
-        };
-        let lib_info = PkgConfig::new()
-            .print_system_libs(false)
-            .cargo_metadata(false)
-            .probe(pkg_name)
-            .ok();
-
-        if let Some(info) = lib_info {
-            if !&info.include_paths.is_empty() && !&info.link_paths.is_empty() {
-                return Self {
-                    include_dir: PathBuf::from(&info.include_paths[0]),
-                    lib_dir: PathBuf::from(&info.link_paths[0]),
-                    version: version.clone(),
-                    lib_info: Some(info),
-                };
-            }
+        Self::find(version, config).unwrap_or_else(|| Self::install(version, config))
+    }
+
+    /// Find an installation of Lua `version`, without building it.
+    /// An installation in the `lua_dir`, e.g. from `rocks install-lua`, is preferred.
+    /// Otherwise, a system installation is searched for with pkg-config and in common locations,
+    /// such as `/usr/include/lua5.1` or Homebrew's prefixes.
+    pub fn find(version: &LuaVersion, config: &Config) -> Option<Self> {
+        if let Some(installation) = Self::installed(version, config) {
+            return Some(installation);
         }
+        let (installation, source) = detect_system_installation(version)?;
+        if config.verbose() {
+            eprintln!(
+                "🌔 Using Lua ({}) from {}: {}",
+                installation.version,
+                source,
+                installation.include_dir.display()
+            );
+        }
+        Some(installation)
+    }
 
+    /// Build Lua `version` from source and install it into the `lua_dir`,
+    /// unless it has already been installed there.
+    pub fn install(version: &LuaVersion, config: &Config) -> Self {
+        if let Some(installation) = Self::installed(version, config) {
+            return installation;
+        }
         let output = Self::path(version, config);
-        if output.exists() {
-            LuaInstallation {
-                include_dir: output.join("include"),
-                lib_dir: output.join("lib"),
-                version: version.clone(),
-                lib_info: None,
+
+        let host = Triple::host();
+        let target = &host.to_string();
+        let host_operating_system = &host.operating_system.to_string();
+
+        let (include_dir, lib_dir) = match version {
+            LuaVersion::LuaJIT | LuaVersion::LuaJIT52 => {
+                // XXX: luajit_src panics if this is not set.
+                let target_pointer_width =
+                    std::env::var("CARGO_CFG_TARGET_POINTER_WIDTH").unwrap_or("64".into());
+                std::env::set_var("CARGO_CFG_TARGET_POINTER_WIDTH", target_pointer_width);
+                let build = luajit_src::Build::new()
+                    .target(target)
+                    .host(host_operating_system)
+                    .out_dir(output)
+                    .lua52compat(matches!(version, LuaVersion::LuaJIT52))
+                    .build();
+
+                (
+                    build.include_dir().to_path_buf(),
+                    build.lib_dir().to_path_buf(),
+                )
             }
-        } else {
-            let host = Triple::host();
-            let target = &host.to_string();
-            let host_operating_system = &host.operating_system.to_string();
-
-            let (include_dir, lib_dir) = match version {
-                LuaVersion::LuaJIT | LuaVersion::LuaJIT52 => {
-                    // XXX: luajit_src panics if this is not set.
-                    let target_pointer_width =
-                        std::env::var("CARGO_CFG_TARGET_POINTER_WIDTH").unwrap_or("64".into());
-                    std::env::set_var("CARGO_CFG_TARGET_POINTER_WIDTH", target_pointer_width);
-                    let build = luajit_src::Build::new()
-                        .target(target)
-                        .host(host_operating_system)
-                        .out_dir(output)
-                        .lua52compat(matches!(version, LuaVersion::LuaJIT52))
-                        .build();
-
-                    (
-                        build.include_dir().to_path_buf(),
-                        build.lib_dir().to_path_buf(),
-                    )
-                }
-                _ => {
-                    let build = lua_src::Build::new()
-                        .target(target)
-                        .host(host_operating_system)
-                        .out_dir(output)
-                        .build(match version {
-                            LuaVersion::Lua51 => lua_src::Version::Lua51,
-                            LuaVersion::Lua52 => lua_src::Version::Lua52,
-                            LuaVersion::Lua53 => lua_src::Version::Lua53,
-                            LuaVersion::Lua54 => lua_src::Version::Lua54,
-                            _ => unreachable!(),
-                        });
-
-                    (
-                        build.include_dir().to_path_buf(),
-                        build.lib_dir().to_path_buf(),
-                    )
-                }
-            };
+            _ => {
+                let build = lua_src::Build::new()
+                    .target(target)
+                    .host(host_operating_system)
+                    .out_dir(output)
+                    .build(match version {
+                        LuaVersion::Lua51 => lua_src::Version::Lua51,
+                        LuaVersion::Lua52 => lua_src::Version::Lua52,
+                        LuaVersion::Lua53 => lua_src::Version::Lua53,
+                        LuaVersion::Lua54 => lua_src::Version::Lua54,
+                        _ => unreachable!(),
+                    });
 
-            LuaInstallation {
-                include_dir,
-                lib_dir,
-                version: version.clone(),
-                lib_info: None,
+                (
+                    build.include_dir().to_path_buf(),
+                    build.lib_dir().to_path_buf(),
+                )
             }
+        };
+
+        LuaInstallation {
+            include_dir,
+            lib_dir,
+            version: version.clone(),
+            release: None,
+            lib_name: default_lib_name(version).into(),
+            lib_info: None,
+        }
+    }
+
+    /// The installation of Lua `version` in the `lua_dir`, if there is one.
+    pub fn installed(version: &LuaVersion, config: &Config) -> Option<Self> {
+        let output = Self::path(version, config);
+        output.exists().then(|| Self::from_dir(output, version))
+    }
+
+    fn from_dir(dir: PathBuf, version: &LuaVersion) -> Self {
+        // LuaJIT releases have their own version numbers, so they are not recorded.
+        let release = if version.is_luajit() {
+            None
+        } else {
+            std::fs::read_to_string(dir.join(source::RELEASE_FILE_NAME))
+                .ok()
+                .and_then(|release| PackageVersion::parse(release.trim()).ok())
+        };
+        LuaInstallation {
+            include_dir: dir.join("include"),
+            lib_dir: dir.join("lib"),
+            version: version.clone(),
+            release,
+            lib_name: default_lib_name(version).into(),
+            lib_info: None,
         }
     }
 
@@ -110,6 +148,18 @@ impl LuaInstallation {
         config.lua_dir().join(version.to_string())
     }
 
+    /// The version to check rockspecs' `lua` dependencies against:
+    /// the release the installation was built from, if known, e.g. `5.4.6`, or `x.y.0` otherwise.
+    pub fn lua_package_version(&self) -> PackageVersion {
+        self.release
+            .clone()
+            .unwrap_or_else(|| self.version.as_version())
+    }
+
+    pub(crate) fn version(&self) -> &LuaVersion {
+        &self.version
+    }
+
     pub(crate) fn compile_args(&self) -> Vec<String> {
         if let Some(info) = &self.lib_info {
             info.include_paths
@@ -140,14 +190,9 @@ impl LuaInstallation {
                 }))
                 .collect_vec()
         } else {
-            let link_lua_arg = match self.version {
-                LuaVersion::LuaJIT => "-lluajit-5.1",
-                LuaVersion::LuaJIT52 => "-lluajit-5.2",
-                _ => "-llua",
-            };
             vec![
                 format!("-L{}", self.lib_dir.display()),
-                link_lua_arg.to_string(),
+                format!("-l{}", self.lib_name),
             ]
         }
     }
@@ -169,6 +214,195 @@ impl HasVariables for LuaInstallation {
     }
 }
 
+/// The name of the library that Lua `version` is built as by `lua_src` and `luajit_src`.
+fn default_lib_name(version: &LuaVersion) -> &'static str {
+    match version {
+        LuaVersion::LuaJIT => "luajit-5.1",
+        LuaVersion::LuaJIT52 => "luajit-5.2",
+        _ => "lua",
+    }
+}
+
+/// Search for a system installation of Lua `version`,
+/// returning it along with a description of where it was found.
+fn detect_system_installation(version: &LuaVersion) -> Option<(LuaInstallation, String)> {
+    probe_pkg_config(version).or_else(|| {
+        system_prefixes(version).into_iter().find_map(|prefix| {
+            let installation = find_in_prefix(&prefix, version)?;
+            Some((installation, prefix.display().to_string()))
+        })
+    })
+}
+
+/// The pkg-config modules that system packages provide Lua `version` as.
+fn pkg_config_names(version: &LuaVersion) -> Vec<String> {
+    if version.is_luajit() {
+        return vec!["luajit".into()];
+    }
+    let version = version.version_compatibility_str();
+    vec![
+        format!("lua{}", version),
+        format!("lua-{}", version),
+        format!("lua{}", version.replace('.', "")),
+        // Only used if its version matches, as this is the latest Lua on most systems.
+        "lua".into(),
+    ]
+}
+
+fn probe_pkg_config(version: &LuaVersion) -> Option<(LuaInstallation, String)> {
+    pkg_config_names(version).into_iter().find_map(|pkg_name| {
+        let info = PkgConfig::new()
+            .print_system_libs(false)
+            .cargo_metadata(false)
+            .probe(&pkg_name)
+            .ok()?;
+        if !pkg_config_version_matches(version, &info.version) {
+            return None;
+        }
+        let include_dir = info.include_paths.first()?.clone();
+        let lib_dir = info.link_paths.first()?.clone();
+        let lib_name = info
+            .libs
+            .first()
+            .cloned()
+            .unwrap_or_else(|| default_lib_name(version).into());
+        Some((
+            LuaInstallation {
+                include_dir,
+                lib_dir,
+                version: version.clone(),
+                release: None,
+                lib_name,
+                lib_info: Some(info),
+            },
+            format!("pkg-config module `{}`", pkg_name),
+        ))
+    })
+}
+
+/// Whether the version of a pkg-config module matches Lua `version`.
+/// LuaJIT has its own version numbers, so they are not checked.
+fn pkg_config_version_matches(version: &LuaVersion, pkg_version: &str) -> bool {
+    version.is_luajit()
+        || pkg_version == version.version_compatibility_str()
+        || pkg_version.starts_with(&format!("{}.", version.version_compatibility_str()))
+}
+
+/// The prefixes that Lua is commonly installed into,
+/// by the system's package manager, manually, or by Homebrew.
+fn system_prefixes(version: &LuaVersion) -> Vec<PathBuf> {
+    let formula = if version.is_luajit() {
+        "luajit".to_string()
+    } else {
+        format!("lua@{}", version.version_compatibility_str())
+    };
+    ["/usr", "/usr/local"]
+        .into_iter()
+        .map(PathBuf::from)
+        .chain(
+            ["/opt/homebrew", "/usr/local"]
+                .into_iter()
+                .flat_map(|homebrew| {
+                    let opt = Path::new(homebrew).join("opt");
+                    [opt.join(&formula), opt.join("lua")]
+                }),
+        )
+        .collect_vec()
+}
+
+/// Find the headers and library of Lua `version` in `prefix`.
+/// The headers are checked for the version, as e.g. `/usr/include/lua.h` may belong to any Lua.
+fn find_in_prefix(prefix: &Path, version: &LuaVersion) -> Option<LuaInstallation> {
+    let include = prefix.join("include");
+    let compatibility_str = version.version_compatibility_str();
+    let include_dirs = if version.is_luajit() {
+        vec![include.join("luajit-2.1"), include.join("luajit-2.0")]
+    } else {
+        vec![
+            include.join(format!("lua{}", compatibility_str)),
+            include.join(format!("lua-{}", compatibility_str)),
+            include.join(format!("lua{}", compatibility_str.replace('.', ""))),
+            include.clone(),
+        ]
+    };
+    let include_dir = include_dirs
+        .into_iter()
+        .find(|include_dir| headers_match(include_dir, version))?;
+
+    let lib_names = if version.is_luajit() {
+        vec![default_lib_name(version).to_string()]
+    } else {
+        vec![
+            format!("lua{}", compatibility_str),
+            format!("lua-{}", compatibility_str),
+            format!("lua{}", compatibility_str.replace('.', "")),
+            "lua".into(),
+        ]
+    };
+    let lib = prefix.join("lib");
+    // Debian-based distributions install libraries into e.g. `/usr/lib/x86_64-linux-gnu`.
+    let multiarch_lib_dirs = std::fs::read_dir(&lib)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.is_dir()
+                && path
+                    .file_name()
+                    .is_some_and(|name| name.to_string_lossy().contains("-linux-"))
+        })
+        .collect_vec();
+    let lib_dirs = std::iter::once(lib)
+        .chain(std::iter::once(prefix.join("lib64")))
+        .chain(multiarch_lib_dirs)
+        .collect_vec();
+    let (lib_dir, lib_name) = lib_names.into_iter().find_map(|lib_name| {
+        lib_dirs
+            .iter()
+            .find(|lib_dir| {
+                ["so", "dylib", "a"].iter().any(|extension| {
+                    lib_dir
+                        .join(format!("lib{}.{}", lib_name, extension))
+                        .is_file()
+                })
+            })
+            .map(|lib_dir| (lib_dir.clone(), lib_name))
+    })?;
+
+    Some(LuaInstallation {
+        include_dir,
+        lib_dir,
+        version: version.clone(),
+        release: None,
+        lib_name,
+        lib_info: None,
+    })
+}
+
+/// Whether `include_dir` contains the headers of Lua `version`,
+/// based on `luajit.h` and the `LUA_VERSION_NUM` in `lua.h`.
+fn headers_match(include_dir: &Path, version: &LuaVersion) -> bool {
+    let Ok(lua_h) = std::fs::read_to_string(include_dir.join("lua.h")) else {
+        return false;
+    };
+    if version.is_luajit() != include_dir.join("luajit.h").is_file() {
+        return false;
+    }
+    let expected_version_num = match version {
+        LuaVersion::Lua51 | LuaVersion::LuaJIT | LuaVersion::LuaJIT52 => "501",
+        LuaVersion::Lua52 => "502",
+        LuaVersion::Lua53 => "503",
+        LuaVersion::Lua54 => "504",
+    };
+    lua_h.lines().any(|line| {
+        let mut tokens = line.split_whitespace();
+        tokens.next() == Some("#define")
+            && tokens.next() == Some("LUA_VERSION_NUM")
+            && tokens.next() == Some(expected_version_num)
+    })
+}
+
 #[derive(Error, Debug)]
 pub enum GetLuaVersionError {
     #[error("failed to run {0}: {1}")]
@@ -225,4 +459,73 @@ mod test {
         let lua_output = "Lua 5.1.5  Copyright (C) 1994-2012 Lua.org, PUC-Rio";
         parse_lua_version_from_output(lua_output).unwrap();
     }
+
+    fn fake_prefix(include_subdir: &str, version_num: &str, libs: &[&str]) -> assert_fs::TempDir {
+        let prefix = assert_fs::TempDir::new().unwrap();
+        let include_dir = prefix.join("include").join(include_subdir);
+        std::fs::create_dir_all(&include_dir).unwrap();
+        std::fs::write(
+            include_dir.join("lua.h"),
+            format!("#define LUA_VERSION_NUM\t{}\n", version_num),
+        )
+        .unwrap();
+        for lib in libs {
+            let lib = prefix.join(lib);
+            std::fs::create_dir_all(lib.parent().unwrap()).unwrap();
+            std::fs::write(lib, "").unwrap();
+        }
+        prefix
+    }
+
+    #[test]
+    fn find_versioned_headers_in_prefix() {
+        let prefix = fake_prefix(
+            "lua5.1",
+            "501",
+            &["lib/x86_64-linux-gnu/liblua5.1.so", "lib/liblua.so"],
+        );
+        let installation = find_in_prefix(prefix.path(), &LuaVersion::Lua51).unwrap();
+        assert_eq!(installation.include_dir, prefix.join("include/lua5.1"));
+        assert_eq!(installation.lib_dir, prefix.join("lib/x86_64-linux-gnu"));
+        assert_eq!(installation.lib_name, "lua5.1");
+    }
+
+    #[test]
+    fn find_unversioned_headers_in_prefix() {
+        let prefix = fake_prefix("", "504", &["lib/liblua.dylib"]);
+        let installation = find_in_prefix(prefix.path(), &LuaVersion::Lua54).unwrap();
+        assert_eq!(installation.include_dir, prefix.join("include"));
+        assert_eq!(installation.lib_dir, prefix.join("lib"));
+        assert_eq!(installation.lib_name, "lua");
+        assert!(find_in_prefix(prefix.path(), &LuaVersion::Lua53).is_none());
+        assert!(find_in_prefix(prefix.path(), &LuaVersion::LuaJIT).is_none());
+    }
+
+    #[test]
+    fn find_luajit_headers_in_prefix() {
+        let prefix = fake_prefix("luajit-2.1", "501", &["lib/libluajit-5.1.a"]);
+        std::fs::write(prefix.join("include/luajit-2.1/luajit.h"), "").unwrap();
+        let installation = find_in_prefix(prefix.path(), &LuaVersion::LuaJIT).unwrap();
+        assert_eq!(installation.include_dir, prefix.join("include/luajit-2.1"));
+        assert_eq!(installation.lib_name, "luajit-5.1");
+        assert!(find_in_prefix(prefix.path(), &LuaVersion::Lua51).is_none());
+    }
+
+    #[test]
+    fn skip_prefix_without_library() {
+        let prefix = fake_prefix("lua5.1", "501", &[]);
+        assert!(find_in_prefix(prefix.path(), &LuaVersion::Lua51).is_none());
+    }
+
+    #[test]
+    fn match_pkg_config_version() {
+        assert!(pkg_config_version_matches(&LuaVersion::Lua51, "5.1.5"));
+        assert!(pkg_config_version_matches(&LuaVersion::Lua54, "5.4"));
+        assert!(!pkg_config_version_matches(&LuaVersion::Lua51, "5.4.6"));
+        assert!(!pkg_config_version_matches(&LuaVersion::Lua51, "5.10.0"));
+        assert!(pkg_config_version_matches(
+            &LuaVersion::LuaJIT,
+            "2.1.1713773202"
+        ));
+    }
 }
@@ -0,0 +1,336 @@
+//! Building a specific release of Lua or LuaJIT from its official source tarball,
+//! as opposed to the release bundled with `lua_src` or `luajit_src`.
+
+use std::{
+    io,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use reqwest::Url;
+use sha2::{Digest, Sha256};
+use target_lexicon::Triple;
+use thiserror::Error;
+use walkdir::WalkDir;
+
+use crate::{
+    config::{Config, LuaVersion},
+    operations::{download_resumable, ResumableDownloadError},
+    progress::{Progress, ProgressBar},
+};
+
+use super::{default_lib_name, LuaInstallation};
+
+/// The file that the release of an installation built by [`LuaInstallation::install_release`]
+/// is recorded in.
+pub(crate) const RELEASE_FILE_NAME: &str = "release";
+
+/// The sha256 checksums of the source tarballs of known releases,
+/// as published on <https://www.lua.org/ftp/> and <https://luajit.org/download.html>.
+const KNOWN_CHECKSUMS: &[(&str, &str)] = &[
+    (
+        "lua-5.1.5.tar.gz",
+        "2640fc56a795f29d28ef15e13c34a47e223960b0240e8cb0a82d9b0738695333",
+    ),
+    (
+        "lua-5.2.4.tar.gz",
+        "b9e2e4aad6789b3b63a056d442f7b39f0ecfca3ae0f1fc0ae4e9614401b69f4b",
+    ),
+    (
+        "lua-5.3.6.tar.gz",
+        "fc5fd69bb8736323f026672b1b7235da613d7177e72558893a0bdcd320466d60",
+    ),
+    (
+        "lua-5.4.4.tar.gz",
+        "164c7849653b80ae67bec4b7473b884bf5cc8d2dca05653475ec2ed27b9ebf61",
+    ),
+    (
+        "lua-5.4.6.tar.gz",
+        "7d5ea1b9cb6aa0b59ca3dde1c6adcb57ef83a1ba8e5432c0ecd06bf439b3ad88",
+    ),
+    (
+        "lua-5.4.7.tar.gz",
+        "9fbf5e28ef86c69858f6d3d34eccc32e911c1a28b4120ff3e84aaa70cfbf1e30",
+    ),
+    (
+        "LuaJIT-2.0.5.tar.gz",
+        "874b1f8297c697821f561f9b73b57ffd419ed8f4278c82e05b48806d30c1e979",
+    ),
+    (
+        "LuaJIT-2.1.0-beta3.tar.gz",
+        "1ad2e34b111c802f9d0cdf019e986909123237a28c746b21295b63c9e785d9c3",
+    ),
+];
+
+#[derive(Error, Debug)]
+pub enum BuildLuaReleaseError {
+    #[error("{release} is not a release of Lua {version}")]
+    ReleaseMismatch {
+        version: LuaVersion,
+        release: String,
+    },
+    #[error("the checksum of {0} is not known. Pass the expected sha256 checksum to verify it")]
+    UnknownChecksum(String),
+    #[error(transparent)]
+    Download(#[from] ResumableDownloadError),
+    #[error("checksum mismatch for {file}:\nexpected: {expected}\nactual:   {actual}")]
+    ChecksumMismatch {
+        file: String,
+        expected: String,
+        actual: String,
+    },
+    #[error("IO operation failed: {0}")]
+    Io(#[from] io::Error),
+    #[error("failed to compile Lua: {0}")]
+    Compile(#[from] cc::Error),
+    #[error("failed to run `{0}`: {1}")]
+    RunMake(String, io::Error),
+    #[error("`{0}` failed to build LuaJIT:\n{1}")]
+    MakeFailed(String, String),
+    #[error("{0} does not contain {1}")]
+    MissingFile(String, String),
+}
+
+impl LuaInstallation {
+    /// Build `release` (e.g. `5.4.6`, or `2.1.0-beta3` for LuaJIT) of Lua `version`
+    /// from its official source tarball and install it into the `lua_dir`,
+    /// replacing an existing installation of `version`.
+    ///
+    /// The tarball is verified against the checksum of a known release or, for other releases,
+    /// against `sha256`. The release is recorded, so that rockspecs' `lua` dependencies
+    /// can be checked against it (see [`LuaInstallation::lua_package_version`]).
+    /// LuaJIT is built with `make`, which is not supported on Windows.
+    pub async fn install_release(
+        version: &LuaVersion,
+        release: &str,
+        sha256: Option<&str>,
+        config: &Config,
+        progress: &Progress<ProgressBar>,
+    ) -> Result<Self, BuildLuaReleaseError> {
+        if !is_release_of(version, release) {
+            return Err(BuildLuaReleaseError::ReleaseMismatch {
+                version: version.clone(),
+                release: release.to_string(),
+            });
+        }
+        let file_name = source_file_name(version, release);
+        let expected = match sha256 {
+            Some(sha256) => sha256.to_lowercase(),
+            None => known_checksum(&file_name)
+                .ok_or_else(|| BuildLuaReleaseError::UnknownChecksum(file_name.clone()))?
+                .to_string(),
+        };
+
+        progress.map(|p| p.set_message(format!("📥 Downloading {}", file_name)));
+        let bytes = download_resumable(&source_url(version, &file_name), config, progress).await?;
+        let actual = hex::encode(Sha256::digest(&bytes));
+        if actual != expected {
+            return Err(BuildLuaReleaseError::ChecksumMismatch {
+                file: file_name,
+                expected,
+                actual,
+            });
+        }
+
+        progress.map(|p| p.set_message(format!("🛠️ Building {}", file_name)));
+        let temp_dir = tempdir::TempDir::new(&file_name)?;
+        tar::Archive::new(flate2::read::GzDecoder::new(&bytes[..])).unpack(temp_dir.path())?;
+        let source_dir = temp_dir
+            .path()
+            .join(file_name.trim_end_matches(".tar.gz"))
+            .join("src");
+        let lib = if version.is_luajit() {
+            build_luajit(&source_dir, version, config)?
+        } else {
+            build_lua(&source_dir, version, &temp_dir.path().join("build"))?
+        };
+
+        let output = Self::path(version, config);
+        if output.exists() {
+            std::fs::remove_dir_all(&output)?;
+        }
+        let include_dir = output.join("include");
+        let lib_dir = output.join("lib");
+        std::fs::create_dir_all(&include_dir)?;
+        std::fs::create_dir_all(&lib_dir)?;
+        for header in headers(version) {
+            let path = source_dir.join(header);
+            if !path.is_file() {
+                return Err(BuildLuaReleaseError::MissingFile(
+                    file_name,
+                    header.to_string(),
+                ));
+            }
+            std::fs::copy(path, include_dir.join(header))?;
+        }
+        std::fs::copy(&lib, lib_dir.join(static_lib_file_name(version)))?;
+        std::fs::write(output.join(RELEASE_FILE_NAME), release)?;
+
+        Ok(Self::from_dir(output, version))
+    }
+}
+
+/// Whether `release` is a release of Lua `version`.
+/// The release must be a plain version, as it is part of the download URL.
+fn is_release_of(version: &LuaVersion, release: &str) -> bool {
+    let is_plain = !release.is_empty()
+        && release
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-');
+    let prefix = if version.is_luajit() {
+        "2.".to_string()
+    } else {
+        format!("{}.", version.version_compatibility_str())
+    };
+    is_plain && release.starts_with(&prefix)
+}
+
+fn known_checksum(file_name: &str) -> Option<&'static str> {
+    KNOWN_CHECKSUMS
+        .iter()
+        .find(|(known, _)| *known == file_name)
+        .map(|(_, checksum)| *checksum)
+}
+
+fn source_file_name(version: &LuaVersion, release: &str) -> String {
+    if version.is_luajit() {
+        format!("LuaJIT-{}.tar.gz", release)
+    } else {
+        format!("lua-{}.tar.gz", release)
+    }
+}
+
+fn source_url(version: &LuaVersion, file_name: &str) -> Url {
+    let base = if version.is_luajit() {
+        "https://luajit.org/download/"
+    } else {
+        "https://www.lua.org/ftp/"
+    };
+    // The file name is made up of characters that are valid in a URL path.
+    format!("{}{}", base, file_name).parse().unwrap()
+}
+
+/// The headers that are installed into the `include` directory.
+fn headers(version: &LuaVersion) -> Vec<&'static str> {
+    let mut headers = vec!["lua.h", "luaconf.h", "lualib.h", "lauxlib.h"];
+    if version.is_luajit() {
+        headers.extend(["lua.hpp", "luajit.h"]);
+    }
+    headers
+}
+
+/// The file name of the static library that [`LuaInstallation::from_dir`] expects.
+fn static_lib_file_name(version: &LuaVersion) -> String {
+    if cfg!(target_env = "msvc") {
+        format!("{}.lib", default_lib_name(version))
+    } else {
+        format!("lib{}.a", default_lib_name(version))
+    }
+}
+
+/// Compile the Lua library from the sources in `source_dir`, in the same way as `lua_src`,
+/// returning the path of the static library.
+fn build_lua(
+    source_dir: &Path,
+    version: &LuaVersion,
+    build_dir: &Path,
+) -> Result<PathBuf, BuildLuaReleaseError> {
+    let host = Triple::host();
+    std::fs::create_dir_all(build_dir)?;
+    let mut build = cc::Build::new();
+    build
+        .cargo_metadata(false)
+        .host(std::env::consts::OS)
+        .opt_level(2)
+        .out_dir(build_dir)
+        .target(&host.to_string())
+        .warnings(false);
+    if cfg!(target_os = "linux") {
+        build.define("LUA_USE_LINUX", None);
+    } else if cfg!(target_os = "macos") {
+        build.define("LUA_USE_MACOSX", None);
+    } else if cfg!(unix) {
+        build
+            .define("LUA_USE_POSIX", None)
+            .define("LUA_USE_DLOPEN", None);
+    }
+    match version {
+        LuaVersion::Lua52 => {
+            build.define("LUA_COMPAT_ALL", None);
+        }
+        LuaVersion::Lua53 => {
+            build.define("LUA_COMPAT_5_2", None);
+        }
+        LuaVersion::Lua54 => {
+            build.define("LUA_COMPAT_5_3", None);
+        }
+        _ => {}
+    }
+    let files = WalkDir::new(source_dir)
+        .max_depth(1)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_map(Result::ok)
+        .map(|entry| entry.into_path())
+        .filter(|path| {
+            path.extension().is_some_and(|extension| extension == "c")
+                // The interpreter and compiler, which are not part of the library.
+                && !path.file_name().is_some_and(|name| {
+                    ["lua.c", "luac.c", "print.c"].contains(&name.to_string_lossy().as_ref())
+                })
+        });
+    build
+        .include(source_dir)
+        .files(files)
+        .try_compile(default_lib_name(version))?;
+    Ok(build_dir.join(static_lib_file_name(version)))
+}
+
+/// Build the static LuaJIT library with `make` in `source_dir`,
+/// returning the path of the static library.
+fn build_luajit(
+    source_dir: &Path,
+    version: &LuaVersion,
+    config: &Config,
+) -> Result<PathBuf, BuildLuaReleaseError> {
+    let make = config.make_cmd().clone();
+    let mut command = Command::new(&make);
+    command.current_dir(source_dir).arg("BUILDMODE=static");
+    if matches!(version, LuaVersion::LuaJIT52) {
+        command.arg("XCFLAGS=-DLUAJIT_ENABLE_LUA52COMPAT");
+    }
+    let output = command
+        .output()
+        .map_err(|err| BuildLuaReleaseError::RunMake(make.clone(), err))?;
+    if !output.status.success() {
+        return Err(BuildLuaReleaseError::MakeFailed(
+            make,
+            String::from_utf8_lossy(&output.stderr).into_owned(),
+        ));
+    }
+    Ok(source_dir.join("libluajit.a"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validate_release() {
+        assert!(is_release_of(&LuaVersion::Lua54, "5.4.6"));
+        assert!(!is_release_of(&LuaVersion::Lua54, "5.3.6"));
+        assert!(!is_release_of(&LuaVersion::Lua51, "5.10.0"));
+        assert!(!is_release_of(&LuaVersion::Lua54, "5.4.6/../../x"));
+        assert!(is_release_of(&LuaVersion::LuaJIT, "2.1.0-beta3"));
+        assert!(is_release_of(&LuaVersion::LuaJIT52, "2.0.5"));
+        assert!(!is_release_of(&LuaVersion::LuaJIT, "5.1.5"));
+        assert_eq!(
+            known_checksum(&source_file_name(&LuaVersion::Lua54, "5.4.6")),
+            Some("7d5ea1b9cb6aa0b59ca3dde1c6adcb57ef83a1ba8e5432c0ecd06bf439b3ad88")
+        );
+        assert_eq!(
+            known_checksum(&source_file_name(&LuaVersion::Lua54, "5.4.0")),
+            None
+        );
+    }
+}
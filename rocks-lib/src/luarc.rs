@@ -0,0 +1,215 @@
+//! Generating `.luarc.json` files, so that lua-language-server
+//! can resolve `require`s of the rocks that are installed in a tree.
+
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+use itertools::Itertools;
+use serde_json::{Map, Value};
+use thiserror::Error;
+
+use crate::tree::Tree;
+
+pub const LUARC_FILE_NAME: &str = ".luarc.json";
+
+#[derive(Error, Debug)]
+pub enum LuarcError {
+    #[error("IO operation failed: {0}")]
+    Io(#[from] io::Error),
+    #[error("failed to parse {0}: {1}")]
+    Parse(PathBuf, serde_json::Error),
+    #[error("expected {0} to contain a JSON object")]
+    NotAnObject(PathBuf),
+    #[error("expected `workspace.library` in {0} to be a list of paths")]
+    InvalidLibrary(PathBuf),
+}
+
+/// The directories containing the Lua sources of the rocks installed in `tree`.
+pub fn library_paths(tree: &Tree) -> io::Result<Vec<PathBuf>> {
+    Ok(tree
+        .list()?
+        .into_values()
+        .flatten()
+        .map(|package| tree.rock_layout(&package).src)
+        .sorted()
+        .dedup()
+        .collect_vec())
+}
+
+/// The directories that the library paths of `tree` can point into:
+/// its root, and the `lib` and `src` directories of its rocks,
+/// which are outside of the root with the FHS and luarocks layouts.
+fn tree_dirs(tree: &Tree) -> io::Result<Vec<PathBuf>> {
+    let shared_dirs = tree
+        .shared_lua_dirs()
+        .map(|(lib, src)| vec![lib, src])
+        .unwrap_or_default();
+    let rock_dirs = tree.list()?.into_values().flatten().flat_map(|package| {
+        let layout = tree.rock_layout(&package);
+        [layout.lib, layout.src]
+    });
+    Ok(std::iter::once(tree.root())
+        .chain(shared_dirs)
+        .chain(rock_dirs)
+        .sorted()
+        .dedup()
+        .collect_vec())
+}
+
+/// Write the Lua source directories of the rocks installed in `tree`
+/// to the `workspace.library` of the `.luarc.json` at `path`, creating it if necessary.
+///
+/// Entries that point into the tree, including its shared `lib/lua` and `share/lua` directories,
+/// are replaced, while all other keys and library entries are left untouched,
+/// so that running this repeatedly yields the same result.
+pub fn update_luarc(path: &Path, tree: &Tree) -> Result<(), LuarcError> {
+    let mut luarc = match std::fs::read_to_string(path) {
+        Ok(content) if content.trim().is_empty() => Value::Object(Map::new()),
+        Ok(content) => serde_json::from_str(&content)
+            .map_err(|err| LuarcError::Parse(path.to_path_buf(), err))?,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Value::Object(Map::new()),
+        Err(err) => return Err(err.into()),
+    };
+
+    let root = luarc
+        .as_object_mut()
+        .ok_or_else(|| LuarcError::NotAnObject(path.to_path_buf()))?;
+
+    // lua-language-server accepts both nested and dotted keys.
+    let library = if let Some(library) = root.get_mut("workspace.library") {
+        library
+    } else {
+        root.entry("workspace")
+            .or_insert_with(|| Value::Object(Map::new()))
+            .as_object_mut()
+            .ok_or_else(|| LuarcError::NotAnObject(path.to_path_buf()))?
+            .entry("library")
+            .or_insert_with(|| Value::Array(Vec::new()))
+    };
+
+    let library = library
+        .as_array_mut()
+        .ok_or_else(|| LuarcError::InvalidLibrary(path.to_path_buf()))?;
+
+    let tree_dirs = tree_dirs(tree)?;
+    library.retain(|entry| {
+        entry.as_str().is_none_or(|entry| {
+            !tree_dirs
+                .iter()
+                .any(|tree_dir| Path::new(entry).starts_with(tree_dir))
+        })
+    });
+    library.extend(
+        library_paths(tree)?
+            .into_iter()
+            .map(|path| Value::String(path.to_string_lossy().to_string())),
+    );
+
+    std::fs::write(path, serde_json::to_string_pretty(&luarc).unwrap() + "\n")?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_fs::prelude::{FileWriteStr as _, PathChild as _, PathCopy as _};
+    use serde_json::json;
+
+    use crate::{config::LuaVersion, tree::TreeLayout};
+
+    use super::*;
+
+    #[test]
+    fn update_luarc_is_idempotent() {
+        let tree_path =
+            PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("resources/test/sample-tree");
+        let temp = assert_fs::TempDir::new().unwrap();
+        temp.copy_from(&tree_path, &["**"]).unwrap();
+        let tree = Tree::new(temp.to_path_buf(), LuaVersion::Lua51).unwrap();
+        let library = library_paths(&tree).unwrap();
+        assert!(!library.is_empty());
+
+        let luarc = temp.child(LUARC_FILE_NAME);
+        luarc
+            .write_str(
+                r#"{
+                    "runtime.version": "LuaJIT",
+                    "workspace": { "checkThirdParty": false, "library": ["/usr/share/nvim/runtime"] }
+                }"#,
+            )
+            .unwrap();
+
+        update_luarc(luarc.path(), &tree).unwrap();
+        let first = std::fs::read_to_string(luarc.path()).unwrap();
+        update_luarc(luarc.path(), &tree).unwrap();
+        let second = std::fs::read_to_string(luarc.path()).unwrap();
+        assert_eq!(first, second);
+
+        let expected_library = std::iter::once("/usr/share/nvim/runtime".to_string())
+            .chain(
+                library
+                    .iter()
+                    .map(|path| path.to_string_lossy().to_string()),
+            )
+            .collect_vec();
+        assert_eq!(
+            serde_json::from_str::<Value>(&second).unwrap(),
+            json!({
+                "runtime.version": "LuaJIT",
+                "workspace": { "checkThirdParty": false, "library": expected_library }
+            })
+        );
+    }
+
+    #[test]
+    fn update_luarc_creates_file() {
+        let tree_path =
+            PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("resources/test/sample-tree");
+        let temp = assert_fs::TempDir::new().unwrap();
+        temp.copy_from(&tree_path, &["**"]).unwrap();
+        let tree = Tree::new(temp.to_path_buf(), LuaVersion::Lua51).unwrap();
+
+        let luarc = temp.child(LUARC_FILE_NAME);
+        update_luarc(luarc.path(), &tree).unwrap();
+
+        let luarc: Value =
+            serde_json::from_str(&std::fs::read_to_string(luarc.path()).unwrap()).unwrap();
+        assert_eq!(
+            luarc["workspace"]["library"].as_array().unwrap().len(),
+            library_paths(&tree).unwrap().len()
+        );
+    }
+
+    #[test]
+    fn update_luarc_replaces_fhs_library_paths() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let tree =
+            Tree::new_with_layout(temp.to_path_buf(), LuaVersion::Lua51, TreeLayout::Fhs).unwrap();
+        let (lib, src) = tree.shared_lua_dirs().unwrap();
+        assert!(!src.starts_with(tree.root()));
+
+        let luarc = temp.child(LUARC_FILE_NAME);
+        luarc
+            .write_str(
+                &json!({
+                    "workspace.library": [
+                        "/usr/share/nvim/runtime",
+                        src.to_string_lossy(),
+                        lib.to_string_lossy(),
+                    ]
+                })
+                .to_string(),
+            )
+            .unwrap();
+        update_luarc(luarc.path(), &tree).unwrap();
+
+        let luarc: Value =
+            serde_json::from_str(&std::fs::read_to_string(luarc.path()).unwrap()).unwrap();
+        assert_eq!(
+            luarc,
+            json!({ "workspace.library": ["/usr/share/nvim/runtime"] })
+        );
+    }
+}
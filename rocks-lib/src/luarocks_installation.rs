@@ -89,7 +89,8 @@ impl LuaRocksInstallation {
     pub fn new(config: &Config) -> Result<Self, LuaRocksError> {
         let config = config.clone().with_tree(config.luarocks_tree().clone());
         let luarocks_installation = Self {
-            tree: Tree::new(config.luarocks_tree().clone(), LuaVersion::from(&config)?)?,
+            tree: Tree::new(config.luarocks_tree().clone(), LuaVersion::from(&config)?)?
+                .with_lock_timeout(*config.lock_timeout()),
             config,
         };
         Ok(luarocks_installation)
@@ -106,6 +107,7 @@ impl LuaRocksInstallation {
             let rockspec = Rockspec::new(LUAROCKS_ROCKSPEC).unwrap();
             let pkg = build(
                 rockspec,
+                None,
                 PinnedState::Unpinned,
                 LockConstraint::Constrained(luarocks_req.version_req().clone()),
                 BuildBehaviour::NoForce,
@@ -119,32 +121,44 @@ impl LuaRocksInstallation {
         Ok(())
     }
 
+    /// Install the `build_dependencies` of `rockspec` into this installation's tree,
+    /// which serves as the build tree: the build backends can use the rocks in it,
+    /// but they are not added to the tree the rock is installed into.
+    /// `build_backend` is the name of the rock's luarocks build backend, if it has one.
     pub async fn install_build_dependencies(
         &self,
-        build_backend: &str,
+        build_backend: Option<&str>,
         rockspec: &Rockspec,
         progress_arc: Arc<Progress<MultiProgress>>,
     ) -> Result<(), InstallBuildDependenciesError> {
-        let progress = Arc::clone(&progress_arc);
-        let mut lockfile = self.tree.lockfile()?;
-        let package_db = RemotePackageDB::from_config(&self.config).await?;
-        let build_dependencies = match rockspec.rockspec_format {
-            Some(RockspecFormat::_1_0 | RockspecFormat::_2_0) => {
+        let build_dependencies = match (rockspec.rockspec_format.as_ref(), build_backend) {
+            (Some(RockspecFormat::_1_0 | RockspecFormat::_2_0), Some(build_backend)) => {
                 // XXX: rockspec formats < 3.0 don't support `build_dependencies`,
                 // so we have to fetch the build backend from the dependencies.
                 rockspec
                     .dependencies
-                    .current_platform()
+                    .for_platform(self.config.platform())
                     .iter()
                     .filter(|dep| dep.name().to_string().contains(build_backend))
                     .cloned()
                     .collect_vec()
             }
-            _ => rockspec.build_dependencies.current_platform().to_vec(),
+            (Some(RockspecFormat::_1_0 | RockspecFormat::_2_0), None) => Vec::new(),
+            _ => rockspec
+                .build_dependencies
+                .for_platform(self.config.platform())
+                .to_vec(),
         }
         .into_iter()
         .map(|dep| (BuildBehaviour::NoForce, dep))
         .collect_vec();
+        if build_dependencies.is_empty() {
+            return Ok(());
+        }
+
+        let progress = Arc::clone(&progress_arc);
+        let mut lockfile = self.tree.lockfile()?;
+        let package_db = RemotePackageDB::from_config(&self.config).await?;
 
         let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
         let pin = PinnedState::Unpinned;
@@ -152,8 +166,9 @@ impl LuaRocksInstallation {
             tx,
             build_dependencies,
             pin,
+            Vec::new(),
             Arc::new(package_db),
-            Arc::new(lockfile.clone()),
+            Arc::new(lockfile.snapshot()),
             &self.config,
             progress_arc,
         )
@@ -176,6 +191,7 @@ impl LuaRocksInstallation {
                 let rockspec = install_spec.rockspec;
                 let pkg = crate::build::build(
                     rockspec,
+                    install_spec.spec.namespace.clone(),
                     pin,
                     install_spec.spec.constraint(),
                     install_spec.build_behaviour,
@@ -232,15 +248,28 @@ impl LuaRocksInstallation {
             &dest_dir_str,
             &rockspec_path_str,
         ];
-        self.exec(args, build_dir, lua)
+        self.exec(args, build_dir, lua).map(|_| ())
+    }
+
+    /// List the rocks that luarocks finds in the tree at `tree_root`,
+    /// in the format of `luarocks list --porcelain`.
+    pub fn list(
+        self,
+        tree_root: &Path,
+        lua: &LuaInstallation,
+    ) -> Result<String, ExecLuaRocksError> {
+        let tree_root_str = tree_root.to_string_lossy().to_string();
+        let args = vec!["list", "--porcelain", "--tree", &tree_root_str];
+        self.exec(args, tree_root, lua)
     }
 
+    /// Run luarocks, returning its standard output.
     fn exec(
         self,
         args: Vec<&str>,
         cwd: &Path,
         lua: &LuaInstallation,
-    ) -> Result<(), ExecLuaRocksError> {
+    ) -> Result<String, ExecLuaRocksError> {
         let luarocks_paths = Paths::from_tree(self.tree)?;
         // Ensure a pure environment so we can do parallel builds
         let temp_dir = TempDir::new("rocks-run-luarocks").unwrap();
@@ -271,7 +300,7 @@ variables = {{
             .env("LUAROCKS_CONFIG", luarocks_config)
             .output()?;
         if output.status.success() {
-            Ok(())
+            Ok(String::from_utf8_lossy(&output.stdout).into())
         } else {
             Err(ExecLuaRocksError::CommandFailure {
                 status: output.status,
@@ -1,14 +1,22 @@
 use itertools::Itertools;
 use mlua::{Lua, LuaSerdeExt};
-use reqwest::{header::ToStrError, Client};
+use reqwest::header::ToStrError;
+use reqwest::Url;
 use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, LazyLock, Mutex};
 use std::time::SystemTime;
 use thiserror::Error;
-use tokio::{fs, io};
+use tokio::{fs, io, sync::OnceCell};
 
 use crate::{
-    config::{Config, LuaVersion},
-    package::{PackageName, PackageReq, PackageSpec, PackageVersion, RemotePackage},
+    config::{
+        env_vars::{self, EnvVarError},
+        Config, LuaVersion,
+    },
+    package::{
+        PackageName, PackageNamespace, PackageReq, PackageSpec, PackageVersion, RemotePackage,
+    },
 };
 
 #[derive(Error, Debug)]
@@ -21,13 +29,13 @@ pub enum ManifestFromServerError {
     InvalidDate(#[from] httpdate::Error),
     #[error("non-ASCII characters returned in response header: {0}")]
     InvalidHeader(#[from] ToStrError),
+    #[error(transparent)]
+    EnvVar(#[from] EnvVarError),
 }
 
-async fn manifest_from_server(
-    url: &str,
-    config: &Config,
-) -> Result<String, ManifestFromServerError> {
-    let manifest_filename = "manifest".to_string()
+/// The file name of the manifest for the configured Lua version, e.g. `manifest-5.1`.
+fn manifest_filename(config: &Config) -> String {
+    "manifest".to_string()
         + &config
             .lua_version()
             .filter(|lua_version| {
@@ -45,24 +53,42 @@ async fn manifest_from_server(
                     _ => None,
                 }))
             .map(|s| format!("-{}", s))
-            .unwrap_or_default();
+            .unwrap_or_default()
+}
+
+async fn manifest_from_server(
+    url: &str,
+    namespace: Option<&PackageNamespace>,
+    config: &Config,
+) -> Result<String, ManifestFromServerError> {
+    let manifest_filename = manifest_filename(config);
     let url = url.trim_end_matches('/').to_string() + "/" + &manifest_filename;
+    let request_url = env_vars::expand_env_vars(&url)?;
+    let redact = |err| env_vars::redact_error(err, &url);
 
     // Stores a path to the manifest cache (this allows us to operate on a manifest without
     // needing to pull it from the luarocks servers each time).
-    let cache = config.cache_dir().join(&manifest_filename);
+    // Namespaced manifests are cached separately, as they share the same file name.
+    let cache = match namespace {
+        Some(namespace) => config
+            .cache_dir()
+            .join("manifests")
+            .join(namespace.to_string())
+            .join(&manifest_filename),
+        None => config.cache_dir().join(&manifest_filename),
+    };
 
     // Ensure all intermediate directories for the cache file are created (e.g. `~/.cache/rocks/manifest`)
     fs::create_dir_all(cache.parent().unwrap()).await?;
 
-    let client = Client::new();
+    let client = config.http_client();
 
     // Read the metadata of the local cache and attempt to get the last modified date.
     if let Ok(metadata) = fs::metadata(&cache).await {
         let last_modified_local: SystemTime = metadata.modified()?;
 
         // Ask the server for the last modified date of its manifest.
-        let response = client.head(&url).send().await?;
+        let response = client.head(&request_url).send().await.map_err(redact)?;
 
         if let Some(last_modified_header) = response.headers().get("Last-Modified") {
             let server_last_modified = httpdate::parse_http_date(last_modified_header.to_str()?)?;
@@ -71,7 +97,14 @@ async fn manifest_from_server(
             if server_last_modified > last_modified_local {
                 // Since we only pulled in the headers previously we must now request the entire
                 // manifest from scratch.
-                let new_manifest_content = client.get(&url).send().await?.text().await?;
+                let new_manifest_content = client
+                    .get(&request_url)
+                    .send()
+                    .await
+                    .map_err(redact)?
+                    .text()
+                    .await
+                    .map_err(redact)?;
                 fs::write(&cache, &new_manifest_content).await?;
 
                 return Ok(new_manifest_content);
@@ -84,13 +117,77 @@ async fn manifest_from_server(
 
     // If our cache file does not exist then pull the whole manifest.
 
-    let new_manifest = client.get(url).send().await?.text().await?;
+    let new_manifest = client
+        .get(&request_url)
+        .send()
+        .await
+        .map_err(redact)?
+        .text()
+        .await
+        .map_err(redact)?;
 
     fs::write(&cache, &new_manifest).await?;
 
     Ok(new_manifest)
 }
 
+/// The metadata of the manifests that have been pulled during this invocation, by manifest URL.
+/// Parsing a manifest is expensive, so all [`Manifest`]s with the same URL share their metadata,
+/// however often a [`RemotePackageDB`](crate::remote_package_db::RemotePackageDB) is constructed.
+static METADATA_CACHE: LazyLock<Mutex<HashMap<String, SharedMetadata>>> =
+    LazyLock::new(Mutex::default);
+
+/// The metadata of a manifest, which is parsed by the first [`Manifest`] that needs it.
+type SharedMetadata = Arc<OnceCell<Arc<ManifestMetadata>>>;
+
+/// Pull and parse the manifest at `url`, unless it has already been parsed during this invocation.
+async fn metadata_from_server(
+    url: &str,
+    namespace: Option<&PackageNamespace>,
+    config: &Config,
+) -> Result<Arc<ManifestMetadata>, ManifestError> {
+    let manifest_url = format!(
+        "{}/{}",
+        url.trim_end_matches('/'),
+        manifest_filename(config)
+    );
+    let cell = METADATA_CACHE
+        .lock()
+        .unwrap()
+        .entry(manifest_url)
+        .or_default()
+        .clone();
+    let metadata = cell
+        .get_or_try_init(|| async {
+            if let Some(dir) = file_url_path(url) {
+                let metadata = ManifestMetadata::from_dir(&dir)
+                    .await
+                    .map_err(ManifestFromServerError::from)?;
+                return Ok::<_, ManifestError>(Arc::new(metadata));
+            }
+            let manifest = manifest_from_server(url, namespace, config).await?;
+            Ok(Arc::new(ManifestMetadata::new(&manifest)?))
+        })
+        .await?;
+    Ok(metadata.clone())
+}
+
+/// The local path that a `file://` URL points to.
+/// A `file://` server is a local directory of `.rockspec` and `.rock` files, e.g. an offline mirror,
+/// which is indexed by scanning it instead of pulling a manifest.
+pub(crate) fn file_url_path(url: &str) -> Option<PathBuf> {
+    if !url.starts_with("file://") {
+        return None;
+    }
+    Url::parse(url).ok()?.to_file_path().ok()
+}
+
+#[cfg(test)]
+thread_local! {
+    /// The number of manifests that have been parsed on the current thread.
+    static PARSE_COUNT: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+}
+
 #[derive(Clone)]
 pub(crate) struct ManifestMetadata {
     pub repository: HashMap<PackageName, HashMap<PackageVersion, Vec<ManifestRockEntry>>>,
@@ -119,6 +216,9 @@ pub enum ManifestError {
 
 impl ManifestMetadata {
     pub fn new(manifest: &String) -> Result<Self, ManifestLuaError> {
+        #[cfg(test)]
+        PARSE_COUNT.with(|count| count.set(count.get() + 1));
+
         let lua = Lua::new();
 
         lua.load(manifest).exec()?;
@@ -131,19 +231,84 @@ impl ManifestMetadata {
         Ok(manifest)
     }
 
+    /// Index the `.rockspec` and `.rock` files in `dir`, which must be named as on a luarocks server,
+    /// e.g. `foo-1.0.0-1.rockspec`, `foo-1.0.0-1.src.rock` or `foo-1.0.0-1.linux-x86_64.rock`.
+    /// Other files are ignored.
+    pub async fn from_dir(dir: &Path) -> io::Result<Self> {
+        let mut repository: HashMap<PackageName, HashMap<PackageVersion, Vec<ManifestRockEntry>>> =
+            HashMap::new();
+        let mut entries = fs::read_dir(dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            if !entry.file_type().await?.is_file() {
+                continue;
+            }
+            let file_name = entry.file_name().to_string_lossy().to_string();
+            if let Some((name, version, arch)) = parse_rock_file_name(&file_name) {
+                repository
+                    .entry(name)
+                    .or_default()
+                    .entry(version)
+                    .or_default()
+                    .push(ManifestRockEntry {
+                        arch,
+                        labels: None,
+                        yanked: false,
+                    });
+            }
+        }
+        Ok(Self { repository })
+    }
+
     pub fn has_rock(&self, rock_name: &PackageName) -> bool {
         self.repository.contains_key(rock_name)
     }
 
-    pub fn latest_version(&self, rock_name: &PackageName) -> Option<&PackageVersion> {
+    /// The latest version of a rock, excluding the versions that `skip` returns `true` for.
+    pub fn latest_version(
+        &self,
+        rock_name: &PackageName,
+        skip: impl Fn(&PackageVersion) -> bool,
+    ) -> Option<&PackageVersion> {
         if !self.has_rock(rock_name) {
             return None;
         }
 
-        self.repository[rock_name].keys().sorted().last()
+        self.repository[rock_name]
+            .keys()
+            .filter(|version| !skip(version))
+            .max()
+    }
+
+    /// Whether the manifest marks a version of a rock as yanked,
+    /// i.e. as a version that should no longer be installed unless it is requested explicitly.
+    pub fn is_yanked(&self, rock_name: &PackageName, version: &PackageVersion) -> bool {
+        self.repository
+            .get(rock_name)
+            .and_then(|versions| versions.get(version))
+            .is_some_and(|entries| entries.iter().any(|entry| entry.yanked))
+    }
+
+    /// The labels of a version of a rock, if the manifest lists them.
+    /// The manifests of luarocks servers usually don't.
+    pub fn labels(
+        &self,
+        rock_name: &PackageName,
+        version: &PackageVersion,
+    ) -> Option<&Vec<String>> {
+        self.repository
+            .get(rock_name)?
+            .get(version)?
+            .iter()
+            .find_map(|entry| entry.labels.as_ref())
     }
 
-    pub fn latest_match(&self, lua_package_req: &PackageReq) -> Option<PackageSpec> {
+    /// The latest version of a rock that matches the requirement,
+    /// excluding the versions that `skip` returns `true` for.
+    pub fn latest_match(
+        &self,
+        lua_package_req: &PackageReq,
+        skip: impl Fn(&PackageVersion) -> bool,
+    ) -> Option<PackageSpec> {
         if !self.has_rock(lua_package_req.name()) {
             return None;
         }
@@ -152,7 +317,7 @@ impl ManifestMetadata {
             .keys()
             .sorted()
             .rev()
-            .find(|version| lua_package_req.version_req().matches(version))?;
+            .find(|version| lua_package_req.version_req().matches(version) && !skip(version))?;
 
         Some(PackageSpec::new(
             lua_package_req.name().to_owned(),
@@ -164,34 +329,68 @@ impl ManifestMetadata {
 #[derive(Clone)]
 pub(crate) struct Manifest {
     server_url: String,
-    metadata: ManifestMetadata,
+    namespace: Option<PackageNamespace>,
+    metadata: Arc<ManifestMetadata>,
 }
 
 impl Manifest {
-    pub fn new(server_url: &str, metadata: ManifestMetadata) -> Self {
+    /// `metadata` can be shared with other manifests of the same server, e.g. via the metadata cache.
+    pub fn new(server_url: &str, metadata: impl Into<Arc<ManifestMetadata>>) -> Self {
         Self {
             server_url: server_url.into(),
-            metadata,
+            namespace: None,
+            metadata: metadata.into(),
         }
     }
 
     pub async fn from_config(server_url: &str, config: &Config) -> Result<Self, ManifestError> {
-        let manifest = crate::manifest::manifest_from_server(server_url, config).await?;
-        let metadata = ManifestMetadata::new(&manifest)?;
+        let metadata = metadata_from_server(server_url, None, config).await?;
         Ok(Self::new(server_url, metadata))
     }
+
+    /// Fetch the manifest of a namespace on the given server,
+    /// which luarocks servers host at `<server>/manifests/<namespace>/`.
+    pub async fn from_config_namespaced(
+        server_url: &str,
+        namespace: &PackageNamespace,
+        config: &Config,
+    ) -> Result<Self, ManifestError> {
+        let server_url = format!(
+            "{}/manifests/{}",
+            server_url.trim_end_matches('/'),
+            namespace
+        );
+        let metadata = metadata_from_server(&server_url, Some(namespace), config).await?;
+        Ok(Self {
+            namespace: Some(namespace.clone()),
+            ..Self::new(&server_url, metadata)
+        })
+    }
+
     pub fn server_url(&self) -> &String {
         &self.server_url
     }
+    pub fn namespace(&self) -> Option<&PackageNamespace> {
+        self.namespace.as_ref()
+    }
     pub fn metadata(&self) -> &ManifestMetadata {
         &self.metadata
     }
-    pub fn search(&self, package_req: &PackageReq) -> Option<RemotePackage> {
-        if !self.metadata().has_rock(package_req.name()) {
+    /// Search for the latest version of a rock that matches the requirement,
+    /// excluding the versions that `skip` returns `true` for.
+    pub fn search(
+        &self,
+        package_req: &PackageReq,
+        skip: impl Fn(&PackageVersion) -> bool,
+    ) -> Option<RemotePackage> {
+        if package_req
+            .namespace()
+            .is_some_and(|namespace| self.namespace() != Some(namespace))
+        {
             None
         } else {
             Some(RemotePackage {
-                package: self.metadata().latest_match(package_req).unwrap(),
+                package: self.metadata().latest_match(package_req, skip)?,
                 server_url: self.server_url().into(),
             })
         }
@@ -202,6 +401,12 @@ impl Manifest {
 pub struct ManifestRockEntry {
     /// e.g. "linux-x86_64", "rockspec", "src", ...
     pub arch: String,
+    /// The rock's `description.labels`, if the server includes them in its manifest.
+    #[serde(default)]
+    pub labels: Option<Vec<String>>,
+    /// Whether the server marks this version as yanked.
+    #[serde(default)]
+    pub yanked: bool,
 }
 
 /// Intermediate implementation for deserializing
@@ -233,6 +438,21 @@ fn from_intermediate(intermediate: IntermediateManifest) -> ManifestMetadata {
     ManifestMetadata { repository }
 }
 
+/// Split the file name of a rockspec or rock into the package name, version and architecture.
+fn parse_rock_file_name(file_name: &str) -> Option<(PackageName, PackageVersion, String)> {
+    let (stem, arch) = match file_name.strip_suffix(".rockspec") {
+        Some(stem) => (stem, "rockspec"),
+        None => file_name.strip_suffix(".rock")?.rsplit_once('.')?,
+    };
+    // The version is the last two components, e.g. `1.0.0-1` in `lua-cjson-1.0.0-1`.
+    let mut parts = stem.rsplitn(3, '-');
+    let revision = parts.next()?;
+    let version = parts.next()?;
+    let name = parts.next().filter(|name| !name.is_empty())?;
+    let version = PackageVersion::parse(&format!("{}-{}", version, revision)).ok()?;
+    Some((name.into(), version, arch.to_string()))
+}
+
 #[cfg(test)]
 mod tests {
     use std::path::PathBuf;
@@ -240,7 +460,10 @@ mod tests {
     use httptest::{matchers::request, responders::status_code, Expectation, Server};
     use serial_test::serial;
 
-    use crate::{config::ConfigBuilder, package::PackageReq};
+    use crate::{
+        config::ConfigBuilder, operations::download_rockspec, package::PackageReq,
+        progress::Progress, remote_package_db::RemotePackageDB,
+    };
 
     use super::*;
 
@@ -271,7 +494,7 @@ mod tests {
             .lua_version(Some(crate::config::LuaVersion::LuaJIT))
             .build()
             .unwrap();
-        manifest_from_server(&url_str, &config).await.unwrap();
+        manifest_from_server(&url_str, None, &config).await.unwrap();
     }
 
     #[tokio::test]
@@ -288,7 +511,7 @@ mod tests {
             .build()
             .unwrap();
 
-        manifest_from_server(&url_str, &config).await.unwrap();
+        manifest_from_server(&url_str, None, &config).await.unwrap();
     }
 
     #[tokio::test]
@@ -307,10 +530,44 @@ mod tests {
             .lua_version(Some(crate::config::LuaVersion::Lua51))
             .build()
             .unwrap();
-        let result = manifest_from_server(&url_str, &config).await.unwrap();
+        let result = manifest_from_server(&url_str, None, &config).await.unwrap();
         assert_eq!(result, manifest_content);
     }
 
+    #[tokio::test]
+    pub async fn parse_manifest_once_per_invocation() {
+        let server = Server::run();
+        server.expect(
+            Expectation::matching(request::path("/manifest-5.1"))
+                .times(1)
+                .respond_with(status_code(200).body(
+                    r#"
+repository = {
+    neorg = { ["8.0.0-1"] = { { arch = "rockspec" } } },
+    ["pathlib.nvim"] = { ["2.2.3-1"] = { { arch = "rockspec" } } },
+}
+"#,
+                )),
+        );
+        let cache_dir = assert_fs::TempDir::new().unwrap();
+        let config = ConfigBuilder::new()
+            .server(Some(server.url_str("/")))
+            .cache_dir(Some(cache_dir.to_path_buf()))
+            .lua_version(Some(crate::config::LuaVersion::Lua51))
+            .build()
+            .unwrap();
+        let parse_count = || PARSE_COUNT.with(|count| count.get());
+        let initial_count = parse_count();
+
+        for package_req in ["neorg", "pathlib.nvim"] {
+            let package_db = RemotePackageDB::from_config(&config).await.unwrap();
+            assert!(package_db
+                .latest_match(&package_req.parse().unwrap())
+                .is_some());
+        }
+        assert_eq!(parse_count() - initial_count, 1);
+    }
+
     #[tokio::test]
     pub async fn parse_metadata_from_empty_manifest() {
         let manifest = "
@@ -338,6 +595,104 @@ mod tests {
         let metadata = ManifestMetadata::new(&manifest).unwrap();
 
         let package_req: PackageReq = "30log > 1.3.0".parse().unwrap();
-        assert!(metadata.latest_match(&package_req).is_none());
+        assert!(metadata.latest_match(&package_req, |_| false).is_none());
+    }
+
+    #[test]
+    fn parse_rock_file_names() {
+        let version = PackageVersion::parse("2.1.0-1").unwrap();
+        assert_eq!(
+            parse_rock_file_name("lua-cjson-2.1.0-1.rockspec"),
+            Some(("lua-cjson".into(), version.clone(), "rockspec".into()))
+        );
+        assert_eq!(
+            parse_rock_file_name("lua-cjson-2.1.0-1.src.rock"),
+            Some(("lua-cjson".into(), version.clone(), "src".into()))
+        );
+        assert_eq!(
+            parse_rock_file_name("lua-cjson-2.1.0-1.linux-x86_64.rock"),
+            Some(("lua-cjson".into(), version, "linux-x86_64".into()))
+        );
+        assert_eq!(parse_rock_file_name("README.md"), None);
+        assert_eq!(parse_rock_file_name("2.1.0-1.rockspec"), None);
+    }
+
+    #[tokio::test]
+    async fn resolve_from_local_directory() {
+        let dir = assert_fs::TempDir::new().unwrap();
+        std::fs::write(
+            dir.join("foo-1.0.0-1.rockspec"),
+            r#"
+package = "foo"
+version = "1.0.0-1"
+source = {
+    url = "https://example.com/foo.tar.gz",
+}
+"#,
+        )
+        .unwrap();
+        for file_name in [
+            "foo-1.0.0-1.src.rock",
+            "foo-2.0.0-1.rockspec",
+            "lua-cjson-2.1.0-1.src.rock",
+            "manifest",
+        ] {
+            std::fs::write(dir.join(file_name), "").unwrap();
+        }
+        let server_url = Url::from_directory_path(dir.path()).unwrap().to_string();
+        let cache_dir = assert_fs::TempDir::new().unwrap();
+        let data_dir = assert_fs::TempDir::new().unwrap();
+        let config = ConfigBuilder::new()
+            .server(Some(server_url))
+            .cache_dir(Some(cache_dir.to_path_buf()))
+            .data_dir(Some(data_dir.to_path_buf()))
+            .lua_version(Some(crate::config::LuaVersion::Lua51))
+            .build()
+            .unwrap();
+        let package_db = RemotePackageDB::from_config(&config).await.unwrap();
+
+        let foo = package_db
+            .latest_match(&"foo < 2.0.0".parse().unwrap())
+            .unwrap();
+        assert_eq!(foo.version().to_string(), "1.0.0-1");
+        let lua_cjson = package_db.latest_match(&"lua-cjson".parse().unwrap());
+        assert_eq!(lua_cjson.unwrap().version().to_string(), "2.1.0-1");
+        assert!(package_db.latest_match(&"bar".parse().unwrap()).is_none());
+        assert!(!cache_dir.join("manifest-5.1").exists());
+
+        let rockspec = download_rockspec(
+            &"foo < 2.0.0".parse().unwrap(),
+            &package_db,
+            &config,
+            &Progress::NoProgress,
+        )
+        .await
+        .unwrap();
+        assert_eq!(rockspec.version.to_string(), "1.0.0-1");
+    }
+
+    #[test]
+    fn parse_labels_from_manifest() {
+        let manifest = r#"
+            repository = {
+                foo = {
+                    ["1.0.0-1"] = {
+                        { arch = "rockspec", labels = { "testing" } },
+                        { arch = "src" },
+                    },
+                },
+                bar = {
+                    ["1.0.0-1"] = { { arch = "rockspec" } },
+                },
+            }
+        "#
+        .to_string();
+        let metadata = ManifestMetadata::new(&manifest).unwrap();
+        let version = PackageVersion::parse("1.0.0-1").unwrap();
+        assert_eq!(
+            metadata.labels(&"foo".into(), &version),
+            Some(&vec!["testing".to_string()])
+        );
+        assert_eq!(metadata.labels(&"bar".into(), &version), None);
     }
 }
@@ -0,0 +1,179 @@
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+use itertools::Itertools;
+use regex::Regex;
+use thiserror::Error;
+use walkdir::WalkDir;
+
+use crate::{
+    config::Config,
+    package::{PackageSpec, PackageVersion},
+    progress::{Progress, ProgressBar},
+    rockspec::Rockspec,
+    tree::Tree,
+};
+
+use super::{fetch_src, FetchSrcError};
+
+#[derive(Error, Debug)]
+pub enum ChangelogError {
+    #[error("IO operation failed: {0}")]
+    Io(#[from] io::Error),
+    #[error("failed to fetch source to look for a changelog: {0}")]
+    FetchSrc(#[from] FetchSrcError),
+}
+
+/// The file names that changelogs are commonly shipped as, compared case-insensitively.
+const CHANGELOG_FILE_NAMES: &[&str] = &[
+    "changelog.md",
+    "changelog",
+    "changelog.txt",
+    "changes.md",
+    "changes",
+    "history.md",
+    "news.md",
+    "news",
+];
+
+/// Find the changelog of the rock that `rockspec` describes.
+/// If the rock is installed in `tree`, its `copy_directories` and documentation are searched.
+/// Otherwise, or if they don't contain a changelog, the rock's source is fetched and searched.
+/// Returns `None` if no changelog was found.
+pub async fn find_changelog(
+    rockspec: &Rockspec,
+    tree: &Tree,
+    config: &Config,
+    progress: &Progress<ProgressBar>,
+) -> Result<Option<String>, ChangelogError> {
+    let req =
+        PackageSpec::new(rockspec.package.clone(), rockspec.version.clone()).into_package_req();
+    if let Some(package) = tree.has_rock(&req) {
+        let layout = tree.rock_layout(&package);
+        let changelog = [&layout.etc, &layout.doc]
+            .into_iter()
+            .find_map(|dir| find_changelog_file(dir));
+        if let Some(path) = changelog {
+            return Ok(Some(std::fs::read_to_string(path)?));
+        }
+    }
+
+    progress.map(|p| {
+        p.set_message(format!(
+            "📥 Fetching source of {}@{} to look for a changelog",
+            rockspec.package, rockspec.version
+        ))
+    });
+    let temp_dir = tempdir::TempDir::new(&rockspec.package.to_string())?;
+    let rock_source = rockspec.source.for_platform(config.platform());
+    fetch_src(temp_dir.path(), rock_source, config, progress).await?;
+    let source_dir = match &rock_source.unpack_dir {
+        Some(unpack_dir) => temp_dir.path().join(unpack_dir),
+        None => temp_dir.path().to_path_buf(),
+    };
+    match find_changelog_file(&source_dir) {
+        Some(path) => Ok(Some(std::fs::read_to_string(path)?)),
+        None => Ok(None),
+    }
+}
+
+/// Find a changelog in `dir` or, failing that, one of its subdirectories.
+fn find_changelog_file(dir: &Path) -> Option<PathBuf> {
+    WalkDir::new(dir)
+        .max_depth(2)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_type().is_file())
+        .filter(|entry| {
+            CHANGELOG_FILE_NAMES
+                .contains(&entry.file_name().to_string_lossy().to_lowercase().as_str())
+        })
+        .min_by_key(|entry| entry.depth())
+        .map(|entry| entry.into_path())
+}
+
+/// The entries of `changelog` that are newer than `since`.
+///
+/// This is best-effort: changelogs are expected to list the newest entries first,
+/// with a heading (e.g. `## [1.2.0] - 2024-01-01`) per version.
+/// Everything before the first heading of a version that is not newer than `since` is kept,
+/// including headings without a version, like `## Unreleased`.
+pub fn changelog_since(changelog: &str, since: &PackageVersion) -> String {
+    let version_pattern = Regex::new(r"\d+(?:\.\d+)+").unwrap();
+    changelog
+        .lines()
+        .take_while(|line| {
+            let heading = match line.trim_start().strip_prefix('#') {
+                Some(heading) => heading,
+                None => return true,
+            };
+            version_pattern
+                .find(heading)
+                .and_then(|version| PackageVersion::parse(version.as_str()).ok())
+                .is_none_or(|version| &version > since)
+        })
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_fs::prelude::{FileWriteStr as _, PathChild as _};
+
+    use super::*;
+
+    const CHANGELOG: &str = "# Changelog
+
+## Unreleased
+
+- Something new
+
+## [2.0.0] - 2024-06-01
+
+- Breaking change
+
+## v1.1.0
+
+- Feature
+
+## 1.0.0
+
+- Initial release";
+
+    #[test]
+    fn changelog_entries_since_version() {
+        let since = PackageVersion::parse("1.1.0").unwrap();
+        assert_eq!(
+            changelog_since(CHANGELOG, &since),
+            "# Changelog
+
+## Unreleased
+
+- Something new
+
+## [2.0.0] - 2024-06-01
+
+- Breaking change
+"
+        );
+        let since = PackageVersion::parse("0.9.0-1").unwrap();
+        assert_eq!(changelog_since(CHANGELOG, &since), CHANGELOG);
+        let since = PackageVersion::parse("3.0.0").unwrap();
+        assert!(!changelog_since(CHANGELOG, &since).contains("2.0.0"));
+    }
+
+    #[test]
+    fn find_changelog_in_subdirectory() {
+        let dir = assert_fs::TempDir::new().unwrap();
+        assert_eq!(find_changelog_file(dir.path()), None);
+        dir.child("doc/CHANGELOG.md").write_str("").unwrap();
+        assert_eq!(
+            find_changelog_file(dir.path()),
+            Some(dir.join("doc/CHANGELOG.md"))
+        );
+        dir.child("NEWS.md").write_str("").unwrap();
+        assert_eq!(find_changelog_file(dir.path()), Some(dir.join("NEWS.md")));
+    }
+}
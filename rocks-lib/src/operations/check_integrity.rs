@@ -0,0 +1,32 @@
+use crate::{
+    lockfile::LocalPackage,
+    tree::{IntegrityViolation, RockManifest, RockManifestError, Tree},
+};
+
+/// The result of checking an installed rock against its `rock_manifest`.
+#[derive(Debug, PartialEq, Eq)]
+pub enum RockIntegrity {
+    Intact,
+    Violated(Vec<IntegrityViolation>),
+    /// The rock was installed without a `rock_manifest`, e.g. by an older version of rocks.
+    NoRockManifest,
+}
+
+/// Check the installed files of `package` against the `rock_manifest` written when it was installed.
+pub fn check_integrity(
+    package: &LocalPackage,
+    tree: &Tree,
+) -> Result<RockIntegrity, RockManifestError> {
+    let rock_layout = tree.rock_layout(package);
+    match RockManifest::load(&rock_layout)? {
+        None => Ok(RockIntegrity::NoRockManifest),
+        Some(manifest) => {
+            let violations = manifest.verify(&rock_layout)?;
+            if violations.is_empty() {
+                Ok(RockIntegrity::Intact)
+            } else {
+                Ok(RockIntegrity::Violated(violations))
+            }
+        }
+    }
+}
@@ -0,0 +1,251 @@
+use std::{
+    collections::HashSet,
+    fmt::Display,
+    io,
+    path::{Path, PathBuf},
+};
+
+use crate::{config::Config, lockfile::Lockfile, tree::Tree};
+
+/// What a [`CleanTarget`] contains.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum CleanTargetKind {
+    /// A build directory left behind by `rocks build --no-install`.
+    BuildDir,
+    /// A source download that was interrupted before it could be built.
+    PartialDownload,
+    /// The object files of a C module, kept to speed up rebuilds.
+    ObjectCache,
+    /// A rock directory in the tree that the lockfile does not reference,
+    /// e.g. because its installation failed.
+    OrphanedRock,
+    /// A cached manifest. Only removed with `all`.
+    ManifestCache,
+    /// The tree's installed rocks and lockfile. Only removed with `all`.
+    Tree,
+}
+
+impl Display for CleanTargetKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::BuildDir => "build directory",
+            Self::PartialDownload => "partial download",
+            Self::ObjectCache => "object cache",
+            Self::OrphanedRock => "orphaned rock",
+            Self::ManifestCache => "manifest cache",
+            Self::Tree => "installed rocks and lockfile",
+        }
+        .fmt(f)
+    }
+}
+
+/// A file or directory that `rocks clean` removes.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct CleanTarget {
+    pub kind: CleanTargetKind,
+    pub path: PathBuf,
+}
+
+/// The directory in which `rocks build --no-install` leaves its build artifacts.
+pub(crate) fn build_cache_dir(config: &Config) -> PathBuf {
+    config.cache_dir().join("build")
+}
+
+/// The directory in which interrupted source downloads are stored until they are resumed.
+pub(crate) fn partial_download_dir(config: &Config) -> PathBuf {
+    config.cache_dir().join("partial")
+}
+
+/// The directory in which the object files of C modules are cached between builds.
+pub(crate) fn object_cache_dir(config: &Config) -> PathBuf {
+    config.cache_dir().join("objects")
+}
+
+/// Collect the transient build state of `tree`:
+/// build directories, partial downloads, cached object files and rock directories that are not in the lockfile.
+/// If `all` is set, this also includes the manifest caches and the tree itself,
+/// i.e. the installed rocks and the lockfile.
+pub fn clean_targets(tree: &Tree, config: &Config, all: bool) -> io::Result<Vec<CleanTarget>> {
+    let mut targets = Vec::new();
+    for path in dir_entries(&build_cache_dir(config))? {
+        targets.push(CleanTarget {
+            kind: CleanTargetKind::BuildDir,
+            path,
+        });
+    }
+    for path in dir_entries(&partial_download_dir(config))? {
+        targets.push(CleanTarget {
+            kind: CleanTargetKind::PartialDownload,
+            path,
+        });
+    }
+    for path in dir_entries(&object_cache_dir(config))? {
+        targets.push(CleanTarget {
+            kind: CleanTargetKind::ObjectCache,
+            path,
+        });
+    }
+    if all {
+        for path in dir_entries(config.cache_dir())? {
+            let is_manifest = path
+                .file_name()
+                .is_some_and(|name| name.to_string_lossy().starts_with("manifest"));
+            if is_manifest {
+                targets.push(CleanTarget {
+                    kind: CleanTargetKind::ManifestCache,
+                    path,
+                });
+            }
+        }
+        if tree.root().is_dir() {
+            targets.push(CleanTarget {
+                kind: CleanTargetKind::Tree,
+                path: tree.root(),
+            });
+        }
+    } else {
+        for path in orphaned_rocks(tree)? {
+            targets.push(CleanTarget {
+                kind: CleanTargetKind::OrphanedRock,
+                path,
+            });
+        }
+    }
+    targets.sort();
+    Ok(targets)
+}
+
+/// Remove the given targets.
+pub fn clean(targets: &[CleanTarget]) -> io::Result<()> {
+    for target in targets {
+        if target.path.is_dir() {
+            std::fs::remove_dir_all(&target.path)?;
+        } else if target.path.exists() {
+            std::fs::remove_file(&target.path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Rock directories are named `<id>-<name>@<version>`.
+/// Without a lockfile, we can't tell which rocks are installed, so nothing is considered orphaned.
+fn orphaned_rocks(tree: &Tree) -> io::Result<Vec<PathBuf>> {
+    let lockfile_path = tree.root().join("lock.json");
+    if !lockfile_path.is_file() {
+        return Ok(Vec::new());
+    }
+    let lockfile = Lockfile::load(&lockfile_path)?;
+    let installed: HashSet<PathBuf> = lockfile
+        .rocks()
+        .iter()
+        .flat_map(|(id, package)| {
+            [
+                tree.root_for(package),
+                tree.root()
+                    .join(format!("{}-{}@{}", id, package.name(), package.version())),
+            ]
+        })
+        .collect();
+    Ok(dir_entries(&tree.root())?
+        .into_iter()
+        .filter(|path| path.is_dir() && !installed.contains(path))
+        .filter(|path| {
+            path.file_name()
+                .is_some_and(|name| name.to_string_lossy().contains('@'))
+        })
+        .collect())
+}
+
+fn dir_entries(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+    std::fs::read_dir(dir)?
+        .map(|entry| Ok(entry?.path()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_fs::prelude::*;
+
+    use crate::{
+        config::{ConfigBuilder, LuaVersion},
+        lockfile::test_package,
+    };
+
+    use super::*;
+
+    #[test]
+    fn clean_transient_state() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let cache_dir = temp.child("cache");
+        cache_dir.child("build/foo.abc/foo.so").touch().unwrap();
+        cache_dir.child("partial/0123abcd").touch().unwrap();
+        cache_dir.child("objects/foo/objects.json").touch().unwrap();
+        cache_dir.child("manifest-5.1").touch().unwrap();
+        let config = ConfigBuilder::new()
+            .cache_dir(Some(cache_dir.to_path_buf()))
+            .tree(Some(temp.join("tree")))
+            .lua_version(Some(LuaVersion::Lua51))
+            .build()
+            .unwrap();
+        let tree = Tree::new(config.tree().clone(), LuaVersion::Lua51).unwrap();
+
+        let installed = test_package("foo", "1.0.0");
+        let orphaned = test_package("bar", "1.0.0");
+        tree.rock(&installed).unwrap();
+        tree.rock(&orphaned).unwrap();
+        let mut lockfile = tree.lockfile().unwrap();
+        lockfile.add(&installed);
+        lockfile.flush().unwrap();
+        drop(lockfile);
+
+        let targets = clean_targets(&tree, &config, false).unwrap();
+        assert_eq!(
+            targets,
+            vec![
+                CleanTarget {
+                    kind: CleanTargetKind::BuildDir,
+                    path: cache_dir.join("build/foo.abc"),
+                },
+                CleanTarget {
+                    kind: CleanTargetKind::PartialDownload,
+                    path: cache_dir.join("partial/0123abcd"),
+                },
+                CleanTarget {
+                    kind: CleanTargetKind::ObjectCache,
+                    path: cache_dir.join("objects/foo"),
+                },
+                CleanTarget {
+                    kind: CleanTargetKind::OrphanedRock,
+                    path: tree.root_for(&orphaned),
+                },
+            ]
+        );
+        clean(&targets).unwrap();
+        assert!(tree.root_for(&installed).is_dir());
+        assert!(!tree.root_for(&orphaned).exists());
+        assert!(tree.root().join("lock.json").is_file());
+        assert!(cache_dir.join("manifest-5.1").is_file());
+        assert!(clean_targets(&tree, &config, false).unwrap().is_empty());
+
+        let targets = clean_targets(&tree, &config, true).unwrap();
+        assert_eq!(
+            targets,
+            vec![
+                CleanTarget {
+                    kind: CleanTargetKind::ManifestCache,
+                    path: cache_dir.join("manifest-5.1"),
+                },
+                CleanTarget {
+                    kind: CleanTargetKind::Tree,
+                    path: tree.root(),
+                },
+            ]
+        );
+        clean(&targets).unwrap();
+        assert!(!tree.root().exists());
+        assert!(!cache_dir.join("manifest-5.1").exists());
+    }
+}
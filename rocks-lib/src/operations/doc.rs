@@ -0,0 +1,329 @@
+use std::{
+    io,
+    path::{Component, Path, PathBuf},
+    process::Command,
+    sync::Arc,
+    time::SystemTime,
+};
+
+use itertools::Itertools;
+use mlua::Lua;
+use thiserror::Error;
+use tokio::{
+    io::{AsyncReadExt as _, AsyncWriteExt as _},
+    net::{TcpListener, TcpStream},
+};
+use walkdir::WalkDir;
+
+use crate::{
+    build::BuildBehaviour,
+    config::Config,
+    lockfile::PinnedState,
+    package::{PackageName, PackageReq, PackageVersionReqError},
+    path::Paths,
+    progress::{MultiProgress, Progress},
+    project::Project,
+    remote_package_db::RemotePackageDB,
+    tree::Tree,
+};
+
+use super::{install, InstallError};
+
+/// The directory LDoc writes to if `config.ld` does not specify a `dir`.
+const DEFAULT_DOC_DIR: &str = "doc";
+
+#[derive(Error, Debug)]
+pub enum DocError {
+    #[error("no documentation generator configured! Please add a `config.ld` or add `ldoc` to your rockspec's dependencies.")]
+    NotConfigured,
+    #[error("failed to execute `{0}`: {1}")]
+    RunCommandFailure(String, io::Error),
+    #[error("documentation generation failed!")]
+    GenerationFailure,
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+#[derive(Error, Debug)]
+pub enum InstallDocDependenciesError {
+    #[error(transparent)]
+    InstallError(#[from] InstallError),
+    #[error(transparent)]
+    PackageVersionReqError(#[from] PackageVersionReqError),
+}
+
+/// Whether the project is configured to generate documentation with LDoc,
+/// i.e. it has a `config.ld` or depends on `ldoc`.
+pub fn has_ldoc(project: &Project) -> bool {
+    let ldoc = PackageName::new("ldoc".into());
+    let rockspec = project.rockspec();
+    project.ldoc_config().is_some()
+        || rockspec
+            .dependencies
+            .current_platform()
+            .iter()
+            .chain(rockspec.build_dependencies.current_platform())
+            .chain(rockspec.test_dependencies.current_platform())
+            .any(|req| req.name() == &ldoc)
+}
+
+/// Ensure that ldoc is installed.
+pub async fn ensure_ldoc(
+    tree: &Tree,
+    package_db: &RemotePackageDB,
+    config: &Config,
+    progress: Arc<Progress<MultiProgress>>,
+) -> Result<(), InstallDocDependenciesError> {
+    let ldoc_req = PackageReq::new("ldoc".into(), None)?;
+
+    if tree.has_rock(&ldoc_req).is_none() {
+        install(
+            vec![(BuildBehaviour::NoForce, ldoc_req)],
+            PinnedState::Unpinned,
+            package_db,
+            config,
+            progress,
+        )
+        .await?;
+    }
+
+    Ok(())
+}
+
+/// The directory in which the project's documentation is generated.
+/// This is the `dir` set in `config.ld`, or `doc` if unset.
+pub fn doc_dir(project: &Project) -> PathBuf {
+    let dir = project
+        .ldoc_config()
+        .and_then(|config_ld| std::fs::read_to_string(config_ld).ok())
+        .and_then(|content| {
+            // `config.ld` is a Lua script that sets globals.
+            let lua = Lua::new();
+            lua.load(content).exec().ok()?;
+            lua.globals().get::<Option<String>>("dir").ok()?
+        })
+        .unwrap_or(DEFAULT_DOC_DIR.into());
+    project.root().join(dir)
+}
+
+/// Generate the project's documentation with the `ldoc` installed in `tree`.
+/// Returns the directory containing the generated documentation.
+pub fn generate_docs(project: &Project, tree: Tree) -> Result<PathBuf, DocError> {
+    if !has_ldoc(project) {
+        return Err(DocError::NotConfigured);
+    }
+    let paths = Paths::from_tree(tree)?;
+    let status = Command::new("ldoc")
+        .current_dir(project.root())
+        .arg(".")
+        .env("PATH", paths.path_prepended().joined())
+        .env("LUA_PATH", paths.package_path().joined())
+        .env("LUA_CPATH", paths.package_cpath().joined())
+        .status()
+        .map_err(|err| DocError::RunCommandFailure("ldoc".into(), err))?;
+    if status.success() {
+        Ok(doc_dir(project))
+    } else {
+        Err(DocError::GenerationFailure)
+    }
+}
+
+/// A local HTTP server for a project's generated documentation.
+/// The documentation is regenerated when a page is requested
+/// and the project's Lua sources have changed since it was last generated.
+pub struct DocServer {
+    listener: TcpListener,
+    project: Project,
+    tree: Tree,
+    doc_dir: PathBuf,
+    generated_at: SystemTime,
+}
+
+impl DocServer {
+    /// Bind the server to `127.0.0.1:<port>`.
+    /// Expects the documentation to have been generated with [`generate_docs`].
+    pub async fn bind(project: Project, tree: Tree, port: u16) -> Result<Self, DocError> {
+        let listener = TcpListener::bind(("127.0.0.1", port)).await?;
+        Ok(Self {
+            listener,
+            doc_dir: doc_dir(&project),
+            project,
+            tree,
+            generated_at: SystemTime::now(),
+        })
+    }
+
+    pub fn url(&self) -> Result<String, DocError> {
+        Ok(format!("http://{}/", self.listener.local_addr()?))
+    }
+
+    /// Serve the documentation until the process is terminated.
+    pub async fn serve(mut self) -> Result<(), DocError> {
+        loop {
+            let (stream, _) = self.listener.accept().await?;
+            // A failing connection should not bring down the server.
+            let _ = self.handle(stream).await;
+        }
+    }
+
+    async fn handle(&mut self, mut stream: TcpStream) -> io::Result<()> {
+        let mut buffer = vec![0; 8192];
+        let len = stream.read(&mut buffer).await?;
+        let request = String::from_utf8_lossy(&buffer[..len]);
+        let (status, content_type, body) = match parse_request_path(&request) {
+            Some(path) => {
+                if is_html(&path) && self.sources_changed() {
+                    if let Err(err) = generate_docs(&self.project, self.tree.clone()) {
+                        let body = format!("failed to regenerate documentation: {}", err);
+                        return write_response(
+                            &mut stream,
+                            "500 Internal Server Error",
+                            "text/plain",
+                            body.as_bytes(),
+                        )
+                        .await;
+                    }
+                    self.generated_at = SystemTime::now();
+                }
+                doc_response(&self.doc_dir, &path)
+            }
+            None => ("400 Bad Request", "text/plain", b"bad request".to_vec()),
+        };
+        write_response(&mut stream, status, content_type, &body).await
+    }
+
+    fn sources_changed(&self) -> bool {
+        WalkDir::new(self.project.root())
+            .into_iter()
+            .filter_entry(|entry| {
+                // Skip hidden directories, like the `.rocks` tree.
+                entry.depth() == 0
+                    || (entry.path() != self.doc_dir
+                        && !entry.file_name().to_string_lossy().starts_with('.'))
+            })
+            .filter_map(Result::ok)
+            .filter(|entry| {
+                entry
+                    .path()
+                    .extension()
+                    .is_some_and(|ext| ext == "lua" || ext == "ld")
+            })
+            .filter_map(|entry| entry.metadata().ok()?.modified().ok())
+            .any(|modified| modified > self.generated_at)
+    }
+}
+
+fn parse_request_path(request: &str) -> Option<String> {
+    let (method, target) = request.lines().next()?.split_whitespace().next_tuple()?;
+    if method != "GET" {
+        return None;
+    }
+    let path = target.split(['?', '#']).next()?.trim_start_matches('/');
+    // Only serve files inside the doc directory: no `..`, absolute paths or drive prefixes.
+    // Backslashes are rejected, as they are path separators on Windows.
+    if path.contains('\\')
+        || !Path::new(path)
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+    {
+        return None;
+    }
+    Some(path.to_string())
+}
+
+fn is_html(path: &str) -> bool {
+    path.is_empty() || path.ends_with('/') || path.ends_with(".html")
+}
+
+fn doc_response(doc_dir: &Path, path: &str) -> (&'static str, &'static str, Vec<u8>) {
+    let mut file = doc_dir.join(path);
+    if file.is_dir() {
+        file = file.join("index.html");
+    }
+    match std::fs::read(&file) {
+        Ok(content) => {
+            let content_type = match file.extension().and_then(|ext| ext.to_str()) {
+                Some("html") => "text/html; charset=utf-8",
+                Some("css") => "text/css",
+                Some("js") => "text/javascript",
+                Some("png") => "image/png",
+                Some("svg") => "image/svg+xml",
+                _ => "text/plain; charset=utf-8",
+            };
+            ("200 OK", content_type, content)
+        }
+        Err(_) => ("404 Not Found", "text/plain", b"not found".to_vec()),
+    }
+}
+
+async fn write_response(
+    stream: &mut TcpStream,
+    status: &str,
+    content_type: &str,
+    body: &[u8],
+) -> io::Result<()> {
+    let header = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n",
+        status,
+        content_type,
+        body.len()
+    );
+    stream.write_all(header.as_bytes()).await?;
+    stream.write_all(body).await?;
+    stream.flush().await
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_fs::prelude::{FileWriteStr as _, PathChild as _};
+
+    use super::*;
+
+    const ROCKSPEC: &str = r#"
+package = "foo"
+version = "1.0.0-1"
+source = {
+    url = "https://github.com/nvim-neorocks/luarocks-stub",
+}
+"#;
+
+    #[test]
+    fn doc_dir_from_config_ld() {
+        let root = assert_fs::TempDir::new().unwrap();
+        root.child("project.rockspec").write_str(ROCKSPEC).unwrap();
+        let project = Project::from(root.path()).unwrap().unwrap();
+        assert!(!has_ldoc(&project));
+        assert_eq!(doc_dir(&project), root.join("doc"));
+
+        root.child("config.ld")
+            .write_str("project = 'foo'\ndir = 'docs/api'\n")
+            .unwrap();
+        assert!(has_ldoc(&project));
+        assert_eq!(doc_dir(&project), root.join("docs").join("api"));
+    }
+
+    #[test]
+    fn serve_doc_files() {
+        let doc_dir = assert_fs::TempDir::new().unwrap();
+        doc_dir.child("index.html").write_str("<html/>").unwrap();
+        doc_dir.child("ldoc.css").write_str("body {}").unwrap();
+
+        let path = parse_request_path("GET /?foo=bar HTTP/1.1\r\n").unwrap();
+        assert!(is_html(&path));
+        let (status, content_type, body) = doc_response(doc_dir.path(), &path);
+        assert_eq!(status, "200 OK");
+        assert_eq!(content_type, "text/html; charset=utf-8");
+        assert_eq!(body, b"<html/>");
+
+        let path = parse_request_path("GET /ldoc.css HTTP/1.1\r\n").unwrap();
+        assert_eq!(doc_response(doc_dir.path(), &path).1, "text/css");
+        let path = parse_request_path("GET /missing.html HTTP/1.1\r\n").unwrap();
+        assert_eq!(doc_response(doc_dir.path(), &path).0, "404 Not Found");
+
+        assert!(parse_request_path("GET /../secret HTTP/1.1\r\n").is_none());
+        assert!(parse_request_path("GET /foo/../../secret HTTP/1.1\r\n").is_none());
+        assert!(parse_request_path("GET /..\\secret HTTP/1.1\r\n").is_none());
+        assert!(parse_request_path("GET /./index.html HTTP/1.1\r\n").is_none());
+        assert!(parse_request_path("POST / HTTP/1.1\r\n").is_none());
+    }
+}
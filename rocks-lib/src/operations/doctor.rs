@@ -0,0 +1,404 @@
+use std::{
+    collections::HashSet,
+    fmt::Display,
+    io,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use itertools::Itertools;
+use reqwest::Client;
+use thiserror::Error;
+
+use crate::{
+    config::{Config, LuaVersion},
+    lockfile::{DependencyCycle, LocalPackage},
+    lua_installation::{get_installed_lua_version, LuaInstallation},
+    progress::{MultiProgress, Progress},
+    remote_package_db::RemotePackageDB,
+    tree::{Tree, TreeLayout},
+};
+
+use super::{
+    install_locked::dependency_order, install_locked::reinstall_locked, InstallLockedError,
+};
+
+/// The outcome of a [`DoctorCheck`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum CheckStatus {
+    Pass,
+    /// Something that only some rocks or operations need is missing.
+    Warn,
+    /// Something that rocks needs to work is missing.
+    Fail,
+}
+
+/// A single check of the environment that rocks runs in.
+#[derive(Debug, Clone)]
+pub struct DoctorCheck {
+    pub status: CheckStatus,
+    pub message: String,
+    /// How to fix the problem, if the check did not pass.
+    pub hint: Option<String>,
+}
+
+impl DoctorCheck {
+    fn pass(message: impl Display) -> Self {
+        Self {
+            status: CheckStatus::Pass,
+            message: message.to_string(),
+            hint: None,
+        }
+    }
+
+    fn warn(message: impl Display, hint: impl Display) -> Self {
+        Self {
+            status: CheckStatus::Warn,
+            message: message.to_string(),
+            hint: Some(hint.to_string()),
+        }
+    }
+
+    fn fail(message: impl Display, hint: impl Display) -> Self {
+        Self {
+            status: CheckStatus::Fail,
+            message: message.to_string(),
+            hint: Some(hint.to_string()),
+        }
+    }
+}
+
+/// Check whether the environment is set up to install and build rocks:
+/// whether Lua and its headers are installed, whether the tools that builds and
+/// sources may need are available, whether the cache is writable
+/// and whether the configured servers can be reached.
+pub async fn doctor(config: &Config) -> Vec<DoctorCheck> {
+    let mut checks = check_lua(config);
+    checks.push(check_c_compiler());
+    checks.push(check_git());
+    checks.push(check_writable_dir("cache directory", config.cache_dir()));
+    let client = config.http_client();
+    for server in std::iter::once(config.server()).chain(config.extra_servers()) {
+        checks.push(check_server(&client, server, config.timeout()).await);
+    }
+    checks
+}
+
+fn check_lua(config: &Config) -> Vec<DoctorCheck> {
+    let lua_version = match LuaVersion::from(config) {
+        Ok(lua_version) => lua_version,
+        Err(_) => {
+            return vec![DoctorCheck::fail(
+                "no Lua version is configured and no Lua interpreter was found",
+                "install Lua, or specify the version with `--lua-version` or a `.lua-version` file",
+            )]
+        }
+    };
+
+    let interpreter = ["lua", "luajit"].into_iter().find_map(|lua_cmd| {
+        get_installed_lua_version(lua_cmd)
+            .ok()
+            .map(|version| (lua_cmd, version))
+    });
+    let interpreter = match interpreter {
+        Some((lua_cmd, version)) => {
+            DoctorCheck::pass(format!("Lua interpreter: {} ({})", lua_cmd, version))
+        }
+        None => DoctorCheck::warn(
+            "no Lua interpreter found on the PATH",
+            format!(
+                "install Lua {} to run rocks and their tests",
+                lua_version.version_compatibility_str()
+            ),
+        ),
+    };
+
+    let headers = match LuaInstallation::find(&lua_version, config) {
+        Some(installation) => DoctorCheck::pass(format!(
+            "Lua {} headers: {}",
+            lua_version,
+            installation.include_dir.display()
+        )),
+        None => DoctorCheck::warn(
+            format!("no headers found for Lua {}", lua_version),
+            "they will be built from source when needed, or run `rocks install-lua` to build them now",
+        ),
+    };
+
+    vec![
+        DoctorCheck::pass(format!("Lua version: {}", lua_version)),
+        interpreter,
+        headers,
+    ]
+}
+
+fn check_c_compiler() -> DoctorCheck {
+    let compiler = std::env::var("CC").ok().or_else(|| {
+        ["cc", "gcc", "clang", "cl"]
+            .into_iter()
+            .find(|compiler| which::which(compiler).is_ok())
+            .map(String::from)
+    });
+    match compiler {
+        Some(compiler) => DoctorCheck::pass(format!("C compiler: {}", compiler)),
+        None => DoctorCheck::warn(
+            "no C compiler found",
+            "install a C compiler (e.g. gcc or clang) to build rocks with C modules",
+        ),
+    }
+}
+
+fn check_git() -> DoctorCheck {
+    match which::which("git") {
+        Ok(git) => DoctorCheck::pass(format!("git: {}", git.display())),
+        Err(_) => DoctorCheck::warn(
+            "git not found",
+            "install git to fetch sources from SSH URLs",
+        ),
+    }
+}
+
+fn check_writable_dir(name: &str, dir: &Path) -> DoctorCheck {
+    let probe = dir.join(".rocks-doctor");
+    let result = std::fs::create_dir_all(dir)
+        .and_then(|_| std::fs::write(&probe, ""))
+        .and_then(|_| std::fs::remove_file(&probe));
+    match result {
+        Ok(()) => DoctorCheck::pass(format!("{} is writable: {}", name, dir.display())),
+        Err(err) => DoctorCheck::fail(
+            format!("{} is not writable: {} ({})", name, dir.display(), err),
+            format!("check the permissions of {}", dir.display()),
+        ),
+    }
+}
+
+async fn check_server(client: &Client, server: &str, timeout: &Duration) -> DoctorCheck {
+    let mut request = client.head(server);
+    // A timeout of 0 means waiting forever.
+    if !timeout.is_zero() {
+        request = request.timeout(*timeout);
+    }
+    // Any response means that the server can be reached.
+    match request.send().await {
+        Ok(_) => DoctorCheck::pass(format!("server is reachable: {}", server)),
+        Err(err) => DoctorCheck::fail(
+            format!("server is not reachable: {} ({})", server, err),
+            "check your network connection, or configure another server with `--server`",
+        ),
+    }
+}
+
+/// An inconsistency between a tree and its lockfile, which [`fix_tree`] can repair.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TreeProblem {
+    /// The lockfile records a rock whose directory is missing from the tree.
+    MissingRock(Box<LocalPackage>),
+    /// A rock directory that the lockfile doesn't record, e.g. left behind by an interrupted removal.
+    OrphanedRockDir(PathBuf),
+    /// The entrypoints recorded in the lockfile are not the rocks that no other rock depends on.
+    StaleEntrypoints,
+}
+
+impl Display for TreeProblem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::MissingRock(package) => write!(
+                f,
+                "{}@{} is in the lockfile, but not installed",
+                package.name(),
+                package.version()
+            ),
+            Self::OrphanedRockDir(dir) => {
+                write!(f, "{} is not in the lockfile", dir.display())
+            }
+            Self::StaleEntrypoints => f.write_str("the lockfile's entrypoints are out of date"),
+        }
+    }
+}
+
+impl TreeProblem {
+    /// A description of what [`fix_tree`] does to repair the problem.
+    pub fn fix(&self) -> String {
+        match self {
+            Self::MissingRock(package) => {
+                format!("reinstall {}@{}", package.name(), package.version())
+            }
+            Self::OrphanedRockDir(dir) => format!("remove {}", dir.display()),
+            Self::StaleEntrypoints => "recompute the lockfile's entrypoints".into(),
+        }
+    }
+}
+
+impl From<&TreeProblem> for DoctorCheck {
+    fn from(problem: &TreeProblem) -> Self {
+        DoctorCheck::fail(
+            problem,
+            format!("run `rocks config doctor --fix` to {}", problem.fix()),
+        )
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum FixTreeError {
+    #[error("failed to reinstall a rock: {0}")]
+    Reinstall(#[from] InstallLockedError),
+    #[error(transparent)]
+    DependencyCycle(#[from] DependencyCycle),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+/// Check `tree` against its lockfile. This is the counterpart to [`super::check_integrity`],
+/// which checks the files of the rocks that are installed.
+///
+/// Trees with the luarocks layout are not checked for orphaned rock directories,
+/// as luarocks may have installed rocks into them.
+pub fn check_tree(tree: &Tree) -> io::Result<Vec<TreeProblem>> {
+    let lockfile = tree.lockfile_snapshot()?;
+    let mut problems = lockfile
+        .rocks()
+        .values()
+        .filter(|package| !tree.root_for(package).is_dir())
+        .map(|package| TreeProblem::MissingRock(Box::new(package.clone())))
+        .collect_vec();
+
+    if *tree.layout() != TreeLayout::Luarocks && tree.root().is_dir() {
+        let rock_dirs: HashSet<PathBuf> = lockfile
+            .rocks()
+            .values()
+            .map(|package| tree.root_for(package))
+            .collect();
+        let mut orphaned_dirs = Vec::new();
+        for entry in std::fs::read_dir(tree.root())? {
+            let path = entry?.path();
+            // Rock directories are named `<id>-<name>@<version>`.
+            // Hidden directories are used for staging installs.
+            let is_rock_dir = path.file_name().is_some_and(|name| {
+                let name = name.to_string_lossy();
+                !name.starts_with('.') && name.contains('@')
+            });
+            if path.is_dir() && is_rock_dir && !rock_dirs.contains(&path) {
+                orphaned_dirs.push(path);
+            }
+        }
+        orphaned_dirs.sort();
+        problems.extend(orphaned_dirs.into_iter().map(TreeProblem::OrphanedRockDir));
+    }
+
+    if lockfile.has_stale_entrypoints() {
+        problems.push(TreeProblem::StaleEntrypoints);
+    }
+    Ok(problems)
+}
+
+/// Repair the `problems` that [`check_tree`] found in `tree`:
+///
+/// - Missing rocks are reinstalled exactly as the lockfile records them, dependencies first.
+/// - Orphaned rock directories are removed.
+/// - The lockfile's entrypoints are recomputed.
+///
+/// `config` must use the tree's Lua version.
+pub async fn fix_tree(
+    problems: &[TreeProblem],
+    tree: &Tree,
+    package_db: &RemotePackageDB,
+    config: &Config,
+    progress: Arc<Progress<MultiProgress>>,
+) -> Result<(), FixTreeError> {
+    for problem in problems {
+        if let TreeProblem::OrphanedRockDir(dir) = problem {
+            std::fs::remove_dir_all(dir)?;
+        }
+    }
+
+    let lockfile = tree.lockfile_snapshot()?;
+    let missing = dependency_order(&lockfile)?
+        .into_iter()
+        .filter(|package| {
+            problems.iter().any(
+                |problem| matches!(problem, TreeProblem::MissingRock(missing) if **missing == **package),
+            )
+        })
+        .collect_vec();
+    reinstall_locked(missing, tree, package_db, config, progress).await?;
+
+    if problems.contains(&TreeProblem::StaleEntrypoints) {
+        // Flushing the lockfile recomputes its entrypoints.
+        tree.lockfile()?
+            .map_then_flush(|_| Ok::<_, io::Error>(()))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_fs::prelude::{FileWriteStr as _, PathChild as _};
+
+    use crate::lockfile::test_package;
+
+    use super::*;
+
+    #[test]
+    fn writable_dir() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let check = check_writable_dir("cache directory", &temp.join("cache"));
+        assert_eq!(check.status, CheckStatus::Pass);
+        assert!(!temp.join("cache").join(".rocks-doctor").exists());
+
+        let file = temp.child("file");
+        file.write_str("").unwrap();
+        let check = check_writable_dir("cache directory", &file.join("cache"));
+        assert_eq!(check.status, CheckStatus::Fail);
+        assert!(check.hint.is_some());
+    }
+
+    #[tokio::test]
+    async fn unreachable_server() {
+        let check = check_server(
+            &Client::new(),
+            "http://127.0.0.1:1",
+            &Duration::from_secs(5),
+        )
+        .await;
+        assert_eq!(check.status, CheckStatus::Fail);
+    }
+
+    #[test]
+    fn tree_problems() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let tree = Tree::new(temp.to_path_buf(), LuaVersion::Lua51).unwrap();
+        let package = |name: &str| test_package(name, "1.0.0-1");
+        let neorg = package("neorg");
+        let nio = package("nvim-nio");
+        tree.lockfile()
+            .unwrap()
+            .map_then_flush(|lockfile| {
+                lockfile.add(&neorg);
+                lockfile.add_dependency(&neorg, &nio);
+                Ok::<_, io::Error>(())
+            })
+            .unwrap();
+        tree.rock(&neorg).unwrap();
+        tree.rock(&nio).unwrap();
+        std::fs::create_dir_all(tree.root().join(".staging-foo")).unwrap();
+        assert_eq!(check_tree(&tree).unwrap(), Vec::new());
+
+        std::fs::remove_dir_all(tree.root_for(&nio)).unwrap();
+        let orphan = tree.root().join("0123-foo@1.0.0-1");
+        std::fs::create_dir_all(&orphan).unwrap();
+        let lockfile_path = tree.root().join("lock.json");
+        let mut lockfile: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&lockfile_path).unwrap()).unwrap();
+        lockfile["entrypoints"] = serde_json::json!([]);
+        std::fs::write(&lockfile_path, lockfile.to_string()).unwrap();
+        assert_eq!(
+            check_tree(&tree).unwrap(),
+            vec![
+                TreeProblem::MissingRock(Box::new(nio)),
+                TreeProblem::OrphanedRockDir(orphan),
+                TreeProblem::StaleEntrypoints,
+            ]
+        );
+    }
+}
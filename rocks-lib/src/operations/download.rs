@@ -1,13 +1,33 @@
-use std::{io, path::PathBuf, string::FromUtf8Error};
+use std::{
+    io::{self, SeekFrom},
+    path::{Path, PathBuf},
+    str::FromStr,
+    string::FromUtf8Error,
+};
 
 use bytes::Bytes;
+use fs2::FileExt as _;
+use itertools::Itertools;
+use reqwest::{
+    header::{HeaderValue, CONTENT_RANGE, ETAG, IF_RANGE, LAST_MODIFIED, RANGE},
+    Response, StatusCode, Url,
+};
+use sha2::{Digest, Sha256};
 use thiserror::Error;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
 
 use crate::{
+    config::{
+        env_vars::{self, EnvVarError},
+        Config,
+    },
+    lockfile::DependencyCycle,
+    manifest::file_url_path,
     package::{PackageName, PackageReq, PackageVersion, RemotePackage},
     progress::{Progress, ProgressBar},
     remote_package_db::{RemotePackageDB, SearchError},
     rockspec::{Rockspec, RockspecError},
+    signature::{self, SignatureError},
 };
 
 pub struct DownloadedSrcRockBytes {
@@ -29,16 +49,19 @@ pub enum DownloadRockspecError {
     Request(#[from] reqwest::Error),
     #[error("failed to convert rockspec response: {0}")]
     ResponseConversion(#[from] FromUtf8Error),
+    #[error(transparent)]
+    EnvVar(#[from] EnvVarError),
 }
 
 pub async fn download_rockspec(
     package_req: &PackageReq,
     package_db: &RemotePackageDB,
+    config: &Config,
     progress: &Progress<ProgressBar>,
 ) -> Result<Rockspec, SearchAndDownloadError> {
     let package = package_db.find(package_req, progress)?;
     progress.map(|p| p.set_message(format!("📥 Downloading rockspec for {}", package_req)));
-    download_rockspec_impl(package).await
+    download_rockspec_impl(package, config, progress).await
 }
 
 #[derive(Error, Debug)]
@@ -55,39 +78,57 @@ pub enum SearchAndDownloadError {
     Utf8(#[from] FromUtf8Error),
     #[error(transparent)]
     Rockspec(#[from] RockspecError),
+    #[error("rockspec signature verification failed: {0}")]
+    Signature(#[from] SignatureError),
+    #[error(transparent)]
+    DependencyCycle(#[from] DependencyCycle),
+    #[error("dependency graph exceeds the maximum depth of {max_depth}: {}", chain.iter().join(" → "))]
+    MaxDepthExceeded {
+        max_depth: usize,
+        chain: Vec<PackageName>,
+    },
 }
 
 pub async fn search_and_download_src_rock(
     package_req: &PackageReq,
     package_db: &RemotePackageDB,
+    config: &Config,
     progress: &Progress<ProgressBar>,
 ) -> Result<DownloadedSrcRockBytes, SearchAndDownloadError> {
     let package = package_db.find(package_req, progress)?;
-    Ok(download_src_rock(&package, progress).await?)
+    Ok(download_src_rock(&package, config, progress).await?)
 }
 
 #[derive(Error, Debug)]
-#[error("failed to download source rock: {0}")]
-pub struct DownloadSrcRockError(#[from] reqwest::Error);
+pub enum DownloadSrcRockError {
+    #[error("failed to download source rock: {0}")]
+    Request(#[from] reqwest::Error),
+    #[error("failed to read source rock: {0}")]
+    Io(#[from] io::Error),
+    #[error(transparent)]
+    EnvVar(#[from] EnvVarError),
+}
 
 pub(crate) async fn download_src_rock(
     remote_package: &RemotePackage,
+    config: &Config,
     progress: &Progress<ProgressBar>,
 ) -> Result<DownloadedSrcRockBytes, DownloadSrcRockError> {
     progress.map(|p| p.set_message(format!("📥 Downloading {}", remote_package.package)));
 
-    download_src_rock_impl(remote_package).await
+    download_src_rock_impl(remote_package, config, progress).await
 }
 
 pub async fn download_to_file(
     package_req: &PackageReq,
     destination_dir: Option<PathBuf>,
     package_db: &RemotePackageDB,
+    config: &Config,
     progress: &Progress<ProgressBar>,
 ) -> Result<DownloadedSrcRock, SearchAndDownloadError> {
     progress.map(|p| p.set_message(format!("📥 Downloading {}", package_req)));
 
-    let rock = search_and_download_src_rock(package_req, package_db, progress).await?;
+    let rock = search_and_download_src_rock(package_req, package_db, config, progress).await?;
     let full_rock_name = full_rock_name(&rock.name, &rock.version);
     tokio::fs::write(
         destination_dir
@@ -106,29 +147,57 @@ pub async fn download_to_file(
 
 async fn download_rockspec_impl(
     remote_package: RemotePackage,
+    config: &Config,
+    progress: &Progress<ProgressBar>,
 ) -> Result<Rockspec, SearchAndDownloadError> {
     let package = &remote_package.package;
     let rockspec_name = format!("{}-{}.rockspec", package.name(), package.version());
-    let bytes = reqwest::get(format!("{}/{}", &remote_package.server_url, rockspec_name))
-        .await
-        .map_err(DownloadRockspecError::Request)?
-        .bytes()
-        .await
-        .map_err(DownloadRockspecError::Request)?;
+    let url = format!("{}/{}", &remote_package.server_url, rockspec_name);
+    let rewritten_url = rewrite_url(&url, config, progress);
+    let request_url =
+        env_vars::expand_env_vars(&rewritten_url).map_err(DownloadRockspecError::EnvVar)?;
+    let redact = |err| DownloadRockspecError::Request(env_vars::redact_error(err, &rewritten_url));
+    let bytes = match file_url_path(&request_url) {
+        Some(path) => Bytes::from(tokio::fs::read(path).await?),
+        None => config
+            .http_client()
+            .get(&request_url)
+            .send()
+            .await
+            .map_err(redact)?
+            .bytes()
+            .await
+            .map_err(redact)?,
+    };
+    signature::verify_download(&url, &bytes, config).await?;
     let content = String::from_utf8(bytes.into())?;
     Ok(Rockspec::new(&content)?)
 }
 
 async fn download_src_rock_impl(
     remote_package: &RemotePackage,
+    config: &Config,
+    progress: &Progress<ProgressBar>,
 ) -> Result<DownloadedSrcRockBytes, DownloadSrcRockError> {
     let package = &remote_package.package;
     let full_rock_name = full_rock_name(package.name(), package.version());
 
-    let bytes = reqwest::get(format!("{}/{}", remote_package.server_url, full_rock_name))
-        .await?
-        .bytes()
-        .await?;
+    let url = format!("{}/{}", remote_package.server_url, full_rock_name);
+    let url = rewrite_url(&url, config, progress);
+    let redact = |err| env_vars::redact_error(err, &url);
+    let request_url = env_vars::expand_env_vars(&url)?;
+    let bytes = match file_url_path(&request_url) {
+        Some(path) => Bytes::from(tokio::fs::read(path).await?),
+        None => {
+            let response = config
+                .http_client()
+                .get(request_url)
+                .send()
+                .await
+                .map_err(redact)?;
+            read_body(response, progress).await.map_err(redact)?
+        }
+    };
     Ok(DownloadedSrcRockBytes {
         name: package.name().clone(),
         version: package.version().clone(),
@@ -137,6 +206,426 @@ async fn download_src_rock_impl(
     })
 }
 
+/// Read the body of `response`, reporting the downloaded bytes to `progress`.
+async fn read_body(
+    mut response: Response,
+    progress: &Progress<ProgressBar>,
+) -> Result<Bytes, reqwest::Error> {
+    progress.map(|p| p.start_download(response.content_length()));
+    let mut bytes = Vec::new();
+    while let Some(chunk) = response.chunk().await? {
+        progress.map(|p| p.inc(chunk.len() as u64));
+        bytes.extend_from_slice(&chunk);
+    }
+    progress.map(|p| p.finish_download());
+    Ok(Bytes::from(bytes))
+}
+
 fn full_rock_name(name: &PackageName, version: &PackageVersion) -> String {
     format!("{}-{}.src.rock", name, version)
 }
+
+/// Apply the configured URL rewrites to `url`, logging the rewrite if `--verbose` is set.
+/// The result must only be used to make requests, so that lockfiles keep the canonical URL.
+pub(crate) fn rewrite_url(url: &str, config: &Config, progress: &Progress<ProgressBar>) -> String {
+    match config.rewrite_url(url) {
+        Some(rewritten) => {
+            if config.verbose() {
+                let message = format!("🔀 Rewriting {} to {}", url, rewritten);
+                match progress {
+                    Progress::Progress(bar) => bar.println(message),
+                    Progress::NoProgress => eprintln!("{}", message),
+                }
+            }
+            rewritten
+        }
+        None => url.to_string(),
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum ResumableDownloadError {
+    #[error("failed to download {0}: {1}")]
+    Request(Url, reqwest::Error),
+    #[error("failed to store partial download: {0}")]
+    Io(#[from] io::Error),
+    #[error(transparent)]
+    EnvVar(#[from] EnvVarError),
+    #[error("rewriting {0} produced an invalid URL: {1}")]
+    InvalidRewrite(Url, #[source] <Url as FromStr>::Err),
+}
+
+/// Download the contents of `url`, persisting them to the cache directory as they arrive.
+/// If a previous download of the same URL was interrupted, it is resumed with an HTTP range request,
+/// which is conditional on the remote file being unchanged (`If-Range`).
+/// If the server does not support range requests, or the remote file has changed,
+/// the file is re-downloaded in full.
+/// The partial download is locked while in use, and concurrent downloads of the same URL
+/// download into a temporary file instead.
+/// The partial download is removed once complete, so callers are responsible for verifying
+/// the integrity of the returned bytes.
+/// Environment variables referenced by `url` are expanded and URL rewrites are applied
+/// for the request only.
+pub(crate) async fn download_resumable(
+    url: &Url,
+    config: &Config,
+    progress: &Progress<ProgressBar>,
+) -> Result<Bytes, ResumableDownloadError> {
+    let rewritten_url: Url = rewrite_url(url.as_str(), config, progress)
+        .parse()
+        .map_err(|err| ResumableDownloadError::InvalidRewrite(url.clone(), err))?;
+    let request_err = |err| {
+        ResumableDownloadError::Request(
+            rewritten_url.clone(),
+            env_vars::redact_error(err, rewritten_url.as_str()),
+        )
+    };
+    let request_url = env_vars::expand_url(&rewritten_url)?;
+
+    let partial_dir = super::partial_download_dir(config);
+    tokio::fs::create_dir_all(&partial_dir).await?;
+    let partial_path = partial_dir.join(hex::encode(Sha256::digest(url.as_str())));
+    let validator_path = partial_path.with_extension("validator");
+    let (file, temp_dir) = match lock_partial_download(&partial_path)? {
+        Some(file) => (file, None),
+        None => {
+            let temp_dir = tempdir::TempDir::new("rocks-download")?;
+            let file = std::fs::File::options()
+                .read(true)
+                .write(true)
+                .create_new(true)
+                .open(temp_dir.path().join("download"))?;
+            (file, Some(temp_dir))
+        }
+    };
+    let is_partial_download = temp_dir.is_none();
+
+    let offset = file.metadata()?.len();
+    // A partial download can only be resumed if we know which version of the remote file it is.
+    let validator = if is_partial_download && offset > 0 {
+        std::fs::read_to_string(&validator_path).ok()
+    } else {
+        None
+    };
+
+    let client = config.http_client();
+    let mut request = client.get(request_url.clone());
+    if let Some(validator) = &validator {
+        progress.map(|p| p.set_message(format!("📥 Resuming download of {}", url)));
+        request = request
+            .header(RANGE, format!("bytes={}-", offset))
+            .header(IF_RANGE, validator);
+    }
+    let mut response = request.send().await.map_err(request_err)?;
+
+    let resumed = validator.is_some()
+        && response.status() == StatusCode::PARTIAL_CONTENT
+        && content_range_start(&response) == Some(offset);
+    if !resumed {
+        // The server either sent the full file, because it doesn't support range requests
+        // or the remote file has changed, or a range that doesn't match the partial download,
+        // so we start over.
+        if validator.is_some() && response.status() != StatusCode::OK {
+            response = client.get(request_url).send().await.map_err(request_err)?;
+        }
+        response = response.error_for_status().map_err(request_err)?;
+        file.set_len(0)?;
+        if is_partial_download {
+            match response_validator(&response) {
+                Some(validator) => std::fs::write(&validator_path, validator)?,
+                None => remove_if_exists(&validator_path)?,
+            }
+        }
+    }
+
+    let mut file = tokio::fs::File::from_std(file);
+    file.seek(SeekFrom::End(0)).await?;
+    let downloaded = if resumed { offset } else { 0 };
+    progress.map(|p| {
+        p.start_download(response.content_length().map(|length| length + downloaded));
+        p.inc(downloaded);
+    });
+    while let Some(chunk) = response.chunk().await.map_err(request_err)? {
+        progress.map(|p| p.inc(chunk.len() as u64));
+        file.write_all(&chunk).await?;
+    }
+    progress.map(|p| p.finish_download());
+    file.flush().await?;
+
+    // The content is read from the locked file, as the path may refer to
+    // another download's partial download once the lock is released.
+    file.seek(SeekFrom::Start(0)).await?;
+    let mut bytes = Vec::new();
+    file.read_to_end(&mut bytes).await?;
+    if is_partial_download {
+        remove_if_exists(&partial_path)?;
+        remove_if_exists(&validator_path)?;
+    }
+    Ok(Bytes::from(bytes))
+}
+
+/// Open and lock the partial download at `path`.
+/// Returns `None` if another download of the same URL holds the lock.
+fn lock_partial_download(path: &Path) -> io::Result<Option<std::fs::File>> {
+    let file = std::fs::File::options()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)?;
+    match file.try_lock_exclusive() {
+        Ok(()) => Ok(Some(file)),
+        Err(err) if err.kind() == fs2::lock_contended_error().kind() => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+/// The first byte of a partial response's `Content-Range`, e.g. `5` for `bytes 5-10/11`.
+fn content_range_start(response: &Response) -> Option<u64> {
+    response
+        .headers()
+        .get(CONTENT_RANGE)?
+        .to_str()
+        .ok()?
+        .strip_prefix("bytes ")?
+        .split('-')
+        .next()?
+        .trim()
+        .parse()
+        .ok()
+}
+
+/// The validator of the remote file to make a range request conditional on, if any.
+/// Weak `ETag`s can't be used with `If-Range`, so the `Last-Modified` date is used instead.
+fn response_validator(response: &Response) -> Option<String> {
+    let header = |name| {
+        response
+            .headers()
+            .get(name)
+            .and_then(|value: &HeaderValue| value.to_str().ok())
+    };
+    header(ETAG)
+        .filter(|etag| !etag.starts_with("W/"))
+        .or_else(|| header(LAST_MODIFIED))
+        .map(str::to_string)
+}
+
+fn remove_if_exists(path: &Path) -> io::Result<()> {
+    match std::fs::remove_file(path) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use httptest::{
+        matchers::{all_of, contains, key, not, request},
+        responders::status_code,
+        Expectation, Server,
+    };
+
+    use regex::Regex;
+
+    use crate::config::ConfigBuilder;
+
+    use super::*;
+
+    fn test_config(cache_dir: PathBuf) -> Config {
+        ConfigBuilder::new()
+            .cache_dir(Some(cache_dir))
+            .build()
+            .unwrap()
+    }
+
+    fn partial_path(config: &Config, url: &Url) -> PathBuf {
+        config
+            .cache_dir()
+            .join("partial")
+            .join(hex::encode(Sha256::digest(url.as_str())))
+    }
+
+    fn write_partial_download(config: &Config, url: &Url, content: &str, validator: Option<&str>) {
+        let partial_path = partial_path(config, url);
+        std::fs::create_dir_all(partial_path.parent().unwrap()).unwrap();
+        std::fs::write(&partial_path, content).unwrap();
+        if let Some(validator) = validator {
+            std::fs::write(partial_path.with_extension("validator"), validator).unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn resume_partial_download() {
+        let server = Server::run();
+        server.expect(
+            Expectation::matching(all_of![
+                request::path("/source.tar.gz"),
+                request::headers(contains(("range", "bytes=5-"))),
+                request::headers(contains(("if-range", "\"v1\""))),
+            ])
+            .respond_with(
+                status_code(206)
+                    .insert_header("content-range", "bytes 5-10/11")
+                    .body(" world"),
+            ),
+        );
+        let url: Url = server.url_str("/source.tar.gz").parse().unwrap();
+        let cache_dir = assert_fs::TempDir::new().unwrap();
+        let config = test_config(cache_dir.to_path_buf());
+        write_partial_download(&config, &url, "hello", Some("\"v1\""));
+
+        let bytes = download_resumable(&url, &config, &Progress::NoProgress)
+            .await
+            .unwrap();
+        assert_eq!(bytes, "hello world");
+        let partial_path = partial_path(&config, &url);
+        assert!(!partial_path.exists());
+        assert!(!partial_path.with_extension("validator").exists());
+    }
+
+    #[tokio::test]
+    async fn restart_download_if_ranges_unsupported() {
+        let server = Server::run();
+        server.expect(
+            Expectation::matching(request::path("/source.tar.gz"))
+                .respond_with(status_code(200).body("hello world")),
+        );
+        let url: Url = server.url_str("/source.tar.gz").parse().unwrap();
+        let cache_dir = assert_fs::TempDir::new().unwrap();
+        let config = test_config(cache_dir.to_path_buf());
+        write_partial_download(&config, &url, "hello", Some("\"v1\""));
+
+        let bytes = download_resumable(&url, &config, &Progress::NoProgress)
+            .await
+            .unwrap();
+        assert_eq!(bytes, "hello world");
+        assert!(!partial_path(&config, &url).exists());
+    }
+
+    #[tokio::test]
+    async fn restart_download_without_validator() {
+        let server = Server::run();
+        server.expect(
+            Expectation::matching(all_of![
+                request::path("/source.tar.gz"),
+                request::headers(not(contains(key("range")))),
+            ])
+            .respond_with(
+                status_code(200)
+                    .insert_header("etag", "\"v2\"")
+                    .body("hello there"),
+            ),
+        );
+        let url: Url = server.url_str("/source.tar.gz").parse().unwrap();
+        let cache_dir = assert_fs::TempDir::new().unwrap();
+        let config = test_config(cache_dir.to_path_buf());
+        write_partial_download(&config, &url, "hello", None);
+
+        let bytes = download_resumable(&url, &config, &Progress::NoProgress)
+            .await
+            .unwrap();
+        assert_eq!(bytes, "hello there");
+    }
+
+    #[tokio::test]
+    async fn restart_download_if_range_does_not_match() {
+        let server = Server::run();
+        server.expect(
+            Expectation::matching(all_of![
+                request::path("/source.tar.gz"),
+                request::headers(contains(key("range"))),
+            ])
+            .respond_with(
+                status_code(206)
+                    .insert_header("content-range", "bytes 3-10/11")
+                    .body("lo world"),
+            ),
+        );
+        server.expect(
+            Expectation::matching(all_of![
+                request::path("/source.tar.gz"),
+                request::headers(not(contains(key("range")))),
+            ])
+            .respond_with(status_code(200).body("hello world")),
+        );
+        let url: Url = server.url_str("/source.tar.gz").parse().unwrap();
+        let cache_dir = assert_fs::TempDir::new().unwrap();
+        let config = test_config(cache_dir.to_path_buf());
+        write_partial_download(&config, &url, "hello", Some("\"v1\""));
+
+        let bytes = download_resumable(&url, &config, &Progress::NoProgress)
+            .await
+            .unwrap();
+        assert_eq!(bytes, "hello world");
+    }
+
+    #[tokio::test]
+    async fn do_not_store_error_responses() {
+        let server = Server::run();
+        server.expect(
+            Expectation::matching(request::path("/source.tar.gz"))
+                .respond_with(status_code(404).body("not found")),
+        );
+        let url: Url = server.url_str("/source.tar.gz").parse().unwrap();
+        let cache_dir = assert_fs::TempDir::new().unwrap();
+        let config = test_config(cache_dir.to_path_buf());
+
+        assert!(matches!(
+            download_resumable(&url, &config, &Progress::NoProgress).await,
+            Err(ResumableDownloadError::Request(..))
+        ));
+        let partial_path = partial_path(&config, &url);
+        assert!(std::fs::read(&partial_path).is_ok_and(|content| content.is_empty()));
+    }
+
+    #[tokio::test]
+    async fn concurrent_download_does_not_touch_locked_partial_download() {
+        let server = Server::run();
+        server.expect(
+            Expectation::matching(all_of![
+                request::path("/source.tar.gz"),
+                request::headers(not(contains(key("range")))),
+            ])
+            .respond_with(status_code(200).body("hello world")),
+        );
+        let url: Url = server.url_str("/source.tar.gz").parse().unwrap();
+        let cache_dir = assert_fs::TempDir::new().unwrap();
+        let config = test_config(cache_dir.to_path_buf());
+        write_partial_download(&config, &url, "hello", Some("\"v1\""));
+        let partial_path = partial_path(&config, &url);
+        let other_download = std::fs::File::open(&partial_path).unwrap();
+        other_download.lock_exclusive().unwrap();
+
+        let bytes = download_resumable(&url, &config, &Progress::NoProgress)
+            .await
+            .unwrap();
+        assert_eq!(bytes, "hello world");
+        assert_eq!(std::fs::read_to_string(&partial_path).unwrap(), "hello");
+    }
+
+    #[tokio::test]
+    async fn download_from_rewritten_url() {
+        let server = Server::run();
+        server.expect(
+            Expectation::matching(request::path("/github/foo/bar/archive/v1.0.0.tar.gz"))
+                .respond_with(status_code(200).body("hello world")),
+        );
+        let cache_dir = assert_fs::TempDir::new().unwrap();
+        let config = ConfigBuilder::new()
+            .cache_dir(Some(cache_dir.to_path_buf()))
+            .url_rewrites(Some(vec![(
+                Regex::new("^https://github.com/").unwrap(),
+                server.url_str("/github/"),
+            )]))
+            .build()
+            .unwrap();
+        let url: Url = "https://github.com/foo/bar/archive/v1.0.0.tar.gz"
+            .parse()
+            .unwrap();
+
+        let bytes = download_resumable(&url, &config, &Progress::NoProgress)
+            .await
+            .unwrap();
+        assert_eq!(bytes, "hello world");
+    }
+}
@@ -1,16 +1,27 @@
-use std::{collections::HashMap, io, sync::Arc};
+use std::{
+    collections::HashMap,
+    io,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
 use crate::{
     build::{BuildBehaviour, BuildError},
     config::{Config, LuaVersion, LuaVersionUnset},
-    lockfile::{LocalPackage, LocalPackageId, Lockfile, PinnedState},
+    lockfile::{
+        LocalPackage, LocalPackageId, LockConstraint, Lockfile, PinnedState, RemotePackageSourceUrl,
+    },
     luarocks_installation::{
         InstallBuildDependenciesError, LuaRocksError, LuaRocksInstallError, LuaRocksInstallation,
     },
-    package::{PackageName, PackageReq},
+    package::{PackageName, PackageNamespace, PackageReq, PackageSpec},
     progress::{MultiProgress, Progress, ProgressBar},
+    project::GitDependency,
     remote_package_db::RemotePackageDB,
-    rockspec::{BuildBackendSpec, LuaVersionError},
+    rockspec::{
+        BuildBackendSpec, GitSource, LuaVersionError, PerPlatform, RockSource, RockSourceSpec,
+        Rockspec, RockspecError,
+    },
     tree::Tree,
 };
 
@@ -18,7 +29,12 @@ use futures::future::join_all;
 use itertools::Itertools;
 use thiserror::Error;
 
-use super::{resolve::get_all_dependencies, SearchAndDownloadError};
+use super::{
+    fetch_src, git_head_commit, redacted_git_url,
+    resolve::{get_all_dependencies, PackageInstallSpec},
+    unpack::{unpack_src_rock, UnpackError as UnpackSrcRockError},
+    FetchSrcError, SearchAndDownloadError,
+};
 
 #[derive(Error, Debug)]
 pub enum InstallError {
@@ -38,6 +54,26 @@ pub enum InstallError {
     InstallBuildDependenciesError(#[from] InstallBuildDependenciesError),
     #[error("failed to build {0}: {1}")]
     BuildError(PackageName, BuildError),
+    #[error(transparent)]
+    FetchSrcError(#[from] FetchSrcError),
+    #[error(transparent)]
+    RockspecError(#[from] RockspecError),
+    #[error("no rockspec found in {0}")]
+    RockspecNotFound(String),
+    #[error("{0} is a binary rock. Only source rocks (.src.rock) can be installed from a file")]
+    BinaryRock(PathBuf),
+    #[error(transparent)]
+    UnpackSrcRock(#[from] UnpackSrcRockError),
+    #[error("{url} contains more than one rockspec:\n{}", .rockspecs.iter().map(|path| path.display()).join("\n"))]
+    MultipleRockspecs {
+        url: String,
+        rockspecs: Vec<PathBuf>,
+    },
+    #[error("the git dependency {expected} points to a repository with the rock {actual}")]
+    GitDependencyNameMismatch {
+        expected: PackageName,
+        actual: PackageName,
+    },
 }
 
 pub async fn install(
@@ -49,12 +85,28 @@ pub async fn install(
 ) -> Result<Vec<LocalPackage>, InstallError>
 where
 {
+    install_with_features(packages, pin, Vec::new(), package_db, config, progress).await
+}
+
+/// Like [`install`], but also installs the `optional_dependencies` of `packages`
+/// that are enabled by `features`, i.e. whose names are in `features`.
+/// The enabled features are recorded in the lockfile, and stay enabled
+/// when the packages are reinstalled or updated.
+pub async fn install_with_features(
+    packages: Vec<(BuildBehaviour, PackageReq)>,
+    pin: PinnedState,
+    features: Vec<PackageName>,
+    package_db: &RemotePackageDB,
+    config: &Config,
+    progress: Arc<Progress<MultiProgress>>,
+) -> Result<Vec<LocalPackage>, InstallError> {
     let lua_version = LuaVersion::from(config)?;
-    let tree = Tree::new(config.tree().clone(), lua_version)?;
+    let tree = Tree::from_config(config, lua_version)?;
     let mut lockfile = tree.lockfile()?;
     let result = install_impl(
         packages,
         pin,
+        features,
         package_db.clone(),
         config,
         &mut lockfile,
@@ -65,34 +117,534 @@ where
     result
 }
 
+/// Install a rock from a git repository containing its rockspec, rather than from a rocks server.
+/// If the repository contains more than one rockspec, `rockspec_path`
+/// (relative to the repository's root) selects the one to install.
+/// The sources are built from the checked out repository,
+/// and the git URL and the checked out commit are recorded in the lockfile.
+pub async fn install_from_git(
+    source: GitSource,
+    rockspec_path: Option<PathBuf>,
+    pin: PinnedState,
+    build_behaviour: BuildBehaviour,
+    package_db: &RemotePackageDB,
+    config: &Config,
+    progress: Arc<Progress<MultiProgress>>,
+) -> Result<LocalPackage, InstallError> {
+    let bar = progress.map(|p| {
+        p.add(ProgressBar::from(format!(
+            "🦠 Cloning {}",
+            redacted_git_url(&source.url)
+        )))
+    });
+    let (rockspec, source, _repo_dir) =
+        fetch_git_rockspec(source, rockspec_path, config, &bar).await?;
+    install_local_rockspec(
+        rockspec,
+        source,
+        pin,
+        build_behaviour,
+        package_db,
+        config,
+        &bar,
+        progress,
+    )
+    .await
+}
+
+/// Clone a git repository containing a rockspec into a temporary directory.
+/// Returns the rockspec, with the checked out repository as its source,
+/// the git source to record in the lockfile and the directory, which is removed when dropped.
+pub(crate) async fn fetch_git_rockspec(
+    source: GitSource,
+    rockspec_path: Option<PathBuf>,
+    config: &Config,
+    bar: &Progress<ProgressBar>,
+) -> Result<(Rockspec, RemotePackageSourceUrl, tempdir::TempDir), InstallError> {
+    // Credentials must neither be displayed nor persisted in the lockfile.
+    let url = redacted_git_url(&source.url);
+    let repo_dir = tempdir::TempDir::new("rocks-git")?;
+    let git_source = RockSource {
+        source_spec: RockSourceSpec::Git(source.clone()),
+        integrity: None,
+        archive_name: None,
+        unpack_dir: None,
+    };
+    fetch_src(repo_dir.path(), &git_source, config, bar).await?;
+    let commit = git_head_commit(repo_dir.path())?;
+
+    let rockspec_path = match rockspec_path {
+        Some(rockspec_path) => rockspec_path,
+        None => match find_rockspecs(repo_dir.path()).as_slice() {
+            [] => return Err(InstallError::RockspecNotFound(url)),
+            [rockspec_path] => rockspec_path.clone(),
+            rockspecs => {
+                return Err(InstallError::MultipleRockspecs {
+                    url,
+                    rockspecs: rockspecs.to_vec(),
+                })
+            }
+        },
+    };
+    let rockspec_content = std::fs::read_to_string(repo_dir.path().join(rockspec_path))?;
+    let mut rockspec = Rockspec::new(&rockspec_content)?;
+    // Build the checked out revision, rather than the source the rockspec points to.
+    rockspec.source = PerPlatform {
+        default: RockSource {
+            source_spec: RockSourceSpec::File(repo_dir.path().to_path_buf()),
+            integrity: None,
+            archive_name: None,
+            unpack_dir: None,
+        },
+        per_platform: HashMap::new(),
+    };
+
+    Ok((
+        rockspec,
+        RemotePackageSourceUrl::Git {
+            url,
+            checkout_ref: source.checkout_ref,
+            commit: Some(commit),
+        },
+        repo_dir,
+    ))
+}
+
+/// Install a packed source rock (`.src.rock`), e.g. a vendored one,
+/// along with its dependencies from `package_db`.
+/// The rock is recorded in the lockfile with the path of the `.src.rock` as its source.
+/// Binary rocks can't be installed, as they can't be built.
+pub async fn install_from_src_rock(
+    path: &Path,
+    pin: PinnedState,
+    build_behaviour: BuildBehaviour,
+    package_db: &RemotePackageDB,
+    config: &Config,
+    progress: Arc<Progress<MultiProgress>>,
+) -> Result<LocalPackage, InstallError> {
+    let path = path.canonicalize()?;
+    let bar = progress.map(|p| {
+        p.add(ProgressBar::from(format!(
+            "📦 Unpacking {}",
+            path.display()
+        )))
+    });
+    let (rockspec, _rock_dir) = unpack_src_rock_rockspec(&path, config, &bar).await?;
+    install_local_rockspec(
+        rockspec,
+        RemotePackageSourceUrl::File { path },
+        pin,
+        build_behaviour,
+        package_db,
+        config,
+        &bar,
+        progress,
+    )
+    .await
+}
+
+/// Unpack the packed source rock at `path` into a temporary directory.
+/// Returns its rockspec, with the packed source as its source,
+/// and the directory, which is removed when dropped.
+pub(crate) async fn unpack_src_rock_rockspec(
+    path: &Path,
+    config: &Config,
+    bar: &Progress<ProgressBar>,
+) -> Result<(Rockspec, tempdir::TempDir), InstallError> {
+    let rock_dir = tempdir::TempDir::new("rocks-src-rock")?;
+    unpack_src_rock(
+        std::fs::File::open(path)?,
+        rock_dir.path().to_path_buf(),
+        bar,
+    )
+    .await?;
+    if rock_dir.path().join("rock_manifest").is_file() {
+        return Err(InstallError::BinaryRock(path.to_path_buf()));
+    }
+
+    let entries = std::fs::read_dir(rock_dir.path())?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<io::Result<Vec<_>>>()?;
+    let (rockspecs, sources): (Vec<_>, Vec<_>) = entries
+        .into_iter()
+        .partition(|entry| entry.extension().is_some_and(|ext| ext == "rockspec"));
+    let rockspec_path = match rockspecs.as_slice() {
+        [] => return Err(InstallError::RockspecNotFound(path.display().to_string())),
+        [rockspec_path] => rockspec_path,
+        rockspecs => {
+            return Err(InstallError::MultipleRockspecs {
+                url: path.display().to_string(),
+                rockspecs: rockspecs.to_vec(),
+            })
+        }
+    };
+    let mut rockspec = Rockspec::new(&std::fs::read_to_string(rockspec_path)?)?;
+
+    // Build the source that is packed alongside the rockspec, rather than the one it points to.
+    // luarocks packs it as a directory (e.g. a git checkout) or as the downloaded archive.
+    let rock_source = rockspec.source.for_platform(config.platform()).clone();
+    rockspec.source = PerPlatform {
+        default: match sources.as_slice() {
+            [source] if source.is_file() => RockSource {
+                source_spec: RockSourceSpec::File(source.clone()),
+                ..rock_source
+            },
+            [source] => RockSource {
+                source_spec: RockSourceSpec::File(source.clone()),
+                integrity: None,
+                archive_name: None,
+                unpack_dir: None,
+            },
+            _ => RockSource {
+                source_spec: RockSourceSpec::File(rock_dir.path().to_path_buf()),
+                integrity: None,
+                archive_name: None,
+                unpack_dir: rock_source.unpack_dir,
+            },
+        },
+        per_platform: HashMap::new(),
+    };
+
+    Ok((rockspec, rock_dir))
+}
+
+/// Install a rockspec whose source has been fetched already, along with its dependencies,
+/// recording it in the lockfile with `source`.
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn install_local_rockspec(
+    rockspec: Rockspec,
+    source: RemotePackageSourceUrl,
+    pin: PinnedState,
+    build_behaviour: BuildBehaviour,
+    package_db: &RemotePackageDB,
+    config: &Config,
+    bar: &Progress<ProgressBar>,
+    progress: Arc<Progress<MultiProgress>>,
+) -> Result<LocalPackage, InstallError> {
+    let dependencies = rockspec
+        .dependencies
+        .for_platform(config.platform())
+        .iter()
+        .filter(|req| !req.name().eq(&PackageName::new("lua".into())))
+        .cloned()
+        .collect_vec();
+    install(
+        dependencies
+            .iter()
+            .map(|req| (BuildBehaviour::NoForce, req.clone()))
+            .collect(),
+        pin,
+        package_db,
+        config,
+        progress.clone(),
+    )
+    .await?;
+
+    install_build_dependencies(&rockspec, config, bar, progress).await?;
+
+    let package_name = rockspec.package.clone();
+    bar.map(|b| b.set_message(format!("💻 Installing {}", package_name)));
+    let package = crate::build::build(
+        rockspec,
+        None,
+        pin,
+        LockConstraint::Unconstrained,
+        build_behaviour,
+        config,
+        bar,
+    )
+    .await
+    .map_err(|err| InstallError::BuildError(package_name, err))?
+    .with_source(Some(source));
+    bar.map(|b| b.finish_and_clear());
+
+    let lua_version = LuaVersion::from(config)?;
+    let tree = Tree::from_config(config, lua_version)?;
+    let dependencies = dependencies
+        .iter()
+        .filter_map(|req| tree.has_rock(req))
+        .collect_vec();
+    tree.lockfile()?.map_then_flush(|lockfile| {
+        lockfile.add(&package);
+        for dependency in &dependencies {
+            lockfile.add_dependency(&package, dependency);
+        }
+        Ok::<_, io::Error>(())
+    })?;
+
+    Ok(package)
+}
+
+/// Install the dependencies of a project's `rockspec` that are missing from the tree `config`
+/// operates on, including its `optional_dependencies` that are enabled by `features`.
+/// Unless [`Config::no_dev_dependencies`] is set, its test dependencies are installed into
+/// the test tree and its build dependencies into the build tree.
+/// The lockfile of the tree only ever records the runtime dependencies.
+pub async fn install_project_dependencies(
+    rockspec: &Rockspec,
+    features: Vec<PackageName>,
+    package_db: &RemotePackageDB,
+    config: &Config,
+    progress: Arc<Progress<MultiProgress>>,
+) -> Result<(), InstallError> {
+    let dependencies = rockspec
+        .dependencies
+        .for_platform(config.platform())
+        .iter()
+        .chain(rockspec.enabled_optional_dependencies(&features, config.platform()))
+        .cloned()
+        .collect_vec();
+    install_missing(dependencies, package_db, config, progress.clone()).await?;
+
+    if config.no_dev_dependencies() {
+        return Ok(());
+    }
+
+    install_missing(
+        rockspec
+            .test_dependencies
+            .for_platform(config.platform())
+            .to_vec(),
+        package_db,
+        &super::test_tree_config(config),
+        progress.clone(),
+    )
+    .await?;
+    let bar = progress.map(|p| p.new_bar());
+    install_build_dependencies(rockspec, config, &bar, progress).await?;
+    bar.map(|b| b.finish_and_clear());
+    Ok(())
+}
+
+/// Install the `git_dependencies` of a project that are missing from the tree `config` operates on.
+/// A git dependency is missing unless the tree has the rock, installed from the same repository
+/// and ref.
+pub async fn install_git_dependencies(
+    git_dependencies: &[GitDependency],
+    package_db: &RemotePackageDB,
+    config: &Config,
+    progress: Arc<Progress<MultiProgress>>,
+) -> Result<Vec<LocalPackage>, InstallError> {
+    let tree = Tree::from_config(config, LuaVersion::from(config)?)?;
+    let missing = {
+        let lockfile = tree.lockfile()?;
+        git_dependencies
+            .iter()
+            .filter(|dependency| {
+                !lockfile.rocks().values().any(|package| {
+                    package.name() == &dependency.name
+                        && matches!(
+                            package.source(),
+                            Some(RemotePackageSourceUrl::Git { url, checkout_ref, .. })
+                                if *url == redacted_git_url(&dependency.source.url)
+                                    && *checkout_ref == dependency.source.checkout_ref
+                        )
+                })
+            })
+            .collect_vec()
+    };
+    let mut installed = Vec::new();
+    for dependency in missing {
+        let package = install_from_git(
+            dependency.source.clone(),
+            dependency.rockspec.clone(),
+            PinnedState::Unpinned,
+            BuildBehaviour::NoForce,
+            package_db,
+            config,
+            progress.clone(),
+        )
+        .await?;
+        if package.name() != &dependency.name {
+            return Err(InstallError::GitDependencyNameMismatch {
+                expected: dependency.name.clone(),
+                actual: package.name().clone(),
+            });
+        }
+        installed.push(package);
+    }
+    Ok(installed)
+}
+
+async fn install_missing(
+    dependencies: Vec<PackageReq>,
+    package_db: &RemotePackageDB,
+    config: &Config,
+    progress: Arc<Progress<MultiProgress>>,
+) -> Result<(), InstallError> {
+    let tree = Tree::from_config(config, LuaVersion::from(config)?)?;
+    let dependencies = dependencies
+        .into_iter()
+        .filter(|req| !req.name().eq(&PackageName::new("lua".into())))
+        .filter(|req| tree.has_rock(req).is_none())
+        .map(|req| (BuildBehaviour::NoForce, req))
+        .collect_vec();
+    if !dependencies.is_empty() {
+        install(
+            dependencies,
+            PinnedState::Unpinned,
+            package_db,
+            config,
+            progress,
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+/// Find the rockspecs in a repository, relative to its root.
+fn find_rockspecs(repo_dir: &Path) -> Vec<PathBuf> {
+    walkdir::WalkDir::new(repo_dir)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_entry(|entry| entry.file_name() != ".git")
+        .filter_map(Result::ok)
+        .filter(|entry| {
+            entry.file_type().is_file()
+                && entry
+                    .path()
+                    .extension()
+                    .is_some_and(|ext| ext == "rockspec")
+        })
+        .filter_map(|entry| {
+            entry
+                .path()
+                .strip_prefix(repo_dir)
+                .ok()
+                .map(Path::to_path_buf)
+        })
+        .collect()
+}
+
+/// A package that [`install`] would build and add to the lockfile.
+#[derive(Debug, Clone)]
+pub struct PlannedInstall {
+    pub package: PackageSpec,
+    pub namespace: Option<PackageNamespace>,
+    pub build_behaviour: BuildBehaviour,
+    /// Whether the package would only be installed as a dependency of a requested package.
+    pub is_dependency: bool,
+    /// The installed rock that would be removed to reinstall the package, if any.
+    pub replaces: Option<LocalPackage>,
+}
+
+/// Resolve the packages that [`install`] would download, build and add to the lockfile,
+/// without building anything, modifying the lockfile or creating the tree.
+pub async fn install_plan(
+    packages: Vec<(BuildBehaviour, PackageReq)>,
+    pin: PinnedState,
+    features: Vec<PackageName>,
+    package_db: &RemotePackageDB,
+    config: &Config,
+    progress: Arc<Progress<MultiProgress>>,
+) -> Result<Vec<PlannedInstall>, InstallError> {
+    let lua_version = LuaVersion::from(config)?;
+    let tree = Tree::from_config_unchecked(config, lua_version);
+    let lockfile = Arc::new(tree.lockfile_snapshot()?);
+    let (requested, resolved) = resolve(
+        packages,
+        pin,
+        features,
+        Arc::new(package_db.clone()),
+        lockfile.clone(),
+        config,
+        progress,
+    )
+    .await?;
+    Ok(resolved
+        .into_iter()
+        .map(|(id, install_spec)| PlannedInstall {
+            package: PackageSpec::new(install_spec.rockspec.package, install_spec.rockspec.version),
+            namespace: install_spec.spec.namespace,
+            is_dependency: !requested.contains(&id),
+            replaces: match install_spec.build_behaviour {
+                BuildBehaviour::Force => lockfile.get(&id).cloned(),
+                BuildBehaviour::NoForce => None,
+            },
+            build_behaviour: install_spec.build_behaviour,
+        })
+        .sorted_by(|a, b| a.package.name().cmp(b.package.name()))
+        .collect())
+}
+
+/// Resolve the packages to install, including their dependencies.
+/// Returns the IDs of the requested packages and the install specs of all packages.
+async fn resolve(
+    packages: Vec<(BuildBehaviour, PackageReq)>,
+    pin: PinnedState,
+    features: Vec<PackageName>,
+    package_db: Arc<RemotePackageDB>,
+    lockfile: Arc<Lockfile>,
+    config: &Config,
+    progress: Arc<Progress<MultiProgress>>,
+) -> Result<
+    (
+        Vec<LocalPackageId>,
+        HashMap<LocalPackageId, PackageInstallSpec>,
+    ),
+    SearchAndDownloadError,
+> {
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+
+    let requested = get_all_dependencies(
+        tx, packages, pin, features, package_db, lockfile, config, progress,
+    )
+    .await?;
+
+    let mut all_packages = HashMap::with_capacity(rx.len());
+
+    while let Some(dep) = rx.recv().await {
+        all_packages.insert(dep.spec.id(), dep);
+    }
+
+    Ok((requested, all_packages))
+}
+
+/// Install the `build_dependencies` of `rockspec`, and its luarocks build backend if it has one,
+/// into the luarocks tree, so that they are available to the build
+/// without being installed into the tree the rock is installed into.
+pub(crate) async fn install_build_dependencies(
+    rockspec: &Rockspec,
+    config: &Config,
+    bar: &Progress<ProgressBar>,
+    progress: Arc<Progress<MultiProgress>>,
+) -> Result<(), InstallError> {
+    let luarocks = LuaRocksInstallation::new(config)?;
+    let build_backend = match &rockspec.build.for_platform(config.platform()).build_backend {
+        Some(BuildBackendSpec::LuaRock(build_backend)) => {
+            luarocks.ensure_installed(bar).await?;
+            Some(build_backend.as_str())
+        }
+        _ => None,
+    };
+    luarocks
+        .install_build_dependencies(build_backend, rockspec, progress)
+        .await?;
+    Ok(())
+}
+
 async fn install_impl(
     packages: Vec<(BuildBehaviour, PackageReq)>,
     pin: PinnedState,
+    features: Vec<PackageName>,
     package_db: RemotePackageDB,
     config: &Config,
     lockfile: &mut Lockfile,
     progress_arc: Arc<Progress<MultiProgress>>,
 ) -> Result<Vec<LocalPackage>, InstallError> {
     let progress = Arc::clone(&progress_arc);
-    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
 
-    get_all_dependencies(
-        tx,
+    let (_, all_packages) = resolve(
         packages,
         pin,
+        features,
         Arc::new(package_db),
-        Arc::new(lockfile.clone()),
+        Arc::new(lockfile.snapshot()),
         config,
         progress_arc.clone(),
     )
     .await?;
 
-    let mut all_packages = HashMap::with_capacity(rx.len());
-
-    while let Some(dep) = rx.recv().await {
-        all_packages.insert(dep.spec.id(), dep);
-    }
-
     let installed_packages = join_all(all_packages.clone().into_values().map(|install_spec| {
         let progress_arc = progress_arc.clone();
         let package = install_spec.rockspec.package.clone();
@@ -106,19 +658,13 @@ async fn install_impl(
         let config = config.clone();
 
         tokio::spawn(async move {
+            let features = install_spec.spec.features().to_vec();
             let rockspec = install_spec.rockspec;
-            if let Some(BuildBackendSpec::LuaRock(build_backend)) =
-                &rockspec.build.current_platform().build_backend
-            {
-                let luarocks = LuaRocksInstallation::new(&config)?;
-                luarocks.ensure_installed(&bar).await?;
-                luarocks
-                    .install_build_dependencies(build_backend, &rockspec, progress_arc)
-                    .await?;
-            }
+            install_build_dependencies(&rockspec, &config, &bar, progress_arc).await?;
 
             let pkg = crate::build::build(
                 rockspec,
+                install_spec.spec.namespace.clone(),
                 pin,
                 install_spec.spec.constraint(),
                 install_spec.build_behaviour,
@@ -126,7 +672,8 @@ async fn install_impl(
                 &bar,
             )
             .await
-            .map_err(|err| InstallError::BuildError(package, err))?;
+            .map_err(|err| InstallError::BuildError(package, err))?
+            .with_features(features);
 
             bar.map(|b| b.finish_and_clear());
 
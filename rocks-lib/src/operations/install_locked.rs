@@ -0,0 +1,395 @@
+use std::{collections::HashMap, io, sync::Arc};
+
+use itertools::Itertools;
+use thiserror::Error;
+
+use crate::{
+    build::{BuildBehaviour, BuildError},
+    config::{Config, LuaVersion, LuaVersionUnset},
+    hash::HasIntegrity,
+    lockfile::{DependencyCycle, LocalPackage, Lockfile, RemotePackageSourceUrl},
+    package::{PackageName, PackageReq},
+    progress::{MultiProgress, Progress, ProgressBar},
+    project::{GitDependency, Project},
+    remote_package_db::{RemotePackageDB, RemotePackageDBError},
+    rockspec::{GitSource, PerPlatform, RockSource, Rockspec, SourceUrlError},
+    tree::Tree,
+};
+
+use super::{
+    download_rockspec,
+    install::{
+        fetch_git_rockspec, install_build_dependencies, install_local_rockspec,
+        unpack_src_rock_rockspec,
+    },
+    redacted_git_url, test_tree_config, InstallError, SearchAndDownloadError,
+};
+
+#[derive(Error, Debug)]
+pub enum InstallLockedError {
+    #[error("the lockfile {} is out of date with the project.rockspec:\n{}", .0.display(), .1.join("\n"))]
+    OutOfDate(std::path::PathBuf, Vec<String>),
+    #[error("{0} does not match the hashes in the lockfile")]
+    IntegrityMismatch(String),
+    #[error(transparent)]
+    Install(#[from] InstallError),
+    #[error(transparent)]
+    SearchAndDownload(#[from] SearchAndDownloadError),
+    #[error(transparent)]
+    RemotePackageDB(#[from] RemotePackageDBError),
+    #[error(transparent)]
+    SourceUrl(#[from] SourceUrlError),
+    #[error(transparent)]
+    DependencyCycle(#[from] DependencyCycle),
+    #[error(transparent)]
+    LuaVersionUnset(#[from] LuaVersionUnset),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+/// Install exactly the rocks that are recorded in the lockfiles of a project's trees,
+/// e.g. to bootstrap a project from its committed lockfiles in CI.
+///
+/// - Fails before installing anything if a lockfile is out of date with the `project.rockspec`,
+///   i.e. if it doesn't satisfy the project's dependencies, or if it records rocks
+///   that the project doesn't depend on.
+/// - The rocks that are missing from a tree are installed at their locked versions,
+///   with their locked dependencies. Nothing is resolved, so the lockfiles don't change.
+/// - Fails if the rockspec or the source of a rock doesn't match the hashes recorded in
+///   the lockfile. They are verified before the rock is built, so it isn't installed,
+///   and the tree and its lockfile are left as they were.
+///
+/// The runtime dependencies, including the `optional_dependencies` and the `git_dependencies`,
+/// are installed into the tree `config` operates on. Unless [`Config::no_dev_dependencies`]
+/// is set, the `test_dependencies` are installed into the test tree.
+/// The build dependencies of rocks that use a luarocks build backend are not locked.
+pub async fn install_locked(
+    project: &Project,
+    package_db: &RemotePackageDB,
+    config: &Config,
+    progress: Arc<Progress<MultiProgress>>,
+) -> Result<Vec<LocalPackage>, InstallLockedError> {
+    let rockspec = project.rockspec();
+    let lua_version = LuaVersion::from(config)?;
+    let dependencies = rockspec
+        .dependencies
+        .for_platform(config.platform())
+        .iter()
+        .collect_vec();
+    // Optional dependencies are only locked if their feature is enabled.
+    let allowed = dependencies
+        .iter()
+        .copied()
+        .chain(
+            rockspec
+                .optional_dependencies
+                .for_platform(config.platform()),
+        )
+        .collect_vec();
+    let mut trees = vec![(
+        config.clone(),
+        dependencies,
+        allowed,
+        project.git_dependencies(),
+    )];
+    if !config.no_dev_dependencies() {
+        let test_dependencies = rockspec
+            .test_dependencies
+            .for_platform(config.platform())
+            .iter()
+            .collect_vec();
+        trees.push((
+            test_tree_config(config),
+            test_dependencies.clone(),
+            test_dependencies,
+            &[],
+        ));
+    }
+
+    let mut locked_trees = Vec::new();
+    for (config, required, allowed, git_dependencies) in trees {
+        let tree = Tree::from_config(&config, lua_version.clone())?;
+        let lockfile = tree.lockfile_snapshot()?;
+        let problems = out_of_date(&lockfile, &required, &allowed, git_dependencies);
+        if !problems.is_empty() {
+            return Err(InstallLockedError::OutOfDate(
+                tree.root().join("lock.json"),
+                problems,
+            ));
+        }
+        locked_trees.push((config, tree, lockfile));
+    }
+
+    let mut package_db = package_db.clone();
+    let mut installed = Vec::new();
+    for (config, tree, lockfile) in locked_trees {
+        package_db
+            .add_namespaces(
+                lockfile
+                    .rocks()
+                    .values()
+                    .filter_map(|package| package.namespace()),
+                &config,
+            )
+            .await?;
+        installed.extend(
+            install_missing_locked(&lockfile, &tree, &package_db, &config, progress.clone())
+                .await?,
+        );
+    }
+    Ok(installed)
+}
+
+/// Why `lockfile` is out of date with the `required` and `allowed` dependencies
+/// and the `git_dependencies` of a project, if it is.
+fn out_of_date(
+    lockfile: &Lockfile,
+    required: &[&PackageReq],
+    allowed: &[&PackageReq],
+    git_dependencies: &[GitDependency],
+) -> Vec<String> {
+    let lua = PackageName::new("lua".into());
+    let missing = required
+        .iter()
+        .filter(|req| *req.name() != lua && lockfile.has_rock(req).is_none())
+        .map(|req| format!("{} is not locked", req));
+    let missing_git = git_dependencies
+        .iter()
+        .filter(|dependency| {
+            !lockfile.rocks().values().any(|package| {
+                package.name() == &dependency.name
+                    && matches!(
+                        package.source(),
+                        Some(RemotePackageSourceUrl::Git { url, checkout_ref, .. })
+                            if *url == redacted_git_url(&dependency.source.url)
+                                && *checkout_ref == dependency.source.checkout_ref
+                    )
+            })
+        })
+        .map(|dependency| format!("the git dependency {} is not locked", dependency.name));
+    let unused = lockfile
+        .entrypoints()
+        .into_iter()
+        .filter(|package| {
+            !allowed.iter().any(|req| req.name() == package.name())
+                && !git_dependencies
+                    .iter()
+                    .any(|dependency| &dependency.name == package.name())
+        })
+        .map(|package| {
+            format!(
+                "{}@{} is locked, but not a dependency",
+                package.name(),
+                package.version()
+            )
+        });
+    missing.chain(missing_git).chain(unused).collect_vec()
+}
+
+/// Install the rocks of `lockfile` that are missing from `tree`, dependencies first.
+async fn install_missing_locked(
+    lockfile: &Lockfile,
+    tree: &Tree,
+    package_db: &RemotePackageDB,
+    config: &Config,
+    progress: Arc<Progress<MultiProgress>>,
+) -> Result<Vec<LocalPackage>, InstallLockedError> {
+    let missing = dependency_order(lockfile)?
+        .into_iter()
+        .filter(|package| !tree.root_for(package).is_dir())
+        .collect_vec();
+    reinstall_locked(missing, tree, package_db, config, progress).await
+}
+
+/// The rocks of `lockfile`, with dependencies before the rocks that depend on them.
+pub(crate) fn dependency_order(lockfile: &Lockfile) -> Result<Vec<&LocalPackage>, DependencyCycle> {
+    let mut ordered: Vec<&LocalPackage> = Vec::new();
+    for entrypoint in lockfile.entrypoints() {
+        for package in lockfile
+            .all_dependencies(&entrypoint.id())?
+            .into_iter()
+            .chain(std::iter::once(entrypoint))
+        {
+            if !ordered.iter().any(|ordered| ordered.id() == package.id()) {
+                ordered.push(package);
+            }
+        }
+    }
+    Ok(ordered)
+}
+
+/// Install the `missing` rocks of `tree`'s lockfile exactly as they are recorded in it.
+/// They must be ordered with dependencies first, e.g. with [`dependency_order`].
+pub(crate) async fn reinstall_locked(
+    missing: Vec<&LocalPackage>,
+    tree: &Tree,
+    package_db: &RemotePackageDB,
+    config: &Config,
+    progress: Arc<Progress<MultiProgress>>,
+) -> Result<Vec<LocalPackage>, InstallLockedError> {
+    if missing.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    // The missing rocks are removed from the lockfile until they are installed,
+    // as it records the installed rocks, which are not reinstalled as dependencies.
+    tree.lockfile()?.map_then_flush(|tree_lockfile| {
+        for package in &missing {
+            tree_lockfile.remove(package);
+        }
+        Ok::<_, io::Error>(())
+    })?;
+
+    // The rocks are installed one at a time, as installing a git dependency
+    // or a source rock updates the lockfile.
+    let mut installed = Vec::new();
+    for (index, locked) in missing.iter().enumerate() {
+        let package =
+            match install_locked_package(locked, package_db, config, progress.clone()).await {
+                Ok(package) => package,
+                Err(err) => {
+                    // The rocks that haven't been installed are recorded again,
+                    // so that a failed install leaves the lockfile as it was.
+                    tree.lockfile()?.map_then_flush(|tree_lockfile| {
+                        for package in &missing[index..] {
+                            tree_lockfile.add(package);
+                        }
+                        Ok::<_, io::Error>(())
+                    })?;
+                    return Err(err);
+                }
+            };
+        tree.lockfile()?.map_then_flush(|tree_lockfile| {
+            tree_lockfile.remove(&package);
+            tree_lockfile.add(locked);
+            Ok::<_, io::Error>(())
+        })?;
+        installed.push((*locked).clone());
+    }
+    Ok(installed)
+}
+
+/// Install `locked`, verifying its rockspec and source against the hashes in the lockfile
+/// before building it, so that a rock that doesn't match them is never installed.
+async fn install_locked_package(
+    locked: &LocalPackage,
+    package_db: &RemotePackageDB,
+    config: &Config,
+    progress: Arc<Progress<MultiProgress>>,
+) -> Result<LocalPackage, InstallLockedError> {
+    let bar = progress.map(|p| {
+        p.add(ProgressBar::from(format!(
+            "💻 Installing {}@{}",
+            locked.name(),
+            locked.version()
+        )))
+    });
+    match locked.source() {
+        Some(RemotePackageSourceUrl::Git {
+            url,
+            checkout_ref,
+            commit,
+        }) => {
+            let source = GitSource {
+                checkout_ref: commit.clone().or(checkout_ref.clone()),
+                ..GitSource::from_repository_url(url)?
+            };
+            let (mut rockspec, source, _repo_dir) =
+                fetch_git_rockspec(source, None, config, &bar).await?;
+            if !rockspec_matches(&rockspec, locked)? {
+                return Err(integrity_mismatch(locked));
+            }
+            expect_locked_source(&mut rockspec, locked, config);
+            Ok(install_local_rockspec(
+                rockspec,
+                source,
+                locked.pinned(),
+                BuildBehaviour::Force,
+                package_db,
+                config,
+                &bar,
+                progress,
+            )
+            .await
+            .map_err(|err| source_mismatch(err, locked))?)
+        }
+        Some(RemotePackageSourceUrl::File { path }) => {
+            let (mut rockspec, _rock_dir) = unpack_src_rock_rockspec(path, config, &bar).await?;
+            if !rockspec_matches(&rockspec, locked)? {
+                return Err(integrity_mismatch(locked));
+            }
+            expect_locked_source(&mut rockspec, locked, config);
+            Ok(install_local_rockspec(
+                rockspec,
+                RemotePackageSourceUrl::File { path: path.clone() },
+                locked.pinned(),
+                BuildBehaviour::Force,
+                package_db,
+                config,
+                &bar,
+                progress,
+            )
+            .await
+            .map_err(|err| source_mismatch(err, locked))?)
+        }
+        None => {
+            let mut rockspec =
+                download_rockspec(&locked.clone().into_package_req(), package_db, config, &bar)
+                    .await?;
+            if !rockspec_matches(&rockspec, locked)? {
+                return Err(integrity_mismatch(locked));
+            }
+            expect_locked_source(&mut rockspec, locked, config);
+            install_build_dependencies(&rockspec, config, &bar, progress).await?;
+            let package = crate::build::build(
+                rockspec,
+                locked.namespace().cloned(),
+                locked.pinned(),
+                locked.constraint(),
+                BuildBehaviour::Force,
+                config,
+                &bar,
+            )
+            .await
+            .map_err(|err| match err {
+                BuildError::SourceIntegrityMismatch { .. } => integrity_mismatch(locked),
+                err => InstallError::BuildError(locked.name().clone(), err).into(),
+            })?;
+            bar.map(|b| b.finish_and_clear());
+            Ok(package)
+        }
+    }
+}
+
+fn integrity_mismatch(locked: &LocalPackage) -> InstallLockedError {
+    InstallLockedError::IntegrityMismatch(format!("{}@{}", locked.name(), locked.version()))
+}
+
+/// Whether `rockspec` matches the rockspec hash of `locked`.
+fn rockspec_matches(rockspec: &Rockspec, locked: &LocalPackage) -> io::Result<bool> {
+    Ok(rockspec.hash()? == locked.hashes().rockspec)
+}
+
+/// Makes the build verify the fetched source against the source hash of `locked`
+/// before building it, rather than against the integrity that the rockspec declares, if any.
+fn expect_locked_source(rockspec: &mut Rockspec, locked: &LocalPackage, config: &Config) {
+    let source = rockspec.source.for_platform(config.platform()).clone();
+    rockspec.source = PerPlatform {
+        default: RockSource {
+            integrity: Some(locked.hashes().source.clone()),
+            ..source
+        },
+        per_platform: HashMap::new(),
+    };
+}
+
+/// Turns a build failure caused by a source that doesn't match its integrity
+/// into an [`InstallLockedError::IntegrityMismatch`].
+fn source_mismatch(err: InstallError, locked: &LocalPackage) -> InstallLockedError {
+    match err {
+        InstallError::BuildError(_, BuildError::SourceIntegrityMismatch { .. }) => {
+            integrity_mismatch(locked)
+        }
+        err => err.into(),
+    }
+}
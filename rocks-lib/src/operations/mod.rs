@@ -1,22 +1,38 @@
 #![allow(ambiguous_glob_reexports)]
 
+mod changelog;
+mod check_integrity;
+mod clean;
+mod doc;
+mod doctor;
 mod download;
 mod fetch;
 mod install;
+mod install_locked;
+mod pack;
 mod pin;
 mod remove;
 mod resolve;
 mod run;
+mod search;
 mod test;
 mod unpack;
 mod update;
 
+pub use changelog::*;
+pub use check_integrity::*;
+pub use clean::*;
+pub use doc::*;
+pub use doctor::*;
 pub use download::*;
 pub use fetch::*;
 pub use install::*;
+pub use install_locked::*;
+pub use pack::*;
 pub use pin::*;
 pub use remove::*;
 pub use run::*;
+pub use search::*;
 pub use test::*;
 pub use unpack::*;
 pub use update::*;
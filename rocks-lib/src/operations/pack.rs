@@ -0,0 +1,466 @@
+use std::{
+    io::{self, Read as _, Write as _},
+    path::{Path, PathBuf},
+};
+
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use gpgme::{Context, Data};
+use itertools::Itertools;
+use thiserror::Error;
+use walkdir::WalkDir;
+use zip::{write::SimpleFileOptions, ZipWriter};
+
+use crate::{
+    build::{utils::is_glob, STAGING_DIR_NAME},
+    config::Config,
+    project::Project,
+    rockspec::{BuildBackendSpec, ModuleSpec, Rockspec},
+    upload::SignatureProtocol,
+};
+
+#[derive(Error, Debug)]
+pub enum PackError {
+    #[error("IO operation failed: {0}")]
+    Io(#[from] io::Error),
+    #[error("failed to write rock archive: {0}")]
+    Zip(#[from] zip::result::ZipError),
+    #[error("failed to sign {0}: {1}")]
+    Signature(PathBuf, gpgme::Error),
+    #[error("cannot sign {0} without a signature protocol")]
+    NoSignatureProtocol(PathBuf),
+    #[error("invalid glob pattern '{pattern}': {err}")]
+    InvalidGlob {
+        pattern: String,
+        err: globset::Error,
+    },
+    #[error("cannot exclude {path} with '{pattern}': it is a source of a module declared in the rockspec")]
+    ExcludesRequiredFile { pattern: String, path: String },
+}
+
+/// Globs, relative to the project root, that select which of the project's files are packed.
+#[derive(Debug, Default, Clone)]
+pub struct PackFilter {
+    /// If not empty, only the files that match one of these globs are packed,
+    /// in addition to the rockspec and the sources of the modules it declares.
+    pub include: Vec<String>,
+    /// The files and directories that match one of these globs are not packed.
+    /// The sources of the modules that the rockspec declares can't be excluded.
+    pub exclude: Vec<String>,
+}
+
+fn glob(pattern: &str) -> Result<Glob, PackError> {
+    Glob::new(pattern.trim_start_matches("./")).map_err(|err| PackError::InvalidGlob {
+        pattern: pattern.to_string(),
+        err,
+    })
+}
+
+fn glob_set(patterns: &[String]) -> Result<GlobSet, PackError> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        builder.add(glob(pattern)?);
+    }
+    builder.build().map_err(|err| PackError::InvalidGlob {
+        pattern: patterns.join(", "),
+        err,
+    })
+}
+
+/// The file name of the `.src.rock` that [`pack_project`] produces for `project`.
+pub fn packed_rock_file_name(project: &Project) -> String {
+    let rockspec = project.rockspec();
+    format!("{}-{}.src.rock", rockspec.package, rockspec.version)
+}
+
+/// Pack the project's sources into a `.src.rock` in `dest_dir`.
+/// The archive contains the project's `project.rockspec`, renamed to `<name>-<version>.rockspec`,
+/// and all other files in the project root that `filter` selects, except for the project's tree,
+/// `.git` and previously packed rocks.
+/// Returns the path of the packed rock.
+pub fn pack_project(
+    project: &Project,
+    dest_dir: &Path,
+    filter: &PackFilter,
+) -> Result<PathBuf, PackError> {
+    let rockspec = project.rockspec();
+    let include = glob_set(&filter.include)?;
+    let exclude = glob_set(&filter.exclude)?;
+    let required_files = module_sources(project);
+    for pattern in &filter.exclude {
+        let matcher = glob(pattern)?.compile_matcher();
+        // Excluding a directory excludes the files in it.
+        if let Some(path) = required_files.iter().find(|path| {
+            Path::new(path)
+                .ancestors()
+                .any(|path| matcher.is_match(path))
+        }) {
+            return Err(PackError::ExcludesRequiredFile {
+                pattern: pattern.clone(),
+                path: path.clone(),
+            });
+        }
+    }
+
+    let rock_path = dest_dir.join(packed_rock_file_name(project));
+    std::fs::create_dir_all(dest_dir)?;
+
+    let mut zip = ZipWriter::new(std::fs::File::create(&rock_path)?);
+    let options = SimpleFileOptions::default();
+
+    zip.start_file(
+        format!("{}-{}.rockspec", rockspec.package, rockspec.version),
+        options,
+    )?;
+    zip.write_all(&std::fs::read(project.root().join("project.rockspec"))?)?;
+
+    let walker = WalkDir::new(project.root())
+        .min_depth(1)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_entry(|entry| {
+            let file_name = entry.file_name().to_string_lossy();
+            (entry.depth() > 1
+                || !(matches!(file_name.as_ref(), ".rocks" | ".git" | "project.rockspec")
+                    || file_name.ends_with(".src.rock")
+                    || file_name.ends_with(".src.rock.asc")))
+                && !exclude.is_match(archive_name(project, entry.path()))
+        });
+    for entry in walker {
+        let entry = entry.map_err(io::Error::from)?;
+        let path = entry.path();
+        // The destination may be inside the project root.
+        if path == rock_path {
+            continue;
+        }
+        let name = archive_name(project, path);
+        if entry.file_type().is_dir() {
+            // With an include filter, only the directories of the included files are packed,
+            // which are created when unpacking.
+            if filter.include.is_empty() {
+                zip.add_directory(name, options)?;
+            }
+        } else if entry.file_type().is_file()
+            && (filter.include.is_empty()
+                || include.is_match(&name)
+                || required_files.contains(&name))
+        {
+            zip.start_file(name, options)?;
+            zip.write_all(&std::fs::read(path)?)?;
+        }
+    }
+    zip.finish()?;
+
+    Ok(rock_path)
+}
+
+/// The file name of the binary rock that [`pack_binary_rock`] produces for `rockspec`,
+/// e.g. `foo-1.0.0-1.linux-x86_64.rock`.
+/// Rocks built with the debug [`crate::build::BuildProfile`] get a `-debug` suffix,
+/// e.g. `foo-1.0.0-1.linux-x86_64-debug.rock`.
+pub fn binary_rock_file_name(rockspec: &Rockspec, config: &Config) -> String {
+    format!(
+        "{}-{}.{}-{}{}.rock",
+        rockspec.package,
+        rockspec.version,
+        std::env::consts::OS,
+        std::env::consts::ARCH,
+        config.build_profile().rock_name_suffix()
+    )
+}
+
+/// Pack the files that [`crate::build::build_no_install`] staged in `build_dir`
+/// into a binary rock in `dest_dir`, along with the rockspec.
+/// Returns the path of the packed rock.
+pub fn pack_binary_rock(
+    rockspec: &Rockspec,
+    build_dir: &Path,
+    dest_dir: &Path,
+    config: &Config,
+) -> Result<PathBuf, PackError> {
+    let staging_dir = build_dir.join(STAGING_DIR_NAME);
+    let rock_path = dest_dir.join(binary_rock_file_name(rockspec, config));
+    std::fs::create_dir_all(dest_dir)?;
+
+    let mut zip = ZipWriter::new(std::fs::File::create(&rock_path)?);
+    let options = SimpleFileOptions::default();
+
+    zip.start_file(
+        format!("{}-{}.rockspec", rockspec.package, rockspec.version),
+        options,
+    )?;
+    zip.write_all(rockspec.raw_content.as_bytes())?;
+
+    for entry in WalkDir::new(&staging_dir).min_depth(1).sort_by_file_name() {
+        let entry = entry.map_err(io::Error::from)?;
+        let name = entry
+            .path()
+            .strip_prefix(&staging_dir)
+            .expect("walked path is inside the staging directory")
+            .to_string_lossy()
+            .replace('\\', "/");
+        if entry.file_type().is_dir() {
+            zip.add_directory(name, options)?;
+        } else if entry.file_type().is_file() {
+            zip.start_file(name, options)?;
+            zip.write_all(&std::fs::read(entry.path())?)?;
+        }
+    }
+    zip.finish()?;
+
+    Ok(rock_path)
+}
+
+/// The name of a file in the project root in the packed rock.
+fn archive_name(project: &Project, path: &Path) -> String {
+    path.strip_prefix(project.root())
+        .expect("walked path is inside the project root")
+        .to_string_lossy()
+        .replace('\\', "/")
+}
+
+/// The source files of the modules that the project's rockspec declares for the current platform,
+/// relative to the project root.
+fn module_sources(project: &Project) -> Vec<String> {
+    let modules = match &project.rockspec().build.current_platform().build_backend {
+        Some(BuildBackendSpec::Builtin(build_spec)) => &build_spec.modules,
+        _ => return Vec::new(),
+    };
+    modules
+        .values()
+        .flat_map(|module_spec| match module_spec {
+            ModuleSpec::SourcePath(path) => vec![path.clone()],
+            ModuleSpec::SourcePaths(paths) => paths.clone(),
+            ModuleSpec::ModulePaths(module_paths) => module_paths.sources.clone(),
+        })
+        .filter(|path| !is_glob(path))
+        .map(|path| {
+            path.to_string_lossy()
+                .trim_start_matches("./")
+                .replace('\\', "/")
+        })
+        .sorted()
+        .dedup()
+        .collect()
+}
+
+/// Create a detached, armored signature of the packed rock at `rock_path`,
+/// which is written to `<rock_path>.asc`, where rocks expects signatures to be hosted.
+/// `key` is the fingerprint or key ID of the secret key to sign with.
+/// If it is not set, GPG's default key is used.
+/// Returns the path of the signature.
+pub fn sign_rock(
+    rock_path: &Path,
+    key: Option<&str>,
+    protocol: SignatureProtocol,
+) -> Result<PathBuf, PackError> {
+    if let SignatureProtocol::None = protocol {
+        return Err(PackError::NoSignatureProtocol(rock_path.to_path_buf()));
+    }
+    let signature_err = |err| PackError::Signature(rock_path.to_path_buf(), err);
+
+    let content = std::fs::read(rock_path)?;
+    let mut ctx = Context::from_protocol(protocol.into()).map_err(signature_err)?;
+    ctx.set_armor(true);
+    if let Some(key) = key {
+        let key = ctx.get_secret_key(key).map_err(signature_err)?;
+        ctx.add_signer(&key).map_err(signature_err)?;
+    }
+
+    let mut signature = Data::new().map_err(signature_err)?;
+    ctx.sign_detached(content, &mut signature)
+        .map_err(signature_err)?;
+    let mut signature_str = String::new();
+    signature.read_to_string(&mut signature_str)?;
+
+    let signature_path = signature_path(rock_path);
+    std::fs::write(&signature_path, signature_str)?;
+
+    Ok(signature_path)
+}
+
+/// The path of the detached signature of the file at `path`.
+pub fn signature_path(path: &Path) -> PathBuf {
+    let mut signature_path = path.as_os_str().to_owned();
+    signature_path.push(".asc");
+    PathBuf::from(signature_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+
+    use assert_fs::prelude::{FileWriteStr as _, PathChild as _, PathCreateDir as _};
+
+    use crate::{
+        build::BuildProfile,
+        config::ConfigBuilder,
+        progress::{MultiProgress, Progress},
+    };
+
+    use super::*;
+
+    #[tokio::test]
+    async fn pack_and_unpack() {
+        let project_dir = assert_fs::TempDir::new().unwrap();
+        project_dir
+            .child("project.rockspec")
+            .write_str(
+                r#"
+                package = "foo"
+                version = "1.0.0-1"
+                source = { url = "https://github.com/example/foo/archive/v1.0.0.zip" }
+                "#,
+            )
+            .unwrap();
+        project_dir
+            .child("src/foo.lua")
+            .write_str("return {}")
+            .unwrap();
+        project_dir.child(".rocks/5.1").create_dir_all().unwrap();
+        project_dir.child(".git").create_dir_all().unwrap();
+        let project = Project::from(project_dir.path()).unwrap().unwrap();
+
+        let rock_path = pack_project(&project, project_dir.path(), &PackFilter::default()).unwrap();
+        assert_eq!(rock_path, project_dir.join("foo-1.0.0-1.src.rock"));
+
+        // Packing again must not include the previous rock.
+        let rock_path = pack_project(&project, project_dir.path(), &PackFilter::default()).unwrap();
+
+        let dest = assert_fs::TempDir::new().unwrap();
+        crate::operations::unpack_src_rock(
+            File::open(&rock_path).unwrap(),
+            dest.to_path_buf(),
+            &Progress::Progress(MultiProgress::new().new_bar()),
+        )
+        .await
+        .unwrap();
+        assert!(dest.join("foo-1.0.0-1.rockspec").is_file());
+        assert!(dest.join("src/foo.lua").is_file());
+        assert!(!dest.join("project.rockspec").exists());
+        assert!(!dest.join(".rocks").exists());
+        assert!(!dest.join(".git").exists());
+        assert!(!dest.join("foo-1.0.0-1.src.rock").exists());
+    }
+
+    #[tokio::test]
+    async fn pack_with_filter() {
+        let project_dir = assert_fs::TempDir::new().unwrap();
+        project_dir
+            .child("project.rockspec")
+            .write_str(
+                r#"
+                package = "foo"
+                version = "1.0.0-1"
+                source = { url = "https://github.com/example/foo/archive/v1.0.0.zip" }
+                build = {
+                    type = "builtin",
+                    modules = { foo = "src/foo.lua" },
+                }
+                "#,
+            )
+            .unwrap();
+        project_dir
+            .child("src/foo.lua")
+            .write_str("return {}")
+            .unwrap();
+        project_dir.child("README.md").write_str("# foo").unwrap();
+        project_dir
+            .child("spec/foo_spec.lua")
+            .write_str("describe('foo', function() end)")
+            .unwrap();
+        project_dir
+            .child("spec/fixtures/large.bin")
+            .write_str("...")
+            .unwrap();
+        let project = Project::from(project_dir.path()).unwrap().unwrap();
+        let dest_dir = assert_fs::TempDir::new().unwrap();
+
+        let unpack = |rock_path: PathBuf| async move {
+            let dest = assert_fs::TempDir::new().unwrap();
+            crate::operations::unpack_src_rock(
+                File::open(&rock_path).unwrap(),
+                dest.to_path_buf(),
+                &Progress::Progress(MultiProgress::new().new_bar()),
+            )
+            .await
+            .unwrap();
+            dest
+        };
+
+        let filter = PackFilter {
+            exclude: vec!["spec/fixtures".into()],
+            ..PackFilter::default()
+        };
+        let rock_path = pack_project(&project, dest_dir.path(), &filter).unwrap();
+        let dest = unpack(rock_path).await;
+        assert!(dest.join("spec/foo_spec.lua").is_file());
+        assert!(!dest.join("spec/fixtures").exists());
+
+        let filter = PackFilter {
+            include: vec!["*.md".into()],
+            ..PackFilter::default()
+        };
+        let rock_path = pack_project(&project, dest_dir.path(), &filter).unwrap();
+        let dest = unpack(rock_path).await;
+        assert!(dest.join("foo-1.0.0-1.rockspec").is_file());
+        assert!(dest.join("README.md").is_file());
+        // Declared modules are always packed.
+        assert!(dest.join("src/foo.lua").is_file());
+        assert!(!dest.join("spec").exists());
+
+        for pattern in ["src/*.lua", "src"] {
+            let filter = PackFilter {
+                exclude: vec![pattern.into()],
+                ..PackFilter::default()
+            };
+            assert!(matches!(
+                pack_project(&project, dest_dir.path(), &filter),
+                Err(PackError::ExcludesRequiredFile { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn binary_rock_name_depends_on_build_profile() {
+        let rockspec = Rockspec::new(
+            r#"
+            package = "foo"
+            version = "1.0.0-1"
+            source = { url = "https://github.com/example/foo/archive/v1.0.0.zip" }
+            "#,
+        )
+        .unwrap();
+        let build_dir = assert_fs::TempDir::new().unwrap();
+        build_dir
+            .child(STAGING_DIR_NAME)
+            .child("lib/foo.so")
+            .write_str("")
+            .unwrap();
+        let dest_dir = assert_fs::TempDir::new().unwrap();
+        let arch = format!("{}-{}", std::env::consts::OS, std::env::consts::ARCH);
+
+        let config = ConfigBuilder::new().build().unwrap();
+        let release = pack_binary_rock(&rockspec, &build_dir, &dest_dir, &config).unwrap();
+        assert_eq!(release, dest_dir.join(format!("foo-1.0.0-1.{}.rock", arch)));
+
+        let config = config.with_build_profile(BuildProfile::Debug);
+        let debug = pack_binary_rock(&rockspec, &build_dir, &dest_dir, &config).unwrap();
+        assert_eq!(
+            debug,
+            dest_dir.join(format!("foo-1.0.0-1.{}-debug.rock", arch))
+        );
+        assert!(release.is_file());
+
+        let mut archive = zip::ZipArchive::new(File::open(&debug).unwrap()).unwrap();
+        assert!(archive.by_name("foo-1.0.0-1.rockspec").is_ok());
+        assert!(archive.by_name("lib/foo.so").is_ok());
+    }
+
+    #[test]
+    fn signature_path_of_rock() {
+        assert_eq!(
+            signature_path(Path::new("/tmp/foo-1.0.0-1.src.rock")),
+            PathBuf::from("/tmp/foo-1.0.0-1.src.rock.asc")
+        );
+    }
+}
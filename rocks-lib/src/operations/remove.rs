@@ -2,15 +2,31 @@ use std::io;
 
 use crate::config::{LuaVersion, LuaVersionUnset};
 use crate::lockfile::LocalPackage;
+use crate::package::{PackageName, PackageSpec};
 use crate::progress::{Progress, ProgressBar};
+use crate::project::{DependencyType, Project, ProjectError};
+use crate::tree::TreeLayout;
 use crate::{config::Config, tree::Tree};
+use itertools::Itertools as _;
 use thiserror::Error;
 
+use super::test_tree_config;
+
 #[derive(Error, Debug)]
-#[error(transparent)]
 pub enum RemoveError {
+    #[error(transparent)]
     LuaVersionUnset(#[from] LuaVersionUnset),
+    #[error(transparent)]
     Io(#[from] io::Error),
+    #[error("cannot remove {0}: rocks installed into a prefix or a luarocks tree share their directories with other rocks, so their files can't be told apart")]
+    SharedLayout(PackageSpec),
+    #[error(transparent)]
+    Project(#[from] ProjectError),
+    #[error("{package} is not one of the project's {dependency_type}")]
+    NotAProjectDependency {
+        package: PackageName,
+        dependency_type: &'static str,
+    },
 }
 
 // TODO: Remove dependencies recursively too!
@@ -27,15 +43,142 @@ pub async fn remove(
         ))
     });
 
-    remove_impl(package, config).await
+    remove_packages(&[package], config).await
 }
 
-async fn remove_impl(package: LocalPackage, config: &Config) -> Result<(), RemoveError> {
-    let tree = Tree::new(config.tree().clone(), LuaVersion::from(config)?)?;
+/// Remove `packages` from the tree, writing its lockfile once rather than once per package.
+async fn remove_packages(packages: &[LocalPackage], config: &Config) -> Result<(), RemoveError> {
+    let tree = Tree::from_config(config, LuaVersion::from(config)?)?;
+
+    if let Some(package) = packages.first() {
+        if matches!(tree.layout(), TreeLayout::Fhs | TreeLayout::Luarocks) {
+            return Err(RemoveError::SharedLayout(package.to_package()));
+        }
+    }
 
-    tree.lockfile()?.remove(&package);
+    tree.lockfile()?.map_then_flush(|lockfile| {
+        for package in packages {
+            lockfile.remove(package);
+        }
+        Ok::<_, io::Error>(())
+    })?;
 
-    std::fs::remove_dir_all(tree.root_for(&package))?;
+    for package in packages {
+        std::fs::remove_dir_all(tree.root_for(package))?;
+    }
 
     Ok(())
 }
+
+/// Remove `package` from the project's dependencies of `dependency_type`,
+/// then uninstall it from the tree it is installed into
+/// (the test tree for test dependencies), unless another installed rock depends on it.
+/// Returns the uninstalled rocks.
+pub async fn remove_project_dependency(
+    project: &mut Project,
+    dependency_type: DependencyType,
+    package: &PackageName,
+    config: &Config,
+    progress: &Progress<ProgressBar>,
+) -> Result<Vec<LocalPackage>, RemoveError> {
+    let removed = project.remove(dependency_type, package)?;
+    if removed.is_empty() {
+        return Err(RemoveError::NotAProjectDependency {
+            package: package.clone(),
+            dependency_type: dependency_type.rockspec_field(),
+        });
+    }
+
+    let config = match dependency_type {
+        DependencyType::Test => test_tree_config(config),
+        DependencyType::Regular | DependencyType::Build | DependencyType::Optional => {
+            config.clone()
+        }
+    };
+    let tree = Tree::from_config(&config, LuaVersion::from(&config)?)?;
+    let unused_packages = {
+        let lockfile = tree.lockfile()?;
+        removed
+            .iter()
+            .filter_map(|req| lockfile.has_rock(req))
+            .filter(|package| lockfile.dependents(package).is_empty())
+            .collect::<Vec<_>>()
+    };
+
+    if !unused_packages.is_empty() {
+        progress.map(|p| {
+            p.set_message(format!(
+                "🗑️ Removing {}",
+                unused_packages
+                    .iter()
+                    .map(|package| format!("{}@{}", package.name(), package.version()))
+                    .join(", ")
+            ))
+        });
+        remove_packages(&unused_packages, &config).await?;
+    }
+    Ok(unused_packages)
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_fs::prelude::{FileWriteStr as _, PathChild as _};
+
+    use crate::{config::ConfigBuilder, lockfile::test_package};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn remove_test_dependency() {
+        let root = assert_fs::TempDir::new().unwrap();
+        root.child("project.rockspec")
+            .write_str(
+                r#"
+package = "foo"
+version = "1.0.0-1"
+source = {
+    url = "https://github.com/nvim-neorocks/luarocks-stub",
+}
+test_dependencies = {
+    "busted >= 2.0",
+}
+"#,
+            )
+            .unwrap();
+        let mut project = Project::from(root.path()).unwrap().unwrap();
+        let config = ConfigBuilder::new()
+            .tree(Some(root.join("tree")))
+            .lua_version(Some(LuaVersion::Lua51))
+            .build()
+            .unwrap();
+
+        let test_tree = Tree::from_config(&test_tree_config(&config), LuaVersion::Lua51).unwrap();
+        let busted = test_package("busted", "2.2.0-1");
+        test_tree
+            .lockfile()
+            .unwrap()
+            .map_then_flush(|lockfile| {
+                lockfile.add(&busted);
+                Ok::<_, io::Error>(())
+            })
+            .unwrap();
+        std::fs::create_dir_all(test_tree.root_for(&busted)).unwrap();
+
+        let removed = remove_project_dependency(
+            &mut project,
+            DependencyType::Test,
+            &"busted".into(),
+            &config,
+            &Progress::NoProgress,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(removed, vec![busted.clone()]);
+        assert!(project.rockspec().test_dependencies.default.is_empty());
+        let content = std::fs::read_to_string(root.join("project.rockspec")).unwrap();
+        assert!(!content.contains("busted"));
+        assert!(!test_tree.root_for(&busted).exists());
+        assert!(test_tree.list().unwrap().is_empty());
+    }
+}
@@ -9,8 +9,10 @@ use tokio::sync::mpsc::UnboundedSender;
 use crate::{
     build::BuildBehaviour,
     config::Config,
-    lockfile::{LocalPackageId, LocalPackageSpec, LockConstraint, Lockfile, PinnedState},
-    package::{PackageReq, PackageVersionReq},
+    lockfile::{
+        DependencyCycle, LocalPackageId, LocalPackageSpec, LockConstraint, Lockfile, PinnedState,
+    },
+    package::{PackageName, PackageReq, PackageVersionReq},
     progress::{MultiProgress, Progress},
     remote_package_db::RemotePackageDB,
     rockspec::Rockspec,
@@ -25,11 +27,43 @@ pub(crate) struct PackageInstallSpec {
     pub spec: LocalPackageSpec,
 }
 
-#[async_recursion]
+/// `features` enable the `optional_dependencies` of `packages` with the same names.
+/// Features that were enabled when a package was installed previously stay enabled.
+#[allow(clippy::too_many_arguments)]
 pub(crate) async fn get_all_dependencies(
     tx: UnboundedSender<PackageInstallSpec>,
     packages: Vec<(BuildBehaviour, PackageReq)>,
     pin: PinnedState,
+    features: Vec<PackageName>,
+    package_db: Arc<RemotePackageDB>,
+    lockfile: Arc<Lockfile>,
+    config: &Config,
+    progress: Arc<Progress<MultiProgress>>,
+) -> Result<Vec<LocalPackageId>, SearchAndDownloadError> {
+    get_dependencies_of(
+        tx,
+        packages,
+        Vec::new(),
+        pin,
+        features,
+        package_db,
+        lockfile,
+        config,
+        progress,
+    )
+    .await
+}
+
+/// `ancestors` are the names of the packages that (transitively) depend on `packages`,
+/// so that circular dependencies are reported instead of being resolved forever.
+#[async_recursion]
+#[allow(clippy::too_many_arguments)]
+async fn get_dependencies_of(
+    tx: UnboundedSender<PackageInstallSpec>,
+    packages: Vec<(BuildBehaviour, PackageReq)>,
+    ancestors: Vec<PackageName>,
+    pin: PinnedState,
+    features: Vec<PackageName>,
     package_db: Arc<RemotePackageDB>,
     lockfile: Arc<Lockfile>,
     config: &Config,
@@ -48,11 +82,13 @@ pub(crate) async fn get_all_dependencies(
                 let package_db = Arc::clone(&package_db);
                 let progress = Arc::clone(&progress);
                 let lockfile = Arc::clone(&lockfile);
+                let mut ancestors = ancestors.clone();
+                let features = features.clone();
 
                 tokio::spawn(async move {
                     let bar = progress.map(|p| p.new_bar());
 
-                    let rockspec = download_rockspec(&package, &package_db, &bar)
+                    let rockspec = download_rockspec(&package, &package_db, &config, &bar)
                         .await
                         .unwrap();
 
@@ -63,18 +99,58 @@ pub(crate) async fn get_all_dependencies(
                             LockConstraint::Constrained(package.version_req().clone())
                         };
 
-                    let dependencies = rockspec
-                        .dependencies
-                        .current_platform()
+                    let recorded_features = lockfile.features_of(&rockspec.package);
+                    let enabled_features = rockspec
+                        .optional_dependencies
+                        .for_platform(config.platform())
                         .iter()
-                        .filter(|dep| !dep.name().eq(&"lua".into()))
-                        .map(|dep| (build_behaviour, dep.clone()))
+                        .map(|dep| dep.name().clone())
+                        .filter(|name| features.contains(name) || recorded_features.contains(name))
+                        .unique()
                         .collect_vec();
 
-                    let dependencies = get_all_dependencies(
+                    let dependencies =
+                        rockspec
+                            .dependencies
+                            .for_platform(config.platform())
+                            .iter()
+                            .chain(rockspec.enabled_optional_dependencies(
+                                &enabled_features,
+                                config.platform(),
+                            ))
+                            .filter(|dep| !dep.name().eq(&"lua".into()))
+                            .map(|dep| (build_behaviour, dep.clone()))
+                            .collect_vec();
+
+                    ancestors.push(rockspec.package.clone());
+                    for (_, dep) in &dependencies {
+                        if let Some(start) = ancestors.iter().position(|name| name == dep.name()) {
+                            let mut cycle = ancestors[start..].to_vec();
+                            cycle.push(dep.name().clone());
+                            return Err(DependencyCycle(cycle).into());
+                        }
+                    }
+                    if let Some(max_depth) = config.max_dependency_depth() {
+                        // The dependencies are at a depth of `ancestors.len()`.
+                        if let Some((_, dep)) = dependencies.first() {
+                            if ancestors.len() > max_depth {
+                                let mut chain = ancestors.clone();
+                                chain.push(dep.name().clone());
+                                return Err(SearchAndDownloadError::MaxDepthExceeded {
+                                    max_depth,
+                                    chain,
+                                });
+                            }
+                        }
+                    }
+
+                    let dependencies = get_dependencies_of(
                         tx.clone(),
                         dependencies,
+                        ancestors,
                         pin,
+                        // Features only apply to the requested packages.
+                        Vec::new(),
                         package_db,
                         lockfile,
                         &config,
@@ -88,7 +164,9 @@ pub(crate) async fn get_all_dependencies(
                         constraint,
                         dependencies,
                         &pin,
-                    );
+                    )
+                    .with_namespace(package.namespace().cloned())
+                    .with_features(enabled_features);
 
                     let install_spec = PackageInstallSpec {
                         build_behaviour,
@@ -107,3 +185,107 @@ pub(crate) async fn get_all_dependencies(
     .flatten()
     .try_collect()
 }
+
+#[cfg(test)]
+mod tests {
+    use httptest::{matchers::request, responders::status_code, Expectation, Server};
+    use tokio::sync::mpsc;
+
+    use crate::{
+        config::{ConfigBuilder, LuaVersion},
+        tree::Tree,
+    };
+
+    use super::*;
+
+    /// A rocks server with the chain of dependencies `a → b → c → d`.
+    fn start_chain_server() -> Server {
+        let server = Server::run();
+        let chain = ["a", "b", "c", "d"];
+        let manifest = chain
+            .iter()
+            .map(|name| format!(r#"{name} = {{ ["1.0.0-1"] = {{ {{ arch = "rockspec" }} }} }},"#))
+            .join("\n");
+        server.expect(
+            Expectation::matching(request::path("/manifest-5.1"))
+                .times(1..)
+                .respond_with(status_code(200).body(format!("repository = {{\n{manifest}\n}}"))),
+        );
+        let dependencies = chain.iter().skip(1).map(Some).chain([None]);
+        for (name, dependency) in chain.iter().zip(dependencies) {
+            let rockspec = format!(
+                r#"
+package = "{name}"
+version = "1.0.0-1"
+source = {{
+    url = "https://example.com/{name}.tar.gz",
+}}
+dependencies = {{ {} }}
+build = {{
+    type = "builtin",
+    modules = {{}},
+}}
+"#,
+                dependency
+                    .map(|dependency| format!("\"{dependency}\""))
+                    .unwrap_or_default()
+            );
+            server.expect(
+                Expectation::matching(request::path(format!("/{name}-1.0.0-1.rockspec")))
+                    .times(0..)
+                    .respond_with(status_code(200).body(rockspec)),
+            );
+        }
+        server
+    }
+
+    async fn resolve_chain(
+        max_depth: Option<usize>,
+    ) -> Result<Vec<LocalPackageId>, SearchAndDownloadError> {
+        let server = start_chain_server();
+        let mut server_url = server.url_str("");
+        server_url.pop();
+        let temp = assert_fs::TempDir::new().unwrap();
+        let config = ConfigBuilder::new()
+            .server(Some(server_url))
+            .cache_dir(Some(temp.join("cache")))
+            .data_dir(Some(temp.join("data")))
+            .tree(Some(temp.join("tree")))
+            .lua_version(Some(LuaVersion::Lua51))
+            .build()
+            .unwrap()
+            .with_max_dependency_depth(max_depth);
+        let tree = Tree::new(config.tree().clone(), LuaVersion::Lua51).unwrap();
+        let lockfile = tree.lockfile().unwrap();
+        let package_db = RemotePackageDB::from_config(&config).await.unwrap();
+        let (tx, _rx) = mpsc::unbounded_channel();
+        get_all_dependencies(
+            tx,
+            vec![(BuildBehaviour::NoForce, "a".parse().unwrap())],
+            PinnedState::Unpinned,
+            Vec::new(),
+            Arc::new(package_db),
+            Arc::new(lockfile),
+            &config,
+            MultiProgress::new_arc(),
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn max_dependency_depth() {
+        assert_eq!(resolve_chain(None).await.unwrap().len(), 1);
+        // `d` is at depth 3.
+        assert_eq!(resolve_chain(Some(3)).await.unwrap().len(), 1);
+        match resolve_chain(Some(2)).await {
+            Err(SearchAndDownloadError::MaxDepthExceeded { max_depth, chain }) => {
+                assert_eq!(max_depth, 2);
+                assert_eq!(
+                    chain,
+                    vec!["a".into(), "b".into(), "c".into(), "d".into()] as Vec<PackageName>
+                );
+            }
+            result => panic!("expected the maximum depth to be exceeded, got {result:?}"),
+        }
+    }
+}
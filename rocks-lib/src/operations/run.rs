@@ -7,6 +7,7 @@ use crate::{
     package::{PackageReq, PackageVersionReqError},
     path::Paths,
     progress::MultiProgress,
+    project::Project,
     remote_package_db::{RemotePackageDB, RemotePackageDBError},
     tree::Tree,
 };
@@ -20,6 +21,8 @@ pub enum RunError {
     RunFailure(String),
     #[error("failed to execute `{0}`: {1}")]
     RunCommandFailure(String, io::Error),
+    #[error("the project does not define a script called `{0}`")]
+    ScriptNotFound(String),
     #[error(transparent)]
     LuaVersionUnset(#[from] LuaVersionUnset),
     #[error(transparent)]
@@ -27,23 +30,57 @@ pub enum RunError {
 }
 
 pub async fn run(command: &str, args: Vec<String>, config: Config) -> Result<(), RunError> {
-    let lua_version = LuaVersion::from(&config)?;
-    let tree = Tree::new(config.tree().clone(), lua_version.clone())?;
+    let mut cmd = Command::new(command);
+    cmd.args(args);
+    run_with_tree_paths(cmd, command, &config)
+}
+
+/// Run the project script called `name` with `args` appended to it,
+/// in the project root and with the tree's paths set, like [`run`].
+/// The script is run by the shell (`sh` or, on Windows, `cmd`).
+pub async fn run_script(
+    project: &Project,
+    name: &str,
+    args: Vec<String>,
+    config: Config,
+) -> Result<(), RunError> {
+    let script = project
+        .script(name)
+        .ok_or_else(|| RunError::ScriptNotFound(name.into()))?;
+    let mut cmd = if cfg!(windows) {
+        let mut cmd = Command::new("cmd");
+        cmd.arg("/C").arg(script).args(args);
+        cmd
+    } else {
+        // The arguments are passed as positional parameters, so the shell doesn't split them.
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c")
+            .arg(format!("{script} \"$@\""))
+            .arg(name)
+            .args(args);
+        cmd
+    };
+    cmd.current_dir(project.root());
+    run_with_tree_paths(cmd, name, &config)
+}
+
+fn run_with_tree_paths(mut cmd: Command, name: &str, config: &Config) -> Result<(), RunError> {
+    let lua_version = LuaVersion::from(config)?;
+    let tree = Tree::from_config(config, lua_version.clone())?;
     let paths = Paths::from_tree(tree)?;
-    let status = match Command::new(command)
-        .args(args)
+    let status = match cmd
         .env("PATH", paths.path_prepended().joined())
         .env("LUA_PATH", paths.package_path().joined())
         .env("LUA_CPATH", paths.package_cpath().joined())
         .status()
     {
         Ok(status) => Ok(status),
-        Err(err) => Err(RunError::RunCommandFailure(command.into(), err)),
+        Err(err) => Err(RunError::RunCommandFailure(name.into(), err)),
     }?;
     if status.success() {
         Ok(())
     } else {
-        Err(RunError::RunFailure(command.into()))
+        Err(RunError::RunFailure(name.into()))
     }
 }
 
@@ -65,7 +102,7 @@ pub async fn install_command(command: &str, config: &Config) -> Result<(), Insta
         PinnedState::Unpinned,
         &package_db,
         config,
-        MultiProgress::new_arc(),
+        MultiProgress::new_arc_from_config(config),
     )
     .await?;
     Ok(())
@@ -0,0 +1,77 @@
+use futures::future::try_join_all;
+
+use crate::{
+    config::Config,
+    package::{PackageName, PackageSpec, PackageVersion},
+    progress::{Progress, ProgressBar},
+    remote_package_db::RemotePackageDB,
+};
+
+use super::{download_rockspec, SearchAndDownloadError};
+
+/// Keep the search results whose latest version has all of the given `labels`,
+/// compared case-insensitively.
+///
+/// The labels are taken from the manifest if the server includes them.
+/// Otherwise, the rockspec of the latest version is downloaded.
+pub async fn filter_by_labels<'a>(
+    results: Vec<(&'a PackageName, Vec<&'a PackageVersion>)>,
+    labels: &[String],
+    package_db: &RemotePackageDB,
+    config: &Config,
+    progress: &Progress<ProgressBar>,
+) -> Result<Vec<(&'a PackageName, Vec<&'a PackageVersion>)>, SearchAndDownloadError> {
+    if labels.is_empty() {
+        return Ok(results);
+    }
+    let matches = try_join_all(results.iter().map(|(name, versions)| async move {
+        let latest = match versions.iter().max() {
+            Some(latest) => *latest,
+            None => return Ok(false),
+        };
+        let rock_labels = match package_db.labels(name, latest) {
+            Some(rock_labels) => rock_labels.clone(),
+            None => {
+                let package_req =
+                    PackageSpec::new((*name).clone(), latest.clone()).into_package_req();
+                download_rockspec(&package_req, package_db, config, progress)
+                    .await?
+                    .description
+                    .labels
+            }
+        };
+        Ok::<_, SearchAndDownloadError>(has_labels(&rock_labels, labels))
+    }))
+    .await?;
+    Ok(results
+        .into_iter()
+        .zip(matches)
+        .filter_map(|(result, matches)| matches.then_some(result))
+        .collect())
+}
+
+fn has_labels(rock_labels: &[String], labels: &[String]) -> bool {
+    labels.iter().all(|label| {
+        rock_labels
+            .iter()
+            .any(|rock_label| rock_label.eq_ignore_ascii_case(label))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn all_labels_must_match() {
+        let rock_labels = vec!["testing".to_string(), "Neovim".to_string()];
+        assert!(has_labels(&rock_labels, &[]));
+        assert!(has_labels(&rock_labels, &["testing".into()]));
+        assert!(has_labels(
+            &rock_labels,
+            &["neovim".into(), "testing".into()]
+        ));
+        assert!(!has_labels(&rock_labels, &["testing".into(), "web".into()]));
+        assert!(!has_labels(&[], &["testing".into()]));
+    }
+}
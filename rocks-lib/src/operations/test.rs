@@ -1,18 +1,24 @@
-use std::{io, process::Command, sync::Arc};
+use std::{
+    io::{self, Write as _},
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    process::{Command, ExitStatus},
+    sync::{Arc, Mutex},
+};
 
 use crate::{
     build::BuildBehaviour,
-    config::Config,
+    config::{Config, LuaVersion, LuaVersionUnset},
     lockfile::PinnedState,
     package::{PackageName, PackageReq, PackageVersionReqError},
     path::Paths,
     progress::{MultiProgress, Progress},
     project::Project,
     remote_package_db::RemotePackageDB,
-    rockspec::Rockspec,
+    rockspec::{Rockspec, TestSpec},
     tree::Tree,
 };
-use itertools::Itertools;
+use itertools::{Either, Itertools};
 use thiserror::Error;
 
 use super::{install, InstallError};
@@ -26,12 +32,60 @@ pub enum TestEnv {
 pub enum RunTestsError {
     #[error("tests failed!")]
     TestFailure,
+    #[error("test command `{command}` failed ({status})")]
+    TestCommandFailure { command: String, status: ExitStatus },
     #[error("failed to execute `{0}`: {1}")]
     RunCommandFailure(String, io::Error),
     #[error("lua version not set! Please provide a version through `--lua-version <ver>` or add it to your rockspec's dependencies.")]
     LuaVersionUnset,
     #[error(transparent)]
     Io(#[from] io::Error),
+    #[error("failed to generate coverage report!")]
+    CoverageReportFailure,
+    #[error("could not find a coverage summary in {0}")]
+    CoverageSummaryNotFound(PathBuf),
+    #[error("coverage {coverage:.2}% is below the minimum of {min_coverage:.2}%")]
+    CoverageBelowMinimum { coverage: f64, min_coverage: f64 },
+}
+
+/// The format of the coverage report generated by luacov.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+#[cfg_attr(feature = "clap", clap(rename_all = "lowercase"))]
+pub enum CoverageFormat {
+    /// luacov's default plain text report.
+    #[default]
+    Text,
+    /// An HTML report, generated with luacov-html.
+    Html,
+    /// An lcov tracefile, generated with luacov-reporter-lcov.
+    Lcov,
+}
+
+impl CoverageFormat {
+    /// The luacov reporter and the rock that provides it, if not the default reporter.
+    fn reporter(&self) -> Option<(&'static str, &'static str)> {
+        match self {
+            Self::Text => None,
+            Self::Html => Some(("html", "luacov-html")),
+            Self::Lcov => Some(("lcov", "luacov-reporter-lcov")),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct CoverageConfig {
+    pub format: CoverageFormat,
+    /// The minimum total coverage, in percent.
+    pub min_coverage: Option<f64>,
+}
+
+#[derive(Debug, Clone)]
+pub struct CoverageReport {
+    /// The total coverage, in percent.
+    pub coverage: f64,
+    /// The report generated by luacov.
+    pub report: PathBuf,
 }
 
 pub async fn run_tests<I>(
@@ -43,68 +97,349 @@ pub async fn run_tests<I>(
 where
     I: IntoIterator<Item = String> + Send,
 {
-    let rockspec = project.rockspec();
-    let lua_version = match rockspec.lua_version_from_config(&config) {
-        Ok(lua_version) => Ok(lua_version),
-        Err(_) => rockspec
-            .test_lua_version()
-            .ok_or(RunTestsError::LuaVersionUnset),
-    }?;
-    let tree = Tree::new(config.tree().clone(), lua_version)?;
-    let tree_root = &tree.root().clone();
-    let paths = Paths::from_tree(tree)?;
-    let mut command = Command::new("busted");
-    let mut command = command
-        .current_dir(project.root())
-        .args(test_args)
+    run_tests_impl(&project, test_args, env, config).map(|_| ())
+}
+
+/// Run the tests with coverage enabled and generate a coverage report with luacov.
+/// Expects luacov to be installed (see [`ensure_luacov`]).
+pub async fn run_tests_with_coverage<I>(
+    project: Project,
+    test_args: I,
+    env: TestEnv,
+    coverage_config: CoverageConfig,
+    config: Config,
+) -> Result<CoverageReport, RunTestsError>
+where
+    I: IntoIterator<Item = String> + Send,
+{
+    let stats_file = project.root().join("luacov.stats.out");
+    let _ = std::fs::remove_file(&stats_file);
+    let test_args = std::iter::once("--coverage".to_string()).chain(test_args);
+    let paths = run_tests_impl(&project, test_args, env, config)?;
+
+    // Always generate the default report, as it contains the summary.
+    let report = project.root().join("luacov.report.out");
+    run_luacov(project.root(), &paths, None)?;
+    let coverage = parse_coverage_summary(&std::fs::read_to_string(&report)?)
+        .ok_or_else(|| RunTestsError::CoverageSummaryNotFound(report.clone()))?;
+    let report = match coverage_config.format.reporter() {
+        Some((reporter, _)) => {
+            run_luacov(project.root(), &paths, Some(reporter))?;
+            match coverage_config.format {
+                // luacov-html writes to a directory rather than the report file.
+                CoverageFormat::Html => project.root().join("luacov-html").join("index.html"),
+                _ => report,
+            }
+        }
+        None => report,
+    };
+
+    if let Some(min_coverage) = coverage_config.min_coverage {
+        if coverage < min_coverage {
+            return Err(RunTestsError::CoverageBelowMinimum {
+                coverage,
+                min_coverage,
+            });
+        }
+    }
+    Ok(CoverageReport { coverage, report })
+}
+
+fn run_luacov(
+    project_root: &Path,
+    paths: &Paths,
+    reporter: Option<&str>,
+) -> Result<(), RunTestsError> {
+    let mut command = Command::new("luacov");
+    command
+        .current_dir(project_root)
         .env("PATH", paths.path_prepended().joined())
         .env("LUA_PATH", paths.package_path().joined())
         .env("LUA_CPATH", paths.package_cpath().joined());
-    if let TestEnv::Pure = env {
-        // isolate the test runner from the user's own config/data files
-        // by initialising empty HOME and XDG base directory paths
-        let home = tree_root.join("home");
-        let xdg = home.join("xdg");
-        let _ = std::fs::remove_dir_all(&home);
-        let xdg_config_home = xdg.join("config");
-        std::fs::create_dir_all(&xdg_config_home)?;
-        let xdg_state_home = xdg.join("local").join("state");
-        std::fs::create_dir_all(&xdg_state_home)?;
-        let xdg_data_home = xdg.join("local").join("share");
-        std::fs::create_dir_all(&xdg_data_home)?;
-        command = command
-            .env("HOME", home)
-            .env("XDG_CONFIG_HOME", xdg_config_home)
-            .env("XDG_STATE_HOME", xdg_state_home)
-            .env("XDG_DATA_HOME", xdg_data_home);
-    }
-    let status = match command.status() {
+    if let Some(reporter) = reporter {
+        command.args(["-r", reporter]);
+    }
+    let status = command
+        .status()
+        .map_err(|err| RunTestsError::RunCommandFailure("luacov".into(), err))?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(RunTestsError::CoverageReportFailure)
+    }
+}
+
+/// Parse the total coverage, in percent, from the summary of a default luacov report.
+fn parse_coverage_summary(report: &str) -> Option<f64> {
+    report
+        .lines()
+        .rev()
+        .find(|line| line.starts_with("Total"))?
+        .split_whitespace()
+        .last()?
+        .trim_end_matches('%')
+        .parse()
+        .ok()
+}
+
+fn run_tests_impl<I>(
+    project: &Project,
+    test_args: I,
+    env: TestEnv,
+    config: Config,
+) -> Result<Paths, RunTestsError>
+where
+    I: IntoIterator<Item = String>,
+{
+    let test_spec = project.rockspec().test.for_platform(config.platform());
+    let test_env = TestEnvironment::new(project, env, config)?;
+    if let TestSpec::Command(spec) = test_spec {
+        let args = spec.flags().iter().cloned().chain(test_args);
+        let status = test_env
+            .command(spec.command(), args)
+            .status()
+            .map_err(|err| RunTestsError::RunCommandFailure(spec.command().into(), err))?;
+        return if status.success() {
+            Ok(test_env.paths)
+        } else {
+            Err(RunTestsError::TestCommandFailure {
+                command: spec.command().into(),
+                status,
+            })
+        };
+    }
+    let status = match test_env.busted(test_args).status() {
         Ok(status) => Ok(status),
         Err(err) => Err(RunTestsError::RunCommandFailure("busted".into(), err)),
     }?;
     if status.success() {
-        Ok(())
+        Ok(test_env.paths)
     } else {
         Err(RunTestsError::TestFailure)
     }
 }
 
+/// The results of [`run_tests_in_parallel`], per test file.
+#[derive(Debug, Default, Clone)]
+pub struct TestSummary {
+    pub passed: Vec<PathBuf>,
+    pub failed: Vec<PathBuf>,
+}
+
+impl TestSummary {
+    pub fn success(&self) -> bool {
+        self.failed.is_empty()
+    }
+}
+
+/// Run each test file in a separate busted process, with up to `jobs` processes at a time.
+/// busted can't run the tests of a single process in parallel, so the test files
+/// (the `*_spec.lua` files in the project's `spec` directory) are distributed across processes.
+/// The output of each process is printed once it has finished, so that it is not interleaved.
+pub async fn run_tests_in_parallel<I>(
+    project: Project,
+    test_args: I,
+    env: TestEnv,
+    jobs: NonZeroUsize,
+    config: Config,
+) -> Result<TestSummary, RunTestsError>
+where
+    I: IntoIterator<Item = String> + Send,
+{
+    let test_args = test_args.into_iter().collect_vec();
+    let test_env = TestEnvironment::new(&project, env, config)?;
+    let queue = Mutex::new(find_test_files(&project.root().join("spec")).into_iter());
+    let results = Mutex::new(Vec::new());
+
+    std::thread::scope(|scope| {
+        let workers = (0..jobs.get())
+            .map(|_| {
+                scope.spawn(|| -> Result<(), RunTestsError> {
+                    loop {
+                        let Some(test_file) = queue.lock().unwrap().next() else {
+                            return Ok(());
+                        };
+                        let test_args = test_args
+                            .iter()
+                            .cloned()
+                            .chain(std::iter::once(test_file.to_string_lossy().to_string()));
+                        let output = test_env.busted(test_args).output().map_err(|err| {
+                            RunTestsError::RunCommandFailure("busted".into(), err)
+                        })?;
+                        {
+                            let mut stdout = io::stdout().lock();
+                            stdout.write_all(&output.stdout)?;
+                            io::stderr().write_all(&output.stderr)?;
+                            stdout.flush()?;
+                        }
+                        results
+                            .lock()
+                            .unwrap()
+                            .push((test_file, output.status.success()));
+                    }
+                })
+            })
+            .collect_vec();
+        workers
+            .into_iter()
+            .try_for_each(|worker| worker.join().unwrap())
+    })?;
+
+    let (passed, failed) = results
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .sorted()
+        .partition_map(|(test_file, success)| {
+            if success {
+                Either::Left(test_file)
+            } else {
+                Either::Right(test_file)
+            }
+        });
+    Ok(TestSummary { passed, failed })
+}
+
+/// The test files in busted's default `spec` directory, relative to the project root.
+fn find_test_files(spec_dir: &Path) -> Vec<PathBuf> {
+    let project_root = spec_dir.parent().unwrap_or(spec_dir);
+    walkdir::WalkDir::new(spec_dir)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter(|entry| {
+            entry.file_type().is_file()
+                && entry.file_name().to_string_lossy().ends_with("_spec.lua")
+        })
+        .filter_map(|entry| {
+            entry
+                .path()
+                .strip_prefix(project_root)
+                .ok()
+                .map(Path::to_path_buf)
+        })
+        .collect()
+}
+
+/// The environment that the tests run in, with the tree and the test tree on the search paths.
+struct TestEnvironment {
+    project_root: PathBuf,
+    paths: Paths,
+    env_vars: Vec<(&'static str, PathBuf)>,
+}
+
+impl TestEnvironment {
+    fn new(project: &Project, env: TestEnv, config: Config) -> Result<Self, RunTestsError> {
+        let rockspec = project.rockspec();
+        let lua_version = match rockspec.lua_version_from_config(&config) {
+            Ok(lua_version) => Ok(lua_version),
+            Err(_) => rockspec
+                .test_lua_version()
+                .ok_or(RunTestsError::LuaVersionUnset),
+        }?;
+        let tree = Tree::from_config(&config, lua_version.clone())?;
+        let tree_root = &tree.root().clone();
+        let mut paths = Paths::from_tree(tree)?;
+        let test_tree = Tree::from_config(&test_tree_config(&config), lua_version)?;
+        paths.prepend(&Paths::from_tree(test_tree)?);
+        let mut env_vars = Vec::new();
+        if let TestEnv::Pure = env {
+            // isolate the test runner from the user's own config/data files
+            // by initialising empty HOME and XDG base directory paths
+            let home = tree_root.join("home");
+            let xdg = home.join("xdg");
+            let _ = std::fs::remove_dir_all(&home);
+            let xdg_config_home = xdg.join("config");
+            std::fs::create_dir_all(&xdg_config_home)?;
+            let xdg_state_home = xdg.join("local").join("state");
+            std::fs::create_dir_all(&xdg_state_home)?;
+            let xdg_data_home = xdg.join("local").join("share");
+            std::fs::create_dir_all(&xdg_data_home)?;
+            env_vars = vec![
+                ("HOME", home),
+                ("XDG_CONFIG_HOME", xdg_config_home),
+                ("XDG_STATE_HOME", xdg_state_home),
+                ("XDG_DATA_HOME", xdg_data_home),
+            ];
+        }
+        Ok(Self {
+            project_root: project.root().to_path_buf(),
+            paths,
+            env_vars,
+        })
+    }
+
+    fn busted<I>(&self, test_args: I) -> Command
+    where
+        I: IntoIterator<Item = String>,
+    {
+        self.with_env(Command::new("busted"), test_args)
+    }
+
+    /// A `test.type = "command"` test command, which is run by the shell
+    /// (`sh` or, on Windows, `cmd`).
+    fn command<I>(&self, test_command: &str, test_args: I) -> Command
+    where
+        I: IntoIterator<Item = String>,
+    {
+        if cfg!(windows) {
+            let mut command = Command::new("cmd");
+            command.arg("/C").arg(test_command);
+            self.with_env(command, test_args)
+        } else {
+            // The arguments are passed as positional parameters, so the shell doesn't split them.
+            let mut command = Command::new("sh");
+            command
+                .arg("-c")
+                .arg(format!("{test_command} \"$@\""))
+                .arg(test_command);
+            self.with_env(command, test_args)
+        }
+    }
+
+    fn with_env<I>(&self, mut command: Command, test_args: I) -> Command
+    where
+        I: IntoIterator<Item = String>,
+    {
+        command
+            .current_dir(&self.project_root)
+            .args(test_args)
+            .env("PATH", self.paths.path_prepended().joined())
+            .env("LUA_PATH", self.paths.package_path().joined())
+            .env("LUA_CPATH", self.paths.package_cpath().joined())
+            .envs(self.env_vars.iter().cloned());
+        command
+    }
+}
+
 #[derive(Error, Debug)]
 pub enum InstallTestDependenciesError {
     #[error(transparent)]
     InstallError(#[from] InstallError),
     #[error(transparent)]
     PackageVersionReqError(#[from] PackageVersionReqError),
+    #[error(transparent)]
+    LuaVersionUnset(#[from] LuaVersionUnset),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+/// The configuration for installing into the test tree, `<tree>/test`.
+/// Test dependencies and the test tools (busted and luacov) are installed into the test tree,
+/// so that they are recorded in its lockfile rather than the lockfile of the tree `config` operates on.
+pub fn test_tree_config(config: &Config) -> Config {
+    let test_tree = config.tree().join("test");
+    config.clone().with_tree(test_tree)
 }
 
-/// Ensure that busted is installed.
-/// This defaults to the local project tree if cwd is a project root.
+/// Ensure that busted is installed into the test tree.
 pub async fn ensure_busted(
-    tree: &Tree,
     package_db: &RemotePackageDB,
     config: &Config,
     progress: Arc<Progress<MultiProgress>>,
 ) -> Result<(), InstallTestDependenciesError> {
+    let config = test_tree_config(config);
+    let tree = Tree::from_config(&config, LuaVersion::from(&config)?)?;
     let busted_req = PackageReq::new("busted".into(), None)?;
 
     if tree.has_rock(&busted_req).is_none() {
@@ -112,7 +447,7 @@ pub async fn ensure_busted(
             vec![(BuildBehaviour::NoForce, busted_req)],
             PinnedState::Unpinned,
             package_db,
-            config,
+            &config,
             progress,
         )
         .await?;
@@ -121,39 +456,131 @@ pub async fn ensure_busted(
     Ok(())
 }
 
-/// Ensure dependencies and test dependencies are installed
-/// This defaults to the local project tree if cwd is a project root.
+/// Ensure that luacov, and the reporter for the coverage `format`, are installed into the test tree.
+pub async fn ensure_luacov(
+    package_db: &RemotePackageDB,
+    format: CoverageFormat,
+    config: &Config,
+    progress: Arc<Progress<MultiProgress>>,
+) -> Result<(), InstallTestDependenciesError> {
+    let config = test_tree_config(config);
+    let tree = Tree::from_config(&config, LuaVersion::from(&config)?)?;
+    let packages = std::iter::once("luacov")
+        .chain(format.reporter().map(|(_, rock)| rock))
+        .map(|name| PackageReq::new(name.into(), None))
+        .filter_ok(|req| tree.has_rock(req).is_none())
+        .map_ok(|req| (BuildBehaviour::NoForce, req))
+        .try_collect::<_, Vec<_>, _>()?;
+
+    if !packages.is_empty() {
+        install(
+            packages,
+            PinnedState::Unpinned,
+            package_db,
+            &config,
+            progress,
+        )
+        .await?;
+    }
+
+    Ok(())
+}
+
+/// Ensure that the rockspec's dependencies are installed into the tree that `config` operates on,
+/// and that its test dependencies are installed into the test tree.
 pub async fn ensure_dependencies(
     rockspec: &Rockspec,
-    tree: &Tree,
     package_db: &RemotePackageDB,
     config: &Config,
     progress: Arc<Progress<MultiProgress>>,
 ) -> Result<(), InstallTestDependenciesError> {
-    let dependencies = rockspec
-        .test_dependencies
-        .current_platform()
-        .iter()
-        .chain(rockspec.dependencies.current_platform())
-        .filter(|req| !req.name().eq(&PackageName::new("lua".into())))
-        .filter_map(|req| {
-            let build_behaviour = if tree.has_rock(req).is_some() {
-                Some(BuildBehaviour::Force)
-            } else {
-                None
-            };
-            build_behaviour.map(|it| (it, req.to_owned()))
-        })
-        .collect_vec();
-
-    install(
-        dependencies,
-        PinnedState::Unpinned,
+    install_missing(
+        rockspec.dependencies.for_platform(config.platform()),
         package_db,
         config,
-        progress,
+        progress.clone(),
     )
     .await?;
+    install_missing(
+        rockspec.test_dependencies.for_platform(config.platform()),
+        package_db,
+        &test_tree_config(config),
+        progress,
+    )
+    .await
+}
+
+async fn install_missing(
+    dependencies: &[PackageReq],
+    package_db: &RemotePackageDB,
+    config: &Config,
+    progress: Arc<Progress<MultiProgress>>,
+) -> Result<(), InstallTestDependenciesError> {
+    let tree = Tree::from_config(config, LuaVersion::from(config)?)?;
+    let dependencies = dependencies
+        .iter()
+        .filter(|req| !req.name().eq(&PackageName::new("lua".into())))
+        .filter(|req| tree.has_rock(req).is_none())
+        .map(|req| (BuildBehaviour::NoForce, req.to_owned()))
+        .collect_vec();
+
+    if !dependencies.is_empty() {
+        install(
+            dependencies,
+            PinnedState::Unpinned,
+            package_db,
+            config,
+            progress,
+        )
+        .await?;
+    }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use assert_fs::prelude::{FileWriteStr as _, PathChild as _};
+
+    use super::*;
+
+    #[test]
+    fn find_spec_files() {
+        let project_root = assert_fs::TempDir::new().unwrap();
+        for file in [
+            "spec/b_spec.lua",
+            "spec/a_spec.lua",
+            "spec/nested/c_spec.lua",
+            "spec/helpers.lua",
+            "lua/foo_spec.lua",
+        ] {
+            project_root.child(file).write_str("").unwrap();
+        }
+        assert_eq!(
+            find_test_files(&project_root.join("spec")),
+            vec![
+                PathBuf::from("spec/a_spec.lua"),
+                PathBuf::from("spec/b_spec.lua"),
+                PathBuf::from("spec/nested/c_spec.lua"),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_luacov_summary() {
+        let report = "\
+==============================================================================
+Summary
+==============================================================================
+
+File        Hits Missed Coverage
+--------------------------------
+src/foo.lua 10   2      83.33%
+src/bar.lua 5    0      100.00%
+--------------------------------
+Total       15   2      88.24%
+";
+        assert_eq!(parse_coverage_summary(report), Some(88.24));
+        assert_eq!(parse_coverage_summary("no summary"), None);
+    }
+}
@@ -6,7 +6,7 @@ use crate::{
     build::BuildBehaviour,
     config::Config,
     lockfile::{LocalPackage, PinnedState},
-    package::{PackageReq, PackageSpec, RockConstraintUnsatisfied},
+    package::{PackageReq, PackageSpec, PackageVersion, RockConstraintUnsatisfied},
     progress::{MultiProgress, Progress, ProgressBar},
     remote_package_db::RemotePackageDB,
 };
@@ -31,6 +31,19 @@ pub enum UpdateError {
     },
 }
 
+/// The version that [`update`] would update `package` to, if any.
+/// Pinned packages are never updated.
+pub fn update_plan(
+    package: &LocalPackage,
+    constraint: &PackageReq,
+    package_db: &RemotePackageDB,
+) -> Result<Option<PackageVersion>, RockConstraintUnsatisfied> {
+    if package.pinned() == PinnedState::Pinned {
+        return Ok(None);
+    }
+    package.to_package().has_update_with(constraint, package_db)
+}
+
 pub async fn update(
     package: LocalPackage,
     constraint: PackageReq,
@@ -40,11 +53,7 @@ pub async fn update(
 ) -> Result<(), UpdateError> {
     let bar = progress.map(|p| p.add(ProgressBar::from(format!("Updating {}...", package.name()))));
 
-    let latest_version = package
-        .to_package()
-        .has_update_with(&constraint, package_db)?;
-
-    if latest_version.is_some() && package.pinned() == PinnedState::Unpinned {
+    if update_plan(&package, &constraint, package_db)?.is_some() {
         // TODO(vhyrro): There's a slight dissonance in the API here.
         // `install` expects a MultiProgress, since it assumes you'll be installing
         // many rocks. We might want to have a function for installing a single package, too,
@@ -38,6 +38,7 @@ impl PackageSpec {
     }
     pub fn into_package_req(self) -> PackageReq {
         PackageReq {
+            namespace: None,
             name: self.name,
             version_req: self.version.into_version_req(),
         }
@@ -89,8 +90,10 @@ impl FromStr for PackageSpec {
     type Err = ParseRemotePackageError;
 
     fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        // `name version` is how a `PackageSpec` is displayed.
         let (name, version) = s
             .split_once('@')
+            .or_else(|| s.split_once(' '))
             .ok_or_else(|| ParseRemotePackageError::InvalidInput(s.to_string()))?;
 
         Self::parse(name.to_string(), version.to_string()).map_err(|error| {
@@ -107,6 +110,9 @@ impl FromStr for PackageSpec {
 #[cfg_attr(feature = "clap", derive(clap::Args))]
 #[cfg_attr(feature = "lua", derive(mlua::FromLua))]
 pub struct PackageReq {
+    /// The namespace of the package, for example "user" in "user/rock".
+    #[cfg_attr(feature = "clap", clap(skip))]
+    namespace: Option<PackageNamespace>,
     /// The name of the package.
     name: PackageName,
     /// The version requirement, for example "1.0.0" or ">=1.0.0".
@@ -117,6 +123,7 @@ pub struct PackageReq {
 impl PackageReq {
     pub fn new(name: String, version: Option<String>) -> Result<Self, PackageVersionReqError> {
         Ok(Self {
+            namespace: None,
             name: PackageName::new(name),
             version_req: match version {
                 Some(version_req_str) => PackageVersionReq::parse(version_req_str.as_str())?,
@@ -130,9 +137,21 @@ impl PackageReq {
     pub fn name(&self) -> &PackageName {
         &self.name
     }
+    pub fn namespace(&self) -> Option<&PackageNamespace> {
+        self.namespace.as_ref()
+    }
+    pub fn with_namespace(self, namespace: Option<PackageNamespace>) -> Self {
+        Self { namespace, ..self }
+    }
     pub fn version_req(&self) -> &PackageVersionReq {
         &self.version_req
     }
+    pub fn with_version_req(self, version_req: PackageVersionReq) -> Self {
+        Self {
+            version_req,
+            ..self
+        }
+    }
     /// Evaluate whether the given package satisfies the package requirement
     /// given by `self`.
     pub fn matches(&self, package: &PackageSpec) -> bool {
@@ -142,6 +161,9 @@ impl PackageReq {
 
 impl Display for PackageReq {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(namespace) = &self.namespace {
+            write!(f, "{}/", namespace)?;
+        }
         if self.version_req.eq(&PackageVersionReq::default()) {
             self.name.fmt(f)
         } else {
@@ -150,6 +172,15 @@ impl Display for PackageReq {
     }
 }
 
+impl Serialize for PackageReq {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        self.to_string().serialize(serializer)
+    }
+}
+
 impl From<PackageSpec> for PackageReq {
     fn from(value: PackageSpec) -> Self {
         value.into_package_req()
@@ -167,40 +198,120 @@ impl mlua::UserData for PackageReq {
 
 #[derive(Error, Debug)]
 pub enum PackageReqParseError {
-    #[error("could not parse dependency name from {0}")]
+    #[error("could not parse dependency name from '{0}': expected a rock name, e.g. 'foo' or 'foo >= 1.0'")]
     InvalidDependencyName(String),
-    #[error("could not parse version requirement in '{str}': {error}")]
+    #[error("unexpected '{token}' at column {column} in '{str}'{}", did_you_mean(.suggestion))]
+    UnexpectedToken {
+        str: String,
+        token: char,
+        /// 1-based, in characters.
+        column: usize,
+        suggestion: Option<String>,
+    },
+    #[error("could not parse version requirement at column {column} in '{str}': {error}")]
     InvalidPackageVersionReq {
         #[source]
         error: PackageVersionReqError,
         str: String,
+        /// 1-based, in characters.
+        column: usize,
     },
 }
 
+fn did_you_mean(suggestion: &Option<String>) -> String {
+    suggestion
+        .as_ref()
+        .map(|suggestion| format!(". Did you mean '{suggestion}'?"))
+        .unwrap_or_default()
+}
+
+/// Whether `c` can start a version requirement, e.g. `>= 1.0`, `~> 1.0` or `&lt; 2.0`.
+fn is_operator_start(c: char) -> bool {
+    matches!(c, '=' | '>' | '<' | '~' | '^' | '&')
+}
+
 impl FromStr for PackageReq {
     type Err = PackageReqParseError;
 
     fn from_str(str: &str) -> Result<Self, PackageReqParseError> {
-        let rock_name_str = str
-            .chars()
-            .peeking_take_while(|t| t.is_alphanumeric() || matches!(t, '-' | '_' | '.'))
-            .collect::<String>();
+        let parse_name = |str: &str| {
+            str.chars()
+                .peeking_take_while(|t| t.is_alphanumeric() || matches!(t, '-' | '_' | '.'))
+                .collect::<String>()
+        };
+
+        let mut rock_name_str = parse_name(str);
+        let mut rest = &str[rock_name_str.len()..];
+
+        // Namespaced rocks are specified as `namespace/name`
+        let namespace = match rest.strip_prefix('/') {
+            Some(unnamespaced) if !rock_name_str.is_empty() => {
+                let namespace = PackageNamespace::new(rock_name_str);
+                rock_name_str = parse_name(unnamespaced);
+                rest = &unnamespaced[rock_name_str.len()..];
+                Some(namespace)
+            }
+            _ => None,
+        };
 
         if rock_name_str.is_empty() {
             return Err(PackageReqParseError::InvalidDependencyName(str.to_string()));
         }
+        let name_part = &str[..str.len() - rest.len()];
+        // `rest` is always a suffix of `str`.
+        let column = |rest: &str| str[..str.len() - rest.len()].chars().count() + 1;
 
-        let constraints = str.trim_start_matches(&rock_name_str).trim();
-        let version_req = match constraints {
+        if let Some(token) = rest
+            .chars()
+            .next()
+            .filter(|c| !c.is_whitespace() && *c != '@' && !is_operator_start(*c))
+        {
+            return Err(PackageReqParseError::UnexpectedToken {
+                str: str.to_string(),
+                token,
+                column: column(rest),
+                suggestion: None,
+            });
+        }
+
+        // `name@version` is shorthand for `name == version`
+        let constraints = rest.trim_start();
+        let constraints = match constraints.strip_prefix('@') {
+            Some(version) => {
+                let version = version.trim_start();
+                if let Some(token) = version
+                    .chars()
+                    .next()
+                    .filter(|c| *c == '@' || is_operator_start(*c))
+                {
+                    let suggestion = if token == '@' {
+                        format!("{name_part}@{}", version.trim_start_matches('@').trim())
+                    } else {
+                        format!("{name_part} {}", version.trim())
+                    };
+                    return Err(PackageReqParseError::UnexpectedToken {
+                        str: str.to_string(),
+                        token,
+                        column: column(version),
+                        suggestion: Some(suggestion),
+                    });
+                }
+                version
+            }
+            None => constraints,
+        };
+        let version_req = match constraints.trim_end() {
             "" => PackageVersionReq::default(),
-            constraints => PackageVersionReq::parse(constraints.trim_start()).map_err(|error| {
+            trimmed => PackageVersionReq::parse(trimmed).map_err(|error| {
                 PackageReqParseError::InvalidPackageVersionReq {
                     error,
                     str: str.to_string(),
+                    column: column(constraints),
                 }
             })?,
         };
         Ok(Self {
+            namespace,
             name: PackageName::new(rock_name_str),
             version_req,
         })
@@ -266,9 +377,50 @@ impl Display for PackageName {
     }
 }
 
+/// A luarocks namespace, e.g. the `user` in `user/rock`, which is always lowercase
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Hash)]
+pub struct PackageNamespace(String);
+
+impl PackageNamespace {
+    pub fn new(namespace: String) -> Self {
+        Self(namespace.to_lowercase())
+    }
+}
+
+impl<'de> Deserialize<'de> for PackageNamespace {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        Ok(PackageNamespace::new(String::deserialize(deserializer)?))
+    }
+}
+
+impl Serialize for PackageNamespace {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        self.0.serialize(serializer)
+    }
+}
+
+impl From<&str> for PackageNamespace {
+    fn from(value: &str) -> Self {
+        Self::new(value.into())
+    }
+}
+
+impl Display for PackageNamespace {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.0.as_str())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use proptest::prelude::*;
 
     #[tokio::test]
     async fn parse_name() {
@@ -362,6 +514,8 @@ mod tests {
         let neorg = PackageSpec::parse("neorg".into(), "1.0.5".into()).unwrap();
         assert!(package_req.matches(&neorg));
         let neorg = PackageSpec::parse("neorg".into(), "1.0.6".into()).unwrap();
+        assert!(package_req.matches(&neorg));
+        let neorg = PackageSpec::parse("neorg".into(), "1.1.0".into()).unwrap();
         assert!(!package_req.matches(&neorg));
         // Testing incomplete version constraints
         let package_req: PackageReq = "lua-utils.nvim ~> 1.1-1".parse().unwrap();
@@ -371,5 +525,110 @@ mod tests {
         assert!(package_req.matches(&lua_utils));
         let lua_utils = PackageSpec::parse("lua-utils.nvim".into(), "1.2-1".into()).unwrap();
         assert!(!package_req.matches(&lua_utils));
+        // Testing disjoint version constraints
+        let package_req: PackageReq = "neorg >= 1.0, < 2.0 || >= 3.0".parse().unwrap();
+        assert_eq!(package_req.name.to_string(), "neorg");
+        let neorg = PackageSpec::parse("neorg".into(), "1.5".into()).unwrap();
+        assert!(package_req.matches(&neorg));
+        let neorg = PackageSpec::parse("neorg".into(), "3.1".into()).unwrap();
+        assert!(package_req.matches(&neorg));
+        let neorg = PackageSpec::parse("neorg".into(), "2.5".into()).unwrap();
+        assert!(!package_req.matches(&neorg));
+    }
+
+    #[tokio::test]
+    async fn parse_namespaced_package_req() {
+        let package_req: PackageReq = "user/rock@1.0".parse().unwrap();
+        assert_eq!(package_req.namespace(), Some(&"user".into()));
+        assert_eq!(package_req.name().to_string(), "rock");
+        assert!(package_req.matches(&PackageSpec::parse("rock".into(), "1.0".into()).unwrap()));
+        assert!(!package_req.matches(&PackageSpec::parse("rock".into(), "2.0".into()).unwrap()));
+        let package_req: PackageReq = "User/rock.nvim >= 1.0".parse().unwrap();
+        assert_eq!(package_req.namespace(), Some(&"user".into()));
+        assert_eq!(package_req.name().to_string(), "rock.nvim");
+        assert_eq!(package_req.to_string(), "user/rock.nvim >=1.0");
+        let package_req: PackageReq = "user/rock".parse().unwrap();
+        assert_eq!(package_req.to_string(), "user/rock");
+        let package_req: PackageReq = "rock@1.0".parse().unwrap();
+        assert_eq!(package_req.namespace(), None);
+        assert!(package_req.matches(&PackageSpec::parse("rock".into(), "1.0".into()).unwrap()));
+        assert!("/rock".parse::<PackageReq>().is_err());
+        assert!("user/".parse::<PackageReq>().is_err());
+    }
+
+    #[test]
+    fn package_req_parse_errors() {
+        let err = |str: &str| str.parse::<PackageReq>().unwrap_err().to_string();
+        assert_eq!(
+            err("neorg@@1.0"),
+            "unexpected '@' at column 7 in 'neorg@@1.0'. Did you mean 'neorg@1.0'?"
+        );
+        assert_eq!(
+            err("user/neorg@>=1.0"),
+            "unexpected '>' at column 12 in 'user/neorg@>=1.0'. Did you mean 'user/neorg >=1.0'?"
+        );
+        assert_eq!(
+            err("foo >= "),
+            "could not parse version requirement at column 5 in 'foo >= ': \
+             missing version after '>=', e.g. '>= 1.0'"
+        );
+        assert_eq!(
+            err("foo >= 1.0, <"),
+            "could not parse version requirement at column 5 in 'foo >= 1.0, <': \
+             missing version after '<', e.g. '< 1.0'"
+        );
+        assert_eq!(err("foo!"), "unexpected '!' at column 4 in 'foo!'");
+        assert_eq!(
+            err(">= 1.0"),
+            "could not parse dependency name from '>= 1.0': expected a rock name, e.g. 'foo' or 'foo >= 1.0'"
+        );
+        assert!(err("foo >= abc")
+            .starts_with("could not parse version requirement at column 5 in 'foo >= abc': "));
+    }
+
+    proptest! {
+        #[test]
+        fn package_req_display_round_trips(
+            namespace in "([a-z][a-z0-9_-]{0,8}/)?",
+            name in "[a-z][a-z0-9_.-]{0,15}",
+            separator in "( |@)",
+            version_req in version_req_strategy(),
+        ) {
+            let separator = if version_req.contains(['<', '>', '=', '~', '|', ',', '@']) { " " } else { separator.as_str() };
+            let package_req: PackageReq = format!("{namespace}{name}{separator}{version_req}").parse().unwrap();
+            prop_assert_eq!(package_req.to_string().parse::<PackageReq>().unwrap(), package_req);
+        }
+
+        #[test]
+        fn package_spec_display_round_trips(name in "[a-z][a-z0-9_.-]{0,15}", components in prop::collection::vec(0u64..100, 1..5)) {
+            let package = PackageSpec::parse(name, format!("{}-1", components.iter().join("."))).unwrap();
+            let parsed: PackageSpec = package.to_string().parse().unwrap();
+            prop_assert_eq!(parsed.name(), package.name());
+            prop_assert_eq!(parsed.version(), package.version());
+        }
+    }
+
+    /// Valid luarocks version requirements, e.g. `>= 1.0, < 2.0`, `~> 1.2` or `scm`,
+    /// including disjunctions of them.
+    pub(crate) fn version_req_strategy() -> impl Strategy<Value = String> {
+        let version = || {
+            prop::collection::vec(0u64..100, 1..4)
+                .prop_map(|components| components.iter().join("."))
+        };
+        let specrev = "(-[0-9])?";
+        let comparator = ("(=|>|>=|<|<=)?", " ?", version(), specrev).prop_map(
+            |(op, space, version, specrev)| {
+                format!("{op}{space}{version}{specrev}")
+                    .trim_start()
+                    .to_string()
+            },
+        );
+        let range = prop::collection::vec(comparator, 1..3).prop_map(|reqs| reqs.join(", "));
+        // The luarocks-specific operators can't be combined with other comparators.
+        let luarocks_req = ("(@|==|~>)", " ?", version(), specrev)
+            .prop_map(|(op, space, version, specrev)| format!("{op}{space}{version}{specrev}"));
+        let dev_req = "(== ?)?(dev|scm|git)";
+        let req = prop_oneof![3 => range, 1 => luarocks_req, 1 => dev_req];
+        prop::collection::vec(req, 1..3).prop_map(|reqs| reqs.join(" || "))
     }
 }
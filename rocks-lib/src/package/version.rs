@@ -3,7 +3,7 @@ use std::{cmp::Ordering, fmt::Display, str::FromStr};
 use html_escape::decode_html_entities;
 use itertools::Itertools;
 use mlua::FromLua;
-use semver::{Comparator, Error, Op, Version, VersionReq};
+use semver::{BuildMetadata, Comparator, Error, Op, Prerelease, Version, VersionReq};
 use serde::{de, Deserialize, Deserializer, Serialize};
 use thiserror::Error;
 
@@ -39,6 +39,26 @@ impl PackageVersion {
             }
         }
     }
+    /// A requirement for this version or newer.
+    /// Note that this loses the specrev information.
+    pub fn into_minimum_version_req(&self) -> PackageVersionReq {
+        match self {
+            PackageVersion::DevVer(DevVer { modrev, .. }) => {
+                PackageVersionReq::Dev(modrev.to_owned())
+            }
+            PackageVersion::SemVer(SemVer { version, .. }) => {
+                PackageVersionReq::SemVer(VersionReq {
+                    comparators: vec![Comparator {
+                        op: Op::GreaterEq,
+                        major: version.major,
+                        minor: Some(version.minor),
+                        patch: Some(version.patch),
+                        pre: version.pre.clone(),
+                    }],
+                })
+            }
+        }
+    }
 }
 
 #[derive(Error, Debug)]
@@ -118,9 +138,11 @@ impl FromStr for PackageVersion {
                 specrev,
             }));
         }
+        let (version, component_count, pre_release) = parse_luarocks_version(modrev)?;
         Ok(PackageVersion::SemVer(SemVer {
-            component_count: text.chars().filter(|c| *c == '.').count() + 1,
-            version: parse_version(modrev)?,
+            component_count,
+            version,
+            pre_release,
             specrev,
         }))
     }
@@ -129,20 +151,37 @@ impl FromStr for PackageVersion {
 // TODO: Stop deriving Eq here
 #[derive(Clone, Eq, PartialEq, Hash, Debug)]
 pub struct SemVer {
+    /// Components beyond `major.minor.patch` (e.g. the `4` in `1.2.3.4`)
+    /// are stored as build metadata, so that version requirements ignore them.
     version: Version,
     component_count: usize,
+    /// The pre-release suffix as written in the version string, e.g. `beta1` or `-rc.2`.
+    /// It is stored as a semver pre-release in `version`, e.g. `beta.1`.
+    pre_release: String,
     specrev: u16,
 }
 
+impl SemVer {
+    /// The numeric components of the version, including any components beyond `major.minor.patch`.
+    fn components(&self) -> impl Iterator<Item = u64> + '_ {
+        [self.version.major, self.version.minor, self.version.patch]
+            .into_iter()
+            .chain(
+                self.version
+                    .build
+                    .as_str()
+                    .split('.')
+                    .filter_map(|component| component.parse().ok()),
+            )
+    }
+}
+
 impl Display for SemVer {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let str = format!(
-            "{}-{}",
-            self.version
-                .to_string()
-                .split('.')
-                .take(self.component_count)
-                .join("."),
+            "{}{}-{}",
+            self.components().take(self.component_count).join("."),
+            self.pre_release,
             self.specrev
         );
         str.fmt(f)
@@ -159,12 +198,94 @@ impl Serialize for SemVer {
 }
 
 impl Ord for SemVer {
+    /// Versions are ordered like in luarocks:
+    /// - Numeric components are compared one by one, with missing components treated as `0`.
+    /// - Pre-releases (e.g. `1.0beta1`) come before their release (`1.0`),
+    ///   with `alpha < beta < pre < rc`, and numbers within them compared numerically.
+    /// - Finally, the specrev is compared.
     fn cmp(&self, other: &Self) -> Ordering {
-        let result = self.version.cmp(&other.version);
-        if result == Ordering::Equal {
-            return self.specrev.cmp(&other.specrev);
+        cmp_luarocks_versions(
+            self.components(),
+            &self.version.pre,
+            other.components(),
+            &other.version.pre,
+        )
+        .then_with(|| self.specrev.cmp(&other.specrev))
+    }
+}
+
+/// Compares two versions, given as their numeric components and pre-releases,
+/// like luarocks does (see [`SemVer::cmp`]), ignoring specrevs.
+fn cmp_luarocks_versions(
+    a: impl Iterator<Item = u64>,
+    a_pre: &Prerelease,
+    b: impl Iterator<Item = u64>,
+    b_pre: &Prerelease,
+) -> Ordering {
+    a.zip_longest(b)
+        .map(|pair| {
+            let (a, b) = pair.or(0, 0);
+            a.cmp(&b)
+        })
+        .find(|ordering| ordering.is_ne())
+        .unwrap_or(Ordering::Equal)
+        .then_with(|| match (a_pre.is_empty(), b_pre.is_empty()) {
+            (true, true) => Ordering::Equal,
+            (true, false) => Ordering::Greater,
+            (false, true) => Ordering::Less,
+            (false, false) => a_pre.cmp(b_pre),
+        })
+}
+
+/// Whether `version` matches `comparator`.
+/// Unlike [`Comparator::matches`], which excludes pre-releases and ignores build metadata,
+/// this compares versions like luarocks, so that e.g. `>= 1.0` matches `2.0beta1`
+/// and `> 1.2.3` matches `1.2.3.4`.
+/// Missing components are treated as `0`, except for `=` and wildcards,
+/// which only compare the components that the comparator specifies.
+fn comparator_matches(comparator: &Comparator, version: &SemVer) -> bool {
+    let components = std::iter::once(comparator.major)
+        .chain(comparator.minor)
+        .chain(comparator.patch)
+        .collect_vec();
+    let cmp = |components: &[u64], pre: &Prerelease| {
+        cmp_luarocks_versions(
+            version.components(),
+            &version.version.pre,
+            components.iter().copied(),
+            pre,
+        )
+    };
+    let ordering = cmp(&components, &comparator.pre);
+    let below = |upper_bound: &[u64]| cmp(upper_bound, &Prerelease::EMPTY).is_lt();
+    match comparator.op {
+        Op::Exact | Op::Wildcard => {
+            version
+                .components()
+                .take(components.len())
+                .eq(components.iter().copied())
+                && version.version.pre == comparator.pre
+        }
+        Op::Greater => ordering.is_gt(),
+        Op::GreaterEq => ordering.is_ge(),
+        Op::Less => ordering.is_lt(),
+        Op::LessEq => ordering.is_le(),
+        Op::Tilde => {
+            ordering.is_ge()
+                && match comparator.minor {
+                    Some(minor) => below(&[comparator.major, minor + 1]),
+                    None => below(&[comparator.major + 1]),
+                }
+        }
+        Op::Caret => {
+            ordering.is_ge()
+                && match (comparator.major, comparator.minor, comparator.patch) {
+                    (0, Some(0), Some(patch)) => below(&[0, 0, patch + 1]),
+                    (0, Some(minor), _) => below(&[0, minor + 1]),
+                    (major, _, _) => below(&[major + 1]),
+                }
         }
-        result
+        _ => comparator.matches(&version.version),
     }
 }
 
@@ -196,14 +317,24 @@ impl Serialize for DevVer {
     }
 }
 
+impl DevVer {
+    /// Like luarocks, we consider `dev` newer than `scm`.
+    /// `git` is not known to luarocks, so we consider it the oldest.
+    fn rank(&self) -> u8 {
+        match self.modrev.as_str() {
+            "dev" => 2,
+            "scm" => 1,
+            _ => 0,
+        }
+    }
+}
+
 impl Ord for DevVer {
     fn cmp(&self, other: &Self) -> Ordering {
-        // NOTE: We compare specrevs first for dev versions
-        let result = self.specrev.cmp(&other.specrev);
-        if result == Ordering::Equal {
-            return self.modrev.cmp(&other.modrev);
-        }
-        result
+        self.rank()
+            .cmp(&other.rank())
+            .then_with(|| self.modrev.cmp(&other.modrev))
+            .then_with(|| self.specrev.cmp(&other.specrev))
     }
 }
 
@@ -214,15 +345,21 @@ impl PartialOrd for DevVer {
 }
 
 #[derive(Error, Debug)]
-#[error(transparent)]
-pub struct PackageVersionReqError(#[from] Error);
+pub enum PackageVersionReqError {
+    #[error("missing version after '{0}', e.g. '{0} 1.0'")]
+    MissingVersion(String),
+    #[error(transparent)]
+    SemVer(#[from] Error),
+}
 
 /// **SemVer version** requirement as defined by <https://semver.org>.
 /// or a **Dev** version requirement, which can be one of "dev", "scm", or "git"
+/// or **Any** of several alternative requirements, separated by `||`, e.g. `>=1.0,<2.0 || >=3.0`.
 #[derive(Clone, Eq, PartialEq, Hash, Debug)]
 pub enum PackageVersionReq {
     SemVer(VersionReq),
     Dev(String),
+    Any(Vec<PackageVersionReq>),
 }
 
 impl PackageVersionReq {
@@ -231,9 +368,13 @@ impl PackageVersionReq {
     }
     pub fn matches(&self, version: &PackageVersion) -> bool {
         match (self, version) {
-            (PackageVersionReq::SemVer(version_req), PackageVersion::SemVer(semver)) => {
-                version_req.matches(&semver.version)
-            }
+            (PackageVersionReq::Any(version_reqs), version) => version_reqs
+                .iter()
+                .any(|version_req| version_req.matches(version)),
+            (PackageVersionReq::SemVer(version_req), PackageVersion::SemVer(semver)) => version_req
+                .comparators
+                .iter()
+                .all(|comparator| comparator_matches(comparator, semver)),
             (PackageVersionReq::SemVer(..), PackageVersion::DevVer(..)) => true,
             (PackageVersionReq::Dev(..), PackageVersion::SemVer(..)) => false,
             (PackageVersionReq::Dev(name_req), PackageVersion::DevVer(devver)) => {
@@ -241,6 +382,23 @@ impl PackageVersionReq {
             }
         }
     }
+    /// Whether this requirement pins a single version, e.g. `== 1.0.0`,
+    /// like the requirements of the rocks that are recorded in a lockfile.
+    pub fn is_exact(&self) -> bool {
+        match self {
+            PackageVersionReq::SemVer(version_req) => matches!(
+                version_req.comparators.as_slice(),
+                [Comparator {
+                    op: Op::Exact,
+                    minor: Some(_),
+                    patch: Some(_),
+                    ..
+                }]
+            ),
+            PackageVersionReq::Dev(..) => true,
+            PackageVersionReq::Any(..) => false,
+        }
+    }
 }
 
 impl Display for PackageVersionReq {
@@ -248,6 +406,9 @@ impl Display for PackageVersionReq {
         match self {
             PackageVersionReq::SemVer(version_req) => version_req.fmt(f),
             PackageVersionReq::Dev(name_req) => f.write_str(name_req.as_str()),
+            PackageVersionReq::Any(version_reqs) => {
+                f.write_str(version_reqs.iter().join(" || ").as_str())
+            }
         }
     }
 }
@@ -262,14 +423,36 @@ impl FromStr for PackageVersionReq {
     type Err = PackageVersionReqError;
 
     fn from_str(text: &str) -> Result<Self, Self::Err> {
+        if text.contains("||") {
+            return Ok(PackageVersionReq::Any(
+                text.split("||")
+                    .map(|version_req| PackageVersionReq::from_str(version_req.trim()))
+                    .try_collect()?,
+            ));
+        }
+
+        if let Some(operator) = text
+            .split(',')
+            .map(str::trim)
+            .find(|comparator| is_operator_only(comparator))
+        {
+            return Err(PackageVersionReqError::MissingVersion(operator.to_string()));
+        }
+
+        // Strip specrevs (e.g. the `-1` in `>= 1.0-1`), but keep semver pre-releases
+        // (e.g. the `-beta.1` in `=1.0.0-beta.1`), which is how requirements are displayed.
         let text = text
             .split('-')
             .map(str::to_string)
             .coalesce(|version, rest| {
-                Ok(format!(
-                    "{version}{}",
-                    rest.trim_start_matches(['0', '1', '2', '3', '4', '5', '6', '7', '8', '9'])
-                ))
+                if rest.starts_with(|c: char| c.is_ascii_digit()) {
+                    Ok(format!(
+                        "{version}{}",
+                        rest.trim_start_matches(|c: char| c.is_ascii_digit())
+                    ))
+                } else {
+                    Ok(format!("{version}-{rest}"))
+                }
             })
             .collect::<String>();
 
@@ -319,10 +502,67 @@ fn split_specrev(version_str: &str) -> Result<(&str, u16), SpecrevParseError> {
     }
 }
 
+/// Whether `comparator` is an operator without a version, e.g. `>=`.
+fn is_operator_only(comparator: &str) -> bool {
+    let comparator = decode_html_entities(comparator);
+    !comparator.is_empty()
+        && comparator
+            .chars()
+            .all(|c| matches!(c, '=' | '>' | '<' | '~' | '^' | '@'))
+}
+
 fn is_dev_version_str(text: &str) -> bool {
     matches!(text, "dev" | "scm" | "git")
 }
 
+/// Parses a luarocks version (without the specrev), e.g. `1.2.3.4` or `1.0beta1`.
+/// Returns the version, the number of numeric components and the pre-release suffix as written.
+fn parse_luarocks_version(s: &str) -> Result<(Version, usize, String), Error> {
+    let numeric_len = s
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(s.len());
+    let numeric = s[..numeric_len].trim_end_matches('.');
+    let pre_release = &s[numeric.len()..];
+    if numeric.is_empty() {
+        // Not a luarocks version. Let the semver crate produce an appropriate error.
+        return parse_version(s).map(|version| (version, 3, String::new()));
+    }
+    let components = numeric.split('.').collect_vec();
+    let mut version = parse_version(&components.iter().take(3).join("."))?;
+    if components.len() > 3 {
+        version.build = BuildMetadata::new(&components[3..].join("."))?;
+    }
+    if !pre_release.is_empty() {
+        version.pre = Prerelease::new(&normalize_pre_release(pre_release))?;
+    }
+    Ok((version, components.len(), pre_release.to_string()))
+}
+
+/// Transforms a luarocks pre-release suffix into a semver pre-release,
+/// splitting letters from numbers so that they are compared numerically,
+/// e.g. `beta10` -> `beta.10`.
+fn normalize_pre_release(pre_release: &str) -> String {
+    pre_release
+        .split(['-', '.', '_'])
+        .filter(|part| !part.is_empty())
+        .flat_map(|part| {
+            part.chars()
+                .chunk_by(|c| c.is_ascii_digit())
+                .into_iter()
+                .map(|(is_numeric, chunk)| {
+                    let chunk = chunk.collect::<String>();
+                    // semver does not allow leading zeros in numeric identifiers.
+                    if is_numeric {
+                        chunk.parse::<u64>().map_or(chunk, |n| n.to_string())
+                    } else {
+                        chunk
+                    }
+                })
+                .collect_vec()
+        })
+        .join(".")
+}
+
 /// Parses a Version from a string, automatically supplying any missing details (i.e. missing
 /// minor/patch sections).
 fn parse_version(s: &str) -> Result<Version, Error> {
@@ -347,24 +587,20 @@ fn parse_version_req(version_constraints: &str) -> Result<VersionReq, Error> {
     Ok(version_req)
 }
 
+/// Transforms luarocks' "compatible with" operator into a range.
+/// `~> 6` means `>= 6, < 7`, while `~> 6.1` and `~> 6.1.2`
+/// mean `>= 6.1, < 6.2` and `>= 6.1.2, < 6.2`, respectively.
 fn parse_pessimistic_version_constraint(version_constraint: String) -> Result<String, Error> {
-    // pessimistic operator
-    let min_version_str = &version_constraint[2..].trim();
-    let min_version = Version::parse(&append_minor_patch_if_missing(min_version_str.to_string()))?;
-
-    let max_version = match min_version_str.matches('.').count() {
-        0 => Version {
-            major: &min_version.major + 1,
-            ..min_version.clone()
-        },
-        1 => Version {
-            minor: &min_version.minor + 1,
-            ..min_version.clone()
-        },
-        _ => Version {
-            patch: &min_version.patch + 1,
-            ..min_version.clone()
-        },
+    let (min_version, component_count, _) = parse_luarocks_version(version_constraint[2..].trim())?;
+    // Comparators can't have build metadata, which is where we store the 4th component.
+    let min_version = Version {
+        build: BuildMetadata::EMPTY,
+        ..min_version
+    };
+    let max_version = if component_count <= 1 {
+        Version::new(min_version.major + 1, 0, 0)
+    } else {
+        Version::new(min_version.major, min_version.minor + 1, 0)
     };
 
     Ok(format!(">= {min_version}, < {max_version}"))
@@ -382,6 +618,7 @@ fn append_minor_patch_if_missing(version: String) -> String {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use proptest::prelude::*;
 
     #[tokio::test]
     async fn parse_semver_version() {
@@ -390,6 +627,7 @@ mod tests {
             PackageVersion::SemVer(SemVer {
                 version: "1.0.0".parse().unwrap(),
                 component_count: 1,
+                pre_release: String::new(),
                 specrev: 1,
             })
         );
@@ -398,6 +636,7 @@ mod tests {
             PackageVersion::SemVer(SemVer {
                 version: "1.0.0".parse().unwrap(),
                 component_count: 2,
+                pre_release: String::new(),
                 specrev: 1,
             })
         );
@@ -406,6 +645,7 @@ mod tests {
             PackageVersion::SemVer(SemVer {
                 version: "1.0.0".parse().unwrap(),
                 component_count: 3,
+                pre_release: String::new(),
                 specrev: 1
             })
         );
@@ -414,6 +654,7 @@ mod tests {
             PackageVersion::SemVer(SemVer {
                 version: "1.0.0".parse().unwrap(),
                 component_count: 3,
+                pre_release: String::new(),
                 specrev: 1
             })
         );
@@ -490,4 +731,234 @@ mod tests {
             PackageVersionReq::SemVer("> 1, < 1.2".parse().unwrap())
         );
     }
+
+    #[tokio::test]
+    async fn parse_disjoint_version_req() {
+        let version_req = PackageVersionReq::parse(">=1.0,<2.0 || >=3.0").unwrap();
+        assert_eq!(
+            version_req,
+            PackageVersionReq::Any(vec![
+                PackageVersionReq::SemVer(">=1.0,<2.0".parse().unwrap()),
+                PackageVersionReq::SemVer(">=3.0".parse().unwrap()),
+            ])
+        );
+        assert_eq!(version_req.to_string(), ">=1.0, <2.0 || >=3.0");
+        assert!(version_req.matches(&"1.5-1".parse().unwrap()));
+        assert!(version_req.matches(&"3.1-1".parse().unwrap()));
+        assert!(!version_req.matches(&"2.5-1".parse().unwrap()));
+        assert!(!version_req.matches(&"0.9-1".parse().unwrap()));
+        assert!(PackageVersionReq::parse(">=1.0 || scm")
+            .unwrap()
+            .matches(&"scm-1".parse().unwrap()));
+        assert!(PackageVersionReq::parse(">=1.0 ||").is_err());
+    }
+
+    #[tokio::test]
+    async fn compatible_version_req() {
+        for (version_req, version, matches) in [
+            ("~> 6", "6-1", true),
+            ("~> 6", "6.0.1-1", true),
+            ("~> 6", "6.9.9-1", true),
+            ("~> 6", "5.9-1", false),
+            ("~> 6", "7-1", false),
+            ("~> 6.1", "6.1-1", true),
+            ("~> 6.1", "6.1.9-1", true),
+            ("~> 6.1", "6.0.9-1", false),
+            ("~> 6.1", "6.2-1", false),
+            ("~> 6.1.2", "6.1.2-1", true),
+            ("~> 6.1.2", "6.1.3-1", true),
+            ("~> 6.1.2", "6.1.1-1", false),
+            ("~> 6.1.2", "6.2-1", false),
+            ("~> 6.1.2.3", "6.1.5-1", true),
+            ("~> 6.1.2.3", "6.2-1", false),
+            ("~>6.1", "6.1.1-1", true),
+            ("~> 1.0beta1", "1.0-1", true),
+            ("~> 1.0beta1", "1.0alpha1-1", false),
+            ("~> 6.1-1", "6.1.1-1", true),
+            ("~> 6.1-1", "6.2-1", false),
+            ("~> 6", "scm-1", true),
+        ] {
+            let req = PackageVersionReq::parse(version_req).unwrap();
+            let version = PackageVersion::parse(version).unwrap();
+            assert_eq!(
+                req.matches(&version),
+                matches,
+                "expected {version_req} to {}match {version}",
+                if matches { "" } else { "not " }
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn luarocks_version_req() {
+        for (version_req, version, matches) in [
+            (">=1.0", "2.0beta1-1", true),
+            (">=1.0", "1.0beta1-1", false),
+            ("<2.0", "2.0beta1-1", true),
+            ("<2.0", "2.0-1", false),
+            (">1.2.3", "1.2.3.4-1", true),
+            (">1.2.3", "1.2.3-1", false),
+            ("<=1.2.3", "1.2.3.4-1", false),
+            (">=1.2.3", "1.2.3.4-1", true),
+            ("~> 1.2", "1.2.0.1-1", true),
+            ("~> 1.2", "1.3beta1-1", true),
+            ("~> 1.2", "1.3-1", false),
+            ("~> 1.2", "1.2beta1-1", false),
+            ("== 1.2.3", "1.2.3-1", true),
+            ("== 1.2.3", "1.2.3beta1-1", false),
+            ("== 1.0", "1.0.0-1", true),
+            ("1.2", "1.9.9.9-1", true),
+            ("1.2", "2.0-1", false),
+        ] {
+            let req = PackageVersionReq::parse(version_req).unwrap();
+            let version = PackageVersion::parse(version).unwrap();
+            assert_eq!(
+                req.matches(&version),
+                matches,
+                "expected {version_req} to {}match {version}",
+                if matches { "" } else { "not " }
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn parse_luarocks_versions() {
+        for version in [
+            "1.2.3.4-1",
+            "1.0beta1-1",
+            "3.0rc1-2",
+            "1.0.0-beta1-1",
+            "2.0.rc.2-1",
+            "0.1.0alpha-1",
+        ] {
+            assert_eq!(PackageVersion::parse(version).unwrap().to_string(), version);
+        }
+    }
+
+    #[tokio::test]
+    async fn luarocks_version_ordering() {
+        let ordered = [
+            "0.9-1",
+            "1.0alpha-1",
+            "1.0alpha2-1",
+            "1.0beta1-1",
+            "1.0beta2-1",
+            "1.0beta10-1",
+            "1.0pre1-1",
+            "1.0rc1-1",
+            "1.0rc1-2",
+            "1.0-1",
+            "1.0.0.1-1",
+            "1.0.1-1",
+            "1.0.10-1",
+            "1.1-1",
+            "1.2.3.4-1",
+            "1.2.3.10-1",
+            "10.0-1",
+            "git-1",
+            "scm-1",
+            "scm-2",
+            "dev-1",
+        ]
+        .map(|version| PackageVersion::parse(version).unwrap());
+        for (lower, higher) in ordered.iter().tuple_windows() {
+            assert!(lower < higher, "expected {lower} < {higher}");
+        }
+        assert_eq!(
+            PackageVersion::parse("1.0-1")
+                .unwrap()
+                .cmp(&PackageVersion::parse("1.0.0-1").unwrap()),
+            Ordering::Equal
+        );
+    }
+
+    #[tokio::test]
+    async fn minimum_version_req() {
+        let req = PackageVersion::parse("1.2.3-1")
+            .unwrap()
+            .into_minimum_version_req();
+        assert_eq!(req.to_string(), ">=1.2.3");
+        assert!(req.matches(&PackageVersion::parse("1.3.0-1").unwrap()));
+        assert!(!req.matches(&PackageVersion::parse("1.2.2-1").unwrap()));
+        assert_eq!(
+            PackageVersion::parse("scm-1")
+                .unwrap()
+                .into_minimum_version_req(),
+            PackageVersionReq::Dev("scm".into())
+        );
+    }
+
+    #[tokio::test]
+    async fn exact_version_req() {
+        for exact in ["== 1.2.3", "@1.2.3", "scm"] {
+            assert!(
+                PackageVersionReq::parse(exact).unwrap().is_exact(),
+                "{exact}"
+            );
+        }
+        assert!(PackageVersion::parse("1.2.3-1")
+            .unwrap()
+            .into_version_req()
+            .is_exact());
+        for inexact in [
+            ">= 1.2.3",
+            "~> 1.2",
+            ">= 1.0, < 2.0",
+            "== 1.0.0 || == 2.0.0",
+        ] {
+            assert!(
+                !PackageVersionReq::parse(inexact).unwrap().is_exact(),
+                "{inexact}"
+            );
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn version_display_round_trips(components in prop::collection::vec(0u64..100, 1..5), tag in "((-)?(alpha|beta|pre|rc)[0-9]{0,3})?", specrev in 1u16..10) {
+            let version = PackageVersion::parse(&format!("{}{tag}-{specrev}", components.iter().join("."))).unwrap();
+            prop_assert_eq!(PackageVersion::parse(&version.to_string()).unwrap(), version);
+        }
+
+        #[test]
+        fn version_req_display_round_trips(version_req in crate::package::tests::version_req_strategy()) {
+            let version_req = PackageVersionReq::parse(&version_req).unwrap();
+            prop_assert_eq!(PackageVersionReq::parse(&version_req.to_string()).unwrap(), version_req);
+        }
+
+        #[test]
+        fn exact_version_req_display_round_trips(components in prop::collection::vec(0u64..100, 1..4), tag in "((alpha|beta|pre|rc)[0-9]{0,3})?") {
+            let version = PackageVersion::parse(&format!("{}{tag}-1", components.iter().join("."))).unwrap();
+            for version_req in [version.into_version_req(), version.into_minimum_version_req()] {
+                prop_assert_eq!(PackageVersionReq::parse(&version_req.to_string()).unwrap(), version_req);
+            }
+        }
+
+        #[test]
+        fn pre_release_before_release(components in prop::collection::vec(0u64..100, 1..5), tag in "(alpha|beta|pre|rc)[0-9]{0,3}") {
+            let release = components.iter().join(".");
+            let release_version = PackageVersion::parse(&format!("{release}-1")).unwrap();
+            let pre_release_version = PackageVersion::parse(&format!("{release}{tag}-1")).unwrap();
+            prop_assert!(pre_release_version < release_version);
+        }
+
+        #[test]
+        fn ordering_matches_components(a in prop::collection::vec(0u64..100, 1..5), b in prop::collection::vec(0u64..100, 1..5)) {
+            let version_a = PackageVersion::parse(&format!("{}-1", a.iter().join("."))).unwrap();
+            let version_b = PackageVersion::parse(&format!("{}-1", b.iter().join("."))).unwrap();
+            let pad = |components: &Vec<u64>| {
+                let mut components = components.clone();
+                components.resize(4, 0);
+                components
+            };
+            prop_assert_eq!(version_a.cmp(&version_b), pad(&a).cmp(&pad(&b)));
+        }
+
+        #[test]
+        fn dev_versions_are_newest(components in prop::collection::vec(0u64..100, 1..5), modrev in "(dev|scm|git)") {
+            let version = PackageVersion::parse(&format!("{}-1", components.iter().join("."))).unwrap();
+            let dev_version = PackageVersion::parse(&format!("{modrev}-1")).unwrap();
+            prop_assert!(version < dev_version);
+        }
+    }
 }
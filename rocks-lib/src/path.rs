@@ -1,6 +1,12 @@
 use itertools::Itertools;
 use serde::Serialize;
-use std::{env, fmt::Display, io, path::PathBuf, str::FromStr};
+use std::{
+    env,
+    fmt::Display,
+    io,
+    path::{PathBuf, MAIN_SEPARATOR_STR},
+    str::FromStr,
+};
 
 use crate::{build::utils::lua_lib_extension, tree::Tree};
 
@@ -61,6 +67,111 @@ impl Paths {
         path.prepend(self.path());
         path
     }
+
+    /// The environment variables that make these paths available to a child process.
+    /// The `$PATH` is prepended to the existing `$PATH`,
+    /// and Lua falls back to its default search paths after the `LUA_PATH` and `LUA_CPATH`.
+    pub fn env_vars(&self) -> [(&'static str, String); 3] {
+        [
+            ("PATH", self.path_prepended().joined()),
+            ("LUA_PATH", format!("{};;", self.package_path().joined())),
+            ("LUA_CPATH", format!("{};;", self.package_cpath().joined())),
+        ]
+    }
+
+    /// Prepend the paths of `other`, so that they take precedence.
+    pub fn prepend(&mut self, other: &Self) {
+        self.src.prepend(&other.src);
+        self.lib.prepend(&other.lib);
+        self.bin.prepend(&other.bin);
+    }
+
+    /// The existing files that `module` resolves to, in the order that `require` searches them.
+    /// If `kind` is set, only modules of that kind are searched for.
+    pub fn find_module(&self, module: &str, kind: Option<ModuleKind>) -> Vec<ModuleFile> {
+        self.module_candidates(module, kind)
+            .into_iter()
+            .filter(|file| file.path.is_file())
+            .collect()
+    }
+
+    /// Resolve `module` the way `require` does, considering the precedence
+    /// of the `package.path` and the `package.cpath`.
+    /// Modules that are preloaded or found on Lua's default search paths are not considered.
+    pub fn resolve_require(&self, module: &str, kind: Option<ModuleKind>) -> RequireResolution {
+        let mut searched = Vec::new();
+        for file in self.module_candidates(module, kind) {
+            if file.path.is_file() {
+                return RequireResolution {
+                    searched,
+                    loaded: Some(file),
+                };
+            }
+            searched.push(file);
+        }
+        RequireResolution {
+            searched,
+            loaded: None,
+        }
+    }
+
+    /// The files that `require` tries, in order: the `package.path`, the `package.cpath`, and,
+    /// for a submodule, the `package.cpath` for a C library that contains all of its root's modules.
+    fn module_candidates(&self, module: &str, kind: Option<ModuleKind>) -> Vec<ModuleFile> {
+        let lua = self.src.search(module).map(|path| ModuleFile {
+            path,
+            kind: ModuleKind::Lua,
+        });
+        let all_in_one = module
+            .split_once('.')
+            .map(|(root, _)| self.lib.search(root).collect_vec())
+            .unwrap_or_default();
+        let c = self
+            .lib
+            .search(module)
+            .chain(all_in_one)
+            .map(|path| ModuleFile {
+                path,
+                kind: ModuleKind::C,
+            });
+        lua.chain(c)
+            .filter(|file| kind.is_none() || kind == Some(file.kind))
+            .unique()
+            .collect()
+    }
+}
+
+/// The kind of a module, which determines where `require` searches for it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ModuleKind {
+    /// A pure Lua module, on the `package.path`.
+    Lua,
+    /// A C module, on the `package.cpath`.
+    C,
+}
+
+impl Display for ModuleKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Lua => "lua".fmt(f),
+            Self::C => "c".fmt(f),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ModuleFile {
+    pub path: PathBuf,
+    pub kind: ModuleKind,
+}
+
+/// The result of [`Paths::resolve_require`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequireResolution {
+    /// The files that were searched before the loaded one, which don't exist.
+    pub searched: Vec<ModuleFile>,
+    /// The file that `require` loads, if any.
+    pub loaded: Option<ModuleFile>,
 }
 
 #[derive(PartialEq, Eq, Debug, Default, Serialize)]
@@ -81,6 +192,14 @@ impl PackagePath {
             .map(|path| path.to_string_lossy())
             .join(LUA_PATH_SEPARATOR)
     }
+
+    /// The files that Lua's `package.searchpath` tries for `name`, in order.
+    fn search<'a>(&'a self, name: &str) -> impl Iterator<Item = PathBuf> + 'a {
+        let name = name.replace('.', MAIN_SEPARATOR_STR);
+        self.0
+            .iter()
+            .map(move |template| PathBuf::from(template.to_string_lossy().replace('?', &name)))
+    }
 }
 
 impl FromStr for PackagePath {
@@ -143,6 +262,8 @@ impl Display for BinPath {
 
 #[cfg(test)]
 mod test {
+    use assert_fs::prelude::{FileTouch as _, PathChild as _, PathCreateDir as _};
+
     use super::*;
 
     #[test]
@@ -163,4 +284,77 @@ mod test {
             "/path/to/some/lib/lua/5.1/?.so;/path/to/another/lib/lua/5.1/?.so"
         );
     }
+
+    #[test]
+    fn find_lua_and_c_modules() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let src = temp.child("src");
+        let lib = temp.child("lib");
+        src.create_dir_all().unwrap();
+        lib.create_dir_all().unwrap();
+        src.child("foo.lua").touch().unwrap();
+        lib.child("foo.so").touch().unwrap();
+        lib.child("bar.so").touch().unwrap();
+        let paths = Paths {
+            src: PackagePath(vec![src.join("?.lua"), src.join("?").join("init.lua")]),
+            lib: PackagePath(vec![lib.join("?.so")]),
+            bin: BinPath::default(),
+        };
+        let lua = ModuleFile {
+            path: src.join("foo.lua"),
+            kind: ModuleKind::Lua,
+        };
+        let c = ModuleFile {
+            path: lib.join("foo.so"),
+            kind: ModuleKind::C,
+        };
+
+        assert_eq!(paths.find_module("foo", None), vec![lua.clone(), c.clone()]);
+        assert_eq!(
+            paths.find_module("foo", Some(ModuleKind::Lua)),
+            vec![lua.clone()]
+        );
+        assert_eq!(
+            paths.find_module("foo", Some(ModuleKind::C)),
+            vec![c.clone()]
+        );
+
+        // The package.path takes precedence.
+        let resolution = paths.resolve_require("foo", None);
+        assert!(resolution.searched.is_empty());
+        assert_eq!(resolution.loaded, Some(lua));
+        let resolution = paths.resolve_require("foo", Some(ModuleKind::C));
+        assert_eq!(resolution.loaded, Some(c));
+
+        // Submodules can be loaded from a C library that contains all of its root's modules.
+        let resolution = paths.resolve_require("bar.baz", None);
+        assert_eq!(
+            resolution.searched,
+            vec![
+                ModuleFile {
+                    path: src.join("bar").join("baz.lua"),
+                    kind: ModuleKind::Lua,
+                },
+                ModuleFile {
+                    path: src.join("bar").join("baz").join("init.lua"),
+                    kind: ModuleKind::Lua,
+                },
+                ModuleFile {
+                    path: lib.join("bar").join("baz.so"),
+                    kind: ModuleKind::C,
+                },
+            ]
+        );
+        assert_eq!(
+            resolution.loaded,
+            Some(ModuleFile {
+                path: lib.join("bar.so"),
+                kind: ModuleKind::C,
+            })
+        );
+
+        let resolution = paths.resolve_require("missing", Some(ModuleKind::Lua));
+        assert_eq!(resolution.searched.len(), 2);
+        assert_eq!(resolution.loaded, None);
+    }
 }
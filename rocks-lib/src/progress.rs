@@ -1,4 +1,8 @@
-use std::{borrow::Cow, sync::Arc, time::Duration};
+use std::{borrow::Cow, io::Write as _, path::PathBuf, sync::Arc, time::Duration};
+
+use serde::Serialize;
+
+use crate::config::Config;
 
 mod private {
     pub trait HasProgress {}
@@ -34,21 +38,134 @@ where
     }
 }
 
+/// How progress, warnings and errors are reported.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+#[cfg_attr(feature = "clap", clap(rename_all = "lowercase"))]
+pub enum MessageFormat {
+    /// Progress bars and human readable messages.
+    #[default]
+    Human,
+    /// Newline-delimited JSON [`Event`]s on stdout, for editors and other tools.
+    Json,
+}
+
+/// An event reported with [`MessageFormat::Json`].
+/// Each event is printed as a single line of JSON, with a `type` field
+/// that is one of the variants below, in kebab-case, e.g.
+///
+/// ```json
+/// {"type":"progress","message":"Building foo@1.0.0-1..."}
+/// {"type":"artifact","kind":"rock","path":"/home/user/foo-1.0.0-1.src.rock"}
+/// ```
+///
+/// New event types and fields may be added, so consumers should ignore the ones they don't know.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum Event {
+    /// A task has started or made progress.
+    Progress { message: String },
+    /// An informational message.
+    Message { message: String },
+    /// A task has finished.
+    Finished { message: String },
+    /// A file or directory was produced.
+    Artifact { kind: ArtifactKind, path: PathBuf },
+    /// A rock was added to, removed from or changed in the lockfile.
+    LockfileChange {
+        change: LockfileChangeKind,
+        name: String,
+        old_versions: Vec<String>,
+        new_versions: Vec<String>,
+    },
+    /// Something went wrong, but the command carried on.
+    Warning { message: String },
+    /// The command failed.
+    Error { message: String },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ArtifactKind {
+    /// A packed rock, e.g. a `.src.rock`.
+    Rock,
+    /// A directory with the contents of a rock.
+    Directory,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LockfileChangeKind {
+    Added,
+    Removed,
+    /// Another version was locked, or the source of the same version changed.
+    Updated,
+}
+
+impl Event {
+    /// Print the event to stdout if `format` is [`MessageFormat::Json`].
+    pub fn emit(&self, format: MessageFormat) {
+        if format == MessageFormat::Json {
+            let mut stdout = std::io::stdout().lock();
+            // Events are best-effort, like progress bars.
+            if let Ok(json) = serde_json::to_string(self) {
+                let _ = writeln!(stdout, "{}", json);
+            }
+        }
+    }
+}
+
+/// Report a warning: on stderr with [`MessageFormat::Human`],
+/// or as an [`Event::Warning`] with [`MessageFormat::Json`].
+pub fn warn<M: Into<String>>(format: MessageFormat, message: M) {
+    let message = message.into();
+    match format {
+        MessageFormat::Human => eprintln!("⚠️ WARNING: {}", message),
+        MessageFormat::Json => Event::Warning { message }.emit(format),
+    }
+}
+
 // WARNING: Don't implement `Clone` for this.
-pub struct MultiProgress(indicatif::MultiProgress);
-pub struct ProgressBar(indicatif::ProgressBar);
+pub struct MultiProgress(indicatif::MultiProgress, MessageFormat);
+pub struct ProgressBar(indicatif::ProgressBar, MessageFormat);
 
 impl MultiProgress {
     pub fn new() -> Self {
-        Self(indicatif::MultiProgress::new())
+        Self::with_format(MessageFormat::Human)
+    }
+
+    /// Progress bars with [`MessageFormat::Human`],
+    /// or hidden ones that report their progress as [`Event`]s with [`MessageFormat::Json`].
+    pub fn with_format(format: MessageFormat) -> Self {
+        match format {
+            MessageFormat::Human => Self(indicatif::MultiProgress::new(), format),
+            MessageFormat::Json => Self(
+                indicatif::MultiProgress::with_draw_target(indicatif::ProgressDrawTarget::hidden()),
+                format,
+            ),
+        }
+    }
+
+    /// Progress bars in the [`Config::message_format`].
+    pub fn from_config(config: &Config) -> Self {
+        Self::with_format(config.message_format())
     }
 
     pub fn new_arc() -> Arc<Progress<MultiProgress>> {
         Arc::new(Progress::Progress(MultiProgress::new()))
     }
 
+    pub fn new_arc_from_config(config: &Config) -> Arc<Progress<MultiProgress>> {
+        Arc::new(Progress::Progress(MultiProgress::from_config(config)))
+    }
+
+    /// Add `bar`, which takes on this `MultiProgress`'s message format.
     pub fn add(&self, bar: ProgressBar) -> ProgressBar {
-        ProgressBar(self.0.insert_from_back(0, bar.0))
+        let message = bar.0.message();
+        if !message.is_empty() {
+            Event::Progress { message }.emit(self.1);
+        }
+        ProgressBar(self.0.insert_from_back(0, bar.0), self.1)
     }
 
     pub fn new_bar(&self) -> ProgressBar {
@@ -75,7 +192,7 @@ impl ProgressBar {
             indicatif::ProgressBar::new_spinner().with_finish(indicatif::ProgressFinish::AndClear);
         bar.enable_steady_tick(Duration::from_millis(100));
 
-        Self(bar)
+        Self(bar, MessageFormat::Human)
     }
 
     pub fn into_raw(self) -> indicatif::ProgressBar {
@@ -86,6 +203,11 @@ impl ProgressBar {
     where
         M: Into<Cow<'static, str>>,
     {
+        let message = message.into();
+        Event::Progress {
+            message: message.to_string(),
+        }
+        .emit(self.1);
         self.0.set_message(message)
     }
 
@@ -97,10 +219,50 @@ impl ProgressBar {
         self.0.position()
     }
 
+    /// Show the progress of a download of `length` bytes, with the transfer rate and ETA.
+    /// If the length is unknown, e.g. because the server didn't send a `Content-Length`,
+    /// the spinner shows the downloaded bytes and the transfer rate.
+    /// Report downloaded bytes with [`ProgressBar::inc`],
+    /// and call [`ProgressBar::finish_download`] when done.
+    pub fn start_download(&self, length: Option<u64>) {
+        self.0.reset();
+        let template = match length {
+            Some(length) => {
+                self.0.set_length(length);
+                "{spinner} {msg} {bytes}/{total_bytes} ({binary_bytes_per_sec}, {eta})"
+            }
+            None => {
+                self.0.unset_length();
+                "{spinner} {msg} {bytes} ({binary_bytes_per_sec})"
+            }
+        };
+        self.0.set_style(
+            indicatif::ProgressStyle::with_template(template)
+                .expect("download progress template is valid"),
+        );
+    }
+
+    /// Report `delta` more downloaded bytes.
+    pub fn inc(&self, delta: u64) {
+        self.0.inc(delta)
+    }
+
+    /// Go back to a plain spinner after a download started with [`ProgressBar::start_download`].
+    pub fn finish_download(&self) {
+        self.0.unset_length();
+        self.0.set_position(0);
+        self.0
+            .set_style(indicatif::ProgressStyle::default_spinner());
+    }
+
     pub fn println<M>(&self, message: M)
     where
         M: AsRef<str>,
     {
+        Event::Message {
+            message: message.as_ref().to_string(),
+        }
+        .emit(self.1);
         self.0.println(message)
     }
 
@@ -108,6 +270,11 @@ impl ProgressBar {
     where
         M: Into<Cow<'static, str>>,
     {
+        let message = message.into();
+        Event::Finished {
+            message: message.to_string(),
+        }
+        .emit(self.1);
         self.0.finish_with_message(message)
     }
 
@@ -124,7 +291,8 @@ impl Default for ProgressBar {
 
 impl From<String> for ProgressBar {
     fn from(message: String) -> Self {
-        Self(Self::new().0.with_message(message))
+        let bar = Self::new();
+        Self(bar.0.with_message(message), bar.1)
     }
 }
 
@@ -136,3 +304,53 @@ impl From<u64> for ProgressBar {
         new
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn event_schema() {
+        let events = [
+            Event::Progress {
+                message: "Building foo@1.0.0-1...".into(),
+            },
+            Event::Artifact {
+                kind: ArtifactKind::Rock,
+                path: "foo-1.0.0-1.src.rock".into(),
+            },
+            Event::Error {
+                message: "failed".into(),
+            },
+        ];
+        let json = events
+            .iter()
+            .map(|event| serde_json::to_string(event).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            json,
+            vec![
+                r#"{"type":"progress","message":"Building foo@1.0.0-1..."}"#,
+                r#"{"type":"artifact","kind":"rock","path":"foo-1.0.0-1.src.rock"}"#,
+                r#"{"type":"error","message":"failed"}"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn download_progress() {
+        let bar = ProgressBar::new();
+        bar.start_download(Some(10));
+        bar.inc(4);
+        assert_eq!(bar.position(), 4);
+        assert_eq!(bar.0.length(), Some(10));
+        bar.finish_download();
+        assert_eq!(bar.position(), 0);
+        assert_eq!(bar.0.length(), None);
+
+        bar.start_download(None);
+        bar.inc(4);
+        assert_eq!(bar.position(), 4);
+        assert_eq!(bar.0.length(), None);
+    }
+}
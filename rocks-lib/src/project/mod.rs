@@ -1,21 +1,90 @@
+use full_moon::{
+    ast::{punctuated::Pair, Expression, Field, Stmt, Var},
+    node::Node as _,
+    tokenizer::{TokenReference, TokenType},
+};
+use itertools::Itertools;
 use lets_find_up::{find_up_with, FindUpKind, FindUpOptions};
+use mlua::{Lua, Table};
 use std::{
+    collections::BTreeMap,
     io,
+    ops::Range,
     path::{Path, PathBuf},
 };
 use thiserror::Error;
 
 use crate::{
-    config::LuaVersion,
-    rockspec::{Rockspec, RockspecError},
+    config::{Config, LuaVersion},
+    package::{PackageName, PackageReq},
+    rockspec::{GitSource, Rockspec, RockspecError, RockspecFormat, RockspecFormatMismatch},
     tree::Tree,
 };
 
 #[derive(Error, Debug)]
-#[error(transparent)]
 pub enum ProjectError {
+    #[error(transparent)]
     Io(#[from] io::Error),
+    #[error(transparent)]
     Rockspec(#[from] RockspecError),
+    #[error("cannot write to project.rockspec: {0}")]
+    RockspecFormat(#[from] RockspecFormatMismatch),
+    #[error("invalid .lua-version file: {0}")]
+    LuaVersionFile(String),
+    #[error("failed to evaluate project.rockspec: {0}")]
+    Lua(#[from] mlua::Error),
+    #[error("invalid git dependency {name}: {message}")]
+    GitDependency { name: String, message: String },
+    #[error("invalid script {0}: expected a command string")]
+    Script(String),
+    #[error("cannot edit {field} in project.rockspec: {message}")]
+    Edit { field: String, message: String },
+}
+
+/// The kind of a project's dependency.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+#[cfg_attr(feature = "clap", clap(rename_all = "lowercase"))]
+pub enum DependencyType {
+    /// A dependency needed at runtime (`dependencies`).
+    Regular,
+    /// A dependency needed to build the project (`build_dependencies`).
+    Build,
+    /// A dependency needed to run the project's tests (`test_dependencies`).
+    Test,
+    /// A dependency that is only installed if its feature is enabled (`optional_dependencies`).
+    Optional,
+}
+
+impl DependencyType {
+    pub fn rockspec_field(&self) -> &'static str {
+        match self {
+            Self::Regular => "dependencies",
+            Self::Build => "build_dependencies",
+            Self::Test => "test_dependencies",
+            Self::Optional => "optional_dependencies",
+        }
+    }
+}
+
+/// A dependency on the rock in a git repository, rather than on a rocks server,
+/// declared in the `git_dependencies` table of the `project.rockspec`, e.g.
+///
+/// ```lua
+/// git_dependencies = {
+///     ["foo"] = { git = "https://github.com/user/foo", tag = "v1.0.0" },
+/// }
+/// ```
+///
+/// luarocks ignores this table, so git dependencies are not part of the rockspec's `dependencies`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GitDependency {
+    pub name: PackageName,
+    /// The repository and the tag, branch or commit to check out.
+    pub source: GitSource,
+    /// The path of the rockspec to install, relative to the repository's root,
+    /// if the repository contains more than one rockspec.
+    pub rockspec: Option<PathBuf>,
 }
 
 #[derive(Debug)]
@@ -24,6 +93,8 @@ pub struct Project {
     root: PathBuf,
     /// The parsed rockspec.
     rockspec: Rockspec,
+    git_dependencies: Vec<GitDependency>,
+    scripts: BTreeMap<String, String>,
 }
 
 impl Project {
@@ -32,20 +103,14 @@ impl Project {
     }
 
     pub fn from(start: impl AsRef<Path>) -> Result<Option<Self>, ProjectError> {
-        if !start.as_ref().exists() {
-            return Ok(None);
-        }
-
-        match find_up_with(
-            "project.rockspec",
-            FindUpOptions {
-                cwd: start.as_ref(),
-                kind: FindUpKind::File,
-            },
-        )? {
+        match Self::find_rockspec(start)? {
             Some(path) => {
                 let rockspec_content = std::fs::read_to_string(&path)?;
                 let rockspec = Rockspec::new(&rockspec_content)?;
+                let lua = Lua::new();
+                lua.load(&rockspec_content).exec()?;
+                let git_dependencies = parse_git_dependencies(&lua.globals())?;
+                let scripts = parse_scripts(&lua.globals())?;
 
                 let root = path.parent().unwrap();
 
@@ -54,6 +119,8 @@ impl Project {
                 Ok(Some(Project {
                     root: root.to_path_buf(),
                     rockspec,
+                    git_dependencies,
+                    scripts,
                 }))
             }
             None => Ok(None),
@@ -62,6 +129,21 @@ impl Project {
 }
 
 impl Project {
+    /// Find the `project.rockspec` in `start` or its closest ancestor, without parsing it.
+    pub fn find_rockspec(start: impl AsRef<Path>) -> io::Result<Option<PathBuf>> {
+        if !start.as_ref().exists() {
+            return Ok(None);
+        }
+
+        find_up_with(
+            "project.rockspec",
+            FindUpOptions {
+                cwd: start.as_ref(),
+                kind: FindUpKind::File,
+            },
+        )
+    }
+
     pub fn root(&self) -> &Path {
         &self.root
     }
@@ -70,9 +152,736 @@ impl Project {
         &self.rockspec
     }
 
-    pub fn tree(&self, lua_version: LuaVersion) -> io::Result<Tree> {
-        Tree::new(self.root.join(".rocks"), lua_version)
+    /// The project's `git_dependencies`, sorted by name.
+    pub fn git_dependencies(&self) -> &[GitDependency] {
+        &self.git_dependencies
+    }
+
+    /// The project's `scripts`, by name.
+    pub fn scripts(&self) -> &BTreeMap<String, String> {
+        &self.scripts
+    }
+
+    /// The command of the script called `name`, if the project defines one.
+    pub fn script(&self, name: &str) -> Option<&str> {
+        self.scripts.get(name).map(String::as_str)
+    }
+
+    /// The default root of the project's tree, `.rocks` in the project root.
+    pub fn default_tree_root_dir(&self) -> PathBuf {
+        self.root.join(".rocks")
+    }
+
+    /// The root of the project's tree, which is [`Config::target_dir`] if it is set.
+    pub fn tree_root_dir(&self, config: &Config) -> PathBuf {
+        config
+            .target_dir()
+            .cloned()
+            .unwrap_or_else(|| self.default_tree_root_dir())
+    }
+
+    pub fn tree(&self, config: &Config, lua_version: LuaVersion) -> io::Result<Tree> {
+        Tree::new(self.tree_root_dir(config), lua_version)
+    }
+
+    /// The LDoc configuration file (`config.ld`) in the project root, if present.
+    pub fn ldoc_config(&self) -> Option<PathBuf> {
+        let path = self.root.join("config.ld");
+        path.is_file().then_some(path)
+    }
+
+    /// The Lua version declared in a `.lua-version` file in the project root, if present.
+    /// The file is expected to contain a single line, e.g. `5.1` or `luajit`.
+    pub fn lua_version_file(&self) -> Result<Option<LuaVersion>, ProjectError> {
+        let path = self.root.join(".lua-version");
+        if !path.is_file() {
+            return Ok(None);
+        }
+        let content = std::fs::read_to_string(&path)?;
+        content
+            .lines()
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .parse()
+            .map(Some)
+            .map_err(ProjectError::LuaVersionFile)
+    }
+
+    /// Add dependencies to the `project.rockspec`,
+    /// replacing any existing dependencies on the same packages.
+    /// Fails if the declared `rockspec_format` doesn't support the dependency type.
+    pub fn add(
+        &mut self,
+        dependency_type: DependencyType,
+        packages: Vec<PackageReq>,
+    ) -> Result<(), ProjectError> {
+        let feature = dependency_type.rockspec_field();
+        let required = RockspecFormat::required_for(feature);
+        if let Some(declared) = self
+            .rockspec
+            .rockspec_format
+            .clone()
+            .filter(|declared| *declared < required)
+        {
+            return Err(RockspecFormatMismatch {
+                feature,
+                required,
+                declared,
+            }
+            .into());
+        }
+        let entries = packages
+            .iter()
+            .map(|package| format!("\"{}\"", package))
+            .collect_vec();
+        self.edit_table(
+            dependency_type.rockspec_field(),
+            |field| {
+                dependency_entry(field)
+                    .is_some_and(|dep| packages.iter().any(|package| package.name() == dep.name()))
+            },
+            &entries,
+        )
+    }
+
+    /// Add a git dependency to the `project.rockspec`,
+    /// replacing any existing git dependency on the same package.
+    pub fn add_git(&mut self, dependency: GitDependency) -> Result<(), ProjectError> {
+        let name = dependency.name.to_string();
+        let mut fields = vec![format!("git = \"{}\"", dependency.source.url)];
+        if let Some(checkout_ref) = &dependency.source.checkout_ref {
+            fields.push(format!("tag = \"{}\"", checkout_ref));
+        }
+        if let Some(rockspec) = &dependency.rockspec {
+            fields.push(format!(
+                "rockspec = \"{}\"",
+                rockspec.to_string_lossy().replace('\\', "/")
+            ));
+        }
+        let entry = format!("[\"{}\"] = {{ {} }}", name, fields.join(", "));
+        self.edit_table(
+            "git_dependencies",
+            |field| table_key(field).is_some_and(|key| key == name),
+            &[entry],
+        )?;
+        self.git_dependencies = self
+            .git_dependencies
+            .iter()
+            .filter(|dep| dep.name.to_string() != name)
+            .cloned()
+            .chain(std::iter::once(dependency))
+            .sorted_by(|a, b| a.name.cmp(&b.name))
+            .collect_vec();
+        Ok(())
+    }
+
+    /// Remove the dependencies on `package` from the `project.rockspec`,
+    /// returning the removed requirements.
+    pub fn remove(
+        &mut self,
+        dependency_type: DependencyType,
+        package: &PackageName,
+    ) -> Result<Vec<PackageReq>, ProjectError> {
+        let removed = self
+            .dependencies(dependency_type)
+            .iter()
+            .filter(|dep| dep.name() == package)
+            .cloned()
+            .collect_vec();
+        if !removed.is_empty() {
+            self.edit_table(
+                dependency_type.rockspec_field(),
+                |field| dependency_entry(field).is_some_and(|dep| dep.name() == package),
+                &[],
+            )?;
+        }
+        Ok(removed)
+    }
+
+    fn dependencies(&self, dependency_type: DependencyType) -> &Vec<PackageReq> {
+        match dependency_type {
+            DependencyType::Regular => &self.rockspec.dependencies.default,
+            DependencyType::Build => &self.rockspec.build_dependencies.default,
+            DependencyType::Test => &self.rockspec.test_dependencies.default,
+            DependencyType::Optional => &self.rockspec.optional_dependencies.default,
+        }
+    }
+
+    /// Edit the table assigned to `field` in the `project.rockspec` in place,
+    /// removing the entries for which `remove` returns `true` and appending `entries`.
+    /// The rest of the file, including comments and the table's other entries
+    /// (e.g. `platforms` overrides), is left as it is.
+    fn edit_table(
+        &mut self,
+        field: &str,
+        remove: impl Fn(&Field) -> bool,
+        entries: &[String],
+    ) -> Result<(), ProjectError> {
+        let path = self.root.join("project.rockspec");
+        let content = std::fs::read_to_string(&path)?;
+        let content = edit_table(&content, field, remove, entries)?;
+        self.rockspec = Rockspec::new(&content)?;
+        std::fs::write(&path, content)?;
+        Ok(())
+    }
+}
+
+fn parse_git_dependencies(globals: &Table) -> Result<Vec<GitDependency>, ProjectError> {
+    let Some(table) = globals.get::<Option<Table>>("git_dependencies")? else {
+        return Ok(Vec::new());
+    };
+    let mut dependencies = table
+        .pairs::<String, Table>()
+        .map(|pair| {
+            let (name, spec) = pair?;
+            let invalid = |message: String| ProjectError::GitDependency {
+                name: name.clone(),
+                message,
+            };
+            let url = spec
+                .get::<Option<String>>("git")?
+                .ok_or_else(|| invalid("missing 'git' URL".into()))?;
+            let source = GitSource {
+                checkout_ref: spec.get("tag")?,
+                ..GitSource::from_repository_url(&url).map_err(|err| invalid(err.to_string()))?
+            };
+            Ok(GitDependency {
+                name: PackageName::new(name.clone()),
+                source,
+                rockspec: spec.get::<Option<String>>("rockspec")?.map(PathBuf::from),
+            })
+        })
+        .collect::<Result<Vec<_>, ProjectError>>()?;
+    dependencies.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(dependencies)
+}
+
+/// Parse the `scripts` table of the `project.rockspec`, which maps script names to commands, e.g.
+///
+/// ```lua
+/// scripts = {
+///     test = "busted",
+///     lint = "luacheck .",
+/// }
+/// ```
+///
+/// Like `git_dependencies`, this table is ignored by luarocks.
+fn parse_scripts(globals: &Table) -> Result<BTreeMap<String, String>, ProjectError> {
+    let Some(table) = globals.get::<Option<Table>>("scripts")? else {
+        return Ok(BTreeMap::new());
+    };
+    table
+        .pairs::<String, mlua::Value>()
+        .map(|pair| {
+            let (name, command) = pair?;
+            match command {
+                mlua::Value::String(command) => Ok((name, command.to_str()?.to_string())),
+                _ => Err(ProjectError::Script(name)),
+            }
+        })
+        .collect()
+}
+
+/// Edit the table assigned to the top-level `field` of a rockspec in place,
+/// removing the entries for which `remove` returns `true` and appending `entries`,
+/// or append the table to the rockspec if there is none.
+/// New entries are inserted after the table's last entry without a key,
+/// so that they end up before e.g. a `platforms` override.
+fn edit_table(
+    content: &str,
+    field: &str,
+    remove: impl Fn(&Field) -> bool,
+    entries: &[String],
+) -> Result<String, ProjectError> {
+    let edit_error = |message: String| ProjectError::Edit {
+        field: field.to_string(),
+        message,
+    };
+    let ast = full_moon::parse(content)
+        .map_err(|errors| edit_error(errors.iter().map(|err| err.to_string()).join("\n")))?;
+    let lines = |indent: &str| {
+        entries
+            .iter()
+            .map(|entry| format!("{}{},\n", indent, entry))
+            .collect::<String>()
+    };
+    // If a field is assigned more than once, the last assignment wins.
+    let Some(value) = ast
+        .nodes()
+        .stmts()
+        .filter_map(|stmt| match stmt {
+            Stmt::Assignment(assignment) => assignment
+                .variables()
+                .iter()
+                .zip(assignment.expressions().iter())
+                .find_map(|(var, value)| match var {
+                    Var::Name(name) if name.token().to_string() == field => Some(value),
+                    _ => None,
+                }),
+            _ => None,
+        })
+        .last()
+    else {
+        if entries.is_empty() {
+            return Ok(content.to_string());
+        }
+        return Ok(format!(
+            "{}\n\n{} = {{\n{}}}\n",
+            content.trim_end(),
+            field,
+            lines(DEFAULT_INDENT)
+        ));
+    };
+    let Expression::TableConstructor(table) = value else {
+        return Err(edit_error("it is not a table".into()));
+    };
+
+    let (open, close) = table.braces().tokens();
+    let open_end = open.token().end_position().bytes();
+    let close_start = close.token().start_position().bytes();
+    let is_single_line = !content[open_end..close_start].contains('\n');
+    let (removed, kept): (Vec<_>, Vec<_>) = table
+        .fields()
+        .pairs()
+        .partition(|pair| remove(pair.value()));
+    if is_single_line && kept.is_empty() && !entries.is_empty() {
+        // Expand e.g. `{}` to a table with an entry per line.
+        return Ok(format!(
+            "{}\n{}{}",
+            &content[..open_end],
+            lines(DEFAULT_INDENT),
+            &content[close_start..]
+        ));
+    }
+
+    let mut edits = removed
+        .into_iter()
+        .map(|pair| (removal_span(content, pair), String::new()))
+        .collect_vec();
+    if !entries.is_empty() {
+        // The entry to insert the new entries after, and the entry that follows it, if any.
+        let after = kept
+            .iter()
+            .rposition(|pair| matches!(pair.value(), Field::NoKey(_)))
+            .or(kept.len().checked_sub(1));
+        if let Some(last @ Pair::End(_)) = after.map(|after| kept[after]) {
+            let end = entry_end(last);
+            edits.push((end..end, ",".into()));
+        }
+        if is_single_line {
+            let end = after.map_or(open_end, |after| entry_end(kept[after]));
+            let entries = entries
+                .iter()
+                .map(|entry| format!(" {},", entry))
+                .collect::<String>();
+            edits.push((end..end, entries));
+        } else {
+            let indent = after
+                .and_then(|after| line_indent(content, kept[after]))
+                .unwrap_or(DEFAULT_INDENT);
+            // Insert the entries on lines of their own, before the next entry
+            // (and the comments on the lines before it) or the closing brace.
+            let next_start = match kept.get(after.map_or(0, |after| after + 1)) {
+                Some(next) => next
+                    .surrounding_trivia()
+                    .0
+                    .first()
+                    .map(|trivia| trivia.start_position().bytes())
+                    .or_else(|| next.start_position().map(|position| position.bytes()))
+                    .unwrap_or(close_start),
+                None => close_start,
+            };
+            let line_start = content[..next_start].trim_end_matches([' ', '\t']).len();
+            let lines = if content[..line_start].ends_with('\n') {
+                lines(indent)
+            } else {
+                format!("\n{}", lines(indent))
+            };
+            edits.push((line_start..line_start, lines));
+        }
+    }
+
+    let mut content = content.to_string();
+    // Apply the edits back to front, so that the earlier spans remain valid.
+    for (span, replacement) in edits
+        .into_iter()
+        .sorted_by_key(|(span, _)| (span.start, span.end))
+        .rev()
+    {
+        content.replace_range(span, &replacement);
+    }
+    Ok(content)
+}
+
+/// The indentation of the entries of a table written by rocks.
+const DEFAULT_INDENT: &str = "    ";
+
+/// The indentation of a table entry, if it is the first thing on its line.
+fn line_indent<'a>(content: &'a str, pair: &Pair<Field>) -> Option<&'a str> {
+    let start = pair.start_position()?.bytes();
+    let line_start = content[..start]
+        .rfind('\n')
+        .map_or(0, |newline| newline + 1);
+    let indent = &content[line_start..start];
+    indent
+        .chars()
+        .all(|char| char == ' ' || char == '\t')
+        .then_some(indent)
+}
+
+/// The byte offset of the end of a table entry, including its separator if it has one,
+/// but not the whitespace or comments that follow it.
+fn entry_end(pair: &Pair<Field>) -> usize {
+    match pair {
+        Pair::Punctuated(_, separator) => separator.token().end_position().bytes(),
+        Pair::End(field) => field
+            .end_position()
+            .map(|position| position.bytes())
+            .unwrap_or_default(),
+    }
+}
+
+/// The byte range of a table entry to remove, including its separator.
+/// If the entry is on a line of its own, the whole line is removed,
+/// including a trailing comment. Otherwise, the whitespace before it is removed.
+fn removal_span(content: &str, pair: &Pair<Field>) -> Range<usize> {
+    let start = pair
+        .start_position()
+        .map(|position| position.bytes())
+        .unwrap_or_default();
+    let end = entry_end(pair);
+    let indented = content[..start].trim_end_matches([' ', '\t']);
+    let rest = &content[end..];
+    let line_len = rest.find('\n').map_or(rest.len(), |newline| newline + 1);
+    let rest_of_line = rest[..line_len].trim();
+    let is_own_line = (indented.is_empty() || indented.ends_with('\n'))
+        && (rest_of_line.is_empty()
+            // Long comments may span multiple lines.
+            || (rest_of_line.starts_with("--") && !rest_of_line.starts_with("--[")));
+    if is_own_line {
+        indented.len()..end + line_len
+    } else {
+        indented.len()..end
+    }
+}
+
+/// The string value of a token, if it is a string literal.
+fn string_literal(token: &TokenReference) -> Option<String> {
+    match token.token_type() {
+        TokenType::StringLiteral { literal, .. } => Some(literal.to_string()),
+        _ => None,
+    }
+}
+
+/// The dependency of a table entry, if it is a plain dependency string, e.g. `"foo >= 1.0"`.
+fn dependency_entry(field: &Field) -> Option<PackageReq> {
+    match field {
+        Field::NoKey(Expression::String(token)) => string_literal(token)?.parse().ok(),
+        _ => None,
+    }
+}
+
+/// The key of a table entry, e.g. `foo` for `foo = ...` and `["foo"] = ...`.
+fn table_key(field: &Field) -> Option<String> {
+    match field {
+        Field::NameKey { key, .. } => Some(key.token().to_string()),
+        Field::ExpressionKey {
+            key: Expression::String(token),
+            ..
+        } => string_literal(token),
+        _ => None,
     }
 }
 
 // TODO: Add plenty of tests
+#[cfg(test)]
+mod tests {
+    use assert_fs::prelude::{FileWriteStr as _, PathChild as _};
+
+    use super::*;
+
+    #[test]
+    fn add_dependencies() {
+        let root = assert_fs::TempDir::new().unwrap();
+        root.child("project.rockspec")
+            .write_str(
+                r#"
+package = "foo"
+version = "1.0.0-1"
+source = {
+    url = "https://github.com/nvim-neorocks/luarocks-stub",
+}
+-- keep me
+dependencies = {
+    "lua >= 5.1", -- the {lua} version
+    "say >= 1.0",
+}
+build = {
+    type = "builtin",
+}
+"#,
+            )
+            .unwrap();
+        let mut project = Project::from(root.path()).unwrap().unwrap();
+        project
+            .add(
+                DependencyType::Regular,
+                vec!["say >= 1.3".parse().unwrap(), "penlight".parse().unwrap()],
+            )
+            .unwrap();
+        project
+            .add(DependencyType::Test, vec!["busted".parse().unwrap()])
+            .unwrap();
+
+        let content = std::fs::read_to_string(root.join("project.rockspec")).unwrap();
+        assert!(content.contains("-- keep me"));
+        assert!(content.contains("type = \"builtin\""));
+        let rockspec = Project::from(root.path()).unwrap().unwrap().rockspec;
+        assert_eq!(
+            rockspec
+                .dependencies
+                .default
+                .iter()
+                .map(|dep| dep.to_string())
+                .collect::<Vec<_>>(),
+            vec!["lua >=5.1", "say >=1.3", "penlight"]
+        );
+        assert_eq!(rockspec.test_dependencies.default.len(), 1);
+        assert_eq!(
+            rockspec.test_dependencies.default,
+            project.rockspec().test_dependencies.default
+        );
+    }
+
+    #[test]
+    fn edit_dependencies_in_place() {
+        let root = assert_fs::TempDir::new().unwrap();
+        root.child("project.rockspec")
+            .write_str(
+                r#"
+package = "foo"
+version = "1.0.0-1"
+source = {
+    url = "https://github.com/nvim-neorocks/luarocks-stub",
+}
+description = {
+    detailed = [[
+        dependencies = { "not-a-dependency" }
+    ]],
+}
+dependencies = {
+    -- pinned until the next release }
+    "lua >= 5.1",
+    "say >= 1.0", -- keep say { pinned
+    "penlight",
+    platforms = {
+        unix = {
+            "luaposix",
+        },
+    },
+}
+"#,
+            )
+            .unwrap();
+        let mut project = Project::from(root.path()).unwrap().unwrap();
+        project
+            .add(DependencyType::Regular, vec!["busted".parse().unwrap()])
+            .unwrap();
+        project
+            .remove(DependencyType::Regular, &"penlight".into())
+            .unwrap();
+
+        let content = std::fs::read_to_string(root.join("project.rockspec")).unwrap();
+        assert!(content.contains("-- pinned until the next release }"));
+        assert!(content.contains("\"say >= 1.0\", -- keep say { pinned"));
+        assert!(content.contains("dependencies = { \"not-a-dependency\" }"));
+        let rockspec = Project::from(root.path()).unwrap().unwrap().rockspec;
+        assert_eq!(
+            rockspec
+                .dependencies
+                .default
+                .iter()
+                .map(|dep| dep.to_string())
+                .collect_vec(),
+            vec!["lua >=5.1", "say >=1.0", "busted"]
+        );
+        assert_eq!(
+            rockspec
+                .dependencies
+                .for_platform(&crate::rockspec::PlatformIdentifier::Linux)
+                .iter()
+                .map(|dep| dep.name().to_string())
+                .sorted()
+                .collect_vec(),
+            vec!["busted", "lua", "luaposix", "say"]
+        );
+    }
+
+    #[test]
+    fn refuse_dependency_types_unsupported_by_rockspec_format() {
+        let root = assert_fs::TempDir::new().unwrap();
+        root.child("project.rockspec")
+            .write_str(
+                r#"
+rockspec_format = "1.0"
+package = "foo"
+version = "1.0.0-1"
+source = {
+    url = "https://github.com/nvim-neorocks/luarocks-stub",
+}
+"#,
+            )
+            .unwrap();
+        let mut project = Project::from(root.path()).unwrap().unwrap();
+        assert!(matches!(
+            project.add(DependencyType::Test, vec!["busted".parse().unwrap()]),
+            Err(ProjectError::RockspecFormat(RockspecFormatMismatch {
+                feature: "test_dependencies",
+                ..
+            }))
+        ));
+        project
+            .add(DependencyType::Regular, vec!["say".parse().unwrap()])
+            .unwrap();
+        let content = std::fs::read_to_string(root.join("project.rockspec")).unwrap();
+        assert!(!content.contains("test_dependencies"));
+    }
+
+    #[test]
+    fn add_git_dependencies() {
+        let root = assert_fs::TempDir::new().unwrap();
+        root.child("project.rockspec")
+            .write_str(
+                r#"
+package = "foo"
+version = "1.0.0-1"
+source = {
+    url = "https://github.com/nvim-neorocks/luarocks-stub",
+}
+git_dependencies = {
+    ["bar"] = { git = "https://github.com/user/bar" },
+}
+"#,
+            )
+            .unwrap();
+        let mut project = Project::from(root.path()).unwrap().unwrap();
+        assert_eq!(project.git_dependencies().len(), 1);
+        assert_eq!(project.git_dependencies()[0].source.checkout_ref, None);
+
+        project
+            .add_git(GitDependency {
+                name: "baz".into(),
+                source: GitSource {
+                    checkout_ref: Some("v1".into()),
+                    ..GitSource::from_repository_url("https://github.com/user/baz").unwrap()
+                },
+                rockspec: Some("rockspecs/baz-scm-1.rockspec".into()),
+            })
+            .unwrap();
+        project
+            .add_git(GitDependency {
+                name: "bar".into(),
+                source: GitSource {
+                    checkout_ref: Some("main".into()),
+                    ..GitSource::from_repository_url("https://github.com/user/bar").unwrap()
+                },
+                rockspec: None,
+            })
+            .unwrap();
+
+        let reloaded = Project::from(root.path()).unwrap().unwrap();
+        assert_eq!(reloaded.git_dependencies(), project.git_dependencies());
+        assert_eq!(
+            reloaded
+                .git_dependencies()
+                .iter()
+                .map(|dep| (
+                    dep.name.to_string(),
+                    dep.source.checkout_ref.clone().unwrap()
+                ))
+                .collect_vec(),
+            vec![("bar".into(), "main".into()), ("baz".into(), "v1".into())]
+        );
+        assert_eq!(
+            reloaded.git_dependencies()[1].rockspec,
+            Some("rockspecs/baz-scm-1.rockspec".into())
+        );
+        // Git dependencies are not part of the rockspec's dependencies.
+        assert!(reloaded.rockspec().dependencies.default.is_empty());
+    }
+
+    #[test]
+    fn tree_in_target_dir() {
+        let root = assert_fs::TempDir::new().unwrap();
+        root.child("project.rockspec")
+            .write_str(
+                r#"
+package = "foo"
+version = "1.0.0-1"
+source = {
+    url = "https://github.com/nvim-neorocks/luarocks-stub",
+}
+"#,
+            )
+            .unwrap();
+        let project = Project::from(root.path()).unwrap().unwrap();
+        let target_dir = assert_fs::TempDir::new().unwrap();
+        let config = crate::config::ConfigBuilder::new()
+            .target_dir(Some(target_dir.to_path_buf()))
+            .build()
+            .unwrap();
+
+        let tree = project.tree(&config, LuaVersion::Lua51).unwrap();
+        tree.lockfile().unwrap();
+        assert_eq!(tree.root(), target_dir.join("5.1"));
+        assert!(target_dir.join("5.1").join("lock.json").is_file());
+        assert!(!project.default_tree_root_dir().exists());
+        // The project files stay in the project root.
+        assert!(root.join("project.rockspec").is_file());
+    }
+
+    #[test]
+    fn parse_project_scripts() {
+        let root = assert_fs::TempDir::new().unwrap();
+        root.child("project.rockspec")
+            .write_str(
+                r#"
+package = "foo"
+version = "1.0.0-1"
+source = {
+    url = "https://github.com/nvim-neorocks/luarocks-stub",
+}
+scripts = {
+    test = "busted",
+    lint = "luacheck .",
+}
+"#,
+            )
+            .unwrap();
+        let project = Project::from(root.path()).unwrap().unwrap();
+        assert_eq!(project.script("lint"), Some("luacheck ."));
+        assert_eq!(project.script("test"), Some("busted"));
+        assert_eq!(project.script("build"), None);
+        assert_eq!(project.scripts().len(), 2);
+
+        root.child("project.rockspec")
+            .write_str(
+                r#"
+package = "foo"
+version = "1.0.0-1"
+source = {
+    url = "https://github.com/nvim-neorocks/luarocks-stub",
+}
+scripts = {
+    test = { "busted" },
+}
+"#,
+            )
+            .unwrap();
+        assert!(matches!(
+            Project::from(root.path()),
+            Err(ProjectError::Script(name)) if name == "test"
+        ));
+    }
+}
@@ -1,14 +1,21 @@
 use crate::{
     config::Config,
     manifest::{Manifest, ManifestError},
-    package::{PackageName, PackageReq, PackageSpec, PackageVersion, RemotePackage},
+    package::{
+        PackageName, PackageNamespace, PackageReq, PackageSpec, PackageVersion, RemotePackage,
+    },
     progress::{Progress, ProgressBar},
 };
 use itertools::Itertools as _;
 use thiserror::Error;
 
 #[derive(Clone)]
-pub struct RemotePackageDB(Vec<Manifest>);
+pub struct RemotePackageDB {
+    manifests: Vec<Manifest>,
+    /// Versions that are treated as yanked, in addition to the ones the manifests mark as yanked.
+    yanked: Vec<PackageReq>,
+    allow_yanked: bool,
+}
 
 #[derive(Error, Debug)]
 pub enum RemotePackageDBError {
@@ -20,10 +27,15 @@ pub enum RemotePackageDBError {
 pub enum SearchError {
     #[error(transparent)]
     Mlua(#[from] mlua::Error),
-    #[error("no rock that matches '{0}' found")]
-    RockNotFound(PackageReq),
+    #[error("no rock that matches '{req}' found{suggestions}", req = .0, suggestions = did_you_mean(.1))]
+    RockNotFound(PackageReq, Vec<PackageName>),
     #[error("error when pulling manifest: {0}")]
     Manifest(#[from] ManifestError),
+    #[error("rock '{name}' exists in multiple namespaces: {}. Please specify one of them, e.g. '{}/{name}'", .namespaces.iter().join(", "), .namespaces[0])]
+    AmbiguousNamespace {
+        name: PackageName,
+        namespaces: Vec<PackageNamespace>,
+    },
 }
 
 impl RemotePackageDB {
@@ -34,29 +46,129 @@ impl RemotePackageDB {
             manifests.push(manifest);
         }
         manifests.push(Manifest::from_config(config.server(), config).await?);
-        Ok(Self(manifests))
+        if !config.namespace().is_empty() {
+            let namespace = PackageNamespace::new(config.namespace().clone());
+            manifests
+                .push(Manifest::from_config_namespaced(config.server(), &namespace, config).await?);
+        }
+        Ok(Self {
+            manifests,
+            yanked: config.yanked().clone(),
+            allow_yanked: config.allow_yanked(),
+        })
+    }
+
+    /// Fetch the manifests of the given namespaces from the configured server,
+    /// so that namespaced packages can be found.
+    pub async fn add_namespaces<'a>(
+        &mut self,
+        namespaces: impl IntoIterator<Item = &'a PackageNamespace>,
+        config: &Config,
+    ) -> Result<(), RemotePackageDBError> {
+        for namespace in namespaces {
+            if !self
+                .manifests
+                .iter()
+                .any(|manifest| manifest.namespace() == Some(namespace))
+            {
+                self.manifests.push(
+                    Manifest::from_config_namespaced(config.server(), namespace, config).await?,
+                );
+            }
+        }
+        Ok(())
     }
 
-    /// Find a package that matches the requirement
+    /// Whether `version` of a rock that `manifest` lists is yanked, either by the manifest
+    /// or by the [`Config::yanked`] denylist.
+    fn is_yanked(
+        &self,
+        manifest: &Manifest,
+        rock_name: &PackageName,
+        version: &PackageVersion,
+    ) -> bool {
+        manifest.metadata().is_yanked(rock_name, version)
+            || self
+                .yanked
+                .iter()
+                .any(|yanked| yanked.matches(&PackageSpec::new(rock_name.clone(), version.clone())))
+    }
+
+    /// Whether the latest match of `package_req` must not be `version`, because it is yanked.
+    /// Yanked versions are not skipped if they are requested exactly,
+    /// e.g. with `foo == 1.2.0`, as the rocks recorded in a lockfile are,
+    /// so that they can be reinstalled even if they were yanked after they were locked.
+    /// Nothing is skipped if [`Config::allow_yanked`] is set.
+    fn skips_yanked(
+        &self,
+        manifest: &Manifest,
+        package_req: &PackageReq,
+        version: &PackageVersion,
+    ) -> bool {
+        !self.allow_yanked
+            && !package_req.version_req().is_exact()
+            && self.is_yanked(manifest, package_req.name(), version)
+    }
+
+    /// Find a package that matches the requirement.
+    /// If the requirement is not namespaced, the package is looked up in the
+    /// non-namespaced manifests first, falling back to namespaced manifests
+    /// if it is found in exactly one namespace.
+    /// Yanked versions are skipped, unless they are requested exactly (see [`Config::allow_yanked`]).
     pub(crate) fn find(
         &self,
         package_req: &PackageReq,
         progress: &Progress<ProgressBar>,
     ) -> Result<RemotePackage, SearchError> {
-        let result = self.0.iter().find_map(|manifest| {
+        let search = |manifest: &Manifest| {
             progress.map(|p| p.set_message(format!("🔎 Searching {}", &manifest.server_url())));
-            manifest.search(package_req)
-        });
-        match result {
-            Some(package) => Ok(package),
-            None => Err(SearchError::RockNotFound(package_req.clone())),
+            manifest.search(package_req, |version| {
+                self.skips_yanked(manifest, package_req, version)
+            })
+        };
+        let result = self
+            .manifests
+            .iter()
+            .filter(|manifest| package_req.namespace().is_some() || manifest.namespace().is_none())
+            .find_map(search);
+        if let Some(package) = result {
+            return Ok(package);
+        }
+        let mut namespaced = self
+            .manifests
+            .iter()
+            .filter(|manifest| manifest.namespace().is_some())
+            .filter_map(|manifest| Some((manifest.namespace()?, search(manifest)?)))
+            .unique_by(|(namespace, _)| *namespace)
+            .collect_vec();
+        match namespaced.len() {
+            0 => Err(SearchError::RockNotFound(
+                package_req.clone(),
+                self.similar_names(package_req.name())
+                    .into_iter()
+                    .cloned()
+                    .collect(),
+            )),
+            1 => Ok(namespaced.pop().unwrap().1),
+            _ => Err(SearchError::AmbiguousNamespace {
+                name: package_req.name().clone(),
+                namespaces: namespaced
+                    .into_iter()
+                    .map(|(namespace, _)| namespace.clone())
+                    .collect(),
+            }),
         }
     }
 
     /// Search for all packages that match the requirement
     pub fn search(&self, package_req: &PackageReq) -> Vec<(&PackageName, Vec<&PackageVersion>)> {
-        self.0
+        self.manifests
             .iter()
+            .filter(|manifest| {
+                package_req
+                    .namespace()
+                    .is_none_or(|namespace| manifest.namespace() == Some(namespace))
+            })
             .flat_map(|manifest| {
                 manifest
                     .metadata()
@@ -80,24 +192,284 @@ impl RemotePackageDB {
             .collect()
     }
 
+    /// The names of the packages whose names are closest to `name`, e.g. to suggest
+    /// a fix for a typo. Only names within a small edit distance are considered.
+    pub fn similar_names(&self, name: &PackageName) -> Vec<&PackageName> {
+        let name_str = name.to_string();
+        let max_distance = (name_str.chars().count() / 4).clamp(1, 3);
+        self.manifests
+            .iter()
+            .flat_map(|manifest| manifest.metadata().repository.keys())
+            .filter(|candidate| *candidate != name)
+            .filter_map(|candidate| {
+                let distance = edit_distance(&name_str, &candidate.to_string());
+                (distance <= max_distance).then_some((distance, candidate))
+            })
+            .sorted()
+            .map(|(_, candidate)| candidate)
+            .dedup()
+            .take(MAX_SUGGESTIONS)
+            .collect()
+    }
+
+    /// The labels of a version of a rock, if any of the manifests list them.
+    pub fn labels(
+        &self,
+        rock_name: &PackageName,
+        version: &PackageVersion,
+    ) -> Option<&Vec<String>> {
+        self.manifests
+            .iter()
+            .find_map(|manifest| manifest.metadata().labels(rock_name, version))
+    }
+
+    /// The latest version of a rock that is not yanked.
     pub fn latest_version(&self, rock_name: &PackageName) -> Option<&PackageVersion> {
-        self.0
+        self.manifests
             .iter()
-            .filter_map(|manifest| manifest.metadata().latest_version(rock_name))
+            .filter_map(|manifest| {
+                manifest.metadata().latest_version(rock_name, |version| {
+                    !self.allow_yanked && self.is_yanked(manifest, rock_name, version)
+                })
+            })
             .sorted()
             .last()
     }
 
+    /// All versions of a rock that the manifests list, newest first,
+    /// each with whether it is yanked, e.g. to choose a version to pin.
+    /// If `namespace` is set, only the manifests of that namespace are searched.
+    pub fn versions(
+        &self,
+        rock_name: &PackageName,
+        namespace: Option<&PackageNamespace>,
+    ) -> Vec<(&PackageVersion, bool)> {
+        self.manifests
+            .iter()
+            .filter(|manifest| {
+                namespace.is_none_or(|namespace| manifest.namespace() == Some(namespace))
+            })
+            .flat_map(|manifest| {
+                manifest
+                    .metadata()
+                    .repository
+                    .get(rock_name)
+                    .into_iter()
+                    .flat_map(|versions| versions.keys())
+                    .map(move |version| (version, self.is_yanked(manifest, rock_name, version)))
+            })
+            .sorted_by(|(a, _), (b, _)| Ord::cmp(b, a))
+            .unique_by(|(version, _)| *version)
+            .collect()
+    }
+
+    /// The latest version of a rock that matches the requirement.
+    /// Yanked versions are skipped, unless they are requested exactly (see [`Config::allow_yanked`]).
     pub fn latest_match(&self, package_req: &PackageReq) -> Option<PackageSpec> {
-        self.0
+        self.manifests
             .iter()
-            .filter_map(|manifest| manifest.metadata().latest_match(package_req))
-            .last()
+            .filter_map(|manifest| {
+                manifest.metadata().latest_match(package_req, |version| {
+                    self.skips_yanked(manifest, package_req, version)
+                })
+            })
+            .next_back()
+    }
+}
+
+const MAX_SUGGESTIONS: usize = 3;
+
+/// Format a "did you mean" hint for a rock that was not found, if there are any suggestions.
+pub fn did_you_mean(suggestions: &[PackageName]) -> String {
+    match suggestions {
+        [] => String::new(),
+        [suggestion] => format!(". Did you mean '{}'?", suggestion),
+        suggestions => format!(
+            ". Did you mean one of {}?",
+            suggestions
+                .iter()
+                .map(|suggestion| format!("'{}'", suggestion))
+                .join(", ")
+        ),
+    }
+}
+
+/// The Levenshtein distance between two strings.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect_vec();
+    let mut previous = (0..=b.len()).collect_vec();
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a_char != *b_char);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        previous = current;
     }
+    previous[b.len()]
 }
 
 impl From<Manifest> for RemotePackageDB {
     fn from(manifest: Manifest) -> Self {
-        RemotePackageDB(vec![manifest])
+        RemotePackageDB {
+            manifests: vec![manifest],
+            yanked: Vec::new(),
+            allow_yanked: false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use crate::manifest::ManifestMetadata;
+
+    use super::*;
+
+    fn package_db() -> RemotePackageDB {
+        let metadata = ManifestMetadata::new(
+            &r#"
+repository = {
+    neorg = { ["8.0.0-1"] = { { arch = "rockspec" } } },
+    ["neorg-telescope"] = { ["1.0.0-1"] = { { arch = "rockspec" } } },
+    norg = { ["1.0.0-1"] = { { arch = "rockspec" } } },
+    ["pathlib.nvim"] = { ["2.2.3-1"] = { { arch = "rockspec" } } },
+}
+"#
+            .to_string(),
+        )
+        .unwrap();
+        Manifest::new("https://luarocks.org", metadata).into()
+    }
+
+    #[test]
+    fn edit_distances() {
+        assert_eq!(edit_distance("neorg", "neorg"), 0);
+        assert_eq!(edit_distance("neorgg", "neorg"), 1);
+        assert_eq!(edit_distance("nerog", "neorg"), 2);
+        assert_eq!(edit_distance("", "neorg"), 5);
+    }
+
+    #[test]
+    fn suggest_similar_names() {
+        let package_db = package_db();
+        assert_eq!(
+            package_db.similar_names(&PackageName::new("neorgg".into())),
+            vec![&PackageName::new("neorg".into())]
+        );
+        assert_eq!(
+            package_db.similar_names(&PackageName::new("norgg".into())),
+            vec![&PackageName::new("norg".into())]
+        );
+        assert!(package_db
+            .similar_names(&PackageName::new("telescope".into()))
+            .is_empty());
+
+        let err = package_db
+            .find(&"neorgg".parse().unwrap(), &Progress::NoProgress)
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "no rock that matches 'neorgg' found. Did you mean 'neorg'?"
+        );
+    }
+
+    #[test]
+    fn skip_yanked_versions() {
+        let metadata = ManifestMetadata::new(
+            &r#"
+repository = {
+    foo = {
+        ["1.0.0-1"] = { { arch = "rockspec" } },
+        ["1.1.0-1"] = { { arch = "rockspec" } },
+        ["2.0.0-1"] = { { arch = "rockspec", yanked = true }, { arch = "src" } },
+    },
+}
+"#
+            .to_string(),
+        )
+        .unwrap();
+        let package_db: RemotePackageDB = Manifest::new("https://luarocks.org", metadata).into();
+        let latest = |package_db: &RemotePackageDB, package_req: &str| {
+            package_db
+                .latest_match(&package_req.parse().unwrap())
+                .map(|package| package.version().to_string())
+        };
+
+        assert_eq!(latest(&package_db, "foo"), Some("1.1.0-1".into()));
+        assert_eq!(latest(&package_db, "foo >= 2.0.0"), None);
+        // Requested exactly, e.g. by a lockfile.
+        assert_eq!(latest(&package_db, "foo == 2.0.0"), Some("2.0.0-1".into()));
+        assert_eq!(
+            package_db
+                .find(&"foo".parse().unwrap(), &Progress::NoProgress)
+                .unwrap()
+                .package
+                .version()
+                .to_string(),
+            "1.1.0-1"
+        );
+        assert_eq!(
+            package_db
+                .latest_version(&"foo".into())
+                .unwrap()
+                .to_string(),
+            "1.1.0-1"
+        );
+
+        let denylisted = RemotePackageDB {
+            yanked: vec!["foo == 1.1.0".parse().unwrap()],
+            ..package_db.clone()
+        };
+        assert_eq!(latest(&denylisted, "foo"), Some("1.0.0-1".into()));
+
+        assert_eq!(
+            denylisted
+                .versions(&"foo".into(), None)
+                .into_iter()
+                .map(|(version, yanked)| (version.to_string(), yanked))
+                .collect_vec(),
+            vec![
+                ("2.0.0-1".into(), true),
+                ("1.1.0-1".into(), true),
+                ("1.0.0-1".into(), false),
+            ]
+        );
+
+        let allowed = RemotePackageDB {
+            allow_yanked: true,
+            ..denylisted
+        };
+        assert_eq!(latest(&allowed, "foo"), Some("2.0.0-1".into()));
+    }
+
+    #[test]
+    fn list_versions_newest_first() {
+        let manifest = std::fs::read_to_string(
+            PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("resources/test/manifest-5.1"),
+        )
+        .unwrap();
+        let package_db: RemotePackageDB = Manifest::new(
+            "https://luarocks.org",
+            ManifestMetadata::new(&manifest).unwrap(),
+        )
+        .into();
+
+        let versions = package_db
+            .versions(&"30log".into(), None)
+            .into_iter()
+            .map(|(version, yanked)| {
+                assert!(!yanked);
+                version.to_string()
+            })
+            .collect_vec();
+        assert_eq!(versions.len(), 16);
+        assert_eq!(versions[..3], ["1.3.0-1", "1.2.0-1", "1.1.0-1"]);
+        assert_eq!(versions[versions.len() - 3..], ["0.3-0", "0.2-2", "0.2-1"]);
+        assert!(package_db
+            .versions(&"30log".into(), Some(&PackageNamespace::new("user".into())))
+            .is_empty());
+        assert!(package_db.versions(&"bar".into(), None).is_empty());
     }
 }
@@ -1,5 +1,5 @@
 use itertools::Itertools as _;
-use serde::{de, Deserialize, Deserializer};
+use serde::{de, Deserialize, Deserializer, Serialize};
 use std::{collections::HashMap, convert::Infallible, fmt::Display, path::PathBuf, str::FromStr};
 use thiserror::Error;
 
@@ -11,13 +11,15 @@ use crate::{
     },
 };
 
-#[derive(Debug, PartialEq, Deserialize, Default, Clone)]
+#[derive(Debug, PartialEq, Deserialize, Serialize, Default, Clone)]
 pub struct BuiltinBuildSpec {
-    /// Keys are module names in the format normally used by the `require()` function
+    /// Keys are module names in the format normally used by the `require()` function.
+    /// If empty, the Lua modules in the source's `src`, `lua` and `lib` directories are
+    /// detected when building.
     pub modules: HashMap<LuaModule, ModuleSpec>,
 }
 
-#[derive(Debug, PartialEq, Eq, Deserialize, Default, Clone, Hash)]
+#[derive(Debug, PartialEq, Eq, Deserialize, Serialize, Default, Clone, Hash)]
 pub struct LuaModule(String);
 
 impl LuaModule {
@@ -75,7 +77,8 @@ impl Display for LuaModule {
     }
 }
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone, Serialize)]
+#[serde(untagged)]
 pub enum ModuleSpec {
     /// Pathnames of Lua files or C sources, for modules based on a single source file.
     SourcePath(PathBuf),
@@ -203,7 +206,7 @@ impl PlatformOverridable for ModuleSpecInternal {
 #[error("missing or empty field `sources`")]
 pub struct ModulePathsMissingSources;
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone, Serialize)]
 pub struct ModulePaths {
     /// Path names of C sources, mandatory field
     pub sources: Vec<PathBuf>,
@@ -1,6 +1,8 @@
 use std::collections::HashMap;
 
-#[derive(Debug, PartialEq, Clone)]
+use serde::Serialize;
+
+#[derive(Debug, PartialEq, Clone, Serialize)]
 pub struct CMakeBuildSpec {
     pub cmake_lists_content: Option<String>,
     /// Whether to perform a build pass.
@@ -10,6 +12,9 @@ pub struct CMakeBuildSpec {
     /// Default is true.
     pub install_pass: bool,
     pub variables: HashMap<String, String>,
+    /// The CMake generator to use, e.g. `Ninja`.
+    /// If not set, CMake picks the platform's default generator.
+    pub generator: Option<String>,
 }
 
 impl Default for CMakeBuildSpec {
@@ -19,6 +24,7 @@ impl Default for CMakeBuildSpec {
             build_pass: default_pass(),
             install_pass: default_pass(),
             variables: Default::default(),
+            generator: Default::default(),
         }
     }
 }
@@ -1,6 +1,8 @@
 use std::{collections::HashMap, path::PathBuf};
 
-#[derive(Debug, PartialEq, Clone)]
+use serde::Serialize;
+
+#[derive(Debug, PartialEq, Clone, Serialize)]
 pub struct MakeBuildSpec {
     /// Makefile to be used.
     /// Default is "Makefile" on Unix variants and "Makefile.win" under Win32.
@@ -25,11 +25,14 @@ use std::{
 };
 use thiserror::Error;
 
-use serde::{de, de::IntoDeserializer, Deserialize, Deserializer};
+use serde::{de, de::IntoDeserializer, Deserialize, Deserializer, Serialize};
 
 use crate::{
+    build::BuildBehaviour,
     config::Config,
     lua_installation::LuaInstallation,
+    package::PackageSpec,
+    path::Paths,
     progress::{Progress, ProgressBar},
     tree::RockLayout,
 };
@@ -42,7 +45,7 @@ use super::{
 ///
 /// See [the rockspec format](https://github.com/luarocks/luarocks/wiki/Rockspec-format) for more
 /// info.
-#[derive(Clone, Debug, PartialEq, Default)]
+#[derive(Clone, Debug, PartialEq, Default, Serialize)]
 pub struct BuildSpec {
     /// Determines the build backend to use.
     pub build_backend: Option<BuildBackendSpec>,
@@ -118,6 +121,7 @@ impl BuildSpec {
                     build_pass: internal.build_pass.unwrap_or(default.build_pass),
                     install_pass: internal.install_pass.unwrap_or(default.install_pass),
                     variables: internal.variables.unwrap_or_default(),
+                    generator: internal.cmake_generator,
                 }))
             }
             BuildType::Command => {
@@ -209,17 +213,19 @@ impl Default for BuildBackendSpec {
 ///
 /// Luarocks provides several default build types, these are also reflected in `rocks`
 /// for compatibility.
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone, Serialize)]
+#[serde(rename_all = "lowercase")]
 pub enum BuildBackendSpec {
     Builtin(BuiltinBuildSpec),
     Make(MakeBuildSpec),
     CMake(CMakeBuildSpec),
     Command(CommandBuildSpec),
     LuaRock(String),
+    #[serde(rename = "rust-mlua")]
     RustMlua(RustMluaBuildSpec),
 }
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone, Serialize)]
 pub struct CommandBuildSpec {
     pub build_command: String,
     pub install_command: String,
@@ -233,7 +239,7 @@ pub struct CommandBuildSpec {
 /// to indicate which subdirectory the file should be copied to.
 /// For example, build.install.lua = {["foo.bar"] = {"src/bar.lua"}} will copy src/bar.lua
 /// to the foo directory under the rock's Lua files directory.
-#[derive(Debug, PartialEq, Default, Deserialize, Clone)]
+#[derive(Debug, PartialEq, Default, Deserialize, Serialize, Clone)]
 pub struct InstallSpec {
     /// Lua modules written in Lua.
     #[serde(default)]
@@ -245,12 +251,28 @@ pub struct InstallSpec {
     #[serde(default)]
     pub conf: HashMap<String, PathBuf>,
     /// Lua command-line scripts.
-    // TODO(vhyrro): The String component should be checked to ensure that it consists of a single
-    // path component, such that targets like `my.binary` are not allowed.
-    #[serde(default)]
+    /// The targets are the names of the executables, so each one must be a single path component.
+    #[serde(default, deserialize_with = "deserialize_bin_targets")]
     pub bin: HashMap<String, PathBuf>,
 }
 
+fn deserialize_bin_targets<'de, D>(deserializer: D) -> Result<HashMap<String, PathBuf>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let bin = HashMap::<String, PathBuf>::deserialize(deserializer)?;
+    match bin
+        .keys()
+        .find(|target| target.is_empty() || target.contains(['.', '/', '\\']))
+    {
+        Some(target) => Err(de::Error::custom(format!(
+            "invalid bin target '{}': expected the name of an executable, without '.' or path separators",
+            target
+        ))),
+        None => Ok(bin),
+    }
+}
+
 fn deserialize_copy_directories<'de, D>(deserializer: D) -> Result<Option<Vec<PathBuf>>, D::Error>
 where
     D: Deserializer<'de>,
@@ -301,6 +323,8 @@ struct BuildSpecInternal {
     #[serde(rename = "cmake", default)]
     cmake_lists_content: Option<String>,
     #[serde(default)]
+    cmake_generator: Option<String>,
+    #[serde(default)]
     build_command: Option<String>,
     #[serde(default)]
     install_command: Option<String>,
@@ -432,6 +456,7 @@ fn override_build_spec_internal(
             &override_spec.cmake_lists_content,
             &base.cmake_lists_content,
         ),
+        cmake_generator: override_opt(&override_spec.cmake_generator, &base.cmake_generator),
         build_command: override_opt(&override_spec.build_command, &base.build_command),
         install_command: override_opt(&override_spec.install_command, &base.install_command),
         install: override_opt(&override_spec.install, &base.install),
@@ -532,11 +557,15 @@ impl Default for BuildType {
 pub trait Build {
     type Err: std::error::Error;
 
+    #[allow(clippy::too_many_arguments)]
     fn run(
         self,
         output_paths: &RockLayout,
         no_install: bool,
+        behaviour: BuildBehaviour,
+        package: &PackageSpec,
         lua: &LuaInstallation,
+        build_env: &Paths,
         config: &Config,
         build_dir: &Path,
         progress: &Progress<ProgressBar>,
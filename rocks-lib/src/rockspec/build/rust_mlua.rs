@@ -1,6 +1,8 @@
 use std::{collections::HashMap, path::PathBuf};
 
-#[derive(Debug, PartialEq, Default, Clone)]
+use serde::Serialize;
+
+#[derive(Debug, PartialEq, Default, Clone, Serialize)]
 pub struct RustMluaBuildSpec {
     /// Keys are module names in the format normally used by the `require()` function.
     /// values are the library names in the target directory.
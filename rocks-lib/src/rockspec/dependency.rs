@@ -1,11 +1,11 @@
 use std::{collections::HashMap, convert::Infallible, path::PathBuf};
 
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
 use super::{PartialOverride, PerPlatform, PlatformOverridable};
 
 /// Can be defined in a [platform-agnostic](https://github.com/luarocks/luarocks/wiki/platform-agnostic-external-dependencies) manner
-#[derive(Debug, Deserialize, PartialEq, Clone)]
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
 #[serde(rename_all = "lowercase")]
 pub enum ExternalDependencySpec {
     /// A header file, e.g. "foo.h"
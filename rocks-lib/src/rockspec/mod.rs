@@ -5,7 +5,7 @@ mod rock_source;
 mod serde_util;
 mod test_spec;
 
-use std::{collections::HashMap, io, path::PathBuf, str::FromStr};
+use std::{collections::HashMap, fmt::Display, io, path::PathBuf, str::FromStr};
 
 use itertools::Itertools;
 use mlua::{FromLua, Lua, LuaSerdeExt, Value};
@@ -16,13 +16,14 @@ pub use dependency::*;
 pub use platform::*;
 pub use rock_source::*;
 pub use serde_util::*;
-use ssri::Integrity;
+use ssri::{Algorithm, Integrity};
 pub use test_spec::*;
 use thiserror::Error;
 
 use crate::{
     config::{Config, LuaVersion, LuaVersionUnset},
-    hash::HasIntegrity,
+    hash::{self, HasIntegrity},
+    lua_installation::LuaInstallation,
     package::{PackageName, PackageReq, PackageVersion},
 };
 
@@ -36,7 +37,7 @@ pub enum RockspecError {
     LuaTable(#[from] LuaTableError),
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize)]
 pub struct Rockspec {
     /// The file format version. Example: "1.0"
     pub rockspec_format: Option<RockspecFormat>,
@@ -48,36 +49,97 @@ pub struct Rockspec {
     pub supported_platforms: PlatformSupport,
     pub dependencies: PerPlatform<Vec<PackageReq>>,
     pub build_dependencies: PerPlatform<Vec<PackageReq>>,
+    /// Dependencies that are only installed if their feature is enabled.
+    /// The feature that enables an optional dependency is the dependency's name.
+    pub optional_dependencies: PerPlatform<Vec<PackageReq>>,
     pub external_dependencies: PerPlatform<HashMap<String, ExternalDependencySpec>>,
     pub test_dependencies: PerPlatform<Vec<PackageReq>>,
     pub source: PerPlatform<RockSource>,
     pub build: PerPlatform<BuildSpec>,
     pub test: PerPlatform<TestSpec>,
     /// The original content of this rockspec, needed by luarocks
+    #[serde(skip)]
     pub raw_content: String,
     /// The sha256 of this rockspec
+    #[serde(skip)]
     hash: Integrity,
 }
 
+/// A field of a rockspec that could not be parsed by [`Rockspec::new_lenient`],
+/// and was replaced with its default value.
+#[derive(Error, Debug)]
+#[error("ignoring invalid field '{field}': {error}")]
+pub struct RockspecWarning {
+    pub field: &'static str,
+    pub error: RockspecError,
+}
+
 impl Rockspec {
     pub fn new(rockspec_content: &str) -> Result<Self, RockspecError> {
+        Self::parse(rockspec_content, None)
+    }
+
+    /// Like [`Rockspec::new`], but fields other than `package`, `version` and `source`
+    /// that fail to parse are replaced with their defaults and reported as warnings.
+    pub fn new_lenient(
+        rockspec_content: &str,
+    ) -> Result<(Self, Vec<RockspecWarning>), RockspecError> {
+        let mut warnings = Vec::new();
+        let rockspec = Self::parse(rockspec_content, Some(&mut warnings))?;
+        Ok((rockspec, warnings))
+    }
+
+    /// If `warnings` is `Some`, non-critical fields are parsed leniently.
+    fn parse(
+        rockspec_content: &str,
+        mut warnings: Option<&mut Vec<RockspecWarning>>,
+    ) -> Result<Self, RockspecError> {
         let lua = Lua::new();
         lua.load(rockspec_content).exec()?;
 
         let globals = lua.globals();
         let rockspec = Rockspec {
-            rockspec_format: globals.get("rockspec_format")?,
+            rockspec_format: lenient(
+                "rockspec_format",
+                globals.get("rockspec_format"),
+                &mut warnings,
+            )?,
             package: globals.get("package")?,
             version: globals.get("version")?,
-            description: parse_lua_tbl_or_default(&lua, "description")?,
-            supported_platforms: parse_lua_tbl_or_default(&lua, "supported_platforms")?,
-            dependencies: globals.get("dependencies")?,
-            build_dependencies: globals.get("build_dependencies")?,
-            test_dependencies: globals.get("test_dependencies")?,
-            external_dependencies: globals.get("external_dependencies")?,
+            description: lenient(
+                "description",
+                parse_lua_tbl_or_default(&lua, "description"),
+                &mut warnings,
+            )?,
+            supported_platforms: lenient(
+                "supported_platforms",
+                parse_lua_tbl_or_default(&lua, "supported_platforms"),
+                &mut warnings,
+            )?,
+            dependencies: lenient("dependencies", globals.get("dependencies"), &mut warnings)?,
+            build_dependencies: lenient(
+                "build_dependencies",
+                globals.get("build_dependencies"),
+                &mut warnings,
+            )?,
+            optional_dependencies: lenient(
+                "optional_dependencies",
+                globals.get("optional_dependencies"),
+                &mut warnings,
+            )?,
+            test_dependencies: lenient(
+                "test_dependencies",
+                globals.get("test_dependencies"),
+                &mut warnings,
+            )?,
+            external_dependencies: lenient(
+                "external_dependencies",
+                globals.get("external_dependencies"),
+                &mut warnings,
+            )?,
             source: globals.get("source")?,
-            build: globals.get("build")?,
-            test: globals.get("test")?,
+            build: lenient("build", globals.get("build"), &mut warnings)?,
+            test: lenient("test", globals.get("test"), &mut warnings)?,
             hash: Integrity::from(rockspec_content),
             raw_content: rockspec_content.into(),
         };
@@ -112,7 +174,11 @@ impl Rockspec {
 
     pub fn lua_version_from_config(&self, config: &Config) -> Result<LuaVersion, LuaVersionError> {
         let version = LuaVersion::from(config)?;
-        if self.supports_lua_version(&version) {
+        // Check against the installed release, e.g. `5.4.6`, if Lua has been installed.
+        let lua_pkg_version = LuaInstallation::installed(&version, config)
+            .map(|lua| lua.lua_package_version())
+            .unwrap_or_else(|| version.as_version());
+        if self.supports_lua_package_version(&lua_pkg_version, config.platform()) {
             Ok(version)
         } else {
             Err(LuaVersionError::LuaVersionUnsupported(
@@ -123,18 +189,26 @@ impl Rockspec {
         }
     }
 
+    #[cfg(test)]
     fn supports_lua_version(&self, lua_version: &LuaVersion) -> bool {
+        self.supports_lua_package_version(&lua_version.as_version(), &get_platform())
+    }
+
+    fn supports_lua_package_version(
+        &self,
+        lua_pkg_version: &PackageVersion,
+        platform: &PlatformIdentifier,
+    ) -> bool {
         let lua_version_reqs = self
             .dependencies
-            .current_platform()
+            .for_platform(platform)
             .iter()
             .filter(|val| *val.name() == "lua".into())
             .collect_vec();
-        let lua_pkg_version = lua_version.as_version();
         lua_version_reqs.is_empty()
             || lua_version_reqs
                 .into_iter()
-                .any(|lua| lua.version_req().matches(&lua_pkg_version))
+                .any(|lua| lua.version_req().matches(lua_pkg_version))
     }
 
     pub fn lua_version(&self) -> Option<LuaVersion> {
@@ -144,6 +218,49 @@ impl Rockspec {
     pub fn test_lua_version(&self) -> Option<LuaVersion> {
         latest_lua_version(&self.test_dependencies).or(self.lua_version())
     }
+
+    /// The `optional_dependencies` of `platform` that are enabled by `features`.
+    pub fn enabled_optional_dependencies(
+        &self,
+        features: &[PackageName],
+        platform: &PlatformIdentifier,
+    ) -> Vec<&PackageReq> {
+        self.optional_dependencies
+            .for_platform(platform)
+            .iter()
+            .filter(|dep| features.contains(dep.name()))
+            .collect_vec()
+    }
+
+    /// The features used by this rockspec that require a newer `rockspec_format`
+    /// than the one it declares. Rockspecs that don't declare a format are not checked.
+    pub fn format_mismatches(&self) -> Vec<RockspecFormatMismatch> {
+        let Some(declared) = &self.rockspec_format else {
+            return Vec::new();
+        };
+        let used = |feature: &str| match feature {
+            "build_dependencies" => !is_unset(&self.build_dependencies, Vec::is_empty),
+            "test_dependencies" => !is_unset(&self.test_dependencies, Vec::is_empty),
+            "test" => !is_unset(&self.test, |test| *test == TestSpec::AutoDetect),
+            "description.labels" => !self.description.labels.is_empty(),
+            "description.issues_url" => self.description.issues_url.is_some(),
+            _ => false,
+        };
+        FORMAT_FEATURES
+            .iter()
+            .filter(|(feature, required)| required > declared && used(feature))
+            .map(|(feature, required)| RockspecFormatMismatch {
+                feature,
+                required: required.clone(),
+                declared: declared.clone(),
+            })
+            .collect_vec()
+    }
+}
+
+/// Whether a rockspec field has its default value on all platforms.
+fn is_unset<T>(field: &PerPlatform<T>, is_default: impl Fn(&T) -> bool) -> bool {
+    is_default(&field.default) && field.per_platform.values().all(is_default)
 }
 
 fn latest_lua_version(dependencies: &PerPlatform<Vec<PackageReq>>) -> Option<LuaVersion> {
@@ -169,7 +286,7 @@ fn latest_lua_version(dependencies: &PerPlatform<Vec<PackageReq>>) -> Option<Lua
 
 #[derive(Error, Debug)]
 pub enum LuaVersionError {
-    #[error("The lua version {0} is not supported by {1} version {1}!")]
+    #[error("The lua version {0} is not supported by {1} version {2}!")]
     LuaVersionUnsupported(LuaVersion, PackageName, PackageVersion),
     #[error(transparent)]
     LuaVersionUnset(#[from] LuaVersionUnset),
@@ -179,9 +296,15 @@ impl HasIntegrity for Rockspec {
     fn hash(&self) -> io::Result<Integrity> {
         Ok(self.hash.to_owned())
     }
+
+    fn hash_with(&self, algorithms: &[Algorithm]) -> io::Result<Integrity> {
+        Ok(hash::integrity_opts(algorithms)
+            .chain(&self.raw_content)
+            .result())
+    }
 }
 
-#[derive(Clone, Deserialize, Debug, PartialEq, Default)]
+#[derive(Clone, Deserialize, Serialize, Debug, PartialEq, Default)]
 pub struct RockDescription {
     /// A one-line description of the package.
     pub summary: Option<String>,
@@ -204,7 +327,7 @@ pub struct RockDescription {
 #[error("invalid rockspec format: {0}")]
 pub struct InvalidRockspecFormat(String);
 
-#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 pub enum RockspecFormat {
     #[serde(rename = "1.0")]
     _1_0,
@@ -227,6 +350,45 @@ impl FromStr for RockspecFormat {
     }
 }
 
+impl Display for RockspecFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::_1_0 => "1.0".fmt(f),
+            Self::_2_0 => "2.0".fmt(f),
+            Self::_3_0 => "3.0".fmt(f),
+        }
+    }
+}
+
+impl RockspecFormat {
+    /// The oldest `rockspec_format` that supports `feature`, a (dotted) rockspec field.
+    pub fn required_for(feature: &str) -> Self {
+        FORMAT_FEATURES
+            .iter()
+            .find(|(name, _)| *name == feature)
+            .map(|(_, format)| format.clone())
+            .unwrap_or(Self::_1_0)
+    }
+}
+
+/// The rockspec fields that luarocks only accepts from a newer `rockspec_format` than "1.0".
+const FORMAT_FEATURES: [(&str, RockspecFormat); 5] = [
+    ("build_dependencies", RockspecFormat::_3_0),
+    ("test_dependencies", RockspecFormat::_3_0),
+    ("test", RockspecFormat::_3_0),
+    ("description.labels", RockspecFormat::_3_0),
+    ("description.issues_url", RockspecFormat::_3_0),
+];
+
+/// A rockspec feature that is not supported by the declared `rockspec_format`.
+#[derive(Error, Debug, Clone, PartialEq)]
+#[error("'{feature}' requires rockspec_format = \"{required}\", but \"{declared}\" is declared")]
+pub struct RockspecFormatMismatch {
+    pub feature: &'static str,
+    pub required: RockspecFormat,
+    pub declared: RockspecFormat,
+}
+
 impl From<&str> for RockspecFormat {
     fn from(s: &str) -> Self {
         Self::from_str(s).unwrap()
@@ -254,6 +416,30 @@ pub enum LuaTableError {
     MLua(#[from] mlua::Error),
 }
 
+/// Fall back to the default value of a non-critical field that failed to parse,
+/// if `warnings` are being collected.
+fn lenient<T, E>(
+    field: &'static str,
+    result: Result<T, E>,
+    warnings: &mut Option<&mut Vec<RockspecWarning>>,
+) -> Result<T, RockspecError>
+where
+    T: Default,
+    E: Into<RockspecError>,
+{
+    match (result, warnings) {
+        (Ok(value), _) => Ok(value),
+        (Err(err), Some(warnings)) => {
+            warnings.push(RockspecWarning {
+                field,
+                error: err.into(),
+            });
+            Ok(T::default())
+        }
+        (Err(err), None) => Err(err.into()),
+    }
+}
+
 fn parse_lua_tbl_or_default<T>(lua: &Lua, lua_var_name: &str) -> Result<T, LuaTableError>
 where
     T: Default,
@@ -275,6 +461,9 @@ mod tests {
 
     use std::path::PathBuf;
 
+    use serial_test::serial;
+
+    use crate::config::ConfigBuilder;
     use crate::package::PackageSpec;
     use crate::rockspec::PlatformIdentifier;
 
@@ -395,6 +584,7 @@ mod tests {
         supported_platforms = { 'unix', '!windows' }\n
         dependencies = { 'neorg ~> 6' }\n
         build_dependencies = { 'foo' }\n
+        optional_dependencies = { 'bar >= 2.0', 'baz' }\n
         external_dependencies = { FOO = { header = 'foo.h' } }\n
         test_dependencies = { 'busted >= 2.0.0' }\n
         source = {\n
@@ -423,6 +613,15 @@ mod tests {
         assert!(!rockspec
             .supported_platforms
             .is_supported(&PlatformIdentifier::Windows));
+        assert_eq!(rockspec.optional_dependencies.default.len(), 2);
+        assert_eq!(
+            rockspec
+                .enabled_optional_dependencies(&["baz".into(), "foo".into()], &get_platform())
+                .into_iter()
+                .map(|dep| dep.to_string())
+                .collect_vec(),
+            vec!["baz"]
+        );
         let neorg = PackageSpec::parse("neorg".into(), "6.0.0".into()).unwrap();
         assert!(rockspec
             .dependencies
@@ -539,7 +738,7 @@ mod tests {
         build = {\n
             install = {\n
                 lua = {['foo.bar'] = 'src/bar.lua'},\n
-                bin = {['foo.bar'] = 'bin/bar'},\n
+                bin = {bar = 'bin/bar'},\n
             },\n
         }\n
         "
@@ -557,8 +756,23 @@ mod tests {
             .get(&LuaModule::from_str("foo.bar").unwrap())
             .unwrap();
         assert_eq!(*foo_bar_path, PathBuf::from("src/bar.lua"));
-        let foo_bar_path = rockspec.build.default.install.bin.get("foo.bar").unwrap();
-        assert_eq!(*foo_bar_path, PathBuf::from("bin/bar"));
+        let bar_path = rockspec.build.default.install.bin.get("bar").unwrap();
+        assert_eq!(*bar_path, PathBuf::from("bin/bar"));
+        let rockspec_content = "
+        rockspec_format = '1.0'\n
+        package = 'foo'\n
+        version = '1.0.0-1'\n
+        source = {\n
+            url = 'git+https://hub.com/example-project/foo.zip',\n
+        }\n
+        build = {\n
+            install = {\n
+                bin = {['foo.bar'] = 'bin/bar'},\n
+            },\n
+        }\n
+        "
+        .to_string();
+        let _rockspec = Rockspec::new(&rockspec_content).unwrap_err();
         let rockspec_content = "
         rockspec_format = '1.0'\n
         package = 'foo'\n
@@ -985,6 +1199,55 @@ mod tests {
         assert_eq!(rockspec.version, "scm-1".parse().unwrap());
     }
 
+    #[tokio::test]
+    pub async fn parse_cmake_generator() {
+        let rockspec_content = "
+        package = 'foo'\n
+        version = '1.0.0-1'\n
+        source = { url = 'git+https://hub.com/example-project/foo.zip' }\n
+        build = {\n
+            type = 'cmake',\n
+            cmake_generator = 'Ninja',\n
+            variables = { FOO = 'bar' },\n
+        }\n
+        "
+        .to_string();
+        let rockspec = Rockspec::new(&rockspec_content).unwrap();
+        assert_eq!(
+            rockspec.build.default.build_backend,
+            Some(BuildBackendSpec::CMake(CMakeBuildSpec {
+                variables: vec![("FOO".into(), "bar".into())].into_iter().collect(),
+                generator: Some("Ninja".into()),
+                ..CMakeBuildSpec::default()
+            }))
+        );
+        let rockspec_content = "
+        package = 'foo'\n
+        version = '1.0.0-1'\n
+        source = { url = 'git+https://hub.com/example-project/foo.zip' }\n
+        build = {\n
+            type = 'cmake',\n
+            cmake_generator = 'Ninja',\n
+            platforms = {\n
+                windows = { cmake_generator = 'Visual Studio 17 2022' },\n
+                linux = { variables = { FOO = 'bar' } },\n
+            },\n
+        }\n
+        "
+        .to_string();
+        let rockspec = Rockspec::new(&rockspec_content).unwrap();
+        let per_platform = rockspec.build.per_platform;
+        let generator = |platform| match &per_platform.get(&platform).unwrap().build_backend {
+            Some(BuildBackendSpec::CMake(spec)) => spec.generator.clone(),
+            _ => panic!("expected a cmake build backend"),
+        };
+        assert_eq!(
+            generator(PlatformIdentifier::Windows),
+            Some("Visual Studio 17 2022".into())
+        );
+        assert_eq!(generator(PlatformIdentifier::Linux), Some("Ninja".into()));
+    }
+
     #[tokio::test]
     pub async fn regression_luasystem() {
         let rockspec_content = "
@@ -1188,4 +1451,346 @@ build = {
             panic!("Expected RustMlua build backend");
         }
     }
+
+    #[tokio::test]
+    pub async fn resolve_dependencies_for_overridden_platform() {
+        let rockspec_content = "
+        package = 'foo'\n
+        version = '1.0.0-1'\n
+        source = {\n
+            url = 'https://github.com/example/foo/archive/1.0.0.zip',\n
+        }\n
+        dependencies = {\n
+          'bar >= 1',\n
+          platforms = {\n
+            windows = {\n
+              'winapi >= 1',\n
+            },\n
+            unix = {\n
+              'luaposix >= 1',\n
+            },\n
+          },\n
+        }\n
+        ";
+        let rockspec = Rockspec::new(rockspec_content).unwrap();
+        let dependency_names = |platform| {
+            let config = ConfigBuilder::new()
+                .platform(Some(platform))
+                .build()
+                .unwrap();
+            rockspec
+                .dependencies
+                .for_platform(config.platform())
+                .iter()
+                .map(|dep| dep.name().to_string())
+                .sorted()
+                .collect_vec()
+        };
+
+        let windows_dependencies = dependency_names(PlatformIdentifier::Windows);
+        let unix_dependencies = dependency_names(PlatformIdentifier::Unix);
+
+        assert_eq!(windows_dependencies, vec!["bar", "winapi"]);
+        assert_eq!(unix_dependencies, vec!["bar", "luaposix"]);
+    }
+
+    #[tokio::test]
+    pub async fn parse_negated_platform_overrides() {
+        let rockspec_content = "
+        package = 'foo'\n
+        version = '1.0.0-1'\n
+        source = {\n
+            url = 'https://github.com/example/foo/archive/1.0.0.zip',\n
+        }\n
+        dependencies = {\n
+          'bar >= 1',\n
+          platforms = {\n
+            unix = {\n
+              'luaposix >= 1',\n
+            },\n
+            ['!macosx'] = {\n
+              'inotify >= 1',\n
+            },\n
+          },\n
+        }\n
+        ";
+        let rockspec = Rockspec::new(rockspec_content).unwrap();
+        let dependency_names = |platform: &PlatformIdentifier| {
+            rockspec
+                .dependencies
+                .for_platform(platform)
+                .iter()
+                .map(|dep| dep.name().to_string())
+                .sorted()
+                .collect_vec()
+        };
+        assert_eq!(
+            dependency_names(&PlatformIdentifier::Linux),
+            vec!["bar", "inotify", "luaposix"]
+        );
+        assert_eq!(
+            dependency_names(&PlatformIdentifier::FreeBSD),
+            vec!["bar", "inotify", "luaposix"]
+        );
+        assert_eq!(
+            dependency_names(&PlatformIdentifier::MacOSX),
+            vec!["bar", "luaposix"]
+        );
+        assert_eq!(
+            dependency_names(&PlatformIdentifier::Windows),
+            vec!["bar", "inotify"]
+        );
+    }
+
+    #[tokio::test]
+    pub async fn serialize_rockspec() {
+        let rockspec_content = "
+        package = 'foo'\n
+        version = '1.0.0-1'\n
+        dependencies = { 'lua >= 5.1' }\n
+        source = {\n
+            url = 'https://github.com/nvim-neorocks/rocks.nvim/archive/1.0.0/rocks.nvim.zip',\n
+        }\n
+        ";
+        let rockspec = Rockspec::new(rockspec_content).unwrap();
+        let json = serde_json::to_value(&rockspec).unwrap();
+        assert_eq!(json["package"], "foo");
+        assert_eq!(json["version"], "1.0.0-1");
+        assert_eq!(json["dependencies"]["default"][0], "lua >=5.1");
+        assert_eq!(
+            json["source"]["default"]["source_spec"]["url"],
+            "https://github.com/nvim-neorocks/rocks.nvim/archive/1.0.0/rocks.nvim.zip"
+        );
+        assert!(json.get("raw_content").is_none());
+    }
+
+    #[tokio::test]
+    #[serial]
+    pub async fn luajit_lua_version_compatibility() {
+        let rockspec_with_lua = |lua_dependency: &str| {
+            Rockspec::new(&format!(
+                "
+                package = 'foo'\n
+                version = '1.0.0-1'\n
+                dependencies = {{ '{lua_dependency}' }}\n
+                source = {{\n
+                    url = 'https://github.com/nvim-neorocks/rocks.nvim/archive/1.0.0/rocks.nvim.zip',\n
+                }}\n
+                "
+            ))
+            .unwrap()
+        };
+
+        let rockspec = rockspec_with_lua("lua >= 5.1");
+        assert!(rockspec.supports_lua_version(&LuaVersion::LuaJIT));
+        assert!(rockspec.supports_lua_version(&LuaVersion::LuaJIT52));
+
+        let rockspec = rockspec_with_lua("lua == 5.1");
+        assert!(rockspec.supports_lua_version(&LuaVersion::LuaJIT));
+        assert!(!rockspec.supports_lua_version(&LuaVersion::LuaJIT52));
+
+        let rockspec = rockspec_with_lua("lua >= 5.2");
+        assert!(!rockspec.supports_lua_version(&LuaVersion::LuaJIT));
+        assert!(rockspec.supports_lua_version(&LuaVersion::LuaJIT52));
+
+        let config = ConfigBuilder::new()
+            .lua_version(Some(LuaVersion::LuaJIT))
+            .build()
+            .unwrap();
+        assert_eq!(
+            rockspec_with_lua("lua >= 5.1")
+                .lua_version_from_config(&config)
+                .unwrap(),
+            LuaVersion::LuaJIT
+        );
+        assert!(rockspec_with_lua("lua >= 5.2")
+            .lua_version_from_config(&config)
+            .is_err());
+    }
+
+    #[tokio::test]
+    #[serial]
+    pub async fn installed_lua_release_compatibility() {
+        let rockspec = Rockspec::new(
+            "
+            package = 'foo'\n
+            version = '1.0.0-1'\n
+            dependencies = { 'lua >= 5.4.6' }\n
+            source = {\n
+                url = 'https://github.com/nvim-neorocks/rocks.nvim/archive/1.0.0/rocks.nvim.zip',\n
+            }\n
+            ",
+        )
+        .unwrap();
+        let lua_dir = assert_fs::TempDir::new().unwrap();
+        let config = ConfigBuilder::new()
+            .lua_dir(Some(lua_dir.to_path_buf()))
+            .lua_version(Some(LuaVersion::Lua54))
+            .build()
+            .unwrap();
+        assert!(rockspec.lua_version_from_config(&config).is_err());
+
+        let installation = LuaInstallation::path(&LuaVersion::Lua54, &config);
+        std::fs::create_dir_all(&installation).unwrap();
+        std::fs::write(installation.join("release"), "5.4.6").unwrap();
+        assert_eq!(
+            rockspec.lua_version_from_config(&config).unwrap(),
+            LuaVersion::Lua54
+        );
+    }
+
+    #[tokio::test]
+    pub async fn parse_rockspec_leniently() {
+        let rockspec_content = "
+        package = 'foo'\n
+        version = '1.0.0-1'\n
+        description = 'not a table'\n
+        source = {\n
+            url = 'https://github.com/nvim-neorocks/rocks.nvim/archive/1.0.0/rocks.nvim.zip',\n
+        }\n
+        ";
+        assert!(Rockspec::new(rockspec_content).is_err());
+        let (rockspec, warnings) = Rockspec::new_lenient(rockspec_content).unwrap();
+        assert_eq!(rockspec.package, "foo".into());
+        assert_eq!(rockspec.description, RockDescription::default());
+        assert_eq!(
+            warnings.iter().map(|warning| warning.field).collect_vec(),
+            vec!["description"]
+        );
+
+        let rockspec_content = "
+        package = {}\n
+        version = '1.0.0-1'\n
+        source = {\n
+            url = 'https://github.com/nvim-neorocks/rocks.nvim/archive/1.0.0/rocks.nvim.zip',\n
+        }\n
+        ";
+        assert!(Rockspec::new_lenient(rockspec_content).is_err());
+    }
+
+    #[tokio::test]
+    pub async fn parse_command_test_spec() {
+        let rockspec_content = "
+        package = 'foo'\n
+        version = 'scm-1'\n
+        source = {\n
+            url = 'https://github.com/nvim-neorocks/rocks.nvim/archive/1.0.0/rocks.nvim.zip',\n
+        }\n
+        test = {\n
+            type = 'command',\n
+            command = 'make check',\n
+            flags = { '--verbose' },\n
+        }\n
+        ";
+        let rockspec = Rockspec::new(rockspec_content).unwrap();
+        match rockspec.test.current_platform() {
+            TestSpec::Command(spec) => {
+                assert_eq!(spec.command(), "make check");
+                assert_eq!(spec.flags(), ["--verbose".to_string()]);
+            }
+            test_spec => panic!("expected a command test spec, got {test_spec:?}"),
+        }
+    }
+
+    #[tokio::test]
+    pub async fn rockspec_format_mismatches() {
+        let rockspec_content = "
+        rockspec_format = '1.0'\n
+        package = 'foo'\n
+        version = '1.0.0-1'\n
+        description = { labels = { 'neovim' } }\n
+        source = {\n
+            url = 'https://github.com/nvim-neorocks/rocks.nvim/archive/1.0.0/rocks.nvim.zip',\n
+        }\n
+        test_dependencies = { 'busted' }\n
+        ";
+        let rockspec = Rockspec::new(rockspec_content).unwrap();
+        assert_eq!(
+            rockspec.format_mismatches(),
+            vec![
+                RockspecFormatMismatch {
+                    feature: "test_dependencies",
+                    required: RockspecFormat::_3_0,
+                    declared: RockspecFormat::_1_0,
+                },
+                RockspecFormatMismatch {
+                    feature: "description.labels",
+                    required: RockspecFormat::_3_0,
+                    declared: RockspecFormat::_1_0,
+                },
+            ]
+        );
+        assert_eq!(
+            rockspec.format_mismatches()[0].to_string(),
+            "'test_dependencies' requires rockspec_format = \"3.0\", but \"1.0\" is declared"
+        );
+
+        let rockspec = Rockspec::new(&rockspec_content.replace("'1.0'", "'3.0'")).unwrap();
+        assert!(rockspec.format_mismatches().is_empty());
+        let rockspec =
+            Rockspec::new(&rockspec_content.replace("rockspec_format = '1.0'", "")).unwrap();
+        assert!(rockspec.format_mismatches().is_empty());
+    }
+
+    #[tokio::test]
+    pub async fn dependencies_for_other_platforms() {
+        let rockspec_content = "
+        package = 'foo'\n
+        version = '1.0.0-1'\n
+        source = {\n
+            url = 'https://github.com/nvim-neorocks/rocks.nvim/archive/1.0.0/rocks.nvim.zip',\n
+        }\n
+        dependencies = {\n
+          'neorg ~> 6',\n
+          platforms = {\n
+            windows = {\n
+              'luafilesystem',\n
+            },\n
+            unix = {\n
+              'luaposix',\n
+            },\n
+            linux = {\n
+              'neorg = 5.0.0',\n
+            },\n
+          },\n
+        }\n
+        ";
+        let rockspec = Rockspec::new(rockspec_content).unwrap();
+        let dependency_names = |platform| {
+            rockspec
+                .dependencies
+                .for_platform(&platform)
+                .iter()
+                .map(|dep| dep.name().to_string())
+                .sorted()
+                .collect_vec()
+        };
+        assert_eq!(
+            dependency_names(PlatformIdentifier::Windows),
+            vec!["luafilesystem", "neorg"]
+        );
+        assert_eq!(
+            dependency_names(PlatformIdentifier::MacOSX),
+            vec!["luaposix", "neorg"]
+        );
+        assert_eq!(
+            dependency_names(PlatformIdentifier::Linux),
+            vec!["luaposix", "neorg"]
+        );
+        assert_eq!(
+            dependency_names(PlatformIdentifier::Unknown("haiku".into())),
+            vec!["neorg"]
+        );
+        let neorg_5 = PackageSpec::parse("neorg".into(), "5.0.0".into()).unwrap();
+        let neorg_6 = PackageSpec::parse("neorg".into(), "6.1.0".into()).unwrap();
+        let linux_dependencies = rockspec
+            .dependencies
+            .for_platform(&PlatformIdentifier::Linux);
+        assert!(linux_dependencies.iter().any(|dep| dep.matches(&neorg_5)));
+        assert!(!linux_dependencies.iter().any(|dep| dep.matches(&neorg_6)));
+        let macos_dependencies = rockspec
+            .dependencies
+            .for_platform(&PlatformIdentifier::MacOSX);
+        assert!(macos_dependencies.iter().any(|dep| dep.matches(&neorg_6)));
+    }
 }
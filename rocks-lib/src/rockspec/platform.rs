@@ -1,4 +1,4 @@
-use itertools::Itertools;
+use itertools::{Either, Itertools};
 use mlua::{FromLua, Lua, LuaSerdeExt as _, Value};
 use std::{cmp::Ordering, collections::HashMap, convert::Infallible, marker::PhantomData};
 use strum::IntoEnumIterator;
@@ -7,7 +7,7 @@ use thiserror::Error;
 
 use serde::{
     de::{self, DeserializeOwned},
-    Deserialize, Deserializer,
+    Deserialize, Deserializer, Serialize,
 };
 use serde_enum_str::{Deserialize_enum_str, Serialize_enum_str};
 
@@ -52,6 +52,8 @@ impl PartialOrd for PlatformIdentifier {
 }
 
 /// Retrieves the target compilation platform and returns it as an identifier.
+/// Use [`Config::platform`](crate::config::Config::platform) to select the per-platform
+/// entries of rockspecs, as it can be overridden.
 pub fn get_platform() -> PlatformIdentifier {
     if cfg!(target_os = "linux") {
         PlatformIdentifier::Linux
@@ -100,7 +102,7 @@ impl PlatformIdentifier {
     }
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Serialize)]
 pub struct PlatformSupport {
     /// Do not match this platform
     platform_map: HashMap<PlatformIdentifier, bool>,
@@ -272,7 +274,7 @@ pub trait FromPlatformOverridable<T: PlatformOverridable, G: FromPlatformOverrid
 }
 
 /// Data that that can vary per platform
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Serialize)]
 pub struct PerPlatform<T> {
     /// The base data, applicable if no platform is specified
     pub default: T,
@@ -281,7 +283,11 @@ pub struct PerPlatform<T> {
 }
 
 impl<T> PerPlatform<T> {
-    pub fn get(&self, platform: &PlatformIdentifier) -> &T {
+    /// The value for `platform`, which need not be the platform rocks is running on,
+    /// e.g. to inspect the Windows dependencies of a rock on Linux.
+    /// If there is no override for `platform`, the override for the most specific platform
+    /// it extends is used (e.g. `unix` for `linux`), falling back to the default.
+    pub fn for_platform(&self, platform: &PlatformIdentifier) -> &T {
         self.per_platform.get(platform).unwrap_or(
             platform
                 .get_subsets()
@@ -296,8 +302,9 @@ impl<T> PerPlatform<T> {
         )
     }
 
+    /// The value for the platform rocks is running on, ignoring [`Config::platform`](crate::config::Config::platform).
     pub fn current_platform(&self) -> &T {
-        self.get(&get_platform())
+        self.for_platform(&get_platform())
     }
 }
 
@@ -321,7 +328,7 @@ where
     fn from_lua(value: Value, lua: &Lua) -> mlua::Result<Self> {
         match &value {
             list @ Value::Table(tbl) => {
-                let mut per_platform = match tbl.get("platforms")? {
+                let per_platform = match tbl.get("platforms")? {
                     val @ Value::Table(_) => Ok(lua.from_value(val)?),
                     Value::Nil => Ok(HashMap::default()),
                     val => Err(mlua::Error::DeserializeError(format!(
@@ -331,11 +338,12 @@ where
                 }?;
                 let _ = tbl.raw_remove("platforms");
                 let default = lua.from_value(list.to_owned())?;
-                apply_per_platform_overrides(&mut per_platform, &default).map_err(
-                    |err: <T as PartialOverride>::Err| {
-                        mlua::Error::DeserializeError(err.to_string())
-                    },
-                )?;
+                let to_lua_err = |err: <T as PartialOverride>::Err| {
+                    mlua::Error::DeserializeError(err.to_string())
+                };
+                let mut per_platform =
+                    expand_negated_platforms(per_platform).map_err(to_lua_err)?;
+                apply_per_platform_overrides(&mut per_platform, &default).map_err(to_lua_err)?;
                 Ok(PerPlatform {
                     default,
                     per_platform,
@@ -389,6 +397,49 @@ where
     }
 }
 
+/// Resolve the keys of a `platforms` table to platform identifiers.
+/// A negated key, e.g. `!macosx`, applies its overrides to all platforms except the
+/// negated platform, the platforms it extends and its extensions,
+/// so that the negated platform does not inherit them, e.g. from `unix`.
+/// Where both apply, overrides for a platform take precedence over negated overrides.
+fn expand_negated_platforms<T>(
+    per_platform: HashMap<String, T>,
+) -> Result<HashMap<PlatformIdentifier, T>, T::Err>
+where
+    T: PartialOverride,
+    T: Clone,
+{
+    let (negated, positive): (Vec<_>, Vec<_>) =
+        per_platform
+            .into_iter()
+            .partition_map(|(platform, overrides)| match platform.strip_prefix('!') {
+                Some(platform) => Either::Left((parse_platform(platform), overrides)),
+                None => Either::Right((parse_platform(&platform), overrides)),
+            });
+    let mut result: HashMap<PlatformIdentifier, T> = positive.into_iter().collect();
+    for (excluded, overrides) in negated {
+        for platform in PlatformIdentifier::iter().filter(|platform| {
+            !matches!(platform, PlatformIdentifier::Unknown(_))
+                && *platform != excluded
+                && !platform.is_subset_of(&excluded)
+                && !platform.is_extension_of(&excluded)
+        }) {
+            let overridden = match result.get(&platform) {
+                Some(platform_overrides) => overrides.apply_overrides(platform_overrides)?,
+                None => overrides.clone(),
+            };
+            result.insert(platform, overridden);
+        }
+    }
+    Ok(result)
+}
+
+fn parse_platform(platform: &str) -> PlatformIdentifier {
+    platform
+        .parse()
+        .unwrap_or_else(|_| PlatformIdentifier::Unknown(platform.to_string()))
+}
+
 fn apply_per_platform_overrides<T>(
     per_platform: &mut HashMap<PlatformIdentifier, T>,
     base: &T,
@@ -405,12 +456,12 @@ where
         per_platform.insert(platform, overridden);
     }
     for (platform, overrides) in per_platform_raw {
-        // Add extended platform dependencies (without base deps) for each platform
+        // Add extended platform dependencies for each platform
         for extended_platform in &platform.get_extended_platforms() {
             let extended_overrides = per_platform
                 .get(extended_platform)
                 .cloned()
-                .unwrap_or_default();
+                .unwrap_or_else(|| base.clone());
             per_platform.insert(
                 extended_platform.to_owned(),
                 extended_overrides.apply_overrides(&overrides)?,
@@ -489,11 +540,11 @@ mod tests {
             .into_iter()
             .collect(),
         };
-        assert_eq!(*foo.get(&PlatformIdentifier::MacOSX), "unix");
-        assert_eq!(*foo.get(&PlatformIdentifier::Linux), "linux");
-        assert_eq!(*foo.get(&PlatformIdentifier::FreeBSD), "freebsd");
-        assert_eq!(*foo.get(&PlatformIdentifier::Cygwin), "cygwin");
-        assert_eq!(*foo.get(&PlatformIdentifier::Windows), "default");
+        assert_eq!(*foo.for_platform(&PlatformIdentifier::MacOSX), "unix");
+        assert_eq!(*foo.for_platform(&PlatformIdentifier::Linux), "linux");
+        assert_eq!(*foo.for_platform(&PlatformIdentifier::FreeBSD), "freebsd");
+        assert_eq!(*foo.for_platform(&PlatformIdentifier::Cygwin), "cygwin");
+        assert_eq!(*foo.for_platform(&PlatformIdentifier::Windows), "default");
     }
 
     #[tokio::test]
@@ -1,16 +1,17 @@
 use git_url_parse::{GitUrl, GitUrlParseError};
 use mlua::{FromLua, Lua, Value};
 use reqwest::Url;
-use serde::{de, Deserialize, Deserializer};
+use serde::{de, Deserialize, Deserializer, Serialize};
 use ssri::Integrity;
 use std::{borrow::Cow, convert::Infallible, fs, io, path::PathBuf, str::FromStr};
 use thiserror::Error;
 
 use super::{
-    FromPlatformOverridable, PartialOverride, PerPlatform, PerPlatformWrapper, PlatformOverridable,
+    serialize_display, FromPlatformOverridable, PartialOverride, PerPlatform, PerPlatformWrapper,
+    PlatformOverridable,
 };
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Serialize)]
 pub struct RockSource {
     pub source_spec: RockSourceSpec,
     pub integrity: Option<Integrity>,
@@ -84,12 +85,13 @@ impl FromLua for PerPlatform<RockSource> {
     }
 }
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone, Serialize)]
+#[serde(rename_all = "lowercase")]
 pub enum RockSourceSpec {
     Cvs(CvsSource),
     Git(GitSource),
     File(PathBuf),
-    Url(Url),
+    Url(#[serde(serialize_with = "serialize_display")] Url),
     Mercurial(MercurialSource),
     Sscm(SscmSource),
     Svn(SvnSource),
@@ -125,31 +127,58 @@ impl RockSourceSpec {
     }
 }
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone, Serialize)]
 pub struct CvsSource {
     pub url: String,
     pub module: String,
 }
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone, Serialize)]
 pub struct GitSource {
+    #[serde(serialize_with = "serialize_display")]
     pub url: GitUrl,
     pub checkout_ref: Option<String>,
 }
 
-#[derive(Debug, PartialEq, Clone)]
+impl FromStr for GitSource {
+    type Err = SourceUrlError;
+
+    /// Parses a git source URL, e.g. `git+https://github.com/user/repo`.
+    fn from_str(str: &str) -> Result<Self, Self::Err> {
+        match SourceUrl::from_str(str)? {
+            SourceUrl::Git(url) => Ok(Self {
+                url,
+                checkout_ref: None,
+            }),
+            _ => Err(SourceUrlError::Unsupported(str.to_string())),
+        }
+    }
+}
+
+impl GitSource {
+    /// Parses the URL of a git repository, which, unlike a git source URL,
+    /// doesn't need a `git+` prefix, e.g. `https://github.com/user/repo`.
+    pub fn from_repository_url(url: &str) -> Result<Self, SourceUrlError> {
+        Ok(Self {
+            url: url.trim_start_matches("git+").parse()?,
+            checkout_ref: None,
+        })
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize)]
 pub struct MercurialSource {
     pub url: String,
     pub checkout_ref: Option<String>,
 }
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone, Serialize)]
 pub struct SscmSource {
     pub url: String,
     pub module: String,
 }
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone, Serialize)]
 pub struct SvnSource {
     pub url: String,
     pub module: Option<String>,
@@ -264,6 +293,7 @@ impl FromStr for SourceUrl {
             {
                 Ok(Self::Git(s.trim_start_matches("git+").parse()?))
             }
+            s if s.starts_with("ssh://") || is_scp_like(s) => Ok(Self::Git(s.parse()?)),
             s if starts_with_any(s, ["https://", "http://", "ftp://"].into()) => {
                 Ok(Self::Url(s.parse().map_err(SourceUrlError::Url)?))
             }
@@ -319,6 +349,14 @@ fn starts_with_any(str: &str, prefixes: Vec<&str>) -> bool {
     prefixes.iter().any(|&prefix| str.starts_with(prefix))
 }
 
+/// Whether `str` is an scp-like git URL, e.g. `git@github.com:user/repo.git`.
+fn is_scp_like(str: &str) -> bool {
+    !str.contains("://")
+        && str.split_once(':').is_some_and(|(user_and_host, path)| {
+            user_and_host.contains('@') && !user_and_host.contains('/') && !path.is_empty()
+        })
+}
+
 #[cfg(test)]
 mod tests {
 
@@ -370,4 +408,44 @@ mod tests {
         let url: SourceUrl = "svn://bar".parse().unwrap();
         assert_eq!(url, SourceUrl::Svn("svn://bar".into()));
     }
+
+    #[tokio::test]
+    async fn parse_ssh_git_source_url() {
+        for (url, host, user, path) in [
+            (
+                "git@github.com:user/repo.git",
+                "github.com",
+                "git",
+                "user/repo.git",
+            ),
+            (
+                "ssh://git@github.com/user/repo.git",
+                "github.com",
+                "git",
+                "user/repo.git",
+            ),
+            (
+                "git+ssh://git@example.com/user/repo",
+                "example.com",
+                "git",
+                "user/repo",
+            ),
+        ] {
+            match url.parse::<SourceUrl>().unwrap() {
+                SourceUrl::Git(git_url) => {
+                    assert_eq!(git_url.scheme, git_url_parse::Scheme::Ssh, "{url}");
+                    assert_eq!(git_url.host.as_deref(), Some(host), "{url}");
+                    assert_eq!(git_url.user.as_deref(), Some(user), "{url}");
+                    assert_eq!(git_url.path.trim_start_matches('/'), path, "{url}");
+                }
+                source_url => panic!("expected a git URL for {url}, got {source_url:?}"),
+            }
+        }
+        // scp-like URLs are passed on to git as-is
+        let git_source: GitSource = "git@github.com:user/repo.git".parse().unwrap();
+        assert_eq!(git_source.url.to_string(), "git@github.com:user/repo.git");
+
+        let _err = SourceUrl::from_str("user/repo").unwrap_err();
+        let _err = SourceUrl::from_str("C:/Users/repo").unwrap_err();
+    }
 }
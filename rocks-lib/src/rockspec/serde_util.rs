@@ -1,5 +1,7 @@
+use std::fmt::Display;
+
 use itertools::Itertools as _;
-use serde::{de, Deserialize, Deserializer};
+use serde::{de, Deserialize, Deserializer, Serializer};
 use thiserror::Error;
 
 #[derive(Hash, Debug, Eq, PartialEq, Clone)]
@@ -27,6 +29,16 @@ impl<'de> Deserialize<'de> for LuaTableKey {
     }
 }
 
+/// Serialize a value as its [`Display`] representation,
+/// for types that don't implement [`serde::Serialize`], like URLs.
+pub(crate) fn serialize_display<S, T>(value: &T, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+    T: Display,
+{
+    serializer.collect_str(value)
+}
+
 /// Deserialize a json value into a Vec<T>, treating empty json objects as empty lists
 /// This is needed to be able to deserialise Lua tables.
 pub fn deserialize_vec_from_lua<'de, D, T>(deserializer: D) -> std::result::Result<Vec<T>, D::Error>
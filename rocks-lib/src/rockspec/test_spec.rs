@@ -3,13 +3,14 @@ use mlua::FromLua;
 use std::{convert::Infallible, path::PathBuf};
 use thiserror::Error;
 
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
 use super::{
     FromPlatformOverridable, PartialOverride, PerPlatform, PerPlatformWrapper, PlatformOverridable,
 };
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
 pub enum TestSpec {
     AutoDetect,
     Busted(BustedTestSpec),
@@ -67,18 +68,30 @@ impl FromLua for PerPlatform<TestSpec> {
     }
 }
 
-#[derive(Clone, Debug, PartialEq, Default)]
+#[derive(Clone, Debug, PartialEq, Default, Serialize)]
 pub struct BustedTestSpec {
     flags: Vec<String>,
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Serialize)]
 pub struct CommandTestSpec {
     command: String,
     flags: Vec<String>,
 }
 
-#[derive(Clone, Debug, PartialEq)]
+impl CommandTestSpec {
+    /// The command that runs the tests. It is run by the shell.
+    pub fn command(&self) -> &str {
+        &self.command
+    }
+
+    /// Arguments to pass to the command.
+    pub fn flags(&self) -> &[String] {
+        &self.flags
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
 pub struct ScriptTestSpec {
     script: PathBuf,
     flags: Vec<String>,
@@ -0,0 +1,369 @@
+//! Checking for and installing new releases of `rocks` itself.
+//!
+//! Releases are looked up with the GitHub release API.
+//! Each release is expected to have one executable asset per target,
+//! named `rocks-<target-triple>` (with an `.exe` extension on Windows),
+//! whose SHA-256 digest is reported by the API.
+
+use std::{
+    io,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use reqwest::header::USER_AGENT;
+use semver::Version;
+use serde::{Deserialize, Serialize};
+use ssri::{Algorithm, Integrity};
+use target_lexicon::Triple;
+use thiserror::Error;
+
+use crate::{
+    config::Config,
+    hash::{self, HasIntegrity as _},
+    progress::{Progress, ProgressBar},
+};
+
+pub const RELEASES_URL: &str = "https://api.github.com/repos/nvim-neorocks/rocks/releases/latest";
+
+/// How long the result of an update check is reused before the release API is queried again.
+const CHECK_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+const STATE_FILE_NAME: &str = "self-update.json";
+
+/// Installation prefixes that are managed by a system package manager.
+const PACKAGE_MANAGER_PREFIXES: &[&str] = &["/nix/store", "/usr/bin", "/usr/lib", "/opt/homebrew"];
+
+#[derive(Error, Debug)]
+pub enum SelfUpdateError {
+    #[error("IO operation failed: {0}")]
+    Io(#[from] io::Error),
+    #[error("failed to query the rocks releases: {0}")]
+    Request(#[from] reqwest::Error),
+    #[error("invalid release version '{0}': {1}")]
+    InvalidVersion(String, semver::Error),
+    #[error("release {version} has no executable for {target}")]
+    NoAsset { version: Version, target: String },
+    #[error("the release API did not report a digest for {0}, so it cannot be verified")]
+    NoDigest(String),
+    #[error("invalid digest for {0}: {1}")]
+    InvalidDigest(String, String),
+    #[error("integrity mismatch for {name}.\nExpected: {expected},\nbut got: {actual}")]
+    IntegrityMismatch {
+        name: String,
+        expected: Integrity,
+        actual: Integrity,
+    },
+    #[error("{0} appears to be managed by a package manager. Use it to update rocks instead.")]
+    ManagedByPackageManager(PathBuf),
+}
+
+#[derive(Deserialize, Debug, Clone)]
+struct ReleaseResponse {
+    tag_name: String,
+    assets: Vec<ReleaseAsset>,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ReleaseAsset {
+    pub name: String,
+    pub browser_download_url: String,
+    /// The asset's digest, e.g. `sha256:<hex>`.
+    pub digest: Option<String>,
+}
+
+/// A published release of `rocks`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Release {
+    pub version: Version,
+    pub assets: Vec<ReleaseAsset>,
+}
+
+impl Release {
+    /// The executable for the target `rocks` is running on.
+    pub fn asset_for_host(&self) -> Result<&ReleaseAsset, SelfUpdateError> {
+        let target = Triple::host().to_string();
+        let name = format!("rocks-{}{}", target, std::env::consts::EXE_SUFFIX);
+        self.assets
+            .iter()
+            .find(|asset| asset.name == name)
+            .ok_or_else(|| SelfUpdateError::NoAsset {
+                version: self.version.clone(),
+                target,
+            })
+    }
+}
+
+/// Query the latest release from `releases_url`, usually [`RELEASES_URL`].
+pub async fn latest_release(
+    releases_url: &str,
+    config: &Config,
+) -> Result<Release, SelfUpdateError> {
+    let response: ReleaseResponse = config
+        .http_client()
+        .get(releases_url)
+        // Required by the GitHub API.
+        .header(USER_AGENT, "rocks")
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    let version = response.tag_name.trim_start_matches('v');
+    Ok(Release {
+        version: version
+            .parse()
+            .map_err(|err| SelfUpdateError::InvalidVersion(response.tag_name.clone(), err))?,
+        assets: response.assets,
+    })
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct UpdateCheckState {
+    /// Seconds since the Unix epoch.
+    checked_at: u64,
+    latest_version: Option<String>,
+    /// The latest version that a notice has been shown for.
+    notified_version: Option<String>,
+}
+
+/// Check whether a release newer than `current_version` exists,
+/// if enabled with [`Config::check_for_updates`].
+/// Returns the new version the first time it is found, so that the caller can show a notice.
+/// The release API is queried at most once a day and all errors are ignored,
+/// so that an update check never gets in the way of the command that is being run.
+pub async fn update_notice(
+    current_version: &Version,
+    releases_url: &str,
+    config: &Config,
+) -> Option<Version> {
+    if !config.check_for_updates() {
+        return None;
+    }
+    let state_file = config.cache_dir().join(STATE_FILE_NAME);
+    let mut state: UpdateCheckState = std::fs::read_to_string(&state_file)
+        .ok()
+        .and_then(|content| serde_json::from_str(&content).ok())
+        .unwrap_or_default();
+
+    let now = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    if now.saturating_sub(state.checked_at) >= CHECK_INTERVAL.as_secs() {
+        let release =
+            tokio::time::timeout(Duration::from_secs(5), latest_release(releases_url, config))
+                .await
+                .ok()?
+                .ok()?;
+        state.checked_at = now;
+        state.latest_version = Some(release.version.to_string());
+    }
+
+    let notice = state
+        .latest_version
+        .as_ref()
+        .filter(|&latest| state.notified_version.as_ref() != Some(latest))
+        .and_then(|latest| latest.parse::<Version>().ok())
+        .filter(|latest| latest > current_version);
+    if let Some(latest) = &notice {
+        state.notified_version = Some(latest.to_string());
+    }
+    if let Ok(content) = serde_json::to_string(&state) {
+        let _ = std::fs::create_dir_all(config.cache_dir());
+        let _ = std::fs::write(&state_file, content);
+    }
+    notice
+}
+
+/// Download the executable of `release` for the host target, verify its digest
+/// and replace `executable` with it.
+pub async fn install_release(
+    release: &Release,
+    executable: &Path,
+    config: &Config,
+    progress: &Progress<ProgressBar>,
+) -> Result<(), SelfUpdateError> {
+    if PACKAGE_MANAGER_PREFIXES
+        .iter()
+        .any(|prefix| executable.starts_with(prefix))
+    {
+        return Err(SelfUpdateError::ManagedByPackageManager(
+            executable.to_path_buf(),
+        ));
+    }
+
+    let asset = release.asset_for_host()?;
+    let expected = asset_integrity(asset)?;
+
+    progress.map(|p| p.set_message(format!("📥 Downloading rocks {}", release.version)));
+    let bytes = config
+        .http_client()
+        .get(&asset.browser_download_url)
+        .header(USER_AGENT, "rocks")
+        .send()
+        .await?
+        .error_for_status()?
+        .bytes()
+        .await?;
+
+    // Written next to the executable, so that it can be renamed into place.
+    let new_executable = executable.with_extension("new");
+    std::fs::write(&new_executable, &bytes)?;
+    let actual = new_executable.hash_with(&hash::algorithms(&expected))?;
+    if hash::integrity_matches(&expected, &actual).is_none() {
+        let _ = std::fs::remove_file(&new_executable);
+        return Err(SelfUpdateError::IntegrityMismatch {
+            name: asset.name.clone(),
+            expected,
+            actual,
+        });
+    }
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt as _;
+        std::fs::set_permissions(&new_executable, std::fs::Permissions::from_mode(0o755))?;
+    }
+
+    // A running executable can't be overwritten on Windows, but it can be renamed.
+    let old_executable = executable.with_extension("old");
+    std::fs::rename(executable, &old_executable)?;
+    if let Err(err) = std::fs::rename(&new_executable, executable) {
+        std::fs::rename(&old_executable, executable)?;
+        return Err(err.into());
+    }
+    let _ = std::fs::remove_file(&old_executable);
+
+    Ok(())
+}
+
+fn asset_integrity(asset: &ReleaseAsset) -> Result<Integrity, SelfUpdateError> {
+    let digest = asset
+        .digest
+        .as_ref()
+        .ok_or_else(|| SelfUpdateError::NoDigest(asset.name.clone()))?;
+    match digest.split_once(':') {
+        Some(("sha256", hex)) => Integrity::from_hex(hex, Algorithm::Sha256)
+            .map_err(|err| SelfUpdateError::InvalidDigest(asset.name.clone(), err.to_string())),
+        Some(("sha512", hex)) => Integrity::from_hex(hex, Algorithm::Sha512)
+            .map_err(|err| SelfUpdateError::InvalidDigest(asset.name.clone(), err.to_string())),
+        _ => Err(SelfUpdateError::InvalidDigest(
+            asset.name.clone(),
+            digest.clone(),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use httptest::{matchers::request, responders::json_encoded, Expectation, Server};
+    use sha2::{Digest, Sha256};
+
+    use crate::config::ConfigBuilder;
+
+    use super::*;
+
+    fn host_asset_name() -> String {
+        format!("rocks-{}{}", Triple::host(), std::env::consts::EXE_SUFFIX)
+    }
+
+    fn release_server(content: &'static [u8], digest: &str) -> Server {
+        let server = Server::run();
+        let asset_url = server.url_str("/download");
+        server.expect(
+            Expectation::matching(request::path("/latest"))
+                .times(..)
+                .respond_with(json_encoded(serde_json::json!({
+                    "tag_name": "v99.0.0",
+                    "assets": [{
+                        "name": host_asset_name(),
+                        "browser_download_url": asset_url,
+                        "digest": digest,
+                    }],
+                }))),
+        );
+        server.expect(
+            Expectation::matching(request::path("/download"))
+                .times(..)
+                .respond_with(httptest::responders::status_code(200).body(content)),
+        );
+        server
+    }
+
+    #[tokio::test]
+    async fn notify_about_new_release_once() {
+        let server = release_server(b"", "sha256:00");
+        let temp = assert_fs::TempDir::new().unwrap();
+        let releases_url = server.url_str("/latest");
+        let current_version = Version::new(0, 1, 0);
+
+        let config = ConfigBuilder::new()
+            .cache_dir(Some(temp.to_path_buf()))
+            .build()
+            .unwrap();
+        assert_eq!(
+            update_notice(&current_version, &releases_url, &config).await,
+            None
+        );
+
+        let config = ConfigBuilder::new()
+            .cache_dir(Some(temp.to_path_buf()))
+            .check_for_updates(Some(true))
+            .build()
+            .unwrap();
+        assert_eq!(
+            update_notice(&current_version, &releases_url, &config).await,
+            Some(Version::new(99, 0, 0))
+        );
+        assert_eq!(
+            update_notice(&current_version, &releases_url, &config).await,
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn install_verified_release() {
+        let content = b"#!/bin/sh\necho new";
+        let server = release_server(
+            content,
+            &format!("sha256:{}", hex::encode(Sha256::digest(content))),
+        );
+        let temp = assert_fs::TempDir::new().unwrap();
+        let executable = temp.join("rocks");
+        std::fs::write(&executable, "old").unwrap();
+        let config = ConfigBuilder::new().build().unwrap();
+
+        let release = latest_release(&server.url_str("/latest"), &config)
+            .await
+            .unwrap();
+        assert_eq!(release.version, Version::new(99, 0, 0));
+        install_release(&release, &executable, &config, &Progress::NoProgress)
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read(&executable).unwrap(), content);
+        assert!(!executable.with_extension("old").exists());
+    }
+
+    #[tokio::test]
+    async fn reject_release_with_mismatched_digest() {
+        let server = release_server(
+            b"tampered",
+            &format!("sha256:{}", hex::encode(Sha256::digest(b"original"))),
+        );
+        let temp = assert_fs::TempDir::new().unwrap();
+        let executable = temp.join("rocks");
+        std::fs::write(&executable, "old").unwrap();
+        let config = ConfigBuilder::new().build().unwrap();
+
+        let release = latest_release(&server.url_str("/latest"), &config)
+            .await
+            .unwrap();
+        assert!(matches!(
+            install_release(&release, &executable, &config, &Progress::NoProgress).await,
+            Err(SelfUpdateError::IntegrityMismatch { .. })
+        ));
+        assert_eq!(std::fs::read_to_string(&executable).unwrap(), "old");
+        assert!(!executable.with_extension("new").exists());
+    }
+}
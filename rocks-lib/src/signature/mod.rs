@@ -0,0 +1,278 @@
+use std::{io, path::PathBuf};
+
+use gpgme::{Context, Protocol};
+use itertools::Itertools;
+use thiserror::Error;
+
+use crate::{
+    config::{
+        env_vars::{self, EnvVarError},
+        Config,
+    },
+    manifest::file_url_path,
+};
+
+#[derive(Error, Debug)]
+pub enum SignatureError {
+    #[error("IO operation failed: {0}")]
+    Io(#[from] io::Error),
+    #[error("GPG operation failed: {0}")]
+    Gpg(#[from] gpgme::Error),
+    #[error("failed to fetch signature for {0}: {1}")]
+    Request(String, reqwest::Error),
+    #[error("no signature found for {0}, but signatures are required")]
+    SignatureMissing(String),
+    #[error("invalid signature for {0}")]
+    InvalidSignature(String),
+    #[error("signature for {url} was made by untrusted key {fingerprint}")]
+    UntrustedKey { url: String, fingerprint: String },
+    #[error(transparent)]
+    EnvVar(#[from] EnvVarError),
+}
+
+/// A public key in the `rocks` keyring.
+#[derive(Debug, Clone)]
+pub struct TrustedKey {
+    pub fingerprint: String,
+    pub user_ids: Vec<String>,
+}
+
+/// The keyring containing the public keys that are trusted to sign rockspecs and sources.
+/// It is kept separate from the user's own GPG keyring, in the `rocks` data directory.
+pub struct Keyring {
+    home_dir: PathBuf,
+}
+
+impl Keyring {
+    pub fn new(config: &Config) -> io::Result<Self> {
+        let home_dir = config.data_dir().join("keyring");
+        std::fs::create_dir_all(&home_dir)?;
+
+        // GPG warns about home directories that are accessible by other users.
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt as _;
+            std::fs::set_permissions(&home_dir, std::fs::Permissions::from_mode(0o700))?;
+        }
+
+        Ok(Self { home_dir })
+    }
+
+    fn context(&self) -> Result<Context, gpgme::Error> {
+        let mut ctx = Context::from_protocol(Protocol::OpenPgp)?;
+        ctx.set_engine_home_dir(self.home_dir.to_string_lossy().into_owned())?;
+        Ok(ctx)
+    }
+
+    /// Import (armored or binary) public keys into the keyring.
+    /// Returns the fingerprints of the imported keys.
+    pub fn import(&self, keys: &[u8]) -> Result<Vec<String>, SignatureError> {
+        let mut ctx = self.context()?;
+        let result = ctx.import(keys)?;
+        Ok(result
+            .imports()
+            .filter_map(|import| import.fingerprint().ok().map(String::from))
+            .unique()
+            .collect())
+    }
+
+    /// Whether the keyring contains no keys.
+    pub fn is_empty(&self) -> Result<bool, SignatureError> {
+        // Avoid spawning GPG if no keys have ever been imported.
+        if !["pubring.kbx", "pubring.gpg"]
+            .iter()
+            .any(|file| self.home_dir.join(file).is_file())
+        {
+            return Ok(true);
+        }
+        Ok(self.list()?.is_empty())
+    }
+
+    /// List the keys in the keyring.
+    pub fn list(&self) -> Result<Vec<TrustedKey>, SignatureError> {
+        let mut ctx = self.context()?;
+        let keys = ctx
+            .keys()?
+            .filter_map(|key| key.ok())
+            .filter_map(|key| {
+                Some(TrustedKey {
+                    fingerprint: key.fingerprint().ok()?.to_string(),
+                    user_ids: key
+                        .user_ids()
+                        .filter_map(|user_id| user_id.id().ok().map(String::from))
+                        .collect(),
+                })
+            })
+            .collect();
+        Ok(keys)
+    }
+
+    /// Verify a detached signature of `content`.
+    /// The signature must have been made by a key in the keyring and, if `trusted_keys` is
+    /// not empty, the key's fingerprint (or long key ID) must be one of `trusted_keys`.
+    pub fn verify(
+        &self,
+        url: &str,
+        content: &[u8],
+        signature: &[u8],
+        trusted_keys: &[String],
+    ) -> Result<(), SignatureError> {
+        let mut ctx = self.context()?;
+        let result = ctx.verify_detached(signature, content)?;
+        let valid_fingerprints = result
+            .signatures()
+            .filter(|signature| signature.status().is_ok())
+            .filter_map(|signature| signature.fingerprint().ok().map(str::to_uppercase))
+            .collect_vec();
+
+        let is_trusted = |fingerprint: &String| {
+            trusted_keys.is_empty()
+                || trusted_keys
+                    .iter()
+                    .filter_map(|trusted| normalize_key_id(trusted))
+                    .any(|trusted| {
+                        *fingerprint == trusted
+                            || (trusted.len() == LONG_KEY_ID_LEN && fingerprint.ends_with(&trusted))
+                    })
+        };
+        if valid_fingerprints.iter().any(is_trusted) {
+            return Ok(());
+        }
+
+        match valid_fingerprints.into_iter().next() {
+            Some(fingerprint) => Err(SignatureError::UntrustedKey {
+                url: url.to_string(),
+                fingerprint,
+            }),
+            None => Err(SignatureError::InvalidSignature(url.to_string())),
+        }
+    }
+}
+
+/// The number of hex digits of a long key ID, i.e. the last 64 bits of a fingerprint.
+const LONG_KEY_ID_LEN: usize = 16;
+
+/// Normalize a trusted key, given as a full fingerprint or a long key ID,
+/// to upper case hex digits without spaces or a `0x` prefix.
+/// Returns `None` if `key` is neither, e.g. if it is a short key ID,
+/// which is too easy to collide with to be trusted.
+pub(crate) fn normalize_key_id(key: &str) -> Option<String> {
+    let key = key.split_whitespace().collect::<String>().to_uppercase();
+    let key = key.strip_prefix("0X").unwrap_or(&key);
+    // v4 fingerprints have 40 hex digits, v5 fingerprints have 64.
+    let is_valid = matches!(key.len(), LONG_KEY_ID_LEN | 40 | 64)
+        && key.chars().all(|char| char.is_ascii_hexdigit());
+    is_valid.then(|| key.to_string())
+}
+
+/// Verify the detached signature of a file that has been downloaded from `url`.
+/// Signatures are expected to be hosted alongside the file, at `<url>.asc`.
+/// Verification is skipped if there are no keys in the keyring and signatures are not required.
+pub(crate) async fn verify_download(
+    url: &str,
+    content: &[u8],
+    config: &Config,
+) -> Result<(), SignatureError> {
+    let keyring = Keyring::new(config)?;
+    if !config.require_signatures() && keyring.is_empty()? {
+        return Ok(());
+    }
+
+    let signature_url = format!("{}.asc", url);
+    let signature_url = config.rewrite_url(&signature_url).unwrap_or(signature_url);
+    let signature = match fetch_signature(url, &signature_url, config).await? {
+        Some(signature) => signature,
+        None if config.require_signatures() => {
+            return Err(SignatureError::SignatureMissing(url.to_string()))
+        }
+        None => return Ok(()),
+    };
+
+    keyring.verify(url, content, &signature, config.trusted_keys())
+}
+
+/// Fetch the signature of `url` from `signature_url`, which may point to a local server.
+/// Returns `None` if there is no signature.
+async fn fetch_signature(
+    url: &str,
+    signature_url: &str,
+    config: &Config,
+) -> Result<Option<Vec<u8>>, SignatureError> {
+    let request_url = env_vars::expand_env_vars(signature_url)?;
+    if let Some(path) = file_url_path(&request_url) {
+        return match tokio::fs::read(path).await {
+            Ok(signature) => Ok(Some(signature)),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        };
+    }
+    let request_err =
+        |err| SignatureError::Request(url.to_string(), env_vars::redact_error(err, signature_url));
+    let response = config
+        .http_client()
+        .get(request_url)
+        .send()
+        .await
+        .map_err(request_err)?;
+    if !response.status().is_success() {
+        return Ok(None);
+    }
+    Ok(Some(response.bytes().await.map_err(request_err)?.to_vec()))
+}
+
+#[cfg(test)]
+mod tests {
+    use httptest::{matchers::request, responders::status_code, Expectation, Server};
+
+    use crate::config::ConfigBuilder;
+
+    use super::*;
+
+    #[test]
+    fn normalize_trusted_keys() {
+        assert_eq!(
+            normalize_key_id("0x1234567890abcdef"),
+            Some("1234567890ABCDEF".into())
+        );
+        assert_eq!(
+            normalize_key_id("1234 5678 90AB CDEF 1234  5678 90AB CDEF 1234 5678"),
+            Some("1234567890ABCDEF1234567890ABCDEF12345678".into())
+        );
+        assert_eq!(normalize_key_id(""), None);
+        assert_eq!(normalize_key_id("90ABCDEF"), None);
+        assert_eq!(normalize_key_id("1234567890ABCDEF1234"), None);
+        assert_eq!(normalize_key_id("1234567890ABCDEG"), None);
+    }
+
+    #[tokio::test]
+    async fn skip_verification_without_keys() {
+        let data_dir = assert_fs::TempDir::new().unwrap();
+        let config = ConfigBuilder::new()
+            .data_dir(Some(data_dir.to_path_buf()))
+            .build()
+            .unwrap();
+        verify_download("http://localhost:0/foo.rockspec", b"content", &config)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn require_signatures_fails_closed() {
+        let server = Server::run();
+        server.expect(
+            Expectation::matching(request::path("/foo.rockspec.asc"))
+                .respond_with(status_code(404)),
+        );
+        let data_dir = assert_fs::TempDir::new().unwrap();
+        let config = ConfigBuilder::new()
+            .data_dir(Some(data_dir.to_path_buf()))
+            .require_signatures(Some(true))
+            .build()
+            .unwrap();
+        let url = server.url_str("/foo.rockspec");
+        assert!(matches!(
+            verify_download(&url, b"content", &config).await,
+            Err(SignatureError::SignatureMissing(_))
+        ));
+    }
+}
@@ -0,0 +1,121 @@
+//! Named environments, which are isolated trees with their own lockfiles.
+//!
+//! Each environment lives in `<tree>/envs/<name>`, where `<tree>` is the
+//! project's `.rocks` directory or the global tree.
+//! The `default` environment is the tree itself.
+//! The currently selected environment is stored in `<tree>/env`.
+
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+use itertools::Itertools;
+use thiserror::Error;
+
+use crate::config::Config;
+
+/// The name of the environment that corresponds to the base tree.
+pub const DEFAULT_ENVIRONMENT: &str = "default";
+
+#[derive(Error, Debug)]
+pub enum EnvironmentError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error("invalid environment name '{0}'. Environment names may only contain alphanumeric characters, '-' and '_'")]
+    InvalidName(String),
+}
+
+fn validate_name(name: &str) -> Result<(), EnvironmentError> {
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_alphanumeric() || matches!(c, '-' | '_'))
+    {
+        Err(EnvironmentError::InvalidName(name.to_string()))
+    } else {
+        Ok(())
+    }
+}
+
+/// The tree root of the environment `name`, relative to the base tree.
+pub(crate) fn environment_tree(base_tree: &Path, name: &str) -> Result<PathBuf, EnvironmentError> {
+    validate_name(name)?;
+    if name == DEFAULT_ENVIRONMENT {
+        Ok(base_tree.to_path_buf())
+    } else {
+        Ok(base_tree.join("envs").join(name))
+    }
+}
+
+/// The environment that has been selected with [`use_environment`], if any.
+pub(crate) fn selected_environment(base_tree: &Path) -> io::Result<Option<String>> {
+    match std::fs::read_to_string(base_tree.join("env")) {
+        Ok(name) if !name.trim().is_empty() => Ok(Some(name.trim().to_string())),
+        Ok(_) => Ok(None),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+/// List the names of all environments, including the default environment.
+pub fn list_environments(config: &Config) -> io::Result<Vec<String>> {
+    let envs_dir = config.base_tree().join("envs");
+    let environments = if envs_dir.is_dir() {
+        std::fs::read_dir(envs_dir)?
+            .filter_map(Result::ok)
+            .filter(|entry| entry.path().is_dir())
+            .map(|entry| entry.file_name().to_string_lossy().to_string())
+            .sorted()
+            .collect_vec()
+    } else {
+        Vec::new()
+    };
+    Ok(std::iter::once(DEFAULT_ENVIRONMENT.to_string())
+        .chain(environments)
+        .collect())
+}
+
+/// Select the environment to use when no `--tree-name` is provided,
+/// creating it if it doesn't exist.
+pub fn use_environment(config: &Config, name: &str) -> Result<PathBuf, EnvironmentError> {
+    let tree = environment_tree(config.base_tree(), name)?;
+    std::fs::create_dir_all(&tree)?;
+    std::fs::write(config.base_tree().join("env"), name)?;
+    Ok(tree)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::ConfigBuilder;
+
+    use super::*;
+
+    #[test]
+    fn environments() {
+        let base_tree = assert_fs::TempDir::new().unwrap();
+        let config = ConfigBuilder::new()
+            .tree(Some(base_tree.to_path_buf()))
+            .build()
+            .unwrap();
+        assert_eq!(list_environments(&config).unwrap(), vec!["default"]);
+
+        let dev_tree = use_environment(&config, "dev").unwrap();
+        assert_eq!(dev_tree, base_tree.join("envs").join("dev"));
+        assert_eq!(list_environments(&config).unwrap(), vec!["default", "dev"]);
+        assert_eq!(
+            selected_environment(base_tree.path()).unwrap(),
+            Some("dev".into())
+        );
+
+        let config = ConfigBuilder::new()
+            .tree(Some(base_tree.to_path_buf()))
+            .tree_name(Some("ci".into()))
+            .build()
+            .unwrap();
+        assert_eq!(config.tree(), &base_tree.join("envs").join("ci"));
+        assert_eq!(config.tree_name(), "ci");
+
+        assert!(use_environment(&config, "../escape").is_err());
+    }
+}
@@ -0,0 +1,202 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io,
+    path::{Path, PathBuf},
+    sync::{Arc, LazyLock, Mutex, Weak},
+    time::{Duration, Instant},
+};
+
+use fs2::FileExt as _;
+use thiserror::Error;
+use tokio::runtime::RuntimeFlavor;
+
+/// How long to wait for another process to release a tree's lock by default.
+pub const DEFAULT_LOCK_TIMEOUT: Duration = Duration::from_secs(60);
+
+const LOCK_FILE_NAME: &str = "tree.lock";
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// The locks held by this process, so that a tree can be locked more than once
+/// (e.g. by an install that reads the lockfile while building a dependency),
+/// without the process blocking itself.
+static HELD_LOCKS: LazyLock<Mutex<HashMap<PathBuf, Weak<File>>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+#[derive(Error, Debug)]
+#[error("another rocks process holds the tree lock at {} (waited {}s)", .lock_file.display(), .timeout.as_secs())]
+pub struct TreeLockTimeout {
+    pub lock_file: PathBuf,
+    pub timeout: Duration,
+}
+
+/// An advisory lock on a tree, which serialises modifications of the tree's lockfile
+/// across `rocks` processes.
+/// The lock is released once all clones of it have been dropped.
+#[derive(Clone, Debug)]
+pub struct TreeLock {
+    _file: Arc<File>,
+}
+
+impl TreeLock {
+    /// Lock the tree at `root`, waiting up to `timeout` for other processes to release it.
+    /// Fails with an [`io::ErrorKind::TimedOut`] error wrapping a [`TreeLockTimeout`]
+    /// if the lock could not be acquired in time.
+    pub fn acquire(root: &Path, timeout: Duration) -> io::Result<Self> {
+        std::fs::create_dir_all(root)?;
+        let lock_file = root.join(LOCK_FILE_NAME);
+        let start = Instant::now();
+        let mut handle = None;
+        loop {
+            // Only held while polling, so that waiting for another process
+            // doesn't block other threads that lock a tree.
+            let mut held_locks = HELD_LOCKS.lock().unwrap_or_else(|err| err.into_inner());
+            if let Some(file) = held_locks.get(&lock_file).and_then(Weak::upgrade) {
+                return Ok(Self { _file: file });
+            }
+
+            let file = match handle.take() {
+                Some(file) => file,
+                None => File::options()
+                    .create(true)
+                    .truncate(false)
+                    .write(true)
+                    .open(&lock_file)?,
+            };
+            match file.try_lock_exclusive() {
+                Ok(()) => {
+                    let file = Arc::new(file);
+                    held_locks.insert(lock_file, Arc::downgrade(&file));
+                    return Ok(Self { _file: file });
+                }
+                Err(err) if err.kind() == fs2::lock_contended_error().kind() => {
+                    if start.elapsed() >= timeout {
+                        return Err(io::Error::new(
+                            io::ErrorKind::TimedOut,
+                            TreeLockTimeout { lock_file, timeout },
+                        ));
+                    }
+                }
+                Err(err) => return Err(err),
+            }
+            drop(held_locks);
+            wait(POLL_INTERVAL);
+            handle = Some(file);
+        }
+    }
+}
+
+/// Sleep for `duration`.
+/// On a multi-threaded tokio runtime, the worker thread's other tasks are moved
+/// to another thread first, so that waiting for a lock doesn't stall them.
+fn wait(duration: Duration) {
+    match tokio::runtime::Handle::try_current() {
+        Ok(handle) if handle.runtime_flavor() == RuntimeFlavor::MultiThread => {
+            tokio::task::block_in_place(|| std::thread::sleep(duration))
+        }
+        _ => std::thread::sleep(duration),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, process::Command};
+
+    use crate::{config::LuaVersion, lockfile::test_package, tree::Tree};
+
+    use super::*;
+
+    #[test]
+    fn lock_is_reentrant_within_a_process() {
+        let root = assert_fs::TempDir::new().unwrap();
+        let lock = TreeLock::acquire(&root, Duration::ZERO).unwrap();
+        let _nested = TreeLock::acquire(&root, Duration::ZERO).unwrap();
+        drop(lock);
+
+        let other = File::open(root.join(LOCK_FILE_NAME)).unwrap();
+        assert!(other.try_lock_exclusive().is_err());
+    }
+
+    #[test]
+    fn times_out_if_another_process_holds_the_lock() {
+        let root = assert_fs::TempDir::new().unwrap();
+        std::fs::write(root.join(LOCK_FILE_NAME), "").unwrap();
+        // A separate file handle behaves like a lock held by another process.
+        let other = File::open(root.join(LOCK_FILE_NAME)).unwrap();
+        other.lock_exclusive().unwrap();
+
+        let err = TreeLock::acquire(&root, Duration::from_millis(100)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert!(err
+            .to_string()
+            .starts_with("another rocks process holds the tree lock"));
+
+        other.unlock().unwrap();
+        TreeLock::acquire(&root, Duration::from_millis(100)).unwrap();
+    }
+
+    #[test]
+    fn waiting_for_a_lock_does_not_block_other_trees() {
+        let contended = assert_fs::TempDir::new().unwrap();
+        let other_tree = assert_fs::TempDir::new().unwrap();
+        std::fs::write(contended.join(LOCK_FILE_NAME), "").unwrap();
+        let other = File::open(contended.join(LOCK_FILE_NAME)).unwrap();
+        other.lock_exclusive().unwrap();
+
+        let contended_root = contended.to_path_buf();
+        let waiter = std::thread::spawn(move || {
+            TreeLock::acquire(&contended_root, Duration::from_secs(2)).unwrap_err()
+        });
+        std::thread::sleep(Duration::from_millis(100));
+
+        let start = Instant::now();
+        TreeLock::acquire(&other_tree, Duration::ZERO).unwrap();
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert_eq!(waiter.join().unwrap().kind(), io::ErrorKind::TimedOut);
+    }
+
+    /// Spawned by [`concurrent_writers`], to add a package to the tree's lockfile
+    /// from a separate process.
+    #[test]
+    #[ignore]
+    fn lockfile_writer() {
+        let (Ok(root), Ok(name)) = (env::var("ROCKS_TEST_TREE"), env::var("ROCKS_TEST_PACKAGE"))
+        else {
+            return;
+        };
+        let tree = Tree::new(root.into(), LuaVersion::Lua51).unwrap();
+        let mut lockfile = tree.lockfile().unwrap();
+        // Make sure the writers overlap.
+        std::thread::sleep(Duration::from_millis(300));
+        lockfile.add(&test_package(&name, "1.0.0-1"));
+    }
+
+    #[test]
+    fn concurrent_writers() {
+        let root = assert_fs::TempDir::new().unwrap();
+        let writers = ["foo", "bar"]
+            .into_iter()
+            .map(|name| {
+                Command::new(env::current_exe().unwrap())
+                    .args(["--exact", "tree::lock::tests::lockfile_writer", "--ignored"])
+                    .env("ROCKS_TEST_TREE", root.path())
+                    .env("ROCKS_TEST_PACKAGE", name)
+                    .spawn()
+                    .unwrap()
+            })
+            .collect::<Vec<_>>();
+        for mut writer in writers {
+            assert!(writer.wait().unwrap().success());
+        }
+
+        let tree = Tree::new(root.to_path_buf(), LuaVersion::Lua51).unwrap();
+        let mut names = tree
+            .as_rock_list()
+            .unwrap()
+            .into_iter()
+            .map(|package| package.name().to_string())
+            .collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(names, vec!["bar", "foo"]);
+    }
+}
@@ -0,0 +1,225 @@
+//! The `manifest` of a tree with the [`TreeLayout::Luarocks`](super::TreeLayout::Luarocks) layout,
+//! which luarocks reads to find the installed rocks and the modules and commands they provide.
+//!
+//! Like `luarocks-admin make-manifest`, the manifest is generated from the `rock_manifest`s
+//! of the rocks in the tree, so rocks installed by luarocks are included too.
+
+use std::{collections::BTreeMap, io, path::Path, sync::Mutex};
+
+use crate::build::utils::lua_lib_extension;
+
+use super::{
+    rock_manifest::{lua_string, RockManifest},
+    Tree, ROCK_MANIFEST_FILE_NAME,
+};
+
+pub const LUAROCKS_MANIFEST_FILE_NAME: &str = "manifest";
+
+/// Rocks may be installed concurrently, so writing the manifest is serialized,
+/// to make sure that the last manifest that is written includes all installed rocks.
+static MANIFEST_LOCK: Mutex<()> = Mutex::new(());
+
+/// An installed rock, as listed in the manifest.
+#[derive(Debug, Default)]
+struct InstalledRock {
+    /// Module names, mapped to their paths relative to the tree's module directories.
+    modules: BTreeMap<String, String>,
+    commands: Vec<String>,
+}
+
+impl Tree {
+    /// Regenerate the luarocks `manifest` from the `rock_manifest`s of the installed rocks.
+    pub(crate) fn write_luarocks_manifest(&self) -> io::Result<()> {
+        let _guard = MANIFEST_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+        let rocks_dir = self.root();
+        let rocks = installed_rocks(&rocks_dir)?;
+        std::fs::write(
+            rocks_dir.join(LUAROCKS_MANIFEST_FILE_NAME),
+            to_lua_string(&rocks),
+        )
+    }
+}
+
+/// The rocks in `rocks_dir`, by name and version.
+fn installed_rocks(
+    rocks_dir: &Path,
+) -> io::Result<BTreeMap<String, BTreeMap<String, InstalledRock>>> {
+    let mut rocks: BTreeMap<String, BTreeMap<String, InstalledRock>> = BTreeMap::new();
+    for name_entry in std::fs::read_dir(rocks_dir)? {
+        let name_entry = name_entry?;
+        let name = name_entry.file_name().to_string_lossy().to_string();
+        // Skip the staging trees.
+        if name.starts_with('.') || !name_entry.file_type()?.is_dir() {
+            continue;
+        }
+        for version_entry in std::fs::read_dir(name_entry.path())? {
+            let version_entry = version_entry?;
+            let rock_manifest_path = version_entry.path().join(ROCK_MANIFEST_FILE_NAME);
+            if !rock_manifest_path.is_file() {
+                continue;
+            }
+            let rock_manifest = RockManifest::parse(&std::fs::read_to_string(rock_manifest_path)?)
+                .map_err(io::Error::other)?;
+            rocks.entry(name.clone()).or_default().insert(
+                version_entry.file_name().to_string_lossy().to_string(),
+                installed_rock(&rock_manifest),
+            );
+        }
+    }
+    Ok(rocks)
+}
+
+fn installed_rock(rock_manifest: &RockManifest) -> InstalledRock {
+    let mut rock = InstalledRock::default();
+    for (key, _) in rock_manifest.files() {
+        let (section, relative_path) = key.split_once('/').unwrap_or(("", key));
+        let module_path = match section {
+            "lua" => relative_path.strip_suffix(".lua"),
+            "lib" => relative_path
+                .strip_suffix(lua_lib_extension())
+                .and_then(|path| path.strip_suffix('.')),
+            "bin" => {
+                rock.commands.push(relative_path.to_string());
+                None
+            }
+            _ => None,
+        };
+        if let Some(module_path) = module_path {
+            rock.modules
+                .insert(module_path.replace('/', "."), relative_path.to_string());
+        }
+    }
+    rock
+}
+
+fn to_lua_string(rocks: &BTreeMap<String, BTreeMap<String, InstalledRock>>) -> String {
+    let mut commands: BTreeMap<&str, Vec<String>> = BTreeMap::new();
+    let mut modules: BTreeMap<&str, Vec<String>> = BTreeMap::new();
+    for (name, versions) in rocks {
+        for (version, rock) in versions {
+            let rock_id = format!("{}/{}", name, version);
+            for command in &rock.commands {
+                commands.entry(command).or_default().push(rock_id.clone());
+            }
+            for module in rock.modules.keys() {
+                modules.entry(module).or_default().push(rock_id.clone());
+            }
+        }
+    }
+
+    let mut out = String::new();
+    for (field, providers) in [("commands", &commands), ("modules", &modules)] {
+        out.push_str(&format!("{} = {{\n", field));
+        for (key, rock_ids) in providers {
+            out.push_str(&format!("   [{}] = {{\n", lua_string(key)));
+            for rock_id in rock_ids {
+                out.push_str(&format!("      {},\n", lua_string(rock_id)));
+            }
+            out.push_str("   },\n");
+        }
+        out.push_str("}\n");
+    }
+
+    out.push_str("dependencies = {\n");
+    for (name, versions) in rocks {
+        out.push_str(&format!("   [{}] = {{\n", lua_string(name)));
+        for version in versions.keys() {
+            out.push_str(&format!("      [{}] = {{}},\n", lua_string(version)));
+        }
+        out.push_str("   },\n");
+    }
+    out.push_str("}\n");
+
+    out.push_str("repository = {\n");
+    for (name, versions) in rocks {
+        out.push_str(&format!("   [{}] = {{\n", lua_string(name)));
+        for (version, rock) in versions {
+            out.push_str(&format!("      [{}] = {{\n", lua_string(version)));
+            out.push_str("         {\n");
+            out.push_str("            arch = \"installed\",\n");
+            out.push_str("            commands = {\n");
+            for command in &rock.commands {
+                out.push_str(&format!(
+                    "               [{}] = {},\n",
+                    lua_string(command),
+                    lua_string(command)
+                ));
+            }
+            out.push_str("            },\n");
+            out.push_str("            dependencies = {},\n");
+            out.push_str("            modules = {\n");
+            for (module, path) in &rock.modules {
+                out.push_str(&format!(
+                    "               [{}] = {},\n",
+                    lua_string(module),
+                    lua_string(path)
+                ));
+            }
+            out.push_str("            },\n");
+            out.push_str("         },\n");
+            out.push_str("      },\n");
+        }
+        out.push_str("   },\n");
+    }
+    out.push_str("}\n");
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_fs::prelude::{FileWriteStr as _, PathChild as _};
+    use mlua::{Lua, Table};
+
+    use crate::{config::LuaVersion, lockfile::test_package, tree::TreeLayout};
+
+    use super::*;
+
+    #[test]
+    fn write_luarocks_manifest() {
+        let root = assert_fs::TempDir::new().unwrap();
+        let tree =
+            Tree::new_with_layout(root.to_path_buf(), LuaVersion::Lua51, TreeLayout::Luarocks)
+                .unwrap();
+        let package = test_package("foo", "1.0.0-1");
+        let layout = tree.rock(&package).unwrap();
+        root.child("share/lua/5.1/foo/init.lua")
+            .write_str("return {}")
+            .unwrap();
+        root.child("share/lua/5.1/foo/bar.lua")
+            .write_str("return {}")
+            .unwrap();
+        root.child("bin/foo").write_str("#!/bin/sh").unwrap();
+        RockManifest::generate(&layout, tree.layout(), &[tree.bin().join("foo")])
+            .unwrap()
+            .write(&layout)
+            .unwrap();
+
+        tree.write_luarocks_manifest().unwrap();
+
+        let lua = Lua::new();
+        lua.load(std::fs::read_to_string(tree.root().join(LUAROCKS_MANIFEST_FILE_NAME)).unwrap())
+            .exec()
+            .unwrap();
+        let repository: Table = lua.globals().get("repository").unwrap();
+        let entry: Table = repository
+            .get::<Table>("foo")
+            .unwrap()
+            .get::<Table>("1.0.0-1")
+            .unwrap()
+            .get(1)
+            .unwrap();
+        assert_eq!(entry.get::<String>("arch").unwrap(), "installed");
+        let modules: Table = entry.get("modules").unwrap();
+        assert_eq!(modules.get::<String>("foo.bar").unwrap(), "foo/bar.lua");
+        assert_eq!(modules.get::<String>("foo.init").unwrap(), "foo/init.lua");
+        let commands: Table = lua.globals().get("commands").unwrap();
+        assert_eq!(
+            commands
+                .get::<Table>("foo")
+                .unwrap()
+                .get::<String>(1)
+                .unwrap(),
+            "foo/1.0.0-1"
+        );
+    }
+}
@@ -3,16 +3,29 @@ use crate::{
         utils::escape_path,
         variables::{self, HasVariables},
     },
-    config::LuaVersion,
+    config::{Config, LuaVersion},
     lockfile::{LocalPackage, Lockfile},
     package::PackageReq,
 };
-use std::{io, path::PathBuf};
+use std::{
+    io,
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
 #[cfg(feature = "lua")]
 use mlua::ExternalResult as _;
 
+pub mod environment;
 mod list;
+mod lock;
+mod luarocks_manifest;
+mod rock_manifest;
+
+pub use lock::{TreeLock, TreeLockTimeout, DEFAULT_LOCK_TIMEOUT};
+pub use rock_manifest::{
+    IntegrityViolation, RockManifest, RockManifestError, ROCK_MANIFEST_FILE_NAME,
+};
 
 /// A tree is a collection of files where installed rocks are located.
 ///
@@ -24,6 +37,9 @@ mod list;
 /// - /rocks/<lua-version>/<rock>/lib - shared libraries (.so files)
 /// - /rocks/<lua-version>/<rock>/src - library code for the rock
 /// - /bin - binary files produced by various rocks
+///
+/// See [`TreeLayout::Fhs`] for the layout used for system-wide installs
+/// and [`TreeLayout::Luarocks`] for the layout used to share a tree with luarocks.
 
 #[derive(Clone, Debug)]
 pub struct Tree {
@@ -31,6 +47,65 @@ pub struct Tree {
     version: LuaVersion,
     /// The root of the tree.
     root: PathBuf,
+    /// How the files of the tree's rocks are laid out.
+    layout: TreeLayout,
+    /// How long to wait for other processes to release the tree's lock.
+    lock_timeout: Duration,
+}
+
+/// How a [`Tree`] lays out the files of its rocks.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+#[cfg_attr(feature = "clap", clap(rename_all = "lowercase"))]
+pub enum TreeLayout {
+    /// Each rock is installed into its own directory, as described in [`Tree`].
+    #[default]
+    Rocks,
+    /// The Filesystem Hierarchy Standard layout, which Lua searches by default
+    /// if the tree's root is a system prefix like `/usr/local`.
+    /// All rocks share the following directories:
+    ///
+    /// - /share/lua/<lua-version> - library code
+    /// - /lib/lua/<lua-version> - shared libraries
+    /// - /share/doc/<rock> - documentation
+    /// - /bin - binary files
+    ///
+    /// Resources, configuration files and the lockfile are kept in
+    /// /lib/rocks/<lua-version>, with one directory per rock, as in the default layout.
+    // The FHS layout is selected by installing into a prefix.
+    #[cfg_attr(feature = "clap", value(skip))]
+    Fhs,
+    /// The layout of a luarocks tree, so that luarocks and rocks can share the tree.
+    /// All rocks share the following directories:
+    ///
+    /// - /share/lua/<lua-version> - library code
+    /// - /lib/lua/<lua-version> - shared libraries
+    /// - /bin - binary files
+    ///
+    /// Each rock's documentation, resources, configuration files and `rock_manifest`
+    /// are kept in /lib/luarocks/rocks-<lua-version>/<rock>/<version>.
+    /// That directory also contains the lockfile and a luarocks `manifest`
+    /// of the installed rocks, which is updated whenever a rock is installed.
+    Luarocks,
+}
+
+impl TreeLayout {
+    /// Detect the layout of an existing tree at `root`,
+    /// falling back to the default layout if the tree does not exist yet.
+    pub fn detect(root: &Path, version: &LuaVersion) -> Self {
+        if luarocks_rocks_dir(root, version).is_dir() {
+            Self::Luarocks
+        } else {
+            Self::default()
+        }
+    }
+}
+
+/// The directory containing the rocks of a luarocks tree at `root`.
+fn luarocks_rocks_dir(root: &Path, version: &LuaVersion) -> PathBuf {
+    root.join("lib")
+        .join("luarocks")
+        .join(format!("rocks-{}", version.version_compatibility_str()))
 }
 
 /// Change-agnostic way of referencing various paths for a rock.
@@ -102,28 +177,114 @@ impl mlua::UserData for RockLayout {
 
 impl Tree {
     pub fn new(root: PathBuf, version: LuaVersion) -> io::Result<Self> {
-        let path_with_version = root.join(version.to_string());
+        Self::new_with_layout(root, version, TreeLayout::default())
+    }
+
+    pub fn new_with_layout(
+        root: PathBuf,
+        version: LuaVersion,
+        layout: TreeLayout,
+    ) -> io::Result<Self> {
+        let tree = Self::new_unchecked(root, version, layout);
 
         // Ensure that the root and the version directory exist.
-        std::fs::create_dir_all(&path_with_version)?;
+        std::fs::create_dir_all(tree.root())?;
 
         // Ensure that the bin directory exists.
-        std::fs::create_dir_all(root.join("bin"))?;
+        std::fs::create_dir_all(tree.bin())?;
+
+        Ok(tree)
+    }
+
+    /// A tree that may not exist yet, without creating its directories.
+    fn new_unchecked(root: PathBuf, version: LuaVersion, layout: TreeLayout) -> Self {
+        Self {
+            root,
+            version,
+            layout,
+            lock_timeout: DEFAULT_LOCK_TIMEOUT,
+        }
+    }
+
+    /// The tree that `config` operates on:
+    /// [`Config::prefix`] with the [`TreeLayout::Fhs`] layout if it is set,
+    /// or [`Config::tree`] otherwise, with [`Config::tree_layout`] or the layout
+    /// that is detected from the existing tree.
+    pub fn from_config(config: &Config, version: LuaVersion) -> io::Result<Self> {
+        let tree = Self::from_config_unchecked(config, version);
+        Self::new_with_layout(tree.root, tree.version, tree.layout)
+            .map(|tree| tree.with_lock_timeout(*config.lock_timeout()))
+    }
+
+    /// Like [`Tree::from_config`], but without creating the tree's directories,
+    /// e.g. to inspect a tree that may not exist yet with [`Tree::lockfile_snapshot`].
+    pub fn from_config_unchecked(config: &Config, version: LuaVersion) -> Self {
+        let tree = match config.prefix() {
+            Some(prefix) => Self::new_unchecked(prefix.clone(), version, TreeLayout::Fhs),
+            None => {
+                let layout = config
+                    .tree_layout()
+                    .cloned()
+                    .unwrap_or_else(|| TreeLayout::detect(config.tree(), &version));
+                Self::new_unchecked(config.tree().clone(), version, layout)
+            }
+        };
+        tree.with_lock_timeout(*config.lock_timeout())
+    }
+
+    /// The trees that `config` has for each Lua version, i.e. whose version directory exists,
+    /// e.g. `<tree>/5.1` and `<tree>/5.4` with the default layout.
+    /// Unlike [`Tree::from_config`], this does not create any trees.
+    pub fn all_from_config(config: &Config) -> Vec<Self> {
+        LuaVersion::all()
+            .into_iter()
+            .map(|version| Self::from_config_unchecked(config, version))
+            .filter(|tree| tree.root().is_dir())
+            .collect()
+    }
+
+    pub fn version(&self) -> &LuaVersion {
+        &self.version
+    }
 
-        Ok(Self { root, version })
+    /// Set how long [`Tree::lockfile`] waits for other processes to release the tree's lock.
+    pub fn with_lock_timeout(self, lock_timeout: Duration) -> Self {
+        Self {
+            lock_timeout,
+            ..self
+        }
     }
 
     pub fn root(&self) -> PathBuf {
-        self.root.join(self.version.to_string())
+        match self.layout {
+            TreeLayout::Rocks => self.root.join(self.version.to_string()),
+            TreeLayout::Fhs => self
+                .root
+                .join("lib")
+                .join("rocks")
+                .join(self.version.to_string()),
+            TreeLayout::Luarocks => luarocks_rocks_dir(&self.root, &self.version),
+        }
+    }
+
+    pub fn layout(&self) -> &TreeLayout {
+        &self.layout
     }
 
     pub fn root_for(&self, package: &LocalPackage) -> PathBuf {
-        self.root().join(format!(
-            "{}-{}@{}",
-            package.id(),
-            package.name(),
-            package.version()
-        ))
+        match self.layout {
+            // luarocks finds installed rocks by their directories.
+            TreeLayout::Luarocks => self
+                .root()
+                .join(package.name().to_string())
+                .join(package.version().to_string()),
+            TreeLayout::Rocks | TreeLayout::Fhs => self.root().join(format!(
+                "{}-{}@{}",
+                package.id(),
+                package.name(),
+                package.version()
+            )),
+        }
     }
 
     pub fn bin(&self) -> PathBuf {
@@ -150,15 +311,44 @@ impl Tree {
             .cloned()
     }
 
+    /// The `lib/lua/<lua-version>` and `share/lua/<lua-version>` directories that all rocks
+    /// share with the [`TreeLayout::Fhs`] and [`TreeLayout::Luarocks`] layouts.
+    /// Unlike the rocks' own directories, they are outside of [`Tree::root`].
+    pub fn shared_lua_dirs(&self) -> Option<(PathBuf, PathBuf)> {
+        match self.layout {
+            TreeLayout::Rocks => None,
+            TreeLayout::Fhs | TreeLayout::Luarocks => {
+                let lua_version = self.version.version_compatibility_str();
+                Some((
+                    self.root.join("lib").join("lua").join(&lua_version),
+                    self.root.join("share").join("lua").join(&lua_version),
+                ))
+            }
+        }
+    }
+
     /// Create a `RockLayout` for a package, without creating the directories.
     pub fn rock_layout(&self, package: &LocalPackage) -> RockLayout {
         let rock_path = self.root_for(package);
         let bin = self.bin();
         let etc = rock_path.join("etc");
-        let lib = rock_path.join("lib");
-        let src = rock_path.join("src");
         let conf = etc.join("conf");
-        let doc = etc.join("doc");
+        let (lib, src, doc) = match (&self.layout, self.shared_lua_dirs()) {
+            (TreeLayout::Fhs, Some((lib, src))) => (
+                lib,
+                src,
+                self.root
+                    .join("share")
+                    .join("doc")
+                    .join(package.name().to_string()),
+            ),
+            (TreeLayout::Luarocks, Some((lib, src))) => (lib, src, rock_path.join("doc")),
+            _ => (
+                rock_path.join("lib"),
+                rock_path.join("src"),
+                etc.join("doc"),
+            ),
+        };
 
         RockLayout {
             rock_path,
@@ -182,9 +372,67 @@ impl Tree {
         Ok(rock_layout)
     }
 
+    /// Create an empty tree with the same Lua version and layout in a temporary directory
+    /// inside this tree, so that a rock can be built and installed into it
+    /// and then moved into place with [`Tree::install_staged`].
+    /// The staging tree is removed when the returned [`TempDir`](tempdir::TempDir) is dropped.
+    pub(crate) fn staging_tree(&self) -> io::Result<(tempdir::TempDir, Tree)> {
+        let staging_dir = tempdir::TempDir::new_in(self.root(), ".staging")?;
+        let staging_tree = Self::new_with_layout(
+            staging_dir.path().to_path_buf(),
+            self.version.clone(),
+            self.layout.clone(),
+        )?;
+        Ok((staging_dir, staging_tree))
+    }
+
+    /// Move the contents of a tree created with [`Tree::staging_tree`] into this tree.
+    /// Directories that don't exist in this tree yet, like the directory of
+    /// a newly installed rock, are moved with a single rename.
+    /// With the [`TreeLayout::Luarocks`] layout, the luarocks `manifest` is updated as well.
+    pub(crate) fn install_staged(&self, staging_tree: &Tree) -> io::Result<()> {
+        move_into(&staging_tree.root, &self.root)?;
+        if self.layout == TreeLayout::Luarocks {
+            self.write_luarocks_manifest()?;
+        }
+        Ok(())
+    }
+
+    /// Load the tree's lockfile, creating it if it doesn't exist.
+    /// The tree is locked until the lockfile is dropped, so that concurrent `rocks` processes
+    /// don't overwrite each other's changes.
     pub fn lockfile(&self) -> io::Result<Lockfile> {
-        Lockfile::new(self.root().join("lock.json"))
+        let lock_dir = match self.layout {
+            TreeLayout::Rocks => self.root.clone(),
+            TreeLayout::Fhs => self.root.join("lib").join("rocks"),
+            TreeLayout::Luarocks => self.root.join("lib").join("luarocks"),
+        };
+        let tree_lock = TreeLock::acquire(&lock_dir, self.lock_timeout)?;
+        Lockfile::new_locked(self.root().join("lock.json"), tree_lock)
     }
+
+    /// Load a read-only copy of the tree's lockfile without locking the tree,
+    /// or an empty lockfile if nothing has been installed yet.
+    pub fn lockfile_snapshot(&self) -> io::Result<Lockfile> {
+        match Lockfile::load(&self.root().join("lock.json")) {
+            Ok(lockfile) => Ok(lockfile),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Lockfile::default()),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+fn move_into(source: &Path, destination: &Path) -> io::Result<()> {
+    for entry in std::fs::read_dir(source)? {
+        let entry = entry?;
+        let destination = destination.join(entry.file_name());
+        if entry.file_type()?.is_dir() && destination.is_dir() {
+            move_into(&entry.path(), &destination)?;
+        } else {
+            std::fs::rename(entry.path(), destination)?;
+        }
+    }
+    Ok(())
 }
 
 #[cfg(feature = "lua")]
@@ -195,6 +443,9 @@ impl mlua::UserData for Tree {
             Ok(this.root_for(&package))
         });
         methods.add_method("bin", |_, this, ()| Ok(this.bin()));
+        methods.add_method("installed_rocks", |_, this, ()| {
+            this.as_rock_list().into_lua_err()
+        });
         methods.add_method("has_rock", |_, this, req: PackageReq| {
             Ok(this.has_rock(&req))
         });
@@ -228,13 +479,13 @@ mod tests {
 
     use crate::{
         build::variables::HasVariables as _,
-        config::LuaVersion,
-        lockfile::{LocalPackage, LocalPackageHashes, LockConstraint},
+        config::{ConfigBuilder, LuaVersion},
+        lockfile::{test_package, LocalPackage, LocalPackageHashes, LockConstraint},
         package::{PackageName, PackageSpec, PackageVersion},
         tree::RockLayout,
     };
 
-    use super::Tree;
+    use super::{Tree, TreeLayout};
 
     #[test]
     fn rock_layout() {
@@ -303,6 +554,25 @@ mod tests {
         );
     }
 
+    #[test]
+    fn all_version_trees() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let config = ConfigBuilder::new()
+            .tree(Some(temp.to_path_buf()))
+            .build()
+            .unwrap();
+        assert!(Tree::all_from_config(&config).is_empty());
+
+        Tree::new(temp.to_path_buf(), LuaVersion::Lua54).unwrap();
+        Tree::new(temp.to_path_buf(), LuaVersion::Lua51).unwrap();
+        let versions = Tree::all_from_config(&config)
+            .into_iter()
+            .map(|tree| tree.version().clone())
+            .collect_vec();
+        assert_eq!(versions, vec![LuaVersion::Lua51, LuaVersion::Lua54]);
+        assert!(!temp.join(LuaVersion::LuaJIT.to_string()).exists());
+    }
+
     #[test]
     fn tree_list() {
         let tree_path =
@@ -333,6 +603,78 @@ mod tests {
         assert_yaml_snapshot!(sorted_result)
     }
 
+    #[test]
+    fn fhs_rock_layout() {
+        let prefix = assert_fs::TempDir::new().unwrap();
+        let prefix = prefix.to_path_buf();
+        let tree =
+            Tree::new_with_layout(prefix.clone(), LuaVersion::LuaJIT, TreeLayout::Fhs).unwrap();
+
+        let package = test_package("neorg", "8.0.0-1");
+        let id = package.id();
+
+        let neorg = tree.rock(&package).unwrap();
+
+        assert_eq!(
+            neorg,
+            RockLayout {
+                bin: prefix.join("bin"),
+                rock_path: prefix.join(format!("lib/rocks/jit/{id}-neorg@8.0.0-1")),
+                etc: prefix.join(format!("lib/rocks/jit/{id}-neorg@8.0.0-1/etc")),
+                lib: prefix.join("lib/lua/5.1"),
+                src: prefix.join("share/lua/5.1"),
+                conf: prefix.join(format!("lib/rocks/jit/{id}-neorg@8.0.0-1/etc/conf")),
+                doc: prefix.join("share/doc/neorg"),
+            }
+        );
+        assert!(prefix.join("bin").is_dir());
+
+        let mut lockfile = tree.lockfile().unwrap();
+        lockfile.add(&package);
+        lockfile.flush().unwrap();
+        assert!(prefix.join("lib/rocks/jit/lock.json").is_file());
+        assert!(!prefix.join("jit").exists());
+    }
+
+    #[test]
+    fn luarocks_rock_layout() {
+        let root = assert_fs::TempDir::new().unwrap();
+        let root = root.to_path_buf();
+        let tree =
+            Tree::new_with_layout(root.clone(), LuaVersion::LuaJIT, TreeLayout::Luarocks).unwrap();
+
+        let package = test_package("neorg", "8.0.0-1");
+
+        let neorg = tree.rock(&package).unwrap();
+
+        assert_eq!(
+            neorg,
+            RockLayout {
+                bin: root.join("bin"),
+                rock_path: root.join("lib/luarocks/rocks-5.1/neorg/8.0.0-1"),
+                etc: root.join("lib/luarocks/rocks-5.1/neorg/8.0.0-1/etc"),
+                lib: root.join("lib/lua/5.1"),
+                src: root.join("share/lua/5.1"),
+                conf: root.join("lib/luarocks/rocks-5.1/neorg/8.0.0-1/etc/conf"),
+                doc: root.join("lib/luarocks/rocks-5.1/neorg/8.0.0-1/doc"),
+            }
+        );
+
+        let mut lockfile = tree.lockfile().unwrap();
+        lockfile.add(&package);
+        lockfile.flush().unwrap();
+        assert!(root.join("lib/luarocks/rocks-5.1/lock.json").is_file());
+
+        assert_eq!(
+            TreeLayout::detect(&root, &LuaVersion::LuaJIT),
+            TreeLayout::Luarocks
+        );
+        assert_eq!(
+            TreeLayout::detect(&root.join("other"), &LuaVersion::LuaJIT),
+            TreeLayout::Rocks
+        );
+    }
+
     #[test]
     fn rock_layout_substiture() {
         let tree_path =
@@ -386,4 +728,48 @@ mod tests {
             ]
         );
     }
+
+    #[cfg(feature = "lua")]
+    #[test]
+    fn lua_tree_paths() {
+        let tree_path =
+            PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("resources/test/sample-tree");
+
+        let temp = assert_fs::TempDir::new().unwrap();
+        temp.copy_from(&tree_path, &["**"]).unwrap();
+        let tree_path = temp.to_path_buf();
+
+        let config = crate::config::ConfigBuilder::new()
+            .tree(Some(tree_path.clone()))
+            .lua_version(Some(LuaVersion::Lua51))
+            .build()
+            .unwrap();
+        let tree = Tree::new(tree_path.clone(), LuaVersion::Lua51).unwrap();
+        let rock_count = tree.as_rock_list().unwrap().len();
+
+        let lua = mlua::Lua::new();
+        lua.globals().set("config", config).unwrap();
+        let (root, bin, installed_rocks, src_dirs): (PathBuf, PathBuf, usize, Vec<PathBuf>) = lua
+            .load(
+                r#"
+                local tree = config:tree()
+                local rocks = tree:installed_rocks()
+                local src_dirs = {}
+                for _, rock in ipairs(rocks) do
+                    table.insert(src_dirs, tree:rock_layout(rock).src)
+                end
+                return tree:root(), tree:bin(), #rocks, src_dirs
+                "#,
+            )
+            .eval()
+            .unwrap();
+
+        assert_eq!(root, tree.root());
+        assert_eq!(bin, tree.bin());
+        assert!(installed_rocks > 0);
+        assert_eq!(installed_rocks, rock_count);
+        assert!(src_dirs
+            .iter()
+            .all(|src| src.starts_with(tree.root()) && src.ends_with("src")));
+    }
 }
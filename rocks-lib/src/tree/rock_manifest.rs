@@ -0,0 +1,383 @@
+//! The `rock_manifest` of an installed rock, which lists the rock's files and their checksums.
+//!
+//! The format is compatible with luarocks: a Lua file that assigns a `rock_manifest` table,
+//! with one subtable per directory and the MD5 checksums of the files as values.
+//! The top-level `lua`, `lib`, `bin`, `doc` and `etc` tables refer to the
+//! corresponding directories of the [`RockLayout`].
+//! Other top-level entries are relative to the rock's installation directory.
+
+use std::{
+    collections::BTreeMap,
+    fmt::Display,
+    io,
+    path::{Path, PathBuf},
+};
+
+use md5::{Digest, Md5};
+use mlua::{Lua, Table, Value};
+use thiserror::Error;
+use walkdir::WalkDir;
+
+use super::{RockLayout, TreeLayout};
+
+pub const ROCK_MANIFEST_FILE_NAME: &str = "rock_manifest";
+
+#[derive(Error, Debug)]
+pub enum RockManifestError {
+    #[error("IO operation failed: {0}")]
+    Io(#[from] io::Error),
+    #[error("failed to parse rock_manifest: {0}")]
+    Lua(#[from] mlua::Error),
+}
+
+/// The files of an installed rock and their checksums.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct RockManifest {
+    /// The MD5 checksums of the files, by `/`-separated path,
+    /// the first component of which is the section (e.g. `lua/foo/init.lua`).
+    files: BTreeMap<String, String>,
+}
+
+/// A file that does not match a [`RockManifest`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IntegrityViolation {
+    Missing(PathBuf),
+    Modified(PathBuf),
+}
+
+impl Display for IntegrityViolation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Missing(path) => write!(f, "missing: {}", path.display()),
+            Self::Modified(path) => write!(f, "modified: {}", path.display()),
+        }
+    }
+}
+
+impl RockManifest {
+    /// Enumerate the files of the rock installed into `rock_layout`.
+    /// `bins` are the executables the rock installed into the tree's shared `bin` directory.
+    /// With the [`TreeLayout::Fhs`] layout, Lua modules and native libraries are installed
+    /// into directories shared by all rocks, so they are not part of the manifest.
+    /// With the [`TreeLayout::Luarocks`] layout, they are, as luarocks expects,
+    /// so `rock_layout` must belong to a staging tree that only contains this rock.
+    pub fn generate(
+        rock_layout: &RockLayout,
+        tree_layout: &TreeLayout,
+        bins: &[PathBuf],
+    ) -> io::Result<Self> {
+        let mut manifest = Self::default();
+        if tree_layout != &TreeLayout::Fhs {
+            manifest.add_dir("lua", &rock_layout.src, None)?;
+            manifest.add_dir("lib", &rock_layout.lib, None)?;
+        }
+        manifest.add_dir("doc", &rock_layout.doc, None)?;
+        manifest.add_dir("etc", &rock_layout.etc, Some(&rock_layout.doc))?;
+        for bin in bins {
+            if let Some(file_name) = bin.file_name() {
+                manifest.files.insert(
+                    format!("bin/{}", file_name.to_string_lossy()),
+                    hash_file(bin)?,
+                );
+            }
+        }
+        Ok(manifest)
+    }
+
+    fn add_dir(&mut self, section: &str, dir: &Path, exclude: Option<&Path>) -> io::Result<()> {
+        let files = WalkDir::new(dir)
+            .sort_by_file_name()
+            .into_iter()
+            .filter_entry(|entry| exclude.is_none_or(|exclude| entry.path() != exclude));
+        for entry in files {
+            let entry = entry.map_err(io::Error::from)?;
+            if !entry.file_type().is_file() {
+                continue;
+            }
+            let relative_path = entry.path().strip_prefix(dir).unwrap_or(entry.path());
+            let key = std::iter::once(section.to_string())
+                .chain(
+                    relative_path
+                        .components()
+                        .map(|component| component.as_os_str().to_string_lossy().to_string()),
+                )
+                .collect::<Vec<_>>()
+                .join("/");
+            self.files.insert(key, hash_file(entry.path())?);
+        }
+        Ok(())
+    }
+
+    /// Load the `rock_manifest` of the rock installed into `rock_layout`,
+    /// if it has one.
+    pub fn load(rock_layout: &RockLayout) -> Result<Option<Self>, RockManifestError> {
+        let path = rock_layout.rock_path.join(ROCK_MANIFEST_FILE_NAME);
+        if !path.is_file() {
+            return Ok(None);
+        }
+        Ok(Some(Self::parse(&std::fs::read_to_string(path)?)?))
+    }
+
+    pub fn parse(content: &str) -> Result<Self, mlua::Error> {
+        let lua = Lua::new();
+        lua.load(content).exec()?;
+        let mut manifest = Self::default();
+        collect_files(
+            &lua.globals().get("rock_manifest")?,
+            "",
+            &mut manifest.files,
+        )?;
+        Ok(manifest)
+    }
+
+    /// Write the manifest to the installation directory of `rock_layout`.
+    pub fn write(&self, rock_layout: &RockLayout) -> io::Result<()> {
+        std::fs::write(
+            rock_layout.rock_path.join(ROCK_MANIFEST_FILE_NAME),
+            self.to_lua_string(),
+        )
+    }
+
+    /// Check the files of the rock installed into `rock_layout` against the manifest.
+    pub fn verify(&self, rock_layout: &RockLayout) -> io::Result<Vec<IntegrityViolation>> {
+        let mut violations = Vec::new();
+        for (key, expected) in &self.files {
+            let path = resolve(rock_layout, key);
+            if !path.is_file() {
+                violations.push(IntegrityViolation::Missing(path));
+            } else if &hash_file(&path)? != expected {
+                violations.push(IntegrityViolation::Modified(path));
+            }
+        }
+        Ok(violations)
+    }
+
+    pub fn files(&self) -> impl Iterator<Item = (&String, &String)> {
+        self.files.iter()
+    }
+
+    /// The paths of the files in the manifest (e.g. `lua/foo/init.lua`),
+    /// along with the paths they were installed to for the rock installed into `rock_layout`.
+    pub fn installed_paths<'a>(
+        &'a self,
+        rock_layout: &'a RockLayout,
+    ) -> impl Iterator<Item = (&'a String, PathBuf)> + 'a {
+        self.files
+            .keys()
+            .map(move |key| (key, resolve(rock_layout, key)))
+    }
+
+    pub fn to_lua_string(&self) -> String {
+        #[derive(Default)]
+        struct Dir<'a> {
+            dirs: BTreeMap<&'a str, Dir<'a>>,
+            files: BTreeMap<&'a str, &'a str>,
+        }
+
+        fn write_dir(out: &mut String, dir: &Dir<'_>, depth: usize) {
+            let indent = "   ".repeat(depth + 1);
+            for (name, subdir) in &dir.dirs {
+                out.push_str(&format!("{}[{}] = {{\n", indent, lua_string(name)));
+                write_dir(out, subdir, depth + 1);
+                out.push_str(&format!("{}}},\n", indent));
+            }
+            for (name, hash) in &dir.files {
+                out.push_str(&format!(
+                    "{}[{}] = {},\n",
+                    indent,
+                    lua_string(name),
+                    lua_string(hash)
+                ));
+            }
+        }
+
+        let mut root = Dir::default();
+        for (key, hash) in &self.files {
+            let mut components = key.split('/').collect::<Vec<_>>();
+            let file_name = components.pop().unwrap_or_default();
+            let dir = components.into_iter().fold(&mut root, |dir, component| {
+                dir.dirs.entry(component).or_default()
+            });
+            dir.files.insert(file_name, hash);
+        }
+
+        let mut out = "rock_manifest = {\n".to_string();
+        write_dir(&mut out, &root, 0);
+        out.push_str("}\n");
+        out
+    }
+}
+
+fn collect_files(
+    table: &Table,
+    prefix: &str,
+    files: &mut BTreeMap<String, String>,
+) -> mlua::Result<()> {
+    for pair in table.pairs::<String, Value>() {
+        let (name, value) = pair?;
+        let key = format!("{}{}", prefix, name);
+        match value {
+            Value::Table(subtable) => collect_files(&subtable, &format!("{}/", key), files)?,
+            Value::String(hash) => {
+                files.insert(key, hash.to_str()?.to_string());
+            }
+            _ => {
+                return Err(mlua::Error::RuntimeError(format!(
+                    "expected a table or a checksum for '{}'",
+                    key
+                )))
+            }
+        }
+    }
+    Ok(())
+}
+
+fn resolve(rock_layout: &RockLayout, key: &str) -> PathBuf {
+    let (section, relative_path) = key.split_once('/').unwrap_or(("", key));
+    let dir = match section {
+        "lua" => &rock_layout.src,
+        "lib" => &rock_layout.lib,
+        "bin" => &rock_layout.bin,
+        "doc" => &rock_layout.doc,
+        "etc" => &rock_layout.etc,
+        _ => return rock_layout.rock_path.join(key),
+    };
+    relative_path
+        .split('/')
+        .fold(dir.clone(), |path, component| path.join(component))
+}
+
+fn hash_file(path: &Path) -> io::Result<String> {
+    Ok(hex::encode(Md5::digest(std::fs::read(path)?)))
+}
+
+pub(super) fn lua_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for byte in s.bytes() {
+        match byte {
+            b'"' => out.push_str("\\\""),
+            b'\\' => out.push_str("\\\\"),
+            0x20..=0x7e => out.push(byte as char),
+            _ => out.push_str(&format!("\\{:03}", byte)),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_fs::prelude::*;
+
+    use super::*;
+
+    fn rock_layout(root: &Path) -> RockLayout {
+        let rock_path = root.join("foo");
+        let etc = rock_path.join("etc");
+        RockLayout {
+            lib: rock_path.join("lib"),
+            src: rock_path.join("src"),
+            bin: root.join("bin"),
+            conf: etc.join("conf"),
+            doc: etc.join("doc"),
+            etc,
+            rock_path,
+        }
+    }
+
+    #[test]
+    fn generate_and_verify() {
+        let temp = assert_fs::TempDir::new().unwrap();
+        let layout = rock_layout(temp.path());
+        temp.child("foo/src/foo/init.lua")
+            .write_str("return {}")
+            .unwrap();
+        temp.child("foo/lib/foo.so").write_str("ELF").unwrap();
+        temp.child("foo/etc/doc/README.md")
+            .write_str("# foo")
+            .unwrap();
+        temp.child("foo/etc/conf/foo.conf").write_str("").unwrap();
+        temp.child("bin/foo").write_str("#!/bin/sh").unwrap();
+
+        let manifest =
+            RockManifest::generate(&layout, &TreeLayout::Rocks, &[layout.bin.join("foo")]).unwrap();
+        assert_eq!(
+            manifest
+                .files()
+                .map(|(key, _)| key.as_str())
+                .collect::<Vec<_>>(),
+            vec![
+                "bin/foo",
+                "doc/README.md",
+                "etc/conf/foo.conf",
+                "lib/foo.so",
+                "lua/foo/init.lua"
+            ]
+        );
+        manifest.write(&layout).unwrap();
+
+        let loaded = RockManifest::load(&layout).unwrap().unwrap();
+        assert_eq!(loaded, manifest);
+        assert!(loaded.verify(&layout).unwrap().is_empty());
+
+        temp.child("foo/src/foo/init.lua")
+            .write_str("return nil")
+            .unwrap();
+        std::fs::remove_file(temp.child("bin/foo")).unwrap();
+        assert_eq!(
+            loaded.verify(&layout).unwrap(),
+            vec![
+                IntegrityViolation::Missing(layout.bin.join("foo")),
+                IntegrityViolation::Modified(layout.src.join("foo").join("init.lua")),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_luarocks_rock_manifest() {
+        let manifest = RockManifest::parse(
+            r#"
+rock_manifest = {
+   doc = {
+      ["README.md"] = "6c9cf80bab4e1f7e4f8a3ce40b3ff5d6"
+   },
+   lua = {
+      foo = {
+         ["init.lua"] = "2cf1aa2f2b4e5be8c5a0b2e5e3d5a4f1"
+      }
+   },
+   ["foo-1.0-1.rockspec"] = "d41d8cd98f00b204e9800998ecf8427e"
+}
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            manifest
+                .files()
+                .map(|(key, _)| key.as_str())
+                .collect::<Vec<_>>(),
+            vec!["doc/README.md", "foo-1.0-1.rockspec", "lua/foo/init.lua"]
+        );
+        assert_eq!(
+            resolve(&rock_layout(Path::new("/tree")), "foo-1.0-1.rockspec"),
+            PathBuf::from("/tree/foo/foo-1.0-1.rockspec")
+        );
+        let layout = rock_layout(Path::new("/tree"));
+        assert_eq!(
+            manifest
+                .installed_paths(&layout)
+                .map(|(_, path)| path)
+                .collect::<Vec<_>>(),
+            vec![
+                layout.doc.join("README.md"),
+                layout.rock_path.join("foo-1.0-1.rockspec"),
+                layout.src.join("foo").join("init.lua"),
+            ]
+        );
+        assert_eq!(
+            RockManifest::parse(&manifest.to_lua_string()).unwrap(),
+            manifest
+        );
+    }
+}
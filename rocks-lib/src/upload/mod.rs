@@ -1,8 +1,11 @@
 use std::env;
-use std::io::Read;
+use std::io::{Cursor, Read};
+use std::path::{Path, PathBuf};
 
+use crate::package::{PackageName, PackageVersion};
+use crate::rockspec::{Rockspec, RockspecError};
 use crate::TOOL_VERSION;
-use crate::{config::Config, project::Project};
+use crate::{config::Config, operations, project::Project};
 use gpgme::{Context, Data};
 use reqwest::{
     multipart::{Form, Part},
@@ -45,6 +48,10 @@ pub enum UploadError {
     RockCheck(#[from] RockCheckError),
     #[error("rock already exists on server: {0}")]
     RockExists(String),
+    #[error("{path} is not a valid rockspec: {err}", path = .0.display(), err = .1)]
+    InvalidRockspec(PathBuf, RockspecError),
+    #[error("{path} is not a valid rock: {reason}", path = .0.display(), reason = .1)]
+    InvalidRock(PathBuf, String),
     #[error("unable to read rockspec: {0}")]
     RockspecRead(#[from] std::io::Error),
     #[error("{0}.\nHINT: If you'd like to skip the signing step supply `--sign-protocol none` to the CLI")]
@@ -131,26 +138,142 @@ pub async fn upload_from_project(
     protocol: SignatureProtocol,
     config: &Config,
 ) -> Result<(), UploadError> {
+    let rockspec = project.rockspec();
+    let rockspec_content = std::fs::read_to_string(project.root().join("project.rockspec"))?;
+
+    // Upload the rock that `rocks pack` produced, along with its signature, if any.
+    let rock_path = project
+        .root()
+        .join(operations::packed_rock_file_name(project));
+    let rock = if rock_path.is_file() {
+        Some(RockArtifact::read(&rock_path)?)
+    } else {
+        None
+    };
+
+    let artifacts = Artifacts {
+        package: rockspec.package.clone(),
+        version: rockspec.version.clone(),
+        rockspec_content,
+        rock,
+    };
+    let client = Client::builder().https_only(true).build()?;
+    upload_artifacts(&client, artifacts, api_key, protocol, config.server()).await
+}
+
+/// Upload a rockspec, and optionally a rock, that were produced beforehand, e.g. by `rocks pack`,
+/// without packing the project again.
+/// The files are validated before anything is uploaded, and are uploaded as they are.
+pub async fn upload_files(
+    rockspec_path: &Path,
+    rock_path: Option<&Path>,
+    api_key: &ApiKey,
+    protocol: SignatureProtocol,
+    config: &Config,
+) -> Result<(), UploadError> {
+    let artifacts = Artifacts::from_files(rockspec_path, rock_path)?;
     let client = Client::builder().https_only(true).build()?;
+    upload_artifacts(&client, artifacts, api_key, protocol, config.server()).await
+}
 
-    helpers::ensure_tool_version(&client, config.server()).await?;
-    helpers::ensure_user_exists(&client, api_key, config.server()).await?;
+/// The files to upload for a rock.
+struct Artifacts {
+    package: PackageName,
+    version: PackageVersion,
+    rockspec_content: String,
+    rock: Option<RockArtifact>,
+}
 
-    let rockspec = project.rockspec();
+struct RockArtifact {
+    file_name: String,
+    content: Vec<u8>,
+    /// The detached signature next to the rock, if any.
+    signature: Option<String>,
+}
+
+impl RockArtifact {
+    fn read(path: &Path) -> Result<Self, UploadError> {
+        let signature_path = operations::signature_path(path);
+        Ok(Self {
+            file_name: path
+                .file_name()
+                .map(|file_name| file_name.to_string_lossy().to_string())
+                .unwrap_or_default(),
+            content: std::fs::read(path)?,
+            signature: if signature_path.is_file() {
+                Some(std::fs::read_to_string(&signature_path)?)
+            } else {
+                None
+            },
+        })
+    }
+}
+
+impl Artifacts {
+    /// Read and validate a rockspec file and a rock file.
+    /// The rock must be named after the rockspec's package and version,
+    /// and must contain the rockspec, like the rocks that `rocks pack` or `luarocks pack` produce.
+    fn from_files(rockspec_path: &Path, rock_path: Option<&Path>) -> Result<Self, UploadError> {
+        let rockspec_content = std::fs::read_to_string(rockspec_path)?;
+        let rockspec = Rockspec::new(&rockspec_content)
+            .map_err(|err| UploadError::InvalidRockspec(rockspec_path.to_path_buf(), err))?;
+        let rock = match rock_path {
+            Some(rock_path) => {
+                let rock = RockArtifact::read(rock_path)?;
+                let invalid =
+                    |reason: String| UploadError::InvalidRock(rock_path.to_path_buf(), reason);
+                let prefix = format!("{}-{}.", rockspec.package, rockspec.version);
+                if !(rock.file_name.starts_with(&prefix) && rock.file_name.ends_with(".rock")) {
+                    return Err(invalid(format!(
+                        "expected a file name of the form {}<arch>.rock",
+                        prefix
+                    )));
+                }
+                let mut archive = zip::ZipArchive::new(Cursor::new(&rock.content))
+                    .map_err(|err| invalid(err.to_string()))?;
+                let rockspec_file_name = format!("{}rockspec", prefix);
+                if archive.by_name(&rockspec_file_name).is_err() {
+                    return Err(invalid(format!(
+                        "it does not contain {}",
+                        rockspec_file_name
+                    )));
+                }
+                Some(rock)
+            }
+            None => None,
+        };
+        Ok(Self {
+            package: rockspec.package,
+            version: rockspec.version,
+            rockspec_content,
+            rock,
+        })
+    }
+}
+
+async fn upload_artifacts(
+    client: &Client,
+    artifacts: Artifacts,
+    api_key: &ApiKey,
+    protocol: SignatureProtocol,
+    server: &str,
+) -> Result<(), UploadError> {
+    helpers::ensure_tool_version(client, server).await?;
+    helpers::ensure_user_exists(client, api_key, server).await?;
 
     if helpers::rock_exists(
-        &client,
+        client,
         api_key,
-        &rockspec.package,
-        &rockspec.version,
-        config.server(),
+        &artifacts.package,
+        &artifacts.version,
+        server,
     )
     .await?
     {
-        return Err(UploadError::RockExists(config.server().clone()));
+        return Err(UploadError::RockExists(server.to_string()));
     }
 
-    let rockspec_content = std::fs::read_to_string(project.root().join("project.rockspec"))?;
+    let rockspec_content = artifacts.rockspec_content;
 
     let signed = if let SignatureProtocol::None = protocol {
         None
@@ -170,24 +293,43 @@ pub async fn upload_from_project(
     let rockspec = Part::text(rockspec_content)
         .file_name(format!(
             "{}-{}.rockspec",
-            rockspec.package, rockspec.version
+            artifacts.package, artifacts.version
         ))
         .mime_str("application/octet-stream")?;
 
     let multipart = {
         let multipart = Form::new().part("rockspec_file", rockspec);
 
-        match signed {
+        let multipart = match signed {
             Some(signature) => {
                 let part = Part::text(signature).file_name("project.rockspec.sig");
                 multipart.part("rockspec_sig", part)
             }
             None => multipart,
+        };
+
+        match artifacts.rock {
+            Some(rock) => {
+                let part = Part::bytes(rock.content)
+                    .file_name(rock.file_name.clone())
+                    .mime_str("application/octet-stream")?;
+                let multipart = multipart.part("rock_file", part);
+
+                match rock.signature {
+                    Some(signature) => {
+                        let part =
+                            Part::text(signature).file_name(format!("{}.asc", rock.file_name));
+                        multipart.part("rock_sig", part)
+                    }
+                    None => multipart,
+                }
+            }
+            None => multipart,
         }
     };
 
     client
-        .post(helpers::url_for_method(config.server(), api_key, "upload"))
+        .post(helpers::url_for_method(server, api_key, "upload"))
         .multipart(multipart)
         .send()
         .await?;
@@ -197,7 +339,6 @@ pub async fn upload_from_project(
 
 mod helpers {
     use super::*;
-    use crate::package::{PackageName, PackageVersion};
     use crate::upload::RockCheckError;
     use crate::upload::{ToolCheckError, UserCheckError};
     use reqwest::Client;
@@ -265,3 +406,129 @@ mod helpers {
             != "{}")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write as _;
+
+    use assert_fs::prelude::{FileWriteBin as _, FileWriteStr as _, PathChild as _};
+    use httptest::{matchers::*, responders::*, Expectation, Server};
+    use zip::{write::SimpleFileOptions, ZipWriter};
+
+    use super::*;
+
+    const ROCKSPEC: &str = r#"
+package = "foo"
+version = "1.0.0-1"
+source = { url = "https://github.com/example/foo/archive/v1.0.0.zip" }
+"#;
+
+    fn rock_with(file_name: &str, content: &str) -> Vec<u8> {
+        let mut rock = Vec::new();
+        let mut zip = ZipWriter::new(Cursor::new(&mut rock));
+        zip.start_file(file_name, SimpleFileOptions::default())
+            .unwrap();
+        zip.write_all(content.as_bytes()).unwrap();
+        zip.finish().unwrap();
+        rock
+    }
+
+    /// A regex that matches `bytes` exactly.
+    fn bytes_regex(bytes: &[u8]) -> String {
+        bytes.iter().fold("(?s-u)".to_string(), |regex, byte| {
+            format!("{}\\x{:02x}", regex, byte)
+        })
+    }
+
+    #[tokio::test]
+    async fn upload_files_unmodified() {
+        let dir = assert_fs::TempDir::new().unwrap();
+        let rockspec_file = dir.child("foo-1.0.0-1.rockspec");
+        rockspec_file.write_str(ROCKSPEC).unwrap();
+        let rock = rock_with("foo-1.0.0-1.rockspec", ROCKSPEC);
+        let rock_file = dir.child("foo-1.0.0-1.src.rock");
+        rock_file.write_binary(&rock).unwrap();
+        dir.child("foo-1.0.0-1.src.rock.asc")
+            .write_str("rock signature")
+            .unwrap();
+
+        let server = Server::run();
+        server.expect(
+            Expectation::matching(request::method_path("POST", "/api/tool_version"))
+                .respond_with(json_encoded(serde_json::json!({ "version": TOOL_VERSION }))),
+        );
+        server.expect(
+            Expectation::matching(request::method_path("GET", "/api/1/key/status"))
+                .respond_with(status_code(200)),
+        );
+        server.expect(
+            Expectation::matching(request::method_path("GET", "/api/1/key/check_rockspec"))
+                .respond_with(status_code(200).body("{}")),
+        );
+        server.expect(
+            Expectation::matching(all_of![
+                request::method_path("POST", "/api/1/key/upload"),
+                request::body(matches(bytes_regex(ROCKSPEC.as_bytes()))),
+                request::body(matches(bytes_regex(&rock))),
+                request::body(matches("rock signature")),
+            ])
+            .respond_with(status_code(200)),
+        );
+
+        let artifacts =
+            Artifacts::from_files(rockspec_file.path(), Some(rock_file.path())).unwrap();
+        upload_artifacts(
+            &Client::new(),
+            artifacts,
+            &unsafe { ApiKey::from("key".into()) },
+            SignatureProtocol::None,
+            &server.url_str("/"),
+        )
+        .await
+        .unwrap();
+    }
+
+    #[test]
+    fn reject_invalid_files() {
+        let dir = assert_fs::TempDir::new().unwrap();
+        let rockspec_file = dir.child("foo-1.0.0-1.rockspec");
+        rockspec_file.write_str(ROCKSPEC).unwrap();
+
+        let invalid_rockspec = dir.child("invalid.rockspec");
+        invalid_rockspec.write_str("package = 'foo'").unwrap();
+        assert!(matches!(
+            Artifacts::from_files(invalid_rockspec.path(), None),
+            Err(UploadError::InvalidRockspec(..))
+        ));
+
+        let not_a_zip = dir.child("foo-1.0.0-1.src.rock");
+        not_a_zip.write_str(ROCKSPEC).unwrap();
+        assert!(matches!(
+            Artifacts::from_files(rockspec_file.path(), Some(not_a_zip.path())),
+            Err(UploadError::InvalidRock(..))
+        ));
+
+        let other_version = dir.child("foo-2.0.0-1.src.rock");
+        other_version
+            .write_binary(&rock_with("foo-2.0.0-1.rockspec", ROCKSPEC))
+            .unwrap();
+        assert!(matches!(
+            Artifacts::from_files(rockspec_file.path(), Some(other_version.path())),
+            Err(UploadError::InvalidRock(..))
+        ));
+
+        let without_rockspec = dir.child("foo-1.0.0-1.all.rock");
+        without_rockspec
+            .write_binary(&rock_with("foo.lua", "return {}"))
+            .unwrap();
+        assert!(matches!(
+            Artifacts::from_files(rockspec_file.path(), Some(without_rockspec.path())),
+            Err(UploadError::InvalidRock(..))
+        ));
+
+        let rock = dir.child("foo-1.0.0-1.all.rock");
+        rock.write_binary(&rock_with("foo-1.0.0-1.rockspec", ROCKSPEC))
+            .unwrap();
+        assert!(Artifacts::from_files(rockspec_file.path(), Some(rock.path())).is_ok());
+    }
+}
@@ -6,6 +6,7 @@ use rocks_lib::{
     lockfile::{LockConstraint::Unconstrained, PinnedState::Unpinned},
     progress::{MultiProgress, Progress},
     rockspec::Rockspec,
+    tree::Tree,
 };
 use tempdir::TempDir;
 
@@ -28,6 +29,7 @@ async fn builtin_build() {
 
     build::build(
         rockspec,
+        None,
         Unpinned,
         Unconstrained,
         Force,
@@ -58,6 +60,7 @@ async fn make_build() {
 
     build::build(
         rockspec,
+        None,
         Unpinned,
         Unconstrained,
         Force,
@@ -68,6 +71,16 @@ async fn make_build() {
     .unwrap();
 }
 
+#[tokio::test]
+async fn make_build_default_variables() {
+    // The Makefile fails if `LUA_INCDIR`, `PREFIX`, etc. are not passed to `make`.
+    test_build_rockspec(
+        "resources/test/make-default-variables-project/make-default-variables-project-scm-1.rockspec"
+            .into(),
+    )
+    .await
+}
+
 #[tokio::test]
 async fn cmake_build() {
     test_build_rockspec("resources/test/luv-1.48.0-2.rockspec".into()).await
@@ -100,6 +113,7 @@ async fn test_build_rockspec(rockspec_path: PathBuf) {
 
     build::build(
         rockspec,
+        None,
         Unpinned,
         Unconstrained,
         Force,
@@ -109,3 +123,52 @@ async fn test_build_rockspec(rockspec_path: PathBuf) {
     .await
     .unwrap();
 }
+
+#[tokio::test]
+async fn reinstall_preserves_edited_conf() {
+    let dir = TempDir::new("rocks-test").unwrap();
+    let content = String::from_utf8(
+        std::fs::read("resources/test/conf-project/conf-project-scm-1.rockspec").unwrap(),
+    )
+    .unwrap();
+    let rockspec = Rockspec::new(&content).unwrap();
+    let config = ConfigBuilder::new()
+        .tree(Some(dir.path().to_path_buf()))
+        .build()
+        .unwrap();
+    let build = || {
+        build::build(
+            rockspec.clone(),
+            None,
+            Unpinned,
+            Unconstrained,
+            Force,
+            &config,
+            &Progress::NoProgress,
+        )
+    };
+
+    let package = build().await.unwrap();
+    let lua_version = rockspec.lua_version_from_config(&config).unwrap();
+    let conf_dir = Tree::from_config(&config, lua_version)
+        .unwrap()
+        .rock_layout(&package)
+        .conf;
+    let conf = conf_dir.join("conf-project.conf");
+    assert_eq!(
+        std::fs::read_to_string(&conf).unwrap(),
+        "greeting = \"hello\"\n"
+    );
+    assert!(!conf_dir.join("conf-project.conf.new").exists());
+
+    std::fs::write(&conf, "greeting = \"edited\"\n").unwrap();
+    build().await.unwrap();
+    assert_eq!(
+        std::fs::read_to_string(&conf).unwrap(),
+        "greeting = \"edited\"\n"
+    );
+    assert_eq!(
+        std::fs::read_to_string(conf_dir.join("conf-project.conf.new")).unwrap(),
+        "greeting = \"hello\"\n"
+    );
+}
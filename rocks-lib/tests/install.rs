@@ -0,0 +1,962 @@
+use assert_fs::prelude::*;
+use git2::{Repository, Signature};
+use httptest::{
+    matchers::{self, request},
+    responders::status_code,
+    Expectation, Server,
+};
+use regex::Regex;
+use rocks_lib::{
+    build::{BuildBehaviour, BuildError, FileConflict},
+    config::{Config, ConfigBuilder, LuaVersion},
+    lockfile::{PinnedState, RemotePackageSourceUrl},
+    operations::{self, InstallError, InstallLockedError, RemoveError, RockIntegrity, TreeProblem},
+    progress::{MultiProgress, Progress},
+    project::Project,
+    remote_package_db::RemotePackageDB,
+    rockspec::{GitSource, Rockspec},
+    tree::{IntegrityViolation, Tree},
+};
+use std::path::Path;
+
+const ROCKSPEC: &str = r#"
+package = "foo"
+version = "1.0.0-1"
+source = {
+    url = "git+https://example.com/foo",
+    tag = "v1.0.0",
+}
+dependencies = {
+    "lua >= 5.1",
+}
+build = {
+    type = "builtin",
+    modules = {
+        foo = "src/foo.lua",
+    },
+}
+"#;
+
+const ROCKSPEC_WITH_BIN: &str = r#"
+package = "foo"
+version = "1.0.0-1"
+source = {
+    url = "git+https://example.com/foo",
+    tag = "v1.0.0",
+}
+build = {
+    type = "builtin",
+    modules = {
+        foo = "src/foo.lua",
+    },
+    install = {
+        bin = {
+            hello = "bin/hello.lua",
+        },
+    },
+}
+"#;
+
+fn init_repo(dir: &Path) {
+    let repo = Repository::init(dir).unwrap();
+    let mut index = repo.index().unwrap();
+    index
+        .add_all(["*"], git2::IndexAddOption::DEFAULT, None)
+        .unwrap();
+    index.write().unwrap();
+    let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+    let signature = Signature::now("rocks", "rocks@example.com").unwrap();
+    let commit = repo
+        .commit(Some("HEAD"), &signature, &signature, "init", &tree, &[])
+        .unwrap();
+    repo.tag_lightweight("v1.0.0", &repo.find_object(commit, None).unwrap(), false)
+        .unwrap();
+}
+
+/// A rocks server without any rocks.
+fn start_test_server() -> Server {
+    let server = Server::run();
+    server.expect(
+        Expectation::matching(request::path("/manifest-5.1"))
+            .times(1..)
+            .respond_with(status_code(200).body("repository = {}")),
+    );
+    server
+}
+
+fn test_config(server: &Server, temp: &assert_fs::TempDir) -> Config {
+    let mut server_url = server.url_str("");
+    server_url.pop();
+    ConfigBuilder::new()
+        .server(Some(server_url))
+        .cache_dir(Some(temp.join("cache")))
+        .tree(Some(temp.join("tree")))
+        .lua_version(Some(LuaVersion::Lua51))
+        .build()
+        .unwrap()
+}
+
+#[tokio::test]
+async fn install_from_git() {
+    let repo_dir = assert_fs::TempDir::new().unwrap();
+    repo_dir
+        .child("foo-1.0.0-1.rockspec")
+        .write_str(ROCKSPEC)
+        .unwrap();
+    repo_dir
+        .child("src/foo.lua")
+        .write_str("return {}")
+        .unwrap();
+    init_repo(&repo_dir);
+
+    let server = start_test_server();
+    let temp = assert_fs::TempDir::new().unwrap();
+    let config = test_config(&server, &temp);
+    let package_db = RemotePackageDB::from_config(&config).await.unwrap();
+    let source = GitSource {
+        checkout_ref: Some("v1.0.0".into()),
+        ..format!("git+file://{}", repo_dir.display())
+            .parse()
+            .unwrap()
+    };
+
+    let package = operations::install_from_git(
+        source,
+        None,
+        PinnedState::Unpinned,
+        BuildBehaviour::NoForce,
+        &package_db,
+        &config,
+        MultiProgress::new_arc(),
+    )
+    .await
+    .unwrap();
+
+    let tree = Tree::new(config.tree().clone(), LuaVersion::Lua51).unwrap();
+    assert!(tree.rock(&package).unwrap().src.join("foo.lua").is_file());
+    let lockfile = tree.lockfile().unwrap();
+    let locked = lockfile.get(&package.id()).unwrap();
+    assert!(matches!(
+        locked.source(),
+        Some(RemotePackageSourceUrl::Git { checkout_ref: Some(tag), .. }) if tag == "v1.0.0"
+    ));
+}
+
+#[tokio::test]
+async fn install_from_git_without_persisting_credentials() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    // The repository's path stands in for a credential in the URL.
+    let repo_dir = temp.child("s3cr3t");
+    repo_dir
+        .child("foo-1.0.0-1.rockspec")
+        .write_str(ROCKSPEC)
+        .unwrap();
+    repo_dir
+        .child("src/foo.lua")
+        .write_str("return {}")
+        .unwrap();
+    init_repo(&repo_dir);
+
+    let server = start_test_server();
+    let config = test_config(&server, &temp);
+    let package_db = RemotePackageDB::from_config(&config).await.unwrap();
+    let source: GitSource = format!(
+        "git+file://{}/${{ROCKS_TEST_GIT_TOKEN}}",
+        temp.path().display()
+    )
+    .parse()
+    .unwrap();
+
+    std::env::set_var("ROCKS_TEST_GIT_TOKEN", "s3cr3t");
+    let result = operations::install_from_git(
+        source,
+        None,
+        PinnedState::Unpinned,
+        BuildBehaviour::NoForce,
+        &package_db,
+        &config,
+        MultiProgress::new_arc(),
+    )
+    .await;
+    std::env::remove_var("ROCKS_TEST_GIT_TOKEN");
+    result.unwrap();
+
+    let tree = Tree::new(config.tree().clone(), LuaVersion::Lua51).unwrap();
+    let lockfile = std::fs::read_to_string(tree.root().join("lock.json")).unwrap();
+    assert!(lockfile.contains("ROCKS_TEST_GIT_TOKEN"));
+    assert!(!lockfile.contains("s3cr3t"));
+}
+
+#[tokio::test]
+async fn install_from_src_rock() {
+    // luarocks packs the rockspec alongside the source, which is a directory for git sources.
+    let temp = assert_fs::TempDir::new().unwrap();
+    let src_rock = temp.join("foo-1.0.0-1.src.rock");
+    let mut zip = zip::ZipWriter::new(std::fs::File::create(&src_rock).unwrap());
+    let options = zip::write::SimpleFileOptions::default();
+    zip.start_file("foo-1.0.0-1.rockspec", options).unwrap();
+    std::io::Write::write_all(&mut zip, ROCKSPEC.as_bytes()).unwrap();
+    zip.start_file("foo/src/foo.lua", options).unwrap();
+    std::io::Write::write_all(&mut zip, b"return {}").unwrap();
+    zip.finish().unwrap();
+
+    let server = start_test_server();
+    let config = test_config(&server, &temp);
+    let package_db = RemotePackageDB::from_config(&config).await.unwrap();
+
+    let package = operations::install_from_src_rock(
+        &src_rock,
+        PinnedState::Unpinned,
+        BuildBehaviour::NoForce,
+        &package_db,
+        &config,
+        MultiProgress::new_arc(),
+    )
+    .await
+    .unwrap();
+
+    let tree = Tree::new(config.tree().clone(), LuaVersion::Lua51).unwrap();
+    assert!(tree.rock(&package).unwrap().src.join("foo.lua").is_file());
+    let lockfile = tree.lockfile().unwrap();
+    let locked = lockfile.get(&package.id()).unwrap();
+    assert!(matches!(
+        locked.source(),
+        Some(RemotePackageSourceUrl::File { path }) if *path == src_rock.canonicalize().unwrap()
+    ));
+}
+
+#[tokio::test]
+async fn install_from_rewritten_git_url() {
+    let repo_dir = assert_fs::TempDir::new().unwrap();
+    repo_dir
+        .child("foo-1.0.0-1.rockspec")
+        .write_str(ROCKSPEC)
+        .unwrap();
+    repo_dir
+        .child("src/foo.lua")
+        .write_str("return {}")
+        .unwrap();
+    init_repo(&repo_dir);
+
+    let server = start_test_server();
+    let temp = assert_fs::TempDir::new().unwrap();
+    let mut server_url = server.url_str("");
+    server_url.pop();
+    let config = ConfigBuilder::new()
+        .server(Some(server_url))
+        .cache_dir(Some(temp.join("cache")))
+        .tree(Some(temp.join("tree")))
+        .lua_version(Some(LuaVersion::Lua51))
+        .url_rewrites(Some(vec![(
+            Regex::new("^https://github.com/foo/foo$").unwrap(),
+            format!("file://{}", repo_dir.display()),
+        )]))
+        .build()
+        .unwrap();
+    let package_db = RemotePackageDB::from_config(&config).await.unwrap();
+    let source: GitSource = "git+https://github.com/foo/foo".parse().unwrap();
+    let canonical_url = source.url.to_string();
+
+    let package = operations::install_from_git(
+        source,
+        None,
+        PinnedState::Unpinned,
+        BuildBehaviour::NoForce,
+        &package_db,
+        &config,
+        MultiProgress::new_arc(),
+    )
+    .await
+    .unwrap();
+
+    let tree = Tree::new(config.tree().clone(), LuaVersion::Lua51).unwrap();
+    let lockfile = tree.lockfile().unwrap();
+    let locked = lockfile.get(&package.id()).unwrap();
+    assert!(matches!(
+        locked.source(),
+        Some(RemotePackageSourceUrl::Git { url, .. }) if *url == canonical_url
+    ));
+}
+
+#[tokio::test]
+async fn installed_rock_integrity() {
+    let repo_dir = assert_fs::TempDir::new().unwrap();
+    repo_dir
+        .child("foo-1.0.0-1.rockspec")
+        .write_str(ROCKSPEC)
+        .unwrap();
+    repo_dir
+        .child("src/foo.lua")
+        .write_str("return {}")
+        .unwrap();
+    init_repo(&repo_dir);
+
+    let server = start_test_server();
+    let temp = assert_fs::TempDir::new().unwrap();
+    let config = test_config(&server, &temp);
+    let package_db = RemotePackageDB::from_config(&config).await.unwrap();
+    let source: GitSource = format!("git+file://{}", repo_dir.display())
+        .parse()
+        .unwrap();
+
+    let package = operations::install_from_git(
+        source,
+        None,
+        PinnedState::Unpinned,
+        BuildBehaviour::NoForce,
+        &package_db,
+        &config,
+        MultiProgress::new_arc(),
+    )
+    .await
+    .unwrap();
+
+    let tree = Tree::new(config.tree().clone(), LuaVersion::Lua51).unwrap();
+    let rock_layout = tree.rock_layout(&package);
+    assert!(rock_layout.rock_path.join("rock_manifest").is_file());
+    assert_eq!(
+        operations::check_integrity(&package, &tree).unwrap(),
+        RockIntegrity::Intact
+    );
+
+    let module = rock_layout.src.join("foo.lua");
+    std::fs::write(&module, "return nil").unwrap();
+    assert_eq!(
+        operations::check_integrity(&package, &tree).unwrap(),
+        RockIntegrity::Violated(vec![IntegrityViolation::Modified(module)])
+    );
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn install_bin_script() {
+    let repo_dir = assert_fs::TempDir::new().unwrap();
+    repo_dir
+        .child("foo-1.0.0-1.rockspec")
+        .write_str(ROCKSPEC_WITH_BIN)
+        .unwrap();
+    repo_dir
+        .child("src/foo.lua")
+        .write_str(r#"return { message = "Hello from foo" }"#)
+        .unwrap();
+    repo_dir
+        .child("bin/hello.lua")
+        .write_str(r#"print(require("foo").message, ...)"#)
+        .unwrap();
+    init_repo(&repo_dir);
+
+    let server = start_test_server();
+    let temp = assert_fs::TempDir::new().unwrap();
+    let config = test_config(&server, &temp);
+    let package_db = RemotePackageDB::from_config(&config).await.unwrap();
+    let source: GitSource = format!("git+file://{}", repo_dir.display())
+        .parse()
+        .unwrap();
+
+    operations::install_from_git(
+        source,
+        None,
+        PinnedState::Unpinned,
+        BuildBehaviour::NoForce,
+        &package_db,
+        &config,
+        MultiProgress::new_arc(),
+    )
+    .await
+    .unwrap();
+
+    let tree = Tree::new(config.tree().clone(), LuaVersion::Lua51).unwrap();
+    let output = std::process::Command::new(tree.bin().join("hello"))
+        .arg("world")
+        .env_remove("LUA_PATH")
+        .env_remove("LUA_CPATH")
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout).trim(),
+        "Hello from foo\tworld"
+    );
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn install_conflicting_bin_scripts() {
+    let server = start_test_server();
+    let temp = assert_fs::TempDir::new().unwrap();
+    let config = test_config(&server, &temp);
+    let package_db = RemotePackageDB::from_config(&config).await.unwrap();
+    let install_rock = |package: &'static str, config: Config| {
+        let package_db = package_db.clone();
+        async move {
+            let repo_dir = assert_fs::TempDir::new().unwrap();
+            repo_dir
+                .child(format!("{package}-1.0.0-1.rockspec"))
+                .write_str(
+                    &ROCKSPEC_WITH_BIN
+                        .replace(r#"package = "foo""#, &format!(r#"package = "{package}""#)),
+                )
+                .unwrap();
+            repo_dir
+                .child("src/foo.lua")
+                .write_str("return {}")
+                .unwrap();
+            repo_dir
+                .child("bin/hello.lua")
+                .write_str(&format!(r#"print("Hello from {package}")"#))
+                .unwrap();
+            init_repo(&repo_dir);
+            operations::install_from_git(
+                format!("git+file://{}", repo_dir.display())
+                    .parse()
+                    .unwrap(),
+                None,
+                PinnedState::Unpinned,
+                BuildBehaviour::NoForce,
+                &package_db,
+                &config,
+                MultiProgress::new_arc(),
+            )
+            .await
+        }
+    };
+
+    install_rock("foo", config.clone()).await.unwrap();
+    let tree = Tree::new(config.tree().clone(), LuaVersion::Lua51).unwrap();
+    let hello = tree.bin().join("hello");
+    let launcher = std::fs::read_to_string(&hello).unwrap();
+
+    match install_rock("bar", config.clone()).await {
+        Err(InstallError::BuildError(_, BuildError::FileConflicts { package, conflicts })) => {
+            assert_eq!(package, "bar@1.0.0-1");
+            assert_eq!(
+                conflicts,
+                vec![FileConflict {
+                    path: hello.clone(),
+                    owner: "foo@1.0.0-1".into(),
+                }]
+            );
+        }
+        result => panic!("expected a file conflict, got {result:?}"),
+    }
+    assert_eq!(std::fs::read_to_string(&hello).unwrap(), launcher);
+    assert!(!tree.list().unwrap().contains_key(&"bar".into()));
+
+    install_rock("bar", config.with_allow_conflicts(true))
+        .await
+        .unwrap();
+    assert_ne!(std::fs::read_to_string(&hello).unwrap(), launcher);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn install_with_build_dependencies() {
+    // `gen` provides a code generator, which `foo` runs during its build.
+    let gen_repo_dir = assert_fs::TempDir::new().unwrap();
+    let gen_rockspec = format!(
+        r#"
+package = "gen"
+version = "1.0.0-1"
+source = {{
+    url = "git+file://{}",
+    tag = "v1.0.0",
+}}
+build = {{
+    type = "builtin",
+    modules = {{}},
+    install = {{
+        bin = {{
+            ["gen-module"] = "bin/gen.lua",
+        }},
+    }},
+}}
+"#,
+        gen_repo_dir.display()
+    );
+    gen_repo_dir
+        .child("gen-1.0.0-1.rockspec")
+        .write_str(&gen_rockspec)
+        .unwrap();
+    gen_repo_dir
+        .child("bin/gen.lua")
+        .write_str(
+            r#"local file = assert(io.open("generated.lua", "w"))
+file:write("return 'generated'")
+file:close()"#,
+        )
+        .unwrap();
+    init_repo(&gen_repo_dir);
+
+    let repo_dir = assert_fs::TempDir::new().unwrap();
+    repo_dir
+        .child("foo-1.0.0-1.rockspec")
+        .write_str(
+            r#"
+rockspec_format = "3.0"
+package = "foo"
+version = "1.0.0-1"
+source = {
+    url = "git+https://example.com/foo",
+    tag = "v1.0.0",
+}
+build_dependencies = {
+    "gen",
+}
+build = {
+    type = "command",
+    build_command = "gen-module",
+    install_command = "true",
+    install = {
+        lua = {
+            generated = "generated.lua",
+        },
+    },
+}
+"#,
+        )
+        .unwrap();
+    init_repo(&repo_dir);
+
+    let server = Server::run();
+    server.expect(
+        Expectation::matching(request::path("/manifest-5.1"))
+            .times(1..)
+            .respond_with(
+                status_code(200)
+                    .body(r#"repository = { gen = { ["1.0.0-1"] = { { arch = "rockspec" } } } }"#),
+            ),
+    );
+    server.expect(
+        Expectation::matching(request::path("/gen-1.0.0-1.rockspec"))
+            .respond_with(status_code(200).body(gen_rockspec)),
+    );
+    let temp = assert_fs::TempDir::new().unwrap();
+    let build_tree = temp.join("build-tree");
+    let config = ConfigBuilder::new()
+        .server(Some(server.url_str("").trim_end_matches('/').to_string()))
+        .cache_dir(Some(temp.join("cache")))
+        .tree(Some(temp.join("tree")))
+        .luarocks_tree(Some(build_tree.clone()))
+        .lua_version(Some(LuaVersion::Lua51))
+        .build()
+        .unwrap();
+    let package_db = RemotePackageDB::from_config(&config).await.unwrap();
+    let source: GitSource = format!("git+file://{}", repo_dir.display())
+        .parse()
+        .unwrap();
+
+    let package = operations::install_from_git(
+        source,
+        None,
+        PinnedState::Unpinned,
+        BuildBehaviour::NoForce,
+        &package_db,
+        &config,
+        MultiProgress::new_arc(),
+    )
+    .await
+    .unwrap();
+
+    let tree = Tree::new(config.tree().clone(), LuaVersion::Lua51).unwrap();
+    assert!(tree
+        .rock_layout(&package)
+        .src
+        .join("generated.lua")
+        .is_file());
+    assert!(!tree.list().unwrap().contains_key(&"gen".into()));
+    assert!(!tree.bin().join("gen-module").exists());
+    let build_tree = Tree::new(build_tree, LuaVersion::Lua51).unwrap();
+    assert!(build_tree.list().unwrap().contains_key(&"gen".into()));
+}
+
+/// Serve a rock called `name` with a single module from a local git repository.
+/// Returns the repository, which must be kept alive while the rock is installed.
+fn serve_rock(server: &Server, name: &str) -> assert_fs::TempDir {
+    let repo_dir = assert_fs::TempDir::new().unwrap();
+    let rockspec = format!(
+        r#"
+package = "{name}"
+version = "1.0.0-1"
+source = {{
+    url = "git+file://{}",
+    tag = "v1.0.0",
+}}
+build = {{
+    type = "builtin",
+    modules = {{
+        {name} = "src/{name}.lua",
+    }},
+}}
+"#,
+        repo_dir.display()
+    );
+    repo_dir
+        .child(format!("src/{name}.lua"))
+        .write_str("return true")
+        .unwrap();
+    init_repo(&repo_dir);
+    server.expect(
+        Expectation::matching(request::path(matchers::eq(format!(
+            "/{name}-1.0.0-1.rockspec"
+        ))))
+        .times(0..)
+        .respond_with(status_code(200).body(rockspec)),
+    );
+    repo_dir
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn install_project_dependencies_without_dev_dependencies() {
+    let server = Server::run();
+    server.expect(
+        Expectation::matching(request::path("/manifest-5.1"))
+            .times(1..)
+            .respond_with(status_code(200).body(
+                r#"repository = {
+                    bar = { ["1.0.0-1"] = { { arch = "rockspec" } } },
+                    baz = { ["1.0.0-1"] = { { arch = "rockspec" } } },
+                }"#,
+            )),
+    );
+    let _bar = serve_rock(&server, "bar");
+    let _baz = serve_rock(&server, "baz");
+    let rockspec = Rockspec::new(
+        r#"
+package = "foo"
+version = "1.0.0-1"
+source = {
+    url = "git+https://example.com/foo",
+}
+dependencies = {
+    "bar",
+}
+test_dependencies = {
+    "baz",
+}
+"#,
+    )
+    .unwrap();
+
+    let temp = assert_fs::TempDir::new().unwrap();
+    let config = ConfigBuilder::new()
+        .server(Some(server.url_str("").trim_end_matches('/').to_string()))
+        .cache_dir(Some(temp.join("cache")))
+        .tree(Some(temp.join("tree")))
+        .luarocks_tree(Some(temp.join("build-tree")))
+        .lua_version(Some(LuaVersion::Lua51))
+        .no_dev_dependencies(Some(true))
+        .build()
+        .unwrap();
+    let package_db = RemotePackageDB::from_config(&config).await.unwrap();
+    operations::install_project_dependencies(
+        &rockspec,
+        Vec::new(),
+        &package_db,
+        &config,
+        MultiProgress::new_arc(),
+    )
+    .await
+    .unwrap();
+
+    let tree = Tree::new(config.tree().clone(), LuaVersion::Lua51).unwrap();
+    let test_tree = Tree::new(
+        operations::test_tree_config(&config).tree().clone(),
+        LuaVersion::Lua51,
+    )
+    .unwrap();
+    assert!(tree.list().unwrap().contains_key(&"bar".into()));
+    assert!(!tree.list().unwrap().contains_key(&"baz".into()));
+    assert!(!test_tree.list().unwrap().contains_key(&"baz".into()));
+
+    let config = config.with_no_dev_dependencies(false);
+    operations::install_project_dependencies(
+        &rockspec,
+        Vec::new(),
+        &package_db,
+        &config,
+        MultiProgress::new_arc(),
+    )
+    .await
+    .unwrap();
+    assert!(!tree.list().unwrap().contains_key(&"baz".into()));
+    assert!(test_tree.list().unwrap().contains_key(&"baz".into()));
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn install_locked_project_dependencies() {
+    let server = Server::run();
+    server.expect(
+        Expectation::matching(request::path("/manifest-5.1"))
+            .times(1..)
+            .respond_with(status_code(200).body(
+                r#"repository = {
+                    bar = { ["1.0.0-1"] = { { arch = "rockspec" } } },
+                    baz = { ["1.0.0-1"] = { { arch = "rockspec" } } },
+                }"#,
+            )),
+    );
+    let _bar = serve_rock(&server, "bar");
+    let _baz = serve_rock(&server, "baz");
+    let rockspec = r#"
+package = "foo"
+version = "1.0.0-1"
+source = {
+    url = "git+https://example.com/foo",
+}
+dependencies = {
+    "bar",
+}
+"#;
+    let project_dir = assert_fs::TempDir::new().unwrap();
+    let project_rockspec = project_dir.child("project.rockspec");
+    project_rockspec.write_str(rockspec).unwrap();
+    let project = Project::from(project_dir.path()).unwrap().unwrap();
+
+    let temp = assert_fs::TempDir::new().unwrap();
+    let config = ConfigBuilder::new()
+        .server(Some(server.url_str("").trim_end_matches('/').to_string()))
+        .cache_dir(Some(temp.join("cache")))
+        .tree(Some(project_dir.join(".rocks")))
+        .luarocks_tree(Some(temp.join("build-tree")))
+        .lua_version(Some(LuaVersion::Lua51))
+        .no_dev_dependencies(Some(true))
+        .build()
+        .unwrap();
+    let package_db = RemotePackageDB::from_config(&config).await.unwrap();
+    operations::install_project_dependencies(
+        project.rockspec(),
+        Vec::new(),
+        &package_db,
+        &config,
+        MultiProgress::new_arc(),
+    )
+    .await
+    .unwrap();
+    let tree = Tree::new(config.tree().clone(), LuaVersion::Lua51).unwrap();
+    let lockfile_path = tree.root().join("lock.json");
+    let lockfile = std::fs::read_to_string(&lockfile_path).unwrap();
+
+    // Bootstrap the project from its committed lockfile.
+    std::fs::remove_dir_all(config.tree()).unwrap();
+    std::fs::create_dir_all(tree.root()).unwrap();
+    std::fs::write(&lockfile_path, &lockfile).unwrap();
+    let installed =
+        operations::install_locked(&project, &package_db, &config, MultiProgress::new_arc())
+            .await
+            .unwrap();
+    assert_eq!(installed.len(), 1);
+    assert!(tree.list().unwrap().contains_key(&"bar".into()));
+    assert_eq!(std::fs::read_to_string(&lockfile_path).unwrap(), lockfile);
+
+    // Nothing is installed if the lockfile is out of date with the project.rockspec.
+    project_rockspec
+        .write_str(&rockspec.replace(r#""bar","#, r#""bar", "baz","#))
+        .unwrap();
+    let project = Project::from(project_dir.path()).unwrap().unwrap();
+    assert!(matches!(
+        operations::install_locked(&project, &package_db, &config, MultiProgress::new_arc()).await,
+        Err(InstallLockedError::OutOfDate(..))
+    ));
+    assert!(!tree.list().unwrap().contains_key(&"baz".into()));
+
+    // The installed rocks must match the lockfile's hashes.
+    project_rockspec.write_str(rockspec).unwrap();
+    let project = Project::from(project_dir.path()).unwrap().unwrap();
+    std::fs::remove_dir_all(config.tree()).unwrap();
+    std::fs::create_dir_all(tree.root()).unwrap();
+    let tampered = Regex::new(r#""source": "sha256-[^"]*""#)
+        .unwrap()
+        .replace(
+            &lockfile,
+            r#""source": "sha256-AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=""#,
+        )
+        .to_string();
+    assert_ne!(tampered, lockfile);
+    std::fs::write(&lockfile_path, &tampered).unwrap();
+    assert!(matches!(
+        operations::install_locked(&project, &package_db, &config, MultiProgress::new_arc()).await,
+        Err(InstallLockedError::IntegrityMismatch(..))
+    ));
+    // The rock is verified before it's built, so the tree is left untouched.
+    assert!(tree
+        .as_rock_list()
+        .unwrap()
+        .iter()
+        .all(|package| !tree.root_for(package).exists()));
+    assert_eq!(std::fs::read_to_string(&lockfile_path).unwrap(), tampered);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn fix_tree_problems() {
+    let server = Server::run();
+    server.expect(
+        Expectation::matching(request::path("/manifest-5.1"))
+            .times(1..)
+            .respond_with(status_code(200).body(
+                r#"repository = {
+                    bar = { ["1.0.0-1"] = { { arch = "rockspec" } } },
+                }"#,
+            )),
+    );
+    let _bar = serve_rock(&server, "bar");
+    let temp = assert_fs::TempDir::new().unwrap();
+    let config = ConfigBuilder::new()
+        .server(Some(server.url_str("").trim_end_matches('/').to_string()))
+        .cache_dir(Some(temp.join("cache")))
+        .tree(Some(temp.join("tree")))
+        .luarocks_tree(Some(temp.join("build-tree")))
+        .lua_version(Some(LuaVersion::Lua51))
+        .build()
+        .unwrap();
+    let package_db = RemotePackageDB::from_config(&config).await.unwrap();
+    let installed = operations::install(
+        vec![(BuildBehaviour::NoForce, "bar".parse().unwrap())],
+        PinnedState::Unpinned,
+        &package_db,
+        &config,
+        MultiProgress::new_arc(),
+    )
+    .await
+    .unwrap();
+    let tree = Tree::new(config.tree().clone(), LuaVersion::Lua51).unwrap();
+    let lockfile_path = tree.root().join("lock.json");
+    let lockfile = std::fs::read_to_string(&lockfile_path).unwrap();
+
+    std::fs::remove_dir_all(tree.root_for(&installed[0])).unwrap();
+    let orphan = tree.root().join("0123-foo@1.0.0-1");
+    std::fs::create_dir_all(&orphan).unwrap();
+    std::fs::write(
+        &lockfile_path,
+        Regex::new(r#""entrypoints": \[[^\]]*\]"#)
+            .unwrap()
+            .replace(&lockfile, r#""entrypoints": []"#)
+            .as_ref(),
+    )
+    .unwrap();
+    let problems = operations::check_tree(&tree).unwrap();
+    assert_eq!(
+        problems,
+        vec![
+            TreeProblem::MissingRock(Box::new(installed[0].clone())),
+            TreeProblem::OrphanedRockDir(orphan.clone()),
+            TreeProblem::StaleEntrypoints,
+        ]
+    );
+
+    operations::fix_tree(
+        &problems,
+        &tree,
+        &package_db,
+        &config,
+        MultiProgress::new_arc(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(operations::check_tree(&tree).unwrap(), Vec::new());
+    assert!(tree.root_for(&installed[0]).is_dir());
+    assert!(!orphan.exists());
+    assert_eq!(std::fs::read_to_string(&lockfile_path).unwrap(), lockfile);
+}
+
+#[tokio::test]
+async fn install_from_git_with_multiple_rockspecs() {
+    let repo_dir = assert_fs::TempDir::new().unwrap();
+    repo_dir
+        .child("foo-1.0.0-1.rockspec")
+        .write_str(ROCKSPEC)
+        .unwrap();
+    repo_dir
+        .child("rockspecs/foo-scm-1.rockspec")
+        .write_str(&ROCKSPEC.replace("1.0.0-1", "scm-1"))
+        .unwrap();
+    repo_dir
+        .child("src/foo.lua")
+        .write_str("return {}")
+        .unwrap();
+    init_repo(&repo_dir);
+
+    let server = start_test_server();
+    let temp = assert_fs::TempDir::new().unwrap();
+    let config = test_config(&server, &temp);
+    let package_db = RemotePackageDB::from_config(&config).await.unwrap();
+    let source: GitSource = format!("git+file://{}", repo_dir.display())
+        .parse()
+        .unwrap();
+
+    let err = operations::install_from_git(
+        source.clone(),
+        None,
+        PinnedState::Unpinned,
+        BuildBehaviour::NoForce,
+        &package_db,
+        &config,
+        MultiProgress::new_arc(),
+    )
+    .await
+    .unwrap_err();
+    assert!(
+        matches!(err, InstallError::MultipleRockspecs { rockspecs, .. } if rockspecs.len() == 2)
+    );
+
+    let package = operations::install_from_git(
+        source,
+        Some("rockspecs/foo-scm-1.rockspec".into()),
+        PinnedState::Unpinned,
+        BuildBehaviour::NoForce,
+        &package_db,
+        &config,
+        MultiProgress::new_arc(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(package.version().to_string(), "scm-1");
+}
+
+#[tokio::test]
+async fn install_into_prefix() {
+    let repo_dir = assert_fs::TempDir::new().unwrap();
+    repo_dir
+        .child("foo-1.0.0-1.rockspec")
+        .write_str(ROCKSPEC)
+        .unwrap();
+    repo_dir
+        .child("src/foo.lua")
+        .write_str("return {}")
+        .unwrap();
+    init_repo(&repo_dir);
+
+    let server = start_test_server();
+    let temp = assert_fs::TempDir::new().unwrap();
+    let prefix = temp.join("usr/local");
+    let config = test_config(&server, &temp).with_prefix(prefix.clone());
+    let package_db = RemotePackageDB::from_config(&config).await.unwrap();
+    let source: GitSource = format!("git+file://{}", repo_dir.display())
+        .parse()
+        .unwrap();
+
+    let package = operations::install_from_git(
+        source,
+        None,
+        PinnedState::Unpinned,
+        BuildBehaviour::NoForce,
+        &package_db,
+        &config,
+        MultiProgress::new_arc(),
+    )
+    .await
+    .unwrap();
+
+    assert!(prefix.join("share/lua/5.1/foo.lua").is_file());
+    assert!(prefix.join("lib/rocks/5.1/lock.json").is_file());
+    assert!(!config.tree().join("5.1/lock.json").exists());
+    let tree = Tree::from_config(&config, LuaVersion::Lua51).unwrap();
+    assert!(tree.lockfile().unwrap().get(&package.id()).is_some());
+
+    assert!(matches!(
+        operations::remove(package, &config, &Progress::NoProgress).await,
+        Err(RemoveError::SharedLayout(_))
+    ));
+}
@@ -0,0 +1,95 @@
+#![cfg(unix)]
+
+use assert_fs::prelude::{FileWriteStr as _, PathChild as _};
+use assert_fs::TempDir;
+use rocks_lib::{
+    config::{ConfigBuilder, LuaVersion},
+    lua_installation::{BuildLuaReleaseError, LuaInstallation},
+    package::PackageVersion,
+    progress::Progress,
+};
+
+/// A fake pkg-config, which only knows the `lua5.1` module.
+const FAKE_PKG_CONFIG: &str = r#"#!/bin/sh
+for arg in "$@"; do module="$arg"; done
+[ "$module" = "lua5.1" ] || exit 1
+case "$*" in
+  *--modversion*) echo "5.1.5" ;;
+  *) echo "-I$FAKE_LUA_PREFIX/include/lua5.1 -L$FAKE_LUA_PREFIX/lib -llua5.1" ;;
+esac
+"#;
+
+#[test]
+fn detect_lua_with_pkg_config() {
+    use std::os::unix::fs::PermissionsExt as _;
+
+    let temp = TempDir::new().unwrap();
+    let pkg_config = temp.child("pkg-config");
+    pkg_config.write_str(FAKE_PKG_CONFIG).unwrap();
+    std::fs::set_permissions(pkg_config.path(), std::fs::Permissions::from_mode(0o755)).unwrap();
+    let prefix = temp.child("prefix");
+    std::env::set_var("PKG_CONFIG", pkg_config.path());
+    std::env::set_var("FAKE_LUA_PREFIX", prefix.path());
+
+    let lua_dir = temp.child("lua");
+    let config = ConfigBuilder::new()
+        .lua_dir(Some(lua_dir.to_path_buf()))
+        .verbose(Some(true))
+        .build()
+        .unwrap();
+    let lua = LuaInstallation::new(&LuaVersion::Lua51, &config);
+    assert_eq!(
+        lua.include_dir,
+        prefix.path().join("include").join("lua5.1")
+    );
+    assert_eq!(lua.lib_dir, prefix.path().join("lib"));
+    assert!(!lua_dir.path().exists());
+}
+
+#[tokio::test]
+async fn build_pinned_lua_release() {
+    if which::which("cc").is_err() {
+        println!("Skipping build_pinned_lua_release, as no C compiler is available.");
+        return;
+    }
+    let temp = TempDir::new().unwrap();
+    let config = ConfigBuilder::new()
+        .lua_dir(Some(temp.child("lua").to_path_buf()))
+        .lua_version(Some(LuaVersion::Lua54))
+        .build()
+        .unwrap();
+    let lua = LuaInstallation::install_release(
+        &LuaVersion::Lua54,
+        "5.4.6",
+        None,
+        &config,
+        &Progress::NoProgress,
+    )
+    .await
+    .unwrap();
+    assert!(lua.include_dir.join("lua.h").is_file());
+    assert!(lua.lib_dir.join("liblua.a").is_file());
+    assert_eq!(
+        lua.lua_package_version(),
+        PackageVersion::parse("5.4.6").unwrap()
+    );
+    assert_eq!(
+        LuaInstallation::installed(&LuaVersion::Lua54, &config)
+            .unwrap()
+            .lua_package_version(),
+        PackageVersion::parse("5.4.6").unwrap()
+    );
+
+    let err = LuaInstallation::install_release(
+        &LuaVersion::Lua54,
+        "5.4.6",
+        Some("0000"),
+        &config,
+        &Progress::NoProgress,
+    )
+    .await;
+    assert!(matches!(
+        err,
+        Err(BuildLuaReleaseError::ChecksumMismatch { .. })
+    ));
+}
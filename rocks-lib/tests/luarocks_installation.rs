@@ -6,9 +6,13 @@ use assert_fs::TempDir;
 use predicates::prelude::predicate;
 use rocks_lib::progress::{MultiProgress, Progress, ProgressBar};
 use rocks_lib::{
+    build::{self, BuildBehaviour},
     config::{ConfigBuilder, LuaVersion},
+    lockfile::{LockConstraint, PinnedState},
     lua_installation::LuaInstallation,
     luarocks_installation::LuaRocksInstallation,
+    rockspec::Rockspec,
+    tree::TreeLayout,
 };
 
 #[tokio::test]
@@ -43,3 +47,57 @@ async fn luarocks_make() {
     foo_init.assert(predicate::path::is_file());
     foo_init.assert(predicate::str::contains("return true"));
 }
+
+#[tokio::test]
+async fn luarocks_lists_rocks_in_luarocks_tree() {
+    let luarocks_dir = TempDir::new().unwrap();
+    let luarocks_config = ConfigBuilder::new()
+        .tree(Some(luarocks_dir.path().into()))
+        .build()
+        .unwrap();
+    let luarocks = LuaRocksInstallation::new(&luarocks_config).unwrap();
+    let progress = Progress::Progress(MultiProgress::new());
+    let bar = progress.map(|p| p.add(ProgressBar::from("Installing luarocks".to_string())));
+    luarocks.ensure_installed(&bar).await.unwrap();
+
+    let tree_dir = TempDir::new().unwrap();
+    let config = ConfigBuilder::new()
+        .tree(Some(tree_dir.path().into()))
+        .tree_layout(Some(TreeLayout::Luarocks))
+        .build()
+        .unwrap();
+    let rockspec_content = std::fs::read_to_string(
+        PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("resources/test/sample-project-no-build-spec/foo-1.0.0-1.rockspec"),
+    )
+    .unwrap();
+    build::build(
+        Rockspec::new(&rockspec_content).unwrap(),
+        None,
+        PinnedState::Unpinned,
+        LockConstraint::Unconstrained,
+        BuildBehaviour::Force,
+        &config,
+        &bar,
+    )
+    .await
+    .unwrap();
+
+    let lua_version = config.lua_version().unwrap_or(&LuaVersion::Lua51);
+    let lua = LuaInstallation::new(lua_version, &config);
+    let rocks_dir = tree_dir
+        .child("lib")
+        .child("luarocks")
+        .child(format!("rocks-{}", lua_version.version_compatibility_str()));
+    rocks_dir
+        .child("manifest")
+        .assert(predicate::path::is_file());
+    rocks_dir
+        .child("foo")
+        .child("1.0.0-1")
+        .child("foo-1.0.0-1.rockspec")
+        .assert(predicate::path::is_file());
+
+    let output = luarocks.list(tree_dir.path(), &lua).unwrap();
+    assert!(output.contains("foo\t1.0.0-1"), "{}", output);
+}
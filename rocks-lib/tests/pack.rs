@@ -0,0 +1,71 @@
+use std::time::Duration;
+
+use assert_fs::prelude::*;
+use gpgme::{Context, CreateKeyFlags, ExportMode, Protocol};
+use rocks_lib::{
+    config::ConfigBuilder,
+    operations::{pack_project, sign_rock, PackFilter},
+    project::Project,
+    signature::Keyring,
+    upload::SignatureProtocol,
+};
+
+#[test]
+fn pack_signed_rock() {
+    let gnupg_home = assert_fs::TempDir::new().unwrap();
+    std::env::set_var("GNUPGHOME", gnupg_home.path());
+    let mut ctx = Context::from_protocol(Protocol::OpenPgp).unwrap();
+    let key = ctx
+        .create_key_with_flags(
+            "rocks test <test@example.com>",
+            "default",
+            Duration::ZERO,
+            CreateKeyFlags::NOPASSWD,
+        )
+        .unwrap();
+    let fingerprint = key.fingerprint().unwrap().to_string();
+    let mut public_key = Vec::new();
+    ctx.export([fingerprint.as_str()], ExportMode::empty(), &mut public_key)
+        .unwrap();
+
+    let project_dir = assert_fs::TempDir::new().unwrap();
+    project_dir
+        .child("project.rockspec")
+        .write_str(
+            r#"
+            package = "foo"
+            version = "1.0.0-1"
+            source = { url = "https://github.com/example/foo/archive/v1.0.0.zip" }
+            "#,
+        )
+        .unwrap();
+    project_dir
+        .child("src/foo.lua")
+        .write_str("return {}")
+        .unwrap();
+    let project = Project::from(project_dir.path()).unwrap().unwrap();
+
+    let rock_path = pack_project(&project, project_dir.path(), &PackFilter::default()).unwrap();
+    let signature_path =
+        sign_rock(&rock_path, Some(&fingerprint), SignatureProtocol::OpenPGP).unwrap();
+    assert_eq!(signature_path, project_dir.join("foo-1.0.0-1.src.rock.asc"));
+
+    let data_dir = assert_fs::TempDir::new().unwrap();
+    let config = ConfigBuilder::new()
+        .data_dir(Some(data_dir.to_path_buf()))
+        .build()
+        .unwrap();
+    let keyring = Keyring::new(&config).unwrap();
+    keyring.import(&public_key).unwrap();
+
+    let content = std::fs::read(&rock_path).unwrap();
+    let signature = std::fs::read(&signature_path).unwrap();
+    keyring
+        .verify("foo-1.0.0-1.src.rock", &content, &signature, &[fingerprint])
+        .unwrap();
+
+    // The signature must not verify for a modified archive.
+    assert!(keyring
+        .verify("foo-1.0.0-1.src.rock", b"tampered", &signature, &[])
+        .is_err());
+}
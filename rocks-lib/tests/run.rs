@@ -1,6 +1,8 @@
+use assert_fs::prelude::{FileWriteStr as _, PathChild as _};
 use rocks_lib::{
-    config::ConfigBuilder,
-    operations::{install_command, run},
+    config::{ConfigBuilder, LuaVersion},
+    operations::{install_command, run, run_script},
+    project::Project,
 };
 use tempdir::TempDir;
 
@@ -14,3 +16,45 @@ async fn run_nlua() {
     install_command("nlua", &config).await.unwrap();
     run("nlua", vec!["-v".into()], config).await.unwrap()
 }
+
+#[cfg(unix)]
+#[tokio::test]
+async fn run_project_script() {
+    let root = assert_fs::TempDir::new().unwrap();
+    root.child("project.rockspec")
+        .write_str(
+            r#"
+package = "foo"
+version = "1.0.0-1"
+source = {
+    url = "https://github.com/nvim-neorocks/luarocks-stub",
+}
+scripts = {
+    hello = "echo hello >",
+}
+"#,
+        )
+        .unwrap();
+    let project = Project::from(root.path()).unwrap().unwrap();
+    let config = ConfigBuilder::new()
+        .tree(Some(root.join(".rocks")))
+        .lua_version(Some(LuaVersion::Lua51))
+        .build()
+        .unwrap();
+    run_script(
+        &project,
+        "hello",
+        vec!["out file.txt".into()],
+        config.clone(),
+    )
+    .await
+    .unwrap();
+    // The script runs in the project root, with the arguments appended.
+    assert_eq!(
+        std::fs::read_to_string(root.join("out file.txt")).unwrap(),
+        "hello\n"
+    );
+    assert!(run_script(&project, "missing", Vec::new(), config)
+        .await
+        .is_err());
+}
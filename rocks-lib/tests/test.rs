@@ -1,8 +1,16 @@
-use std::path::PathBuf;
+use std::{
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+};
 
+use assert_fs::prelude::*;
+use httptest::{matchers::request, responders::status_code, Expectation, Server};
 use rocks_lib::{
-    config::ConfigBuilder,
-    operations::{ensure_busted, run_tests, TestEnv},
+    config::{Config, ConfigBuilder, LuaVersion},
+    operations::{
+        ensure_busted, ensure_dependencies, run_tests, run_tests_in_parallel, test_tree_config,
+        RunTestsError, TestEnv,
+    },
     progress::MultiProgress,
     project::Project,
     remote_package_db::RemotePackageDB,
@@ -17,12 +25,219 @@ async fn run_busted_test() {
     let tree_root = project.root().to_path_buf().join(".rocks");
     let _ = std::fs::remove_dir_all(&tree_root);
     let config = ConfigBuilder::new().tree(Some(tree_root)).build().unwrap();
-    let tree = Tree::new(config.tree().clone(), config.lua_version().unwrap().clone()).unwrap();
     let package_db = RemotePackageDB::from_config(&config).await.unwrap();
-    ensure_busted(&tree, &package_db, &config, MultiProgress::new_arc())
+    ensure_busted(&package_db, &config, MultiProgress::new_arc())
         .await
         .unwrap();
     run_tests(project, Vec::new(), TestEnv::Pure, config)
         .await
         .unwrap()
 }
+
+#[tokio::test]
+async fn run_busted_test_in_parallel() {
+    let project_root =
+        PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("resources/test/sample-project-busted");
+    let temp = assert_fs::TempDir::new().unwrap();
+    temp.copy_from(&project_root, &["**"]).unwrap();
+    temp.child("spec/other_spec.lua")
+        .write_str(
+            r#"describe("other", function() it("fails", function() assert.is_true(false) end) end)"#,
+        )
+        .unwrap();
+    let project: Project = Project::from(temp.path()).unwrap().unwrap();
+    let tree_root = project.root().to_path_buf().join(".rocks");
+    let _ = std::fs::remove_dir_all(&tree_root);
+    let config = ConfigBuilder::new().tree(Some(tree_root)).build().unwrap();
+    let package_db = RemotePackageDB::from_config(&config).await.unwrap();
+    ensure_busted(&package_db, &config, MultiProgress::new_arc())
+        .await
+        .unwrap();
+    let summary = run_tests_in_parallel(
+        project,
+        Vec::new(),
+        TestEnv::Pure,
+        NonZeroUsize::new(2).unwrap(),
+        config,
+    )
+    .await
+    .unwrap();
+    assert_eq!(summary.passed, vec![PathBuf::from("spec/example_spec.lua")]);
+    assert_eq!(summary.failed, vec![PathBuf::from("spec/other_spec.lua")]);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn run_command_test() {
+    let project_dir = assert_fs::TempDir::new().unwrap();
+    project_dir
+        .child("project.rockspec")
+        .write_str(
+            r#"
+package = "sample-project"
+version = "scm-1"
+source = {
+    url = "https://github.com/nvim-neorocks/luarocks-stub",
+}
+dependencies = {
+    "lua >= 5.1",
+}
+build = {
+    type = "builtin",
+}
+test = {
+    type = "command",
+    command = "sh run-tests.sh",
+    flags = { "expected" },
+}
+"#,
+        )
+        .unwrap();
+    // Fails with exit code 3 unless it is called with the `flags` and the test arguments,
+    // and the test tree is on the `PATH`.
+    project_dir
+        .child("run-tests.sh")
+        .write_str(
+            r#"
+[ "$1" = "expected" ] && [ "$2" = "--arg with spaces" ] || exit 3
+case "$PATH" in
+    *"/test/"*) ;;
+    *) exit 3 ;;
+esac
+"#,
+        )
+        .unwrap();
+    let project = Project::from(project_dir.path()).unwrap().unwrap();
+    let config = ConfigBuilder::new()
+        .tree(Some(project.root().join(".rocks")))
+        .lua_version(Some(LuaVersion::Lua51))
+        .build()
+        .unwrap();
+
+    run_tests(
+        project,
+        vec!["--arg with spaces".into()],
+        TestEnv::Pure,
+        config.clone(),
+    )
+    .await
+    .unwrap();
+
+    let project = Project::from(project_dir.path()).unwrap().unwrap();
+    let err = run_tests(project, Vec::new(), TestEnv::Pure, config)
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        RunTestsError::TestCommandFailure { status, .. } if status.code() == Some(3)
+    ));
+}
+
+fn rockspec(name: &str, source_dir: &Path) -> String {
+    format!(
+        r#"
+package = "{name}"
+version = "1.0.0-1"
+source = {{
+    url = "file://{}",
+}}
+build = {{
+    type = "builtin",
+    modules = {{
+        {name} = "{name}.lua",
+    }},
+}}
+"#,
+        source_dir.display()
+    )
+}
+
+#[tokio::test]
+async fn test_dependencies_are_locked_separately() {
+    let source_dir = assert_fs::TempDir::new().unwrap();
+    for name in ["foo", "busted"] {
+        source_dir
+            .child(format!("{name}.lua"))
+            .write_str("return {}")
+            .unwrap();
+    }
+
+    let server = Server::run();
+    server.expect(
+        Expectation::matching(request::path("/manifest-5.1"))
+            .times(1..)
+            .respond_with(status_code(200).body(
+                r#"
+repository = {
+    foo = { ["1.0.0-1"] = { { arch = "rockspec" } } },
+    busted = { ["1.0.0-1"] = { { arch = "rockspec" } } },
+}
+"#,
+            )),
+    );
+    for name in ["foo", "busted"] {
+        server.expect(
+            Expectation::matching(request::path(format!("/{name}-1.0.0-1.rockspec")))
+                .times(1..)
+                .respond_with(status_code(200).body(rockspec(name, &source_dir))),
+        );
+    }
+
+    let project_dir = assert_fs::TempDir::new().unwrap();
+    project_dir
+        .child("project.rockspec")
+        .write_str(
+            r#"
+package = "sample-project"
+version = "scm-1"
+source = {
+    url = "https://github.com/nvim-neorocks/luarocks-stub",
+}
+dependencies = {
+    "lua >= 5.1",
+    "foo",
+}
+test_dependencies = {
+    "busted",
+}
+build = {
+    type = "builtin",
+}
+"#,
+        )
+        .unwrap();
+    let project = Project::from(project_dir.path()).unwrap().unwrap();
+
+    let mut server_url = server.url_str("");
+    server_url.pop();
+    let temp = assert_fs::TempDir::new().unwrap();
+    let config = ConfigBuilder::new()
+        .server(Some(server_url))
+        .cache_dir(Some(temp.join("cache")))
+        .tree(Some(project.root().join(".rocks")))
+        .lua_version(Some(LuaVersion::Lua51))
+        .build()
+        .unwrap();
+    let package_db = RemotePackageDB::from_config(&config).await.unwrap();
+
+    ensure_dependencies(
+        project.rockspec(),
+        &package_db,
+        &config,
+        MultiProgress::new_arc(),
+    )
+    .await
+    .unwrap();
+
+    let installed = |config: &Config| {
+        Tree::from_config(config, LuaVersion::Lua51)
+            .unwrap()
+            .as_rock_list()
+            .unwrap()
+            .into_iter()
+            .map(|package| package.name().to_string())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(installed(&config), vec!["foo"]);
+    assert_eq!(installed(&test_tree_config(&config)), vec!["busted"]);
+}